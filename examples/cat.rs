@@ -2,7 +2,7 @@ use std::env;
 use std::fs::File;
 use std::io::{self, prelude::*};
 
-use fatfs::{FileSystem, FsOptions};
+use axfatfs::{FileSystem, FsOptions};
 use fscommon::BufStream;
 
 fn main() -> io::Result<()> {
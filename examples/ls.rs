@@ -2,8 +2,8 @@ use std::env;
 use std::fs::File;
 use std::io;
 
+use axfatfs::{FileSystem, FsOptions};
 use chrono::NaiveDateTime;
-use fatfs::{FileSystem, FsOptions};
 use fscommon::BufStream;
 
 fn format_file_size(size: u64) -> String {
@@ -0,0 +1,212 @@
+use core::fmt;
+use core::future::Future;
+use core::pin::pin;
+use core::task::{Context, Poll, RawWaker, RawWakerVTable, Waker};
+
+use embedded_io_async::ErrorKind;
+
+use crate::error::IoError;
+use crate::io::{IoBase, Read, Seek, SeekFrom, Write};
+
+/// An error returned by `BlockingAsyncStorage`.
+#[derive(Debug)]
+pub enum AsyncStorageError<E> {
+    /// The wrapped storage returned an error.
+    Io(E),
+    /// A read operation cannot be completed because an end of the storage has been reached prematurely.
+    UnexpectedEof,
+    /// A write operation cannot be completed because the storage accepted no data.
+    WriteZero,
+}
+
+impl<E: embedded_io_async::Error> IoError for AsyncStorageError<E> {
+    fn is_interrupted(&self) -> bool {
+        match self {
+            AsyncStorageError::Io(io_error) => io_error.kind() == ErrorKind::Interrupted,
+            _ => false,
+        }
+    }
+
+    fn new_unexpected_eof_error() -> Self {
+        AsyncStorageError::UnexpectedEof
+    }
+
+    fn new_write_zero_error() -> Self {
+        AsyncStorageError::WriteZero
+    }
+}
+
+impl<E: fmt::Debug> fmt::Display for AsyncStorageError<E> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            AsyncStorageError::Io(io_error) => write!(f, "IO error: {:?}", io_error),
+            AsyncStorageError::UnexpectedEof => write!(f, "Unexpected end of file"),
+            AsyncStorageError::WriteZero => write!(f, "Write zero"),
+        }
+    }
+}
+
+/// A storage adapter for devices implementing the `embedded-io-async` traits.
+///
+/// The filesystem API is synchronous, so every operation of the wrapped storage is driven to completion by polling
+/// its future in a busy loop. Drivers completing their futures from interrupt handlers or by polling the hardware
+/// (common for SD/eMMC drivers) work without an executor. Do not use this adapter for a storage which depends on
+/// an executor running on the same thread - it would never complete.
+pub struct BlockingAsyncStorage<T> {
+    inner: T,
+}
+
+impl<T> BlockingAsyncStorage<T> {
+    /// Creates a new `BlockingAsyncStorage` instance that wraps the provided `inner` storage.
+    pub fn new(inner: T) -> Self {
+        Self { inner }
+    }
+
+    /// Returns inner storage
+    pub fn into_inner(self) -> T {
+        self.inner
+    }
+}
+
+impl<T: embedded_io_async::ErrorType> IoBase for BlockingAsyncStorage<T> {
+    type Error = AsyncStorageError<T::Error>;
+}
+
+impl<T: embedded_io_async::Read> Read for BlockingAsyncStorage<T> {
+    fn read(&mut self, buf: &mut [u8]) -> Result<usize, Self::Error> {
+        block_on(self.inner.read(buf)).map_err(AsyncStorageError::Io)
+    }
+}
+
+impl<T: embedded_io_async::Write> Write for BlockingAsyncStorage<T> {
+    fn write(&mut self, buf: &[u8]) -> Result<usize, Self::Error> {
+        block_on(self.inner.write(buf)).map_err(AsyncStorageError::Io)
+    }
+
+    fn flush(&mut self) -> Result<(), Self::Error> {
+        block_on(self.inner.flush()).map_err(AsyncStorageError::Io)
+    }
+}
+
+impl<T: embedded_io_async::Seek> Seek for BlockingAsyncStorage<T> {
+    fn seek(&mut self, pos: SeekFrom) -> Result<u64, Self::Error> {
+        let pos = match pos {
+            SeekFrom::Start(n) => embedded_io_async::SeekFrom::Start(n),
+            SeekFrom::End(n) => embedded_io_async::SeekFrom::End(n),
+            SeekFrom::Current(n) => embedded_io_async::SeekFrom::Current(n),
+        };
+        block_on(self.inner.seek(pos)).map_err(AsyncStorageError::Io)
+    }
+}
+
+fn noop_raw_waker() -> RawWaker {
+    fn clone(_: *const ()) -> RawWaker {
+        noop_raw_waker()
+    }
+    fn noop(_: *const ()) {}
+    static VTABLE: RawWakerVTable = RawWakerVTable::new(clone, noop, noop, noop);
+    RawWaker::new(core::ptr::null(), &VTABLE)
+}
+
+fn block_on<F: Future>(future: F) -> F::Output {
+    // SAFETY: the waker does nothing and does not use the data pointer
+    let waker = unsafe { Waker::from_raw(noop_raw_waker()) };
+    let mut context = Context::from_waker(&waker);
+    let mut future = pin!(future);
+    loop {
+        if let Poll::Ready(output) = future.as_mut().poll(&mut context) {
+            return output;
+        }
+        core::hint::spin_loop();
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{FileSystem, FsOptions};
+
+    // In-memory storage which is not ready on every other poll
+    struct AsyncRamDisk {
+        data: std::vec::Vec<u8>,
+        pos: usize,
+        ready: bool,
+    }
+
+    impl AsyncRamDisk {
+        async fn wait(&mut self) {
+            core::future::poll_fn(|cx| {
+                self.ready = !self.ready;
+                if self.ready {
+                    Poll::Ready(())
+                } else {
+                    cx.waker().wake_by_ref();
+                    Poll::Pending
+                }
+            })
+            .await;
+        }
+    }
+
+    impl embedded_io_async::ErrorType for AsyncRamDisk {
+        type Error = ErrorKind;
+    }
+
+    impl embedded_io_async::Read for AsyncRamDisk {
+        async fn read(&mut self, buf: &mut [u8]) -> Result<usize, Self::Error> {
+            self.wait().await;
+            let n = buf.len().min(self.data.len() - self.pos);
+            buf[..n].copy_from_slice(&self.data[self.pos..self.pos + n]);
+            self.pos += n;
+            Ok(n)
+        }
+    }
+
+    impl embedded_io_async::Write for AsyncRamDisk {
+        async fn write(&mut self, buf: &[u8]) -> Result<usize, Self::Error> {
+            self.wait().await;
+            let n = buf.len().min(self.data.len() - self.pos);
+            self.data[self.pos..self.pos + n].copy_from_slice(&buf[..n]);
+            self.pos += n;
+            Ok(n)
+        }
+    }
+
+    impl embedded_io_async::Seek for AsyncRamDisk {
+        async fn seek(&mut self, pos: embedded_io_async::SeekFrom) -> Result<u64, Self::Error> {
+            self.wait().await;
+            let new_pos = match pos {
+                embedded_io_async::SeekFrom::Start(n) => Some(n),
+                embedded_io_async::SeekFrom::End(n) => (self.data.len() as u64).checked_add_signed(n),
+                embedded_io_async::SeekFrom::Current(n) => (self.pos as u64).checked_add_signed(n),
+            };
+            self.pos = new_pos.ok_or(ErrorKind::InvalidInput)? as usize;
+            Ok(self.pos as u64)
+        }
+    }
+
+    #[test]
+    fn test_blocking_async_storage() {
+        let data = std::fs::read("resources/fat16.img").unwrap();
+        let storage = BlockingAsyncStorage::new(AsyncRamDisk {
+            data,
+            pos: 0,
+            ready: false,
+        });
+        let fs = FileSystem::new(storage, FsOptions::new()).unwrap();
+        let mut file = fs.root_dir().create_file("async.txt").unwrap();
+        file.write_all(b"Hello async world!").unwrap();
+        file.seek(SeekFrom::Start(0)).unwrap();
+        let mut buf = [0_u8; 18];
+        file.read_exact(&mut buf).unwrap();
+        assert_eq!(&buf, b"Hello async world!");
+        drop(file);
+        let mut buf = [0_u8; 14];
+        fs.root_dir()
+            .open_file("short.txt")
+            .unwrap()
+            .read_exact(&mut buf)
+            .unwrap();
+        assert_eq!(&buf, b"Rust is cool!\n");
+    }
+}
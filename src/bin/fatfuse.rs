@@ -0,0 +1,548 @@
+//! FUSE driver exposing a FAT filesystem image on the host, so it can be inspected and edited with normal tools.
+//!
+//! The image is mounted by `fatfuse <image> <mountpoint>` and unmounted by `fusermount -u <mountpoint>`. All
+//! accesses go through the read and write paths of the library.
+
+use std::collections::HashMap;
+use std::ffi::OsStr;
+use std::fs;
+use std::io::{self, Read, Seek, SeekFrom, Write};
+use std::process::ExitCode;
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+
+use axfatfs::{DateTime, Error, FileAttributes, FileSystem, FsOptions, Metadata, StdIoWrapper};
+use clap::Parser;
+use fuser::{
+    FileAttr, FileType, Filesystem, MountOption, ReplyAttr, ReplyCreate, ReplyData, ReplyDirectory, ReplyEmpty,
+    ReplyEntry, ReplyStatfs, ReplyWrite, Request, TimeOrNow, FUSE_ROOT_ID,
+};
+
+// The image is modified only through this process, but keep the kernel caches short-lived anyway
+const TTL: Duration = Duration::from_secs(1);
+const EIO: i32 = 5;
+const EINVAL: i32 = 22;
+
+type ImageFs = FileSystem<StdIoWrapper<fs::File>>;
+
+#[derive(Parser)]
+#[command(name = "fatfuse", version, about = "Mount a FAT filesystem image using FUSE")]
+struct Args {
+    /// Path of the filesystem image
+    image: String,
+    /// Directory the image is mounted on
+    mountpoint: String,
+    /// Mount the image read-only
+    #[arg(long)]
+    read_only: bool,
+}
+
+// Maps inode numbers to paths in the image - FAT has no inode numbers. Paths are compared case-insensitively like
+// names in FAT directories.
+struct Inodes {
+    paths: HashMap<u64, String>,
+    inodes: HashMap<String, u64>,
+    next_inode: u64,
+}
+
+impl Inodes {
+    fn new() -> Self {
+        let mut inodes = Self {
+            paths: HashMap::new(),
+            inodes: HashMap::new(),
+            next_inode: FUSE_ROOT_ID + 1,
+        };
+        inodes.paths.insert(FUSE_ROOT_ID, String::new());
+        inodes.inodes.insert(String::new(), FUSE_ROOT_ID);
+        inodes
+    }
+
+    fn path(&self, ino: u64) -> Result<&str, i32> {
+        self.paths.get(&ino).map(String::as_str).ok_or(EINVAL)
+    }
+
+    fn child_path(&self, parent: u64, name: &OsStr) -> Result<String, i32> {
+        let parent_path = self.path(parent)?;
+        let name = name.to_str().ok_or(EINVAL)?;
+        Ok(if parent_path.is_empty() {
+            name.to_owned()
+        } else {
+            format!("{}/{}", parent_path, name)
+        })
+    }
+
+    fn inode(&mut self, path: &str) -> u64 {
+        if let Some(ino) = self.inodes.get(&path.to_lowercase()) {
+            return *ino;
+        }
+        let ino = self.next_inode;
+        self.next_inode += 1;
+        self.paths.insert(ino, path.to_owned());
+        self.inodes.insert(path.to_lowercase(), ino);
+        ino
+    }
+
+    fn remove(&mut self, path: &str) {
+        if let Some(ino) = self.inodes.remove(&path.to_lowercase()) {
+            self.paths.remove(&ino);
+        }
+    }
+
+    // Updates paths of a renamed entry and all entries below it
+    fn rename(&mut self, src: &str, dst: &str) {
+        self.remove(dst);
+        let src_key = src.to_lowercase();
+        let src_prefix = format!("{}/", src_key);
+        let moved: Vec<(String, u64)> = self
+            .inodes
+            .iter()
+            .filter(|(key, _)| **key == src_key || key.starts_with(&src_prefix))
+            .map(|(key, ino)| (key.clone(), *ino))
+            .collect();
+        for (key, ino) in moved {
+            self.inodes.remove(&key);
+            let new_path = format!("{}{}", dst, &self.paths[&ino][src.len()..]);
+            self.inodes.insert(new_path.to_lowercase(), ino);
+            self.paths.insert(ino, new_path);
+        }
+    }
+}
+
+fn errno(err: &Error<io::Error>) -> i32 {
+    match err {
+        Error::Io(io_error) => io_error.raw_os_error().unwrap_or(EIO),
+        _ => err.code(),
+    }
+}
+
+// Converts a FAT timestamp (stored without a time zone) to a system time, treating it as UTC
+fn system_time(date_time: DateTime) -> SystemTime {
+    // Days from the civil date algorithm by Howard Hinnant
+    let month = u64::from(date_time.date.month);
+    let year = u64::from(date_time.date.year) - u64::from(month <= 2);
+    let era = year / 400;
+    let year_of_era = year - era * 400;
+    let day_of_year = (153 * ((month + 9) % 12) + 2) / 5 + u64::from(date_time.date.day) - 1;
+    let day_of_era = year_of_era * 365 + year_of_era / 4 - year_of_era / 100 + day_of_year;
+    let days = (era * 146_097 + day_of_era).saturating_sub(719_468);
+    let secs = days * 86400
+        + u64::from(date_time.time.hour) * 3600
+        + u64::from(date_time.time.min) * 60
+        + u64::from(date_time.time.sec);
+    UNIX_EPOCH + Duration::from_secs(secs) + Duration::from_millis(u64::from(date_time.time.millis))
+}
+
+struct FatFuse {
+    fs: ImageFs,
+    inodes: Inodes,
+    mount_time: SystemTime,
+}
+
+impl FatFuse {
+    fn new(fs: ImageFs) -> Self {
+        Self {
+            fs,
+            inodes: Inodes::new(),
+            mount_time: SystemTime::now(),
+        }
+    }
+
+    fn attr(&self, req: &Request<'_>, ino: u64, metadata: Option<&Metadata>) -> FileAttr {
+        let (kind, size, created, modified) = match metadata {
+            Some(m) if m.is_file() => (
+                FileType::RegularFile,
+                m.len(),
+                system_time(m.created()),
+                system_time(m.modified()),
+            ),
+            Some(m) => (
+                FileType::Directory,
+                0,
+                system_time(m.created()),
+                system_time(m.modified()),
+            ),
+            // The root directory has no directory entry
+            None => (FileType::Directory, 0, self.mount_time, self.mount_time),
+        };
+        let read_only =
+            self.fs.is_read_only() || metadata.map_or(false, |m| m.attributes().contains(FileAttributes::READ_ONLY));
+        let perm = match (kind, read_only) {
+            (FileType::Directory, false) => 0o755,
+            (FileType::Directory, true) => 0o555,
+            (_, false) => 0o644,
+            (_, true) => 0o444,
+        };
+        let cluster_size = self.fs.cluster_size();
+        FileAttr {
+            ino,
+            size,
+            blocks: (size + 511) / 512,
+            atime: modified,
+            mtime: modified,
+            ctime: modified,
+            crtime: created,
+            kind,
+            perm,
+            nlink: 1,
+            uid: req.uid(),
+            gid: req.gid(),
+            rdev: 0,
+            blksize: cluster_size,
+            flags: 0,
+        }
+    }
+
+    // Returns attributes of the entry at `path`, assigning it an inode number if needed
+    fn entry_attr(&mut self, req: &Request<'_>, path: &str) -> Result<FileAttr, i32> {
+        let metadata = self.fs.metadata(path).map_err(|e| errno(&e))?;
+        let ino = self.inodes.inode(path);
+        Ok(self.attr(req, ino, Some(&metadata)))
+    }
+
+    fn ino_attr(&self, req: &Request<'_>, ino: u64) -> Result<FileAttr, i32> {
+        let path = self.inodes.path(ino)?;
+        if path.is_empty() {
+            return Ok(self.attr(req, ino, None));
+        }
+        let metadata = self.fs.metadata(path).map_err(|e| errno(&e))?;
+        Ok(self.attr(req, ino, Some(&metadata)))
+    }
+
+    fn set_len(&self, path: &str, len: u64) -> Result<(), Error<io::Error>> {
+        let mut file = self.fs.open_file(path)?;
+        let old_len = file.seek(SeekFrom::End(0))?;
+        if len < old_len {
+            file.seek(SeekFrom::Start(len))?;
+            file.truncate()?;
+        } else if len > old_len {
+            // the gap is filled with zeros
+            file.seek(SeekFrom::Start(len - 1))?;
+            file.write_all(&[0])?;
+        }
+        Ok(())
+    }
+
+    fn read_at(&self, path: &str, offset: u64, size: u32) -> Result<Vec<u8>, Error<io::Error>> {
+        let mut file = self.fs.open_file(path)?;
+        file.seek(SeekFrom::Start(offset))?;
+        let mut data = Vec::with_capacity(size as usize);
+        file.take(u64::from(size)).read_to_end(&mut data)?;
+        Ok(data)
+    }
+
+    fn write_at(&self, path: &str, offset: u64, data: &[u8]) -> Result<(), Error<io::Error>> {
+        let mut file = self.fs.open_file(path)?;
+        file.seek(SeekFrom::Start(offset))?;
+        file.write_all(data)?;
+        Ok(())
+    }
+
+    fn dir_entries(&self, path: &str) -> Result<Vec<(String, bool)>, Error<io::Error>> {
+        let dir = if path.is_empty() {
+            self.fs.root_dir()
+        } else {
+            self.fs.open_dir(path)?
+        };
+        let mut entries = Vec::new();
+        for r in dir.iter() {
+            let e = r?;
+            let name = e.file_name();
+            if name != "." && name != ".." {
+                entries.push((name, e.is_dir()));
+            }
+        }
+        Ok(entries)
+    }
+}
+
+impl Filesystem for FatFuse {
+    fn destroy(&mut self) {
+        if let Err(err) = self.fs.flush() {
+            eprintln!("fatfuse: flush failed: {}", err);
+        }
+    }
+
+    fn lookup(&mut self, req: &Request<'_>, parent: u64, name: &OsStr, reply: ReplyEntry) {
+        match self
+            .inodes
+            .child_path(parent, name)
+            .and_then(|path| self.entry_attr(req, &path))
+        {
+            Ok(attr) => reply.entry(&TTL, &attr, 0),
+            Err(err) => reply.error(err),
+        }
+    }
+
+    fn getattr(&mut self, req: &Request<'_>, ino: u64, reply: ReplyAttr) {
+        match self.ino_attr(req, ino) {
+            Ok(attr) => reply.attr(&TTL, &attr),
+            Err(err) => reply.error(err),
+        }
+    }
+
+    fn setattr(
+        &mut self,
+        req: &Request<'_>,
+        ino: u64,
+        _mode: Option<u32>,
+        _uid: Option<u32>,
+        _gid: Option<u32>,
+        size: Option<u64>,
+        _atime: Option<TimeOrNow>,
+        _mtime: Option<TimeOrNow>,
+        _ctime: Option<SystemTime>,
+        _fh: Option<u64>,
+        _crtime: Option<SystemTime>,
+        _chgtime: Option<SystemTime>,
+        _bkuptime: Option<SystemTime>,
+        _flags: Option<u32>,
+        reply: ReplyAttr,
+    ) {
+        // only the size can be changed, other attributes have no FAT equivalent and are ignored
+        let result = self.inodes.path(ino).and_then(|path| match size {
+            Some(len) => self.set_len(path, len).map_err(|e| errno(&e)),
+            None => Ok(()),
+        });
+        match result.and_then(|()| self.ino_attr(req, ino)) {
+            Ok(attr) => reply.attr(&TTL, &attr),
+            Err(err) => reply.error(err),
+        }
+    }
+
+    fn mkdir(&mut self, req: &Request<'_>, parent: u64, name: &OsStr, _mode: u32, _umask: u32, reply: ReplyEntry) {
+        let result = self.inodes.child_path(parent, name).and_then(|path| {
+            self.fs.create_dir(&path).map_err(|e| errno(&e))?;
+            self.entry_attr(req, &path)
+        });
+        match result {
+            Ok(attr) => reply.entry(&TTL, &attr, 0),
+            Err(err) => reply.error(err),
+        }
+    }
+
+    fn unlink(&mut self, _req: &Request<'_>, parent: u64, name: &OsStr, reply: ReplyEmpty) {
+        let result = self.inodes.child_path(parent, name).and_then(|path| {
+            self.fs.remove(&path).map_err(|e| errno(&e))?;
+            self.inodes.remove(&path);
+            Ok(())
+        });
+        match result {
+            Ok(()) => reply.ok(),
+            Err(err) => reply.error(err),
+        }
+    }
+
+    fn rmdir(&mut self, req: &Request<'_>, parent: u64, name: &OsStr, reply: ReplyEmpty) {
+        // removal of a non-empty directory fails, so it is the same as unlink
+        self.unlink(req, parent, name, reply);
+    }
+
+    fn rename(
+        &mut self,
+        _req: &Request<'_>,
+        parent: u64,
+        name: &OsStr,
+        newparent: u64,
+        newname: &OsStr,
+        _flags: u32,
+        reply: ReplyEmpty,
+    ) {
+        let result = self.inodes.child_path(parent, name).and_then(|src| {
+            let dst = self.inodes.child_path(newparent, newname)?;
+            self.fs.rename_replace(&src, &dst).map_err(|e| errno(&e))?;
+            self.inodes.rename(&src, &dst);
+            Ok(())
+        });
+        match result {
+            Ok(()) => reply.ok(),
+            Err(err) => reply.error(err),
+        }
+    }
+
+    fn read(
+        &mut self,
+        _req: &Request<'_>,
+        ino: u64,
+        _fh: u64,
+        offset: i64,
+        size: u32,
+        _flags: i32,
+        _lock_owner: Option<u64>,
+        reply: ReplyData,
+    ) {
+        let result = self.inodes.path(ino).and_then(|path| {
+            let offset = u64::try_from(offset).map_err(|_| EINVAL)?;
+            self.read_at(path, offset, size).map_err(|e| errno(&e))
+        });
+        match result {
+            Ok(data) => reply.data(&data),
+            Err(err) => reply.error(err),
+        }
+    }
+
+    fn write(
+        &mut self,
+        _req: &Request<'_>,
+        ino: u64,
+        _fh: u64,
+        offset: i64,
+        data: &[u8],
+        _write_flags: u32,
+        _flags: i32,
+        _lock_owner: Option<u64>,
+        reply: ReplyWrite,
+    ) {
+        let result = self.inodes.path(ino).and_then(|path| {
+            let offset = u64::try_from(offset).map_err(|_| EINVAL)?;
+            let size = u32::try_from(data.len()).map_err(|_| EINVAL)?;
+            self.write_at(path, offset, data).map_err(|e| errno(&e))?;
+            Ok(size)
+        });
+        match result {
+            Ok(size) => reply.written(size),
+            Err(err) => reply.error(err),
+        }
+    }
+
+    fn flush(&mut self, _req: &Request<'_>, _ino: u64, _fh: u64, _lock_owner: u64, reply: ReplyEmpty) {
+        match self.fs.flush() {
+            Ok(()) => reply.ok(),
+            Err(err) => reply.error(errno(&err)),
+        }
+    }
+
+    fn fsync(&mut self, req: &Request<'_>, ino: u64, _fh: u64, _datasync: bool, reply: ReplyEmpty) {
+        self.flush(req, ino, 0, 0, reply);
+    }
+
+    fn readdir(&mut self, _req: &Request<'_>, ino: u64, _fh: u64, offset: i64, mut reply: ReplyDirectory) {
+        let path = match self.inodes.path(ino) {
+            Ok(path) => path.to_owned(),
+            Err(err) => return reply.error(err),
+        };
+        let entries = match self.dir_entries(&path) {
+            Ok(entries) => entries,
+            Err(err) => return reply.error(errno(&err)),
+        };
+        let parent_ino = match path.rfind('/') {
+            Some(n) => self.inodes.inode(&path[..n]),
+            None => FUSE_ROOT_ID,
+        };
+        let mut all_entries = vec![(ino, FileType::Directory, ".".to_owned())];
+        all_entries.push((parent_ino, FileType::Directory, "..".to_owned()));
+        for (name, is_dir) in entries {
+            let child_path = if path.is_empty() {
+                name.clone()
+            } else {
+                format!("{}/{}", path, name)
+            };
+            let kind = if is_dir {
+                FileType::Directory
+            } else {
+                FileType::RegularFile
+            };
+            all_entries.push((self.inodes.inode(&child_path), kind, name));
+        }
+        let skip = usize::try_from(offset).unwrap_or(0);
+        for (i, (entry_ino, kind, name)) in all_entries.into_iter().enumerate().skip(skip) {
+            // the offset passed back by the kernel is the index of the next entry
+            if reply.add(entry_ino, (i + 1) as i64, kind, name) {
+                break;
+            }
+        }
+        reply.ok();
+    }
+
+    fn statfs(&mut self, _req: &Request<'_>, _ino: u64, reply: ReplyStatfs) {
+        match self.fs.stats() {
+            Ok(stats) => reply.statfs(
+                u64::from(stats.total_clusters()),
+                u64::from(stats.free_clusters()),
+                u64::from(stats.free_clusters()),
+                0,
+                0,
+                stats.cluster_size(),
+                255,
+                stats.cluster_size(),
+            ),
+            Err(err) => reply.error(errno(&err)),
+        }
+    }
+
+    fn create(
+        &mut self,
+        req: &Request<'_>,
+        parent: u64,
+        name: &OsStr,
+        _mode: u32,
+        _umask: u32,
+        _flags: i32,
+        reply: ReplyCreate,
+    ) {
+        let result = self.inodes.child_path(parent, name).and_then(|path| {
+            self.fs.create_file(&path).map_err(|e| errno(&e))?;
+            self.entry_attr(req, &path)
+        });
+        match result {
+            Ok(attr) => reply.created(&TTL, &attr, 0, 0, 0),
+            Err(err) => reply.error(err),
+        }
+    }
+}
+
+fn run(args: &Args) -> io::Result<()> {
+    let img_file = fs::OpenOptions::new()
+        .read(true)
+        .write(!args.read_only)
+        .open(&args.image)?;
+    let fs = FileSystem::new(img_file, FsOptions::new().read_only(args.read_only))?;
+    let mut options = vec![
+        MountOption::FSName(args.image.clone()),
+        MountOption::Subtype("fat".into()),
+    ];
+    if args.read_only {
+        options.push(MountOption::RO);
+    }
+    // The filesystem is unmounted (flushed) when `FatFuse` is dropped after the image is unmounted from the host
+    fuser::mount2(FatFuse::new(fs), &args.mountpoint, &options)
+}
+
+fn main() -> ExitCode {
+    match run(&Args::parse()) {
+        Ok(()) => ExitCode::SUCCESS,
+        Err(err) => {
+            eprintln!("fatfuse: {}", err);
+            ExitCode::FAILURE
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use axfatfs::{Date, Time};
+
+    #[test]
+    fn test_system_time() {
+        let date_time = DateTime::new(Date::new(2024, 2, 29), Time::new(12, 30, 15, 500));
+        assert_eq!(
+            system_time(date_time),
+            UNIX_EPOCH + Duration::from_millis(1_709_209_815_500)
+        );
+        let date_time = DateTime::new(Date::new(1980, 1, 1), Time::new(0, 0, 0, 0));
+        assert_eq!(system_time(date_time), UNIX_EPOCH + Duration::from_secs(315_532_800));
+    }
+
+    #[test]
+    fn test_inodes_rename() {
+        let mut inodes = Inodes::new();
+        let dir = inodes.inode("dir");
+        let file = inodes.inode("dir/File.txt");
+        let other = inodes.inode("dirx");
+        assert_eq!(inodes.inode("DIR/file.TXT"), file);
+        inodes.rename("dir", "moved/dir2");
+        assert_eq!(inodes.path(dir), Ok("moved/dir2"));
+        assert_eq!(inodes.path(file), Ok("moved/dir2/File.txt"));
+        assert_eq!(inodes.path(other), Ok("dirx"));
+        assert_eq!(inodes.inode("moved/dir2/file.txt"), file);
+    }
+}
@@ -0,0 +1,204 @@
+//! Command line tool for inspecting and modifying FAT filesystem images without mounting them.
+
+use std::fs;
+use std::io::{self, Write};
+use std::process::ExitCode;
+
+use axfatfs::{FileSystem, FsOptions, RepairOptions, ResultExt, StdIoWrapper};
+use clap::{Parser, Subcommand};
+
+#[derive(Parser)]
+#[command(name = "faxfat", version, about = "Inspect and modify FAT filesystem images")]
+struct Args {
+    /// Path of the filesystem image
+    image: String,
+    #[command(subcommand)]
+    command: Command,
+}
+
+#[derive(Subcommand)]
+enum Command {
+    /// List a directory
+    Ls {
+        /// Directory in the image
+        #[arg(default_value = "/")]
+        path: String,
+    },
+    /// Print a file to the standard output
+    Cat {
+        /// File in the image
+        path: String,
+    },
+    /// Copy a host file into the image
+    CpIn {
+        /// Source file on the host
+        src: String,
+        /// Destination path in the image
+        dst: String,
+    },
+    /// Copy a file from the image to the host
+    CpOut {
+        /// Source file in the image
+        src: String,
+        /// Destination path on the host
+        dst: String,
+    },
+    /// Create a directory
+    Mkdir {
+        /// Directory in the image
+        path: String,
+    },
+    /// Remove a file or an empty directory
+    Rm {
+        /// Path in the image
+        path: String,
+    },
+    /// Rename or move a file or directory
+    Mv {
+        /// Source path in the image
+        src: String,
+        /// Destination path in the image
+        dst: String,
+    },
+    /// Print information about the filesystem
+    Info,
+    /// Check the filesystem consistency
+    Fsck {
+        /// Fix found problems
+        #[arg(long)]
+        repair: bool,
+    },
+}
+
+// Removes leading slashes, the root directory is addressed by an empty path
+fn image_path(path: &str) -> &str {
+    path.trim_start_matches('/')
+}
+
+fn ls(fs: &FileSystem<StdIoWrapper<fs::File>>, path: &str) -> io::Result<()> {
+    let path = image_path(path);
+    let dir = if path.is_empty() {
+        fs.root_dir()
+    } else {
+        fs.open_dir(path).path_context("open directory", path)?
+    };
+    for r in dir.iter() {
+        let e = r.path_context("read directory", path)?;
+        let modified = e.modified();
+        println!(
+            "{} {:>10}  {:04}-{:02}-{:02} {:02}:{:02}:{:02}  {}",
+            if e.is_dir() { 'd' } else { '-' },
+            e.len(),
+            modified.date.year,
+            modified.date.month,
+            modified.date.day,
+            modified.time.hour,
+            modified.time.min,
+            modified.time.sec,
+            e.file_name()
+        );
+    }
+    Ok(())
+}
+
+fn info(fs: &FileSystem<StdIoWrapper<fs::File>>) -> io::Result<()> {
+    let stats = fs.stats()?;
+    println!("FAT type:       {:?}", fs.fat_type());
+    println!("Volume ID:      {:08X}", fs.volume_id());
+    println!("Volume label:   {}", fs.volume_label());
+    println!("Cluster size:   {}", stats.cluster_size());
+    println!("Total clusters: {}", stats.total_clusters());
+    println!("Free clusters:  {}", stats.free_clusters());
+    println!("Dirty:          {}", fs.mount_status_flags().dirty());
+    Ok(())
+}
+
+fn fsck(fs: &FileSystem<StdIoWrapper<fs::File>>, repair: bool) -> io::Result<bool> {
+    if repair {
+        let repair_report = fs.repair(RepairOptions::new())?;
+        for action in repair_report.actions() {
+            println!("fixed: {}", action);
+        }
+    }
+    // the repaired filesystem is checked again, so the exit status reflects problems which could not be fixed
+    let report = fs.check()?;
+    for problem in report.problems() {
+        println!("{}", problem);
+    }
+    println!(
+        "{} files, {} directories, {} used clusters, {} free clusters, {} bad clusters",
+        report.files(),
+        report.dirs(),
+        report.used_clusters(),
+        report.free_clusters(),
+        report.bad_clusters()
+    );
+    Ok(report.is_clean())
+}
+
+impl Command {
+    fn modifies_image(&self) -> bool {
+        match self {
+            Command::Ls { .. } | Command::Cat { .. } | Command::CpOut { .. } | Command::Info => false,
+            Command::Fsck { repair } => *repair,
+            _ => true,
+        }
+    }
+}
+
+fn run(args: Args) -> io::Result<bool> {
+    // commands which only read the image never write to it
+    let read_only = !args.command.modifies_image();
+    let img_file = fs::OpenOptions::new().read(true).write(!read_only).open(&args.image)?;
+    let fs = FileSystem::new(img_file, FsOptions::new().read_only(read_only))?;
+    match args.command {
+        Command::Ls { path } => ls(&fs, &path)?,
+        Command::Cat { path } => {
+            let path = image_path(&path);
+            let mut file = fs.open_file(path).path_context("open file", path)?;
+            let mut stdout = io::stdout().lock();
+            io::copy(&mut file, &mut stdout)?;
+            stdout.flush()?;
+        }
+        Command::CpIn { src, dst } => {
+            let mut src_file = fs::File::open(src)?;
+            let dst = image_path(&dst);
+            let mut dst_file = fs.create_file(dst).path_context("create file", dst)?;
+            dst_file.truncate()?;
+            io::copy(&mut src_file, &mut dst_file)?;
+        }
+        Command::CpOut { src, dst } => {
+            let src = image_path(&src);
+            let mut src_file = fs.open_file(src).path_context("open file", src)?;
+            let mut dst_file = fs::File::create(dst)?;
+            io::copy(&mut src_file, &mut dst_file)?;
+        }
+        Command::Mkdir { path } => {
+            let path = image_path(&path);
+            fs.create_dir(path).path_context("create directory", path)?;
+        }
+        Command::Rm { path } => fs.remove(image_path(&path)).path_context("remove", &path)?,
+        Command::Mv { src, dst } => fs
+            .rename(image_path(&src), image_path(&dst))
+            .path_context("rename", &src)?,
+        Command::Info => info(&fs)?,
+        Command::Fsck { repair } => {
+            let clean = fsck(&fs, repair)?;
+            fs.unmount()?;
+            return Ok(clean);
+        }
+    }
+    fs.unmount()?;
+    Ok(true)
+}
+
+fn main() -> ExitCode {
+    match run(Args::parse()) {
+        Ok(true) => ExitCode::SUCCESS,
+        Ok(false) => ExitCode::FAILURE,
+        Err(err) => {
+            eprintln!("faxfat: {}", err);
+            ExitCode::FAILURE
+        }
+    }
+}
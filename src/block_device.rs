@@ -0,0 +1,499 @@
+use core::cmp;
+use core::fmt;
+
+use crate::error::IoError;
+use crate::fs::MAX_SECTOR_SIZE;
+use crate::io::{AlignedBuffer, IoBase, Read, Seek, SeekFrom, Write};
+
+/// A block device driver.
+///
+/// The trait is a copy of the `BlockDriverOps` trait from the `axdriver_block` crate used by `ArceOS` drivers
+/// (virtio-blk, ramdisk, SDHCI), so the crate does not depend on it by default. If the `axdriver_block` feature is
+/// enabled this trait is implemented for all types implementing `BlockDriverOps`, otherwise such drivers can be
+/// wrapped in a newtype forwarding the calls. Buffers passed to `read_block` and `write_block` are always a non-zero
+/// multiple of the block size long.
+pub trait BlockDevice {
+    /// Type of errors returned by the driver.
+    type Error: fmt::Debug;
+
+    /// Returns the number of blocks of the device.
+    fn num_blocks(&self) -> u64;
+
+    /// Returns the size of a block in bytes.
+    fn block_size(&self) -> usize;
+
+    /// Returns the alignment of memory addresses of buffers required by the driver (e.g. by a DMA engine).
+    ///
+    /// It is returned by `IoBase::buffer_alignment` of `BlockDeviceStorage`. Default is `1` (no requirement).
+    fn buffer_alignment(&self) -> usize {
+        1
+    }
+
+    /// Reads consecutive blocks starting from `block_id` into `buf`.
+    ///
+    /// # Errors
+    ///
+    /// Driver specific errors.
+    fn read_block(&mut self, block_id: u64, buf: &mut [u8]) -> Result<(), Self::Error>;
+
+    /// Writes consecutive blocks starting from `block_id` from `buf`.
+    ///
+    /// # Errors
+    ///
+    /// Driver specific errors.
+    fn write_block(&mut self, block_id: u64, buf: &[u8]) -> Result<(), Self::Error>;
+
+    /// Flushes data written to the device.
+    ///
+    /// # Errors
+    ///
+    /// Driver specific errors.
+    fn flush(&mut self) -> Result<(), Self::Error>;
+}
+
+#[cfg(feature = "axdriver_block")]
+impl<T: axdriver_block::BlockDriverOps> BlockDevice for T {
+    type Error = axdriver_block::DevError;
+
+    fn num_blocks(&self) -> u64 {
+        axdriver_block::BlockDriverOps::num_blocks(self)
+    }
+
+    fn block_size(&self) -> usize {
+        axdriver_block::BlockDriverOps::block_size(self)
+    }
+
+    fn read_block(&mut self, block_id: u64, buf: &mut [u8]) -> Result<(), Self::Error> {
+        axdriver_block::BlockDriverOps::read_block(self, block_id, buf)
+    }
+
+    fn write_block(&mut self, block_id: u64, buf: &[u8]) -> Result<(), Self::Error> {
+        axdriver_block::BlockDriverOps::write_block(self, block_id, buf)
+    }
+
+    fn flush(&mut self) -> Result<(), Self::Error> {
+        axdriver_block::BlockDriverOps::flush(self)
+    }
+}
+
+/// An error returned by `BlockDeviceStorage`.
+#[derive(Debug)]
+pub enum BlockDeviceError<E> {
+    /// The block device driver returned an error.
+    Io(E),
+    /// A read operation cannot be completed because an end of the storage has been reached prematurely.
+    UnexpectedEof,
+    /// A write operation cannot be completed because an end of the storage has been reached.
+    WriteZero,
+    /// A seek to a negative position was requested.
+    InvalidSeek,
+    /// The block size of the device is not a power of two or it is bigger than the supported sector size.
+    UnsupportedBlockSize,
+    /// The partition is not contained in the device.
+    InvalidPartition,
+}
+
+impl<E: fmt::Debug> IoError for BlockDeviceError<E> {
+    fn is_interrupted(&self) -> bool {
+        false
+    }
+
+    fn new_unexpected_eof_error() -> Self {
+        BlockDeviceError::UnexpectedEof
+    }
+
+    fn new_write_zero_error() -> Self {
+        BlockDeviceError::WriteZero
+    }
+}
+
+impl<E: fmt::Debug> fmt::Display for BlockDeviceError<E> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            BlockDeviceError::Io(io_error) => write!(f, "IO error: {:?}", io_error),
+            BlockDeviceError::UnexpectedEof => write!(f, "Unexpected end of file"),
+            BlockDeviceError::WriteZero => write!(f, "Write zero"),
+            BlockDeviceError::InvalidSeek => write!(f, "Invalid seek"),
+            BlockDeviceError::UnsupportedBlockSize => write!(f, "Unsupported block size"),
+            BlockDeviceError::InvalidPartition => write!(f, "Partition out of device bounds"),
+        }
+    }
+}
+
+/// A storage adapter for block devices.
+///
+/// Provides byte-granular `Read`, `Write` and `Seek` implementations over a `BlockDevice` or a range of its blocks
+/// (a partition). Transfers of whole aligned blocks are passed directly to the driver, partial blocks are read into
+/// an internal buffer and written back using read-modify-write. The internal buffer is aligned to 4096 bytes, so
+/// partial block transfers meet the buffer alignment required by the device.
+pub struct BlockDeviceStorage<D> {
+    device: D,
+    first_block: u64,
+    num_blocks: u64,
+    block_size: usize,
+    pos: u64,
+    buf: AlignedBuffer,
+}
+
+impl<D: BlockDevice> BlockDeviceStorage<D> {
+    /// Creates a new `BlockDeviceStorage` instance spanning the whole `device`.
+    ///
+    /// # Errors
+    ///
+    /// `BlockDeviceError::UnsupportedBlockSize` will be returned if the block size of the device is not a power of
+    /// two or it is bigger than the largest sector size supported by the enabled features.
+    pub fn new(device: D) -> Result<Self, BlockDeviceError<D::Error>> {
+        let num_blocks = device.num_blocks();
+        Self::with_partition(device, 0, num_blocks)
+    }
+
+    /// Creates a new `BlockDeviceStorage` instance spanning `num_blocks` blocks of `device` starting from
+    /// `first_block`.
+    ///
+    /// Position 0 of the storage corresponds to the first byte of `first_block`, so the partition start offset
+    /// found in a partition table can be passed directly.
+    ///
+    /// # Errors
+    ///
+    /// Errors that can be returned:
+    ///
+    /// * `BlockDeviceError::UnsupportedBlockSize` will be returned if the block size of the device is not a power of
+    ///   two or it is bigger than the largest sector size supported by the enabled features.
+    /// * `BlockDeviceError::InvalidPartition` will be returned if the partition does not fit in the device.
+    pub fn with_partition(device: D, first_block: u64, num_blocks: u64) -> Result<Self, BlockDeviceError<D::Error>> {
+        let block_size = device.block_size();
+        if !block_size.is_power_of_two() || block_size > MAX_SECTOR_SIZE {
+            return Err(BlockDeviceError::UnsupportedBlockSize);
+        }
+        match first_block.checked_add(num_blocks) {
+            Some(end_block) if end_block <= device.num_blocks() => {}
+            _ => return Err(BlockDeviceError::InvalidPartition),
+        }
+        Ok(Self {
+            device,
+            first_block,
+            num_blocks,
+            block_size,
+            pos: 0,
+            buf: AlignedBuffer([0; MAX_SECTOR_SIZE]),
+        })
+    }
+
+    /// Returns inner block device
+    pub fn into_inner(self) -> D {
+        self.device
+    }
+
+    fn size(&self) -> u64 {
+        self.num_blocks * self.block_size as u64
+    }
+
+    // Returns the device block containing the current position and the offset of the position in this block
+    fn block_and_offset(&self) -> (u64, usize) {
+        let block_size = self.block_size as u64;
+        (
+            self.first_block + self.pos / block_size,
+            (self.pos % block_size) as usize,
+        )
+    }
+
+    // Returns the number of bytes which can be transferred from the current position, limited by the storage size
+    fn available(&self, len: usize) -> usize {
+        cmp::min(len as u64, self.size().saturating_sub(self.pos)) as usize
+    }
+}
+
+impl<D: BlockDevice> IoBase for BlockDeviceStorage<D> {
+    type Error = BlockDeviceError<D::Error>;
+
+    fn buffer_alignment(&self) -> usize {
+        self.device.buffer_alignment()
+    }
+}
+
+impl<D: BlockDevice> Read for BlockDeviceStorage<D> {
+    fn read(&mut self, buf: &mut [u8]) -> Result<usize, Self::Error> {
+        let len = self.available(buf.len());
+        if len == 0 {
+            return Ok(0);
+        }
+        let (block, offset) = self.block_and_offset();
+        let block_size = self.block_size;
+        let n = if offset == 0 && len >= block_size {
+            let n = len - len % block_size;
+            self.device
+                .read_block(block, &mut buf[..n])
+                .map_err(BlockDeviceError::Io)?;
+            n
+        } else {
+            let n = cmp::min(len, block_size - offset);
+            self.device
+                .read_block(block, &mut self.buf.0[..block_size])
+                .map_err(BlockDeviceError::Io)?;
+            buf[..n].copy_from_slice(&self.buf.0[offset..offset + n]);
+            n
+        };
+        self.pos += n as u64;
+        Ok(n)
+    }
+}
+
+impl<D: BlockDevice> Write for BlockDeviceStorage<D> {
+    fn write(&mut self, buf: &[u8]) -> Result<usize, Self::Error> {
+        let len = self.available(buf.len());
+        if len == 0 {
+            return Ok(0);
+        }
+        let (block, offset) = self.block_and_offset();
+        let block_size = self.block_size;
+        let n = if offset == 0 && len >= block_size {
+            let n = len - len % block_size;
+            self.device
+                .write_block(block, &buf[..n])
+                .map_err(BlockDeviceError::Io)?;
+            n
+        } else {
+            let n = cmp::min(len, block_size - offset);
+            self.device
+                .read_block(block, &mut self.buf.0[..block_size])
+                .map_err(BlockDeviceError::Io)?;
+            self.buf.0[offset..offset + n].copy_from_slice(&buf[..n]);
+            self.device
+                .write_block(block, &self.buf.0[..block_size])
+                .map_err(BlockDeviceError::Io)?;
+            n
+        };
+        self.pos += n as u64;
+        Ok(n)
+    }
+
+    fn flush(&mut self) -> Result<(), Self::Error> {
+        self.device.flush().map_err(BlockDeviceError::Io)
+    }
+}
+
+impl<D: BlockDevice> Seek for BlockDeviceStorage<D> {
+    fn seek(&mut self, pos: SeekFrom) -> Result<u64, Self::Error> {
+        let (base, offset) = match pos {
+            SeekFrom::Start(n) => (n, 0),
+            SeekFrom::End(n) => (self.size(), n),
+            SeekFrom::Current(n) => (self.pos, n),
+        };
+        let new_pos = if offset >= 0 {
+            base.checked_add(offset.unsigned_abs())
+        } else {
+            base.checked_sub(offset.unsigned_abs())
+        };
+        self.pos = new_pos.ok_or(BlockDeviceError::InvalidSeek)?;
+        Ok(self.pos)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{format_volume, FileSystem, FormatVolumeOptions, FsOptions};
+
+    struct RamDisk {
+        data: std::vec::Vec<u8>,
+        block_size: usize,
+        alignment: usize,
+        requests: usize,
+    }
+
+    impl RamDisk {
+        fn new(num_blocks: usize, block_size: usize) -> Self {
+            Self {
+                data: std::vec![0xAA; num_blocks * block_size],
+                block_size,
+                alignment: 1,
+                requests: 0,
+            }
+        }
+    }
+
+    impl BlockDevice for RamDisk {
+        type Error = ();
+
+        fn num_blocks(&self) -> u64 {
+            (self.data.len() / self.block_size) as u64
+        }
+
+        fn block_size(&self) -> usize {
+            self.block_size
+        }
+
+        fn buffer_alignment(&self) -> usize {
+            self.alignment
+        }
+
+        fn read_block(&mut self, block_id: u64, buf: &mut [u8]) -> Result<(), Self::Error> {
+            assert!(!buf.is_empty() && buf.len() % self.block_size == 0);
+            assert!(buf.as_ptr() as usize % self.alignment == 0);
+            self.requests += 1;
+            let start = block_id as usize * self.block_size;
+            buf.copy_from_slice(self.data.get(start..start + buf.len()).ok_or(())?);
+            Ok(())
+        }
+
+        fn write_block(&mut self, block_id: u64, buf: &[u8]) -> Result<(), Self::Error> {
+            assert!(!buf.is_empty() && buf.len() % self.block_size == 0);
+            assert!(buf.as_ptr() as usize % self.alignment == 0);
+            self.requests += 1;
+            let start = block_id as usize * self.block_size;
+            self.data
+                .get_mut(start..start + buf.len())
+                .ok_or(())?
+                .copy_from_slice(buf);
+            Ok(())
+        }
+
+        fn flush(&mut self) -> Result<(), Self::Error> {
+            Ok(())
+        }
+    }
+
+    #[test]
+    fn test_block_device_storage_partition() {
+        let mut storage = BlockDeviceStorage::with_partition(RamDisk::new(4096, 512), 2048, 2048).unwrap();
+        format_volume(&mut storage, FormatVolumeOptions::new()).unwrap();
+        storage.seek(SeekFrom::Start(0)).unwrap();
+        let fs = FileSystem::new(storage, FsOptions::new()).unwrap();
+        let mut file = fs.root_dir().create_file("block.txt").unwrap();
+        file.write_all(b"Hello block device!").unwrap();
+        drop(file);
+        let disk = fs.into_inner().unwrap().into_inner();
+        // Blocks before the partition are untouched
+        assert!(disk.data[..2048 * 512].iter().all(|b| *b == 0xAA));
+        assert_eq!(&disk.data[2048 * 512 + 510..2048 * 512 + 512], &[0x55, 0xAA]);
+
+        let storage = BlockDeviceStorage::with_partition(disk, 2048, 2048).unwrap();
+        let fs = FileSystem::new(storage, FsOptions::new()).unwrap();
+        let mut buf = [0_u8; 19];
+        fs.root_dir()
+            .open_file("block.txt")
+            .unwrap()
+            .read_exact(&mut buf)
+            .unwrap();
+        assert_eq!(&buf, b"Hello block device!");
+    }
+
+    #[test]
+    fn test_block_device_storage_buffer_alignment() {
+        let mut disk = RamDisk::new(4096, 512);
+        disk.alignment = 512;
+        let mut storage = BlockDeviceStorage::new(disk).unwrap();
+        assert_eq!(storage.buffer_alignment(), 512);
+        format_volume(&mut storage, FormatVolumeOptions::new()).unwrap();
+        let fs = FileSystem::new(storage, FsOptions::new()).unwrap();
+        let data = [7_u8; 1500];
+        let mut file = fs.root_dir().create_file("dma.bin").unwrap();
+        file.write_all(&data[1..]).unwrap();
+        file.seek(SeekFrom::Start(0)).unwrap();
+        let mut buf = [0_u8; 1500];
+        file.read_exact(&mut buf[1..]).unwrap();
+        assert_eq!(&buf[1..], &data[1..]);
+    }
+
+    #[cfg(feature = "axdriver_block")]
+    struct AxRamDisk(RamDisk);
+
+    #[cfg(feature = "axdriver_block")]
+    impl axdriver_block::BaseDriverOps for AxRamDisk {
+        fn device_name(&self) -> &'static str {
+            "ramdisk"
+        }
+
+        fn device_type(&self) -> axdriver_block::DeviceType {
+            axdriver_block::DeviceType::Block
+        }
+    }
+
+    #[cfg(feature = "axdriver_block")]
+    impl axdriver_block::BlockDriverOps for AxRamDisk {
+        fn num_blocks(&self) -> u64 {
+            self.0.num_blocks()
+        }
+
+        fn block_size(&self) -> usize {
+            self.0.block_size()
+        }
+
+        fn read_block(&mut self, block_id: u64, buf: &mut [u8]) -> axdriver_block::DevResult {
+            self.0
+                .read_block(block_id, buf)
+                .map_err(|()| axdriver_block::DevError::Io)
+        }
+
+        fn write_block(&mut self, block_id: u64, buf: &[u8]) -> axdriver_block::DevResult {
+            self.0
+                .write_block(block_id, buf)
+                .map_err(|()| axdriver_block::DevError::Io)
+        }
+
+        fn flush(&mut self) -> axdriver_block::DevResult {
+            Ok(())
+        }
+    }
+
+    #[test]
+    #[cfg(feature = "axdriver_block")]
+    fn test_block_driver_ops_storage() {
+        let mut storage = BlockDeviceStorage::with_partition(AxRamDisk(RamDisk::new(4096, 512)), 2048, 2048).unwrap();
+        format_volume(&mut storage, FormatVolumeOptions::new()).unwrap();
+        let fs = FileSystem::new(storage, FsOptions::new()).unwrap();
+        fs.root_dir()
+            .create_file("ax.txt")
+            .unwrap()
+            .write_all(b"ArceOS")
+            .unwrap();
+        let disk = fs.into_inner().unwrap().into_inner();
+        assert!(disk.0.data[..2048 * 512].iter().all(|b| *b == 0xAA));
+
+        let fs = FileSystem::new(
+            BlockDeviceStorage::with_partition(disk, 2048, 2048).unwrap(),
+            FsOptions::new(),
+        )
+        .unwrap();
+        let mut buf = [0_u8; 6];
+        fs.root_dir().open_file("ax.txt").unwrap().read_exact(&mut buf).unwrap();
+        assert_eq!(&buf, b"ArceOS");
+    }
+
+    #[test]
+    fn test_block_device_storage_unaligned_io() {
+        let mut storage = BlockDeviceStorage::new(RamDisk::new(4, 512)).unwrap();
+        assert_eq!(storage.seek(SeekFrom::End(-1)).unwrap(), 2047);
+        assert_eq!(storage.write(&[1, 2, 3]).unwrap(), 1);
+        assert_eq!(storage.write(&[1]).unwrap(), 0);
+        storage.seek(SeekFrom::Start(500)).unwrap();
+        storage.write_all(&[7; 600]).unwrap();
+        storage.seek(SeekFrom::Current(-600)).unwrap();
+        let mut buf = [0_u8; 602];
+        storage.read_exact(&mut buf).unwrap();
+        assert!(buf[..600].iter().all(|b| *b == 7));
+        assert_eq!(&buf[600..], &[0xAA, 0xAA]);
+        assert!(matches!(
+            storage.seek(SeekFrom::Current(-2000)),
+            Err(BlockDeviceError::InvalidSeek)
+        ));
+        // Whole aligned blocks are transferred using a single request
+        let mut disk = storage.into_inner();
+        disk.requests = 0;
+        let mut storage = BlockDeviceStorage::new(disk).unwrap();
+        storage.write_all(&[9; 1024]).unwrap();
+        assert_eq!(storage.into_inner().requests, 1);
+    }
+
+    #[test]
+    fn test_block_device_storage_invalid_geometry() {
+        assert!(matches!(
+            BlockDeviceStorage::new(RamDisk::new(4, 100)),
+            Err(BlockDeviceError::UnsupportedBlockSize)
+        ));
+        assert!(matches!(
+            BlockDeviceStorage::with_partition(RamDisk::new(4, 512), 2, 3),
+            Err(BlockDeviceError::InvalidPartition)
+        ));
+    }
+}
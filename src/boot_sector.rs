@@ -13,7 +13,7 @@ use core::slice;
 
 use crate::dir_entry::DIR_ENTRY_SIZE;
 use crate::error::{Error, IoError};
-use crate::fs::{FatType, FormatVolumeOptions, FsStatusFlags};
+use crate::fs::{FatType, FormatVolumeOptions, FsStatusFlags, ValidationLevel, MAX_SECTOR_SIZE};
 use crate::io::{Read, ReadLeExt, Write, WriteLeExt};
 use crate::table::RESERVED_FAT_ENTRIES;
 
@@ -23,8 +23,59 @@ const KB_64: u64 = 1024;
 const MB_64: u64 = KB_64 * 1024;
 const GB_64: u64 = MB_64 * 1024;
 
+// Size of the boot code area following the BPB on FAT12/FAT16 volumes
+pub(crate) const BOOT_CODE_SIZE: usize = 448;
+// Size of the boot code area following the extended BPB on FAT32 volumes
+const FAT32_BOOT_CODE_SIZE: usize = 420;
+// Offset of the boot signature in the Boot Sector
+const BOOT_SIG_OFFSET: usize = 510;
+
+// Custom jump instruction and boot code written when formatting a volume
+#[derive(Debug, Clone)]
+pub(crate) struct BootCode {
+    pub(crate) bootjmp: [u8; 3],
+    pub(crate) code: [u8; BOOT_CODE_SIZE],
+    pub(crate) len: usize,
+}
+
+impl BootCode {
+    fn validate<E: IoError>(&self, fat_type: FatType) -> Result<(), Error<E>> {
+        let code_size = if fat_type == FatType::Fat32 {
+            FAT32_BOOT_CODE_SIZE
+        } else {
+            BOOT_CODE_SIZE
+        };
+        if self.len > code_size {
+            error!(
+                "Boot code is too long: {} bytes, {} bytes available",
+                self.len, code_size
+            );
+            return Err(Error::InvalidInput);
+        }
+        // Jump must skip the BPB and land in the boot code area
+        let target = match self.bootjmp {
+            [0xEB, rel, 0x90] => 2 + i32::from(i8::from_le_bytes([rel])),
+            [0xE9, lo, hi] => 3 + i32::from(i16::from_le_bytes([lo, hi])),
+            _ => {
+                error!("Boot code must start with a short jump followed by NOP or with a near jump");
+                return Err(Error::InvalidInput);
+            }
+        };
+        let code_area = BOOT_SIG_OFFSET - code_size..BOOT_SIG_OFFSET;
+        if !usize::try_from(target).map_or(false, |t| code_area.contains(&t)) {
+            error!("Boot code jump target {:#x} is outside of the boot code area", target);
+            return Err(Error::InvalidInput);
+        }
+        Ok(())
+    }
+}
+
+/// A BIOS Parameter Block parsed from the Boot Sector.
+///
+/// It is returned by `FileSystem::bpb` for diagnostic tools. Values are returned as they are stored on the disk,
+/// except for the methods computing values from several fields (e.g. `total_sectors`).
 #[derive(Default, Debug, Clone)]
-pub(crate) struct BiosParameterBlock {
+pub struct BiosParameterBlock {
     pub(crate) bytes_per_sector: u16,
     pub(crate) sectors_per_cluster: u8,
     pub(crate) reserved_sectors: u16,
@@ -141,17 +192,17 @@ impl BiosParameterBlock {
             );
             return Err(Error::CorruptedFileSystem);
         }
-        if self.bytes_per_sector < 512 || self.bytes_per_sector > 4096 {
+        if self.bytes_per_sector < 512 || usize::from(self.bytes_per_sector) > MAX_SECTOR_SIZE {
             error!(
-                "invalid bytes_per_sector value in BPB: expected value in range [512, 4096] but got {}",
-                self.bytes_per_sector
+                "invalid bytes_per_sector value in BPB: expected value in range [512, {}] but got {}",
+                MAX_SECTOR_SIZE, self.bytes_per_sector
             );
             return Err(Error::CorruptedFileSystem);
         }
         Ok(())
     }
 
-    fn validate_sectors_per_cluster<E: IoError>(&self) -> Result<(), Error<E>> {
+    fn validate_sectors_per_cluster<E: IoError>(&self, level: ValidationLevel) -> Result<(), Error<E>> {
         if !self.sectors_per_cluster.is_power_of_two() {
             error!(
                 "invalid sectors_per_cluster value in BPB: expected a power of two but got {}",
@@ -170,11 +221,14 @@ impl BiosParameterBlock {
             // However, >32k is not as thoroughly tested...
             warn!("fs compatibility: bytes_per_cluster value '{}' in BPB exceeds '{}', and thus may be incompatible with some implementations",
                 bytes_per_cluster, maximum_compatibility_bytes_per_cluster);
+            if level == ValidationLevel::Strict {
+                return Err(Error::CorruptedFileSystem);
+            }
         }
         Ok(())
     }
 
-    fn validate_reserved_sectors<E: IoError>(&self) -> Result<(), Error<E>> {
+    fn validate_reserved_sectors<E: IoError>(&self, level: ValidationLevel) -> Result<(), Error<E>> {
         let is_fat32 = self.is_fat32();
         if self.reserved_sectors < 1 {
             error!("invalid reserved_sectors value in BPB: {}", self.reserved_sectors);
@@ -186,6 +240,9 @@ impl BiosParameterBlock {
                 "fs compatibility: reserved_sectors value '{}' in BPB is not '1', and thus is incompatible with some implementations",
                 self.reserved_sectors
             );
+            if level == ValidationLevel::Strict {
+                return Err(Error::CorruptedFileSystem);
+            }
         }
         if is_fat32 && self.backup_boot_sector >= self.reserved_sectors {
             error!(
@@ -204,7 +261,7 @@ impl BiosParameterBlock {
         Ok(())
     }
 
-    fn validate_fats<E: IoError>(&self) -> Result<(), Error<E>> {
+    fn validate_fats<E: IoError>(&self, level: ValidationLevel) -> Result<(), Error<E>> {
         if self.fats == 0 {
             error!("invalid fats value in BPB: {}", self.fats);
             return Err(Error::CorruptedFileSystem);
@@ -215,13 +272,22 @@ impl BiosParameterBlock {
                 "fs compatibility: numbers of FATs '{}' in BPB is greater than '2', and thus is incompatible with some implementations",
                 self.fats
             );
+            if level == ValidationLevel::Strict {
+                return Err(Error::CorruptedFileSystem);
+            }
         }
         Ok(())
     }
 
-    fn validate_root_entries<E: IoError>(&self) -> Result<(), Error<E>> {
+    fn validate_root_entries<E: IoError>(&self, level: ValidationLevel) -> Result<(), Error<E>> {
         let is_fat32 = self.is_fat32();
-        if is_fat32 && self.root_entries != 0 {
+        if is_fat32 && self.root_entries != 0 && level == ValidationLevel::Lenient {
+            // the field is not used by FAT32
+            warn!(
+                "Ignoring non-zero root_entries value in FAT32 BPB: {}",
+                self.root_entries
+            );
+        } else if is_fat32 && self.root_entries != 0 {
             error!(
                 "Invalid root_entries value in FAT32 BPB: expected 0 but got {}",
                 self.root_entries
@@ -235,15 +301,19 @@ impl BiosParameterBlock {
             );
             return Err(Error::CorruptedFileSystem);
         }
-        if (u32::from(self.root_entries) * DIR_ENTRY_SIZE) % u32::from(self.bytes_per_sector) != 0 {
+        if !is_fat32 && (u32::from(self.root_entries) * DIR_ENTRY_SIZE) % u32::from(self.bytes_per_sector) != 0 {
             warn!("Root entries should fill sectors fully");
+            if level == ValidationLevel::Strict {
+                return Err(Error::CorruptedFileSystem);
+            }
         }
         Ok(())
     }
 
-    fn validate_total_sectors<E: IoError>(&self) -> Result<(), Error<E>> {
+    fn validate_total_sectors<E: IoError>(&self, level: ValidationLevel) -> Result<(), Error<E>> {
         let is_fat32 = self.is_fat32();
-        if is_fat32 && self.total_sectors_16 != 0 {
+        let lenient = level == ValidationLevel::Lenient;
+        if is_fat32 && self.total_sectors_16 != 0 && !(lenient && self.total_sectors_32 != 0) {
             error!(
                 "Invalid total_sectors_16 value in FAT32 BPB: expected 0 but got {}",
                 self.total_sectors_16
@@ -258,8 +328,14 @@ impl BiosParameterBlock {
             && self.total_sectors_32 != 0
             && u32::from(self.total_sectors_16) != self.total_sectors_32
         {
-            error!("Invalid BPB: total_sectors_16 and total_sectors_32 are non-zero and have conflicting values");
-            return Err(Error::CorruptedFileSystem);
+            if !lenient {
+                error!("Invalid BPB: total_sectors_16 and total_sectors_32 are non-zero and have conflicting values");
+                return Err(Error::CorruptedFileSystem);
+            }
+            warn!(
+                "BPB: total_sectors_16 and total_sectors_32 have conflicting values, using {}",
+                self.total_sectors()
+            );
         }
         let total_sectors = self.total_sectors();
         let first_data_sector = self.first_data_sector();
@@ -285,7 +361,7 @@ impl BiosParameterBlock {
         Ok(())
     }
 
-    fn validate_total_clusters<E: IoError>(&self) -> Result<(), Error<E>> {
+    fn validate_total_clusters<E: IoError>(&self, level: ValidationLevel) -> Result<(), Error<E>> {
         let is_fat32 = self.is_fat32();
         let total_clusters = self.total_clusters();
         let fat_type = FatType::from_clusters(total_clusters);
@@ -306,31 +382,55 @@ impl BiosParameterBlock {
                 "FAT is too small (allows allocation of {} clusters) compared to the total number of clusters ({})",
                 usable_fat_entries, total_clusters
             );
+            if level == ValidationLevel::Strict {
+                return Err(Error::CorruptedFileSystem);
+            }
         }
         Ok(())
     }
 
-    fn validate<E: IoError>(&self) -> Result<(), Error<E>> {
-        if self.fs_version != 0 {
-            error!("Unsupported filesystem version: expected 0 but got {}", self.fs_version);
+    fn validate_drive_num<E: IoError>(&self, level: ValidationLevel) -> Result<(), Error<E>> {
+        // the field is only meaningful for BIOS boot code, many devices store garbage there
+        if level == ValidationLevel::Strict && self.ext_sig == 0x29 && self.drive_num != 0x00 && self.drive_num != 0x80
+        {
+            error!(
+                "Invalid drive_num value in BPB: expected 0x00 or 0x80 but got {:#x}",
+                self.drive_num
+            );
             return Err(Error::CorruptedFileSystem);
         }
+        Ok(())
+    }
+
+    fn validate<E: IoError>(&self, level: ValidationLevel) -> Result<(), Error<E>> {
+        if self.fs_version != 0 {
+            if level != ValidationLevel::Lenient {
+                error!("Unsupported filesystem version: expected 0 but got {}", self.fs_version);
+                return Err(Error::CorruptedFileSystem);
+            }
+            warn!("Ignoring unknown filesystem version {}", self.fs_version);
+        }
         self.validate_bytes_per_sector()?;
-        self.validate_sectors_per_cluster()?;
-        self.validate_reserved_sectors()?;
-        self.validate_fats()?;
-        self.validate_root_entries()?;
-        self.validate_total_sectors()?;
+        self.validate_sectors_per_cluster(level)?;
+        self.validate_reserved_sectors(level)?;
+        self.validate_fats(level)?;
+        self.validate_root_entries(level)?;
+        self.validate_total_sectors(level)?;
         self.validate_sectors_per_fat()?;
-        self.validate_total_clusters()?;
+        self.validate_total_clusters(level)?;
+        self.validate_drive_num(level)?;
         Ok(())
     }
 
-    pub(crate) fn mirroring_enabled(&self) -> bool {
+    /// Checks if all FAT copies are updated (FAT32 extended flags). It is always `true` on FAT12 and FAT16 volumes.
+    #[must_use]
+    pub fn mirroring_enabled(&self) -> bool {
         self.extended_flags & 0x80 == 0
     }
 
-    pub(crate) fn active_fat(&self) -> u16 {
+    /// Returns the zero-based number of the FAT copy used when mirroring is disabled.
+    #[must_use]
+    pub fn active_fat(&self) -> u16 {
         // The zero-based number of the active FAT is only valid if mirroring is disabled.
         if self.mirroring_enabled() {
             0
@@ -339,7 +439,9 @@ impl BiosParameterBlock {
         }
     }
 
-    pub(crate) fn status_flags(&self) -> FsStatusFlags {
+    /// Returns the volume status flags stored in the Boot Sector.
+    #[must_use]
+    pub fn status_flags(&self) -> FsStatusFlags {
         FsStatusFlags::decode(self.reserved_1)
     }
 
@@ -350,7 +452,9 @@ impl BiosParameterBlock {
         self.sectors_per_fat_16 == 0
     }
 
-    pub(crate) fn sectors_per_fat(&self) -> u32 {
+    /// Returns the number of sectors occupied by a single FAT copy.
+    #[must_use]
+    pub fn sectors_per_fat(&self) -> u32 {
         if self.is_fat32() {
             self.sectors_per_fat_32
         } else {
@@ -358,19 +462,43 @@ impl BiosParameterBlock {
         }
     }
 
-    pub(crate) fn total_sectors(&self) -> u32 {
-        if self.total_sectors_16 == 0 {
+    /// Returns the total number of sectors of the volume from the 16-bit or 32-bit field.
+    #[must_use]
+    pub fn total_sectors(&self) -> u32 {
+        // total_sectors_16 should be zero on FAT32, prefer the 32-bit field for volumes accepted in lenient mode
+        if self.total_sectors_16 == 0 || (self.is_fat32() && self.total_sectors_32 != 0) {
             self.total_sectors_32
         } else {
             u32::from(self.total_sectors_16)
         }
     }
 
-    pub(crate) fn reserved_sectors(&self) -> u32 {
+    pub(crate) fn set_total_sectors(&mut self, total_sectors: u32) {
+        match u16::try_from(total_sectors) {
+            Ok(total_sectors_16) if !self.is_fat32() => {
+                self.total_sectors_16 = total_sectors_16;
+                self.total_sectors_32 = 0;
+            }
+            _ => {
+                self.total_sectors_16 = 0;
+                self.total_sectors_32 = total_sectors;
+            }
+        }
+    }
+
+    /// Returns the number of reserved sectors before the first FAT, including the Boot Sector.
+    #[must_use]
+    pub fn reserved_sectors(&self) -> u32 {
         u32::from(self.reserved_sectors)
     }
 
-    pub(crate) fn root_dir_sectors(&self) -> u32 {
+    /// Returns the number of sectors occupied by the FAT12/FAT16 root directory. It is zero on FAT32 volumes.
+    #[must_use]
+    pub fn root_dir_sectors(&self) -> u32 {
+        if self.is_fat32() {
+            // FAT32 root directory is stored in clusters
+            return 0;
+        }
         let root_dir_bytes = u32::from(self.root_entries) * DIR_ENTRY_SIZE;
         (root_dir_bytes + u32::from(self.bytes_per_sector) - 1) / u32::from(self.bytes_per_sector)
     }
@@ -379,13 +507,17 @@ impl BiosParameterBlock {
         u32::from(self.fats) * self.sectors_per_fat()
     }
 
-    pub(crate) fn first_data_sector(&self) -> u32 {
+    /// Returns the number of the first sector of the data area (cluster 2).
+    #[must_use]
+    pub fn first_data_sector(&self) -> u32 {
         let root_dir_sectors = self.root_dir_sectors();
         let fat_sectors = self.sectors_per_all_fats();
         self.reserved_sectors() + fat_sectors + root_dir_sectors
     }
 
-    pub(crate) fn total_clusters(&self) -> u32 {
+    /// Returns the number of clusters in the data area.
+    #[must_use]
+    pub fn total_clusters(&self) -> u32 {
         let total_sectors = self.total_sectors();
         let first_data_sector = self.first_data_sector();
         let data_sectors = total_sectors - first_data_sector;
@@ -402,7 +534,9 @@ impl BiosParameterBlock {
         clusters * u32::from(self.sectors_per_cluster)
     }
 
-    pub(crate) fn cluster_size(&self) -> u32 {
+    /// Returns the cluster size in bytes.
+    #[must_use]
+    pub fn cluster_size(&self) -> u32 {
         u32::from(self.sectors_per_cluster) * u32::from(self.bytes_per_sector)
     }
 
@@ -411,20 +545,125 @@ impl BiosParameterBlock {
         ((bytes + cluster_size - 1) / cluster_size) as u32
     }
 
-    pub(crate) fn fs_info_sector(&self) -> u32 {
+    /// Returns the number of the FS Information Sector. It is only used on FAT32 volumes.
+    #[must_use]
+    pub fn fs_info_sector(&self) -> u32 {
         u32::from(self.fs_info_sector)
     }
 
-    pub(crate) fn backup_boot_sector(&self) -> u32 {
+    /// Returns the number of the backup Boot Sector or zero if there is none. It is only used on FAT32 volumes.
+    #[must_use]
+    pub fn backup_boot_sector(&self) -> u32 {
         u32::from(self.backup_boot_sector)
     }
 }
 
+impl BiosParameterBlock {
+    /// Returns the number of bytes per sector.
+    #[must_use]
+    pub fn bytes_per_sector(&self) -> u16 {
+        self.bytes_per_sector
+    }
+
+    /// Returns the number of sectors per cluster.
+    #[must_use]
+    pub fn sectors_per_cluster(&self) -> u8 {
+        self.sectors_per_cluster
+    }
+
+    /// Returns the number of FAT copies.
+    #[must_use]
+    pub fn fats(&self) -> u8 {
+        self.fats
+    }
+
+    /// Returns the maximal number of entries in the FAT12/FAT16 root directory. It is zero on FAT32 volumes.
+    #[must_use]
+    pub fn root_entries(&self) -> u16 {
+        self.root_entries
+    }
+
+    /// Returns the media descriptor byte, e.g. 0xF8 for fixed disks.
+    #[must_use]
+    pub fn media(&self) -> u8 {
+        self.media
+    }
+
+    /// Returns the number of sectors per track used by the BIOS disk geometry.
+    #[must_use]
+    pub fn sectors_per_track(&self) -> u16 {
+        self.sectors_per_track
+    }
+
+    /// Returns the number of heads used by the BIOS disk geometry.
+    #[must_use]
+    pub fn heads(&self) -> u16 {
+        self.heads
+    }
+
+    /// Returns the number of sectors preceding the volume on the disk, e.g. the start of the partition.
+    #[must_use]
+    pub fn hidden_sectors(&self) -> u32 {
+        self.hidden_sectors
+    }
+
+    /// Returns the raw FAT32 extended flags. It is zero on FAT12 and FAT16 volumes.
+    #[must_use]
+    pub fn extended_flags(&self) -> u16 {
+        self.extended_flags
+    }
+
+    /// Returns the FAT32 version. It is zero on FAT12 and FAT16 volumes.
+    #[must_use]
+    pub fn fs_version(&self) -> u16 {
+        self.fs_version
+    }
+
+    /// Returns the first cluster of the FAT32 root directory. It is zero on FAT12 and FAT16 volumes.
+    #[must_use]
+    pub fn root_dir_first_cluster(&self) -> u32 {
+        self.root_dir_first_cluster
+    }
+
+    /// Returns the BIOS drive number, e.g. 0x80 for the first hard disk.
+    #[must_use]
+    pub fn drive_num(&self) -> u8 {
+        self.drive_num
+    }
+
+    /// Returns the extended boot signature. Volume ID, volume label and file system type label are valid if it is
+    /// 0x29.
+    #[must_use]
+    pub fn ext_sig(&self) -> u8 {
+        self.ext_sig
+    }
+
+    /// Returns the volume serial number.
+    #[must_use]
+    pub fn volume_id(&self) -> u32 {
+        self.volume_id
+    }
+
+    /// Returns the volume label padded with spaces. It is encoded in the OEM codepage.
+    #[must_use]
+    pub fn volume_label(&self) -> &[u8; 11] {
+        &self.volume_label
+    }
+
+    /// Returns the informational file system type label padded with spaces, e.g. `b"FAT32   "`.
+    ///
+    /// The FAT type is determined from the number of clusters and not from this label.
+    #[must_use]
+    pub fn fs_type_label(&self) -> &[u8; 8] {
+        &self.fs_type_label
+    }
+}
+
 pub(crate) struct BootSector {
     bootjmp: [u8; 3],
     oem_name: [u8; 8],
     pub(crate) bpb: BiosParameterBlock,
-    boot_code: [u8; 448],
+    boot_code: [u8; BOOT_CODE_SIZE],
     boot_sig: [u8; 2],
 }
 
@@ -458,18 +697,21 @@ impl BootSector {
         Ok(())
     }
 
-    pub(crate) fn validate<E: IoError>(&self, strict: bool) -> Result<(), Error<E>> {
-        if strict && self.boot_sig != [0x55, 0xAA] {
+    pub(crate) fn validate<E: IoError>(&self, level: ValidationLevel) -> Result<(), Error<E>> {
+        if level != ValidationLevel::Lenient && self.boot_sig != [0x55, 0xAA] {
             error!(
                 "Invalid boot sector signature: expected [0x55, 0xAA] but got {:?}",
                 self.boot_sig
             );
             return Err(Error::CorruptedFileSystem);
         }
-        if strict && self.bootjmp[0] != 0xEB && self.bootjmp[0] != 0xE9 {
+        if level != ValidationLevel::Lenient && self.bootjmp[0] != 0xEB && self.bootjmp[0] != 0xE9 {
             warn!("Unknown opcode {:x} in bootjmp boot sector field", self.bootjmp[0]);
+            if level == ValidationLevel::Strict {
+                return Err(Error::CorruptedFileSystem);
+            }
         }
-        self.bpb.validate()?;
+        self.bpb.validate(level)?;
         Ok(())
     }
 }
@@ -480,7 +722,7 @@ impl Default for BootSector {
             bootjmp: Default::default(),
             oem_name: Default::default(),
             bpb: BiosParameterBlock::default(),
-            boot_code: [0; 448],
+            boot_code: [0; BOOT_CODE_SIZE],
             boot_sig: Default::default(),
         }
     }
@@ -608,11 +850,20 @@ fn try_fs_layout(
     fat_type: FatType,
     root_dir_sectors: u32,
     fats: u8,
+    custom_reserved_sectors: Option<u16>,
 ) -> Result<(u16, u32), Error<()>> {
     // Note: most of implementations use 32 reserved sectors for FAT32 but it's wasting of space
     // This implementation uses only 8. This is enough to fit in two boot sectors (main and backup) with additional
     // bootstrap code and one FSInfo sector. It also makes FAT alligned to 4096 which is a nice number.
-    let reserved_sectors: u16 = if fat_type == FatType::Fat32 { 8 } else { 1 };
+    let default_reserved_sectors: u16 = if fat_type == FatType::Fat32 { 8 } else { 1 };
+    let reserved_sectors = match custom_reserved_sectors {
+        Some(n) if n < default_reserved_sectors => {
+            error!("At least {} reserved sectors are needed", default_reserved_sectors);
+            return Err(Error::InvalidInput);
+        }
+        Some(n) => n,
+        None => default_reserved_sectors,
+    };
 
     // Check if volume has enough space to accomodate reserved sectors, FAT, root directory and some data space
     // Having less than 8 sectors for FAT and data would make a little sense
@@ -698,6 +949,7 @@ fn determine_fs_layout<E: IoError>(options: &FormatVolumeOptions, total_sectors:
             fat_type,
             root_dir_sectors,
             options.fats,
+            options.reserved_sectors,
         );
         if let Ok((reserved_sectors, sectors_per_fat)) = result {
             return Ok(FsLayout {
@@ -767,7 +1019,7 @@ fn format_bpb<E: IoError>(
         sectors_per_fat_16,
         sectors_per_track: options.sectors_per_track,
         heads: options.heads,
-        hidden_sectors: 0,
+        hidden_sectors: options.hidden_sectors,
         total_sectors_32,
         // FAT32 fields start
         sectors_per_fat_32,
@@ -830,6 +1082,13 @@ pub(crate) fn format_boot_sector<E: IoError>(
         boot.boot_code[4] = (message_offset_in_sector >> 8) as u8;
     }
 
+    // replace the default boot code by a user-provided one
+    if let Some(ref boot_code) = options.boot_code {
+        boot_code.validate(fat_type)?;
+        boot.bootjmp = boot_code.bootjmp;
+        boot.boot_code = boot_code.code;
+    }
+
     Ok((boot, fat_type))
 }
 
@@ -900,8 +1159,7 @@ mod tests {
         root_dir_entries: u32,
     ) {
         let total_sectors = total_bytes / u64::from(bytes_per_sector);
-        debug_assert!(total_sectors <= u64::from(u32::MAX), "{:x}", total_sectors);
-        let total_sectors = total_sectors as u32;
+        let total_sectors = u32::try_from(total_sectors).expect("total sectors out of range");
 
         let sectors_per_cluster = (bytes_per_cluster / u32::from(bytes_per_sector)) as u8;
         let root_dir_size = root_dir_entries * DIR_ENTRY_SIZE;
@@ -1008,7 +1266,7 @@ mod tests {
         for total_sectors in total_sectors_vec {
             let (boot, _) = format_boot_sector::<()>(&FormatVolumeOptions::new(), total_sectors)
                 .unwrap_or_else(|_| panic!("format_boot_sector total_sectors: {}", total_sectors));
-            boot.validate::<()>(true).expect("validate");
+            boot.validate::<()>(ValidationLevel::Strict).expect("validate");
         }
     }
 
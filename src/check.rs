@@ -0,0 +1,979 @@
+//! Filesystem consistency checker.
+//!
+//! This module implements `FileSystem::check` which walks the whole directory tree and validates it against
+//! the File Allocation Table, similarly to `fsck.vfat` or `chkdsk`, and `FileSystem::repair` which fixes found
+//! problems.
+
+#[cfg(not(feature = "std"))]
+use alloc::string::String;
+#[cfg(not(feature = "std"))]
+use alloc::vec::Vec;
+#[cfg(not(feature = "std"))]
+use alloc::{format, vec};
+use core::fmt;
+
+use crate::dir::{lfn_checksum, Dir, MAX_LONG_DIR_ENTRIES};
+use crate::dir_entry::{DirEntry, DirEntryData, DirEntryEditor, DIR_ENTRY_SIZE, LFN_ENTRY_LAST_FLAG};
+use crate::error::Error;
+use crate::fs::{FileSystem, OemCpConverter, ReadWriteSeek};
+use crate::table::{read_fat, write_fat, FatValue, RESERVED_FAT_ENTRIES};
+use crate::time::TimeProvider;
+
+/// A problem found by the filesystem consistency checker.
+///
+/// Paths are absolute, '/' separated and use long file names if available.
+#[derive(Clone, Debug, Eq, PartialEq)]
+#[non_exhaustive]
+pub enum CheckProblem {
+    /// A FAT entry contains a value that is neither a valid cluster number nor a special value.
+    InvalidFatEntry {
+        /// Cluster owning the FAT entry.
+        cluster: u32,
+        /// Invalid value.
+        value: u32,
+    },
+    /// A directory entry points to a first cluster outside of the data area or to a free or bad cluster, or
+    /// a directory has no cluster.
+    InvalidFirstCluster {
+        /// Path of the entry.
+        path: String,
+        /// First cluster stored in the entry (0 if missing).
+        cluster: u32,
+    },
+    /// A cluster chain ends with a free, bad or invalid FAT entry instead of the end-of-chain marker.
+    BrokenChain {
+        /// Path of the entry owning the chain.
+        path: String,
+        /// Last valid cluster of the chain.
+        cluster: u32,
+    },
+    /// A cluster chain contains a loop.
+    ChainLoop {
+        /// Path of the entry owning the chain.
+        path: String,
+        /// Last cluster before the chain loops back.
+        cluster: u32,
+    },
+    /// A cluster belongs to chains of two different entries.
+    CrossLinked {
+        /// Path of the entry which reached the cluster as second.
+        path: String,
+        /// Path of the entry which owns the cluster.
+        other_path: String,
+        /// Shared cluster.
+        cluster: u32,
+    },
+    /// Size of a file does not match the length of its cluster chain.
+    SizeMismatch {
+        /// Path of the file.
+        path: String,
+        /// Size stored in the directory entry.
+        size: u64,
+        /// Number of clusters in the chain.
+        clusters: u32,
+    },
+    /// A cluster chain is allocated in the FAT but no directory entry points to it.
+    LostChain {
+        /// First cluster of the chain.
+        first_cluster: u32,
+        /// Number of clusters in the chain.
+        clusters: u32,
+    },
+    /// A sequence of long file name entries is malformed (wrong order, checksum mismatch or no short entry).
+    MalformedLfn {
+        /// Path of the directory containing the entries.
+        path: String,
+        /// Offset of the first entry of the sequence in the directory.
+        offset: u64,
+        /// Number of entries in the sequence.
+        entries: u32,
+    },
+    /// The free cluster count stored in the FS Information Sector is wrong.
+    FreeCountMismatch {
+        /// Stored value.
+        stored: u32,
+        /// Value computed from the FAT.
+        actual: u32,
+    },
+}
+
+impl fmt::Display for CheckProblem {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            CheckProblem::InvalidFatEntry { cluster, value } => {
+                write!(f, "invalid FAT entry for cluster {}: {:#x}", cluster, value)
+            }
+            CheckProblem::InvalidFirstCluster { path, cluster } => {
+                write!(f, "{}: invalid first cluster {}", path, cluster)
+            }
+            CheckProblem::BrokenChain { path, cluster } => {
+                write!(f, "{}: cluster chain broken after {}", path, cluster)
+            }
+            CheckProblem::ChainLoop { path, cluster } => write!(f, "{}: cluster chain loops after {}", path, cluster),
+            CheckProblem::CrossLinked {
+                path,
+                other_path,
+                cluster,
+            } => write!(f, "{}: cross-linked with {} on cluster {}", path, other_path, cluster),
+            CheckProblem::SizeMismatch { path, size, clusters } => {
+                write!(
+                    f,
+                    "{}: size {} does not match chain of {} clusters",
+                    path, size, clusters
+                )
+            }
+            CheckProblem::LostChain {
+                first_cluster,
+                clusters,
+            } => write!(f, "lost chain of {} clusters at {}", clusters, first_cluster),
+            CheckProblem::MalformedLfn { path, offset, entries } => {
+                write!(f, "{}: {} malformed LFN entries at offset {}", path, entries, offset)
+            }
+            CheckProblem::FreeCountMismatch { stored, actual } => {
+                write!(f, "free cluster count is {} but FSInfo says {}", actual, stored)
+            }
+        }
+    }
+}
+
+/// A report created by `FileSystem::check`.
+#[derive(Clone, Debug, Default)]
+pub struct CheckReport {
+    problems: Vec<CheckProblem>,
+    files: u32,
+    dirs: u32,
+    used_clusters: u32,
+    free_clusters: u32,
+    bad_clusters: u32,
+}
+
+impl CheckReport {
+    /// Returns problems found by the checker.
+    #[must_use]
+    pub fn problems(&self) -> &[CheckProblem] {
+        &self.problems
+    }
+
+    /// Checks if no problems have been found.
+    #[must_use]
+    pub fn is_clean(&self) -> bool {
+        self.problems.is_empty()
+    }
+
+    /// Returns number of checked files.
+    #[must_use]
+    pub fn files(&self) -> u32 {
+        self.files
+    }
+
+    /// Returns number of checked directories (including the root directory).
+    #[must_use]
+    pub fn dirs(&self) -> u32 {
+        self.dirs
+    }
+
+    /// Returns number of clusters which are reachable from the directory tree.
+    #[must_use]
+    pub fn used_clusters(&self) -> u32 {
+        self.used_clusters
+    }
+
+    /// Returns number of free clusters according to the FAT.
+    #[must_use]
+    pub fn free_clusters(&self) -> u32 {
+        self.free_clusters
+    }
+
+    /// Returns number of clusters marked as bad in the FAT.
+    #[must_use]
+    pub fn bad_clusters(&self) -> u32 {
+        self.bad_clusters
+    }
+}
+
+/// An action performed (or planned in dry-run mode) by `FileSystem::repair`.
+#[derive(Clone, Debug, Eq, PartialEq)]
+#[non_exhaustive]
+pub enum RepairAction {
+    /// A cluster chain has been truncated. File size is reduced if it exceeds the new chain length.
+    TruncateChain {
+        /// Path of the entry owning the chain.
+        path: String,
+        /// Number of clusters left in the chain.
+        clusters: u32,
+    },
+    /// File size has been changed to match the length of the cluster chain.
+    SetSize {
+        /// Path of the file.
+        path: String,
+        /// Size stored in the directory entry before the repair.
+        old_size: u64,
+        /// New size.
+        new_size: u64,
+    },
+    /// A directory entry which cannot be repaired has been removed. Its clusters are not freed.
+    RemoveEntry {
+        /// Path of the removed entry.
+        path: String,
+    },
+    /// A lost cluster chain has been freed.
+    FreeLostChain {
+        /// First cluster of the chain.
+        first_cluster: u32,
+        /// Number of clusters in the chain.
+        clusters: u32,
+    },
+    /// A lost cluster chain has been saved as a file in the `FOUND.NNN` directory.
+    RecoverLostChain {
+        /// First cluster of the chain.
+        first_cluster: u32,
+        /// Number of clusters in the chain.
+        clusters: u32,
+        /// Path of the created file.
+        path: String,
+    },
+    /// Malformed long file name entries have been removed.
+    RemoveLfnEntries {
+        /// Path of the directory containing the entries.
+        path: String,
+        /// Offset of the first entry in the directory.
+        offset: u64,
+        /// Number of removed entries.
+        entries: u32,
+    },
+    /// The free cluster count in the FS Information Sector has been recomputed.
+    UpdateFreeCount {
+        /// Value stored before the repair.
+        old: u32,
+    },
+}
+
+impl fmt::Display for RepairAction {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            RepairAction::TruncateChain { path, clusters } => {
+                write!(f, "{}: truncate cluster chain to {} clusters", path, clusters)
+            }
+            RepairAction::SetSize {
+                path,
+                old_size,
+                new_size,
+            } => write!(f, "{}: change size from {} to {}", path, old_size, new_size),
+            RepairAction::RemoveEntry { path } => write!(f, "{}: remove entry", path),
+            RepairAction::FreeLostChain {
+                first_cluster,
+                clusters,
+            } => write!(f, "free lost chain of {} clusters at {}", clusters, first_cluster),
+            RepairAction::RecoverLostChain {
+                first_cluster,
+                clusters,
+                path,
+            } => write!(
+                f,
+                "recover lost chain of {} clusters at {} into {}",
+                clusters, first_cluster, path
+            ),
+            RepairAction::RemoveLfnEntries { path, offset, entries } => {
+                write!(f, "{}: remove {} LFN entries at offset {}", path, entries, offset)
+            }
+            RepairAction::UpdateFreeCount { old } => write!(f, "update free cluster count (was {})", old),
+        }
+    }
+}
+
+/// Options for `FileSystem::repair`.
+#[derive(Copy, Clone, Debug)]
+pub struct RepairOptions {
+    pub(crate) dry_run: bool,
+    pub(crate) free_lost_chains: bool,
+    pub(crate) recover_lost_chains: bool,
+}
+
+impl RepairOptions {
+    /// Creates repair options with default values.
+    #[must_use]
+    pub fn new() -> Self {
+        Self {
+            dry_run: false,
+            free_lost_chains: true,
+            recover_lost_chains: false,
+        }
+    }
+
+    /// If enabled the filesystem is not modified and only the list of planned actions is returned.
+    ///
+    /// Default is `false`.
+    #[must_use]
+    pub fn dry_run(mut self, enabled: bool) -> Self {
+        self.dry_run = enabled;
+        self
+    }
+
+    /// If enabled lost cluster chains are freed. Otherwise they are left untouched.
+    ///
+    /// Ignored if `recover_lost_chains` is enabled. Default is `true`.
+    #[must_use]
+    pub fn free_lost_chains(mut self, enabled: bool) -> Self {
+        self.free_lost_chains = enabled;
+        self
+    }
+
+    /// If enabled lost cluster chains are saved as files instead of being freed.
+    ///
+    /// Following `chkdsk` conventions every chain becomes a `FILEnnnn.CHK` file in a newly created `FOUND.nnn`
+    /// directory in the root directory, where `FOUND.nnn` is the first name not used yet. File sizes are set to
+    /// the length of the chains. Default is `false`.
+    #[must_use]
+    pub fn recover_lost_chains(mut self, enabled: bool) -> Self {
+        self.recover_lost_chains = enabled;
+        self
+    }
+}
+
+impl Default for RepairOptions {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+/// A report created by `FileSystem::repair`.
+#[derive(Clone, Debug)]
+pub struct RepairReport {
+    check: CheckReport,
+    actions: Vec<RepairAction>,
+    dry_run: bool,
+}
+
+impl RepairReport {
+    /// Returns a report of the consistency check done before the repair.
+    #[must_use]
+    pub fn check_report(&self) -> &CheckReport {
+        &self.check
+    }
+
+    /// Returns actions performed by the repair (or planned in dry-run mode).
+    #[must_use]
+    pub fn actions(&self) -> &[RepairAction] {
+        &self.actions
+    }
+
+    /// Checks if this is a result of a dry run, i.e. actions were not performed.
+    #[must_use]
+    pub fn is_dry_run(&self) -> bool {
+        self.dry_run
+    }
+}
+
+// Marks a cluster visited while grouping lost clusters into chains
+const VISITED: u32 = u32::MAX;
+
+// Limits of FOUND.NNN directories and FILENNNN.CHK files
+const MAX_FOUND_DIRS: u32 = 1000;
+const MAX_RECOVERED_FILES: u32 = 10000;
+
+// Low level operation used to fix a problem
+enum FixOp<'a, IO: ReadWriteSeek, TP, OCC> {
+    // Mark cluster as the end of its chain
+    EndChain(u32),
+    // Mark clusters as free
+    FreeClusters(Vec<u32>),
+    // Write modified directory entry
+    WriteEntry(DirEntryEditor),
+    // Mark raw directory entries as deleted
+    DeleteEntries(Dir<'a, IO, TP, OCC>, u64, u32),
+    // Recompute the FS Information Sector
+    RecomputeFsInfo,
+    // Create a file pointing to an existing cluster chain in a root subdirectory (created if needed)
+    CreateFile {
+        dir: String,
+        name: String,
+        first_cluster: u32,
+        size: u64,
+    },
+}
+
+struct Fix<'a, IO: ReadWriteSeek, TP, OCC> {
+    action: RepairAction,
+    ops: Vec<FixOp<'a, IO, TP, OCC>>,
+}
+
+// Result of cluster chain validation
+struct ChainInfo {
+    // Number of clusters in the valid part of the chain
+    clusters: u32,
+    // Last cluster of the valid part of the chain
+    last_cluster: Option<u32>,
+    valid: bool,
+}
+
+struct Checker<'a, IO: ReadWriteSeek, TP, OCC> {
+    fs: &'a FileSystem<IO, TP, OCC>,
+    // FAT contents indexed by cluster number
+    fat: Vec<FatValue>,
+    // Index of the path owning a cluster plus one (0 if cluster is not used by any entry)
+    owners: Vec<u32>,
+    paths: Vec<String>,
+    report: CheckReport,
+    options: RepairOptions,
+    fixes: Vec<Fix<'a, IO, TP, OCC>>,
+    // Name of the directory for recovered chains and the number of files planned in it
+    found_dir: Option<String>,
+    recovered_files: u32,
+}
+
+impl<'a, IO: ReadWriteSeek, TP: TimeProvider, OCC: OemCpConverter> Checker<'a, IO, TP, OCC> {
+    fn new(fs: &'a FileSystem<IO, TP, OCC>, options: RepairOptions) -> Result<Self, Error<IO::Error>> {
+        let end_cluster = fs.total_clusters() + RESERVED_FAT_ENTRIES;
+        let mut fat = Vec::with_capacity(end_cluster as usize);
+        {
+            let mut fat_slice = fs.fat_slice();
+            for cluster in 0..end_cluster {
+                fat.push(read_fat(&mut fat_slice, fs.fat_type(), cluster)?);
+            }
+        }
+        Ok(Self {
+            fs,
+            fat,
+            owners: vec![0; end_cluster as usize],
+            paths: Vec::new(),
+            report: CheckReport::default(),
+            options,
+            fixes: Vec::new(),
+            found_dir: None,
+            recovered_files: 0,
+        })
+    }
+
+    fn run(&mut self) -> Result<(), Error<IO::Error>> {
+        self.check_fat_entries();
+        let root_dir = self.fs.root_dir();
+        let root_valid = if let Some(n) = root_dir.metadata()?.first_cluster() {
+            let chain = self.check_chain("/", n);
+            if let (false, Some(last_cluster)) = (chain.valid, chain.last_cluster) {
+                self.fix(
+                    RepairAction::TruncateChain {
+                        path: "/".into(),
+                        clusters: chain.clusters,
+                    },
+                    vec![FixOp::EndChain(last_cluster)],
+                );
+            }
+            chain.valid
+        } else {
+            true
+        };
+        if root_valid {
+            self.check_dir(&root_dir, "")?;
+        }
+        self.find_lost_chains()
+    }
+
+    fn fix(&mut self, action: RepairAction, ops: Vec<FixOp<'a, IO, TP, OCC>>) {
+        self.fixes.push(Fix { action, ops });
+    }
+
+    fn is_valid_cluster(&self, cluster: u32) -> bool {
+        cluster >= RESERVED_FAT_ENTRIES && (cluster as usize) < self.fat.len()
+    }
+
+    // Checks if cluster can be a part of a cluster chain
+    fn is_chain_cluster(&self, cluster: u32) -> bool {
+        self.is_valid_cluster(cluster) && !matches!(self.fat[cluster as usize], FatValue::Free | FatValue::Bad)
+    }
+
+    fn check_fat_entries(&mut self) {
+        for cluster in RESERVED_FAT_ENTRIES..self.fat.len() as u32 {
+            match self.fat[cluster as usize] {
+                FatValue::Free => self.report.free_clusters += 1,
+                FatValue::Bad => self.report.bad_clusters += 1,
+                FatValue::Data(n) if !self.is_valid_cluster(n) => {
+                    self.report
+                        .problems
+                        .push(CheckProblem::InvalidFatEntry { cluster, value: n });
+                }
+                _ => {}
+            }
+        }
+        if let Some(stored) = self.fs.stored_free_cluster_count() {
+            if stored != self.report.free_clusters {
+                self.report.problems.push(CheckProblem::FreeCountMismatch {
+                    stored,
+                    actual: self.report.free_clusters,
+                });
+                self.fix(
+                    RepairAction::UpdateFreeCount { old: stored },
+                    vec![FixOp::RecomputeFsInfo],
+                );
+            }
+        }
+    }
+
+    // Marks clusters of a chain as owned by the given path
+    fn check_chain(&mut self, path: &str, first_cluster: u32) -> ChainInfo {
+        let mut info = ChainInfo {
+            clusters: 0,
+            last_cluster: None,
+            valid: false,
+        };
+        if !self.is_chain_cluster(first_cluster) {
+            self.report.problems.push(CheckProblem::InvalidFirstCluster {
+                path: path.into(),
+                cluster: first_cluster,
+            });
+            return info;
+        }
+        self.paths.push(path.into());
+        let owner = self.paths.len() as u32;
+        let mut cluster = first_cluster;
+        loop {
+            let cluster_owner = self.owners[cluster as usize];
+            if cluster_owner != 0 {
+                let problem = match info.last_cluster {
+                    Some(last_cluster) if cluster_owner == owner => CheckProblem::ChainLoop {
+                        path: path.into(),
+                        cluster: last_cluster,
+                    },
+                    _ => CheckProblem::CrossLinked {
+                        path: path.into(),
+                        other_path: self.paths[cluster_owner as usize - 1].clone(),
+                        cluster,
+                    },
+                };
+                self.report.problems.push(problem);
+                return info;
+            }
+            self.owners[cluster as usize] = owner;
+            info.clusters += 1;
+            info.last_cluster = Some(cluster);
+            self.report.used_clusters += 1;
+            match self.fat[cluster as usize] {
+                FatValue::EndOfChain => {
+                    info.valid = true;
+                    return info;
+                }
+                FatValue::Data(n) if self.is_chain_cluster(n) => cluster = n,
+                _ => {
+                    self.report.problems.push(CheckProblem::BrokenChain {
+                        path: path.into(),
+                        cluster,
+                    });
+                    return info;
+                }
+            }
+        }
+    }
+
+    fn check_dir(&mut self, dir: &Dir<'a, IO, TP, OCC>, path: &str) -> Result<(), Error<IO::Error>> {
+        trace!("Checker::check_dir {}", path);
+        self.report.dirs += 1;
+        self.check_lfn_entries(dir, if path.is_empty() { "/" } else { path })?;
+        for r in dir.iter() {
+            let e = r?;
+            let name = e.short_file_name_as_bytes();
+            if name == b"." || name == b".." {
+                continue;
+            }
+            let entry_path = format!("{}/{}", path, e.file_name());
+            if e.is_dir() {
+                self.check_dir_entry(dir, &e, entry_path)?;
+            } else {
+                self.check_file_entry(&e, entry_path);
+            }
+        }
+        Ok(())
+    }
+
+    fn check_dir_entry(
+        &mut self,
+        parent: &Dir<'a, IO, TP, OCC>,
+        e: &DirEntry<'a, IO, TP, OCC>,
+        path: String,
+    ) -> Result<(), Error<IO::Error>> {
+        let chain = if let Some(n) = e.first_cluster() {
+            self.check_chain(&path, n)
+        } else {
+            self.report.problems.push(CheckProblem::InvalidFirstCluster {
+                path: path.clone(),
+                cluster: 0,
+            });
+            ChainInfo {
+                clusters: 0,
+                last_cluster: None,
+                valid: false,
+            }
+        };
+        if chain.valid {
+            return self.check_dir(&e.to_dir(), &path);
+        }
+        // Do not descend into directories with broken chains - they cannot be read reliably
+        if let Some(last_cluster) = chain.last_cluster {
+            self.fix(
+                RepairAction::TruncateChain {
+                    path,
+                    clusters: chain.clusters,
+                },
+                vec![FixOp::EndChain(last_cluster)],
+            );
+        } else {
+            let (begin, end) = e.offset_range;
+            let entries = ((end - begin) / u64::from(DIR_ENTRY_SIZE)) as u32;
+            self.fix(
+                RepairAction::RemoveEntry { path },
+                vec![FixOp::DeleteEntries(parent.clone(), begin, entries)],
+            );
+        }
+        Ok(())
+    }
+
+    fn check_file_entry(&mut self, e: &DirEntry<'a, IO, TP, OCC>, path: String) {
+        self.report.files += 1;
+        let size = e.len();
+        let chain = match e.first_cluster() {
+            Some(n) => self.check_chain(&path, n),
+            None => ChainInfo {
+                clusters: 0,
+                last_cluster: None,
+                valid: true,
+            },
+        };
+        let expected_clusters = self.fs.clusters_from_bytes(size);
+        let mut editor = e.editor();
+        if !chain.valid {
+            let mut ops = Vec::new();
+            match chain.last_cluster {
+                Some(last_cluster) => ops.push(FixOp::EndChain(last_cluster)),
+                None => editor.set_first_cluster(None, self.fs.fat_type()),
+            }
+            let max_size = self.fs.bytes_from_clusters(chain.clusters);
+            editor.set_size(size.min(max_size));
+            ops.push(FixOp::WriteEntry(editor));
+            self.fix(
+                RepairAction::TruncateChain {
+                    path,
+                    clusters: chain.clusters,
+                },
+                ops,
+            );
+        } else if chain.clusters != expected_clusters {
+            self.report.problems.push(CheckProblem::SizeMismatch {
+                path: path.clone(),
+                size,
+                clusters: chain.clusters,
+            });
+            if chain.clusters < expected_clusters {
+                let new_size = self.fs.bytes_from_clusters(chain.clusters);
+                editor.set_size(new_size);
+                self.fix(
+                    RepairAction::SetSize {
+                        path,
+                        old_size: size,
+                        new_size,
+                    },
+                    vec![FixOp::WriteEntry(editor)],
+                );
+            } else {
+                // Chain is longer than needed - free clusters beyond the file size
+                let mut chain_clusters = Vec::new();
+                let mut cluster_opt = e.first_cluster();
+                while let Some(cluster) = cluster_opt {
+                    chain_clusters.push(cluster);
+                    cluster_opt = match self.fat[cluster as usize] {
+                        FatValue::Data(n) => Some(n),
+                        _ => None,
+                    };
+                }
+                let tail = chain_clusters.split_off(expected_clusters as usize);
+                let mut ops = Vec::new();
+                if let Some(last_cluster) = chain_clusters.last() {
+                    ops.push(FixOp::EndChain(*last_cluster));
+                } else {
+                    editor.set_first_cluster(None, self.fs.fat_type());
+                    ops.push(FixOp::WriteEntry(editor));
+                }
+                ops.push(FixOp::FreeClusters(tail));
+                self.fix(
+                    RepairAction::TruncateChain {
+                        path,
+                        clusters: expected_clusters,
+                    },
+                    ops,
+                );
+            }
+        }
+    }
+
+    fn check_lfn_entries(&mut self, dir: &Dir<'a, IO, TP, OCC>, path: &str) -> Result<(), Error<IO::Error>> {
+        // Currently processed LFN sequence: offset of the first entry, number of entries, expected order of
+        // the next entry and checksum
+        let mut seq: Option<(u64, u32, u8, u8)> = None;
+        let mut stream = dir.raw_stream();
+        let mut offset = 0_u64;
+        // Malformed sequences: offset of the first entry and number of entries
+        let mut malformed = Vec::new();
+        let mut report_seq = |seq: Option<(u64, u32, u8, u8)>, extra: u32| {
+            if let Some((seq_offset, entries, _, _)) = seq {
+                malformed.push((seq_offset, entries + extra));
+            }
+        };
+        loop {
+            let raw_entry = DirEntryData::deserialize(&mut stream)?;
+            if raw_entry.is_end() {
+                report_seq(seq, 0);
+                break;
+            }
+            match raw_entry {
+                _ if raw_entry.is_deleted() => report_seq(seq.take(), 0),
+                DirEntryData::Lfn(data) => {
+                    let index = data.order() & 0x1F;
+                    let index_valid = index != 0 && usize::from(index) <= MAX_LONG_DIR_ENTRIES;
+                    if data.order() & LFN_ENTRY_LAST_FLAG != 0 {
+                        report_seq(seq.take(), 0);
+                        if index_valid {
+                            seq = Some((offset, 1, index, data.checksum()));
+                        } else {
+                            report_seq(Some((offset, 1, 0, 0)), 0);
+                        }
+                    } else {
+                        match seq {
+                            Some((seq_offset, entries, expected, chksum))
+                                if index_valid && index + 1 == expected && data.checksum() == chksum =>
+                            {
+                                seq = Some((seq_offset, entries + 1, index, chksum));
+                            }
+                            Some(_) => report_seq(seq.take(), 1),
+                            None => report_seq(Some((offset, 1, 0, 0)), 0),
+                        }
+                    }
+                }
+                DirEntryData::File(data) => {
+                    if let Some((_, _, index, chksum)) = seq {
+                        if index != 1 || chksum != lfn_checksum(data.name()) {
+                            report_seq(seq, 0);
+                        }
+                    }
+                    seq = None;
+                }
+            }
+            offset += u64::from(DIR_ENTRY_SIZE);
+        }
+        for (offset, entries) in malformed {
+            self.report.problems.push(CheckProblem::MalformedLfn {
+                path: path.into(),
+                offset,
+                entries,
+            });
+            self.fix(
+                RepairAction::RemoveLfnEntries {
+                    path: path.into(),
+                    offset,
+                    entries,
+                },
+                vec![FixOp::DeleteEntries(dir.clone(), offset, entries)],
+            );
+        }
+        Ok(())
+    }
+
+    fn find_lost_chains(&mut self) -> Result<(), Error<IO::Error>> {
+        // Find clusters which are allocated but not used and are pointed by other such clusters
+        let is_lost = |owners: &[u32], fat: &[FatValue], cluster: u32| {
+            owners[cluster as usize] == 0 && matches!(fat[cluster as usize], FatValue::Data(_) | FatValue::EndOfChain)
+        };
+        let end_cluster = self.fat.len() as u32;
+        let mut has_prev = vec![false; self.fat.len()];
+        for cluster in RESERVED_FAT_ENTRIES..end_cluster {
+            if let FatValue::Data(n) = self.fat[cluster as usize] {
+                if is_lost(&self.owners, &self.fat, cluster) && self.is_valid_cluster(n) {
+                    has_prev[n as usize] = true;
+                }
+            }
+        }
+        // Chain heads are processed first, remaining lost clusters form loops
+        for heads_only in [true, false] {
+            for first_cluster in RESERVED_FAT_ENTRIES..end_cluster {
+                if !is_lost(&self.owners, &self.fat, first_cluster) || (heads_only && has_prev[first_cluster as usize])
+                {
+                    continue;
+                }
+                let mut cluster = first_cluster;
+                let mut chain_clusters = Vec::new();
+                loop {
+                    self.owners[cluster as usize] = VISITED;
+                    chain_clusters.push(cluster);
+                    match self.fat[cluster as usize] {
+                        FatValue::Data(n) if self.is_valid_cluster(n) && is_lost(&self.owners, &self.fat, n) => {
+                            cluster = n;
+                        }
+                        _ => break,
+                    }
+                }
+                let clusters = chain_clusters.len() as u32;
+                self.report.problems.push(CheckProblem::LostChain {
+                    first_cluster,
+                    clusters,
+                });
+                if self.options.recover_lost_chains && self.recover_lost_chain(&chain_clusters)? {
+                    continue;
+                }
+                if self.options.free_lost_chains {
+                    self.fix(
+                        RepairAction::FreeLostChain {
+                            first_cluster,
+                            clusters,
+                        },
+                        vec![FixOp::FreeClusters(chain_clusters)],
+                    );
+                }
+            }
+        }
+        Ok(())
+    }
+
+    // Plans saving a lost chain as a file in FOUND.NNN directory. Returns false if there is no free name.
+    fn recover_lost_chain(&mut self, chain_clusters: &[u32]) -> Result<bool, Error<IO::Error>> {
+        if self.found_dir.is_none() {
+            self.found_dir = self.find_found_dir_name()?;
+        }
+        let dir = match &self.found_dir {
+            Some(dir) if self.recovered_files < MAX_RECOVERED_FILES => dir.clone(),
+            _ => return Ok(false),
+        };
+        let name = format!("FILE{:04}.CHK", self.recovered_files);
+        self.recovered_files += 1;
+        let first_cluster = chain_clusters[0];
+        let clusters = chain_clusters.len() as u32;
+        // Unwrapping is safe because chain is never empty
+        let last_cluster = *chain_clusters.last().unwrap();
+        let size = self.fs.bytes_from_clusters(clusters).min(self.fs.max_file_size());
+        self.fix(
+            RepairAction::RecoverLostChain {
+                first_cluster,
+                clusters,
+                path: format!("/{}/{}", dir, name),
+            },
+            vec![
+                // Chain could end with an invalid entry or point into another chain
+                FixOp::EndChain(last_cluster),
+                FixOp::CreateFile {
+                    dir,
+                    name,
+                    first_cluster,
+                    size,
+                },
+            ],
+        );
+        Ok(true)
+    }
+
+    fn find_found_dir_name(&self) -> Result<Option<String>, Error<IO::Error>> {
+        let root_dir = self.fs.root_dir();
+        for i in 0..MAX_FOUND_DIRS {
+            let name = format!("FOUND.{:03}", i);
+            match root_dir.find_entry_by_path(&name) {
+                Err(Error::NotFound) => return Ok(Some(name)),
+                r => r?,
+            };
+        }
+        Ok(None)
+    }
+}
+
+impl<IO: ReadWriteSeek, TP: TimeProvider, OCC: OemCpConverter> FileSystem<IO, TP, OCC> {
+    /// Checks consistency of the filesystem.
+    ///
+    /// The whole directory tree is traversed and cluster chains of all files and directories are validated against
+    /// the File Allocation Table. Detected problems include invalid FAT entries, broken, looped and cross-linked
+    /// cluster chains, file sizes not matching chain lengths, lost cluster chains, malformed long file name entries
+    /// and a wrong free cluster count in the FS Information Sector. The filesystem is not modified.
+    ///
+    /// Note: the whole FAT is loaded into memory, so memory usage is proportional to the number of clusters.
+    ///
+    /// # Errors
+    ///
+    /// `Error::Io` will be returned if the underlying storage object returned an I/O error.
+    pub fn check(&self) -> Result<CheckReport, Error<IO::Error>> {
+        trace!("FileSystem::check");
+        let mut checker = Checker::new(self, RepairOptions::new())?;
+        checker.run()?;
+        Ok(checker.report)
+    }
+
+    /// Checks consistency of the filesystem and repairs found problems.
+    ///
+    /// Problems are detected the same way as in `check`. Then cluster chains are truncated at the point where they
+    /// break, loop or cross-link with another chain, file sizes are fixed to match chain lengths, directories which
+    /// cannot be repaired are removed, lost cluster chains are freed or recovered into files (depending on
+    /// `options`), malformed long file name entries are removed and the free cluster count is recomputed. In dry-run
+    /// mode only the list of planned actions is returned.
+    ///
+    /// Directories with broken chains are not traversed, so running the repair again after truncating them can
+    /// find more problems.
+    ///
+    /// # Errors
+    ///
+    /// Errors that can be returned:
+    ///
+    /// * `Error::ReadOnlyFilesystem` will be returned if the filesystem is mounted read-only and this is not
+    ///   a dry run.
+    /// * `Error::NotEnoughSpace` will be returned if there is not enough free space to create a directory or
+    ///   directory entries for recovered cluster chains.
+    /// * `Error::Io` will be returned if the underlying storage object returned an I/O error.
+    pub fn repair(&self, options: RepairOptions) -> Result<RepairReport, Error<IO::Error>> {
+        trace!("FileSystem::repair");
+        if !options.dry_run {
+            self.check_writable()?;
+        }
+        let mut checker = Checker::new(self, options)?;
+        checker.run()?;
+        let check = checker.report;
+        let fixes = checker.fixes;
+        let mut actions = Vec::new();
+        let mut recompute_fs_info = false;
+        for fix in fixes {
+            if !options.dry_run {
+                for op in fix.ops {
+                    match op {
+                        FixOp::EndChain(cluster) => {
+                            let _guard = self.lock_fat();
+                            write_fat(&mut self.fat_slice(), self.fat_type(), cluster, FatValue::EndOfChain)?;
+                            self.on_chains_changed();
+                        }
+                        FixOp::FreeClusters(clusters) => {
+                            let _guard = self.lock_fat();
+                            for cluster in clusters {
+                                write_fat(&mut self.fat_slice(), self.fat_type(), cluster, FatValue::Free)?;
+                            }
+                            self.on_chains_changed();
+                            recompute_fs_info = true;
+                        }
+                        FixOp::WriteEntry(mut editor) => editor.flush(self)?,
+                        FixOp::DeleteEntries(dir, offset, entries) => {
+                            let _guard = self.dir_locks.write(dir.lock_key());
+                            dir.delete_entries(offset, entries)?;
+                        }
+                        FixOp::RecomputeFsInfo => recompute_fs_info = true,
+                        FixOp::CreateFile {
+                            dir,
+                            name,
+                            first_cluster,
+                            size,
+                        } => {
+                            let dir = self.root_dir().create_dir(&dir)?;
+                            dir.create_file_with_chain(&name, first_cluster, size)?;
+                        }
+                    }
+                }
+            }
+            info!("repair: {}", fix.action);
+            actions.push(fix.action);
+        }
+        if recompute_fs_info {
+            self.recompute_fsinfo()?;
+        }
+        Ok(RepairReport {
+            check,
+            actions,
+            dry_run: options.dry_run,
+        })
+    }
+}
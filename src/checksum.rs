@@ -0,0 +1,549 @@
+#[cfg(all(not(feature = "std"), feature = "alloc"))]
+use alloc::vec::Vec;
+use core::fmt;
+
+#[cfg(feature = "std")]
+use crate::error::Error;
+use crate::error::{ErrorCode, IoError};
+use crate::fs::MAX_SECTOR_SIZE;
+use crate::io::{IoBase, Read, Seek, SeekFrom, Write};
+
+const DEFAULT_CHECKSUM_SECTOR_SIZE: u32 = 512;
+const CHECKSUM_SIZE: usize = 4;
+// Maximal number of sectors transferred by a single read or write call
+const MAX_BATCH_SECTORS: usize = 64;
+
+const CRC32_TABLE: [u32; 256] = crc32_table();
+
+const fn crc32_table() -> [u32; 256] {
+    let mut table = [0_u32; 256];
+    let mut i = 0;
+    while i < 256 {
+        let mut crc = i as u32;
+        let mut bit = 0;
+        while bit < 8 {
+            crc = if crc & 1 != 0 {
+                0xEDB8_8320 ^ (crc >> 1)
+            } else {
+                crc >> 1
+            };
+            bit += 1;
+        }
+        table[i] = crc;
+        i += 1;
+    }
+    table
+}
+
+// CRC-32 (IEEE 802.3) of `data`, also used for GPT headers built by `ImageBuilder`
+pub(crate) fn crc32(data: &[u8]) -> u32 {
+    let crc = data.iter().fold(!0_u32, |crc, &b| {
+        CRC32_TABLE[((crc ^ u32::from(b)) & 0xFF) as usize] ^ (crc >> 8)
+    });
+    !crc
+}
+
+fn checksum_matches(data: &[u8], stored: &[u8]) -> bool {
+    crc32(data) == u32::from_le_bytes(stored.try_into().expect("checksum has 4 bytes"))
+}
+
+// Checks `data` of sector `lba` against the `stored` little-endian checksum
+fn verify_checksum<E>(lba: u64, data: &[u8], stored: &[u8]) -> Result<(), ChecksumStorageError<E>> {
+    if checksum_matches(data, stored) {
+        Ok(())
+    } else {
+        error!("checksum mismatch in sector {}", lba);
+        Err(ChecksumStorageError::ChecksumMismatch { lba })
+    }
+}
+
+/// An error returned by `ChecksumStorage`.
+#[derive(Copy, Clone, Eq, PartialEq, Debug)]
+pub enum ChecksumStorageError<E> {
+    /// The data storage or the checksum storage returned an error.
+    Io(E),
+    /// Content of a sector does not match its stored checksum.
+    ChecksumMismatch {
+        /// Index of the corrupted sector (logical block address) relative to the start of the storage.
+        lba: u64,
+    },
+}
+
+impl<E: IoError> IoError for ChecksumStorageError<E> {
+    fn is_interrupted(&self) -> bool {
+        match self {
+            ChecksumStorageError::Io(io_error) => io_error.is_interrupted(),
+            ChecksumStorageError::ChecksumMismatch { .. } => false,
+        }
+    }
+
+    fn new_unexpected_eof_error() -> Self {
+        ChecksumStorageError::Io(E::new_unexpected_eof_error())
+    }
+
+    fn new_write_zero_error() -> Self {
+        ChecksumStorageError::Io(E::new_write_zero_error())
+    }
+
+    fn is_out_of_space(&self) -> bool {
+        match self {
+            ChecksumStorageError::Io(io_error) => io_error.is_out_of_space(),
+            ChecksumStorageError::ChecksumMismatch { .. } => false,
+        }
+    }
+
+    /// Returns the `errno` of the storage error or `EUCLEAN` (like for `Error::CorruptedFileSystem`) if a checksum
+    /// does not match.
+    fn code(&self) -> i32 {
+        match self {
+            ChecksumStorageError::Io(io_error) => io_error.code(),
+            ChecksumStorageError::ChecksumMismatch { .. } => ErrorCode::NeedsCleaning.errno(),
+        }
+    }
+}
+
+impl<E: fmt::Display> fmt::Display for ChecksumStorageError<E> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            ChecksumStorageError::Io(io_error) => write!(f, "IO error: {}", io_error),
+            ChecksumStorageError::ChecksumMismatch { lba } => write!(f, "Checksum mismatch in sector {}", lba),
+        }
+    }
+}
+
+#[cfg(feature = "std")]
+impl<E: fmt::Debug + fmt::Display> std::error::Error for ChecksumStorageError<E> {}
+
+#[cfg(feature = "std")]
+impl<E: Into<std::io::Error> + fmt::Display> From<ChecksumStorageError<E>> for std::io::Error {
+    fn from(error: ChecksumStorageError<E>) -> Self {
+        match error {
+            ChecksumStorageError::Io(io_error) => io_error.into(),
+            ChecksumStorageError::ChecksumMismatch { .. } => {
+                Self::new(std::io::ErrorKind::InvalidData, error.to_string())
+            }
+        }
+    }
+}
+
+// Makes `std::io` traits available for files of filesystems using checksummed storages
+#[cfg(feature = "std")]
+impl<E: Into<std::io::Error> + fmt::Display> From<Error<ChecksumStorageError<E>>> for std::io::Error {
+    fn from(error: Error<ChecksumStorageError<E>>) -> Self {
+        error.map_io(Self::from).into()
+    }
+}
+
+/// A storage wrapper verifying integrity of every sector using checksums kept in a separate sidecar storage.
+///
+/// A CRC-32 checksum of each sector is stored in the sidecar storage as a little-endian 32-bit number at offset
+/// `lba * 4`. Writes update the checksums of the written sectors and reads verify them, so silent bit rot in
+/// long-lived images - e.g. of hypervisor guests - is reported as `ChecksumStorageError::ChecksumMismatch` with the
+/// index of the offending sector instead of being returned as valid data. Partial sector writes read and verify
+/// the sector before modifying it, so they never hide an existing corruption.
+///
+/// The sidecar storage can be a separate file, a region of another disk or a `RamDisk`. Checksums of an image which
+/// has not been protected yet must be created by `ChecksumStorage::rebuild_checksums`. The size of the storage is
+/// the size of the data storage - writes beyond its end are not possible.
+///
+/// ```
+/// use axfatfs::{ChecksumStorage, ChecksumStorageError, Error, FileSystem, FsOptions, MemoryStorage, RamDisk};
+///
+/// let image = include_bytes!("../resources/fat16.img").to_vec();
+/// let mut storage = ChecksumStorage::new(MemoryStorage::new(image), RamDisk::new())?;
+/// storage.rebuild_checksums()?;
+/// let fs = FileSystem::new(storage, FsOptions::new())?;
+/// let content = fs.read("short.txt")?;
+/// # assert!(!content.is_empty());
+/// # Ok::<(), Error<ChecksumStorageError<axfatfs::MemoryStorageError>>>(())
+/// ```
+#[derive(Clone, Debug)]
+pub struct ChecksumStorage<S, C> {
+    storage: S,
+    sidecar: C,
+    len: u64,
+    pos: u64,
+    sector_size: u32,
+    // Buffer for sectors accessed partially
+    sector_buf: [u8; MAX_SECTOR_SIZE],
+    // Buffer for checksums of sectors transferred by a single call
+    checksum_buf: [u8; MAX_BATCH_SECTORS * CHECKSUM_SIZE],
+}
+
+impl<S: Seek, C> ChecksumStorage<S, C> {
+    /// Creates a new `ChecksumStorage` instance protecting `storage` with checksums stored in `sidecar`.
+    ///
+    /// `sidecar` must contain valid checksums of `storage` sectors, otherwise all reads fail until
+    /// `ChecksumStorage::rebuild_checksums` is called.
+    ///
+    /// # Errors
+    ///
+    /// Returns `ChecksumStorageError::Io` if seeking to the end of `storage` to determine its size fails.
+    pub fn new(mut storage: S, sidecar: C) -> Result<Self, ChecksumStorageError<S::Error>> {
+        let len = storage.seek(SeekFrom::End(0)).map_err(ChecksumStorageError::Io)?;
+        Ok(Self {
+            storage,
+            sidecar,
+            len,
+            pos: 0,
+            sector_size: DEFAULT_CHECKSUM_SECTOR_SIZE,
+            sector_buf: [0; MAX_SECTOR_SIZE],
+            checksum_buf: [0; MAX_BATCH_SECTORS * CHECKSUM_SIZE],
+        })
+    }
+}
+
+impl<S, C> ChecksumStorage<S, C> {
+    /// Sets the size of sectors protected by a single checksum.
+    ///
+    /// It does not have to match the sector size of the filesystem, but accesses are most efficient if it does.
+    /// Default is 512 bytes. Checksums created for another sector size are not valid.
+    ///
+    /// # Panics
+    ///
+    /// Panics if `sector_size` is not a power of two or it is bigger than the largest sector size supported by the
+    /// enabled features.
+    #[must_use]
+    pub fn sector_size(mut self, sector_size: u32) -> Self {
+        assert!(
+            sector_size.is_power_of_two() && sector_size as usize <= MAX_SECTOR_SIZE,
+            "Unsupported checksum sector size"
+        );
+        self.sector_size = sector_size;
+        self
+    }
+
+    /// Returns size of the storage in bytes.
+    #[must_use]
+    pub fn len(&self) -> u64 {
+        self.len
+    }
+
+    /// Returns `true` if the storage has zero size.
+    #[must_use]
+    pub fn is_empty(&self) -> bool {
+        self.len == 0
+    }
+
+    /// Returns number of bytes of checksums stored in the sidecar storage.
+    #[must_use]
+    pub fn sidecar_size(&self) -> u64 {
+        self.num_sectors() * CHECKSUM_SIZE as u64
+    }
+
+    /// Returns references to the data and the sidecar storage.
+    pub fn get_ref(&self) -> (&S, &C) {
+        (&self.storage, &self.sidecar)
+    }
+
+    /// Returns the data and the sidecar storage.
+    pub fn into_parts(self) -> (S, C) {
+        (self.storage, self.sidecar)
+    }
+
+    fn num_sectors(&self) -> u64 {
+        let sector_size = u64::from(self.sector_size);
+        (self.len + sector_size - 1) / sector_size
+    }
+
+    // Returns the size of sector `lba`, the last sector can be shorter
+    fn sector_len(&self, lba: u64) -> usize {
+        let sector_size = u64::from(self.sector_size);
+        sector_size.min(self.len - lba * sector_size) as usize
+    }
+
+    // Returns the number of whole sectors starting at the current position which fit in `max_len` bytes (the
+    // shorter last sector is included if `max_len` reaches the end of the storage) and their size in bytes
+    fn whole_sectors(&self, max_len: usize) -> (usize, usize) {
+        let sector_size = u64::from(self.sector_size);
+        let remaining = self.len - self.pos;
+        let max_len = remaining.min(max_len as u64);
+        let count = if max_len == remaining {
+            (max_len + sector_size - 1) / sector_size
+        } else {
+            max_len / sector_size
+        };
+        let count = (count as usize).min(MAX_BATCH_SECTORS);
+        let n = remaining.min(count as u64 * sector_size) as usize;
+        (count, n)
+    }
+}
+
+impl<S: Read + Seek, C> ChecksumStorage<S, C> {
+    // Reads sector `lba` into the sector buffer without verifying it and returns its size
+    fn load_sector(&mut self, lba: u64) -> Result<usize, ChecksumStorageError<S::Error>> {
+        let sector_len = self.sector_len(lba);
+        self.storage
+            .seek(SeekFrom::Start(lba * u64::from(self.sector_size)))
+            .and_then(|_| self.storage.read_exact(&mut self.sector_buf[..sector_len]))
+            .map_err(ChecksumStorageError::Io)?;
+        Ok(sector_len)
+    }
+}
+
+impl<S: Read + Seek, C: Read + Seek> ChecksumStorage<S, C>
+where
+    S::Error: From<C::Error>,
+{
+    /// Checks all sectors of the storage and returns indices of sectors which do not match their checksums.
+    ///
+    /// It can be used for periodic scrubbing of images, because normal reads stop at the first corrupted sector.
+    ///
+    /// # Errors
+    ///
+    /// Returns `ChecksumStorageError::Io` if the data or the sidecar storage returned an error.
+    #[cfg(feature = "alloc")]
+    pub fn scrub(&mut self) -> Result<Vec<u64>, ChecksumStorageError<S::Error>> {
+        let mut corrupted = Vec::new();
+        for lba in 0..self.num_sectors() {
+            let sector_len = self.load_sector(lba)?;
+            self.read_checksums(lba, 1)?;
+            if !checksum_matches(&self.sector_buf[..sector_len], &self.checksum_buf[..CHECKSUM_SIZE]) {
+                corrupted.push(lba);
+            }
+        }
+        Ok(corrupted)
+    }
+
+    fn read_checksums(&mut self, lba: u64, count: usize) -> Result<(), ChecksumStorageError<S::Error>> {
+        let buf = &mut self.checksum_buf[..count * CHECKSUM_SIZE];
+        self.sidecar
+            .seek(SeekFrom::Start(lba * CHECKSUM_SIZE as u64))
+            .and_then(|_| self.sidecar.read_exact(buf))
+            .map_err(|e| ChecksumStorageError::Io(e.into()))
+    }
+
+    // Reads sector `lba` into the sector buffer and verifies it
+    fn read_sector(&mut self, lba: u64) -> Result<usize, ChecksumStorageError<S::Error>> {
+        let sector_len = self.load_sector(lba)?;
+        self.read_checksums(lba, 1)?;
+        verify_checksum(lba, &self.sector_buf[..sector_len], &self.checksum_buf[..CHECKSUM_SIZE])?;
+        Ok(sector_len)
+    }
+}
+
+impl<S: Read + Seek, C: Write + Seek> ChecksumStorage<S, C>
+where
+    S::Error: From<C::Error>,
+{
+    /// Computes checksums of all sectors of the storage and writes them to the sidecar storage.
+    ///
+    /// It must be called before the first use of a sidecar storage without valid checksums. Existing corruption of
+    /// the data storage cannot be detected afterwards.
+    ///
+    /// # Errors
+    ///
+    /// Returns `ChecksumStorageError::Io` if the data or the sidecar storage returned an error.
+    pub fn rebuild_checksums(&mut self) -> Result<(), ChecksumStorageError<S::Error>> {
+        let num_sectors = self.num_sectors();
+        let mut lba = 0;
+        while lba < num_sectors {
+            let count = (num_sectors - lba).min(MAX_BATCH_SECTORS as u64) as usize;
+            for i in 0..count {
+                let sector_len = self.load_sector(lba + i as u64)?;
+                let offset = i * CHECKSUM_SIZE;
+                self.checksum_buf[offset..offset + CHECKSUM_SIZE]
+                    .copy_from_slice(&crc32(&self.sector_buf[..sector_len]).to_le_bytes());
+            }
+            self.write_checksums(lba, count)?;
+            lba += count as u64;
+        }
+        self.sidecar.flush().map_err(|e| ChecksumStorageError::Io(e.into()))
+    }
+
+    fn write_checksums(&mut self, lba: u64, count: usize) -> Result<(), ChecksumStorageError<S::Error>> {
+        let buf = &self.checksum_buf[..count * CHECKSUM_SIZE];
+        self.sidecar
+            .seek(SeekFrom::Start(lba * CHECKSUM_SIZE as u64))
+            .and_then(|_| self.sidecar.write_all(buf))
+            .map_err(|e| ChecksumStorageError::Io(e.into()))
+    }
+}
+
+impl<S: IoBase, C> IoBase for ChecksumStorage<S, C> {
+    type Error = ChecksumStorageError<S::Error>;
+}
+
+impl<S: Read + Seek, C: Read + Seek> Read for ChecksumStorage<S, C>
+where
+    S::Error: From<C::Error>,
+{
+    fn read(&mut self, buf: &mut [u8]) -> Result<usize, Self::Error> {
+        if self.pos >= self.len || buf.is_empty() {
+            return Ok(0);
+        }
+        let sector_size = u64::from(self.sector_size);
+        let lba = self.pos / sector_size;
+        let offset = (self.pos % sector_size) as usize;
+        let n = if offset == 0 && buf.len() >= self.sector_len(lba) {
+            // read whole sectors directly to the buffer
+            let (count, n) = self.whole_sectors(buf.len());
+            self.storage
+                .seek(SeekFrom::Start(self.pos))
+                .and_then(|_| self.storage.read_exact(&mut buf[..n]))
+                .map_err(ChecksumStorageError::Io)?;
+            self.read_checksums(lba, count)?;
+            for (i, data) in buf[..n].chunks(sector_size as usize).enumerate() {
+                let offset = i * CHECKSUM_SIZE;
+                verify_checksum(lba + i as u64, data, &self.checksum_buf[offset..offset + CHECKSUM_SIZE])?;
+            }
+            n
+        } else {
+            let sector_len = self.read_sector(lba)?;
+            let n = buf.len().min(sector_len - offset);
+            buf[..n].copy_from_slice(&self.sector_buf[offset..offset + n]);
+            n
+        };
+        self.pos += n as u64;
+        Ok(n)
+    }
+}
+
+impl<S: Read + Write + Seek, C: Read + Write + Seek> Write for ChecksumStorage<S, C>
+where
+    S::Error: From<C::Error>,
+{
+    fn write(&mut self, buf: &[u8]) -> Result<usize, Self::Error> {
+        if self.pos >= self.len || buf.is_empty() {
+            return Ok(0);
+        }
+        let sector_size = u64::from(self.sector_size);
+        let lba = self.pos / sector_size;
+        let offset = (self.pos % sector_size) as usize;
+        let (count, n) = if offset == 0 && buf.len() >= self.sector_len(lba) {
+            // write whole sectors directly from the buffer
+            let (count, n) = self.whole_sectors(buf.len());
+            self.storage
+                .seek(SeekFrom::Start(self.pos))
+                .and_then(|_| self.storage.write_all(&buf[..n]))
+                .map_err(ChecksumStorageError::Io)?;
+            for (i, data) in buf[..n].chunks(sector_size as usize).enumerate() {
+                let offset = i * CHECKSUM_SIZE;
+                self.checksum_buf[offset..offset + CHECKSUM_SIZE].copy_from_slice(&crc32(data).to_le_bytes());
+            }
+            (count, n)
+        } else {
+            // read-modify-write of a partial sector
+            let sector_len = self.read_sector(lba)?;
+            let n = buf.len().min(sector_len - offset);
+            self.sector_buf[offset..offset + n].copy_from_slice(&buf[..n]);
+            self.storage
+                .seek(SeekFrom::Start(lba * sector_size))
+                .and_then(|_| self.storage.write_all(&self.sector_buf[..sector_len]))
+                .map_err(ChecksumStorageError::Io)?;
+            self.checksum_buf[..CHECKSUM_SIZE].copy_from_slice(&crc32(&self.sector_buf[..sector_len]).to_le_bytes());
+            (1, n)
+        };
+        self.write_checksums(lba, count)?;
+        self.pos += n as u64;
+        Ok(n)
+    }
+
+    fn flush(&mut self) -> Result<(), Self::Error> {
+        self.storage.flush().map_err(ChecksumStorageError::Io)?;
+        self.sidecar.flush().map_err(|e| ChecksumStorageError::Io(e.into()))
+    }
+}
+
+impl<S: Seek, C> Seek for ChecksumStorage<S, C> {
+    fn seek(&mut self, pos: SeekFrom) -> Result<u64, Self::Error> {
+        // the data storage validates the position
+        if let SeekFrom::Current(_) = pos {
+            self.storage
+                .seek(SeekFrom::Start(self.pos))
+                .map_err(ChecksumStorageError::Io)?;
+        }
+        self.pos = self.storage.seek(pos).map_err(ChecksumStorageError::Io)?;
+        Ok(self.pos)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{FileSystem, FsOptions, MemoryStorage, MemoryStorageError, RamDisk};
+
+    #[test]
+    fn test_crc32() {
+        assert_eq!(crc32(b""), 0);
+        assert_eq!(crc32(b"123456789"), 0xCBF4_3926);
+    }
+
+    #[test]
+    fn test_checksum_storage_detects_bit_rot() {
+        let image = std::fs::read("resources/fat16.img").unwrap();
+        let mut storage = ChecksumStorage::new(MemoryStorage::new(image), RamDisk::new()).unwrap();
+        storage.rebuild_checksums().unwrap();
+        let fs = FileSystem::new(storage, FsOptions::new()).unwrap();
+        fs.write("guest.txt", b"Hello guest!").unwrap();
+        let storage = fs.into_inner().unwrap();
+        assert_eq!(storage.sidecar_size(), storage.len() / 512 * 4);
+        let (data, sidecar) = storage.into_parts();
+
+        // flip a bit of the file content
+        let mut image = data.into_inner();
+        let offset = image.windows(12).position(|w| w == b"Hello guest!").unwrap();
+        image[offset + 3] ^= 0x10;
+        let lba = offset as u64 / 512;
+        let mut storage = ChecksumStorage::new(MemoryStorage::new(image), sidecar).unwrap();
+        assert_eq!(storage.scrub().unwrap(), [lba]);
+
+        let fs = FileSystem::new(storage, FsOptions::new()).unwrap();
+        let error = fs.read("guest.txt").unwrap_err();
+        assert!(matches!(
+            error,
+            Error::Io(ChecksumStorageError::ChecksumMismatch { lba: l }) if l == lba
+        ));
+        assert_eq!(error.code(), 117);
+        let error = std::io::Error::from(error);
+        assert_eq!(error.kind(), std::io::ErrorKind::InvalidData);
+        // unrelated files are still readable
+        assert!(fs.read("short.txt").is_ok());
+    }
+
+    #[test]
+    fn test_checksum_storage_partial_sectors() {
+        let mut data = [0x11_u8; 1000];
+        let mut storage = ChecksumStorage::new(MemoryStorage::new(&mut data[..]), RamDisk::new())
+            .unwrap()
+            .sector_size(256);
+        storage.rebuild_checksums().unwrap();
+        assert_eq!(storage.sidecar_size(), 16);
+        storage.seek(SeekFrom::Start(250)).unwrap();
+        storage.write_all(&[0x22; 300]).unwrap();
+        // a write covering the shorter last sector
+        storage.seek(SeekFrom::Start(768)).unwrap();
+        storage.write_all(&[0x33; 232]).unwrap();
+        assert_eq!(storage.write(&[0x44]).unwrap(), 0);
+
+        let mut buf = [0_u8; 1000];
+        storage.seek(SeekFrom::Start(0)).unwrap();
+        storage.read_exact(&mut buf).unwrap();
+        assert_eq!(storage.read(&mut buf).unwrap(), 0);
+        let mut expected = [0x11_u8; 1000];
+        expected[250..550].fill(0x22);
+        expected[768..].fill(0x33);
+        assert_eq!(buf, expected);
+        assert!(storage.scrub().unwrap().is_empty());
+
+        assert_eq!(
+            storage.seek(SeekFrom::Current(-1001)),
+            Err(ChecksumStorageError::Io(MemoryStorageError::InvalidSeek))
+        );
+        let (_, sidecar) = storage.into_parts();
+        data[999] = 0;
+        let mut storage = ChecksumStorage::new(MemoryStorage::new(&mut data[..]), sidecar)
+            .unwrap()
+            .sector_size(256);
+        storage.seek(SeekFrom::Start(700)).unwrap();
+        assert_eq!(
+            storage.read_exact(&mut buf[..300]),
+            Err(ChecksumStorageError::ChecksumMismatch { lba: 3 })
+        );
+        // a partial write does not hide the corruption
+        assert_eq!(
+            storage.write(&[0x55]),
+            Err(ChecksumStorageError::ChecksumMismatch { lba: 3 })
+        );
+    }
+}
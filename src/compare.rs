@@ -0,0 +1,296 @@
+//! Comparison of directory trees.
+//!
+//! This module implements `compare` and `Dir::compare` which report differences between two directory trees,
+//! possibly stored on different filesystems, without extracting them.
+
+#[cfg(not(feature = "std"))]
+use alloc::{format, string::String, vec, vec::Vec};
+use bitflags::bitflags;
+
+use crate::dir::Dir;
+use crate::dir_entry::DirEntry;
+use crate::error::Error;
+use crate::fs::{FileSystem, OemCpConverter, ReadWriteSeek};
+use crate::io::Read;
+use crate::time::TimeProvider;
+
+const COMPARE_BUFFER_SIZE: usize = 4096;
+
+/// Options for `compare` and `Dir::compare`.
+#[derive(Copy, Clone, Debug)]
+pub struct CompareOptions {
+    pub(crate) compare_modified: bool,
+    pub(crate) compare_content: bool,
+}
+
+impl CompareOptions {
+    /// Creates compare options with default values.
+    #[must_use]
+    pub fn new() -> Self {
+        Self {
+            compare_modified: true,
+            compare_content: true,
+        }
+    }
+
+    /// If enabled files with different last modification times are reported with `ChangeReasons::MODIFIED`.
+    ///
+    /// Default is `true`.
+    #[must_use]
+    pub fn compare_modified(mut self, enabled: bool) -> Self {
+        self.compare_modified = enabled;
+        self
+    }
+
+    /// If enabled content of files with the same size is compared byte by byte and files with different content are
+    /// reported with `ChangeReasons::CONTENT`. Otherwise only metadata is compared, so no file data is read.
+    ///
+    /// Default is `true`.
+    #[must_use]
+    pub fn compare_content(mut self, enabled: bool) -> Self {
+        self.compare_content = enabled;
+        self
+    }
+}
+
+impl Default for CompareOptions {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+bitflags! {
+    /// Reasons why an entry present in both compared trees is reported as changed.
+    #[derive(Default, Debug, Clone, Copy, PartialEq, Eq)]
+    pub struct ChangeReasons: u8 {
+        /// The entry is a file in one tree and a directory in the other one.
+        const KIND     = 0x01;
+        /// Files have different sizes.
+        const SIZE     = 0x02;
+        /// Files have different last modification times.
+        const MODIFIED = 0x04;
+        /// Files have the same size but different content.
+        const CONTENT  = 0x08;
+    }
+}
+
+/// A difference between two directory trees returned by `compare` and `Dir::compare`.
+///
+/// Paths are '/' separated and relative to the compared directories. Contents of added and removed directories are
+/// not reported separately.
+#[derive(Clone, Debug, PartialEq, Eq)]
+#[non_exhaustive]
+pub enum Difference {
+    /// An entry exists only in the second tree.
+    Added {
+        /// Path of the entry in the second tree.
+        path: String,
+        /// `true` if the entry is a directory.
+        is_dir: bool,
+    },
+    /// An entry exists only in the first tree.
+    Removed {
+        /// Path of the entry in the first tree.
+        path: String,
+        /// `true` if the entry is a directory.
+        is_dir: bool,
+    },
+    /// An entry exists in both trees, but differs.
+    Changed {
+        /// Path of the entry in the first tree.
+        path: String,
+        /// Reasons of the change.
+        reasons: ChangeReasons,
+    },
+}
+
+impl Difference {
+    /// Returns the path of the entry.
+    #[must_use]
+    pub fn path(&self) -> &str {
+        match self {
+            Difference::Added { path, .. } | Difference::Removed { path, .. } | Difference::Changed { path, .. } => {
+                path
+            }
+        }
+    }
+}
+
+impl<IO: ReadWriteSeek, TP: TimeProvider, OCC: OemCpConverter> Dir<'_, IO, TP, OCC> {
+    /// Compares this directory tree with the tree of `other` directory and returns the differences.
+    ///
+    /// `other` directory can belong to another filesystem. Entries are matched by names like `open_file` of `other`
+    /// directory would match them. Files are compared by size, last modification time and content as configured by
+    /// `options`, directories present in both trees are compared recursively. Differences are returned in directory
+    /// order of this tree, depth first, followed by entries added in `other` directory.
+    ///
+    /// # Errors
+    ///
+    /// Errors that can be returned:
+    ///
+    /// * `Error::DirectoryModified` will be returned if a compared directory is modified during the comparison.
+    /// * `Error::Io` will be returned if the underlying storage object returned an I/O error.
+    pub fn compare<IO2, TP2, OCC2>(
+        &self,
+        other: &Dir<'_, IO2, TP2, OCC2>,
+        options: CompareOptions,
+    ) -> Result<Vec<Difference>, Error<IO::Error>>
+    where
+        IO2: ReadWriteSeek<Error = IO::Error>,
+        TP2: TimeProvider,
+        OCC2: OemCpConverter,
+    {
+        trace!("Dir::compare");
+        let mut differences = Vec::new();
+        compare_dirs(self, other, "", options, &mut differences)?;
+        Ok(differences)
+    }
+}
+
+/// Compares directory trees of two filesystems and returns the differences.
+///
+/// It is a shortcut for calling `Dir::compare` on the root directories, see its documentation for details.
+///
+/// # Errors
+///
+/// Errors are the same as for `Dir::compare`.
+pub fn compare<IO, TP, OCC, IO2, TP2, OCC2>(
+    fs_a: &FileSystem<IO, TP, OCC>,
+    fs_b: &FileSystem<IO2, TP2, OCC2>,
+    options: CompareOptions,
+) -> Result<Vec<Difference>, Error<IO::Error>>
+where
+    IO: ReadWriteSeek,
+    TP: TimeProvider,
+    OCC: OemCpConverter,
+    IO2: ReadWriteSeek<Error = IO::Error>,
+    TP2: TimeProvider,
+    OCC2: OemCpConverter,
+{
+    fs_a.root_dir().compare(&fs_b.root_dir(), options)
+}
+
+fn compare_dirs<IO, TP, OCC, IO2, TP2, OCC2>(
+    dir_a: &Dir<'_, IO, TP, OCC>,
+    dir_b: &Dir<'_, IO2, TP2, OCC2>,
+    prefix: &str,
+    options: CompareOptions,
+    differences: &mut Vec<Difference>,
+) -> Result<(), Error<IO::Error>>
+where
+    IO: ReadWriteSeek,
+    TP: TimeProvider,
+    OCC: OemCpConverter,
+    IO2: ReadWriteSeek<Error = IO::Error>,
+    TP2: TimeProvider,
+    OCC2: OemCpConverter,
+{
+    let mut entries_b = Vec::new();
+    for r in dir_b.iter() {
+        let e = r?;
+        let name = e.short_file_name_as_bytes();
+        if name != b"." && name != b".." {
+            entries_b.push(e);
+        }
+    }
+    let mut matched = vec![false; entries_b.len()];
+    for r in dir_a.iter() {
+        let e = r?;
+        let name = e.short_file_name_as_bytes();
+        if name == b"." || name == b".." {
+            continue;
+        }
+        let file_name = e.file_name();
+        let path = join_path(prefix, &file_name);
+        let Some(index) = entries_b.iter().position(|e_b| e_b.eq_name(&file_name)) else {
+            differences.push(Difference::Removed {
+                path,
+                is_dir: e.is_dir(),
+            });
+            continue;
+        };
+        matched[index] = true;
+        let e_b = &entries_b[index];
+        let reasons = if e.is_dir() != e_b.is_dir() {
+            ChangeReasons::KIND
+        } else if e.is_dir() {
+            compare_dirs(&e.to_dir(), &e_b.to_dir(), &path, options, differences)?;
+            ChangeReasons::empty()
+        } else {
+            compare_files(&e, e_b, options)?
+        };
+        if !reasons.is_empty() {
+            differences.push(Difference::Changed { path, reasons });
+        }
+    }
+    for (e_b, _) in entries_b.iter().zip(matched).filter(|(_, m)| !m) {
+        differences.push(Difference::Added {
+            path: join_path(prefix, &e_b.file_name()),
+            is_dir: e_b.is_dir(),
+        });
+    }
+    Ok(())
+}
+
+fn compare_files<IO, TP, OCC, IO2, TP2, OCC2>(
+    e_a: &DirEntry<'_, IO, TP, OCC>,
+    e_b: &DirEntry<'_, IO2, TP2, OCC2>,
+    options: CompareOptions,
+) -> Result<ChangeReasons, Error<IO::Error>>
+where
+    IO: ReadWriteSeek,
+    TP: TimeProvider,
+    OCC: OemCpConverter,
+    IO2: ReadWriteSeek<Error = IO::Error>,
+    TP2: TimeProvider,
+    OCC2: OemCpConverter,
+{
+    let mut reasons = ChangeReasons::empty();
+    if e_a.len() != e_b.len() {
+        reasons |= ChangeReasons::SIZE;
+    } else if options.compare_content && !same_content(e_a, e_b)? {
+        reasons |= ChangeReasons::CONTENT;
+    }
+    if options.compare_modified && e_a.modified() != e_b.modified() {
+        reasons |= ChangeReasons::MODIFIED;
+    }
+    Ok(reasons)
+}
+
+// Compares content of two files of the same size
+fn same_content<IO, TP, OCC, IO2, TP2, OCC2>(
+    e_a: &DirEntry<'_, IO, TP, OCC>,
+    e_b: &DirEntry<'_, IO2, TP2, OCC2>,
+) -> Result<bool, Error<IO::Error>>
+where
+    IO: ReadWriteSeek,
+    TP: TimeProvider,
+    OCC: OemCpConverter,
+    IO2: ReadWriteSeek<Error = IO::Error>,
+    TP2: TimeProvider,
+    OCC2: OemCpConverter,
+{
+    let mut file_a = e_a.to_file();
+    let mut file_b = e_b.to_file();
+    let mut buf_a = [0_u8; COMPARE_BUFFER_SIZE];
+    let mut buf_b = [0_u8; COMPARE_BUFFER_SIZE];
+    let mut remaining = e_a.len();
+    while remaining > 0 {
+        let n = usize::try_from(remaining).map_or(COMPARE_BUFFER_SIZE, |r| r.min(COMPARE_BUFFER_SIZE));
+        file_a.read_exact(&mut buf_a[..n])?;
+        file_b.read_exact(&mut buf_b[..n])?;
+        if buf_a[..n] != buf_b[..n] {
+            return Ok(false);
+        }
+        remaining -= n as u64;
+    }
+    Ok(true)
+}
+
+pub(crate) fn join_path(prefix: &str, name: &str) -> String {
+    if prefix.is_empty() {
+        String::from(name)
+    } else {
+        format!("{}/{}", prefix, name)
+    }
+}
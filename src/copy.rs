@@ -0,0 +1,250 @@
+use crate::dir::Dir;
+use crate::dir_entry::DirEntry;
+use crate::error::Error;
+use crate::fs::{OemCpConverter, ReadWriteSeek};
+use crate::io::Write;
+use crate::time::TimeProvider;
+
+/// Options for `Dir::copy_file` and `Dir::copy_to`.
+#[derive(Copy, Clone, Debug)]
+pub struct CopyOptions {
+    pub(crate) preserve_timestamps: bool,
+    pub(crate) preserve_attributes: bool,
+    pub(crate) overwrite: bool,
+}
+
+impl CopyOptions {
+    /// Creates copy options with default values.
+    #[must_use]
+    pub fn new() -> Self {
+        Self {
+            preserve_timestamps: true,
+            preserve_attributes: false,
+            overwrite: false,
+        }
+    }
+
+    /// If enabled creation, last access and last modification times of the source file are copied.
+    /// Otherwise the times come from the `TimeProvider`.
+    ///
+    /// Default is `true`.
+    #[must_use]
+    pub fn preserve_timestamps(mut self, enabled: bool) -> Self {
+        self.preserve_timestamps = enabled;
+        self
+    }
+
+    /// If enabled `READ_ONLY`, `HIDDEN`, `SYSTEM` and `ARCHIVE` attributes of the source file are copied.
+    /// Otherwise attributes of the destination file are not changed.
+    ///
+    /// Default is `false`.
+    #[must_use]
+    pub fn preserve_attributes(mut self, enabled: bool) -> Self {
+        self.preserve_attributes = enabled;
+        self
+    }
+
+    /// If enabled an existing destination file is truncated and overwritten. Otherwise copying to an existing file
+    /// fails.
+    ///
+    /// Default is `false`.
+    #[must_use]
+    pub fn overwrite(mut self, enabled: bool) -> Self {
+        self.overwrite = enabled;
+        self
+    }
+}
+
+impl Default for CopyOptions {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl<'a, IO: ReadWriteSeek, TP: TimeProvider, OCC: OemCpConverter> Dir<'a, IO, TP, OCC> {
+    /// Copies a file inside the filesystem and returns the number of copied bytes.
+    ///
+    /// `src_path` is a '/' separated source file path relative to self directory.
+    /// `dst_path` is a '/' separated destination file path relative to `dst_dir`.
+    /// Data is transferred using `File::copy_range_from`.
+    ///
+    /// # Errors
+    ///
+    /// Errors that can be returned:
+    ///
+    /// * `Error::NotFound` will be returned if `src_path` points to a non-existing directory entry or if `dst_path`
+    ///   stripped from the last component does not point to an existing directory.
+    /// * `Error::InvalidInput` will be returned if `src_path` or `dst_path` points to a directory or if both paths
+    ///   point to the same file.
+    /// * `Error::AlreadyExists` will be returned if `dst_path` points to an existing file and
+    ///   `CopyOptions::overwrite` is disabled.
+    /// * `Error::InvalidFileNameLength` will be returned if the destination file name is empty or if it is too long.
+    /// * `Error::UnsupportedFileNameCharacter` will be returned if the destination file name contains an invalid
+    ///   character.
+    /// * `Error::ReadOnlyFile` will be returned if the existing destination file has the `READ_ONLY` attribute and
+    ///   `FsOptions::enforce_read_only` is enabled.
+    /// * `Error::NotEnoughSpace` will be returned if there is not enough free space to copy the file.
+    /// * `Error::ReadOnlyFilesystem` will be returned if the filesystem is mounted read-only.
+    /// * `Error::Io` will be returned if the underlying storage object returned an I/O error.
+    pub fn copy_file(
+        &self,
+        src_path: &str,
+        dst_dir: &Dir<'a, IO, TP, OCC>,
+        dst_path: &str,
+        options: CopyOptions,
+    ) -> Result<u64, Error<IO::Error>> {
+        trace!("Dir::copy_file {} {}", src_path, dst_path);
+        let src_entry = self.find_entry_by_path(src_path)?.ok_or(Error::InvalidInput)?;
+        if src_entry.is_dir() {
+            debug!("Is a directory");
+            return Err(Error::InvalidInput);
+        }
+        copy_file_entry(&src_entry, dst_dir, dst_path, options)
+    }
+
+    /// Copies a file or a directory tree to a directory of any filesystem and returns the number of copied bytes.
+    ///
+    /// `src_path` is a '/' separated source path relative to self directory. Path pointing to self directory copies
+    /// its contents. `dst_path` is a '/' separated destination path relative to `dst_dir`, which can belong to
+    /// another `FileSystem` instance, e.g. to move files from a FAT12 floppy image to a FAT32 disk image.
+    /// Directories are copied recursively and merged with existing destination directories. Names not supported by
+    /// the destination filesystem are handled according to its `FsOptions::long_name_policy`, e.g. shortened.
+    /// Shortened names cannot be found by their original names, so copying them again creates new entries.
+    /// `options` apply to every copied file and directory. Volume labels are not copied.
+    ///
+    /// # Errors
+    ///
+    /// Errors that can be returned:
+    ///
+    /// * `Error::NotFound` will be returned if `src_path` points to a non-existing directory entry or if `dst_path`
+    ///   stripped from the last component does not point to an existing directory.
+    /// * `Error::InvalidInput` will be returned if a file would be copied to a directory or a directory to a file or
+    ///   if a file would be copied to itself.
+    /// * `Error::AlreadyExists` will be returned if a file would be copied to an existing file and
+    ///   `CopyOptions::overwrite` is disabled.
+    /// * Errors returned by `Dir::create_dir` and `Dir::create_file` for the destination filesystem, e.g.
+    ///   `Error::InvalidFileNameLength` if a name is too long and `LongNamePolicy::Reject` is used.
+    /// * `Error::NotEnoughSpace` will be returned if there is not enough free space in the destination filesystem.
+    /// * `Error::Io` will be returned if the underlying storage object returned an I/O error.
+    pub fn copy_to<IO2, TP2, OCC2>(
+        &self,
+        src_path: &str,
+        dst_dir: &Dir<'_, IO2, TP2, OCC2>,
+        dst_path: &str,
+        options: CopyOptions,
+    ) -> Result<u64, Error<IO::Error>>
+    where
+        IO2: ReadWriteSeek<Error = IO::Error>,
+        TP2: TimeProvider,
+        OCC2: OemCpConverter,
+    {
+        trace!("Dir::copy_to {} {}", src_path, dst_path);
+        match self.find_entry_by_path(src_path)? {
+            Some(e) if e.is_dir() => copy_dir_entries(&e.to_dir(), Some(&e), dst_dir, dst_path, options, None),
+            Some(e) => copy_file_entry(&e, dst_dir, dst_path, options),
+            None => copy_dir_entries(self, None, dst_dir, dst_path, options, None),
+        }
+    }
+}
+
+// Copies file `src_entry` to `dst_path` relative to `dst_dir`
+fn copy_file_entry<IO, TP, OCC, IO2, TP2, OCC2>(
+    src_entry: &DirEntry<'_, IO, TP, OCC>,
+    dst_dir: &Dir<'_, IO2, TP2, OCC2>,
+    dst_path: &str,
+    options: CopyOptions,
+) -> Result<u64, Error<IO::Error>>
+where
+    IO: ReadWriteSeek,
+    TP: TimeProvider,
+    OCC: OemCpConverter,
+    IO2: ReadWriteSeek<Error = IO::Error>,
+    TP2: TimeProvider,
+    OCC2: OemCpConverter,
+{
+    let mut dst_file = match dst_dir.find_entry_by_path(dst_path) {
+        Ok(Some(e)) if e.is_dir() => {
+            debug!("Is a directory");
+            return Err(Error::InvalidInput);
+        }
+        Ok(Some(e)) if e.is_same_entry(src_entry) => {
+            debug!("Source and destination are the same file");
+            return Err(Error::InvalidInput);
+        }
+        Ok(Some(_)) if !options.overwrite => return Err(Error::AlreadyExists),
+        Ok(Some(e)) => {
+            let mut file = e.to_file();
+            file.truncate()?;
+            file
+        }
+        Ok(None) => return Err(Error::InvalidInput),
+        Err(Error::NotFound) => dst_dir.create_file(dst_path)?,
+        Err(err) => return Err(err),
+    };
+    let mut src_file = src_entry.to_file();
+    let metadata = src_file.metadata()?;
+    let copied = dst_file.copy_range_from(&mut src_file, 0, 0, metadata.len())?;
+    if options.preserve_timestamps {
+        dst_file.set_created(metadata.created());
+        dst_file.set_accessed(metadata.accessed());
+        dst_file.set_modified(metadata.modified());
+    }
+    if options.preserve_attributes {
+        dst_file.set_attributes(metadata.attributes());
+    }
+    dst_file.flush()?;
+    Ok(copied)
+}
+
+// Copies entries of `src_dir` to directory `dst_path` relative to `dst_dir` creating it if needed. `skip_dir` is
+// the top destination directory, which is skipped if it is inside the copied tree.
+fn copy_dir_entries<IO, TP, OCC, IO2, TP2, OCC2>(
+    src_dir: &Dir<'_, IO, TP, OCC>,
+    src_entry: Option<&DirEntry<'_, IO, TP, OCC>>,
+    dst_dir: &Dir<'_, IO2, TP2, OCC2>,
+    dst_path: &str,
+    options: CopyOptions,
+    skip_dir: Option<&Dir<'_, IO2, TP2, OCC2>>,
+) -> Result<u64, Error<IO::Error>>
+where
+    IO: ReadWriteSeek,
+    TP: TimeProvider,
+    OCC: OemCpConverter,
+    IO2: ReadWriteSeek<Error = IO::Error>,
+    TP2: TimeProvider,
+    OCC2: OemCpConverter,
+{
+    let mut dst_sub_dir = match dst_dir.find_entry_by_path(dst_path) {
+        Ok(None) => dst_dir.clone(),
+        // Note: shortened names are not found, so such directories are created again
+        Ok(Some(_)) | Err(Error::NotFound) => dst_dir.create_dir(dst_path)?,
+        Err(err) => return Err(err),
+    };
+    let skip_dir = skip_dir.or(Some(&dst_sub_dir));
+    let mut copied = 0;
+    for r in src_dir.iter() {
+        let e = r?;
+        let short_name = e.short_file_name_as_bytes();
+        if short_name == b"." || short_name == b".." || skip_dir.map_or(false, |d| d.is_dir_of(&e)) {
+            continue;
+        }
+        let name = e.file_name();
+        copied += if e.is_dir() {
+            copy_dir_entries(&e.to_dir(), Some(&e), &dst_sub_dir, &name, options, skip_dir)?
+        } else {
+            copy_file_entry(&e, &dst_sub_dir, &name, options)?
+        };
+    }
+    if let (Some(src_e), Some(dst_file)) = (src_entry, dst_sub_dir.as_file_mut()) {
+        if options.preserve_attributes {
+            dst_file.set_attributes(src_e.attributes());
+        }
+        if options.preserve_timestamps {
+            dst_file.set_created(src_e.created());
+            dst_file.set_accessed(src_e.accessed());
+            dst_file.set_modified(src_e.modified());
+        }
+        dst_file.flush()?;
+    }
+    Ok(copied)
+}
@@ -1,21 +1,30 @@
+#[cfg(all(not(feature = "std"), feature = "alloc"))]
+use alloc::string::String;
 #[cfg(all(not(feature = "std"), feature = "alloc", feature = "lfn"))]
 use alloc::vec::Vec;
 use core::num;
 use core::str;
 #[cfg(feature = "lfn")]
 use core::{iter, slice};
+#[cfg(all(feature = "lfn", feature = "normalization"))]
+use unicode_normalization::UnicodeNormalization;
 
 use crate::dir_entry::{
-    DirEntry, DirEntryData, DirFileEntryData, DirLfnEntryData, FileAttributes, ShortName, DIR_ENTRY_SIZE,
+    DirEntry, DirEntryData, DirFileEntryData, DirLfnEntryData, FileAttributes, Metadata, ShortName, DIR_ENTRY_SIZE,
 };
-#[cfg(feature = "lfn")]
-use crate::dir_entry::{LFN_ENTRY_LAST_FLAG, LFN_PART_LEN};
+use crate::dir_entry::{DIR_ENTRY_DELETED_FLAG, LFN_ENTRY_LAST_FLAG, LFN_PART_LEN};
 use crate::dir_entry::{SFN_PADDING, SFN_SIZE};
 use crate::error::{Error, IoError};
 use crate::file::File;
-use crate::fs::{DiskSlice, FileSystem, FsIoAdapter, OemCpConverter, ReadWriteSeek};
+use crate::fs::{
+    DiskSlice, FileSystem, FsIoAdapter, FsOptions, LfnChecksumPolicy, LongNamePolicy, OemCpConverter, ReadWriteSeek,
+    ShortNameTailPolicy, TrailingDotsPolicy,
+};
 use crate::io::{self, IoBase, Read, Seek, SeekFrom, Write};
+use crate::observer::DirOperation;
+use crate::sync::TrackedIteration;
 use crate::time::TimeProvider;
+use crate::watch::FsEvent;
 
 const LFN_PADDING: u16 = 0xFFFF;
 
@@ -39,6 +48,20 @@ impl<IO: ReadWriteSeek, TP, OCC> DirRawStream<'_, IO, TP, OCC> {
         }
     }
 
+    // First cluster of this directory used in `EntryId`, 0 for the root directory
+    fn id_cluster(&self) -> u32 {
+        if self.is_root_dir() {
+            0
+        } else {
+            self.first_cluster().unwrap_or(0)
+        }
+    }
+
+    // Key of this directory in the directory lock table
+    fn lock_key(&self) -> u64 {
+        self.first_cluster().map_or(0, u64::from)
+    }
+
     pub(crate) fn is_root_dir(&self) -> bool {
         match self {
             DirRawStream::File(file) => file.is_root_dir(),
@@ -64,7 +87,7 @@ impl<IO: ReadWriteSeek, TP, OCC> IoBase for DirRawStream<'_, IO, TP, OCC> {
 impl<IO: ReadWriteSeek, TP: TimeProvider, OCC> Read for DirRawStream<'_, IO, TP, OCC> {
     fn read(&mut self, buf: &mut [u8]) -> Result<usize, Self::Error> {
         match self {
-            DirRawStream::File(file) => file.read(buf),
+            DirRawStream::File(file) => file.read_data(buf),
             DirRawStream::Root(raw) => raw.read(buf),
         }
     }
@@ -73,7 +96,7 @@ impl<IO: ReadWriteSeek, TP: TimeProvider, OCC> Read for DirRawStream<'_, IO, TP,
 impl<IO: ReadWriteSeek, TP: TimeProvider, OCC> Write for DirRawStream<'_, IO, TP, OCC> {
     fn write(&mut self, buf: &[u8]) -> Result<usize, Self::Error> {
         match self {
-            DirRawStream::File(file) => file.write(buf),
+            DirRawStream::File(file) => file.write_data(buf),
             DirRawStream::Root(raw) => raw.write(buf),
         }
     }
@@ -94,11 +117,53 @@ impl<IO: ReadWriteSeek, TP, OCC> Seek for DirRawStream<'_, IO, TP, OCC> {
     }
 }
 
+fn split_first_component(path: &str) -> (&str, Option<&str>) {
+    path.find('/')
+        .map_or((path, None), |n| (&path[..n], Some(&path[n + 1..])))
+}
+
+// Returns the part of the path following `..` component that cancels a component preceding the path
+fn skip_to_parent_ref(path: &str) -> Option<&str> {
+    let mut depth = 0_u32;
+    let mut rest = path;
+    loop {
+        let (name, next_opt) = split_first_component(rest);
+        match name {
+            "" | "." => {}
+            ".." if depth == 0 => return Some(next_opt.unwrap_or("")),
+            ".." => depth -= 1,
+            _ => depth += 1,
+        }
+        rest = next_opt?;
+    }
+}
+
+// Splits a path into the first component and the rest of the path after lexical normalization.
+// Empty and `.` components are skipped and components followed by a matching `..` component are removed together
+// with it, so only `..` components pointing above the starting directory are left. Empty name is returned if
+// the path points to the starting directory itself.
 fn split_path(path: &str) -> (&str, Option<&str>) {
-    let trimmed_path = path.trim_matches('/');
-    trimmed_path.find('/').map_or((trimmed_path, None), |n| {
-        (&trimmed_path[..n], Some(&trimmed_path[n + 1..]))
-    })
+    let mut path = path;
+    loop {
+        let (name, rest_opt) = split_first_component(path);
+        let next_opt = match name {
+            "" | "." => rest_opt,
+            ".." => return (name, rest_opt.filter(|r| !split_path(r).0.is_empty())),
+            _ => match rest_opt.and_then(skip_to_parent_ref) {
+                Some(after_parent_ref) => Some(after_parent_ref),
+                None => return (name, rest_opt.filter(|r| !split_path(r).0.is_empty())),
+            },
+        };
+        match next_opt {
+            Some(next) => path = next,
+            None => return ("", None),
+        }
+    }
+}
+
+// Checks if a path points above the starting directory after lexical normalization
+pub(crate) fn points_above(path: &str) -> bool {
+    split_path(path).0 == ".."
 }
 
 enum DirEntryOrShortName<'a, IO: ReadWriteSeek, TP, OCC> {
@@ -110,6 +175,10 @@ enum DirEntryOrShortName<'a, IO: ReadWriteSeek, TP, OCC> {
 ///
 /// This struct is created by the `open_dir` or `create_dir` methods on `Dir`.
 /// The root directory is returned by the `root_dir` method on `FileSystem`.
+///
+/// Paths accepted by `Dir` methods are normalized before lookup: leading, trailing and repeated slashes and `.`
+/// components are ignored and `..` components remove the preceding path component. See
+/// `FsOptions::reject_root_escape` for handling of paths pointing above the root directory.
 pub struct Dir<'a, IO: ReadWriteSeek, TP, OCC> {
     stream: DirRawStream<'a, IO, TP, OCC>,
     fs: &'a FileSystem<IO, TP, OCC>,
@@ -120,35 +189,138 @@ impl<'a, IO: ReadWriteSeek, TP, OCC> Dir<'a, IO, TP, OCC> {
         Dir { stream, fs }
     }
 
+    /// Returns metadata of this directory.
+    ///
+    /// # Errors
+    ///
+    /// `Error::Io` will be returned if the underlying storage object returned an I/O error.
+    pub fn metadata(&self) -> Result<Metadata, Error<IO::Error>> {
+        match self.stream {
+            DirRawStream::File(ref file) => file.metadata(),
+            DirRawStream::Root(ref slice) => Ok(Metadata::root_dir(None, slice.size())),
+        }
+    }
+
+    pub(crate) fn lock_key(&self) -> u64 {
+        self.stream.lock_key()
+    }
+
+    // Records that entries were added to or removed from this directory, so running iterators fail
+    fn mark_modified(&self) {
+        self.fs.dir_changes.record_change(self.lock_key());
+    }
+
+    pub(crate) fn fs(&self) -> &'a FileSystem<IO, TP, OCC> {
+        self.fs
+    }
+
+    // Directory cluster used in IDs of entries of this directory
+    pub(crate) fn id_cluster(&self) -> u32 {
+        self.stream.id_cluster()
+    }
+
+    // Size of the cluster chain of this directory, 0 for the root directory of FAT12/FAT16 volumes
+    pub(crate) fn allocated_size(&self) -> Result<u64, Error<IO::Error>> {
+        self.fs.allocated_size(self.stream.first_cluster())
+    }
+
+    pub(crate) fn raw_stream(&self) -> DirRawStream<'a, IO, TP, OCC> {
+        self.stream.clone()
+    }
+
+    // Checks if `e` is an entry of this directory
+    pub(crate) fn is_dir_of<IO2: ReadWriteSeek, TP2, OCC2: OemCpConverter>(
+        &self,
+        e: &DirEntry<IO2, TP2, OCC2>,
+    ) -> bool {
+        self.fs.is_same_fs(e.fs) && e.is_dir() && self.stream.first_cluster() == e.first_cluster()
+    }
+
+    // Returns the file holding entries of this directory, `None` for the root directory of FAT12/FAT16
+    pub(crate) fn as_file_mut(&mut self) -> Option<&mut File<'a, IO, TP, OCC>> {
+        match self.stream {
+            DirRawStream::File(ref mut file) if !file.is_root_dir() => Some(file),
+            _ => None,
+        }
+    }
+
     /// Creates directory entries iterator.
+    ///
+    /// If entries are added to or removed from the directory before the iteration ends, the iterator returns
+    /// `Error::DirectoryModified` and stops instead of skipping entries or returning stale ones.
     #[must_use]
     #[allow(clippy::iter_not_returning_iterator)]
     pub fn iter(&self) -> DirIter<'a, IO, TP, OCC> {
-        DirIter::new(self.stream.clone(), self.fs, true)
+        DirIter::new(self.stream.clone(), self.fs, true, false, true)
+    }
+
+    /// Creates an iterator over deleted directory entries.
+    ///
+    /// Deleted entries still contain the file size, attributes, timestamps and the first cluster number, but the
+    /// first character of the short name is lost and long file names are not recovered. Returned entries are
+    /// intended to be passed to `undelete`. Do not open them as files or directories because their cluster chains
+    /// have been freed.
+    #[must_use]
+    pub fn deleted_entries(&self) -> DirIter<'a, IO, TP, OCC> {
+        DirIter::new(self.stream.clone(), self.fs, true, true, true)
     }
 }
 
 impl<'a, IO: ReadWriteSeek, TP: TimeProvider, OCC: OemCpConverter> Dir<'a, IO, TP, OCC> {
+    // Creates an entries iterator for a caller which holds the lock of this directory
+    fn iter_locked(&self) -> DirIter<'a, IO, TP, OCC> {
+        DirIter::new(self.stream.clone(), self.fs, true, false, false)
+    }
+
     fn find_entry(
         &self,
         name: &str,
         is_dir: Option<bool>,
         mut short_name_gen: Option<&mut ShortNameGenerator>,
     ) -> Result<DirEntry<'a, IO, TP, OCC>, Error<IO::Error>> {
-        for r in self.iter() {
-            let e = r?;
+        self.fs.observe(|o| o.on_dir_operation(DirOperation::Lookup, name));
+        loop {
+            match Self::find_entry_in(self.iter(), name, is_dir, short_name_gen.as_deref_mut()) {
+                // the directory is not locked between entries so a concurrent change just requires another pass
+                Err(Error::DirectoryModified) => {}
+                r => return r,
+            }
+        }
+    }
+
+    // Variant of `find_entry` for a caller which holds the lock of this directory
+    fn find_entry_locked(
+        &self,
+        name: &str,
+        is_dir: Option<bool>,
+        short_name_gen: Option<&mut ShortNameGenerator>,
+    ) -> Result<DirEntry<'a, IO, TP, OCC>, Error<IO::Error>> {
+        self.fs.observe(|o| o.on_dir_operation(DirOperation::Lookup, name));
+        Self::find_entry_in(self.iter_locked(), name, is_dir, short_name_gen)
+    }
+
+    #[cfg_attr(
+        feature = "tracing",
+        tracing::instrument(name = "lookup", level = "debug", skip(iter, short_name_gen))
+    )]
+    fn find_entry_in(
+        iter: DirIter<'a, IO, TP, OCC>,
+        name: &str,
+        is_dir: Option<bool>,
+        mut short_name_gen: Option<&mut ShortNameGenerator>,
+    ) -> Result<DirEntry<'a, IO, TP, OCC>, Error<IO::Error>> {
+        let dir_cluster = iter.stream.id_cluster();
+        for r in iter {
+            let e = r.map_err(|err| {
+                error!(
+                    "looking up '{}' in directory at cluster {} failed: {:?}",
+                    name, dir_cluster, err
+                );
+                err
+            })?;
             // compare name ignoring case
             if e.eq_name(name) {
-                // check if file or directory is expected
-                if is_dir.is_some() && Some(e.is_dir()) != is_dir {
-                    if e.is_dir() {
-                        debug!("Is a directory");
-                    } else {
-                        debug!("Not a directory");
-                    }
-                    return Err(Error::InvalidInput);
-                }
-                return Ok(e);
+                return Self::check_entry_kind(e, is_dir);
             }
             // update short name generator state
             if let Some(ref mut gen) = short_name_gen {
@@ -158,9 +330,66 @@ impl<'a, IO: ReadWriteSeek, TP: TimeProvider, OCC: OemCpConverter> Dir<'a, IO, T
         Err(Error::NotFound) //("No such file or directory"))
     }
 
+    // Variant of `find_entry` matching only short names (ignoring case) even if an entry has a long name
+    fn find_entry_by_short_name(
+        &self,
+        name: &str,
+        is_dir: Option<bool>,
+    ) -> Result<DirEntry<'a, IO, TP, OCC>, Error<IO::Error>> {
+        'retry: loop {
+            for r in self.iter() {
+                let e = match r {
+                    Err(Error::DirectoryModified) => continue 'retry,
+                    r => r?,
+                };
+                if e.eq_short_name(name) {
+                    return Self::check_entry_kind(e, is_dir);
+                }
+            }
+            return Err(Error::NotFound);
+        }
+    }
+
+    // Reads the entry starting at `offset` (including its LFN entries) in this directory
+    pub(crate) fn entry_at(&self, offset: u32) -> Result<DirEntry<'a, IO, TP, OCC>, Error<IO::Error>> {
+        if offset % DIR_ENTRY_SIZE != 0 {
+            debug!("Misaligned directory entry offset {}", offset);
+            return Err(Error::InvalidInput);
+        }
+        let mut iter = self.iter();
+        match iter.stream.seek(SeekFrom::Start(offset.into())) {
+            Ok(pos) if pos == u64::from(offset) => {}
+            // offset beyond the end of the directory
+            Ok(_) | Err(Error::InvalidInput) => return Err(Error::NotFound),
+            Err(err) => return Err(err),
+        }
+        match iter.next() {
+            Some(Ok(e)) if e.offset_range.0 == u64::from(offset) => Ok(e),
+            Some(Err(err)) => Err(err),
+            // entry was deleted or the offset points inside of an entry
+            _ => Err(Error::NotFound),
+        }
+    }
+
+    fn check_entry_kind(
+        e: DirEntry<'a, IO, TP, OCC>,
+        is_dir: Option<bool>,
+    ) -> Result<DirEntry<'a, IO, TP, OCC>, Error<IO::Error>> {
+        // check if file or directory is expected
+        if is_dir.is_some() && Some(e.is_dir()) != is_dir {
+            if e.is_dir() {
+                debug!("Is a directory");
+            } else {
+                debug!("Not a directory");
+            }
+            return Err(Error::InvalidInput);
+        }
+        Ok(e)
+    }
+
     #[allow(clippy::type_complexity)]
     pub(crate) fn find_volume_entry(&self) -> Result<Option<DirEntry<'a, IO, TP, OCC>>, Error<IO::Error>> {
-        for r in DirIter::new(self.stream.clone(), self.fs, false) {
+        for r in DirIter::new(self.stream.clone(), self.fs, false, false, true) {
             let e = r?;
             if e.data.is_volume() {
                 return Ok(Some(e));
@@ -174,10 +403,15 @@ impl<'a, IO: ReadWriteSeek, TP: TimeProvider, OCC: OemCpConverter> Dir<'a, IO, T
         name: &str,
         is_dir: Option<bool>,
     ) -> Result<DirEntryOrShortName<'a, IO, TP, OCC>, Error<IO::Error>> {
-        let mut short_name_gen = ShortNameGenerator::new(name);
+        let tail_policy = self.fs.options.short_name_tail_policy;
+        let mut short_name_gen = ShortNameGenerator::new(name).with_tail_policy(tail_policy);
+        let mut attempt = 0_u32;
         loop {
+            if let ShortNameTailPolicy::Custom(tail_fn) = tail_policy {
+                short_name_gen.set_custom_tail(tail_fn(short_name_gen.short_name, attempt))?;
+            }
             // find matching entry
-            let r = self.find_entry(name, is_dir, Some(&mut short_name_gen));
+            let r = self.find_entry_locked(name, is_dir, Some(&mut short_name_gen));
             match r {
                 // file not found - continue with short name generation
                 Err(Error::NotFound) => {}
@@ -186,16 +420,67 @@ impl<'a, IO: ReadWriteSeek, TP: TimeProvider, OCC: OemCpConverter> Dir<'a, IO, T
                 // directory already exists - return it
                 Ok(e) => return Ok(DirEntryOrShortName::DirEntry(e)),
             }
+            if self.fs.options.trailing_dots_policy == TrailingDotsPolicy::Reject && name.ends_with(['.', ' ']) {
+                return Err(Error::UnsupportedFileNameCharacter);
+            }
+            if self.fs.options.reject_reserved_names && is_reserved_name(name) {
+                return Err(Error::ReservedFileName);
+            }
+            if self.fs.options.long_name_policy == LongNamePolicy::Reject {
+                short_name_gen.check_short_name()?;
+            }
             // try to generate short name
             if let Ok(name) = short_name_gen.generate() {
                 return Ok(DirEntryOrShortName::ShortName(name));
             }
+            if matches!(tail_policy, ShortNameTailPolicy::Numeric) {
+                // all numeric tails are used
+                return Err(Error::AlreadyExists);
+            }
             // there were too many collisions in short name generation
-            // try different checksum in the next iteration
+            // try different checksum or custom tail in the next iteration
             short_name_gen.next_iteration();
+            attempt = attempt.checked_add(1).ok_or(Error::AlreadyExists)?;
         }
     }
 
+    fn resolve_path<'p>(&self, path: &'p str) -> Result<(&'p str, Option<&'p str>), Error<IO::Error>> {
+        let (mut name, mut rest_opt) = split_path(path);
+        // root directory has no `..` entry - it is its own parent unless escaping the root is forbidden
+        while name == ".." && self.stream.is_root_dir() {
+            if self.fs.options.reject_root_escape {
+                error!("Path points above the root directory");
+                return Err(Error::InvalidInput);
+            }
+            (name, rest_opt) = rest_opt.map_or(("", None), split_path);
+        }
+        if self.fs.options.trailing_dots_policy == TrailingDotsPolicy::Strip && name != "." && name != ".." {
+            let stripped_name = name.trim_end_matches(['.', ' ']);
+            if stripped_name.is_empty() && !name.is_empty() {
+                return Err(Error::InvalidFileNameLength);
+            }
+            name = stripped_name;
+        }
+        Ok((name, rest_opt))
+    }
+
+    // Returns None if the path points to self directory
+    #[allow(clippy::type_complexity)]
+    pub(crate) fn find_entry_by_path(&self, path: &str) -> Result<Option<DirEntry<'a, IO, TP, OCC>>, Error<IO::Error>> {
+        trace!("Dir::find_entry_by_path {}", path);
+        // traverse path
+        let (name, rest_opt) = self.resolve_path(path)?;
+        if name.is_empty() {
+            return Ok(None);
+        }
+        if let Some(rest) = rest_opt {
+            let e = self.find_entry(name, Some(true), None)?;
+            let entry_opt = e.to_dir().find_entry_by_path(rest)?;
+            return Ok(Some(entry_opt.unwrap_or(e)));
+        }
+        self.find_entry(name, None, None).map(Some)
+    }
+
     /// Opens existing subdirectory.
     ///
     /// `path` is a '/' separated directory path relative to self directory.
@@ -209,7 +494,10 @@ impl<'a, IO: ReadWriteSeek, TP: TimeProvider, OCC: OemCpConverter> Dir<'a, IO, T
     /// * `Error::Io` will be returned if the underlying storage object returned an I/O error.
     pub fn open_dir(&self, path: &str) -> Result<Self, Error<IO::Error>> {
         trace!("Dir::open_dir {}", path);
-        let (name, rest_opt) = split_path(path);
+        let (name, rest_opt) = self.resolve_path(path)?;
+        if name.is_empty() {
+            return Ok(self.clone());
+        }
         let e = self.find_entry(name, Some(true), None)?;
         match rest_opt {
             Some(rest) => e.to_dir().open_dir(rest),
@@ -231,7 +519,7 @@ impl<'a, IO: ReadWriteSeek, TP: TimeProvider, OCC: OemCpConverter> Dir<'a, IO, T
     pub fn open_file(&self, path: &str) -> Result<File<'a, IO, TP, OCC>, Error<IO::Error>> {
         trace!("Dir::open_file {}", path);
         // traverse path
-        let (name, rest_opt) = split_path(path);
+        let (name, rest_opt) = self.resolve_path(path)?;
         if let Some(rest) = rest_opt {
             let e = self.find_entry(name, Some(true), None)?;
             return e.to_dir().open_file(rest);
@@ -241,6 +529,103 @@ impl<'a, IO: ReadWriteSeek, TP: TimeProvider, OCC: OemCpConverter> Dir<'a, IO, T
         Ok(e.to_file())
     }
 
+    /// Checks if a file or a directory exists.
+    ///
+    /// `path` is a '/' separated path relative to self directory. Path that normalizes to an empty path points to self
+    /// directory. The path is walked once and no `File` or `Dir` object is created. Errors are treated as if the entry
+    /// did not exist, use `try_exists` to handle them.
+    #[must_use]
+    pub fn exists(&self, path: &str) -> bool {
+        self.try_exists(path).unwrap_or(false)
+    }
+
+    /// Checks if a file or a directory exists and reports errors other than a missing entry.
+    ///
+    /// `path` is a '/' separated path relative to self directory.
+    ///
+    /// # Errors
+    ///
+    /// Errors that can be returned:
+    ///
+    /// * `Error::InvalidInput` will be returned if a non-final component of `path` is not a directory.
+    /// * `Error::Io` will be returned if the underlying storage object returned an I/O error.
+    pub fn try_exists(&self, path: &str) -> Result<bool, Error<IO::Error>> {
+        match self.find_entry_by_path(path) {
+            Ok(_) => Ok(true),
+            Err(Error::NotFound) => Ok(false),
+            Err(err) => Err(err),
+        }
+    }
+
+    /// Checks if `path` points to an existing directory.
+    ///
+    /// `path` is a '/' separated path relative to self directory. Errors are treated as if the entry did not exist.
+    #[must_use]
+    pub fn is_dir(&self, path: &str) -> bool {
+        self.find_entry_by_path(path)
+            .map_or(false, |e| e.map_or(true, |e| e.is_dir()))
+    }
+
+    /// Checks if `path` points to an existing file.
+    ///
+    /// `path` is a '/' separated path relative to self directory. Errors are treated as if the entry did not exist.
+    #[must_use]
+    pub fn is_file(&self, path: &str) -> bool {
+        self.find_entry_by_path(path)
+            .map_or(false, |e| e.map_or(false, |e| !e.is_dir()))
+    }
+
+    /// Opens existing subdirectory matching path components only against short names (8.3 aliases).
+    ///
+    /// `path` is a '/' separated directory path relative to self directory. Unlike `open_dir` long names are
+    /// ignored, so an entry is found by its short name even if another entry has a long name equal to it.
+    /// Short names are compared ignoring case. This is useful for paths recorded by software that only knows
+    /// short names, e.g. `VERY-L~1/SUBDIR`.
+    ///
+    /// # Errors
+    ///
+    /// Errors that can be returned:
+    ///
+    /// * `Error::NotFound` will be returned if `path` does not point to any existing directory entry.
+    /// * `Error::InvalidInput` will be returned if `path` points to a file that is not a directory.
+    /// * `Error::Io` will be returned if the underlying storage object returned an I/O error.
+    pub fn open_dir_short(&self, path: &str) -> Result<Self, Error<IO::Error>> {
+        trace!("Dir::open_dir_short {}", path);
+        let (name, rest_opt) = self.resolve_path(path)?;
+        if name.is_empty() {
+            return Ok(self.clone());
+        }
+        let e = self.find_entry_by_short_name(name, Some(true))?;
+        match rest_opt {
+            Some(rest) => e.to_dir().open_dir_short(rest),
+            None => Ok(e.to_dir()),
+        }
+    }
+
+    /// Opens existing file matching path components only against short names (8.3 aliases).
+    ///
+    /// `path` is a '/' separated file path relative to self directory, e.g. `MYDOCU~1.TXT`. See `open_dir_short`
+    /// for details about matching.
+    ///
+    /// # Errors
+    ///
+    /// Errors that can be returned:
+    ///
+    /// * `Error::NotFound` will be returned if `path` points to a non-existing directory entry.
+    /// * `Error::InvalidInput` will be returned if `path` points to a file that is a directory.
+    /// * `Error::Io` will be returned if the underlying storage object returned an I/O error.
+    pub fn open_file_short(&self, path: &str) -> Result<File<'a, IO, TP, OCC>, Error<IO::Error>> {
+        trace!("Dir::open_file_short {}", path);
+        // traverse path
+        let (name, rest_opt) = self.resolve_path(path)?;
+        if let Some(rest) = rest_opt {
+            let e = self.find_entry_by_short_name(name, Some(true))?;
+            return e.to_dir().open_file_short(rest);
+        }
+        let e = self.find_entry_by_short_name(name, Some(false))?;
+        Ok(e.to_file())
+    }
+
     /// Creates new or opens existing file=.
     ///
     /// `path` is a '/' separated file path relative to `self` directory.
@@ -253,25 +638,57 @@ impl<'a, IO: ReadWriteSeek, TP: TimeProvider, OCC: OemCpConverter> Dir<'a, IO, T
     /// * `Error::InvalidInput` will be returned if `path` points to an existing file that is a directory.
     /// * `Error::InvalidFileNameLength` will be returned if the file name is empty or if it is too long.
     /// * `Error::UnsupportedFileNameCharacter` will be returned if the file name contains an invalid character.
+    /// * `Error::ReservedFileName` will be returned if the file name is a reserved DOS device name and
+    ///   `FsOptions::reject_reserved_names` is enabled.
     /// * `Error::NotEnoughSpace` will be returned if there is not enough free space to create a new file.
+    /// * `Error::ReadOnlyFilesystem` will be returned if a new file has to be created and the filesystem is
+    ///   mounted read-only.
     /// * `Error::Io` will be returned if the underlying storage object returned an I/O error.
     pub fn create_file(&self, path: &str) -> Result<File<'a, IO, TP, OCC>, Error<IO::Error>> {
-        trace!("Dir::create_file {}", path);
+        self.open_or_create_file(path).map(|(file, _)| file)
+    }
+
+    /// Opens existing file or creates a new one and reports which of them happened.
+    ///
+    /// `path` is a '/' separated file path relative to `self` directory. Works like `create_file` but also returns
+    /// `true` if the file has been created. The check and the creation are atomic with respect to other operations of
+    /// this crate, so exactly one of concurrent callers creating the same file gets `true`, e.g. to initialize it.
+    ///
+    /// # Errors
+    ///
+    /// Errors are the same as for `Dir::create_file`.
+    #[allow(clippy::type_complexity)]
+    pub fn open_or_create_file(&self, path: &str) -> Result<(File<'a, IO, TP, OCC>, bool), Error<IO::Error>> {
+        trace!("Dir::open_or_create_file {}", path);
         // traverse path
-        let (name, rest_opt) = split_path(path);
+        let (name, rest_opt) = self.resolve_path(path)?;
         if let Some(rest) = rest_opt {
-            return self.find_entry(name, Some(true), None)?.to_dir().create_file(rest);
+            return self
+                .find_entry(name, Some(true), None)?
+                .to_dir()
+                .open_or_create_file(rest);
         }
         // this is final filename in the path
+        let _guard = self.fs.dir_locks.write(self.lock_key());
         let r = self.check_for_existence(name, Some(false))?;
         match r {
             // file does not exist - create it
             DirEntryOrShortName::ShortName(short_name) => {
+                self.fs.check_writable()?;
                 let sfn_entry = self.create_sfn_entry(short_name, FileAttributes::from_bits_truncate(0), None);
-                Ok(self.write_entry(name, sfn_entry)?.to_file())
+                let e = self.write_entry(name, sfn_entry)?;
+                let file = e.to_file();
+                self.fs.write_through()?;
+                self.fs.notify(|| FsEvent::Created {
+                    id: e.id(),
+                    name,
+                    is_dir: false,
+                });
+                self.fs.observe(|o| o.on_dir_operation(DirOperation::Create, name));
+                Ok((file, true))
             }
             // file already exists - return it
-            DirEntryOrShortName::DirEntry(e) => Ok(e.to_file()),
+            DirEntryOrShortName::DirEntry(e) => Ok((e.to_file(), false)),
         }
     }
 
@@ -286,29 +703,49 @@ impl<'a, IO: ReadWriteSeek, TP: TimeProvider, OCC: OemCpConverter> Dir<'a, IO, T
     /// * `Error::InvalidInput` will be returned if `path` points to an existing file that is not a directory.
     /// * `Error::InvalidFileNameLength` will be returned if the file name is empty or if it is too long.
     /// * `Error::UnsupportedFileNameCharacter` will be returned if the file name contains an invalid character.
+    /// * `Error::ReservedFileName` will be returned if the file name is a reserved DOS device name and
+    ///   `FsOptions::reject_reserved_names` is enabled.
     /// * `Error::NotEnoughSpace` will be returned if there is not enough free space to create a new directory.
+    /// * `Error::ReadOnlyFilesystem` will be returned if a new directory has to be created and the filesystem is
+    ///   mounted read-only.
     /// * `Error::Io` will be returned if the underlying storage object returned an I/O error.
     pub fn create_dir(&self, path: &str) -> Result<Self, Error<IO::Error>> {
-        trace!("Dir::create_dir {}", path);
+        self.open_or_create_dir(path).map(|(dir, _)| dir)
+    }
+
+    /// Opens existing directory or creates a new one and reports which of them happened.
+    ///
+    /// `path` is a '/' separated path relative to self directory. Works like `create_dir` but also returns `true` if
+    /// the directory has been created. The check and the creation are atomic with respect to other operations of this
+    /// crate, so exactly one of concurrent callers creating the same directory gets `true`.
+    ///
+    /// # Errors
+    ///
+    /// Errors are the same as for `Dir::create_dir`.
+    pub fn open_or_create_dir(&self, path: &str) -> Result<(Self, bool), Error<IO::Error>> {
+        trace!("Dir::open_or_create_dir {}", path);
         // traverse path
-        let (name, rest_opt) = split_path(path);
+        let (name, rest_opt) = self.resolve_path(path)?;
         if let Some(rest) = rest_opt {
-            return self.find_entry(name, Some(true), None)?.to_dir().create_dir(rest);
+            return self
+                .find_entry(name, Some(true), None)?
+                .to_dir()
+                .open_or_create_dir(rest);
         }
         // this is final filename in the path
+        let _guard = self.fs.dir_locks.write(self.lock_key());
         let r = self.check_for_existence(name, Some(true))?;
         match r {
             // directory does not exist - create it
             DirEntryOrShortName::ShortName(short_name) => {
+                self.fs.check_writable()?;
                 // alloc cluster for directory data
                 let cluster = self.fs.alloc_cluster(None, true)?;
-                // create entry in parent directory
-                let sfn_entry = self.create_sfn_entry(short_name, FileAttributes::DIRECTORY, Some(cluster));
-                let entry = self.write_entry(name, sfn_entry)?;
-                let dir = entry.to_dir();
-                // create special entries "." and ".."
+                // create special entries "." and ".." before the directory becomes visible to other threads, so
+                // its lock is not needed
+                let dir = Dir::new(DirRawStream::File(File::new(Some(cluster), None, self.fs)), self.fs);
                 let dot_sfn = ShortNameGenerator::generate_dot();
-                let sfn_entry = self.create_sfn_entry(dot_sfn, FileAttributes::DIRECTORY, entry.first_cluster());
+                let sfn_entry = self.create_sfn_entry(dot_sfn, FileAttributes::DIRECTORY, Some(cluster));
                 dir.write_entry(".", sfn_entry)?;
                 let dotdot_sfn = ShortNameGenerator::generate_dotdot();
                 // cluster of the root dir shall be set to 0 in directory entries.
@@ -319,17 +756,61 @@ impl<'a, IO: ReadWriteSeek, TP: TimeProvider, OCC: OemCpConverter> Dir<'a, IO, T
                 };
                 let sfn_entry = self.create_sfn_entry(dotdot_sfn, FileAttributes::DIRECTORY, dotdot_cluster);
                 dir.write_entry("..", sfn_entry)?;
-                Ok(dir)
+                // create entry in parent directory
+                let sfn_entry = self.create_sfn_entry(short_name, FileAttributes::DIRECTORY, Some(cluster));
+                let e = self.write_entry(name, sfn_entry)?;
+                let dir = e.to_dir();
+                self.fs.write_through()?;
+                self.fs.notify(|| FsEvent::Created {
+                    id: e.id(),
+                    name,
+                    is_dir: true,
+                });
+                self.fs.observe(|o| o.on_dir_operation(DirOperation::Create, name));
+                Ok((dir, true))
             }
             // directory already exists - return it
-            DirEntryOrShortName::DirEntry(e) => Ok(e.to_dir()),
+            DirEntryOrShortName::DirEntry(e) => Ok((e.to_dir(), false)),
+        }
+    }
+
+    /// Returns the short name (8.3 alias) of an entry.
+    ///
+    /// If `path` points to an existing entry its short name is returned. Otherwise the short name that would be
+    /// generated for a new entry created in this moment is returned, without modifying the directory.
+    /// Non-ASCII characters are replaced by the replacement character (U+FFFD).
+    ///
+    /// # Errors
+    ///
+    /// Errors that can be returned:
+    ///
+    /// * `Error::NotFound` will be returned if `path` stripped from the last component does not point to an
+    ///   existing directory.
+    /// * `Error::InvalidFileNameLength` will be returned if the file name is empty or if it is too long.
+    /// * `Error::UnsupportedFileNameCharacter` will be returned if the file name contains an invalid character.
+    /// * `Error::AlreadyExists` will be returned if no unique short name can be generated.
+    /// * `Error::Io` will be returned if the underlying storage object returned an I/O error.
+    #[cfg(feature = "alloc")]
+    pub fn short_name_for(&self, path: &str) -> Result<String, Error<IO::Error>> {
+        trace!("Dir::short_name_for {}", path);
+        // traverse path
+        let (name, rest_opt) = self.resolve_path(path)?;
+        if let Some(rest) = rest_opt {
+            return self.find_entry(name, Some(true), None)?.to_dir().short_name_for(rest);
+        }
+        validate_long_name(name)?;
+        let _guard = self.fs.dir_locks.read(self.lock_key());
+        let oem_cp_converter = &self.fs.options.oem_cp_converter;
+        match self.check_for_existence(name, None)? {
+            DirEntryOrShortName::ShortName(short_name) => Ok(ShortName::new(&short_name).to_string(oem_cp_converter)),
+            DirEntryOrShortName::DirEntry(e) => Ok(e.short_file_name()),
         }
     }
 
     fn is_empty(&self) -> Result<bool, Error<IO::Error>> {
         trace!("Dir::is_empty");
         // check if directory contains no files
-        for r in self.iter() {
+        for r in self.iter_locked() {
             let e = r?;
             let name = e.short_file_name_as_bytes();
             // ignore special entries "." and ".."
@@ -353,28 +834,137 @@ impl<'a, IO: ReadWriteSeek, TP: TimeProvider, OCC: OemCpConverter> Dir<'a, IO, T
     /// * `Error::NotFound` will be returned if `path` points to a non-existing directory entry.
     /// * `Error::InvalidInput` will be returned if `path` points to a file that is not a directory.
     /// * `Error::DirectoryIsNotEmpty` will be returned if the specified directory is not empty.
+    /// * `Error::ReadOnlyFile` will be returned if the entry has the `READ_ONLY` attribute and
+    ///   `FsOptions::enforce_read_only` is enabled.
+    /// * `Error::ReadOnlyFilesystem` will be returned if the filesystem is mounted read-only.
     /// * `Error::Io` will be returned if the underlying storage object returned an I/O error.
     pub fn remove(&self, path: &str) -> Result<(), Error<IO::Error>> {
         trace!("Dir::remove {}", path);
+        self.remove_internal(path, false)?;
+        self.fs.write_through()
+    }
+
+    /// Removes existing file or directory making its data unrecoverable.
+    ///
+    /// Works like `remove` but all clusters of the file or directory are filled with zeros before they are freed
+    /// and its directory entries (including the long file name) are cleared except for the deleted entry marker.
+    /// Note: copies of the data made by other software (e.g. backups or journaling storage) are not affected.
+    ///
+    /// # Errors
+    ///
+    /// Errors that can be returned:
+    ///
+    /// * `Error::NotFound` will be returned if `path` points to a non-existing directory entry.
+    /// * `Error::InvalidInput` will be returned if `path` points to a file that is not a directory.
+    /// * `Error::DirectoryIsNotEmpty` will be returned if the specified directory is not empty.
+    /// * `Error::ReadOnlyFile` will be returned if the entry has the `READ_ONLY` attribute and
+    ///   `FsOptions::enforce_read_only` is enabled.
+    /// * `Error::ReadOnlyFilesystem` will be returned if the filesystem is mounted read-only.
+    /// * `Error::Io` will be returned if the underlying storage object returned an I/O error.
+    pub fn remove_secure(&self, path: &str) -> Result<(), Error<IO::Error>> {
+        trace!("Dir::remove_secure {}", path);
+        self.remove_internal(path, true)?;
+        self.fs.write_through()
+    }
+
+    fn remove_internal(&self, path: &str, secure: bool) -> Result<(), Error<IO::Error>> {
         // traverse path
-        let (name, rest_opt) = split_path(path);
+        let (name, rest_opt) = self.resolve_path(path)?;
         if let Some(rest) = rest_opt {
             let e = self.find_entry(name, Some(true), None)?;
-            return e.to_dir().remove(rest);
+            return e.to_dir().remove_internal(rest, secure);
+        }
+        // Lock the removed directory too, so nothing is created in it after it is checked to be empty. The entry is
+        // looked up again after locking in case it was replaced in the meantime.
+        let (e, _guards) = loop {
+            let first_cluster = self.find_entry(name, None, None)?.first_cluster();
+            let guards = self
+                .fs
+                .dir_locks
+                .write_pair(self.lock_key(), first_cluster.map_or(0, u64::from));
+            let e = self.find_entry_locked(name, None, None)?;
+            if e.first_cluster() == first_cluster {
+                break (e, guards);
+            }
+        };
+        self.fs.check_writable()?;
+        if self.fs.options.enforce_read_only && e.data.is_read_only() {
+            debug!("Cannot remove read-only entry");
+            return Err(Error::ReadOnlyFile);
         }
         // in case of directory check if it is empty
-        let e = self.find_entry(name, None, None)?;
         if e.is_dir() && !e.to_dir().is_empty()? {
             return Err(Error::DirectoryIsNotEmpty);
         }
         // free data
         if let Some(n) = e.first_cluster() {
+            if secure {
+                self.fs.zero_cluster_chain(n)?;
+            }
             self.fs.free_cluster_chain(n)?;
         }
         // free long and short name entries
+        let num = ((e.offset_range.1 - e.offset_range.0) / u64::from(DIR_ENTRY_SIZE)) as u32;
+        if secure {
+            self.wipe_entries(e.offset_range.0, num)?;
+        } else {
+            self.delete_entries(e.offset_range.0, num)?;
+        }
+        self.fs.observe(|o| o.on_dir_operation(DirOperation::Remove, name));
+        self.fs.notify(|| FsEvent::Removed { id: e.id(), name });
+        Ok(())
+    }
+
+    // Overwrites `num` raw entries starting at `offset` with zeros and marks them as deleted
+    fn wipe_entries(&self, offset: u64, num: u32) -> Result<(), Error<IO::Error>> {
+        let mut wiped_entry = [0_u8; DIR_ENTRY_SIZE as usize];
+        wiped_entry[0] = DIR_ENTRY_DELETED_FLAG;
         let mut stream = self.stream.clone();
-        stream.seek(SeekFrom::Start(e.offset_range.0))?;
-        let num = ((e.offset_range.1 - e.offset_range.0) / u64::from(DIR_ENTRY_SIZE)) as usize;
+        stream.seek(SeekFrom::Start(offset))?;
+        for _ in 0..num {
+            stream.write_all(&wiped_entry)?;
+        }
+        self.mark_modified();
+        Ok(())
+    }
+
+    // Clears unused and deleted entries in this directory and all its subdirectories
+    pub(crate) fn wipe_slack(&self) -> Result<(), Error<IO::Error>> {
+        let guard = self.fs.dir_locks.write(self.lock_key());
+        let mut stream = self.stream.clone();
+        stream.seek(SeekFrom::Start(0))?;
+        let mut end_found = false;
+        let mut buf = [0_u8; DIR_ENTRY_SIZE as usize];
+        loop {
+            match stream.read_exact(&mut buf) {
+                Err(Error::UnexpectedEof) => break,
+                r => r?,
+            }
+            end_found = end_found || buf[0] == 0;
+            let wiped_marker = if end_found { 0 } else { DIR_ENTRY_DELETED_FLAG };
+            if (end_found || buf[0] == DIR_ENTRY_DELETED_FLAG) && buf.iter().skip(1).any(|b| *b != 0) {
+                buf.fill(0);
+                buf[0] = wiped_marker;
+                stream.seek(SeekFrom::Current(-i64::from(DIR_ENTRY_SIZE)))?;
+                stream.write_all(&buf)?;
+            }
+        }
+        // subdirectories may share the lock
+        drop(guard);
+        for r in self.iter() {
+            let e = r?;
+            let name = e.short_file_name_as_bytes();
+            if e.is_dir() && name != b"." && name != b".." {
+                e.to_dir().wipe_slack()?;
+            }
+        }
+        Ok(())
+    }
+
+    // Marks `num` raw entries starting at `offset` in this directory as deleted
+    pub(crate) fn delete_entries(&self, offset: u64, num: u32) -> Result<(), Error<IO::Error>> {
+        let mut stream = self.stream.clone();
+        stream.seek(SeekFrom::Start(offset))?;
         for _ in 0..num {
             let mut data = DirEntryData::deserialize(&mut stream)?;
             trace!("removing dir entry {:?}", data);
@@ -382,6 +972,7 @@ impl<'a, IO: ReadWriteSeek, TP: TimeProvider, OCC: OemCpConverter> Dir<'a, IO, T
             stream.seek(SeekFrom::Current(-i64::from(DIR_ENTRY_SIZE)))?;
             data.serialize(&mut stream)?;
         }
+        self.mark_modified();
         Ok(())
     }
 
@@ -400,23 +991,72 @@ impl<'a, IO: ReadWriteSeek, TP: TimeProvider, OCC: OemCpConverter> Dir<'a, IO, T
     /// * `Error::NotFound` will be returned if `src_path` points to a non-existing directory entry or if `dst_path`
     ///   stripped from the last component does not point to an existing directory.
     /// * `Error::AlreadyExists` will be returned if `dst_path` points to an existing directory entry.
+    /// * `Error::ReadOnlyFilesystem` will be returned if the filesystem is mounted read-only.
     /// * `Error::Io` will be returned if the underlying storage object returned an I/O error.
     pub fn rename(&self, src_path: &str, dst_dir: &Dir<IO, TP, OCC>, dst_path: &str) -> Result<(), Error<IO::Error>> {
         trace!("Dir::rename {} {}", src_path, dst_path);
         // traverse source path
-        let (src_name, src_rest_opt) = split_path(src_path);
+        let (src_name, src_rest_opt) = self.resolve_path(src_path)?;
         if let Some(rest) = src_rest_opt {
             let e = self.find_entry(src_name, Some(true), None)?;
             return e.to_dir().rename(rest, dst_dir, dst_path);
         }
         // traverse destination path
-        let (dst_name, dst_rest_opt) = split_path(dst_path);
+        let (dst_name, dst_rest_opt) = dst_dir.resolve_path(dst_path)?;
         if let Some(rest) = dst_rest_opt {
             let e = dst_dir.find_entry(dst_name, Some(true), None)?;
             return self.rename(src_path, &e.to_dir(), rest);
         }
         // move/rename file
-        self.rename_internal(src_path, dst_dir, dst_path)
+        let _guards = self.fs.dir_locks.write_pair(self.lock_key(), dst_dir.lock_key());
+        self.rename_internal(src_name, dst_dir, dst_name, false)?;
+        self.fs.write_through()
+    }
+
+    /// Renames or moves existing file or directory replacing an existing destination file.
+    ///
+    /// Works like `rename` but if `dst_path` points to an existing file it is replaced, like POSIX `rename`
+    /// does. The destination entry is updated in place to point to the source file, so the destination path never
+    /// stops to exist. The source entry is removed and the old destination data is freed only after that.
+    /// The destination entry keeps its long and short names.
+    /// Make sure there is no reference to the source or destination file (no File instance) or filesystem
+    /// corruption can happen.
+    ///
+    /// # Errors
+    ///
+    /// Errors that can be returned:
+    ///
+    /// * `Error::NotFound` will be returned if `src_path` points to a non-existing directory entry or if `dst_path`
+    ///   stripped from the last component does not point to an existing directory.
+    /// * `Error::InvalidInput` will be returned if `dst_path` points to an existing directory or if `src_path`
+    ///   points to a directory and `dst_path` points to an existing file.
+    /// * `Error::ReadOnlyFile` will be returned if the destination file has the `READ_ONLY` attribute and
+    ///   `FsOptions::enforce_read_only` is enabled.
+    /// * `Error::ReadOnlyFilesystem` will be returned if the filesystem is mounted read-only.
+    /// * `Error::Io` will be returned if the underlying storage object returned an I/O error.
+    pub fn rename_replace(
+        &self,
+        src_path: &str,
+        dst_dir: &Dir<IO, TP, OCC>,
+        dst_path: &str,
+    ) -> Result<(), Error<IO::Error>> {
+        trace!("Dir::rename_replace {} {}", src_path, dst_path);
+        // traverse source path
+        let (src_name, src_rest_opt) = self.resolve_path(src_path)?;
+        if let Some(rest) = src_rest_opt {
+            let e = self.find_entry(src_name, Some(true), None)?;
+            return e.to_dir().rename_replace(rest, dst_dir, dst_path);
+        }
+        // traverse destination path
+        let (dst_name, dst_rest_opt) = dst_dir.resolve_path(dst_path)?;
+        if let Some(rest) = dst_rest_opt {
+            let e = dst_dir.find_entry(dst_name, Some(true), None)?;
+            return self.rename_replace(src_path, &e.to_dir(), rest);
+        }
+        // move/rename file
+        let _guards = self.fs.dir_locks.write_pair(self.lock_key(), dst_dir.lock_key());
+        self.rename_internal(src_name, dst_dir, dst_name, true)?;
+        self.fs.write_through()
     }
 
     fn rename_internal(
@@ -424,10 +1064,12 @@ impl<'a, IO: ReadWriteSeek, TP: TimeProvider, OCC: OemCpConverter> Dir<'a, IO, T
         src_name: &str,
         dst_dir: &Dir<IO, TP, OCC>,
         dst_name: &str,
+        replace: bool,
     ) -> Result<(), Error<IO::Error>> {
         trace!("Dir::rename_internal {} {}", src_name, dst_name);
+        self.fs.check_writable()?;
         // find existing file
-        let e = self.find_entry(src_name, None, None)?;
+        let e = self.find_entry_locked(src_name, None, None)?;
         // check if destionation filename is unused
         let r = dst_dir.check_for_existence(dst_name, None)?;
         let short_name = match r {
@@ -438,6 +1080,16 @@ impl<'a, IO: ReadWriteSeek, TP: TimeProvider, OCC: OemCpConverter> Dir<'a, IO, T
                     // nothing to do
                     return Ok(());
                 }
+                if replace {
+                    self.replace_entry(&e, dst_dir, dst_e)?;
+                    self.fs.notify(|| FsEvent::Renamed {
+                        old_id: e.id(),
+                        old_name: src_name,
+                        new_id: dst_e.id(),
+                        new_name: dst_name,
+                    });
+                    return Ok(());
+                }
                 // destination file exists and it is not the same as source file - fail
                 return Err(Error::AlreadyExists);
             }
@@ -455,9 +1107,125 @@ impl<'a, IO: ReadWriteSeek, TP: TimeProvider, OCC: OemCpConverter> Dir<'a, IO, T
             stream.seek(SeekFrom::Current(-i64::from(DIR_ENTRY_SIZE)))?;
             data.serialize(&mut stream)?;
         }
+        self.mark_modified();
         // save new directory entry
         let sfn_entry = e.data.renamed(short_name);
-        dst_dir.write_entry(dst_name, sfn_entry)?;
+        let dst_e = dst_dir.write_entry(dst_name, sfn_entry)?;
+        self.fs.observe(|o| o.on_dir_operation(DirOperation::Rename, src_name));
+        self.fs.notify(|| FsEvent::Renamed {
+            old_id: e.id(),
+            old_name: src_name,
+            new_id: dst_e.id(),
+            new_name: dst_name,
+        });
+        Ok(())
+    }
+
+    // Replaces existing file entry `dst_e` in `dst_dir` by entry `e` from this directory
+    fn replace_entry(
+        &self,
+        e: &DirEntry<'a, IO, TP, OCC>,
+        dst_dir: &Dir<IO, TP, OCC>,
+        dst_e: &DirEntry<'a, IO, TP, OCC>,
+    ) -> Result<(), Error<IO::Error>> {
+        if dst_e.is_dir() || e.is_dir() {
+            debug!("Only files can be replaced");
+            return Err(Error::InvalidInput);
+        }
+        if self.fs.options.enforce_read_only && dst_e.data.is_read_only() {
+            debug!("Cannot replace read-only entry");
+            return Err(Error::ReadOnlyFile);
+        }
+        // overwrite the destination short name entry keeping its name - this single entry write commits the change
+        let mut stream = dst_dir.stream.clone();
+        stream.seek(SeekFrom::Start(dst_e.offset_range.1 - u64::from(DIR_ENTRY_SIZE)))?;
+        e.data.renamed_as(&dst_e.data).serialize(&mut stream)?;
+        dst_dir.mark_modified();
+        // free source long and short name entries
+        let num = ((e.offset_range.1 - e.offset_range.0) / u64::from(DIR_ENTRY_SIZE)) as u32;
+        self.delete_entries(e.offset_range.0, num)?;
+        // free data of the replaced file
+        if let Some(n) = dst_e.first_cluster() {
+            self.fs.free_cluster_chain(n)?;
+        }
+        Ok(())
+    }
+
+    /// Restores a deleted directory entry under a new name.
+    ///
+    /// `entry` must be returned by `deleted_entries` called on this directory. `new_name` is a name of the restored
+    /// entry in this directory. Because the FAT chain of a deleted file is lost, its data is assumed to occupy
+    /// consecutive clusters starting from the first cluster of the entry. These clusters are marked as allocated
+    /// again if all of them are still free. Only the first cluster of a deleted directory is restored.
+    /// Note: a free cluster can contain data written after the entry was deleted - this cannot be detected.
+    ///
+    /// # Errors
+    ///
+    /// Errors that can be returned:
+    ///
+    /// * `Error::NotFound` will be returned if `entry` is no longer a deleted entry in this directory.
+    /// * `Error::AlreadyExists` will be returned if an entry named `new_name` exists in this directory.
+    /// * `Error::DataOverwritten` will be returned if any cluster of the entry has been reused.
+    /// * `Error::InvalidFileNameLength` will be returned if `new_name` is empty or too long.
+    /// * `Error::UnsupportedFileNameCharacter` will be returned if `new_name` contains an invalid character.
+    /// * `Error::ReservedFileName` will be returned if `new_name` is a reserved DOS device name and
+    ///   `FsOptions::reject_reserved_names` is enabled.
+    /// * `Error::ReadOnlyFilesystem` will be returned if the filesystem is mounted read-only.
+    /// * `Error::Io` will be returned if the underlying storage object returned an I/O error.
+    pub fn undelete(
+        &self,
+        entry: &DirEntry<'a, IO, TP, OCC>,
+        new_name: &str,
+    ) -> Result<DirEntry<'a, IO, TP, OCC>, Error<IO::Error>> {
+        trace!("Dir::undelete {}", new_name);
+        self.fs.check_writable()?;
+        let _guard = self.fs.dir_locks.write(self.lock_key());
+        // make sure the entry is still deleted and has not been replaced
+        let mut stream = self.stream.clone();
+        stream.seek(SeekFrom::Start(entry.offset_range.0))?;
+        match DirEntryData::deserialize(&mut stream)? {
+            DirEntryData::File(data)
+                if data.is_deleted()
+                    && data.name() == entry.data.name()
+                    && data.first_cluster(self.fs.fat_type()) == entry.first_cluster()
+                    && data.len(true) == entry.data.len(true) => {}
+            _ => return Err(Error::NotFound),
+        }
+        validate_long_name(new_name)?;
+        let short_name = match self.check_for_existence(new_name, None)? {
+            DirEntryOrShortName::DirEntry(_) => return Err(Error::AlreadyExists),
+            DirEntryOrShortName::ShortName(short_name) => short_name,
+        };
+        if let Some(first_cluster) = entry.first_cluster() {
+            let num_clusters = if entry.is_dir() {
+                1
+            } else {
+                self.fs.clusters_from_bytes(entry.len()).max(1)
+            };
+            self.fs.alloc_cluster_range(first_cluster, num_clusters)?;
+        }
+        let entry = self.write_entry(new_name, entry.data.renamed(short_name))?;
+        self.fs.write_through()?;
+        Ok(entry)
+    }
+
+    // Creates a file entry pointing to an existing cluster chain
+    #[cfg(feature = "alloc")]
+    pub(crate) fn create_file_with_chain(
+        &self,
+        name: &str,
+        first_cluster: u32,
+        size: u64,
+    ) -> Result<(), Error<IO::Error>> {
+        trace!("Dir::create_file_with_chain {} {}", name, first_cluster);
+        let _guard = self.fs.dir_locks.write(self.lock_key());
+        let short_name = match self.check_for_existence(name, None)? {
+            DirEntryOrShortName::DirEntry(_) => return Err(Error::AlreadyExists),
+            DirEntryOrShortName::ShortName(short_name) => short_name,
+        };
+        let mut sfn_entry = self.create_sfn_entry(short_name, FileAttributes::ARCHIVE, Some(first_cluster));
+        sfn_entry.set_size(size);
+        self.write_entry(name, sfn_entry)?;
         Ok(())
     }
 
@@ -512,11 +1280,21 @@ impl<'a, IO: ReadWriteSeek, TP: TimeProvider, OCC: OemCpConverter> Dir<'a, IO, T
     }
 
     #[cfg(feature = "lfn")]
-    fn encode_lfn_utf16(name: &str) -> LfnBuffer {
+    fn encode_lfn_utf16(name: &str, options: &FsOptions<TP, OCC>) -> LfnBuffer {
+        #[cfg(feature = "normalization")]
+        if options.normalize_names {
+            let utf16_iter = name.nfc().flat_map(|c| {
+                let mut buf = [0_u16; 2];
+                let len = c.encode_utf16(&mut buf).len();
+                buf.into_iter().take(len)
+            });
+            return LfnBuffer::from_ucs2_units(utf16_iter);
+        }
+        let _ = options;
         LfnBuffer::from_ucs2_units(name.encode_utf16())
     }
     #[cfg(not(feature = "lfn"))]
-    fn encode_lfn_utf16(_name: &str) -> LfnBuffer {
+    fn encode_lfn_utf16(_name: &str, _options: &FsOptions<TP, OCC>) -> LfnBuffer {
         LfnBuffer {}
     }
 
@@ -551,22 +1329,35 @@ impl<'a, IO: ReadWriteSeek, TP: TimeProvider, OCC: OemCpConverter> Dir<'a, IO, T
     fn write_entry(
         &self,
         name: &str,
-        raw_entry: DirFileEntryData,
+        mut raw_entry: DirFileEntryData,
     ) -> Result<DirEntry<'a, IO, TP, OCC>, Error<IO::Error>> {
         trace!("Dir::write_entry {}", name);
         // check if name doesn't contain unsupported characters
         validate_long_name(name)?;
-        // convert long name to UTF-16
-        let lfn_utf16 = Self::encode_lfn_utf16(name);
         // write LFN entries, except for . and .., which need to be at
         // the first two slots and don't need LFNs anyway
-        let (mut stream, start_pos) = if name == "." || name == ".." {
-            self.alloc_sfn_entry()?
-        } else {
+        let is_dot_entry = name == "." || name == "..";
+        let short_name_gen = (!is_dot_entry).then(|| ShortNameGenerator::new(name));
+        // LFN entries are not needed if the short name together with the NT lowercase flags represents the name
+        let write_lfn = self.fs.options.long_name_policy == LongNamePolicy::Create
+            && short_name_gen
+                .as_ref()
+                .map_or(false, |gen| !gen.is_stored_exactly(raw_entry.name()));
+        // convert long name to UTF-16
+        let lfn_utf16 = Self::encode_lfn_utf16(if write_lfn { name } else { "" }, &self.fs.options);
+        let (mut stream, start_pos) = if write_lfn {
             self.alloc_and_write_lfn_entries(&lfn_utf16, raw_entry.name())?
+        } else {
+            if let Some(gen) = short_name_gen {
+                // keep lowercase names without LFN entries
+                let (lowercase_basename, lowercase_ext) = gen.lowercase_parts();
+                raw_entry.set_lowercase_flags(lowercase_basename, lowercase_ext);
+            }
+            self.alloc_sfn_entry()?
         };
         // write short name entry
         raw_entry.serialize(&mut stream)?;
+        self.mark_modified();
         // Get position directory stream after entries were written
         let end_pos = stream.seek(io::SeekFrom::Current(0))?;
         // Get current absolute position on the storage
@@ -587,6 +1378,7 @@ impl<'a, IO: ReadWriteSeek, TP: TimeProvider, OCC: OemCpConverter> Dir<'a, IO, T
             fs: self.fs,
             entry_pos: start_abs_pos,
             offset_range: (start_pos, end_pos),
+            dir_cluster: self.stream.id_cluster(),
         })
     }
 }
@@ -608,15 +1400,31 @@ pub struct DirIter<'a, IO: ReadWriteSeek, TP, OCC> {
     stream: DirRawStream<'a, IO, TP, OCC>,
     fs: &'a FileSystem<IO, TP, OCC>,
     skip_volume: bool,
+    deleted: bool,
+    // Key of the directory lock taken for reading every entry
+    lock_key: Option<u64>,
+    // Registration in the tracker of directory changes made during iteration
+    iteration: TrackedIteration,
     err: bool,
 }
 
 impl<'a, IO: ReadWriteSeek, TP, OCC> DirIter<'a, IO, TP, OCC> {
-    fn new(stream: DirRawStream<'a, IO, TP, OCC>, fs: &'a FileSystem<IO, TP, OCC>, skip_volume: bool) -> Self {
+    fn new(
+        stream: DirRawStream<'a, IO, TP, OCC>,
+        fs: &'a FileSystem<IO, TP, OCC>,
+        skip_volume: bool,
+        deleted: bool,
+        lock: bool,
+    ) -> Self {
+        let lock_key = if lock { Some(stream.lock_key()) } else { None };
+        let iteration = fs.dir_changes.start(stream.lock_key());
         DirIter {
             stream,
             fs,
             skip_volume,
+            deleted,
+            lock_key,
+            iteration,
             err: false,
         }
     }
@@ -624,15 +1432,27 @@ impl<'a, IO: ReadWriteSeek, TP, OCC> DirIter<'a, IO, TP, OCC> {
 
 impl<'a, IO: ReadWriteSeek, TP: TimeProvider, OCC> DirIter<'a, IO, TP, OCC> {
     fn should_skip_entry(&self, raw_entry: &DirEntryData) -> bool {
-        if raw_entry.is_deleted() {
+        if raw_entry.is_deleted() != self.deleted {
             return true;
         }
         match raw_entry {
             DirEntryData::File(sfn_entry) => self.skip_volume && sfn_entry.is_volume(),
-            DirEntryData::Lfn(_) => false,
+            // Order of deleted LFN entries is lost so they are skipped in deleted mode
+            DirEntryData::Lfn(_) => self.deleted,
         }
     }
 
+    // Reports the directory and the offset of an entry which cannot be read, so corruption found deep in
+    // a directory walk can be located
+    fn log_entry_error(&self, offset: u64, err: &Error<IO::Error>) {
+        error!(
+            "reading directory entry at offset {} of directory at cluster {} failed: {:?}",
+            offset,
+            self.stream.id_cluster(),
+            err
+        );
+    }
+
     #[allow(clippy::type_complexity)]
     fn read_dir_entry(&mut self) -> Result<Option<DirEntry<'a, IO, TP, OCC>>, Error<IO::Error>> {
         trace!("DirIter::read_dir_entry");
@@ -640,7 +1460,10 @@ impl<'a, IO: ReadWriteSeek, TP: TimeProvider, OCC> DirIter<'a, IO, TP, OCC> {
         let mut offset = self.stream.seek(SeekFrom::Current(0))?;
         let mut begin_offset = offset;
         loop {
-            let raw_entry = DirEntryData::deserialize(&mut self.stream)?;
+            let raw_entry = DirEntryData::deserialize(&mut self.stream).map_err(|err| {
+                self.log_entry_error(offset, &err);
+                err
+            })?;
             offset += u64::from(DIR_ENTRY_SIZE);
             // Check if this is end of dir
             if raw_entry.is_end() {
@@ -664,7 +1487,17 @@ impl<'a, IO: ReadWriteSeek, TP: TimeProvider, OCC> DirIter<'a, IO, TP, OCC> {
                     // Calculate SFN entry start position on the storage
                     let abs_pos = end_abs_pos - u64::from(DIR_ENTRY_SIZE);
                     // Check if LFN checksum is valid
-                    lfn_builder.validate_chksum(data.name());
+                    if !lfn_builder.validate_chksum(data.name()) {
+                        match self.fs.options.lfn_checksum_policy {
+                            LfnChecksumPolicy::UseShortName => lfn_builder.clear(),
+                            LfnChecksumPolicy::UseLongName => {}
+                            LfnChecksumPolicy::Error => {
+                                let err = Error::CorruptedFileSystem;
+                                self.log_entry_error(begin_offset, &err);
+                                return Err(err);
+                            }
+                        }
+                    }
                     // Return directory entry
                     let short_name = ShortName::new(data.name());
                     trace!("file entry {:?}", data.name());
@@ -676,6 +1509,7 @@ impl<'a, IO: ReadWriteSeek, TP: TimeProvider, OCC> DirIter<'a, IO, TP, OCC> {
                         fs: self.fs,
                         entry_pos: abs_pos,
                         offset_range: (begin_offset, offset),
+                        dir_cluster: self.stream.id_cluster(),
                     }));
                 }
                 DirEntryData::Lfn(data) => {
@@ -696,10 +1530,19 @@ impl<IO: ReadWriteSeek, TP, OCC> Clone for DirIter<'_, IO, TP, OCC> {
             fs: self.fs,
             err: self.err,
             skip_volume: self.skip_volume,
+            deleted: self.deleted,
+            lock_key: self.lock_key,
+            iteration: self.fs.dir_changes.fork(&self.iteration),
         }
     }
 }
 
+impl<IO: ReadWriteSeek, TP, OCC> Drop for DirIter<'_, IO, TP, OCC> {
+    fn drop(&mut self) {
+        self.fs.dir_changes.stop(&self.iteration);
+    }
+}
+
 impl<'a, IO: ReadWriteSeek, TP: TimeProvider, OCC> Iterator for DirIter<'a, IO, TP, OCC> {
     type Item = Result<DirEntry<'a, IO, TP, OCC>, Error<IO::Error>>;
 
@@ -707,6 +1550,14 @@ impl<'a, IO: ReadWriteSeek, TP: TimeProvider, OCC> Iterator for DirIter<'a, IO,
         if self.err {
             return None;
         }
+        // An entry with its long name parts is read atomically, concurrent iterators do not block each other
+        let fs = self.fs;
+        let _guard = self.lock_key.map(|key| fs.dir_locks.read(key));
+        // Entries could be skipped or returned twice if the directory has changed since the previous entry was read
+        if fs.dir_changes.is_changed(&self.iteration) {
+            self.err = true;
+            return Some(Err(Error::DirectoryModified));
+        }
         let r = self.read_dir_entry();
         match r {
             Ok(Some(e)) => Some(Ok(e)),
@@ -741,7 +1592,23 @@ fn validate_long_name<E: IoError>(name: &str) -> Result<(), Error<E>> {
     Ok(())
 }
 
-fn lfn_checksum(short_name: &[u8; SFN_SIZE]) -> u8 {
+// Checks if the name is a DOS device name, which Windows reserves also with an extension and trailing spaces
+fn is_reserved_name(name: &str) -> bool {
+    let base_name = name.split('.').next().unwrap_or(name).trim_end_matches(' ');
+    let bytes = base_name.as_bytes();
+    match bytes.len() {
+        3 => ["CON", "PRN", "AUX", "NUL"]
+            .iter()
+            .any(|n| base_name.eq_ignore_ascii_case(n)),
+        4 => {
+            (bytes[..3].eq_ignore_ascii_case(b"COM") || bytes[..3].eq_ignore_ascii_case(b"LPT"))
+                && (b'1'..=b'9').contains(&bytes[3])
+        }
+        _ => false,
+    }
+}
+
+pub(crate) fn lfn_checksum(short_name: &[u8; SFN_SIZE]) -> u8 {
     let mut chksum = num::Wrapping(0_u8);
     for b in short_name {
         chksum = (chksum << 7) + (chksum >> 1) + num::Wrapping(*b);
@@ -757,8 +1624,7 @@ pub(crate) struct LfnBuffer {
 
 const MAX_LONG_NAME_LEN: usize = 255;
 
-#[cfg(feature = "lfn")]
-const MAX_LONG_DIR_ENTRIES: usize = (MAX_LONG_NAME_LEN + LFN_PART_LEN - 1) / LFN_PART_LEN;
+pub(crate) const MAX_LONG_DIR_ENTRIES: usize = (MAX_LONG_NAME_LEN + LFN_PART_LEN - 1) / LFN_PART_LEN;
 
 #[cfg(all(feature = "lfn", not(feature = "alloc")))]
 const LONG_NAME_BUFFER_LEN: usize = MAX_LONG_DIR_ENTRIES * LFN_PART_LEN;
@@ -934,16 +1800,18 @@ impl LongNameBuilder {
         data.copy_name_to_slice(&mut self.buf.ucs2_units[pos..pos + 13]);
     }
 
-    fn validate_chksum(&mut self, short_name: &[u8; SFN_SIZE]) {
+    // Returns false if the checksum of the processed LFN entries does not match the short name
+    fn validate_chksum(&self, short_name: &[u8; SFN_SIZE]) -> bool {
         if self.is_empty() {
             // Nothing to validate - no LFN entries has been processed
-            return;
+            return true;
         }
         let chksum = lfn_checksum(short_name);
         if chksum != self.chksum {
             warn!("checksum mismatch {:x} {:x} {:?}", chksum, self.chksum, short_name);
-            self.clear();
+            return false;
         }
+        true
     }
 }
 
@@ -959,7 +1827,10 @@ impl LongNameBuilder {
     fn into_vec(self) {}
     fn truncate(&mut self) {}
     fn process(&mut self, _data: &DirLfnEntryData) {}
-    fn validate_chksum(&mut self, _short_name: &[u8; SFN_SIZE]) {}
+    #[allow(clippy::unused_self)]
+    fn validate_chksum(&self, _short_name: &[u8; SFN_SIZE]) -> bool {
+        true
+    }
 }
 
 #[cfg(feature = "lfn")]
@@ -1055,7 +1926,10 @@ impl Iterator for LfnEntriesGenerator {
 #[cfg(not(feature = "lfn"))]
 impl ExactSizeIterator for LfnEntriesGenerator {}
 
+const MAX_NUMERIC_TAIL: u32 = 999_999;
+
 #[derive(Default, Debug, Clone)]
+#[allow(clippy::struct_excessive_bools)]
 struct ShortNameGenerator {
     chksum: u16,
     long_prefix_bitmap: u16,
@@ -1063,8 +1937,18 @@ struct ShortNameGenerator {
     name_fits: bool,
     lossy_conv: bool,
     exact_match: bool,
+    // basename and extension consist of lowercase letters
+    lowercase_parts: (bool, bool),
+    // name cannot be restored from the short name and the NT lowercase flags even if it fits (it has mixed case
+    // basename or extension or it ends with a dot)
+    not_restorable: bool,
     basename_len: usize,
     short_name: [u8; SFN_SIZE],
+    tail_policy: ShortNameTailPolicy,
+    // the largest numeric tail of existing names with a matching prefix
+    max_numeric_tail: u32,
+    // name with a custom tail, reset to None if it collides with an existing name
+    custom_name: Option<[u8; SFN_SIZE]>,
 }
 
 impl ShortNameGenerator {
@@ -1083,17 +1967,64 @@ impl ShortNameGenerator {
             let (_, ext_fits, ext_lossy) = Self::copy_short_name_part(&mut short_name[8..11], &name[dot_index + 1..]);
             (basename_fits && ext_fits, basename_lossy || ext_lossy)
         });
+        let is_lowercase =
+            |part: &str| part.chars().any(|c| c.is_ascii_lowercase()) && !part.chars().any(|c| c.is_ascii_uppercase());
+        let is_mixed_case =
+            |part: &str| part.chars().any(|c| c.is_ascii_lowercase()) && part.chars().any(|c| c.is_ascii_uppercase());
+        let ext_src = dot_index_opt.map_or("", |dot_index| &name[dot_index + 1..]);
         let chksum = Self::checksum(name);
         Self {
             chksum,
             name_fits,
             lossy_conv,
+            lowercase_parts: (is_lowercase(basename_src), is_lowercase(ext_src)),
+            not_restorable: is_mixed_case(basename_src) || is_mixed_case(ext_src) || name.ends_with('.'),
             basename_len,
             short_name,
             ..Self::default()
         }
     }
 
+    fn with_tail_policy(mut self, tail_policy: ShortNameTailPolicy) -> Self {
+        self.tail_policy = tail_policy;
+        self
+    }
+
+    fn set_custom_tail<E: IoError>(&mut self, tail: u32) -> Result<(), Error<E>> {
+        if !(1..=MAX_NUMERIC_TAIL).contains(&tail) {
+            error!("invalid short name tail {}", tail);
+            return Err(Error::InvalidInput);
+        }
+        self.custom_name = Some(self.build_numeric_tail_name(tail));
+        Ok(())
+    }
+
+    // Fails if the name cannot be stored as a short name without changes other than case
+    fn check_short_name<E: IoError>(&self) -> Result<(), Error<E>> {
+        if !self.name_fits {
+            error!("name is not a valid 8.3 name - it is too long");
+            return Err(Error::InvalidFileNameLength);
+        }
+        if self.lossy_conv {
+            error!("name is not a valid 8.3 name - it contains unsupported characters");
+            return Err(Error::UnsupportedFileNameCharacter);
+        }
+        Ok(())
+    }
+
+    // Checks if the basename and the extension of a valid short name are lowercase
+    fn lowercase_parts(&self) -> (bool, bool) {
+        if !self.name_fits || self.lossy_conv {
+            return (false, false);
+        }
+        self.lowercase_parts
+    }
+
+    // Checks if the name is stored without changes by the given short name and the NT lowercase flags
+    fn is_stored_exactly(&self, short_name: &[u8; SFN_SIZE]) -> bool {
+        self.name_fits && !self.lossy_conv && !self.not_restorable && short_name == &self.short_name
+    }
+
     fn generate_dot() -> [u8; SFN_SIZE] {
         let mut short_name = [SFN_PADDING; SFN_SIZE];
         short_name[0] = b'.';
@@ -1149,6 +2080,13 @@ impl ShortNameGenerator {
 
         // check for short prefix + checksum form collision (TE021F~1.TXT)
         self.check_for_short_prefix_collision(short_name);
+
+        // check for numeric tail form collision (TEXTF~10.TXT)
+        self.check_for_numeric_tail_collision(short_name);
+
+        if self.custom_name.as_ref() == Some(short_name) {
+            self.custom_name = None;
+        }
     }
 
     fn check_for_long_prefix_collision(&mut self, short_name: &[u8; SFN_SIZE]) {
@@ -1185,6 +2123,25 @@ impl ShortNameGenerator {
         }
     }
 
+    fn check_for_numeric_tail_collision(&mut self, short_name: &[u8; SFN_SIZE]) {
+        if short_name[8..] != self.short_name[8..] {
+            return;
+        }
+        let basename_len = short_name[..8].iter().position(|b| *b == SFN_PADDING).unwrap_or(8);
+        let Some(tilde_index) = short_name[..basename_len].iter().rposition(|b| *b == b'~') else {
+            return;
+        };
+        if tilde_index > self.basename_len || short_name[..tilde_index] != self.short_name[..tilde_index] {
+            return;
+        }
+        let digits = &short_name[tilde_index + 1..basename_len];
+        if digits.is_empty() || !digits.iter().all(u8::is_ascii_digit) {
+            return;
+        }
+        let num_suffix = digits.iter().fold(0, |acc, d| acc * 10 + u32::from(d - b'0'));
+        self.max_numeric_tail = self.max_numeric_tail.max(num_suffix);
+    }
+
     fn checksum(name: &str) -> u16 {
         // BSD checksum algorithm
         let mut chksum = num::Wrapping(0_u16);
@@ -1200,6 +2157,17 @@ impl ShortNameGenerator {
             // 8.3 convention and there is no collision return it as is
             return Ok(self.short_name);
         }
+        match self.tail_policy {
+            ShortNameTailPolicy::Windows => {}
+            ShortNameTailPolicy::Numeric => {
+                let num = self.max_numeric_tail + 1;
+                if num > MAX_NUMERIC_TAIL {
+                    return Err(Error::AlreadyExists);
+                }
+                return Ok(self.build_numeric_tail_name(num));
+            }
+            ShortNameTailPolicy::Custom(_) => return self.custom_name.ok_or(Error::AlreadyExists),
+        }
         // Try using long 6-characters prefix
         for i in 1..5 {
             if self.long_prefix_bitmap & (1 << i) == 0 {
@@ -1242,6 +2210,26 @@ impl ShortNameGenerator {
         buf
     }
 
+    fn build_numeric_tail_name(&self, num: u32) -> [u8; SFN_SIZE] {
+        let mut tail_buf = [0_u8; 7];
+        let mut tail_start = tail_buf.len();
+        let mut rest = num;
+        while rest > 0 {
+            tail_start -= 1;
+            tail_buf[tail_start] = b'0' + (rest % 10) as u8; // SAFE: value is in range [0, 9]
+            rest /= 10;
+        }
+        tail_start -= 1;
+        tail_buf[tail_start] = b'~';
+        let tail = &tail_buf[tail_start..];
+        let prefix_len = (8 - tail.len()).min(self.basename_len);
+        let mut buf = [SFN_PADDING; SFN_SIZE];
+        buf[..prefix_len].copy_from_slice(&self.short_name[..prefix_len]);
+        buf[prefix_len..prefix_len + tail.len()].copy_from_slice(tail);
+        buf[8..].copy_from_slice(&self.short_name[8..]);
+        buf
+    }
+
     fn u16_to_hex(x: u16) -> [u8; 4] {
         // Unwrapping below is safe because each line takes 4 bits of `x` and shifts them to the right so they form
         // a number in range [0, 15]
@@ -1266,6 +2254,17 @@ mod tests {
         assert_eq!(split_path("aaa/bbb/ccc"), ("aaa", Some("bbb/ccc")));
         assert_eq!(split_path("aaa/bbb"), ("aaa", Some("bbb")));
         assert_eq!(split_path("aaa"), ("aaa", None));
+        assert_eq!(split_path("/aaa//bbb/"), ("aaa", Some("/bbb/")));
+        assert_eq!(split_path("aaa/./"), ("aaa", None));
+        assert_eq!(split_path("./aaa"), ("aaa", None));
+        assert_eq!(split_path("aaa/../bbb"), ("bbb", None));
+        assert_eq!(split_path("aaa/bbb/../../ccc/ddd"), ("ccc", Some("ddd")));
+        assert_eq!(split_path("aaa/bbb/.."), ("aaa", None));
+        assert_eq!(split_path("aaa/.."), ("", None));
+        assert_eq!(split_path("../aaa"), ("..", Some("aaa")));
+        assert_eq!(split_path("aaa/../../bbb"), ("..", Some("bbb")));
+        assert_eq!(split_path(""), ("", None));
+        assert_eq!(split_path("/"), ("", None));
     }
 
     #[test]
@@ -1291,6 +2290,38 @@ mod tests {
         assert_eq!(ShortNameGenerator::new(".foo").generate().ok(), Some(*b"FOO~1      "));
     }
 
+    #[test]
+    fn test_short_name_is_stored_exactly() {
+        let is_stored_exactly = |name| {
+            let gen = ShortNameGenerator::new(name);
+            gen.is_stored_exactly(&gen.short_name)
+        };
+        assert!(is_stored_exactly("foo.txt"));
+        assert!(is_stored_exactly("FOO.TXT"));
+        assert!(is_stored_exactly("foo.TXT"));
+        assert!(is_stored_exactly("foo_1"));
+        assert!(!is_stored_exactly("Foo.txt"));
+        assert!(!is_stored_exactly("foo."));
+        assert!(!is_stored_exactly("foo bar.txt"));
+        assert!(!is_stored_exactly("foobarbaz.txt"));
+        assert!(!is_stored_exactly(".foo"));
+        let gen = ShortNameGenerator::new("foo.txt");
+        assert!(!gen.is_stored_exactly(b"FOO~1   TXT"));
+    }
+
+    #[test]
+    fn test_short_name_lowercase_parts() {
+        assert_eq!(ShortNameGenerator::new("foo.txt").lowercase_parts(), (true, true));
+        assert_eq!(ShortNameGenerator::new("Foo.txt").lowercase_parts(), (false, true));
+        assert_eq!(ShortNameGenerator::new("foo_1").lowercase_parts(), (true, false));
+        assert_eq!(ShortNameGenerator::new("123.TXT").lowercase_parts(), (false, false));
+        assert_eq!(ShortNameGenerator::new("foo bar.txt").lowercase_parts(), (false, false));
+        assert_eq!(
+            ShortNameGenerator::new("foobarbaz.txt").lowercase_parts(),
+            (false, false)
+        );
+    }
+
     #[test]
     fn test_short_name_checksum_overflow() {
         ShortNameGenerator::checksum("\u{FF5A}\u{FF5A}\u{FF5A}\u{FF5A}");
@@ -1338,6 +2369,41 @@ mod tests {
         assert_eq!(&buf, b"TE527E~1TXT");
     }
 
+    #[test]
+    fn test_generate_short_name_numeric_tails() {
+        let mut gen = ShortNameGenerator::new("TextFile.Mine.txt").with_tail_policy(ShortNameTailPolicy::Numeric);
+        assert_eq!(&gen.generate().unwrap(), b"TEXTFI~1TXT");
+        gen.add_existing(b"TEXTFI~9TXT");
+        gen.add_existing(b"TEXTFI~3TXT");
+        gen.add_existing(b"TEXTFI~5DOC");
+        assert_eq!(&gen.generate().unwrap(), b"TEXTF~10TXT");
+        gen.add_existing(b"TEXT~123TXT");
+        gen.add_existing(b"TEX~9999TXT");
+        gen.add_existing(b"TE527D~1TXT");
+        assert_eq!(&gen.generate().unwrap(), b"TE~10000TXT");
+        gen.add_existing(b"T~999999TXT");
+        assert!(gen.generate().is_err());
+
+        let mut gen = ShortNameGenerator::new("x y.txt").with_tail_policy(ShortNameTailPolicy::Numeric);
+        gen.add_existing(b"XY~41   TXT");
+        assert_eq!(&gen.generate().unwrap(), b"XY~42   TXT");
+    }
+
+    #[test]
+    fn test_generate_short_name_custom_tails() {
+        let mut gen =
+            ShortNameGenerator::new("a long name.txt").with_tail_policy(ShortNameTailPolicy::Custom(|_, _| 1));
+        gen.set_custom_tail::<()>(1234).unwrap();
+        assert_eq!(&gen.generate().unwrap(), b"ALO~1234TXT");
+        gen.add_existing(b"ALO~1234TXT");
+        assert!(gen.generate().is_err());
+        gen.next_iteration();
+        gen.set_custom_tail::<()>(5).unwrap();
+        assert_eq!(&gen.generate().unwrap(), b"ALONGN~5TXT");
+        assert!(gen.set_custom_tail::<()>(0).is_err());
+        assert!(gen.set_custom_tail::<()>(1_000_000).is_err());
+    }
+
     #[test]
     fn test_generate_short_name_collisions_short() {
         let mut buf: [u8; SFN_SIZE];
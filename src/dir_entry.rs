@@ -6,15 +6,17 @@ use core::convert::TryInto;
 use core::fmt;
 #[cfg(not(feature = "unicode"))]
 use core::iter;
+#[cfg(feature = "normalization")]
+use unicode_normalization::UnicodeNormalization;
 
 #[cfg(feature = "lfn")]
 use crate::dir::LfnBuffer;
 use crate::dir::{Dir, DirRawStream};
 use crate::error::{Error, IoError};
 use crate::file::File;
-use crate::fs::{FatType, FileSystem, OemCpConverter, ReadWriteSeek};
-use crate::io::{self, Read, ReadLeExt, Write, WriteLeExt};
-use crate::time::{Date, DateTime};
+use crate::fs::{decode_oem_name, FatType, FileSystem, OemCpConverter, ReadWriteSeek};
+use crate::io::{self, Read, ReadLeExt, Seek, Write, WriteLeExt};
+use crate::time::{Date, DateTime, Time};
 
 bitflags! {
     /// A FAT file attributes.
@@ -31,6 +33,19 @@ bitflags! {
     }
 }
 
+#[cfg(feature = "defmt")]
+impl defmt::Format for FileAttributes {
+    fn format(&self, f: defmt::Formatter) {
+        defmt::write!(f, "FileAttributes({=u8:#x})", self.bits());
+    }
+}
+
+// File attributes that can be changed without changing the directory entry type
+const SETTABLE_ATTRIBUTES: FileAttributes = FileAttributes::READ_ONLY
+    .union(FileAttributes::HIDDEN)
+    .union(FileAttributes::SYSTEM)
+    .union(FileAttributes::ARCHIVE);
+
 // Size of single directory entry in bytes
 pub(crate) const DIR_ENTRY_SIZE: u32 = 32;
 
@@ -48,19 +63,56 @@ pub(crate) const SFN_PADDING: u8 = b' ';
 pub(crate) const LFN_PART_LEN: usize = 13;
 
 // Bit used in order field to mark last LFN entry
-#[cfg(feature = "lfn")]
 pub(crate) const LFN_ENTRY_LAST_FLAG: u8 = 0x40;
 
+// Bits of the reserved byte used by FAT+ for bits 32-34 (bits 0-2) and 35-37 (bits 5-7) of the file size
+const FAT_PLUS_SIZE_MASK: u8 = 0xE7;
+
+/// Size in bytes of a buffer always big enough for `DirEntry::short_file_name_to_buf`.
+pub const MAX_SHORT_FILE_NAME_UTF8_LEN: usize = 12 * 4;
+
+/// Size in bytes of a buffer always big enough for `DirEntry::file_name_to_buf`.
+///
+/// A long name occupies up to 20 LFN entries of 13 UCS-2 units and each unit takes at most 3 bytes in UTF-8.
+pub const MAX_FILE_NAME_UTF8_LEN: usize = 20 * LFN_PART_LEN * 3;
+
+// Encodes characters in UTF-8 into `buf`, returns `None` if they do not fit
+fn encode_utf8_to_buf<I: Iterator<Item = char>>(char_iter: I, buf: &mut [u8]) -> Option<&str> {
+    let mut len = 0;
+    for c in char_iter {
+        let char_len = c.len_utf8();
+        if len + char_len > buf.len() {
+            return None;
+        }
+        c.encode_utf8(&mut buf[len..]);
+        len += char_len;
+    }
+    core::str::from_utf8(&buf[..len]).ok()
+}
+
 // Character to upper case conversion which supports Unicode only if `unicode` feature is enabled
 #[cfg(feature = "unicode")]
-fn char_to_uppercase(c: char) -> char::ToUppercase {
+pub(crate) fn char_to_uppercase(c: char) -> char::ToUppercase {
     c.to_uppercase()
 }
 #[cfg(not(feature = "unicode"))]
-fn char_to_uppercase(c: char) -> iter::Once<char> {
+pub(crate) fn char_to_uppercase(c: char) -> iter::Once<char> {
     iter::once(c.to_ascii_uppercase())
 }
 
+pub(crate) fn chars_eq<I: Iterator<Item = char>, J: Iterator<Item = char>>(
+    first: I,
+    second: J,
+    ignore_case: bool,
+) -> bool {
+    if ignore_case {
+        // compare each character in uppercase
+        first.flat_map(char_to_uppercase).eq(second.flat_map(char_to_uppercase))
+    } else {
+        first.eq(second)
+    }
+}
+
 /// Decoded file short name
 #[derive(Clone, Debug, Default)]
 pub(crate) struct ShortName {
@@ -105,20 +157,19 @@ impl ShortName {
         &self.name[..usize::from(self.len)]
     }
 
+    fn to_str_buf<'b, OCC: OemCpConverter>(&self, oem_cp_converter: &OCC, buf: &'b mut [u8]) -> Option<&'b str> {
+        let char_iter = decode_oem_name(self.as_bytes(), oem_cp_converter);
+        encode_utf8_to_buf(char_iter, buf)
+    }
+
     #[cfg(feature = "alloc")]
-    fn to_string<OCC: OemCpConverter>(&self, oem_cp_converter: &OCC) -> String {
-        // Strip non-ascii characters from short name
-        self.as_bytes()
-            .iter()
-            .copied()
-            .map(|c| oem_cp_converter.decode(c))
-            .collect()
+    pub(crate) fn to_string<OCC: OemCpConverter>(&self, oem_cp_converter: &OCC) -> String {
+        decode_oem_name(self.as_bytes(), oem_cp_converter).collect()
     }
 
     fn eq_ignore_case<OCC: OemCpConverter>(&self, name: &str, oem_cp_converter: &OCC) -> bool {
         // Convert name to UTF-8 character iterator
-        let byte_iter = self.as_bytes().iter().copied();
-        let char_iter = byte_iter.map(|c| oem_cp_converter.decode(c));
+        let char_iter = decode_oem_name(self.as_bytes(), oem_cp_converter);
         // Compare interators ignoring case
         let uppercase_char_iter = char_iter.flat_map(char_to_uppercase);
         uppercase_char_iter.eq(name.chars().flat_map(char_to_uppercase))
@@ -145,7 +196,7 @@ pub(crate) struct DirFileEntryData {
 impl DirFileEntryData {
     pub(crate) fn new(name: [u8; SFN_SIZE], attrs: FileAttributes) -> Self {
         Self {
-            name,
+            name: Self::escape_deleted_flag(name),
             attrs,
             ..Self::default()
         }
@@ -153,26 +204,54 @@ impl DirFileEntryData {
 
     pub(crate) fn renamed(&self, new_name: [u8; SFN_SIZE]) -> Self {
         let mut sfn_entry = self.clone();
-        sfn_entry.name = new_name;
+        sfn_entry.name = Self::escape_deleted_flag(new_name);
+        sfn_entry
+    }
+
+    // Returns a copy of this entry with the name and the lowercase flags of `other` entry
+    pub(crate) fn renamed_as(&self, other: &Self) -> Self {
+        let mut sfn_entry = self.clone();
+        sfn_entry.name = other.name;
+        sfn_entry.set_lowercase_flags(other.lowercase_basename(), other.lowercase_ext());
         sfn_entry
     }
 
+    fn escape_deleted_flag(mut name: [u8; SFN_SIZE]) -> [u8; SFN_SIZE] {
+        // 0xE5 is a valid lead byte in some double-byte code pages (e.g. Shift-JIS) and must be stored as 0x05
+        if name[0] == DIR_ENTRY_DELETED_FLAG {
+            name[0] = DIR_ENTRY_REALLY_E5_FLAG;
+        }
+        name
+    }
+
     pub(crate) fn name(&self) -> &[u8; SFN_SIZE] {
         &self.name
     }
 
-    #[cfg(feature = "alloc")]
-    fn lowercase_name(&self) -> ShortName {
+    fn lowercase_name<OCC: OemCpConverter>(&self, oem_cp_converter: &OCC) -> ShortName {
         let mut name_copy: [u8; SFN_SIZE] = self.name;
         if self.lowercase_basename() {
-            name_copy[..8].make_ascii_lowercase();
+            Self::make_part_lowercase(&mut name_copy[..8], oem_cp_converter);
         }
         if self.lowercase_ext() {
-            name_copy[8..].make_ascii_lowercase();
+            Self::make_part_lowercase(&mut name_copy[8..], oem_cp_converter);
         }
         ShortName::new(&name_copy)
     }
 
+    fn make_part_lowercase<OCC: OemCpConverter>(part: &mut [u8], oem_cp_converter: &OCC) {
+        let mut i = 0;
+        while i < part.len() {
+            if oem_cp_converter.is_lead_byte(part[i]) {
+                // trail byte of a double-byte character can be in ASCII range and must be left intact
+                i += 2;
+            } else {
+                part[i].make_ascii_lowercase();
+                i += 1;
+            }
+        }
+    }
+
     pub(crate) fn first_cluster(&self, fat_type: FatType) -> Option<u32> {
         let first_cluster_hi = if fat_type == FatType::Fat32 {
             self.first_cluster_hi
@@ -195,16 +274,42 @@ impl DirFileEntryData {
         self.first_cluster_lo = (n & 0xFFFF) as u16;
     }
 
-    pub(crate) fn size(&self) -> Option<u32> {
+    // Returns the size field - upper bits stored in the reserved byte are used only if `fat_plus` is true
+    pub(crate) fn len(&self, fat_plus: bool) -> u64 {
+        if fat_plus {
+            let hi = u64::from(self.reserved_0 & 0x07) | (u64::from(self.reserved_0 >> 5) << 3);
+            (hi << 32) | u64::from(self.size)
+        } else {
+            u64::from(self.size)
+        }
+    }
+
+    pub(crate) fn size(&self, fat_plus: bool) -> Option<u64> {
         if self.is_file() {
-            Some(self.size)
+            Some(self.len(fat_plus))
         } else {
             None
         }
     }
 
-    fn set_size(&mut self, size: u32) {
-        self.size = size;
+    // Note: upper bits are cleared for sizes fitting in 32 bits, so the entry is valid for non FAT+ implementations
+    pub(crate) fn set_size(&mut self, size: u64) {
+        let hi = (size >> 32) as u8;
+        self.size = size as u32;
+        self.reserved_0 = (self.reserved_0 & !FAT_PLUS_SIZE_MASK) | (hi & 0x07) | ((hi & 0x38) << 2);
+    }
+
+    fn attributes(&self) -> FileAttributes {
+        self.attrs
+    }
+
+    pub(crate) fn is_read_only(&self) -> bool {
+        self.attrs.contains(FileAttributes::READ_ONLY)
+    }
+
+    fn set_attributes(&mut self, attrs: FileAttributes) {
+        // Note: only attributes that do not change the entry type are modified
+        self.attrs = (attrs & SETTABLE_ATTRIBUTES) | (self.attrs - SETTABLE_ATTRIBUTES);
     }
 
     pub(crate) fn is_dir(&self) -> bool {
@@ -215,6 +320,16 @@ impl DirFileEntryData {
         !self.is_dir()
     }
 
+    pub(crate) fn set_lowercase_flags(&mut self, lowercase_basename: bool, lowercase_ext: bool) {
+        self.reserved_0 &= !((1 << 3) | (1 << 4));
+        if lowercase_basename {
+            self.reserved_0 |= 1 << 3;
+        }
+        if lowercase_ext {
+            self.reserved_0 |= 1 << 4;
+        }
+    }
+
     fn lowercase_basename(&self) -> bool {
         self.reserved_0 & (1 << 3) != 0
     }
@@ -251,6 +366,23 @@ impl DirFileEntryData {
         self.modify_time = date_time.time.encode().0;
     }
 
+    // Copies timestamps into a serialized entry if it still belongs to the same file
+    pub(crate) fn apply_timestamps(&self, raw: &mut [u8; DIR_ENTRY_SIZE as usize]) -> bool {
+        if raw[..SFN_SIZE] != self.name
+            || raw[20..22] != self.first_cluster_hi.to_le_bytes()
+            || raw[26..28] != self.first_cluster_lo.to_le_bytes()
+        {
+            return false;
+        }
+        raw[13] = self.create_time_0;
+        raw[14..16].copy_from_slice(&self.create_time_1.to_le_bytes());
+        raw[16..18].copy_from_slice(&self.create_date.to_le_bytes());
+        raw[18..20].copy_from_slice(&self.access_date.to_le_bytes());
+        raw[22..24].copy_from_slice(&self.modify_time.to_le_bytes());
+        raw[24..26].copy_from_slice(&self.modify_date.to_le_bytes());
+        true
+    }
+
     pub(crate) fn serialize<W: Write>(&self, wrt: &mut W) -> Result<(), W::Error> {
         wrt.write_all(&self.name)?;
         wrt.write_u8(self.attrs.bits())?;
@@ -459,21 +591,54 @@ pub(crate) struct DirEntryEditor {
     data: DirFileEntryData,
     pos: u64,
     dirty: bool,
+    // Only timestamps were changed - the write can be deferred
+    timestamps_dirty: bool,
+    // Entry does not exist on disk (file opened by its first cluster) and is never written
+    detached: bool,
+    // ID reported in change events, `None` for detached entries
+    id: Option<EntryId>,
 }
 
 impl DirEntryEditor {
-    fn new(data: DirFileEntryData, pos: u64) -> Self {
+    pub(crate) fn new(data: DirFileEntryData, pos: u64, id: Option<EntryId>) -> Self {
         Self {
             data,
             pos,
             dirty: false,
+            timestamps_dirty: false,
+            detached: false,
+            id,
+        }
+    }
+
+    pub(crate) fn detached(data: DirFileEntryData) -> Self {
+        Self {
+            detached: true,
+            ..Self::new(data, 0, None)
         }
     }
 
+    pub(crate) fn id(&self) -> Option<EntryId> {
+        self.id
+    }
+
+    pub(crate) fn is_detached(&self) -> bool {
+        self.detached
+    }
+
     pub(crate) fn inner(&self) -> &DirFileEntryData {
         &self.data
     }
 
+    pub(crate) fn pos(&self) -> u64 {
+        self.pos
+    }
+
+    // Returns `true` if fields other than timestamps were changed
+    pub(crate) fn is_dirty(&self) -> bool {
+        self.dirty
+    }
+
     pub(crate) fn set_first_cluster(&mut self, first_cluster: Option<u32>, fat_type: FatType) {
         if first_cluster != self.data.first_cluster(fat_type) {
             self.data.set_first_cluster(first_cluster, fat_type);
@@ -481,8 +646,8 @@ impl DirEntryEditor {
         }
     }
 
-    pub(crate) fn set_size(&mut self, size: u32) {
-        match self.data.size() {
+    pub(crate) fn set_size(&mut self, size: u64) {
+        match self.data.size(true) {
             Some(n) if size != n => {
                 self.data.set_size(size);
                 self.dirty = true;
@@ -491,39 +656,221 @@ impl DirEntryEditor {
         }
     }
 
+    pub(crate) fn set_attributes(&mut self, attrs: FileAttributes) {
+        let old_attrs = self.data.attributes();
+        self.data.set_attributes(attrs);
+        if self.data.attributes() != old_attrs {
+            self.dirty = true;
+        }
+    }
+
     pub(crate) fn set_created(&mut self, date_time: DateTime) {
         if date_time != self.data.created() {
             self.data.set_created(date_time);
-            self.dirty = true;
+            self.timestamps_dirty = true;
         }
     }
 
     pub(crate) fn set_accessed(&mut self, date: Date) {
         if date != self.data.accessed() {
             self.data.set_accessed(date);
-            self.dirty = true;
+            self.timestamps_dirty = true;
         }
     }
 
     pub(crate) fn set_modified(&mut self, date_time: DateTime) {
         if date_time != self.data.modified() {
             self.data.set_modified(date_time);
-            self.dirty = true;
+            self.timestamps_dirty = true;
         }
     }
 
-    pub(crate) fn flush<IO: ReadWriteSeek, TP, OCC>(&mut self, fs: &FileSystem<IO, TP, OCC>) -> Result<(), IO::Error> {
-        if self.dirty {
+    pub(crate) fn flush<IO: ReadWriteSeek, TP, OCC>(
+        &mut self,
+        fs: &FileSystem<IO, TP, OCC>,
+    ) -> Result<(), Error<IO::Error>> {
+        if self.detached {
+            // nothing to write
+        } else if self.dirty {
+            fs.check_writable()?;
+            fs.discard_deferred_timestamps(self.pos);
             self.write(fs)?;
-            self.dirty = false;
+        } else if self.timestamps_dirty {
+            fs.check_writable()?;
+            if !(fs.options.lazy_timestamps && fs.defer_timestamps(self.pos, &self.data)) {
+                self.write(fs)?;
+            }
         }
+        self.dirty = false;
+        self.timestamps_dirty = false;
         Ok(())
     }
 
+    // Writes the entry including changed timestamps - their write is never deferred
+    pub(crate) fn flush_all<IO: ReadWriteSeek, TP, OCC>(
+        &mut self,
+        fs: &FileSystem<IO, TP, OCC>,
+    ) -> Result<(), Error<IO::Error>> {
+        self.dirty |= self.timestamps_dirty;
+        self.flush(fs)
+    }
+
     fn write<IO: ReadWriteSeek, TP, OCC>(&self, fs: &FileSystem<IO, TP, OCC>) -> Result<(), IO::Error> {
-        let mut disk = fs.disk.borrow_mut();
+        let mut disk = fs.disk().lock();
         disk.seek(io::SeekFrom::Start(self.pos))?;
-        self.data.serialize(&mut *disk)
+        self.data.serialize(&mut *disk)?;
+        fs.observe(|o| o.on_storage_write(self.pos, DIR_ENTRY_SIZE as usize));
+        Ok(())
+    }
+}
+
+/// Metadata information about a file or a directory.
+///
+/// This structure is returned by the `metadata` methods on `File`, `Dir`, `DirEntry` and `FileSystem`.
+#[derive(Clone, Debug)]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+pub struct Metadata {
+    attrs: FileAttributes,
+    len: u64,
+    created: DateTime,
+    accessed: Date,
+    modified: DateTime,
+    first_cluster: Option<u32>,
+    allocated_size: u64,
+}
+
+impl Metadata {
+    pub(crate) fn from_entry_data(
+        data: &DirFileEntryData,
+        fat_type: FatType,
+        fat_plus: bool,
+        allocated_size: u64,
+    ) -> Self {
+        Self {
+            attrs: data.attrs,
+            len: data.len(fat_plus),
+            created: data.created(),
+            accessed: data.accessed(),
+            modified: data.modified(),
+            first_cluster: data.first_cluster(fat_type),
+            allocated_size,
+        }
+    }
+
+    pub(crate) fn root_dir(first_cluster: Option<u32>, allocated_size: u64) -> Self {
+        // Note: root directory has no entry so timestamps are set to the start of the FAT epoch
+        let epoch = DateTime::new(Date::new(1980, 1, 1), Time::new(0, 0, 0, 0));
+        Self {
+            attrs: FileAttributes::DIRECTORY,
+            len: 0,
+            created: epoch,
+            accessed: epoch.date,
+            modified: epoch,
+            first_cluster,
+            allocated_size,
+        }
+    }
+
+    /// Returns file attributes.
+    #[must_use]
+    pub fn attributes(&self) -> FileAttributes {
+        self.attrs
+    }
+
+    /// Checks if metadata belongs to a directory.
+    #[must_use]
+    pub fn is_dir(&self) -> bool {
+        self.attrs.contains(FileAttributes::DIRECTORY)
+    }
+
+    /// Checks if metadata belongs to a regular file.
+    #[must_use]
+    pub fn is_file(&self) -> bool {
+        !self.is_dir()
+    }
+
+    /// Returns file size or 0 for directory.
+    #[must_use]
+    #[allow(clippy::len_without_is_empty)]
+    pub fn len(&self) -> u64 {
+        self.len
+    }
+
+    /// Returns file creation date and time.
+    ///
+    /// Resolution of the time field is 1/100s.
+    #[must_use]
+    pub fn created(&self) -> DateTime {
+        self.created
+    }
+
+    /// Returns file last access date.
+    #[must_use]
+    pub fn accessed(&self) -> Date {
+        self.accessed
+    }
+
+    /// Returns file last modification date and time.
+    ///
+    /// Resolution of the time field is 2s.
+    #[must_use]
+    pub fn modified(&self) -> DateTime {
+        self.modified
+    }
+
+    /// Returns first cluster of the file data or `None` if no cluster is allocated.
+    ///
+    /// Root directory of FAT12/FAT16 volumes is stored outside of the data area and has no first cluster.
+    #[must_use]
+    pub fn first_cluster(&self) -> Option<u32> {
+        self.first_cluster
+    }
+
+    /// Returns number of bytes occupied on disk by the file data.
+    ///
+    /// This is the size of the cluster chain so it is a multiple of the cluster size.
+    #[must_use]
+    pub fn allocated_size(&self) -> u64 {
+        self.allocated_size
+    }
+}
+
+/// An identifier of a directory entry.
+///
+/// It is made of the first cluster of the parent directory and the offset of the entry inside that directory, so
+/// the entry can be opened again by `FileSystem::entry_by_id` without walking its path. The ID is valid as long as
+/// the entry stays in place. Renaming or moving an entry writes a new entry with a new ID and the old ID can be
+/// reused by entries created later.
+#[derive(Copy, Clone, Debug, PartialEq, Eq, Hash, PartialOrd, Ord)]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+pub struct EntryId {
+    dir_cluster: u32,
+    offset: u32,
+}
+
+impl EntryId {
+    pub(crate) fn new(dir_cluster: u32, offset: u32) -> Self {
+        Self { dir_cluster, offset }
+    }
+
+    pub(crate) fn dir_cluster(self) -> u32 {
+        self.dir_cluster
+    }
+
+    pub(crate) fn offset(self) -> u32 {
+        self.offset
+    }
+
+    /// Returns the ID packed into a single `u64` value, e.g. to be used as an inode number.
+    #[must_use]
+    pub fn to_u64(self) -> u64 {
+        (u64::from(self.dir_cluster) << 32) | u64::from(self.offset)
+    }
+
+    /// Creates an ID from a value returned by `to_u64`.
+    #[must_use]
+    pub fn from_u64(value: u64) -> Self {
+        Self::new((value >> 32) as u32, value as u32)
     }
 }
 
@@ -538,6 +885,7 @@ pub struct DirEntry<'a, IO: ReadWriteSeek, TP, OCC> {
     pub(crate) lfn_utf16: LfnBuffer,
     pub(crate) entry_pos: u64,
     pub(crate) offset_range: (u64, u64),
+    pub(crate) dir_cluster: u32,
     pub(crate) fs: &'a FileSystem<IO, TP, OCC>,
 }
 
@@ -545,7 +893,9 @@ pub struct DirEntry<'a, IO: ReadWriteSeek, TP, OCC> {
 impl<'a, IO: ReadWriteSeek, TP, OCC: OemCpConverter> DirEntry<'a, IO, TP, OCC> {
     /// Returns short file name.
     ///
-    /// Non-ASCII characters are replaced by the replacement character (U+FFFD).
+    /// It is the 8.3 alias stored in the directory entry, also for entries with a long name. Use
+    /// `Dir::short_name_for` to find out the alias a new entry would get. Non-ASCII characters are replaced by
+    /// the replacement character (U+FFFD).
     #[cfg(feature = "alloc")]
     #[must_use]
     pub fn short_file_name(&self) -> String {
@@ -585,7 +935,41 @@ impl<'a, IO: ReadWriteSeek, TP, OCC: OemCpConverter> DirEntry<'a, IO, TP, OCC> {
             }
         }
 
-        self.data.lowercase_name().to_string(&self.fs.options.oem_cp_converter)
+        self.data
+            .lowercase_name(&self.fs.options.oem_cp_converter)
+            .to_string(&self.fs.options.oem_cp_converter)
+    }
+
+    /// Writes short file name encoded in UTF-8 to `buf` and returns it as a string slice.
+    ///
+    /// This is an alternative to `short_file_name` not requiring the `alloc` feature. Non-ASCII characters are
+    /// replaced by the replacement character (U+FFFD). Returns `None` if `buf` is too small. A buffer of
+    /// `MAX_SHORT_FILE_NAME_UTF8_LEN` bytes is always big enough.
+    #[must_use]
+    pub fn short_file_name_to_buf<'b>(&self, buf: &'b mut [u8]) -> Option<&'b str> {
+        self.short_name.to_str_buf(&self.fs.options.oem_cp_converter, buf)
+    }
+
+    /// Writes long file name or if it doesn't exist short file name encoded in UTF-8 to `buf` and returns it as
+    /// a string slice.
+    ///
+    /// This is an alternative to `file_name` not requiring the `alloc` feature. Returns `None` if `buf` is too
+    /// small. A buffer of `MAX_FILE_NAME_UTF8_LEN` bytes is always big enough.
+    #[must_use]
+    pub fn file_name_to_buf<'b>(&self, buf: &'b mut [u8]) -> Option<&'b str> {
+        #[cfg(feature = "lfn")]
+        {
+            let lfn_opt = self.long_file_name_as_ucs2_units();
+            if let Some(lfn) = lfn_opt {
+                let char_iter =
+                    char::decode_utf16(lfn.iter().copied()).map(|r| r.unwrap_or(char::REPLACEMENT_CHARACTER));
+                return encode_utf8_to_buf(char_iter, buf);
+            }
+        }
+
+        self.data
+            .lowercase_name(&self.fs.options.oem_cp_converter)
+            .to_str_buf(&self.fs.options.oem_cp_converter, buf)
     }
 
     /// Returns file attributes.
@@ -594,6 +978,59 @@ impl<'a, IO: ReadWriteSeek, TP, OCC: OemCpConverter> DirEntry<'a, IO, TP, OCC> {
         self.data.attrs
     }
 
+    /// Sets file attributes and writes them to the directory entry.
+    ///
+    /// Only `READ_ONLY`, `HIDDEN`, `SYSTEM` and `ARCHIVE` attributes can be changed. Other bits in `attrs` are
+    /// ignored, so the entry type (file, directory or volume label) is always preserved.
+    ///
+    /// # Errors
+    ///
+    /// `Error::Io` will be returned if the underlying storage object returned an I/O error.
+    pub fn set_attributes(&mut self, attrs: FileAttributes) -> Result<(), Error<IO::Error>> {
+        trace!("DirEntry::set_attributes {:?}", attrs);
+        self.edit(|e| e.set_attributes(attrs))
+    }
+
+    /// Sets date and time of creation and writes it to the directory entry.
+    ///
+    /// # Errors
+    ///
+    /// `Error::Io` will be returned if the underlying storage object returned an I/O error.
+    pub fn set_created(&mut self, date_time: DateTime) -> Result<(), Error<IO::Error>> {
+        trace!("DirEntry::set_created {:?}", date_time);
+        self.edit(|e| e.set_created(date_time))
+    }
+
+    /// Sets date of last access and writes it to the directory entry.
+    ///
+    /// # Errors
+    ///
+    /// `Error::Io` will be returned if the underlying storage object returned an I/O error.
+    pub fn set_accessed(&mut self, date: Date) -> Result<(), Error<IO::Error>> {
+        trace!("DirEntry::set_accessed {:?}", date);
+        self.edit(|e| e.set_accessed(date))
+    }
+
+    /// Sets date and time of last modification and writes it to the directory entry.
+    ///
+    /// Resolution of the time field is 2s.
+    ///
+    /// # Errors
+    ///
+    /// `Error::Io` will be returned if the underlying storage object returned an I/O error.
+    pub fn set_modified(&mut self, date_time: DateTime) -> Result<(), Error<IO::Error>> {
+        trace!("DirEntry::set_modified {:?}", date_time);
+        self.edit(|e| e.set_modified(date_time))
+    }
+
+    pub(crate) fn edit<F: FnOnce(&mut DirEntryEditor)>(&mut self, f: F) -> Result<(), Error<IO::Error>> {
+        let mut editor = self.editor();
+        f(&mut editor);
+        editor.flush(self.fs)?;
+        self.data = editor.data;
+        Ok(())
+    }
+
     /// Checks if entry belongs to directory.
     #[must_use]
     pub fn is_dir(&self) -> bool {
@@ -606,16 +1043,52 @@ impl<'a, IO: ReadWriteSeek, TP, OCC: OemCpConverter> DirEntry<'a, IO, TP, OCC> {
         self.data.is_file()
     }
 
-    pub(crate) fn first_cluster(&self) -> Option<u32> {
+    /// Returns an identifier of this entry which can be passed to `FileSystem::entry_by_id`.
+    ///
+    /// See `EntryId` for details about its lifetime.
+    #[must_use]
+    pub fn id(&self) -> EntryId {
+        // Note: directories cannot be larger than 65536 entries so the offset always fits in u32
+        EntryId::new(self.dir_cluster, self.offset_range.0 as u32)
+    }
+
+    /// Returns the first cluster of the entry data or `None` if no cluster is allocated (e.g. for an empty file).
+    ///
+    /// For the `..` entry of a subdirectory of the root directory it is `None`, as stored on the volume.
+    #[must_use]
+    pub fn first_cluster(&self) -> Option<u32> {
         self.data.first_cluster(self.fs.fat_type())
     }
 
-    fn editor(&self) -> DirEntryEditor {
-        DirEntryEditor::new(self.data.clone(), self.entry_pos)
+    /// Returns the number of clusters allocated for the entry data.
+    ///
+    /// The cluster chain is read from the FAT, but no `File` object is created.
+    ///
+    /// # Errors
+    ///
+    /// `Error::Io` will be returned if the underlying storage object returned an I/O error.
+    pub fn cluster_count(&self) -> Result<u32, Error<IO::Error>> {
+        self.fs.cluster_count(self.first_cluster())
+    }
+
+    /// Returns the number of bytes occupied on disk by the entry data.
+    ///
+    /// It is the number of allocated clusters multiplied by the cluster size, so it is not smaller than the file
+    /// size. The cluster chain is read from the FAT, but no `File` object is created.
+    ///
+    /// # Errors
+    ///
+    /// `Error::Io` will be returned if the underlying storage object returned an I/O error.
+    pub fn allocated_size(&self) -> Result<u64, Error<IO::Error>> {
+        self.fs.allocated_size(self.first_cluster())
+    }
+
+    pub(crate) fn editor(&self) -> DirEntryEditor {
+        DirEntryEditor::new(self.data.clone(), self.entry_pos, Some(self.id()))
     }
 
-    pub(crate) fn is_same_entry(&self, other: &DirEntry<IO, TP, OCC>) -> bool {
-        self.entry_pos == other.entry_pos
+    pub(crate) fn is_same_entry<IO2: ReadWriteSeek, TP2, OCC2>(&self, other: &DirEntry<IO2, TP2, OCC2>) -> bool {
+        self.fs.is_same_fs(other.fs) && self.entry_pos == other.entry_pos
     }
 
     /// Returns `File` struct for this entry.
@@ -649,7 +1122,7 @@ impl<'a, IO: ReadWriteSeek, TP, OCC: OemCpConverter> DirEntry<'a, IO, TP, OCC> {
     /// Returns file size or 0 for directory.
     #[must_use]
     pub fn len(&self) -> u64 {
-        u64::from(self.data.size)
+        self.data.len(self.fs.options.fat_plus)
     }
 
     /// Returns file creation date and time.
@@ -674,36 +1147,53 @@ impl<'a, IO: ReadWriteSeek, TP, OCC: OemCpConverter> DirEntry<'a, IO, TP, OCC> {
         self.data.modified()
     }
 
+    /// Returns metadata of this entry.
+    ///
+    /// # Errors
+    ///
+    /// `Error::Io` will be returned if the underlying storage object returned an I/O error.
+    pub fn metadata(&self) -> Result<Metadata, Error<IO::Error>> {
+        let allocated_size = self.fs.allocated_size(self.first_cluster())?;
+        Ok(Metadata::from_entry_data(
+            &self.data,
+            self.fs.fat_type(),
+            self.fs.options.fat_plus,
+            allocated_size,
+        ))
+    }
+
     pub(crate) fn raw_short_name(&self) -> &[u8; SFN_SIZE] {
         &self.data.name
     }
 
+    fn eq_name_chars<I: Iterator<Item = char>>(&self, self_char_iter: I, name: &str) -> bool {
+        let ignore_case = !self.fs.options.case_sensitive;
+        #[cfg(feature = "normalization")]
+        if self.fs.options.normalize_names {
+            return chars_eq(self_char_iter.nfc(), name.nfc(), ignore_case);
+        }
+        chars_eq(self_char_iter, name.chars(), ignore_case)
+    }
+
     #[cfg(feature = "lfn")]
     fn eq_name_lfn(&self, name: &str) -> bool {
         if let Some(lfn) = self.long_file_name_as_ucs2_units() {
-            let self_decode_iter = char::decode_utf16(lfn.iter().copied());
-            let mut other_uppercase_iter = name.chars().flat_map(char_to_uppercase);
-            for decode_result in self_decode_iter {
-                if let Ok(self_char) = decode_result {
-                    for self_uppercase_char in char_to_uppercase(self_char) {
-                        // compare each character in uppercase
-                        if Some(self_uppercase_char) != other_uppercase_iter.next() {
-                            return false;
-                        }
-                    }
-                } else {
-                    // decoding failed
-                    return false;
-                }
+            if char::decode_utf16(lfn.iter().copied()).any(|r| r.is_err()) {
+                // decoding failed
+                return false;
             }
-            // both iterators should be at the end here
-            other_uppercase_iter.next().is_none()
+            let self_char_iter = char::decode_utf16(lfn.iter().copied()).filter_map(Result::ok);
+            self.eq_name_chars(self_char_iter, name)
         } else {
             // entry has no long name
             false
         }
     }
 
+    pub(crate) fn eq_short_name(&self, name: &str) -> bool {
+        self.short_name.eq_ignore_case(name, &self.fs.options.oem_cp_converter)
+    }
+
     pub(crate) fn eq_name(&self, name: &str) -> bool {
         #[cfg(feature = "lfn")]
         {
@@ -712,7 +1202,17 @@ impl<'a, IO: ReadWriteSeek, TP, OCC: OemCpConverter> DirEntry<'a, IO, TP, OCC> {
             }
         }
 
-        self.short_name.eq_ignore_case(name, &self.fs.options.oem_cp_converter)
+        let oem_cp_converter = &self.fs.options.oem_cp_converter;
+        if self.fs.options.case_sensitive {
+            // short name is matched as returned by `file_name`, with the lowercase flags applied
+            let short_name = self.data.lowercase_name(oem_cp_converter);
+            return self.eq_name_chars(decode_oem_name(short_name.as_bytes(), oem_cp_converter), name);
+        }
+        #[cfg(feature = "normalization")]
+        if self.fs.options.normalize_names {
+            return self.eq_name_chars(decode_oem_name(self.short_name.as_bytes(), oem_cp_converter), name);
+        }
+        self.short_name.eq_ignore_case(name, oem_cp_converter)
     }
 }
 
@@ -763,6 +1263,63 @@ mod tests {
         );
     }
 
+    // Shift-JIS subset containing "日本" (0x93FA 0x967B), "ア" (0x8341) and "蛙" (0xE586)
+    #[derive(Debug)]
+    struct TestDbcsConverter;
+
+    impl OemCpConverter for TestDbcsConverter {
+        fn decode(&self, oem_char: u8) -> char {
+            LossyOemCpConverter::new().decode(oem_char)
+        }
+
+        fn encode(&self, uni_char: char) -> Option<u8> {
+            LossyOemCpConverter::new().encode(uni_char)
+        }
+
+        fn is_lead_byte(&self, oem_char: u8) -> bool {
+            matches!(oem_char, 0x81..=0x9F | 0xE0..=0xFC)
+        }
+
+        fn decode_double_byte(&self, lead: u8, trail: u8) -> char {
+            match (lead, trail) {
+                (0x93, 0xFA) => '日',
+                (0x96, 0x7B) => '本',
+                (0x83, 0x41) => 'ア',
+                (0xE5, 0x86) => '蛙',
+                _ => '\u{FFFD}',
+            }
+        }
+    }
+
+    #[test]
+    fn short_name_double_byte() {
+        let oem_cp_conv = TestDbcsConverter;
+        let short_name = ShortName::new(b"\x93\xFA\x96\x7BA   \x83\x41 ");
+        assert_eq!(short_name.to_string(&oem_cp_conv), "日本A.ア");
+        assert!(short_name.eq_ignore_case("日本a.ア", &oem_cp_conv));
+        // lead byte in the last position of the base name is not paired with the dot
+        assert_eq!(
+            ShortName::new(b"ABCDEFG\x93TXT").to_string(&oem_cp_conv),
+            "ABCDEFG\u{FFFD}.TXT"
+        );
+        // 0xE5 lead byte is stored as 0x05
+        let raw_entry = DirFileEntryData::new(*b"\xE5\x86      TXT", FileAttributes::ARCHIVE);
+        assert_eq!(raw_entry.name()[0], DIR_ENTRY_REALLY_E5_FLAG);
+        assert!(!raw_entry.is_deleted());
+        assert_eq!(ShortName::new(raw_entry.name()).to_string(&oem_cp_conv), "蛙.TXT");
+    }
+
+    #[test]
+    fn lowercase_short_name_double_byte() {
+        let oem_cp_conv = TestDbcsConverter;
+        let raw_entry = DirFileEntryData {
+            name: *b"\x83\x41B     \x83\x41 ",
+            reserved_0: (1 << 3) | (1 << 4),
+            ..DirFileEntryData::default()
+        };
+        assert_eq!(raw_entry.lowercase_name(&oem_cp_conv).to_string(&oem_cp_conv), "アb.ア");
+    }
+
     #[test]
     fn lowercase_short_name() {
         let oem_cp_conv = LossyOemCpConverter::new();
@@ -772,12 +1329,30 @@ mod tests {
             reserved_0: (1 << 3) | (1 << 4),
             ..DirFileEntryData::default()
         };
-        assert_eq!(raw_entry.lowercase_name().to_string(&oem_cp_conv), "foo.rs");
+        assert_eq!(raw_entry.lowercase_name(&oem_cp_conv).to_string(&oem_cp_conv), "foo.rs");
         raw_entry.reserved_0 = 1 << 3;
-        assert_eq!(raw_entry.lowercase_name().to_string(&oem_cp_conv), "foo.RS");
+        assert_eq!(raw_entry.lowercase_name(&oem_cp_conv).to_string(&oem_cp_conv), "foo.RS");
         raw_entry.reserved_0 = 1 << 4;
-        assert_eq!(raw_entry.lowercase_name().to_string(&oem_cp_conv), "FOO.rs");
+        assert_eq!(raw_entry.lowercase_name(&oem_cp_conv).to_string(&oem_cp_conv), "FOO.rs");
         raw_entry.reserved_0 = 0;
-        assert_eq!(raw_entry.lowercase_name().to_string(&oem_cp_conv), "FOO.RS");
+        assert_eq!(raw_entry.lowercase_name(&oem_cp_conv).to_string(&oem_cp_conv), "FOO.RS");
+    }
+
+    #[test]
+    fn fat_plus_size() {
+        let mut raw_entry = DirFileEntryData::new(*b"FOO     RS ", FileAttributes::ARCHIVE);
+        raw_entry.set_lowercase_flags(true, true);
+        raw_entry.set_size((1 << 38) - 1);
+        assert_eq!(raw_entry.size, u32::MAX);
+        assert_eq!(raw_entry.reserved_0, 0xFF);
+        assert_eq!(raw_entry.size(true), Some((1 << 38) - 1));
+        assert_eq!(raw_entry.size(false), Some(u64::from(u32::MAX)));
+        raw_entry.set_size((5 << 32) | 7);
+        assert_eq!(raw_entry.reserved_0, (1 << 3) | (1 << 4) | 5);
+        assert_eq!(raw_entry.size(true), Some((5 << 32) | 7));
+        raw_entry.set_size(100);
+        assert_eq!(raw_entry.reserved_0, (1 << 3) | (1 << 4));
+        assert_eq!(raw_entry.size(true), Some(100));
+        assert!(raw_entry.lowercase_basename() && raw_entry.lowercase_ext());
     }
 }
@@ -1,9 +1,13 @@
+#[cfg(all(not(feature = "std"), feature = "alloc"))]
+use alloc::string::String;
+
 /// Error enum with all errors that can be returned by functions from this crate.
 ///
 /// This enum represents all possible errors that can occur when performing
 /// operations on a FAT filesystem.
 
 #[derive(Debug)]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
 #[non_exhaustive]
 pub enum Error<T> {
     /// A user provided storage instance returned an error during an input/output operation.
@@ -28,11 +32,195 @@ pub enum Error<T> {
     InvalidFileNameLength,
     /// The provided file name contains an invalid character.
     UnsupportedFileNameCharacter,
+    /// The provided file name is a reserved DOS device name (e.g. `CON` or `LPT1`).
+    ReservedFileName,
+    /// An operation cannot be finished because a file or a directory has the read-only attribute.
+    ReadOnlyFile,
+    /// An operation cannot be finished because the filesystem is mounted read-only.
+    ReadOnlyFilesystem,
+    /// The volume was not cleanly unmounted or has hard errors and `FsOptions::on_dirty` requested to refuse it.
+    DirtyVolume,
+    /// A deleted entry cannot be restored because its clusters have been reused.
+    DataOverwritten,
+    /// Entries were added to or removed from a directory while it was being iterated. The iteration has to be
+    /// started again.
+    DirectoryModified,
+}
+
+/// Error codes equal to `errno` values used by Linux.
+///
+/// Values of the codes are stable and can be passed to C code or returned from system calls (negated if required
+/// by the convention of the system). New codes can be added in the future.
+#[derive(Copy, Clone, Debug, PartialEq, Eq, Hash)]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+#[repr(i32)]
+#[non_exhaustive]
+pub enum ErrorCode {
+    /// No such file or directory (`ENOENT`).
+    NoEntry = 2,
+    /// Input/output error (`EIO`).
+    Io = 5,
+    /// Resource temporarily unavailable, try again (`EAGAIN`).
+    TryAgain = 11,
+    /// Permission denied (`EACCES`).
+    AccessDenied = 13,
+    /// File exists (`EEXIST`).
+    Exists = 17,
+    /// Invalid argument (`EINVAL`).
+    InvalidArgument = 22,
+    /// No space left on device (`ENOSPC`).
+    NoSpace = 28,
+    /// Read-only file system (`EROFS`).
+    ReadOnlyFilesystem = 30,
+    /// File name too long (`ENAMETOOLONG`).
+    NameTooLong = 36,
+    /// Directory not empty (`ENOTEMPTY`).
+    NotEmpty = 39,
+    /// No data available (`ENODATA`).
+    NoData = 61,
+    /// Structure needs cleaning (`EUCLEAN`) - used by Linux filesystems for detected corruption.
+    NeedsCleaning = 117,
+}
+
+impl ErrorCode {
+    /// Returns the `errno` value of this code.
+    #[must_use]
+    pub fn errno(self) -> i32 {
+        self as i32
+    }
+}
+
+impl<T: IoError> Error<T> {
+    /// Returns the `errno` value describing this error.
+    ///
+    /// Storage errors are mapped by `IoError::code`, other errors have fixed codes from `ErrorCode`:
+    ///
+    /// * `NotFound` - `ENOENT`,
+    /// * `AlreadyExists` - `EEXIST`,
+    /// * `InvalidInput`, `UnsupportedFileNameCharacter` and `ReservedFileName` - `EINVAL`,
+    /// * `InvalidFileNameLength` - `ENAMETOOLONG`,
+    /// * `DirectoryIsNotEmpty` - `ENOTEMPTY`,
+    /// * `NotEnoughSpace` - `ENOSPC`,
+    /// * `ReadOnlyFile` - `EACCES`,
+    /// * `ReadOnlyFilesystem` - `EROFS`,
+    /// * `CorruptedFileSystem` and `DirtyVolume` - `EUCLEAN`,
+    /// * `DataOverwritten` - `ENODATA`,
+    /// * `DirectoryModified` - `EAGAIN`,
+    /// * `UnexpectedEof` and `WriteZero` - `EIO`.
+    #[must_use]
+    pub fn code(&self) -> i32 {
+        match self {
+            Error::Io(io_error) => io_error.code(),
+            _ => self.error_code().map_or(ErrorCode::Io.errno(), ErrorCode::errno),
+        }
+    }
+
+    /// Returns the `ErrorCode` of this error or `None` for storage errors.
+    #[must_use]
+    pub fn error_code(&self) -> Option<ErrorCode> {
+        Some(match self {
+            Error::Io(_) => return None,
+            Error::UnexpectedEof | Error::WriteZero => ErrorCode::Io,
+            Error::InvalidInput | Error::UnsupportedFileNameCharacter | Error::ReservedFileName => {
+                ErrorCode::InvalidArgument
+            }
+            Error::NotFound => ErrorCode::NoEntry,
+            Error::AlreadyExists => ErrorCode::Exists,
+            Error::DirectoryIsNotEmpty => ErrorCode::NotEmpty,
+            Error::CorruptedFileSystem | Error::DirtyVolume => ErrorCode::NeedsCleaning,
+            Error::NotEnoughSpace => ErrorCode::NoSpace,
+            Error::InvalidFileNameLength => ErrorCode::NameTooLong,
+            Error::ReadOnlyFile => ErrorCode::AccessDenied,
+            Error::ReadOnlyFilesystem => ErrorCode::ReadOnlyFilesystem,
+            Error::DataOverwritten => ErrorCode::NoData,
+            Error::DirectoryModified => ErrorCode::TryAgain,
+        })
+    }
+}
+
+impl<T> Error<T> {
+    // Converts the storage error using `f`, other variants are preserved
+    #[cfg(feature = "std")]
+    pub(crate) fn map_io<U, F: FnOnce(T) -> U>(self, f: F) -> Error<U> {
+        match self {
+            Error::Io(io_error) => Error::Io(f(io_error)),
+            Error::UnexpectedEof => Error::UnexpectedEof,
+            Error::WriteZero => Error::WriteZero,
+            Error::InvalidInput => Error::InvalidInput,
+            Error::NotFound => Error::NotFound,
+            Error::AlreadyExists => Error::AlreadyExists,
+            Error::DirectoryIsNotEmpty => Error::DirectoryIsNotEmpty,
+            Error::CorruptedFileSystem => Error::CorruptedFileSystem,
+            Error::NotEnoughSpace => Error::NotEnoughSpace,
+            Error::InvalidFileNameLength => Error::InvalidFileNameLength,
+            Error::UnsupportedFileNameCharacter => Error::UnsupportedFileNameCharacter,
+            Error::ReservedFileName => Error::ReservedFileName,
+            Error::ReadOnlyFile => Error::ReadOnlyFile,
+            Error::ReadOnlyFilesystem => Error::ReadOnlyFilesystem,
+            Error::DirtyVolume => Error::DirtyVolume,
+            Error::DataOverwritten => Error::DataOverwritten,
+            Error::DirectoryModified => Error::DirectoryModified,
+        }
+    }
 }
 
 impl<T: IoError> From<T> for Error<T> {
     fn from(error: T) -> Self {
-        Error::Io(error)
+        if error.is_out_of_space() {
+            Error::NotEnoughSpace
+        } else {
+            Error::Io(error)
+        }
+    }
+}
+
+/// Converts the error into the matching `axerrno` kind.
+///
+/// `axerrno::AxError` implements `From<AxErrorKind>`, so `Error` can be converted into it directly (e.g. using
+/// the `?` operator in functions returning `AxResult`). Storage errors are mapped by `IoError::code` and become
+/// `AxErrorKind::Io` if the code has no matching kind. Errors reporting a corrupted or dirty volume or overwritten
+/// data are mapped to `AxErrorKind::InvalidData` and `DirectoryModified` to `AxErrorKind::WouldBlock` (`EAGAIN`).
+#[cfg(feature = "axerrno")]
+impl<T: IoError> From<Error<T>> for axerrno::AxErrorKind {
+    fn from(error: Error<T>) -> Self {
+        match error {
+            Error::Io(io_error) => axerrno::LinuxError::try_from(io_error.code())
+                .ok()
+                .and_then(|e| Self::try_from(e).ok())
+                .unwrap_or(Self::Io),
+            Error::UnexpectedEof => Self::UnexpectedEof,
+            Error::WriteZero => Self::WriteZero,
+            Error::InvalidInput | Error::UnsupportedFileNameCharacter | Error::ReservedFileName => Self::InvalidInput,
+            Error::NotFound => Self::NotFound,
+            Error::AlreadyExists => Self::AlreadyExists,
+            Error::DirectoryIsNotEmpty => Self::DirectoryNotEmpty,
+            Error::CorruptedFileSystem | Error::DirtyVolume | Error::DataOverwritten => Self::InvalidData,
+            Error::DirectoryModified => Self::WouldBlock,
+            Error::NotEnoughSpace => Self::StorageFull,
+            Error::InvalidFileNameLength => Self::NameTooLong,
+            Error::ReadOnlyFile => Self::PermissionDenied,
+            Error::ReadOnlyFilesystem => Self::ReadOnlyFilesystem,
+        }
+    }
+}
+
+// Returns the kind of `std::io::Error` used for errors not coming from the storage
+#[cfg(feature = "std")]
+fn std_io_error_kind<T>(error: &Error<T>) -> std::io::ErrorKind {
+    match error {
+        Error::Io(_) => std::io::ErrorKind::Other,
+        Error::UnexpectedEof | Error::NotEnoughSpace => std::io::ErrorKind::UnexpectedEof,
+        Error::WriteZero => std::io::ErrorKind::WriteZero,
+        Error::InvalidInput
+        | Error::InvalidFileNameLength
+        | Error::UnsupportedFileNameCharacter
+        | Error::ReservedFileName
+        | Error::DirectoryIsNotEmpty => std::io::ErrorKind::InvalidInput,
+        Error::NotFound => std::io::ErrorKind::NotFound,
+        Error::AlreadyExists => std::io::ErrorKind::AlreadyExists,
+        Error::CorruptedFileSystem | Error::DirtyVolume | Error::DataOverwritten => std::io::ErrorKind::InvalidData,
+        Error::DirectoryModified => std::io::ErrorKind::WouldBlock,
+        Error::ReadOnlyFile | Error::ReadOnlyFilesystem => std::io::ErrorKind::PermissionDenied,
     }
 }
 
@@ -41,15 +229,7 @@ impl From<Error<std::io::Error>> for std::io::Error {
     fn from(error: Error<Self>) -> Self {
         match error {
             Error::Io(io_error) => io_error,
-            Error::UnexpectedEof | Error::NotEnoughSpace => Self::new(std::io::ErrorKind::UnexpectedEof, error),
-            Error::WriteZero => Self::new(std::io::ErrorKind::WriteZero, error),
-            Error::InvalidInput
-            | Error::InvalidFileNameLength
-            | Error::UnsupportedFileNameCharacter
-            | Error::DirectoryIsNotEmpty => Self::new(std::io::ErrorKind::InvalidInput, error),
-            Error::NotFound => Self::new(std::io::ErrorKind::NotFound, error),
-            Error::AlreadyExists => Self::new(std::io::ErrorKind::AlreadyExists, error),
-            Error::CorruptedFileSystem => Self::new(std::io::ErrorKind::InvalidData, error),
+            _ => Self::new(std_io_error_kind(&error), error),
         }
     }
 }
@@ -64,10 +244,16 @@ impl<T: core::fmt::Display> core::fmt::Display for Error<T> {
             Error::InvalidInput => write!(f, "Invalid input"),
             Error::InvalidFileNameLength => write!(f, "Invalid file name length"),
             Error::UnsupportedFileNameCharacter => write!(f, "Unsupported file name character"),
+            Error::ReservedFileName => write!(f, "Reserved file name"),
             Error::DirectoryIsNotEmpty => write!(f, "Directory is not empty"),
             Error::NotFound => write!(f, "No such file or directory"),
             Error::AlreadyExists => write!(f, "File or directory already exists"),
             Error::CorruptedFileSystem => write!(f, "Corrupted file system"),
+            Error::ReadOnlyFile => write!(f, "File or directory is read-only"),
+            Error::ReadOnlyFilesystem => write!(f, "Filesystem is mounted read-only"),
+            Error::DirtyVolume => write!(f, "Volume was not cleanly unmounted or has hard errors"),
+            Error::DataOverwritten => write!(f, "Data of the deleted entry has been overwritten"),
+            Error::DirectoryModified => write!(f, "Directory was modified during iteration"),
         }
     }
 }
@@ -83,6 +269,160 @@ impl<T: std::error::Error + 'static> std::error::Error for Error<T> {
     }
 }
 
+/// An error with a description of the operation which failed.
+///
+/// It is created from `Error` using methods of the `ResultExt` trait and holds the name of the operation, the path
+/// and the cluster involved if they are known. The wrapped `Error` is returned by `source` of the `std::error::Error`
+/// trait, so the whole cause chain down to the storage error can be logged.
+///
+/// Errors found while reading directory entries, cluster chains and path components are additionally reported by
+/// the crate at the error log level together with the cluster, the offset or the name involved.
+#[cfg(feature = "alloc")]
+#[derive(Debug)]
+pub struct ContextError<T> {
+    error: Error<T>,
+    operation: &'static str,
+    path: Option<String>,
+    cluster: Option<u32>,
+}
+
+#[cfg(feature = "alloc")]
+impl<T> ContextError<T> {
+    /// Creates a `ContextError` for `error` returned by `operation`.
+    #[must_use]
+    pub fn new(error: Error<T>, operation: &'static str) -> Self {
+        Self {
+            error,
+            operation,
+            path: None,
+            cluster: None,
+        }
+    }
+
+    /// Sets the path of a file or a directory involved in the operation.
+    #[must_use]
+    pub fn with_path(mut self, path: &str) -> Self {
+        self.path = Some(String::from(path));
+        self
+    }
+
+    /// Sets the cluster involved in the operation (e.g. the first cluster of a directory being read).
+    #[must_use]
+    pub fn with_cluster(mut self, cluster: u32) -> Self {
+        self.cluster = Some(cluster);
+        self
+    }
+
+    /// Returns the wrapped error.
+    #[must_use]
+    pub fn error(&self) -> &Error<T> {
+        &self.error
+    }
+
+    /// Returns the wrapped error dropping the context.
+    #[must_use]
+    pub fn into_error(self) -> Error<T> {
+        self.error
+    }
+
+    /// Returns the name of the failed operation.
+    #[must_use]
+    pub fn operation(&self) -> &'static str {
+        self.operation
+    }
+
+    /// Returns the path involved in the operation if it is known.
+    #[must_use]
+    pub fn path(&self) -> Option<&str> {
+        self.path.as_deref()
+    }
+
+    /// Returns the cluster involved in the operation if it is known.
+    #[must_use]
+    pub fn cluster(&self) -> Option<u32> {
+        self.cluster
+    }
+}
+
+#[cfg(feature = "alloc")]
+impl<T: IoError> ContextError<T> {
+    /// Returns the `errno` value describing the wrapped error.
+    ///
+    /// See `Error::code`.
+    #[must_use]
+    pub fn code(&self) -> i32 {
+        self.error.code()
+    }
+}
+
+#[cfg(feature = "alloc")]
+impl<T> From<ContextError<T>> for Error<T> {
+    fn from(error: ContextError<T>) -> Self {
+        error.error
+    }
+}
+
+#[cfg(feature = "alloc")]
+impl<T: core::fmt::Display> core::fmt::Display for ContextError<T> {
+    fn fmt(&self, f: &mut core::fmt::Formatter<'_>) -> core::fmt::Result {
+        write!(f, "{} failed", self.operation)?;
+        if let Some(path) = &self.path {
+            write!(f, " for '{}'", path)?;
+        }
+        if let Some(cluster) = self.cluster {
+            write!(f, " at cluster {}", cluster)?;
+        }
+        write!(f, ": {}", self.error)
+    }
+}
+
+#[cfg(all(feature = "std", feature = "alloc"))]
+impl<T: std::error::Error + 'static> std::error::Error for ContextError<T> {
+    fn source(&self) -> Option<&(dyn std::error::Error + 'static)> {
+        Some(&self.error)
+    }
+}
+
+#[cfg(all(feature = "std", feature = "alloc"))]
+impl From<ContextError<std::io::Error>> for std::io::Error {
+    fn from(error: ContextError<Self>) -> Self {
+        let kind = match &error.error {
+            Error::Io(io_error) => io_error.kind(),
+            e => std_io_error_kind(e),
+        };
+        Self::new(kind, error)
+    }
+}
+
+/// Extension trait adding a context to errors returned by functions from this crate.
+#[cfg(feature = "alloc")]
+pub trait ResultExt<R, T> {
+    /// Wraps an error in a `ContextError` with the name of the failed operation.
+    ///
+    /// # Errors
+    ///
+    /// The wrapped error is returned.
+    fn context(self, operation: &'static str) -> Result<R, ContextError<T>>;
+
+    /// Wraps an error in a `ContextError` with the name of the failed operation and the path involved.
+    ///
+    /// # Errors
+    ///
+    /// The wrapped error is returned.
+    fn path_context(self, operation: &'static str, path: &str) -> Result<R, ContextError<T>>;
+}
+
+#[cfg(feature = "alloc")]
+impl<R, T> ResultExt<R, T> for Result<R, Error<T>> {
+    fn context(self, operation: &'static str) -> Result<R, ContextError<T>> {
+        self.map_err(|error| ContextError::new(error, operation))
+    }
+
+    fn path_context(self, operation: &'static str, path: &str) -> Result<R, ContextError<T>> {
+        self.map_err(|error| ContextError::new(error, operation).with_path(path))
+    }
+}
+
 /// Trait that should be implemented by errors returned from the user supplied storage.
 ///
 /// Implementations for `std::io::Error` and `()` are provided by this crate.
@@ -116,6 +456,24 @@ pub trait IoError: core::fmt::Debug {
     ///
     /// A new instance of the error type.
     fn new_write_zero_error() -> Self;
+
+    /// Checks if an operation failed because the storage has no room for more data.
+    ///
+    /// Storage errors for which this returns `true` are reported by the library as `Error::NotEnoughSpace`.
+    ///
+    /// # Returns
+    ///
+    /// `true` if this is an out of space error, `false` otherwise. The default implementation returns `false`.
+    fn is_out_of_space(&self) -> bool {
+        false
+    }
+
+    /// Returns the `errno` value describing this error, used by `Error::code`.
+    ///
+    /// The default implementation returns `EIO` (`ErrorCode::Io`).
+    fn code(&self) -> i32 {
+        ErrorCode::Io.errno()
+    }
 }
 
 impl<T: core::fmt::Debug + IoError> IoError for Error<T> {
@@ -126,6 +484,14 @@ impl<T: core::fmt::Debug + IoError> IoError for Error<T> {
         }
     }
 
+    fn is_out_of_space(&self) -> bool {
+        match self {
+            Error::<T>::Io(io_error) => io_error.is_out_of_space(),
+            Error::<T>::NotEnoughSpace => true,
+            _ => false,
+        }
+    }
+
     fn new_unexpected_eof_error() -> Self {
         Error::<T>::UnexpectedEof
     }
@@ -133,6 +499,10 @@ impl<T: core::fmt::Debug + IoError> IoError for Error<T> {
     fn new_write_zero_error() -> Self {
         Error::<T>::WriteZero
     }
+
+    fn code(&self) -> i32 {
+        Error::code(self)
+    }
 }
 
 impl IoError for () {
@@ -149,6 +519,30 @@ impl IoError for () {
     }
 }
 
+#[cfg(feature = "std")]
+impl IoError for std::io::Error {
+    fn is_interrupted(&self) -> bool {
+        self.kind() == std::io::ErrorKind::Interrupted
+    }
+
+    fn new_unexpected_eof_error() -> Self {
+        Self::new(std::io::ErrorKind::UnexpectedEof, "failed to fill whole buffer")
+    }
+
+    fn new_write_zero_error() -> Self {
+        Self::new(std::io::ErrorKind::WriteZero, "failed to write whole buffer")
+    }
+
+    fn code(&self) -> i32 {
+        // OS error codes are errno values only on Unix-like systems
+        #[cfg(unix)]
+        if let Some(code) = self.raw_os_error() {
+            return code;
+        }
+        ErrorCode::Io.errno()
+    }
+}
+
 #[cfg(test)]
 mod tests {
     use super::*;
@@ -251,6 +645,10 @@ mod tests {
             format!("{}", Error::<std::io::Error>::UnsupportedFileNameCharacter),
             "Unsupported file name character"
         );
+        assert_eq!(
+            format!("{}", Error::<std::io::Error>::ReservedFileName),
+            "Reserved file name"
+        );
         assert_eq!(
             format!("{}", Error::<std::io::Error>::DirectoryIsNotEmpty),
             "Directory is not empty"
@@ -269,25 +667,89 @@ mod tests {
         );
     }
 
-    #[cfg(feature = "std")]
     #[test]
-    fn test_error_io_display_with_std_error() {
-        let io_error = std::io::Error::new(std::io::ErrorKind::NotFound, "test");
-        assert_eq!(format!("{}", Error::<std::io::Error>::Io(io_error)), "IO error: test");
+    fn test_error_code() {
+        assert_eq!(Error::<()>::NotFound.code(), 2);
+        assert_eq!(Error::<()>::Io(()).code(), 5);
+        assert_eq!(Error::<()>::Io(()).error_code(), None);
+        assert_eq!(Error::<()>::ReadOnlyFilesystem.code(), 30);
+        assert_eq!(
+            Error::<()>::CorruptedFileSystem.error_code(),
+            Some(ErrorCode::NeedsCleaning)
+        );
+        assert_eq!(ErrorCode::NeedsCleaning.errno(), 117);
+        assert_eq!(Error::<()>::DirectoryModified.code(), 11);
+        let nested: Error<Error<()>> = Error::Io(Error::NotEnoughSpace);
+        assert_eq!(nested.code(), 28);
     }
-}
 
-#[cfg(feature = "std")]
-impl IoError for std::io::Error {
-    fn is_interrupted(&self) -> bool {
-        self.kind() == std::io::ErrorKind::Interrupted
+    #[cfg(all(feature = "std", unix))]
+    #[test]
+    fn test_error_code_std() {
+        let inner = std::io::Error::from_raw_os_error(16);
+        assert_eq!(Error::Io(inner).code(), 16);
+        let inner = std::io::Error::new(std::io::ErrorKind::Other, "other");
+        assert_eq!(Error::Io(inner).code(), 5);
     }
 
-    fn new_unexpected_eof_error() -> Self {
-        Self::new(std::io::ErrorKind::UnexpectedEof, "failed to fill whole buffer")
+    #[cfg(feature = "axerrno")]
+    #[test]
+    fn test_axerrno() {
+        use axerrno::{AxError, AxErrorKind, LinuxError};
+
+        assert_eq!(AxErrorKind::from(Error::<()>::NotFound), AxErrorKind::NotFound);
+        assert_eq!(AxErrorKind::from(Error::<()>::NotEnoughSpace), AxErrorKind::StorageFull);
+        assert_eq!(
+            AxErrorKind::from(Error::<()>::DirectoryIsNotEmpty),
+            AxErrorKind::DirectoryNotEmpty
+        );
+        assert_eq!(
+            AxErrorKind::from(Error::<()>::CorruptedFileSystem),
+            AxErrorKind::InvalidData
+        );
+        assert_eq!(AxErrorKind::from(Error::<()>::Io(())), AxErrorKind::Io);
+        let error = AxError::from(Error::<()>::AlreadyExists);
+        assert_eq!(LinuxError::from(error), LinuxError::EEXIST);
+        let nested: Error<Error<()>> = Error::Io(Error::ReadOnlyFilesystem);
+        assert_eq!(AxErrorKind::from(nested), AxErrorKind::ReadOnlyFilesystem);
     }
 
-    fn new_write_zero_error() -> Self {
-        Self::new(std::io::ErrorKind::WriteZero, "failed to write whole buffer")
+    #[cfg(feature = "std")]
+    #[test]
+    fn test_context_error() {
+        use std::error::Error as StdError;
+
+        let result: Result<(), Error<std::io::Error>> = Err(Error::CorruptedFileSystem);
+        let error = result
+            .path_context("read directory", "a/b")
+            .unwrap_err()
+            .with_cluster(10);
+        assert_eq!(error.operation(), "read directory");
+        assert_eq!(error.path(), Some("a/b"));
+        assert_eq!(error.cluster(), Some(10));
+        assert_eq!(
+            format!("{}", error),
+            "read directory failed for 'a/b' at cluster 10: Corrupted file system"
+        );
+        assert!(matches!(error.error(), Error::CorruptedFileSystem));
+        let std_error: std::io::Error = error.into();
+        assert_eq!(std_error.kind(), std::io::ErrorKind::InvalidData);
+
+        let inner = std::io::Error::new(std::io::ErrorKind::TimedOut, "timeout");
+        let result: Result<(), _> = Err(Error::Io(inner));
+        let error = result.context("flush").unwrap_err();
+        assert_eq!(format!("{}", error), "flush failed: IO error: timeout");
+        // cause chain leads to the storage error
+        let source = StdError::source(&error).unwrap();
+        assert_eq!(format!("{}", StdError::source(source).unwrap()), "timeout");
+        let std_error: std::io::Error = error.into();
+        assert_eq!(std_error.kind(), std::io::ErrorKind::TimedOut);
+    }
+
+    #[cfg(feature = "std")]
+    #[test]
+    fn test_error_io_display_with_std_error() {
+        let io_error = std::io::Error::new(std::io::ErrorKind::NotFound, "test");
+        assert_eq!(format!("{}", Error::<std::io::Error>::Io(io_error)), "IO error: test");
     }
 }
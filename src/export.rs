@@ -0,0 +1,174 @@
+use std::io;
+
+use crate::dir::Dir;
+use crate::dir_entry::FileAttributes;
+use crate::error::Error;
+use crate::fs::{OemCpConverter, ReadWriteSeek};
+use crate::time::TimeProvider;
+
+const TAR_BLOCK_SIZE: usize = 512;
+const TAR_NAME_LEN: usize = 100;
+const TAR_PREFIX_LEN: usize = 155;
+
+// A ustar header block
+struct TarHeader([u8; TAR_BLOCK_SIZE]);
+
+impl TarHeader {
+    fn new(typeflag: u8, mode: u32, size: u64, mtime: i64) -> Self {
+        let mut block = [0_u8; TAR_BLOCK_SIZE];
+        write_octal(&mut block[100..108], u64::from(mode));
+        write_octal(&mut block[108..116], 0);
+        write_octal(&mut block[116..124], 0);
+        write_octal(&mut block[124..136], size);
+        write_octal(&mut block[136..148], u64::try_from(mtime).unwrap_or(0));
+        block[156] = typeflag;
+        block[257..263].copy_from_slice(b"ustar\0");
+        block[263..265].copy_from_slice(b"00");
+        Self(block)
+    }
+
+    // Stores the path in the name and prefix fields, returns false if it does not fit
+    fn set_path(&mut self, path: &str) -> bool {
+        let bytes = path.as_bytes();
+        if bytes.len() <= TAR_NAME_LEN {
+            self.0[..bytes.len()].copy_from_slice(bytes);
+            return true;
+        }
+        // the prefix is joined with the name using a slash, a trailing slash of directories stays in the name
+        let split_pos = bytes[..bytes.len() - 1]
+            .iter()
+            .enumerate()
+            .filter(|(_, b)| **b == b'/')
+            .map(|(i, _)| i)
+            .find(|i| *i <= TAR_PREFIX_LEN && bytes.len() - i - 1 <= TAR_NAME_LEN);
+        match split_pos {
+            Some(i) if i > 0 => {
+                self.0[345..345 + i].copy_from_slice(&bytes[..i]);
+                self.0[..bytes.len() - i - 1].copy_from_slice(&bytes[i + 1..]);
+                true
+            }
+            _ => {
+                // keep a truncated path for readers not supporting extended headers
+                let mut len = TAR_NAME_LEN;
+                while !path.is_char_boundary(len) {
+                    len -= 1;
+                }
+                self.0[..len].copy_from_slice(&bytes[..len]);
+                false
+            }
+        }
+    }
+
+    fn write<W: io::Write>(mut self, writer: &mut W) -> io::Result<()> {
+        self.0[148..156].fill(b' ');
+        let checksum = self.0.iter().map(|b| u32::from(*b)).sum::<u32>();
+        write_octal(&mut self.0[148..155], u64::from(checksum));
+        writer.write_all(&self.0)
+    }
+}
+
+// Writes the value as a zero padded octal number followed by NUL
+fn write_octal(field: &mut [u8], value: u64) {
+    let digits = field.len() - 1;
+    let octal = format!("{:0width$o}", value, width = digits);
+    field[..digits].copy_from_slice(&octal.as_bytes()[octal.len() - digits..]);
+    field[digits] = 0;
+}
+
+fn write_padding<W: io::Write>(writer: &mut W, size: u64) -> io::Result<()> {
+    let padding = (TAR_BLOCK_SIZE - (size % TAR_BLOCK_SIZE as u64) as usize) % TAR_BLOCK_SIZE;
+    writer.write_all(&[0_u8; TAR_BLOCK_SIZE][..padding])
+}
+
+fn write_entry_header<W: io::Write>(
+    writer: &mut W,
+    path: &str,
+    typeflag: u8,
+    mode: u32,
+    size: u64,
+    mtime: i64,
+) -> io::Result<()> {
+    let mut header = TarHeader::new(typeflag, mode, size, mtime);
+    if !header.set_path(path) {
+        // a pax extended header holds paths which do not fit in the ustar header
+        let record_tail = format!(" path={}\n", path);
+        let mut record_len = record_tail.len() + 1;
+        while record_len.to_string().len() + record_tail.len() != record_len {
+            record_len += 1;
+        }
+        let record = format!("{}{}", record_len, record_tail);
+        let mut pax_header = TarHeader::new(b'x', 0o644, record.len() as u64, mtime);
+        pax_header.set_path("././@PaxHeader");
+        pax_header.write(writer)?;
+        writer.write_all(record.as_bytes())?;
+        write_padding(writer, record.len() as u64)?;
+    }
+    header.write(writer)
+}
+
+impl<IO: ReadWriteSeek, TP: TimeProvider, OCC: OemCpConverter> Dir<'_, IO, TP, OCC>
+where
+    io::Error: From<Error<IO::Error>>,
+{
+    /// Writes this directory tree to `writer` as a tar archive.
+    ///
+    /// The archive uses the ustar format with pax extended headers for paths that do not fit in the ustar header.
+    /// Paths are relative to this directory and use long file names if available. Last modification times are
+    /// preserved and files with the `READ_ONLY` attribute get read-only permissions.
+    ///
+    /// # Errors
+    ///
+    /// Errors that can be returned:
+    ///
+    /// * `std::io::ErrorKind::InvalidData` will be returned if a file is shorter than its size in the directory
+    ///   entry.
+    /// * Errors returned by `writer` and errors of filesystem operations converted to `std::io::Error`.
+    pub fn export_tar<W: io::Write>(&self, mut writer: W) -> io::Result<()> {
+        trace!("Dir::export_tar");
+        export_dir(self, "", &mut writer)?;
+        // end of archive is marked by two zero blocks
+        writer.write_all(&[0_u8; 2 * TAR_BLOCK_SIZE])?;
+        writer.flush()
+    }
+}
+
+fn export_dir<IO: ReadWriteSeek, TP: TimeProvider, OCC: OemCpConverter, W: io::Write>(
+    dir: &Dir<'_, IO, TP, OCC>,
+    prefix: &str,
+    writer: &mut W,
+) -> io::Result<()>
+where
+    io::Error: From<Error<IO::Error>>,
+{
+    for r in dir.iter() {
+        let e = r?;
+        let name = e.file_name();
+        if name == "." || name == ".." {
+            continue;
+        }
+        let mut path = String::from(prefix);
+        path.push_str(&name);
+        let mtime = e.modified().to_unix_time();
+        let read_only = e.attributes().contains(FileAttributes::READ_ONLY);
+        if e.is_dir() {
+            path.push('/');
+            let mode = if read_only { 0o555 } else { 0o755 };
+            write_entry_header(writer, &path, b'5', mode, 0, mtime)?;
+            export_dir(&e.to_dir(), &path, writer)?;
+        } else {
+            let size = e.len();
+            let mode = if read_only { 0o444 } else { 0o644 };
+            write_entry_header(writer, &path, b'0', mode, size, mtime)?;
+            let copied = io::copy(&mut io::Read::take(e.to_file(), size), writer)?;
+            if copied != size {
+                error!("file {} is shorter than its size {}", path, size);
+                return Err(io::Error::new(
+                    io::ErrorKind::InvalidData,
+                    "file is shorter than its size",
+                ));
+            }
+            write_padding(writer, size)?;
+        }
+    }
+    Ok(())
+}
@@ -1,40 +1,128 @@
+#[cfg(all(not(feature = "std"), feature = "alloc"))]
+use alloc::{vec, vec::Vec};
 use core::convert::TryFrom;
+use core::iter;
 
-use crate::dir_entry::DirEntryEditor;
-use crate::error::Error;
-use crate::fs::{FileSystem, ReadWriteSeek};
-use crate::io::{IoBase, Read, Seek, SeekFrom, Write};
+use crate::dir_entry::{DirEntryEditor, FileAttributes, Metadata};
+use crate::error::{Error, IoError};
+use crate::fs::{FileSystem, ReadWriteSeek, WritePolicy, MAX_SECTOR_SIZE};
+use crate::io::{AlignedBuffer, BufRead, IoBase, Read, Seek, SeekFrom, Write};
 use crate::time::{Date, DateTime, TimeProvider};
+use crate::watch::FsEvent;
 
-const MAX_FILE_SIZE: u32 = u32::MAX;
+pub(crate) const MAX_FILE_SIZE: u64 = 0xFFFF_FFFF;
+// Maximal file size if the FAT+ extension is enabled - the size field is extended by 6 bits
+pub(crate) const MAX_FAT_PLUS_FILE_SIZE: u64 = (1 << 38) - 1;
+
+// Maximal number of clusters marked as bad and replaced by a single write call
+const MAX_CLUSTER_REPLACEMENTS_PER_WRITE: u32 = 2;
+
+// Minimal size of the buffer used by `File::copy_range_from`. Bigger clusters are copied one cluster at a time.
+#[cfg(feature = "alloc")]
+pub(crate) const MIN_COPY_BUFFER_SIZE: usize = 64 * 1024;
+
+// Zeros written to fill a gap left by a seek beyond the end of a file
+static ZEROS: AlignedBuffer = AlignedBuffer([0; MAX_SECTOR_SIZE]);
 
 /// A FAT filesystem file object used for reading and writing data.
 ///
-/// This struct is created by the `open_file` or `create_file` methods on `Dir`.
+/// This struct is created by the `open_file` or `create_file` methods on `Dir` or by
+/// `FileSystem::open_file_by_cluster`.
+///
+/// File data is transferred directly between the buffer passed by the caller and the storage - a read or write
+/// of a run of physically contiguous clusters is a single storage request using (a part of) that buffer. Large
+/// cluster-aligned transfers do not need a buffering layer in the storage, which would add a copy.
+///
+/// Like `std::fs::File`, it allows seeking beyond the end of the file. Reading there returns no data and the file
+/// is not extended until data is written - the gap between the old end of the file and the written data is then
+/// filled with zeros.
 pub struct File<'a, IO: ReadWriteSeek, TP, OCC> {
     // Note first_cluster is None if file is empty
     first_cluster: Option<u32>,
     // Note: if offset points between clusters current_cluster is the previous cluster
     current_cluster: Option<u32>,
     // current position in this file
-    offset: u32,
+    offset: u64,
+    // number of bytes between the end of the file and the position set by a seek beyond it - offset points to the
+    // end of the file then
+    gap: u64,
     // file dir entry editor - None for root dir
     entry: Option<DirEntryEditor>,
     // file-system reference
     fs: &'a FileSystem<IO, TP, OCC>,
+    // Clusters of the chain used to seek without reading the FAT again
+    #[cfg(feature = "alloc")]
+    extent_cache: ExtentCache,
+    // Data read ahead by `BufRead::fill_buf`
+    #[cfg(feature = "alloc")]
+    read_buf: ReadBuffer,
+}
+
+// Runs of contiguous clusters (first cluster, number of clusters) covering the beginning of a cluster chain. Chains
+// can be changed through other `File` objects or by removing the entry, so the cache is only valid as long as
+// `FileSystem::chain_generation` is not changed.
+#[cfg(feature = "alloc")]
+#[derive(Clone, Default)]
+struct ExtentCache {
+    extents: Vec<(u32, u32)>,
+    generation: usize,
+}
+
+// Data following the position of a file read ahead from a single cluster. It is valid until the file is written or
+// truncated through the same `File` object.
+#[cfg(feature = "alloc")]
+#[derive(Default)]
+struct ReadBuffer {
+    data: Vec<u8>,
+    // Offset in the file of the first buffered byte
+    start: u64,
+    len: usize,
+    // Cluster containing the buffered data
+    cluster: u32,
 }
 
 /// An extent containing a file's data on disk.
 ///
 /// This is created by the `extents` method on `File`, and represents
 /// a byte range on disk that contains a file's data. All values
-/// are in bytes.
+/// are in bytes and relative to the beginning of the storage passed to
+/// `FileSystem::new` (e.g. the partition).
 #[derive(Clone, Debug, PartialEq)]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
 pub struct Extent {
     pub offset: u64,
     pub size: u32,
 }
 
+impl Extent {
+    /// Returns the first block of this extent for storage using `block_size` bytes long blocks (e.g. 512 for
+    /// virtio block devices).
+    ///
+    /// Add the first block of the partition to get the LBA on a partitioned disk.
+    ///
+    /// # Panics
+    ///
+    /// Will panic if `block_size` is zero.
+    #[must_use]
+    pub fn start_block(&self, block_size: u32) -> u64 {
+        self.offset / u64::from(block_size)
+    }
+
+    /// Returns the number of `block_size` bytes long blocks covering this extent.
+    ///
+    /// The last block can be only partially used by the file.
+    ///
+    /// # Panics
+    ///
+    /// Will panic if `block_size` is zero.
+    #[must_use]
+    pub fn block_count(&self, block_size: u32) -> u64 {
+        let block_size = u64::from(block_size);
+        let end = self.offset + u64::from(self.size);
+        (end + block_size - 1) / block_size - self.offset / block_size
+    }
+}
+
 impl<'a, IO: ReadWriteSeek, TP, OCC> File<'a, IO, TP, OCC> {
     pub(crate) fn new(
         first_cluster: Option<u32>,
@@ -47,74 +135,74 @@ impl<'a, IO: ReadWriteSeek, TP, OCC> File<'a, IO, TP, OCC> {
             fs,
             current_cluster: None, // cluster before first one
             offset: 0,
-        }
-    }
-
-    /// Truncate file in current position.
-    ///
-    /// # Errors
-    ///
-    /// `Error::Io` will be returned if the underlying storage object returned an I/O error.
-    ///
-    /// # Panics
-    ///
-    /// Will panic if this is the root directory.
-    pub fn truncate(&mut self) -> Result<(), Error<IO::Error>> {
-        trace!("File::truncate");
-        if let Some(ref mut e) = self.entry {
-            e.set_size(self.offset);
-            if self.offset == 0 {
-                e.set_first_cluster(None, self.fs.fat_type());
-            }
-        } else {
-            // Note: we cannot handle this case because there is no size field
-            panic!("Trying to truncate a file without an entry");
-        }
-        if let Some(current_cluster) = self.current_cluster {
-            // current cluster is none only if offset is 0
-            debug_assert!(self.offset > 0);
-            self.fs.truncate_cluster_chain(current_cluster)
-        } else {
-            debug_assert!(self.offset == 0);
-            if let Some(n) = self.first_cluster {
-                self.fs.free_cluster_chain(n)?;
-                self.first_cluster = None;
-            }
-            Ok(())
+            gap: 0,
+            #[cfg(feature = "alloc")]
+            extent_cache: ExtentCache::default(),
+            #[cfg(feature = "alloc")]
+            read_buf: ReadBuffer::default(),
         }
     }
 
     /// Get the extents of a file on disk.
     ///
     /// This returns an iterator over the byte ranges on-disk occupied by
-    /// this file.
+    /// this file. Physically contiguous clusters are merged into a single
+    /// extent, so a file stored in one run of clusters has one extent. The
+    /// last extent ends at the end of the file data, not at the end of its
+    /// cluster. Directories have no extents.
     pub fn extents(&mut self) -> impl Iterator<Item = Result<Extent, Error<IO::Error>>> + 'a {
         let fs = self.fs;
         let cluster_size = fs.cluster_size();
-        let Some(mut bytes_left) = self.size() else {
-            return None.into_iter().flatten();
+        let cluster_size = u64::from(cluster_size);
+        let mut bytes_left = match (self.size(), self.first_cluster) {
+            (Some(size), Some(_)) => size,
+            _ => 0,
         };
-        let Some(first) = self.first_cluster else {
-            return None.into_iter().flatten();
-        };
-
-        Some(
-            core::iter::once(Ok(first))
-                .chain(fs.cluster_iter(first))
-                .map(move |cluster_err| match cluster_err {
-                    Ok(cluster) => {
-                        let size = cluster_size.min(bytes_left);
-                        bytes_left -= size;
-                        Ok(Extent {
-                            offset: fs.offset_from_cluster(cluster),
-                            size,
-                        })
+        let mut clusters = self
+            .first_cluster
+            .map(|first| iter::once(Ok(first)).chain(fs.cluster_iter(first)))
+            .into_iter()
+            .flatten();
+        // cluster read after the end of the previous extent
+        let mut next = None;
+        iter::from_fn(move || {
+            if bytes_left == 0 {
+                return None;
+            }
+            let start = match next.take().or_else(|| clusters.next())? {
+                Ok(cluster) => cluster,
+                Err(err) => {
+                    bytes_left = 0;
+                    return Some(Err(err));
+                }
+            };
+            let mut size = cluster_size.min(bytes_left);
+            bytes_left -= size;
+            let mut last = start;
+            // Note: extent size is limited to 32 bits
+            while bytes_left > 0 && u32::try_from(size + cluster_size).is_ok() {
+                match clusters.next() {
+                    Some(Ok(cluster)) if cluster == last + 1 => {
+                        let n = cluster_size.min(bytes_left);
+                        size += n;
+                        bytes_left -= n;
+                        last = cluster;
+                    }
+                    Some(r) => {
+                        next = Some(r);
+                        break;
                     }
-                    Err(e) => Err(e),
-                }),
-        )
-        .into_iter()
-        .flatten()
+                    None => {
+                        // chain is shorter than the file
+                        bytes_left = 0;
+                    }
+                }
+            }
+            Some(Ok(Extent {
+                offset: fs.offset_from_cluster(start),
+                size: size as u32,
+            }))
+        })
     }
 
     pub(crate) fn abs_pos(&self) -> Option<u64> {
@@ -123,7 +211,7 @@ impl<'a, IO: ReadWriteSeek, TP, OCC> File<'a, IO, TP, OCC> {
         match self.current_cluster {
             Some(n) => {
                 let cluster_size = self.fs.cluster_size();
-                let offset_mod_cluster_size = self.offset % cluster_size;
+                let offset_mod_cluster_size = (self.offset % u64::from(cluster_size)) as u32;
                 let offset_in_cluster = if offset_mod_cluster_size == 0 {
                     // position points between clusters - we are returning previous cluster so
                     // offset must be set to the cluster size
@@ -148,7 +236,7 @@ impl<'a, IO: ReadWriteSeek, TP, OCC> File<'a, IO, TP, OCC> {
     /// Sets date and time of creation for this file.
     ///
     /// Note: it is set to a value from the `TimeProvider` when creating a file.
-    #[deprecated]
+    /// The directory entry is updated when the file is flushed or dropped.
     pub fn set_created(&mut self, date_time: DateTime) {
         if let Some(ref mut e) = self.entry {
             e.set_created(date_time);
@@ -157,8 +245,8 @@ impl<'a, IO: ReadWriteSeek, TP, OCC> File<'a, IO, TP, OCC> {
 
     /// Sets date of last access for this file.
     ///
-    /// Note: it is overwritten by a value from the `TimeProvider` on every file read operation.
-    #[deprecated]
+    /// Note: it is overwritten by a value from the `TimeProvider` on every file read operation if
+    /// `FsOptions::update_accessed_date` is enabled. Set it after reading to preserve the value.
     pub fn set_accessed(&mut self, date: Date) {
         if let Some(ref mut e) = self.entry {
             e.set_accessed(date);
@@ -168,20 +256,67 @@ impl<'a, IO: ReadWriteSeek, TP, OCC> File<'a, IO, TP, OCC> {
     /// Sets date and time of last modification for this file.
     ///
     /// Note: it is overwritten by a value from the `TimeProvider` on every file write operation.
-    #[deprecated]
+    /// Set it after writing to preserve the value (e.g. when extracting an archive).
     pub fn set_modified(&mut self, date_time: DateTime) {
         if let Some(ref mut e) = self.entry {
             e.set_modified(date_time);
         }
     }
 
-    fn size(&self) -> Option<u32> {
+    /// Sets attributes of this file.
+    ///
+    /// Only `READ_ONLY`, `HIDDEN`, `SYSTEM` and `ARCHIVE` attributes can be changed. Other bits in `attrs` are
+    /// ignored. The directory entry is updated when the file is flushed or dropped.
+    pub fn set_attributes(&mut self, attrs: FileAttributes) {
+        if let Some(ref mut e) = self.entry {
+            e.set_attributes(attrs);
+        }
+    }
+
+    /// Returns metadata of this file.
+    ///
+    /// Unlike `DirEntry::metadata` it includes changes that were not yet flushed to the directory entry.
+    ///
+    /// # Errors
+    ///
+    /// `Error::Io` will be returned if the underlying storage object returned an I/O error.
+    pub fn metadata(&self) -> Result<Metadata, Error<IO::Error>> {
+        let allocated_size = self.fs.allocated_size(self.first_cluster)?;
+        Ok(match self.entry {
+            Some(ref e) => {
+                Metadata::from_entry_data(e.inner(), self.fs.fat_type(), self.fs.options.fat_plus, allocated_size)
+            }
+            None => Metadata::root_dir(self.first_cluster, allocated_size),
+        })
+    }
+
+    /// Returns the file size or 0 for a directory.
+    ///
+    /// The size includes changes that were not yet flushed to the directory entry. Unlike `metadata` it does not read
+    /// the FAT. A gap left by a seek beyond the end of the file is not included until data is written after it.
+    #[must_use]
+    pub fn len(&self) -> u64 {
+        self.size().unwrap_or(0)
+    }
+
+    /// Returns `true` if the file contains no data. It is always `true` for a directory.
+    #[must_use]
+    pub fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+
+    pub(crate) fn size(&self) -> Option<u64> {
         match self.entry {
-            Some(ref e) => e.inner().size(),
+            Some(ref e) => e.inner().size(self.fs.options.fat_plus),
             None => None,
         }
     }
 
+    // Key of this file in the file lock table
+    fn lock_key(&self) -> u64 {
+        self.entry.as_ref().map_or(0, DirEntryEditor::pos)
+    }
+
     fn is_dir(&self) -> bool {
         match self.entry {
             Some(ref e) => e.inner().is_dir(),
@@ -189,12 +324,117 @@ impl<'a, IO: ReadWriteSeek, TP, OCC> File<'a, IO, TP, OCC> {
         }
     }
 
+    fn check_writable(&self) -> Result<(), Error<IO::Error>> {
+        self.fs.check_writable()?;
+        // Note: read-only attribute is not enforced for directories
+        match self.entry {
+            Some(ref e) if e.is_detached() => {
+                debug!("File has no directory entry");
+                Err(Error::ReadOnlyFile)
+            }
+            Some(ref e) if self.fs.options.enforce_read_only && e.inner().is_read_only() && !e.inner().is_dir() => {
+                debug!("File is read-only");
+                Err(Error::ReadOnlyFile)
+            }
+            _ => Ok(()),
+        }
+    }
+
+    // Writes a prefix of `buf` at `offset_in_fs`
+    fn write_to_storage(&self, offset_in_fs: u64, buf: &[u8]) -> Result<usize, IO::Error> {
+        let mut disk = self.fs.disk().lock();
+        disk.seek(SeekFrom::Start(offset_in_fs))?;
+        disk.write(buf)
+    }
+
+    // Returns the `n`-th cluster of the chain or the last one with its index if the chain is shorter
+    #[cfg(not(feature = "alloc"))]
+    fn nth_cluster(&mut self, first_cluster: u32, n: u32) -> Result<(u32, u32), Error<IO::Error>> {
+        let mut cluster = first_cluster;
+        let mut iter = self.fs.cluster_iter(first_cluster);
+        for i in 0..n {
+            cluster = match iter.next() {
+                Some(r) => r?,
+                None => return Ok((cluster, i)),
+            };
+        }
+        Ok((cluster, n))
+    }
+
+    // Returns the `n`-th cluster of the chain or the last one with its index if the chain is shorter.
+    // Clusters found in the FAT are added to the extent cache.
+    #[cfg(feature = "alloc")]
+    fn nth_cluster(&mut self, first_cluster: u32, n: u32) -> Result<(u32, u32), Error<IO::Error>> {
+        let generation = self.fs.chain_generation();
+        let extents = &mut self.extent_cache.extents;
+        if extents.is_empty() || self.extent_cache.generation != generation {
+            extents.clear();
+            extents.push((first_cluster, 1));
+            self.extent_cache.generation = generation;
+        }
+        let mut index = 0;
+        for &(start, len) in extents.iter() {
+            if n < index + len {
+                return Ok((start + (n - index), n));
+            }
+            index += len;
+        }
+        // Continue walking the chain from the last cached cluster
+        let mut index = index - 1;
+        let (start, len) = extents[extents.len() - 1];
+        let mut cluster = start + len - 1;
+        for r in self.fs.cluster_iter(cluster) {
+            let next_cluster = r?;
+            index += 1;
+            let last = extents.len() - 1;
+            if next_cluster == cluster + 1 {
+                extents[last].1 += 1;
+            } else {
+                extents.push((next_cluster, 1));
+            }
+            cluster = next_cluster;
+            if index == n {
+                break;
+            }
+        }
+        Ok((cluster, index))
+    }
+
+    // Without alloc there is no cache, but keeping the method avoids gating every call site
+    #[cfg_attr(not(feature = "alloc"), allow(clippy::unused_self))]
+    fn invalidate_extent_cache(&mut self) {
+        #[cfg(feature = "alloc")]
+        self.extent_cache.extents.clear();
+    }
+
+    // Without alloc there is no read-ahead buffer, but keeping the method avoids gating every call site
+    #[cfg_attr(not(feature = "alloc"), allow(clippy::unused_self))]
+    fn invalidate_read_buffer(&mut self) {
+        #[cfg(feature = "alloc")]
+        {
+            self.read_buf.len = 0;
+        }
+    }
+
+    // Number of buffered bytes following the current position
+    #[cfg(feature = "alloc")]
+    fn buffered_len(&self) -> usize {
+        let buf = &self.read_buf;
+        if self.gap == 0 && self.offset >= buf.start && self.offset < buf.start + buf.len as u64 {
+            (buf.start + buf.len as u64 - self.offset) as usize
+        } else {
+            0
+        }
+    }
+
     fn bytes_left_in_file(&self) -> Option<usize> {
         // Note: seeking beyond end of file is not allowed so overflow is impossible
-        self.size().map(|s| (s - self.offset) as usize)
+        self.size()
+            .map(|s| usize::try_from(s - self.offset).unwrap_or(usize::MAX))
     }
 
     fn set_first_cluster(&mut self, cluster: u32) {
+        self.invalidate_extent_cache();
         self.first_cluster = Some(cluster);
         if let Some(ref mut e) = self.entry {
             e.set_first_cluster(self.first_cluster, self.fs.fat_type());
@@ -205,10 +445,80 @@ impl<'a, IO: ReadWriteSeek, TP, OCC> File<'a, IO, TP, OCC> {
         self.first_cluster
     }
 
+    // Size of the cluster chain of this file
+    pub(crate) fn allocated_size(&self) -> Result<u64, Error<IO::Error>> {
+        self.fs.allocated_size(self.first_cluster)
+    }
+
+    // Reports a change of the file content to callbacks registered by `FileSystem::watch`. Directory streams are
+    // skipped because changes of their entries are reported separately.
+    fn notify_modified(&self) {
+        match self.entry {
+            Some(ref e) if !e.inner().is_dir() => {
+                if let Some(id) = e.id() {
+                    self.fs.notify(|| FsEvent::Modified { id });
+                }
+            }
+            _ => {}
+        }
+    }
+
     fn flush(&mut self) -> Result<(), Error<IO::Error>> {
         self.flush_dir_entry()?;
-        let mut disk = self.fs.disk.borrow_mut();
+        if self.fs.options.write_policy != WritePolicy::OnUnmount {
+            let mut disk = self.fs.disk().lock();
+            disk.flush()?;
+            self.fs.observe(|o| o.on_storage_flush());
+        }
+        Ok(())
+    }
+
+    /// Flushes data of this file to the storage.
+    ///
+    /// The directory entry is written only if the file size, its first cluster or attributes have changed, so the
+    /// data can be read back after a power failure, but changes of timestamps alone are not stored. It makes
+    /// overwriting a file in place (e.g. by a database) cheaper than `sync_all`. On TFAT volumes FAT changes are
+    /// committed if the file has been extended. The storage is flushed regardless of `FsOptions::write_policy`.
+    ///
+    /// # Errors
+    ///
+    /// `Error::Io` will be returned if the underlying storage object returned an I/O error.
+    pub fn sync_data(&mut self) -> Result<(), Error<IO::Error>> {
+        trace!("File::sync_data");
+        if let Some(ref mut e) = self.entry {
+            if e.is_dirty() {
+                e.flush(self.fs)?;
+                self.fs.commit_fat()?;
+            }
+        }
+        let mut disk = self.fs.disk().lock();
         disk.flush()?;
+        self.fs.observe(|o| o.on_storage_flush());
+        Ok(())
+    }
+
+    /// Flushes data and metadata of this file to the storage.
+    ///
+    /// The directory entry is written including timestamps, even if `FsOptions::lazy_timestamps` is enabled, and
+    /// the filesystem is flushed like by `FileSystem::flush`, which commits FAT changes on TFAT volumes.
+    ///
+    /// # Errors
+    ///
+    /// `Error::Io` will be returned if the underlying storage object returned an I/O error.
+    pub fn sync_all(&mut self) -> Result<(), Error<IO::Error>> {
+        trace!("File::sync_all");
+        if let Some(ref mut e) = self.entry {
+            e.flush_all(self.fs)?;
+        }
+        self.fs.flush()
+    }
+
+    // Writes the directory entry and flushes metadata if the write-through policy is used
+    fn write_through(&mut self) -> Result<(), Error<IO::Error>> {
+        if self.fs.options.write_policy == WritePolicy::WriteThrough {
+            self.flush_dir_entry()?;
+            self.fs.write_through()?;
+        }
         Ok(())
     }
 
@@ -218,48 +528,248 @@ impl<'a, IO: ReadWriteSeek, TP, OCC> File<'a, IO, TP, OCC> {
 }
 
 impl<IO: ReadWriteSeek, TP: TimeProvider, OCC> File<'_, IO, TP, OCC> {
+    /// Truncate file in current position.
+    ///
+    /// If the current position is beyond the end of the file, the file is extended with zeros instead.
+    ///
+    /// # Errors
+    ///
+    /// Errors that can be returned:
+    ///
+    /// * `Error::ReadOnlyFile` will be returned if the file has the `READ_ONLY` attribute and
+    ///   `FsOptions::enforce_read_only` is enabled.
+    /// * `Error::ReadOnlyFilesystem` will be returned if the filesystem is mounted read-only.
+    /// * `Error::Io` will be returned if the underlying storage object returned an I/O error.
+    ///
+    /// # Panics
+    ///
+    /// Will panic if this is the root directory.
+    pub fn truncate(&mut self) -> Result<(), Error<IO::Error>> {
+        trace!("File::truncate");
+        self.check_writable()?;
+        let fs = self.fs;
+        let _guard = fs.file_locks.write(self.lock_key());
+        if self.gap > 0 {
+            self.fill_gap()?;
+        }
+        self.invalidate_extent_cache();
+        self.invalidate_read_buffer();
+        if let Some(ref mut e) = self.entry {
+            e.set_size(self.offset);
+            if self.offset == 0 {
+                e.set_first_cluster(None, self.fs.fat_type());
+            }
+        } else {
+            // Note: we cannot handle this case because there is no size field
+            panic!("Trying to truncate a file without an entry");
+        }
+        if let Some(current_cluster) = self.current_cluster {
+            // current cluster is none only if offset is 0
+            debug_assert!(self.offset > 0);
+            self.fs.truncate_cluster_chain(current_cluster)?;
+        } else {
+            debug_assert!(self.offset == 0);
+            if let Some(n) = self.first_cluster {
+                self.fs.free_cluster_chain(n)?;
+                self.first_cluster = None;
+            }
+        }
+        self.notify_modified();
+        self.write_through()
+    }
+
     fn update_dir_entry_after_write(&mut self) {
         let offset = self.offset;
         if let Some(ref mut e) = self.entry {
             let now = self.fs.options.time_provider.get_current_date_time();
             e.set_modified(now);
-            if e.inner().size().map_or(false, |s| offset > s) {
+            if e.inner().size(self.fs.options.fat_plus).map_or(false, |s| offset > s) {
                 e.set_size(offset);
             }
         }
     }
-}
 
-impl<IO: ReadWriteSeek, TP, OCC> Drop for File<'_, IO, TP, OCC> {
-    fn drop(&mut self) {
-        if let Err(err) = self.flush() {
-            error!("flush failed {:?}", err);
+    /// Read data starting at the given offset without moving the current position of this file.
+    ///
+    /// Reads until `buf` is full or the end of the file is reached and returns the number of bytes read.
+    /// Reading at or after the end of the file returns `0`.
+    ///
+    /// # Errors
+    ///
+    /// Errors that can be returned:
+    ///
+    /// * `Error::InvalidInput` will be returned if `offset` does not fit in the maximal file size.
+    /// * `Error::Io` will be returned if the underlying storage object returned an I/O error.
+    pub fn read_at(&mut self, buf: &mut [u8], offset: u64) -> Result<usize, Error<IO::Error>> {
+        trace!("File::read_at {}", offset);
+        if self.size().map_or(false, |s| offset >= s) {
+            return Ok(0);
         }
+        self.with_offset(offset, |file| {
+            let mut read_bytes = 0;
+            while read_bytes < buf.len() {
+                match file.read(&mut buf[read_bytes..])? {
+                    0 => break,
+                    n => read_bytes += n,
+                }
+            }
+            Ok(read_bytes)
+        })
     }
-}
 
-// Note: derive cannot be used because of invalid bounds. See: https://github.com/rust-lang/rust/issues/26925
-impl<IO: ReadWriteSeek, TP, OCC> Clone for File<'_, IO, TP, OCC> {
-    fn clone(&self) -> Self {
-        File {
-            first_cluster: self.first_cluster,
-            current_cluster: self.current_cluster,
-            offset: self.offset,
-            entry: self.entry.clone(),
-            fs: self.fs,
+    /// Write data starting at the given offset without moving the current position of this file.
+    ///
+    /// Writes the whole `buf` unless the maximal file size is reached and returns the number of bytes written.
+    /// The file is extended if the written range ends after the current end of the file. If `offset` points after
+    /// the end of the file, the gap is filled with zeros.
+    ///
+    /// # Errors
+    ///
+    /// Errors that can be returned:
+    ///
+    /// * `Error::InvalidInput` will be returned if `offset` does not fit in the maximal file size.
+    /// * `Error::ReadOnlyFile` will be returned if the file has the `READ_ONLY` attribute and
+    ///   `FsOptions::enforce_read_only` is enabled.
+    /// * `Error::NotEnoughSpace` will be returned if there is not enough free space to extend the file.
+    /// * `Error::Io` will be returned if the underlying storage object returned an I/O error.
+    pub fn write_at(&mut self, buf: &[u8], offset: u64) -> Result<usize, Error<IO::Error>> {
+        trace!("File::write_at {}", offset);
+        self.with_offset(offset, |file| {
+            let mut written_bytes = 0;
+            while written_bytes < buf.len() {
+                match file.write(&buf[written_bytes..])? {
+                    0 => break,
+                    n => written_bytes += n,
+                }
+            }
+            Ok(written_bytes)
+        })
+    }
+
+    /// Copies `len` bytes starting at `src_offset` in `src` file to this file starting at `dst_offset` and returns
+    /// the number of copied bytes.
+    ///
+    /// `src` file can belong to another filesystem. Current positions of both files are not changed. Copying stops
+    /// early if the end of `src` file or the maximal file size is reached. This file is extended if the written range
+    /// ends after its current end and a gap between its end and `dst_offset` is filled with zeros. Data is
+    /// transferred through a buffer of at least one cluster with chunks aligned to clusters of this file, so runs of
+    /// contiguous clusters are read and written using large storage requests.
+    ///
+    /// # Errors
+    ///
+    /// Errors that can be returned:
+    ///
+    /// * `Error::InvalidInput` will be returned if `dst_offset` or `src_offset` does not fit in the maximal file size
+    ///   or if both files have the same data.
+    /// * `Error::ReadOnlyFile` will be returned if this file has the `READ_ONLY` attribute and
+    ///   `FsOptions::enforce_read_only` is enabled.
+    /// * `Error::NotEnoughSpace` will be returned if there is not enough free space to extend this file.
+    /// * `Error::Io` will be returned if the underlying storage object returned an I/O error.
+    #[cfg(feature = "alloc")]
+    pub fn copy_range_from<IO2, TP2, OCC2>(
+        &mut self,
+        src: &mut File<'_, IO2, TP2, OCC2>,
+        src_offset: u64,
+        dst_offset: u64,
+        len: u64,
+    ) -> Result<u64, Error<IO::Error>>
+    where
+        IO2: ReadWriteSeek<Error = IO::Error>,
+        TP2: TimeProvider,
+    {
+        trace!("File::copy_range_from {} {} {}", src_offset, dst_offset, len);
+        if self.fs.is_same_fs(src.fs) && src.first_cluster.is_some() && src.first_cluster == self.first_cluster {
+            error!("Source and destination are the same file");
+            return Err(Error::InvalidInput);
+        }
+        let cluster_size = self.fs.cluster_size() as usize;
+        let buf_len = usize::try_from(len).unwrap_or(usize::MAX).min(
+            cluster_size
+                .max(src.fs.cluster_size() as usize)
+                .max(MIN_COPY_BUFFER_SIZE),
+        );
+        let mut buf = vec![0_u8; buf_len];
+        let mut copied = 0_u64;
+        while copied < len {
+            // end chunks at cluster boundaries of the destination so whole clusters are written
+            let dst_pos = dst_offset + copied;
+            let offset_in_cluster = (dst_pos % cluster_size as u64) as usize;
+            let max_chunk_len = if buf_len > offset_in_cluster {
+                buf_len - offset_in_cluster
+            } else {
+                buf_len
+            };
+            let chunk_len = usize::try_from(len - copied).map_or(max_chunk_len, |n| n.min(max_chunk_len));
+            let read_bytes = src.read_at(&mut buf[..chunk_len], src_offset + copied)?;
+            if read_bytes == 0 {
+                break;
+            }
+            let written_bytes = self.write_at(&buf[..read_bytes], dst_pos)?;
+            copied += written_bytes as u64;
+            if written_bytes < read_bytes {
+                break;
+            }
         }
+        Ok(copied)
     }
-}
 
-impl<IO: ReadWriteSeek, TP, OCC> IoBase for File<'_, IO, TP, OCC> {
-    type Error = Error<IO::Error>;
-}
+    fn with_offset<R, F>(&mut self, offset: u64, f: F) -> Result<R, Error<IO::Error>>
+    where
+        F: FnOnce(&mut Self) -> Result<R, Error<IO::Error>>,
+    {
+        // Note: cluster chain is never shortened by reads and writes so the saved position stays valid
+        let saved_offset = self.offset;
+        let saved_gap = self.gap;
+        let saved_cluster = self.current_cluster;
+        let result = self.seek(SeekFrom::Start(offset)).and_then(|_| f(self));
+        self.offset = saved_offset;
+        self.gap = saved_gap;
+        self.current_cluster = saved_cluster;
+        result
+    }
 
-impl<IO: ReadWriteSeek, TP: TimeProvider, OCC> Read for File<'_, IO, TP, OCC> {
-    fn read(&mut self, buf: &mut [u8]) -> Result<usize, Self::Error> {
+    // Extends an I/O operation of `size` bytes ending at the end of `cluster` over the following clusters as long as
+    // they are physically contiguous, so it can be done using a single storage request. Returns the new size.
+    fn extend_contiguous(
+        &self,
+        cluster: u32,
+        mut size: usize,
+        max_size: usize,
+        alloc: bool,
+    ) -> Result<usize, Error<IO::Error>> {
+        let cluster_size = self.fs.cluster_size() as usize;
+        let mut last_cluster = cluster;
+        while size < max_size {
+            let next_cluster = match self.fs.cluster_iter(last_cluster).next() {
+                Some(r) => r?,
+                None if alloc => match self.fs.alloc_cluster(Some(last_cluster), self.is_dir()) {
+                    Ok(n) => n,
+                    // Write as much as possible
+                    Err(Error::NotEnoughSpace) => break,
+                    Err(err) => return Err(err),
+                },
+                None => break,
+            };
+            if next_cluster != last_cluster + 1 {
+                break;
+            }
+            last_cluster = next_cluster;
+            size = max_size.min(size + cluster_size);
+        }
+        Ok(size)
+    }
+
+    // Returns the cluster containing the last byte of a contiguous I/O operation
+    fn last_cluster(first_cluster: u32, offset_in_cluster: u32, bytes: usize, cluster_size: u32) -> u32 {
+        first_cluster + ((offset_in_cluster as usize + bytes - 1) / cluster_size as usize) as u32
+    }
+
+    // Reads data without taking the file lock - used for directories which are protected by directory locks
+    #[cfg_attr(feature = "tracing", tracing::instrument(level = "trace", skip_all, fields(offset = self.offset, len = buf.len())))]
+    pub(crate) fn read_data(&mut self, buf: &mut [u8]) -> Result<usize, Error<IO::Error>> {
         trace!("File::read");
         let cluster_size = self.fs.cluster_size();
-        let current_cluster_opt = if self.offset % cluster_size == 0 {
+        let current_cluster_opt = if self.offset % u64::from(cluster_size) == 0 {
             // next cluster
             match self.current_cluster {
                 None => self.first_cluster,
@@ -278,62 +788,87 @@ impl<IO: ReadWriteSeek, TP: TimeProvider, OCC> Read for File<'_, IO, TP, OCC> {
         let Some(current_cluster) = current_cluster_opt else {
             return Ok(0);
         };
-        let offset_in_cluster = self.offset % cluster_size;
+        let offset_in_cluster = (self.offset % u64::from(cluster_size)) as u32;
         let bytes_left_in_cluster = (cluster_size - offset_in_cluster) as usize;
-        let bytes_left_in_file = self.bytes_left_in_file().unwrap_or(bytes_left_in_cluster);
-        let read_size = buf.len().min(bytes_left_in_cluster).min(bytes_left_in_file);
+        let max_read_size = buf.len().min(self.bytes_left_in_file().unwrap_or(usize::MAX));
+        let mut read_size = max_read_size.min(bytes_left_in_cluster);
         if read_size == 0 {
             return Ok(0);
         }
-        trace!("read {} bytes in cluster {}", read_size, current_cluster);
+        if read_size < max_read_size {
+            read_size = self.extend_contiguous(current_cluster, read_size, max_read_size, false)?;
+        }
+        trace!("read {} bytes starting in cluster {}", read_size, current_cluster);
         let offset_in_fs = self.fs.offset_from_cluster(current_cluster) + u64::from(offset_in_cluster);
         let read_bytes = {
-            let mut disk = self.fs.disk.borrow_mut();
+            let mut disk = self.fs.disk().lock();
             disk.seek(SeekFrom::Start(offset_in_fs))?;
             disk.read(&mut buf[..read_size])?
         };
+        self.fs.observe(|o| o.on_storage_read(offset_in_fs, read_bytes));
         if read_bytes == 0 {
             return Ok(0);
         }
-        self.offset += read_bytes as u32;
-        self.current_cluster = Some(current_cluster);
+        self.offset += read_bytes as u64;
+        self.current_cluster = Some(Self::last_cluster(
+            current_cluster,
+            offset_in_cluster,
+            read_bytes,
+            cluster_size,
+        ));
 
         if let Some(ref mut e) = self.entry {
-            if self.fs.options.update_accessed_date {
+            if self.fs.options.update_accessed_date && !self.fs.is_read_only() {
                 let now = self.fs.options.time_provider.get_current_date();
                 e.set_accessed(now);
             }
         }
         Ok(read_bytes)
     }
-}
 
-#[cfg(feature = "std")]
-impl<IO: ReadWriteSeek, TP: TimeProvider, OCC> std::io::Read for File<'_, IO, TP, OCC>
-where
-    std::io::Error: From<Error<IO::Error>>,
-{
-    fn read(&mut self, buf: &mut [u8]) -> std::io::Result<usize> {
-        Ok(Read::read(self, buf)?)
+    // Writes zeros between the end of the file and the position set by a seek beyond it
+    fn fill_gap(&mut self) -> Result<(), Error<IO::Error>> {
+        trace!("File::fill_gap {}", self.gap);
+        let mut gap = core::mem::take(&mut self.gap);
+        while gap > 0 {
+            let len = usize::try_from(gap).unwrap_or(usize::MAX).min(ZEROS.0.len());
+            match self.write_data(&ZEROS.0[..len]) {
+                Ok(0) => return Err(Error::WriteZero),
+                Ok(n) => gap -= n as u64,
+                Err(err) => {
+                    // keep the position requested by the seek
+                    self.gap = gap;
+                    return Err(err);
+                }
+            }
+        }
+        Ok(())
     }
-}
 
-impl<IO: ReadWriteSeek, TP: TimeProvider, OCC> Write for File<'_, IO, TP, OCC> {
-    fn write(&mut self, buf: &[u8]) -> Result<usize, Self::Error> {
+    // Writes data without taking the file lock - used for directories which are protected by directory locks
+    #[cfg_attr(feature = "tracing", tracing::instrument(level = "trace", skip_all, fields(offset = self.offset, len = buf.len())))]
+    pub(crate) fn write_data(&mut self, buf: &[u8]) -> Result<usize, Error<IO::Error>> {
         trace!("File::write");
+        if self.gap > 0 && !buf.is_empty() {
+            self.fill_gap()?;
+        }
         let cluster_size = self.fs.cluster_size();
-        let offset_in_cluster = self.offset % cluster_size;
+        let offset_in_cluster = (self.offset % u64::from(cluster_size)) as u32;
         let bytes_left_in_cluster = (cluster_size - offset_in_cluster) as usize;
-        let bytes_left_until_max_file_size = (MAX_FILE_SIZE - self.offset) as usize;
+        let bytes_left_until_max_file_size =
+            usize::try_from(self.fs.max_file_size() - self.offset).unwrap_or(usize::MAX);
         let write_size = buf.len().min(bytes_left_in_cluster).min(bytes_left_until_max_file_size);
         // Exit early if we are going to write no data
         if write_size == 0 {
             return Ok(0);
         }
+        self.check_writable()?;
+        self.invalidate_read_buffer();
         // Mark the volume 'dirty'
         self.fs.set_dirty_flag(true)?;
         // Get cluster for write possibly allocating new one
-        let current_cluster = if self.offset % cluster_size == 0 {
+        let mut newly_allocated = false;
+        let mut current_cluster = if self.offset % u64::from(cluster_size) == 0 {
             // next cluster
             let next_cluster = match self.current_cluster {
                 None => self.first_cluster,
@@ -355,6 +890,7 @@ impl<IO: ReadWriteSeek, TP: TimeProvider, OCC> Write for File<'_, IO, TP, OCC> {
                 if self.first_cluster.is_none() {
                     self.set_first_cluster(new_cluster);
                 }
+                newly_allocated = true;
                 new_cluster
             }
         } else {
@@ -364,22 +900,171 @@ impl<IO: ReadWriteSeek, TP: TimeProvider, OCC> Write for File<'_, IO, TP, OCC> {
                 None => panic!("Offset inside cluster but no cluster allocated"),
             }
         };
-        trace!("write {} bytes in cluster {}", write_size, current_cluster);
-        let offset_in_fs = self.fs.offset_from_cluster(current_cluster) + u64::from(offset_in_cluster);
-        let written_bytes = {
-            let mut disk = self.fs.disk.borrow_mut();
-            disk.seek(SeekFrom::Start(offset_in_fs))?;
-            disk.write(&buf[..write_size])?
+        let max_failures = self.fs.options.mark_bad_after_write_failures;
+        // Clusters written by a failed write cannot be replaced if the write spans many of them
+        let max_write_size = buf.len().min(bytes_left_until_max_file_size);
+        let mut write_size = write_size;
+        if write_size < max_write_size && (max_failures == 0 || !newly_allocated) {
+            write_size = self.extend_contiguous(current_cluster, write_size, max_write_size, max_failures == 0)?;
+        }
+        trace!("write {} bytes starting in cluster {}", write_size, current_cluster);
+        let mut failures = 0;
+        let mut replacements = 0;
+        let written_bytes = loop {
+            let offset_in_fs = self.fs.offset_from_cluster(current_cluster) + u64::from(offset_in_cluster);
+            match self.write_to_storage(offset_in_fs, &buf[..write_size]) {
+                Ok(n) => {
+                    self.fs.observe(|o| o.on_storage_write(offset_in_fs, n));
+                    break n;
+                }
+                // running out of storage space is not a media failure, replacing the cluster would not help
+                Err(err) if !newly_allocated || max_failures == 0 || err.is_out_of_space() => return Err(err.into()),
+                Err(err) => {
+                    failures += 1;
+                    if failures >= max_failures {
+                        if replacements == MAX_CLUSTER_REPLACEMENTS_PER_WRITE {
+                            // the storage is failing, not the clusters
+                            return Err(err.into());
+                        }
+                        replacements += 1;
+                        // Cluster has no data yet, so it can be replaced by another one
+                        current_cluster = self.fs.replace_bad_cluster(self.current_cluster, current_cluster)?;
+                        self.invalidate_extent_cache();
+                        if self.current_cluster.is_none() {
+                            self.set_first_cluster(current_cluster);
+                        }
+                        failures = 0;
+                    }
+                }
+            }
         };
         if written_bytes == 0 {
             return Ok(0);
         }
         // some bytes were writter - update position and optionally size
-        self.offset += written_bytes as u32;
-        self.current_cluster = Some(current_cluster);
+        self.offset += written_bytes as u64;
+        self.current_cluster = Some(Self::last_cluster(
+            current_cluster,
+            offset_in_cluster,
+            written_bytes,
+            cluster_size,
+        ));
         self.update_dir_entry_after_write();
+        self.notify_modified();
         Ok(written_bytes)
     }
+}
+
+impl<IO: ReadWriteSeek, TP, OCC> Drop for File<'_, IO, TP, OCC> {
+    fn drop(&mut self) {
+        if let Err(err) = self.flush() {
+            error!("flush failed {:?}", err);
+        }
+    }
+}
+
+// Note: derive cannot be used because of invalid bounds. See: https://github.com/rust-lang/rust/issues/26925
+impl<IO: ReadWriteSeek, TP, OCC> Clone for File<'_, IO, TP, OCC> {
+    fn clone(&self) -> Self {
+        File {
+            first_cluster: self.first_cluster,
+            current_cluster: self.current_cluster,
+            offset: self.offset,
+            gap: self.gap,
+            entry: self.entry.clone(),
+            fs: self.fs,
+            #[cfg(feature = "alloc")]
+            extent_cache: self.extent_cache.clone(),
+            #[cfg(feature = "alloc")]
+            read_buf: ReadBuffer::default(),
+        }
+    }
+}
+
+impl<IO: ReadWriteSeek, TP, OCC> IoBase for File<'_, IO, TP, OCC> {
+    type Error = Error<IO::Error>;
+}
+
+impl<IO: ReadWriteSeek, TP: TimeProvider, OCC> Read for File<'_, IO, TP, OCC> {
+    fn read(&mut self, buf: &mut [u8]) -> Result<usize, Self::Error> {
+        let fs = self.fs;
+        let _guard = fs.file_locks.read(self.lock_key());
+        self.read_data(buf)
+    }
+}
+
+/// Data is read ahead from at most one cluster into a buffer allocated on the first use. The buffer is discarded when
+/// the file is written or truncated through the same object, but not when it is changed through another `File`.
+#[cfg(feature = "alloc")]
+impl<IO: ReadWriteSeek, TP: TimeProvider, OCC> BufRead for File<'_, IO, TP, OCC> {
+    fn fill_buf(&mut self) -> Result<&[u8], Self::Error> {
+        if self.buffered_len() == 0 {
+            let fs = self.fs;
+            let _guard = fs.file_locks.read(self.lock_key());
+            let cluster_size = fs.cluster_size() as usize;
+            let mut data = core::mem::take(&mut self.read_buf.data);
+            data.resize(cluster_size, 0);
+            let len_in_cluster = cluster_size - (self.offset % cluster_size as u64) as usize;
+            // Note: reading only advances the position, so restoring it is enough
+            let (offset, current_cluster) = (self.offset, self.current_cluster);
+            let result = self.read_data(&mut data[..len_in_cluster]);
+            let cluster = self.current_cluster.unwrap_or(0);
+            self.offset = offset;
+            self.current_cluster = current_cluster;
+            self.read_buf = ReadBuffer {
+                data,
+                start: offset,
+                len: 0,
+                cluster,
+            };
+            self.read_buf.len = result?;
+        }
+        let begin = (self.offset - self.read_buf.start) as usize;
+        Ok(&self.read_buf.data[begin..self.read_buf.len])
+    }
+
+    fn consume(&mut self, amt: usize) {
+        let amt = amt.min(self.buffered_len());
+        if amt > 0 {
+            // all positions after the beginning of the buffered data belong to its cluster
+            self.offset += amt as u64;
+            self.current_cluster = Some(self.read_buf.cluster);
+        }
+    }
+}
+
+#[cfg(feature = "std")]
+impl<IO: ReadWriteSeek, TP: TimeProvider, OCC> std::io::Read for File<'_, IO, TP, OCC>
+where
+    std::io::Error: From<Error<IO::Error>>,
+{
+    fn read(&mut self, buf: &mut [u8]) -> std::io::Result<usize> {
+        Ok(Read::read(self, buf)?)
+    }
+}
+
+#[cfg(all(feature = "std", feature = "alloc"))]
+impl<IO: ReadWriteSeek, TP: TimeProvider, OCC> std::io::BufRead for File<'_, IO, TP, OCC>
+where
+    std::io::Error: From<Error<IO::Error>>,
+{
+    fn fill_buf(&mut self) -> std::io::Result<&[u8]> {
+        Ok(BufRead::fill_buf(self)?)
+    }
+
+    fn consume(&mut self, amt: usize) {
+        BufRead::consume(self, amt);
+    }
+}
+
+impl<IO: ReadWriteSeek, TP: TimeProvider, OCC> Write for File<'_, IO, TP, OCC> {
+    fn write(&mut self, buf: &[u8]) -> Result<usize, Self::Error> {
+        let fs = self.fs;
+        let _guard = fs.file_locks.write(self.lock_key());
+        let written = self.write_data(buf)?;
+        self.write_through()?;
+        Ok(written)
+    }
 
     fn flush(&mut self) -> Result<(), Self::Error> {
         Self::flush(self)
@@ -408,32 +1093,40 @@ impl<IO: ReadWriteSeek, TP, OCC> Seek for File<'_, IO, TP, OCC> {
     fn seek(&mut self, pos: SeekFrom) -> Result<u64, Self::Error> {
         trace!("File::seek");
         let size_opt = self.size();
-        let new_offset_opt: Option<u32> = match pos {
-            SeekFrom::Current(x) => i64::from(self.offset)
-                .checked_add(x)
-                .and_then(|n| u32::try_from(n).ok()),
-            SeekFrom::Start(x) => u32::try_from(x).ok(),
+        let max_file_size = self.fs.max_file_size();
+        let new_offset_opt: Option<u64> = match pos {
+            SeekFrom::Current(x) => i64::try_from(self.offset + self.gap)
+                .ok()
+                .and_then(|n| n.checked_add(x))
+                .and_then(|n| u64::try_from(n).ok()),
+            SeekFrom::Start(x) => Some(x),
             SeekFrom::End(o) => size_opt
-                .and_then(|s| i64::from(s).checked_add(o))
-                .and_then(|n| u32::try_from(n).ok()),
-        };
+                .and_then(|s| i64::try_from(s).ok())
+                .and_then(|s| s.checked_add(o))
+                .and_then(|n| u64::try_from(n).ok()),
+        }
+        .filter(|&n| n <= max_file_size);
         let Some(mut new_offset) = new_offset_opt else {
             error!("Invalid seek offset");
             return Err(Error::InvalidInput);
         };
-        if let Some(size) = size_opt {
-            if new_offset > size {
-                warn!("Seek beyond the end of the file");
+        // position beyond the end of the file is kept as a gap filled by the next write
+        let mut gap = match size_opt {
+            Some(size) if new_offset > size => {
+                let gap = new_offset - size;
                 new_offset = size;
+                gap
             }
-        }
+            _ => 0,
+        };
         trace!("file seek {} -> {} - entry {:?}", self.offset, new_offset, self.entry);
         if new_offset == self.offset {
             // position is the same - nothing to do
-            return Ok(u64::from(self.offset));
+            self.gap = gap;
+            return Ok(self.offset + self.gap);
         }
-        let new_offset_in_clusters = self.fs.clusters_from_bytes(u64::from(new_offset));
-        let old_offset_in_clusters = self.fs.clusters_from_bytes(u64::from(self.offset));
+        let new_offset_in_clusters = self.fs.clusters_from_bytes(new_offset);
+        let old_offset_in_clusters = self.fs.clusters_from_bytes(self.offset);
         let new_cluster = if new_offset == 0 {
             None
         } else if new_offset_in_clusters == old_offset_in_clusters {
@@ -444,16 +1137,10 @@ impl<IO: ReadWriteSeek, TP, OCC> Seek for File<'_, IO, TP, OCC> {
             // Note: new_offset_in_clusters cannot be 0 here because new_offset is not 0
             debug_assert!(new_offset_in_clusters > 0);
             let clusters_to_skip = new_offset_in_clusters - 1;
-            let mut cluster = first_cluster;
-            let mut iter = self.fs.cluster_iter(first_cluster);
-            for i in 0..clusters_to_skip {
-                cluster = if let Some(r) = iter.next() {
-                    r?
-                } else {
-                    // cluster chain ends before the new position - seek to the end of the last cluster
-                    new_offset = self.fs.bytes_from_clusters(i + 1) as u32;
-                    break;
-                };
+            let (cluster, index) = self.nth_cluster(first_cluster, clusters_to_skip)?;
+            if index < clusters_to_skip {
+                // cluster chain ends before the new position - seek to the end of the last cluster
+                new_offset = self.fs.bytes_from_clusters(index + 1);
             }
             Some(cluster)
         } else {
@@ -461,9 +1148,14 @@ impl<IO: ReadWriteSeek, TP, OCC> Seek for File<'_, IO, TP, OCC> {
             new_offset = 0;
             None
         };
+        if new_offset < size_opt.unwrap_or(0) {
+            // cluster chain is shorter than the file - position cannot be beyond its end
+            gap = 0;
+        }
         self.offset = new_offset;
+        self.gap = gap;
         self.current_cluster = new_cluster;
-        Ok(u64::from(self.offset))
+        Ok(self.offset + self.gap)
     }
 }
 
@@ -480,6 +1172,145 @@ where
 #[cfg(test)]
 mod tests {
     use super::*;
+    use crate::{FsOptions, StdIoWrapper};
+    use std::cell::RefCell;
+    use std::io::Cursor;
+    use std::ops::Range;
+    use std::rc::Rc;
+
+    // Storage recording memory ranges of buffers passed to it
+    struct RecordingStorage {
+        inner: StdIoWrapper<Cursor<Vec<u8>>>,
+        buffers: Rc<RefCell<Vec<Range<usize>>>>,
+        alignment: usize,
+    }
+
+    impl RecordingStorage {
+        fn new(buffers: &Rc<RefCell<Vec<Range<usize>>>>, alignment: usize) -> Self {
+            Self {
+                inner: StdIoWrapper::new(Cursor::new(std::fs::read("resources/fat32.img").unwrap())),
+                buffers: Rc::clone(buffers),
+                alignment,
+            }
+        }
+    }
+
+    impl RecordingStorage {
+        fn record(&self, buf: &[u8]) {
+            let start = buf.as_ptr() as usize;
+            self.buffers.borrow_mut().push(start..start + buf.len());
+        }
+    }
+
+    impl IoBase for RecordingStorage {
+        type Error = std::io::Error;
+
+        fn buffer_alignment(&self) -> usize {
+            self.alignment
+        }
+    }
+
+    impl Read for RecordingStorage {
+        fn read(&mut self, buf: &mut [u8]) -> Result<usize, Self::Error> {
+            self.record(buf);
+            self.inner.read(buf)
+        }
+    }
+
+    impl Write for RecordingStorage {
+        fn write(&mut self, buf: &[u8]) -> Result<usize, Self::Error> {
+            self.record(buf);
+            self.inner.write(buf)
+        }
+
+        fn flush(&mut self) -> Result<(), Self::Error> {
+            self.inner.flush()
+        }
+    }
+
+    impl Seek for RecordingStorage {
+        fn seek(&mut self, pos: SeekFrom) -> Result<u64, Self::Error> {
+            self.inner.seek(pos)
+        }
+    }
+
+    // Returns the number of bytes transferred using the memory of `buf`
+    fn bytes_transferred_in_place(buffers: &RefCell<Vec<Range<usize>>>, buf: &[u8]) -> usize {
+        let range = buf.as_ptr() as usize..buf.as_ptr() as usize + buf.len();
+        buffers
+            .borrow_mut()
+            .drain(..)
+            .filter(|r| r.start >= range.start && r.end <= range.end)
+            .map(|r| r.len())
+            .sum()
+    }
+
+    #[test]
+    fn test_data_is_not_copied() {
+        let buffers = Rc::new(RefCell::new(Vec::new()));
+        let fs = FileSystem::new(RecordingStorage::new(&buffers, 1), FsOptions::new()).unwrap();
+        let data: Vec<u8> = (0..fs.cluster_size() * 8).map(|i| i as u8).collect();
+        let mut file = fs.root_dir().create_file("zero-copy.bin").unwrap();
+        buffers.borrow_mut().clear();
+        file.write_all(&data).unwrap();
+        assert_eq!(bytes_transferred_in_place(&buffers, &data), data.len());
+        file.seek(SeekFrom::Start(0)).unwrap();
+        let mut buf = vec![0_u8; data.len()];
+        file.read_exact(&mut buf).unwrap();
+        assert_eq!(bytes_transferred_in_place(&buffers, &buf), buf.len());
+        assert_eq!(buf, data);
+    }
+
+    #[test]
+    fn test_storage_buffers_are_aligned() {
+        const ALIGNMENT: usize = 512;
+        let buffers = Rc::new(RefCell::new(Vec::new()));
+        let fs = FileSystem::new(RecordingStorage::new(&buffers, ALIGNMENT), FsOptions::new()).unwrap();
+        let data: Vec<u8> = (0..fs.cluster_size() * 4).map(|i| i as u8).collect();
+        let mut file = fs.root_dir().create_file("aligned.bin").unwrap();
+        // Start with an unaligned address
+        let offset = ALIGNMENT - data.as_ptr() as usize % ALIGNMENT + 1;
+        file.write_all(&data[offset..]).unwrap();
+        file.seek(SeekFrom::Start(0)).unwrap();
+        let mut buf = vec![0_u8; data.len()];
+        file.read_exact(&mut buf[offset..]).unwrap();
+        assert_eq!(&buf[offset..], &data[offset..]);
+        drop(file);
+        fs.unmount().unwrap();
+        // Metadata accesses (boot sector, FAT and directory entries) are aligned too
+        assert!(!buffers.borrow().is_empty());
+        for r in buffers.borrow().iter() {
+            assert!(r.start % ALIGNMENT == 0, "unaligned buffer {:?}", r);
+        }
+    }
+
+    #[test]
+    fn test_copy_range_uses_large_transfers() {
+        let buffers = Rc::new(RefCell::new(Vec::new()));
+        let fs = FileSystem::new(RecordingStorage::new(&buffers, 1), FsOptions::new()).unwrap();
+        let cluster_size = fs.cluster_size() as usize;
+        let data: Vec<u8> = (0..cluster_size * 8).map(|i| i as u8).collect();
+        let mut src = fs.root_dir().create_file("src.bin").unwrap();
+        src.write_all(&data).unwrap();
+        let mut dst = fs.root_dir().create_file("dst.bin").unwrap();
+        buffers.borrow_mut().clear();
+        assert_eq!(
+            dst.copy_range_from(&mut src, 0, 0, data.len() as u64).unwrap(),
+            data.len() as u64
+        );
+        let max_transfer = buffers.borrow().iter().map(Range::len).max().unwrap();
+        assert_eq!(max_transfer, data.len());
+        let mut buf = vec![0_u8; data.len()];
+        dst.read_exact(&mut buf).unwrap();
+        assert_eq!(buf, data);
+    }
+
+    #[test]
+    fn test_invalid_buffer_alignment() {
+        let buffers = Rc::new(RefCell::new(Vec::new()));
+        let result = FileSystem::new(RecordingStorage::new(&buffers, 3), FsOptions::new());
+        assert!(matches!(result, Err(Error::InvalidInput)));
+    }
 
     #[test]
     fn test_extent_creation() {
@@ -548,9 +1379,28 @@ mod tests {
         assert_eq!(extent.size, u32::MAX);
     }
 
+    #[test]
+    fn test_extent_blocks() {
+        let extent = Extent {
+            offset: 4096,
+            size: 2048,
+        };
+        assert_eq!(extent.start_block(512), 8);
+        assert_eq!(extent.block_count(512), 4);
+        assert_eq!(extent.start_block(4096), 1);
+        assert_eq!(extent.block_count(4096), 1);
+        let extent = Extent {
+            offset: 4096,
+            size: 1000,
+        };
+        assert_eq!(extent.block_count(512), 2);
+        let extent = Extent { offset: 512, size: 0 };
+        assert_eq!(extent.block_count(512), 0);
+    }
+
     #[test]
     fn test_extent_multiple_extents() {
-        let extents = vec![
+        let extents = [
             Extent {
                 offset: 1024,
                 size: 512,
@@ -1,21 +1,30 @@
 #[cfg(all(not(feature = "std"), feature = "alloc"))]
-use alloc::string::String;
+use alloc::{boxed::Box, string::String, vec, vec::Vec};
 use core::borrow::BorrowMut;
-use core::cell::{Cell, RefCell};
 use core::convert::TryFrom;
 use core::fmt::Debug;
 use core::marker::PhantomData;
+use core::sync::atomic::{AtomicUsize, Ordering};
 
-use crate::boot_sector::{format_boot_sector, BiosParameterBlock, BootSector};
+use crate::boot_sector::{format_boot_sector, BiosParameterBlock, BootCode, BootSector, BOOT_CODE_SIZE};
 use crate::dir::{Dir, DirRawStream};
-use crate::dir_entry::{DirFileEntryData, FileAttributes, SFN_PADDING, SFN_SIZE};
+use crate::dir_entry::{
+    DirEntry, DirEntryEditor, DirFileEntryData, EntryId, FileAttributes, Metadata, DIR_ENTRY_SIZE, SFN_PADDING,
+    SFN_SIZE,
+};
 use crate::error::Error;
-use crate::file::File;
-use crate::io::{self, IoBase, Read, ReadLeExt, Seek, SeekFrom, Write, WriteLeExt};
+use crate::file::{File, MAX_FAT_PLUS_FILE_SIZE, MAX_FILE_SIZE};
+use crate::io::{self, AlignedStorage, IoBase, Read, ReadLeExt, Seek, SeekFrom, Write, WriteLeExt};
+use crate::observer::{FsObserver, ObserverRef};
+use crate::sync::{ChangeTracker, LockTable, Mutex, MutexGuard};
 use crate::table::{
-    alloc_cluster, count_free_clusters, format_fat, read_fat_flags, ClusterIterator, RESERVED_FAT_ENTRIES,
+    alloc_cluster, count_free_clusters, find_first_free_cluster, find_next_free_cluster, format_fat, read_fat,
+    read_fat_flags, write_fat, ClusterIterator, FatValue, RESERVED_FAT_ENTRIES,
 };
-use crate::time::{DefaultTimeProvider, TimeProvider};
+use crate::time::{DateTime, DefaultTimeProvider, TimeProvider};
+use crate::watch::FsEvent;
+#[cfg(feature = "alloc")]
+use crate::watch::{WatchId, Watchers};
 
 // FAT implementation based on:
 //   http://wiki.osdev.org/FAT
@@ -25,6 +34,7 @@ use crate::time::{DefaultTimeProvider, TimeProvider};
 ///
 /// `FatType` values are based on the size of File Allocation Table entry.
 #[derive(Copy, Clone, Eq, PartialEq, Debug)]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
 pub enum FatType {
     /// 12 bits per FAT entry
     ///
@@ -82,6 +92,7 @@ impl FatType {
 
 /// A FAT volume status flags retrived from the Boot Sector and the allocation table second entry.
 #[derive(Copy, Clone, Eq, PartialEq, Debug)]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
 pub struct FsStatusFlags {
     pub(crate) dirty: bool,
     pub(crate) io_error: bool,
@@ -121,6 +132,48 @@ impl FsStatusFlags {
     }
 }
 
+/// A decoded entry of the File Allocation Table.
+///
+/// It is returned by `FileSystem::fat_entry` and written by `FileSystem::set_fat_entry`. Reserved values are
+/// decoded as `EndOfChain`, like the rest of the crate does.
+#[cfg(feature = "unsafe_raw")]
+#[derive(Copy, Clone, Eq, PartialEq, Debug)]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+pub enum FatEntry {
+    /// The cluster is free.
+    Free,
+    /// The cluster is allocated and the chain continues with the given cluster.
+    Next(u32),
+    /// The cluster is marked as bad.
+    Bad,
+    /// The cluster is the last cluster of a chain.
+    EndOfChain,
+}
+
+#[cfg(feature = "unsafe_raw")]
+impl From<FatValue> for FatEntry {
+    fn from(value: FatValue) -> Self {
+        match value {
+            FatValue::Free => Self::Free,
+            FatValue::Data(n) => Self::Next(n),
+            FatValue::Bad => Self::Bad,
+            FatValue::EndOfChain => Self::EndOfChain,
+        }
+    }
+}
+
+#[cfg(feature = "unsafe_raw")]
+impl From<FatEntry> for FatValue {
+    fn from(entry: FatEntry) -> Self {
+        match entry {
+            FatEntry::Free => Self::Free,
+            FatEntry::Next(n) => Self::Data(n),
+            FatEntry::Bad => Self::Bad,
+            FatEntry::EndOfChain => Self::EndOfChain,
+        }
+    }
+}
+
 /// A sum of `Read` and `Seek` traits.
 pub trait ReadSeek: Read + Seek {}
 impl<T: Read + Seek> ReadSeek for T {}
@@ -134,6 +187,8 @@ struct FsInfoSector {
     free_cluster_count: Option<u32>,
     next_free_cluster: Option<u32>,
     dirty: bool,
+    // both values are marked as unknown in the sector stored on disk
+    unknown_on_disk: bool,
 }
 
 impl FsInfoSector {
@@ -179,6 +234,7 @@ impl FsInfoSector {
             free_cluster_count,
             next_free_cluster,
             dirty: false,
+            unknown_on_disk: free_cluster_count.is_none() && next_free_cluster.is_none(),
         })
     }
 
@@ -224,6 +280,29 @@ impl FsInfoSector {
         }
     }
 
+    fn on_cluster_allocated(&mut self, cluster: u32) {
+        self.set_next_free_cluster(cluster + 1);
+        if let Some(n) = self.free_cluster_count {
+            if n == 0 {
+                // A cluster was allocated so the counter is stale - it will be recomputed when needed
+                warn!("free_cluster_count in fs_info is stale, invalidating it");
+                self.free_cluster_count = None;
+            } else {
+                self.free_cluster_count = Some(n - 1);
+            }
+        }
+    }
+
+    fn on_clusters_freed(&mut self, first_cluster: Option<u32>, num_free: u32) {
+        self.map_free_clusters(|n| n + num_free);
+        if let Some(cluster) = first_cluster {
+            // Move the hint backward so freed space gets reused before the end of the volume is reached
+            if self.next_free_cluster.map_or(true, |n| cluster < n) {
+                self.set_next_free_cluster(cluster);
+            }
+        }
+    }
+
     fn set_next_free_cluster(&mut self, cluster: u32) {
         self.next_free_cluster = Some(cluster);
         self.dirty = true;
@@ -235,15 +314,149 @@ impl FsInfoSector {
     }
 }
 
+/// A policy applied when mounting a volume which is marked as dirty or has the hard error flag set.
+///
+/// A volume is marked as dirty when it was not cleanly unmounted (e.g. because of a power loss or a guest crash).
+#[derive(Copy, Clone, Eq, PartialEq, Debug, Default)]
+pub enum DirtyVolumePolicy {
+    /// Mount the volume normally.
+    #[default]
+    Mount,
+    /// Mount the volume read-only. All modifying operations fail with `Error::ReadOnlyFilesystem`.
+    ReadOnly,
+    /// Refuse to mount the volume. `FileSystem::new` fails with `Error::DirtyVolume`.
+    Error,
+}
+
+/// A level of boot sector validation performed when mounting a volume.
+#[derive(Copy, Clone, Eq, PartialEq, Debug, Default)]
+pub enum ValidationLevel {
+    /// Reject any inconsistency in the BIOS Parameter Block, including values accepted by most implementations
+    /// (e.g. unusual drive numbers, non-standard number of reserved sectors, FAT too small for the volume or volume
+    /// bigger than the storage).
+    Strict,
+    /// Reject values which make the filesystem layout invalid or ambiguous, only warn about compatibility issues.
+    #[default]
+    Normal,
+    /// Accept weird but readable values, e.g. missing boot sector signature, unknown filesystem version, conflicting
+    /// total sectors fields or a volume a few sectors bigger than the storage. The volume size is then limited to
+    /// the storage size. Useful for cards formatted by cameras and phones.
+    Lenient,
+}
+
+/// A policy applied to long file name entries whose checksum does not match the short name entry following them.
+///
+/// Such entries are usually written by buggy implementations or left behind by implementations not supporting long
+/// file names which changed the short name entry.
+#[derive(Copy, Clone, Eq, PartialEq, Debug, Default)]
+pub enum LfnChecksumPolicy {
+    /// Log a warning and use the short name, like if there was no long name.
+    #[default]
+    UseShortName,
+    /// Log a warning and use the long name anyway.
+    UseLongName,
+    /// Fail reading the directory with `Error::CorruptedFileSystem`.
+    Error,
+}
+
+/// A policy deciding if long file name entries are written when creating or renaming directory entries.
+#[derive(Copy, Clone, Eq, PartialEq, Debug, Default)]
+pub enum LongNamePolicy {
+    /// Write long file name entries (if the `lfn` feature is enabled). Long file name entries are skipped for valid
+    /// 8.3 names with an uppercase or lowercase basename and extension (e.g. `readme.txt` or `README.TXT`). Lowercase
+    /// parts are recorded using the Windows NT lowercase flags instead.
+    #[default]
+    Create,
+    /// Never write long file name entries. Names which are not valid 8.3 names are silently replaced by
+    /// a generated short name (e.g. `ALONGF~1.TXT` for `a long file name.txt`).
+    Shorten,
+    /// Never write long file name entries. Creating or renaming an entry to a name which is not a valid 8.3 name
+    /// fails.
+    Reject,
+}
+
+/// A policy for names ending with dots or spaces, which are stripped by Windows.
+#[derive(Copy, Clone, Eq, PartialEq, Debug, Default)]
+pub enum TrailingDotsPolicy {
+    /// Use names as provided. Entries created with trailing dots or spaces cannot be opened on Windows.
+    #[default]
+    Allow,
+    /// Strip trailing dots and spaces from path components like Windows does, both when creating and when looking
+    /// up entries.
+    Strip,
+    /// Creating or renaming an entry to a name ending with a dot or a space fails with
+    /// `Error::UnsupportedFileNameCharacter`.
+    Reject,
+}
+
+/// A strategy of generating numeric tails of short names (e.g. `~1` in `TEXTFI~1.TXT`) for long names.
+#[derive(Copy, Clone, Debug, Default)]
+pub enum ShortNameTailPolicy {
+    /// Generate tails like Windows: `TEXTFI~1` to `TEXTFI~4` and then names containing a checksum of the long name
+    /// (e.g. `TE021F~1`). Colliding checksums require scanning the directory again.
+    #[default]
+    Windows,
+    /// Generate sequential tails: `TEXTFI~1` to `TEXTFI~9`, `TEXTF~10` and so on, up to `~999999`. A tail larger than
+    /// all existing tails with the same prefix is used, so the directory is scanned only once.
+    Numeric,
+    /// Generate tails using a provided function.
+    ///
+    /// The function is called with the uppercase short name converted from the long name (without a tail) and
+    /// the number of previous attempts, which collided with existing entries, and returns a tail in range
+    /// `1..=999999`. Every attempt requires scanning the directory. Other tails make the operation fail with
+    /// `Error::InvalidInput`.
+    Custom(fn(short_name: [u8; 11], attempt: u32) -> u32),
+}
+
+/// A policy deciding when metadata changes (directory entries, FAT and FS Information Sector) are flushed to the
+/// storage.
+///
+/// Flushing the storage more often improves durability after a power loss at the cost of performance and wear of
+/// flash memory.
+#[derive(Copy, Clone, Eq, PartialEq, Debug, Default)]
+pub enum WritePolicy {
+    /// Flush metadata and the storage after every operation modifying the filesystem (file write or truncation,
+    /// creating, removing or renaming a directory entry).
+    WriteThrough,
+    /// Flush the storage when a file or the filesystem is flushed.
+    #[default]
+    OnFlush,
+    /// Flush the storage only when the filesystem is flushed explicitly or unmounted. Flushing a file only writes
+    /// its directory entry.
+    OnUnmount,
+}
+
 /// A FAT filesystem mount options.
 ///
 /// Options are specified as an argument for `FileSystem::new` method.
 #[derive(Copy, Clone, Debug, Default)]
+#[allow(clippy::struct_excessive_bools)]
 pub struct FsOptions<TP, OCC> {
     pub(crate) update_accessed_date: bool,
     pub(crate) oem_cp_converter: OCC,
     pub(crate) time_provider: TP,
-    pub(crate) strict: bool,
+    pub(crate) validation: ValidationLevel,
+    pub(crate) reject_root_escape: bool,
+    pub(crate) enforce_read_only: bool,
+    pub(crate) ignore_fs_info: bool,
+    pub(crate) update_fs_info: bool,
+    pub(crate) on_dirty: DirtyVolumePolicy,
+    pub(crate) read_only: bool,
+    pub(crate) mark_bad_after_write_failures: u32,
+    pub(crate) fat_mirror_fallback: bool,
+    pub(crate) tfat: bool,
+    pub(crate) write_policy: WritePolicy,
+    pub(crate) lazy_timestamps: bool,
+    pub(crate) lfn_checksum_policy: LfnChecksumPolicy,
+    pub(crate) long_name_policy: LongNamePolicy,
+    #[cfg(feature = "normalization")]
+    pub(crate) normalize_names: bool,
+    pub(crate) case_sensitive: bool,
+    pub(crate) reject_reserved_names: bool,
+    pub(crate) trailing_dots_policy: TrailingDotsPolicy,
+    pub(crate) short_name_tail_policy: ShortNameTailPolicy,
+    pub(crate) fat_plus: bool,
+    pub(crate) observer: ObserverRef,
 }
 
 impl FsOptions<DefaultTimeProvider, LossyOemCpConverter> {
@@ -254,7 +467,28 @@ impl FsOptions<DefaultTimeProvider, LossyOemCpConverter> {
             update_accessed_date: false,
             oem_cp_converter: LossyOemCpConverter::new(),
             time_provider: DefaultTimeProvider::new(),
-            strict: true,
+            validation: ValidationLevel::Normal,
+            reject_root_escape: false,
+            enforce_read_only: false,
+            ignore_fs_info: false,
+            update_fs_info: true,
+            on_dirty: DirtyVolumePolicy::Mount,
+            read_only: false,
+            mark_bad_after_write_failures: 0,
+            fat_mirror_fallback: false,
+            tfat: false,
+            write_policy: WritePolicy::OnFlush,
+            lazy_timestamps: false,
+            lfn_checksum_policy: LfnChecksumPolicy::UseShortName,
+            long_name_policy: LongNamePolicy::Create,
+            #[cfg(feature = "normalization")]
+            normalize_names: false,
+            case_sensitive: false,
+            reject_reserved_names: false,
+            trailing_dots_policy: TrailingDotsPolicy::Allow,
+            short_name_tail_policy: ShortNameTailPolicy::Windows,
+            fat_plus: false,
+            observer: ObserverRef::default(),
         }
     }
 }
@@ -273,7 +507,28 @@ impl<TP: TimeProvider, OCC: OemCpConverter> FsOptions<TP, OCC> {
             update_accessed_date: self.update_accessed_date,
             oem_cp_converter,
             time_provider: self.time_provider,
-            strict: self.strict,
+            validation: self.validation,
+            reject_root_escape: self.reject_root_escape,
+            enforce_read_only: self.enforce_read_only,
+            ignore_fs_info: self.ignore_fs_info,
+            update_fs_info: self.update_fs_info,
+            on_dirty: self.on_dirty,
+            read_only: self.read_only,
+            mark_bad_after_write_failures: self.mark_bad_after_write_failures,
+            fat_mirror_fallback: self.fat_mirror_fallback,
+            tfat: self.tfat,
+            write_policy: self.write_policy,
+            lazy_timestamps: self.lazy_timestamps,
+            lfn_checksum_policy: self.lfn_checksum_policy,
+            long_name_policy: self.long_name_policy,
+            #[cfg(feature = "normalization")]
+            normalize_names: self.normalize_names,
+            case_sensitive: self.case_sensitive,
+            reject_reserved_names: self.reject_reserved_names,
+            trailing_dots_policy: self.trailing_dots_policy,
+            short_name_tail_policy: self.short_name_tail_policy,
+            fat_plus: self.fat_plus,
+            observer: self.observer,
         }
     }
 
@@ -283,24 +538,303 @@ impl<TP: TimeProvider, OCC: OemCpConverter> FsOptions<TP, OCC> {
             update_accessed_date: self.update_accessed_date,
             oem_cp_converter: self.oem_cp_converter,
             time_provider,
-            strict: self.strict,
+            validation: self.validation,
+            reject_root_escape: self.reject_root_escape,
+            enforce_read_only: self.enforce_read_only,
+            ignore_fs_info: self.ignore_fs_info,
+            update_fs_info: self.update_fs_info,
+            on_dirty: self.on_dirty,
+            read_only: self.read_only,
+            mark_bad_after_write_failures: self.mark_bad_after_write_failures,
+            fat_mirror_fallback: self.fat_mirror_fallback,
+            tfat: self.tfat,
+            write_policy: self.write_policy,
+            lazy_timestamps: self.lazy_timestamps,
+            lfn_checksum_policy: self.lfn_checksum_policy,
+            long_name_policy: self.long_name_policy,
+            #[cfg(feature = "normalization")]
+            normalize_names: self.normalize_names,
+            case_sensitive: self.case_sensitive,
+            reject_reserved_names: self.reject_reserved_names,
+            trailing_dots_policy: self.trailing_dots_policy,
+            short_name_tail_policy: self.short_name_tail_policy,
+            fat_plus: self.fat_plus,
+            observer: self.observer,
         }
     }
 
     /// If enabled more validations are performed to check if file-system is conforming to specification.
+    ///
+    /// `true` selects `ValidationLevel::Normal` and `false` selects `ValidationLevel::Lenient`.
     #[must_use]
     pub fn strict(self, strict: bool) -> Self {
+        self.validation(if strict {
+            ValidationLevel::Normal
+        } else {
+            ValidationLevel::Lenient
+        })
+    }
+
+    /// Sets the level of boot sector validation performed when mounting.
+    ///
+    /// Default is `ValidationLevel::Normal`.
+    #[must_use]
+    pub fn validation(self, validation: ValidationLevel) -> Self {
         Self {
             update_accessed_date: self.update_accessed_date,
             oem_cp_converter: self.oem_cp_converter,
             time_provider: self.time_provider,
-            strict,
+            validation,
+            reject_root_escape: self.reject_root_escape,
+            enforce_read_only: self.enforce_read_only,
+            ignore_fs_info: self.ignore_fs_info,
+            update_fs_info: self.update_fs_info,
+            on_dirty: self.on_dirty,
+            read_only: self.read_only,
+            mark_bad_after_write_failures: self.mark_bad_after_write_failures,
+            fat_mirror_fallback: self.fat_mirror_fallback,
+            tfat: self.tfat,
+            write_policy: self.write_policy,
+            lazy_timestamps: self.lazy_timestamps,
+            lfn_checksum_policy: self.lfn_checksum_policy,
+            long_name_policy: self.long_name_policy,
+            #[cfg(feature = "normalization")]
+            normalize_names: self.normalize_names,
+            case_sensitive: self.case_sensitive,
+            reject_reserved_names: self.reject_reserved_names,
+            trailing_dots_policy: self.trailing_dots_policy,
+            short_name_tail_policy: self.short_name_tail_policy,
+            fat_plus: self.fat_plus,
+            observer: self.observer,
         }
     }
+
+    /// If enabled contents of the FS Information Sector are ignored on mount.
+    ///
+    /// The free cluster count and the next free cluster hint stored there are not trusted and the FAT is scanned
+    /// instead when they are needed. Useful for FAT32 volumes written by implementations which do not maintain
+    /// the FS Information Sector correctly. Default is `false`.
+    #[must_use]
+    pub fn ignore_fs_info(mut self, enabled: bool) -> Self {
+        self.ignore_fs_info = enabled;
+        self
+    }
+
+    /// If disabled the FS Information Sector is not updated when clusters are allocated or freed.
+    ///
+    /// This reduces the number of writes to the storage (e.g. wear of flash memory). Instead of up to date values
+    /// the sector is written at most once per mount to mark both values as unknown, so other implementations do
+    /// not trust stale data. Default is `true`.
+    #[must_use]
+    pub fn update_fs_info(mut self, enabled: bool) -> Self {
+        self.update_fs_info = enabled;
+        self
+    }
+
+    /// Sets a policy for volumes which are marked as dirty or have the hard error flag set on mount.
+    ///
+    /// Status flags read on mount are available through `FileSystem::mount_status_flags`.
+    /// Default is `DirtyVolumePolicy::Mount`.
+    #[must_use]
+    pub fn on_dirty(mut self, policy: DirtyVolumePolicy) -> Self {
+        self.on_dirty = policy;
+        self
+    }
+
+    /// If enabled the volume is mounted read-only.
+    ///
+    /// All modifying operations fail with `Error::ReadOnlyFilesystem` and nothing is written to the storage, not
+    /// even the dirty flag or the accessed date, so a storage opened for writing can be inspected safely.
+    /// Default is `false`.
+    #[must_use]
+    pub fn read_only(mut self, enabled: bool) -> Self {
+        self.read_only = enabled;
+        self
+    }
+
+    /// Sets a number of failed attempts to write file data into a newly allocated cluster after which the cluster
+    /// is marked as bad and replaced by another one.
+    ///
+    /// Only clusters allocated by the current write are handled this way, because they do not contain any data
+    /// yet. At most two clusters are replaced by a single write call - if the storage keeps failing, the I/O error
+    /// is returned. Zero disables this behaviour, so an I/O error is returned immediately. Default is `0`.
+    #[must_use]
+    pub fn mark_bad_after_write_failures(mut self, failures: u32) -> Self {
+        self.mark_bad_after_write_failures = failures;
+        self
+    }
+
+    /// If enabled other copies of the FAT are used when the first one is damaged.
+    ///
+    /// On mount the first FAT copy with a valid media descriptor in its first entry is selected for reading.
+    /// When reading the selected copy returns an I/O error other copies are tried. Writes always go to all copies.
+    /// Has no effect if the volume has only one FAT or FAT mirroring is disabled. Use `FileSystem::sync_fats` to
+    /// repair the damaged copy. Default is `false`.
+    #[must_use]
+    pub fn fat_mirror_fallback(mut self, enabled: bool) -> Self {
+        self.fat_mirror_fallback = enabled;
+        self
+    }
+
+    /// If enabled the volume is accessed using Transaction-Safe FAT (TFAT) semantics.
+    ///
+    /// The first FAT copy holds the last committed state and all FAT changes are made in the second copy. Changes
+    /// are committed by copying the second FAT over the first one when the filesystem is flushed or unmounted.
+    /// On mount uncommitted changes left by an interrupted session are rolled back. Note: directory entries are
+    /// still updated in place. Has no effect if the volume has only one FAT, FAT mirroring is disabled or the
+    /// volume is mounted read-only. Default is `false`.
+    #[must_use]
+    pub fn tfat(mut self, enabled: bool) -> Self {
+        self.tfat = enabled;
+        self
+    }
+
+    /// Sets a policy deciding when metadata changes are flushed to the storage.
+    ///
+    /// Dirty flag and TFAT commits are always made by `FileSystem::flush` and on unmount. Default is
+    /// `WritePolicy::OnFlush`.
+    #[must_use]
+    pub fn write_policy(mut self, policy: WritePolicy) -> Self {
+        self.write_policy = policy;
+        self
+    }
+
+    /// If enabled updates of directory entries changing only timestamps are kept in memory until the filesystem
+    /// is flushed or unmounted.
+    ///
+    /// It avoids writing a directory entry every time a file is read with `update_accessed_date` enabled or
+    /// closed after its timestamps were changed. Up to 16 entries are deferred, further updates are written
+    /// immediately. Timestamps read from directory entries may be outdated until the flush and deferred updates
+    /// are lost on a power failure. Default is `false`.
+    #[must_use]
+    pub fn lazy_timestamps(mut self, enabled: bool) -> Self {
+        self.lazy_timestamps = enabled;
+        self
+    }
+
+    /// Sets a policy deciding if long file name entries are written for created and renamed entries.
+    ///
+    /// Volumes used by boot loaders and small devices which do not support long file names can be limited to 8.3
+    /// names. Names consisting of lowercase letters are stored using the lowercase flags known from Windows NT,
+    /// mixed case names are stored in uppercase. Default is `LongNamePolicy::Create`.
+    #[must_use]
+    pub fn long_name_policy(mut self, policy: LongNamePolicy) -> Self {
+        self.long_name_policy = policy;
+        self
+    }
+
+    /// If enabled long names are normalized to the Unicode Normalization Form C (NFC) when entries are created and
+    /// compared in NFC when looking up entries.
+    ///
+    /// Composed and decomposed forms of the same name (e.g. written by macOS) refer to the same entry then.
+    /// Default is `false`.
+    #[cfg(feature = "normalization")]
+    #[must_use]
+    pub fn normalize_names(mut self, enabled: bool) -> Self {
+        self.normalize_names = enabled;
+        self
+    }
+
+    /// If enabled file names are matched case-sensitively when looking up and creating entries.
+    ///
+    /// Names are always stored as provided. It allows having entries like `Config.txt` and `config.txt` in the same
+    /// directory, which other systems including Windows cannot tell apart, so it should be used only for volumes
+    /// accessed by applications relying on it. Short names are matched with the lowercase flags applied, so
+    /// a short name without the flags set is matched only in uppercase. Default is `false`.
+    #[must_use]
+    pub fn case_sensitive(mut self, enabled: bool) -> Self {
+        self.case_sensitive = enabled;
+        self
+    }
+
+    /// If enabled creating or renaming entries to reserved DOS device names is refused.
+    ///
+    /// Reserved names are `CON`, `PRN`, `AUX`, `NUL`, `COM1` to `COM9` and `LPT1` to `LPT9`, with or without an
+    /// extension (e.g. `nul.txt`). Windows cannot open or delete such entries. Existing entries can still be opened.
+    /// Default is `false`.
+    #[must_use]
+    pub fn reject_reserved_names(mut self, enabled: bool) -> Self {
+        self.reject_reserved_names = enabled;
+        self
+    }
+
+    /// Sets a policy for names ending with dots or spaces.
+    ///
+    /// Windows strips trailing dots and spaces from names, so entries created with them by this crate cannot be
+    /// accessed on Windows and names ending with them do not refer to the same entry as on Windows.
+    /// Default is `TrailingDotsPolicy::Allow`.
+    #[must_use]
+    pub fn trailing_dots_policy(mut self, policy: TrailingDotsPolicy) -> Self {
+        self.trailing_dots_policy = policy;
+        self
+    }
+
+    /// Sets a strategy of generating numeric tails of short names for long names.
+    ///
+    /// `ShortNameTailPolicy::Numeric` or a custom generator can be faster when importing thousands of files with
+    /// similar long names into one directory. Default is `ShortNameTailPolicy::Windows`.
+    #[must_use]
+    pub fn short_name_tail_policy(mut self, policy: ShortNameTailPolicy) -> Self {
+        self.short_name_tail_policy = policy;
+        self
+    }
+
+    /// If enabled files larger than 4 GiB are supported using the FAT+ extension.
+    ///
+    /// FAT+ stores 6 upper bits of the file size in reserved bits of the directory entry, which extends the maximal
+    /// file size to 256 GiB - 1. Files not exceeding 4 GiB - 1 are stored as usual. Implementations not supporting
+    /// FAT+ see only the lower 32 bits of the size of bigger files and may damage them, so it should be enabled only
+    /// for volumes shared with FAT+ aware systems. If disabled the upper bits are ignored and cleared when the size
+    /// of a file changes. Default is `false`.
+    #[must_use]
+    pub fn fat_plus(mut self, enabled: bool) -> Self {
+        self.fat_plus = enabled;
+        self
+    }
+
+    /// Registers an observer notified about storage accesses, cluster allocations and directory operations.
+    ///
+    /// See `FsObserver` for details. By default no observer is registered.
+    #[must_use]
+    pub fn observer(mut self, observer: &'static dyn FsObserver) -> Self {
+        self.observer = ObserverRef(Some(observer));
+        self
+    }
+
+    /// Sets a policy for long file names with a checksum not matching the short name entry.
+    ///
+    /// It has no effect if the `lfn` feature is disabled. Default is `LfnChecksumPolicy::UseShortName`.
+    #[must_use]
+    pub fn lfn_checksum_policy(mut self, policy: LfnChecksumPolicy) -> Self {
+        self.lfn_checksum_policy = policy;
+        self
+    }
+
+    /// If enabled paths containing `..` components pointing above the root directory are rejected.
+    ///
+    /// Paths are normalized before lookup: empty and `.` components are skipped and `..` components remove
+    /// the preceding component. By default `..` in the root directory refers to the root directory itself
+    /// (like in POSIX). If this option is enabled such paths fail with `Error::InvalidInput` instead.
+    #[must_use]
+    pub fn reject_root_escape(mut self, enabled: bool) -> Self {
+        self.reject_root_escape = enabled;
+        self
+    }
+
+    /// If enabled the `READ_ONLY` attribute is enforced.
+    ///
+    /// Writing to or truncating a file with the `READ_ONLY` attribute, as well as removing such file or directory,
+    /// fails with `Error::ReadOnlyFile`. If disabled the attribute is ignored. Default is `false`.
+    #[must_use]
+    pub fn enforce_read_only(mut self, enabled: bool) -> Self {
+        self.enforce_read_only = enabled;
+        self
+    }
 }
 
 /// A FAT volume statistics.
 #[derive(Copy, Clone, Eq, PartialEq, Debug)]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
 pub struct FileSystemStats {
     cluster_size: u32,
     total_clusters: u32,
@@ -327,19 +861,65 @@ impl FileSystemStats {
     }
 }
 
+// Maximal sector size supported by the library - it is the size of sector buffers allocated on the stack
+#[cfg(not(any(
+    feature = "max_sector_size_512",
+    feature = "max_sector_size_1024",
+    feature = "max_sector_size_2048"
+)))]
+pub(crate) const MAX_SECTOR_SIZE: usize = 4096;
+#[cfg(feature = "max_sector_size_2048")]
+pub(crate) const MAX_SECTOR_SIZE: usize = 2048;
+#[cfg(all(feature = "max_sector_size_1024", not(feature = "max_sector_size_2048")))]
+pub(crate) const MAX_SECTOR_SIZE: usize = 1024;
+#[cfg(all(
+    feature = "max_sector_size_512",
+    not(any(feature = "max_sector_size_1024", feature = "max_sector_size_2048"))
+))]
+pub(crate) const MAX_SECTOR_SIZE: usize = 512;
+
+// Maximal buffer alignment which can be required by a storage
+pub(crate) const MAX_BUFFER_ALIGNMENT: usize = 4096;
+
+// Maximal number of directory entries with deferred timestamp updates
+const DEFERRED_TIMESTAMPS_SLOTS: usize = 16;
+
+const STORAGE_PRESENT: &str = "storage is present until the filesystem is consumed";
+
 /// A FAT filesystem object.
 ///
 /// `FileSystem` struct is representing a state of a mounted FAT volume.
 pub struct FileSystem<IO: ReadWriteSeek, TP = DefaultTimeProvider, OCC = LossyOemCpConverter> {
-    pub(crate) disk: RefCell<IO>,
+    // Taken out only when the filesystem is consumed by `into_inner`
+    disk: Option<Mutex<AlignedStorage<IO>>>,
     pub(crate) options: FsOptions<TP, OCC>,
     fat_type: FatType,
     bpb: BiosParameterBlock,
     first_data_sector: u32,
     root_dir_sectors: u32,
     total_clusters: u32,
-    fs_info: RefCell<FsInfoSector>,
-    current_status_flags: Cell<FsStatusFlags>,
+    fs_info: Mutex<FsInfoSector>,
+    mount_status_flags: FsStatusFlags,
+    current_status_flags: Mutex<FsStatusFlags>,
+    // Serializes FAT modifications
+    fat_lock: Mutex<()>,
+    // Incremented when clusters are removed from or relinked in a chain, invalidates extent caches of open files
+    chain_generation: AtomicUsize,
+    // Serialize modifications of directories (keyed by the first cluster) and files (keyed by the entry position)
+    pub(crate) dir_locks: LockTable,
+    pub(crate) file_locks: LockTable,
+    // Changes of the entry lists of iterated directories (keyed like `dir_locks`)
+    pub(crate) dir_changes: ChangeTracker,
+    // Timestamps of directory entries (keyed by the entry position) waiting for a flush
+    deferred_timestamps: Mutex<[Option<(u64, DirFileEntryData)>; DEFERRED_TIMESTAMPS_SLOTS]>,
+    read_only: bool,
+    // Index of the FAT copy used for reads
+    fat_read_copy: u8,
+    boot_sector_from_backup: bool,
+    // Transaction-Safe FAT mode - the FAT copy used for reading is the only one written
+    tfat: bool,
+    #[cfg(feature = "alloc")]
+    watchers: Mutex<Watchers>,
 }
 
 pub trait IntoStorage<T: Read + Write + Seek> {
@@ -375,23 +955,43 @@ impl<IO: Read + Write + Seek, TP, OCC> FileSystem<IO, TP, OCC> {
     ///
     /// * `Error::CorruptedFileSystem` will be returned if the boot sector and/or the file system information sector
     ///   contains invalid values.
+    /// * `Error::DirtyVolume` will be returned if the volume is marked as dirty or has the hard error flag set and
+    ///   `FsOptions::on_dirty` is set to `DirtyVolumePolicy::Error`.
+    /// * `Error::InvalidInput` will be returned if `IoBase::buffer_alignment` of the storage is not a power of two or
+    ///   is greater than 4096.
     /// * `Error::Io` will be returned if the provided storage object returned an I/O error.
     ///
     /// # Panics
     ///
     /// Panics in non-optimized build if `storage` position returned by `seek` is not zero.
+    #[cfg_attr(
+        feature = "tracing",
+        tracing::instrument(name = "mount", level = "debug", skip_all, err(Debug))
+    )]
     pub fn new<T: IntoStorage<IO>>(storage: T, options: FsOptions<TP, OCC>) -> Result<Self, Error<IO::Error>> {
         // Make sure given image is not seeked
         let mut disk = storage.into_storage();
         trace!("FileSystem::new");
         debug_assert!(disk.seek(SeekFrom::Current(0))? == 0);
-
-        // read boot sector
-        let bpb = {
-            let boot = BootSector::deserialize(&mut disk)?;
-            boot.validate(options.strict)?;
-            boot.bpb
+        let buffer_alignment = disk.buffer_alignment();
+        if !buffer_alignment.is_power_of_two() || buffer_alignment > MAX_BUFFER_ALIGNMENT {
+            error!("unsupported buffer alignment {}", buffer_alignment);
+            return Err(Error::InvalidInput);
+        }
+        let mut disk = AlignedStorage::new(disk);
+
+        // read boot sector - if it is damaged try the FAT32 backup boot sector
+        let (mut bpb, boot_sector_from_backup) = match read_boot_sector(&mut disk, 0, options.validation) {
+            Ok(bpb) => (bpb, false),
+            Err(Error::CorruptedFileSystem) => {
+                warn!("boot sector is invalid, trying backup boot sector");
+                (read_backup_boot_sector(&mut disk, options.validation)?, true)
+            }
+            Err(err) => return Err(err),
         };
+        if options.validation != ValidationLevel::Normal {
+            validate_volume_size(&mut disk, &mut bpb, options.validation)?;
+        }
 
         let root_dir_sectors = bpb.root_dir_sectors();
         let first_data_sector = bpb.first_data_sector();
@@ -399,7 +999,7 @@ impl<IO: Read + Write + Seek, TP, OCC> FileSystem<IO, TP, OCC> {
         let fat_type = FatType::from_clusters(total_clusters);
 
         // read FSInfo sector if this is FAT32
-        let mut fs_info = if fat_type == FatType::Fat32 {
+        let mut fs_info = if fat_type == FatType::Fat32 && !options.ignore_fs_info {
             disk.seek(SeekFrom::Start(bpb.bytes_from_sectors(bpb.fs_info_sector())))?;
             FsInfoSector::deserialize(&mut disk)?
         } else {
@@ -414,20 +1014,90 @@ impl<IO: Read + Write + Seek, TP, OCC> FileSystem<IO, TP, OCC> {
         // Validate the numbers stored in the free_cluster_count and next_free_cluster are within bounds for volume
         fs_info.validate_and_fix(total_clusters);
 
+        let fat_read_copy = if options.fat_mirror_fallback {
+            select_fat_copy(&mut disk, &bpb)
+        } else {
+            0
+        };
+
+        // read status flags from both BPB and FAT
+        let status_flags = {
+            let bpb_status = bpb.status_flags();
+            let mut fat =
+                fat_slice::<AlignedStorage<IO>, _>(&mut disk, &bpb, fat_read_copy, options.fat_mirror_fallback, false);
+            let fat_status = read_fat_flags(&mut fat, fat_type)?;
+            FsStatusFlags {
+                dirty: bpb_status.dirty || fat_status.dirty,
+                io_error: bpb_status.io_error || fat_status.io_error,
+            }
+        };
+
+        let unclean = status_flags.dirty || status_flags.io_error;
+        if unclean {
+            warn!("volume is dirty or has hard errors: {:?}", status_flags);
+        }
+        let read_only = match options.on_dirty {
+            DirtyVolumePolicy::Error if unclean => {
+                error!("refusing to mount a dirty volume");
+                return Err(Error::DirtyVolume);
+            }
+            DirtyVolumePolicy::ReadOnly => unclean,
+            _ => false,
+        } || options.read_only;
+
+        // in TFAT mode the second FAT copy is the working copy
+        let tfat = options.tfat && bpb.fats >= 2 && bpb.mirroring_enabled() && !read_only;
+        let fat_read_copy = if tfat { 1 } else { fat_read_copy };
+
         // return FileSystem struct
-        let status_flags = bpb.status_flags();
         trace!("FileSystem::new end");
-        Ok(Self {
-            disk: RefCell::new(disk),
+        let fs = Self {
+            disk: Some(Mutex::new(disk)),
             options,
             fat_type,
             bpb,
             first_data_sector,
             root_dir_sectors,
             total_clusters,
-            fs_info: RefCell::new(fs_info),
-            current_status_flags: Cell::new(status_flags),
-        })
+            fs_info: Mutex::new(fs_info),
+            mount_status_flags: status_flags,
+            current_status_flags: Mutex::new(status_flags),
+            fat_lock: Mutex::new(()),
+            chain_generation: AtomicUsize::new(0),
+            dir_locks: LockTable::new(),
+            file_locks: LockTable::new(),
+            dir_changes: ChangeTracker::new(),
+            deferred_timestamps: Mutex::new(Default::default()),
+            #[cfg(feature = "alloc")]
+            watchers: Mutex::new(Watchers::default()),
+            read_only,
+            fat_read_copy,
+            boot_sector_from_backup,
+            tfat,
+        };
+        if tfat {
+            // roll back changes which were not committed
+            let rolled_back = fs.sync_fat_copies(0, true)?;
+            if rolled_back > 0 {
+                warn!("rolled back {} uncommitted FAT sectors", rolled_back);
+            }
+        }
+        #[cfg(feature = "tracing")]
+        fs.trace_mount();
+        Ok(fs)
+    }
+
+    #[cfg(feature = "tracing")]
+    fn trace_mount(&self) {
+        tracing::debug!(
+            fat_type = ?self.fat_type,
+            total_clusters = self.total_clusters,
+            cluster_size = self.cluster_size(),
+            free_clusters = self.fs_info.lock().free_cluster_count,
+            read_only = self.read_only,
+            tfat = self.tfat,
+            "volume mounted"
+        );
     }
 
     /// Returns a type of File Allocation Table (FAT) used by this filesystem.
@@ -435,11 +1105,277 @@ impl<IO: Read + Write + Seek, TP, OCC> FileSystem<IO, TP, OCC> {
         self.fat_type
     }
 
+    /// Returns status flags read from the Boot Sector and the FAT when the volume was mounted.
+    ///
+    /// Unlike `read_status_flags` the result does not include changes made by this instance, so it can be used to
+    /// check if the volume was cleanly unmounted before it was mounted.
+    pub fn mount_status_flags(&self) -> FsStatusFlags {
+        self.mount_status_flags
+    }
+
+    /// Checks if the filesystem is mounted read-only.
+    ///
+    /// A volume is mounted read-only if `FsOptions::read_only` is enabled or if it was dirty on mount and
+    /// `FsOptions::on_dirty` is set to `DirtyVolumePolicy::ReadOnly`.
+    pub fn is_read_only(&self) -> bool {
+        self.read_only
+    }
+
+    pub(crate) fn check_writable(&self) -> Result<(), Error<IO::Error>> {
+        if self.read_only {
+            debug!("Filesystem is read-only");
+            return Err(Error::ReadOnlyFilesystem);
+        }
+        Ok(())
+    }
+
+    // Checks if `other` is the same filesystem instance
+    pub(crate) fn is_same_fs<IO2: ReadWriteSeek, TP2, OCC2>(&self, other: &FileSystem<IO2, TP2, OCC2>) -> bool {
+        core::ptr::eq(
+            (self as *const Self).cast::<u8>(),
+            (other as *const FileSystem<IO2, TP2, OCC2>).cast::<u8>(),
+        )
+    }
+
+    // Blocks other FAT modifications until the returned guard is dropped. The allocator methods take the lock
+    // themselves so it must not be held when calling them.
+    pub(crate) fn lock_fat(&self) -> MutexGuard<'_, ()> {
+        self.fat_lock.lock()
+    }
+
+    pub(crate) fn chain_generation(&self) -> usize {
+        self.chain_generation.load(Ordering::Acquire)
+    }
+
+    // Must be called when clusters are removed from a chain or a chain is relinked, but not when it is only extended
+    pub(crate) fn on_chains_changed(&self) {
+        self.chain_generation.fetch_add(1, Ordering::AcqRel);
+    }
+
     /// Returns a volume identifier read from BPB in the Boot Sector.
     pub fn volume_id(&self) -> u32 {
         self.bpb.volume_id
     }
 
+    /// Changes a volume identifier (serial number) stored in BPB in the Boot Sector.
+    ///
+    /// For FAT32 volumes the backup Boot Sector is updated as well. Use `generate_volume_id` to create a new
+    /// identifier based on the current time.
+    ///
+    /// # Errors
+    ///
+    /// Errors that can be returned:
+    ///
+    /// * `Error::InvalidInput` will be returned if the Boot Sector has no extended boot signature so the volume
+    ///   identifier field is not available.
+    /// * `Error::ReadOnlyFilesystem` will be returned if the filesystem is mounted read-only.
+    /// * `Error::Io` will be returned if the underlying storage object returned an I/O error.
+    pub fn set_volume_id(&mut self, volume_id: u32) -> Result<(), Error<IO::Error>> {
+        trace!("FileSystem::set_volume_id {:08x}", volume_id);
+        self.check_writable()?;
+        if self.bpb.ext_sig != 0x29 {
+            error!("Volume has no extended boot signature");
+            return Err(Error::InvalidInput);
+        }
+        // Note: only one field is written to avoid rewriting entire boot-sector which could be dangerous
+        let offset = if self.fat_type == FatType::Fat32 { 0x043 } else { 0x027 };
+        let backup_boot_sector = self.bpb.backup_boot_sector();
+        let backup_offset_opt = if self.fat_type == FatType::Fat32 && backup_boot_sector != 0 {
+            Some(self.offset_from_sector(backup_boot_sector) + offset)
+        } else {
+            None
+        };
+        let disk = self.disk_mut().get_mut();
+        disk.seek(SeekFrom::Start(offset))?;
+        disk.write_u32_le(volume_id)?;
+        if let Some(backup_offset) = backup_offset_opt {
+            disk.seek(SeekFrom::Start(backup_offset))?;
+            disk.write_u32_le(volume_id)?;
+        }
+        self.bpb.volume_id = volume_id;
+        Ok(())
+    }
+
+    /// Checks if the volume was mounted using the backup Boot Sector because the primary one was invalid.
+    ///
+    /// Only FAT32 volumes have a backup Boot Sector. Use `restore_boot_sector` to repair the primary one.
+    pub fn is_boot_sector_from_backup(&self) -> bool {
+        self.boot_sector_from_backup
+    }
+
+    /// Rewrites the primary Boot Sector with the content of the backup Boot Sector.
+    ///
+    /// # Errors
+    ///
+    /// Errors that can be returned:
+    ///
+    /// * `Error::InvalidInput` will be returned if the volume has no backup Boot Sector (it is not FAT32).
+    /// * `Error::ReadOnlyFilesystem` will be returned if the filesystem is mounted read-only.
+    /// * `Error::Io` will be returned if the underlying storage object returned an I/O error.
+    pub fn restore_boot_sector(&self) -> Result<(), Error<IO::Error>> {
+        trace!("FileSystem::restore_boot_sector");
+        self.check_writable()?;
+        let backup_boot_sector = self.bpb.backup_boot_sector();
+        if self.fat_type != FatType::Fat32 || backup_boot_sector == 0 {
+            error!("Volume has no backup boot sector");
+            return Err(Error::InvalidInput);
+        }
+        let mut buf = [0_u8; MAX_SECTOR_SIZE];
+        let sector = &mut buf[..usize::from(self.bpb.bytes_per_sector)];
+        let mut disk = self.disk().lock();
+        disk.seek(SeekFrom::Start(self.offset_from_sector(backup_boot_sector)))?;
+        disk.read_exact(sector)?;
+        disk.seek(SeekFrom::Start(0))?;
+        disk.write_all(sector)?;
+        disk.flush()?;
+        Ok(())
+    }
+
+    /// Grows a FAT32 volume to `total_sectors` sectors.
+    ///
+    /// Use it after the storage has been enlarged (e.g. a virtual disk resized on the host). If the current File
+    /// Allocation Table cannot describe all new clusters it is extended and the data area is moved towards the end
+    /// of the storage, which takes time proportional to the size of the data area. The operation is not crash-safe:
+    /// make sure the storage is not disconnected before this function returns. The free clusters count in the
+    /// `FSInfo` sector is recomputed.
+    ///
+    /// # Errors
+    ///
+    /// Errors that can be returned:
+    ///
+    /// * `Error::InvalidInput` will be returned if the volume is not FAT32, `total_sectors` is smaller than the
+    ///   current size of the volume or the new number of clusters is not supported.
+    /// * `Error::UnexpectedEof` will be returned if the storage is smaller than `total_sectors`.
+    /// * `Error::ReadOnlyFilesystem` will be returned if the filesystem is mounted read-only.
+    /// * `Error::Io` will be returned if the underlying storage object returned an I/O error.
+    pub fn grow(&mut self, total_sectors: u32) -> Result<(), Error<IO::Error>> {
+        trace!("FileSystem::grow {}", total_sectors);
+        self.check_writable()?;
+        let old_total_sectors = self.bpb.total_sectors();
+        if self.fat_type != FatType::Fat32 || total_sectors < old_total_sectors {
+            error!("Only FAT32 volumes can grow");
+            return Err(Error::InvalidInput);
+        }
+        if total_sectors == old_total_sectors {
+            return Ok(());
+        }
+        let bytes_per_sector = u32::from(self.bpb.bytes_per_sector);
+        let sectors_per_cluster = u32::from(self.bpb.sectors_per_cluster);
+        let fats = u32::from(self.bpb.fats);
+        let reserved_sectors = self.bpb.reserved_sectors();
+        let old_sectors_per_fat = self.bpb.sectors_per_fat();
+        let old_total_clusters = self.total_clusters;
+
+        // Find the smallest FAT which can describe all clusters
+        let mut sectors_per_fat = old_sectors_per_fat;
+        let total_clusters = loop {
+            let data_sectors = total_sectors - reserved_sectors - fats * sectors_per_fat;
+            let total_clusters = data_sectors / sectors_per_cluster;
+            let fat_bytes = (u64::from(total_clusters) + u64::from(RESERVED_FAT_ENTRIES)) * 4;
+            let needed_sectors_per_fat = (fat_bytes + u64::from(bytes_per_sector) - 1) / u64::from(bytes_per_sector);
+            if needed_sectors_per_fat <= u64::from(sectors_per_fat) {
+                break total_clusters;
+            }
+            sectors_per_fat = u32::try_from(needed_sectors_per_fat).map_err(|_| Error::InvalidInput)?;
+        };
+        // Cluster numbers starting from 0x0FFFFFF7 are reserved for special markers
+        if total_clusters < old_total_clusters || total_clusters + RESERVED_FAT_ENTRIES > 0x0FFF_FFF7 {
+            error!("Unsupported number of clusters {}", total_clusters);
+            return Err(Error::InvalidInput);
+        }
+
+        let mut disk = self.disk().lock();
+        // Make sure the storage is big enough before anything is modified
+        let mut buf = [0_u8; MAX_SECTOR_SIZE];
+        let sector = &mut buf[..bytes_per_sector as usize];
+        disk.seek(SeekFrom::Start(self.bpb.bytes_from_sectors(total_sectors - 1)))?;
+        if disk.read(sector)? == 0 {
+            error!("Storage is smaller than {} sectors", total_sectors);
+            return Err(Error::UnexpectedEof);
+        }
+
+        if sectors_per_fat > old_sectors_per_fat {
+            self.extend_fat(&mut *disk, sectors_per_fat)?;
+        }
+
+        // Update the Boot Sector and its backup
+        // Note: only changed fields are written to avoid rewriting entire boot-sector which could be dangerous
+        let backup_boot_sector = self.bpb.backup_boot_sector();
+        let mut boot_sector_offsets = [Some(0), None];
+        if backup_boot_sector != 0 {
+            boot_sector_offsets[1] = Some(self.offset_from_sector(backup_boot_sector));
+        }
+        for offset in boot_sector_offsets.iter().flatten() {
+            disk.seek(SeekFrom::Start(offset + 0x013))?;
+            disk.write_u16_le(0)?;
+            disk.seek(SeekFrom::Start(offset + 0x020))?;
+            disk.write_u32_le(total_sectors)?;
+            disk.write_u32_le(sectors_per_fat)?;
+        }
+        drop(disk);
+        self.bpb.total_sectors_16 = 0;
+        self.bpb.total_sectors_32 = total_sectors;
+        self.bpb.sectors_per_fat_32 = sectors_per_fat;
+        self.first_data_sector = self.bpb.first_data_sector();
+        self.total_clusters = total_clusters;
+
+        // Mark new clusters as free - entries in the added FAT sectors are already zeroed
+        let old_fat_entries = self.bpb.bytes_from_sectors(old_sectors_per_fat) / 4;
+        let end_cluster = u64::from(total_clusters + RESERVED_FAT_ENTRIES).min(old_fat_entries);
+        {
+            let mut fat = self.fat_slice();
+            for cluster in old_total_clusters + RESERVED_FAT_ENTRIES..end_cluster as u32 {
+                write_fat(&mut fat, self.fat_type, cluster, FatValue::Free)?;
+            }
+        }
+
+        self.recalc_free_clusters()?;
+        self.flush_fs_info()?;
+        self.disk().lock().flush()?;
+        Ok(())
+    }
+
+    // Extends all FAT copies to `sectors_per_fat` sectors moving the data area
+    fn extend_fat(&self, disk: &mut AlignedStorage<IO>, sectors_per_fat: u32) -> Result<(), Error<IO::Error>> {
+        let fats = u32::from(self.bpb.fats);
+        let reserved_sectors = self.bpb.reserved_sectors();
+        let old_sectors_per_fat = self.bpb.sectors_per_fat();
+        let old_total_clusters = self.total_clusters;
+        let fat_growth = sectors_per_fat - old_sectors_per_fat;
+        debug!(
+            "extending FAT from {} to {} sectors",
+            old_sectors_per_fat, sectors_per_fat
+        );
+        // Move the data area
+        let data_offset = self.bpb.bytes_from_sectors(self.first_data_sector);
+        let data_len = self
+            .bpb
+            .bytes_from_sectors(self.bpb.sectors_from_clusters(old_total_clusters));
+        let shift = self.bpb.bytes_from_sectors(fats * fat_growth);
+        copy_backward(disk, data_offset, data_offset + shift, data_len)?;
+        // Move FAT copies starting from the last one, so the source copy is never overwritten before it is read
+        let src_fat = if self.bpb.mirroring_enabled() {
+            u32::from(self.fat_read_copy)
+        } else {
+            u32::from(self.bpb.active_fat())
+        };
+        let old_fat_len = self.bpb.bytes_from_sectors(old_sectors_per_fat);
+        for fat in (0..fats).rev() {
+            let src_sector = if fat < src_fat {
+                reserved_sectors + src_fat * sectors_per_fat
+            } else {
+                reserved_sectors + src_fat * old_sectors_per_fat
+            };
+            let dst_sector = reserved_sectors + fat * sectors_per_fat;
+            let src_offset = self.bpb.bytes_from_sectors(src_sector);
+            let dst_offset = self.bpb.bytes_from_sectors(dst_sector);
+            copy_backward(disk, src_offset, dst_offset, old_fat_len)?;
+            disk.seek(SeekFrom::Start(dst_offset + old_fat_len))?;
+            write_zeros(disk, self.bpb.bytes_from_sectors(fat_growth))?;
+        }
+        Ok(())
+    }
+
     /// Returns a volume label from BPB in the Boot Sector as byte array slice.
     ///
     /// Label is encoded in the OEM codepage.
@@ -454,6 +1390,15 @@ impl<IO: Read + Write + Seek, TP, OCC> FileSystem<IO, TP, OCC> {
         &full_label_slice[..len]
     }
 
+    /// Returns the BIOS Parameter Block parsed from the Boot Sector.
+    ///
+    /// If the primary Boot Sector was invalid and the volume was mounted using the backup one, the backup Boot
+    /// Sector is returned (see `is_boot_sector_from_backup`).
+    #[must_use]
+    pub fn bpb(&self) -> &BiosParameterBlock {
+        &self.bpb
+    }
+
     fn offset_from_sector(&self, sector: u32) -> u64 {
         self.bpb.bytes_from_sectors(sector)
     }
@@ -466,6 +1411,14 @@ impl<IO: Read + Write + Seek, TP, OCC> FileSystem<IO, TP, OCC> {
         self.bpb.cluster_size()
     }
 
+    pub(crate) fn total_clusters(&self) -> u32 {
+        self.total_clusters
+    }
+
+    pub(crate) fn stored_free_cluster_count(&self) -> Option<u32> {
+        self.fs_info.lock().free_cluster_count
+    }
+
     pub(crate) fn offset_from_cluster(&self, cluster: u32) -> u64 {
         self.offset_from_sector(self.sector_from_cluster(cluster))
     }
@@ -478,9 +1431,23 @@ impl<IO: Read + Write + Seek, TP, OCC> FileSystem<IO, TP, OCC> {
         self.bpb.clusters_from_bytes(bytes)
     }
 
-    fn fat_slice(&self) -> impl ReadWriteSeek<Error = Error<IO::Error>> + '_ {
-        let io = FsIoAdapter { fs: self };
-        fat_slice(io, &self.bpb)
+    pub(crate) fn max_file_size(&self) -> u64 {
+        if self.options.fat_plus {
+            MAX_FAT_PLUS_FILE_SIZE
+        } else {
+            MAX_FILE_SIZE
+        }
+    }
+
+    pub(crate) fn fat_slice(&self) -> impl ReadWriteSeek<Error = Error<IO::Error>> + '_ {
+        let io = FsIoAdapter::new(self);
+        fat_slice(
+            io,
+            &self.bpb,
+            self.fat_read_copy,
+            self.options.fat_mirror_fallback,
+            self.tfat,
+        )
     }
 
     pub(crate) fn cluster_iter(
@@ -491,37 +1458,137 @@ impl<IO: Read + Write + Seek, TP, OCC> FileSystem<IO, TP, OCC> {
         ClusterIterator::new(disk_slice, self.fat_type, cluster)
     }
 
+    #[cfg_attr(feature = "tracing", tracing::instrument(level = "debug", skip(self)))]
     pub(crate) fn truncate_cluster_chain(&self, cluster: u32) -> Result<(), Error<IO::Error>> {
+        let _guard = self.lock_fat();
         let mut iter = self.cluster_iter(cluster);
         let num_free = iter.truncate()?;
-        let mut fs_info = self.fs_info.borrow_mut();
-        fs_info.map_free_clusters(|n| n + num_free);
+        self.on_chains_changed();
+        #[cfg(feature = "tracing")]
+        tracing::debug!(num_free, "clusters freed");
+        self.observe(|o| o.on_clusters_freed(num_free));
+        let mut fs_info = self.fs_info.lock();
+        fs_info.on_clusters_freed(None, num_free);
         Ok(())
     }
 
+    #[cfg_attr(feature = "tracing", tracing::instrument(level = "debug", skip(self)))]
     pub(crate) fn free_cluster_chain(&self, cluster: u32) -> Result<(), Error<IO::Error>> {
+        let _guard = self.lock_fat();
         let mut iter = self.cluster_iter(cluster);
         let num_free = iter.free()?;
-        let mut fs_info = self.fs_info.borrow_mut();
-        fs_info.map_free_clusters(|n| n + num_free);
+        self.on_chains_changed();
+        #[cfg(feature = "tracing")]
+        tracing::debug!(num_free, "clusters freed");
+        self.observe(|o| o.on_clusters_freed(num_free));
+        let mut fs_info = self.fs_info.lock();
+        fs_info.on_clusters_freed(Some(cluster), num_free);
+        Ok(())
+    }
+
+    pub(crate) fn zero_cluster_chain(&self, first_cluster: u32) -> Result<(), Error<IO::Error>> {
+        let mut cluster = first_cluster;
+        let mut iter = self.cluster_iter(first_cluster);
+        loop {
+            {
+                let mut disk = self.disk().lock();
+                disk.seek(SeekFrom::Start(self.offset_from_cluster(cluster)))?;
+                write_zeros(&mut *disk, u64::from(self.cluster_size()))?;
+            }
+            match iter.next() {
+                Some(r) => cluster = r?,
+                None => return Ok(()),
+            }
+        }
+    }
+
+    // Returns the length of a cluster chain
+    pub(crate) fn cluster_count(&self, first_cluster: Option<u32>) -> Result<u32, Error<IO::Error>> {
+        let mut clusters = 0;
+        if let Some(first_cluster) = first_cluster {
+            clusters += 1;
+            for r in self.cluster_iter(first_cluster) {
+                r?;
+                clusters += 1;
+            }
+        }
+        Ok(clusters)
+    }
+
+    pub(crate) fn allocated_size(&self, first_cluster: Option<u32>) -> Result<u64, Error<IO::Error>> {
+        Ok(self.bytes_from_clusters(self.cluster_count(first_cluster)?))
+    }
+
+    // Allocates `num_clusters` consecutive clusters as a single chain, failing if any of them is not free
+    #[cfg_attr(feature = "tracing", tracing::instrument(level = "debug", skip(self)))]
+    pub(crate) fn alloc_cluster_range(&self, first_cluster: u32, num_clusters: u32) -> Result<(), Error<IO::Error>> {
+        trace!("alloc_cluster_range {} {}", first_cluster, num_clusters);
+        let end_cluster = first_cluster + num_clusters;
+        if first_cluster < RESERVED_FAT_ENTRIES || end_cluster > self.total_clusters + RESERVED_FAT_ENTRIES {
+            return Err(Error::DataOverwritten);
+        }
+        let _guard = self.lock_fat();
+        let mut fat = self.fat_slice();
+        for cluster in first_cluster..end_cluster {
+            if read_fat(&mut fat, self.fat_type, cluster)? != FatValue::Free {
+                return Err(Error::DataOverwritten);
+            }
+        }
+        for cluster in first_cluster..end_cluster {
+            let value = if cluster + 1 == end_cluster {
+                FatValue::EndOfChain
+            } else {
+                FatValue::Data(cluster + 1)
+            };
+            write_fat(&mut fat, self.fat_type, cluster, value)?;
+        }
+        self.fs_info
+            .lock()
+            .map_free_clusters(|n| n.saturating_sub(num_clusters));
+        self.observe(|o| o.on_clusters_allocated(first_cluster, num_clusters));
         Ok(())
     }
 
+    // Marks an allocated cluster at the end of a chain as bad and allocates a replacement linked to `prev_cluster`
+    pub(crate) fn replace_bad_cluster(&self, prev_cluster: Option<u32>, cluster: u32) -> Result<u32, Error<IO::Error>> {
+        warn!("marking cluster {} as bad", cluster);
+        {
+            let _guard = self.lock_fat();
+            write_fat(&mut self.fat_slice(), self.fat_type, cluster, FatValue::Bad)?;
+        }
+        self.on_chains_changed();
+        self.alloc_cluster(prev_cluster, false)
+    }
+
+    #[cfg_attr(feature = "tracing", tracing::instrument(level = "debug", skip(self)))]
     pub(crate) fn alloc_cluster(&self, prev_cluster: Option<u32>, zero: bool) -> Result<u32, Error<IO::Error>> {
         trace!("alloc_cluster");
-        let hint = self.fs_info.borrow().next_free_cluster;
+        let _guard = self.lock_fat();
+        let hint = self.fs_info.lock().next_free_cluster;
         let cluster = {
             let mut fat = self.fat_slice();
-            alloc_cluster(&mut fat, self.fat_type, prev_cluster, hint, self.total_clusters)?
+            match alloc_cluster(&mut fat, self.fat_type, prev_cluster, hint, self.total_clusters) {
+                Err(Error::NotEnoughSpace) => {
+                    // The whole FAT was scanned so the free cluster count is known for sure
+                    self.fs_info.lock().set_free_cluster_count(0);
+                    return Err(Error::NotEnoughSpace);
+                }
+                r => r?,
+            }
         };
         if zero {
-            let mut disk = self.disk.borrow_mut();
+            let mut disk = self.disk().lock();
             disk.seek(SeekFrom::Start(self.offset_from_cluster(cluster)))?;
             write_zeros(&mut *disk, u64::from(self.cluster_size()))?;
         }
-        let mut fs_info = self.fs_info.borrow_mut();
-        fs_info.set_next_free_cluster(cluster + 1);
-        fs_info.map_free_clusters(|n| n - 1);
+        self.fs_info.lock().on_cluster_allocated(cluster);
+        self.observe(|o| o.on_clusters_allocated(cluster, 1));
+        #[cfg(feature = "tracing")]
+        tracing::debug!(
+            cluster,
+            zeroed_bytes = if zero { self.cluster_size() } else { 0 },
+            "cluster allocated"
+        );
         Ok(cluster)
     }
 
@@ -531,7 +1598,8 @@ impl<IO: Read + Write + Seek, TP, OCC> FileSystem<IO, TP, OCC> {
     ///
     /// `Error::Io` will be returned if the underlying storage object returned an I/O error.
     pub fn read_status_flags(&self) -> Result<FsStatusFlags, Error<IO::Error>> {
-        let bpb_status = self.bpb.status_flags();
+        // BPB copy in memory is not updated so use flags stored by this instance
+        let bpb_status = *self.current_status_flags.lock();
         let fat_status = read_fat_flags(&mut self.fat_slice(), self.fat_type)?;
         Ok(FsStatusFlags {
             dirty: bpb_status.dirty || fat_status.dirty,
@@ -541,14 +1609,15 @@ impl<IO: Read + Write + Seek, TP, OCC> FileSystem<IO, TP, OCC> {
 
     /// Returns filesystem statistics like number of total and free clusters.
     ///
-    /// For FAT32 volumes number of free clusters from the FS Information Sector is returned (may be incorrect).
+    /// For FAT32 volumes number of free clusters from the FS Information Sector is returned (may be incorrect, use
+    /// `recompute_fsinfo` to rebuild it).
     /// For other FAT variants number is computed on the first call to this method and cached for later use.
     ///
     /// # Errors
     ///
     /// `Error::Io` will be returned if the underlying storage object returned an I/O error.
     pub fn stats(&self) -> Result<FileSystemStats, Error<IO::Error>> {
-        let free_clusters_option = self.fs_info.borrow().free_cluster_count;
+        let free_clusters_option = self.fs_info.lock().free_cluster_count;
         let free_clusters = if let Some(n) = free_clusters_option {
             n
         } else {
@@ -563,9 +1632,250 @@ impl<IO: Read + Write + Seek, TP, OCC> FileSystem<IO, TP, OCC> {
 
     /// Forces free clusters recalculation.
     fn recalc_free_clusters(&self) -> Result<u32, Error<IO::Error>> {
+        let _guard = self.lock_fat();
         let mut fat = self.fat_slice();
         let free_cluster_count = count_free_clusters(&mut fat, self.fat_type, self.total_clusters)?;
-        self.fs_info.borrow_mut().set_free_cluster_count(free_cluster_count);
+        self.fs_info.lock().set_free_cluster_count(free_cluster_count);
+        Ok(free_cluster_count)
+    }
+
+    /// Marks a cluster as bad, so it is never allocated.
+    ///
+    /// Use it when the storage reports persistent I/O errors for the cluster. Only free clusters can be marked:
+    /// data of files using a damaged cluster should be copied to a new file first. Marking a cluster which is
+    /// already bad does nothing.
+    ///
+    /// # Errors
+    ///
+    /// Errors that can be returned:
+    ///
+    /// * `Error::InvalidInput` will be returned if `cluster` is outside of the data area or is used by a file or
+    ///   a directory.
+    /// * `Error::ReadOnlyFilesystem` will be returned if the filesystem is mounted read-only.
+    /// * `Error::Io` will be returned if the underlying storage object returned an I/O error.
+    pub fn mark_cluster_bad(&self, cluster: u32) -> Result<(), Error<IO::Error>> {
+        trace!("FileSystem::mark_cluster_bad {}", cluster);
+        self.check_writable()?;
+        if cluster < RESERVED_FAT_ENTRIES || cluster >= self.total_clusters + RESERVED_FAT_ENTRIES {
+            return Err(Error::InvalidInput);
+        }
+        let _guard = self.lock_fat();
+        let mut fat = self.fat_slice();
+        match read_fat(&mut fat, self.fat_type, cluster)? {
+            FatValue::Bad => Ok(()),
+            FatValue::Free => {
+                write_fat(&mut fat, self.fat_type, cluster, FatValue::Bad)?;
+                self.fs_info.lock().map_free_clusters(|n| n.saturating_sub(1));
+                Ok(())
+            }
+            _ => Err(Error::InvalidInput),
+        }
+    }
+
+    /// Checks if a cluster is marked as bad.
+    ///
+    /// # Errors
+    ///
+    /// Errors that can be returned:
+    ///
+    /// * `Error::InvalidInput` will be returned if `cluster` is outside of the data area.
+    /// * `Error::Io` will be returned if the underlying storage object returned an I/O error.
+    pub fn is_cluster_bad(&self, cluster: u32) -> Result<bool, Error<IO::Error>> {
+        if cluster < RESERVED_FAT_ENTRIES || cluster >= self.total_clusters + RESERVED_FAT_ENTRIES {
+            return Err(Error::InvalidInput);
+        }
+        Ok(read_fat(&mut self.fat_slice(), self.fat_type, cluster)? == FatValue::Bad)
+    }
+
+    /// Reads the File Allocation Table entry of a cluster.
+    ///
+    /// The entry is read from the active FAT copy, so 12-bit entries of FAT12 volumes are unpacked.
+    ///
+    /// # Errors
+    ///
+    /// Errors that can be returned:
+    ///
+    /// * `Error::InvalidInput` will be returned if `cluster` is outside of the data area.
+    /// * `Error::Io` will be returned if the underlying storage object returned an I/O error.
+    #[cfg(feature = "unsafe_raw")]
+    pub fn fat_entry(&self, cluster: u32) -> Result<FatEntry, Error<IO::Error>> {
+        if cluster < RESERVED_FAT_ENTRIES || cluster >= self.total_clusters + RESERVED_FAT_ENTRIES {
+            return Err(Error::InvalidInput);
+        }
+        let _guard = self.lock_fat();
+        Ok(read_fat(&mut self.fat_slice(), self.fat_type, cluster)?.into())
+    }
+
+    /// Writes the File Allocation Table entry of a cluster to all FAT copies.
+    ///
+    /// The free cluster count kept in the FS Information Sector is updated, but nothing else is checked: the entry
+    /// can break cluster chains of files and directories or create cross-linked and lost chains. Open files and
+    /// directories cache parts of their cluster chains, so they should not be used after their chains are changed.
+    /// It is intended for repair and forensic tools. Use `FileSystem::check` to validate the result.
+    ///
+    /// # Errors
+    ///
+    /// Errors that can be returned:
+    ///
+    /// * `Error::InvalidInput` will be returned if `cluster` or the next cluster in `value` is outside of the data
+    ///   area.
+    /// * `Error::ReadOnlyFilesystem` will be returned if the filesystem is mounted read-only.
+    /// * `Error::Io` will be returned if the underlying storage object returned an I/O error.
+    #[cfg(feature = "unsafe_raw")]
+    pub fn set_fat_entry(&self, cluster: u32, value: FatEntry) -> Result<(), Error<IO::Error>> {
+        trace!("FileSystem::set_fat_entry {} {:?}", cluster, value);
+        self.check_writable()?;
+        let is_valid_cluster = |n| (RESERVED_FAT_ENTRIES..self.total_clusters + RESERVED_FAT_ENTRIES).contains(&n);
+        if !is_valid_cluster(cluster) || matches!(value, FatEntry::Next(n) if !is_valid_cluster(n)) {
+            return Err(Error::InvalidInput);
+        }
+        let _guard = self.lock_fat();
+        let mut fat = self.fat_slice();
+        let old_value = read_fat(&mut fat, self.fat_type, cluster)?;
+        write_fat(&mut fat, self.fat_type, cluster, value.into())?;
+        self.on_chains_changed();
+        match (old_value == FatValue::Free, value == FatEntry::Free) {
+            (true, false) => self.fs_info.lock().map_free_clusters(|n| n.saturating_sub(1)),
+            (false, true) => self.fs_info.lock().map_free_clusters(|n| n + 1),
+            _ => {}
+        }
+        Ok(())
+    }
+
+    /// Compares all copies of the File Allocation Table.
+    ///
+    /// Returns the number of FAT sectors which differ between copies or cannot be read from one of them.
+    /// Zero is returned if all copies match or the volume has only one FAT.
+    ///
+    /// # Errors
+    ///
+    /// `Error::Io` will be returned if the underlying storage object returned an I/O error.
+    pub fn verify_fats(&self) -> Result<u32, Error<IO::Error>> {
+        trace!("FileSystem::verify_fats");
+        self.sync_fat_copies(self.fat_read_copy, false)
+    }
+
+    /// Copies the File Allocation Table used for reading over other FAT copies.
+    ///
+    /// The source is the first FAT copy unless another one was selected on mount because of
+    /// `FsOptions::fat_mirror_fallback`. In TFAT mode the working copy is the source, so pending FAT changes are
+    /// committed. Sectors which cannot be read from the source copy are taken from other
+    /// copies. Only sectors which differ are written.
+    ///
+    /// Returns the number of written sectors.
+    ///
+    /// # Errors
+    ///
+    /// Errors that can be returned:
+    ///
+    /// * `Error::ReadOnlyFilesystem` will be returned if the filesystem is mounted read-only.
+    /// * `Error::Io` will be returned if the underlying storage object returned an I/O error or a FAT sector
+    ///   cannot be read from any copy.
+    pub fn sync_fats(&self) -> Result<u32, Error<IO::Error>> {
+        trace!("FileSystem::sync_fats");
+        self.check_writable()?;
+        let _guard = self.lock_fat();
+        let written = self.sync_fat_copies(self.fat_read_copy, true)?;
+        self.disk().lock().flush()?;
+        Ok(written)
+    }
+
+    fn sync_fat_copies(&self, source: u8, write: bool) -> Result<u32, Error<IO::Error>> {
+        let fats = self.bpb.fats;
+        if fats < 2 {
+            return Ok(0);
+        }
+        let bytes_per_sector = usize::from(self.bpb.bytes_per_sector);
+        let mut src_buf = [0_u8; MAX_SECTOR_SIZE];
+        let mut buf = [0_u8; MAX_SECTOR_SIZE];
+        let src = &mut src_buf[..bytes_per_sector];
+        let dst = &mut buf[..bytes_per_sector];
+        let mut disk = self.disk().lock();
+        let mut count = 0;
+        for sector in 0..self.bpb.sectors_per_fat() {
+            // Read the sector from the source copy or from the first readable copy
+            let mut src_copy = None;
+            let mut last_err = None;
+            let copies = core::iter::once(source).chain((0..fats).filter(|i| *i != source));
+            for copy in copies {
+                match self.read_fat_sector(&mut *disk, copy, sector, src) {
+                    Ok(()) => {
+                        src_copy = Some(copy);
+                        break;
+                    }
+                    Err(err) => last_err = Some(err),
+                }
+            }
+            let Some(src_copy) = src_copy else {
+                if write {
+                    // Unwrapping is safe because at least one copy was tried
+                    return Err(Error::Io(last_err.unwrap()));
+                }
+                count += 1;
+                continue;
+            };
+            let mut sector_differs = false;
+            for copy in (0..fats).filter(|i| *i != src_copy) {
+                let same = self.read_fat_sector(&mut *disk, copy, sector, dst).is_ok() && src == dst;
+                if same {
+                    continue;
+                }
+                sector_differs = true;
+                if write {
+                    warn!("FAT copy {} sector {} differs, rewriting it", copy, sector);
+                    disk.seek(SeekFrom::Start(self.fat_sector_offset(copy, sector)))?;
+                    disk.write_all(src)?;
+                    count += 1;
+                }
+            }
+            if sector_differs && !write {
+                count += 1;
+            }
+        }
+        Ok(count)
+    }
+
+    fn fat_sector_offset(&self, copy: u8, sector: u32) -> u64 {
+        let sectors_per_fat = self.bpb.sectors_per_fat();
+        self.offset_from_sector(self.bpb.reserved_sectors() + u32::from(copy) * sectors_per_fat + sector)
+    }
+
+    fn read_fat_sector(
+        &self,
+        disk: &mut AlignedStorage<IO>,
+        copy: u8,
+        sector: u32,
+        buf: &mut [u8],
+    ) -> Result<(), IO::Error> {
+        disk.seek(SeekFrom::Start(self.fat_sector_offset(copy, sector)))?;
+        disk.read_exact(buf)
+    }
+
+    /// Rebuilds the free cluster count and the next free cluster hint by scanning the whole FAT.
+    ///
+    /// Both values are used to speed up `stats` and cluster allocation. They are kept up to date by the library but
+    /// can be stale or invalid if the volume was modified by a buggy driver or was not unmounted cleanly. For FAT32
+    /// volumes the rebuilt values are immediately written to the FS Information Sector (unless disabled by
+    /// `FsOptions::update_fs_info`).
+    ///
+    /// Returns the number of free clusters.
+    ///
+    /// # Errors
+    ///
+    /// `Error::Io` will be returned if the underlying storage object returned an I/O error.
+    pub fn recompute_fsinfo(&self) -> Result<u32, Error<IO::Error>> {
+        trace!("FileSystem::recompute_fsinfo");
+        let free_cluster_count = self.recalc_free_clusters()?;
+        let first_free_cluster = {
+            let mut fat = self.fat_slice();
+            find_first_free_cluster(&mut fat, self.fat_type, self.total_clusters)?
+        };
+        {
+            let mut fs_info = self.fs_info.lock();
+            fs_info.next_free_cluster = first_free_cluster;
+            fs_info.dirty = true;
+        }
+        self.flush_fs_info()?;
         Ok(free_cluster_count)
     }
 
@@ -580,31 +1890,200 @@ impl<IO: Read + Write + Seek, TP, OCC> FileSystem<IO, TP, OCC> {
         self.unmount_internal()
     }
 
-    fn unmount_internal(&self) -> Result<(), Error<IO::Error>> {
-        self.flush_fs_info()?;
-        self.set_dirty_flag(false)?;
+    /// Unmounts the filesystem and returns the underlying storage.
+    ///
+    /// Updates the FS Information Sector if needed, flushes the storage and seeks it to the beginning, so it can be
+    /// used for other purposes (e.g. formatted or mounted again) without reopening it.
+    ///
+    /// # Errors
+    ///
+    /// `Error::Io` will be returned if the underlying storage object returned an I/O error. The storage is dropped
+    /// in that case.
+    pub fn into_inner(mut self) -> Result<IO, Error<IO::Error>> {
+        self.unmount_internal()?;
+        self.disk().lock().seek(SeekFrom::Start(0))?;
+        // the remaining fields are dropped by the compiler, `Drop` skips unmounting without the storage
+        let disk = self.take_disk();
+        Ok(disk.into_inner().into_inner())
+    }
+
+    pub(crate) fn disk(&self) -> &Mutex<AlignedStorage<IO>> {
+        self.disk.as_ref().expect(STORAGE_PRESENT)
+    }
+
+    fn disk_mut(&mut self) -> &mut Mutex<AlignedStorage<IO>> {
+        self.disk.as_mut().expect(STORAGE_PRESENT)
+    }
+
+    fn take_disk(&mut self) -> Mutex<AlignedStorage<IO>> {
+        self.disk.take().expect(STORAGE_PRESENT)
+    }
+
+    fn unmount_internal(&self) -> Result<(), Error<IO::Error>> {
+        self.flush()
+    }
+
+    /// Flushes all pending metadata changes and marks the volume as clean.
+    ///
+    /// Writes timestamps deferred by `FsOptions::lazy_timestamps`, updates the FS Information Sector if needed, clears the dirty flag set on the first modification of the
+    /// volume and flushes the underlying storage. The volume is marked as dirty again when it is modified after
+    /// this call. If the volume was already dirty when it was mounted the flag is left untouched so the previous
+    /// unclean shutdown stays detectable.
+    ///
+    /// # Errors
+    ///
+    /// `Error::Io` will be returned if the underlying storage object returned an I/O error.
+    #[cfg_attr(
+        feature = "tracing",
+        tracing::instrument(name = "flush", level = "debug", skip_all, err(Debug))
+    )]
+    pub fn flush(&self) -> Result<(), Error<IO::Error>> {
+        self.flush_deferred_timestamps()?;
+        self.flush_fs_info()?;
+        self.commit_fat()?;
+        self.set_dirty_flag(false)?;
+        self.disk().lock().flush()?;
+        self.observe(|o| o.on_storage_flush());
+        Ok(())
+    }
+
+    // Commits FAT changes on TFAT volumes, other volumes have no uncommitted FAT changes
+    #[cfg_attr(feature = "tracing", tracing::instrument(level = "debug", skip_all, fields(tfat = self.tfat)))]
+    pub(crate) fn commit_fat(&self) -> Result<(), Error<IO::Error>> {
+        if self.tfat {
+            // Do not commit the FAT in the middle of an allocation
+            let _guard = self.lock_fat();
+            // make sure data is stored before FAT changes are committed
+            self.disk().lock().flush()?;
+            self.sync_fat_copies(self.fat_read_copy, true)?;
+        }
+        Ok(())
+    }
+
+    // Notifies callbacks registered by `watch` about a change, the event is created only if there are any
+    #[cfg(feature = "alloc")]
+    pub(crate) fn notify<'e, F: FnOnce() -> FsEvent<'e>>(&self, f: F) {
+        let watchers = self.watchers.lock();
+        if !watchers.is_empty() {
+            watchers.notify(&f());
+        }
+    }
+
+    // Callbacks cannot be registered without alloc, the stub keeps call sites free of feature gates
+    #[cfg(not(feature = "alloc"))]
+    #[inline]
+    #[allow(clippy::unused_self)]
+    pub(crate) fn notify<'e, F: FnOnce() -> FsEvent<'e>>(&self, _f: F) {}
+
+    /// Unregisters a callback registered by `watch` or `watch_all`.
+    ///
+    /// Returns `false` if there is no callback with the given ID.
+    #[cfg(feature = "alloc")]
+    pub fn unwatch(&self, id: WatchId) -> bool {
+        self.watchers.lock().remove(id)
+    }
+
+    /// Registers a callback notified about all changes made to files and directories by this instance.
+    ///
+    /// See `FsEvent` for the list of reported changes. Callbacks are called synchronously after the change has been
+    /// made, with internal locks held, so they must not access the filesystem and should be quick (e.g. forward the
+    /// event to a channel). Changes made by other software accessing the volume are not reported.
+    #[cfg(feature = "alloc")]
+    pub fn watch_all<F: Fn(&FsEvent<'_>) + Send + Sync + 'static>(&self, callback: F) -> WatchId {
+        self.watchers.lock().add(None, Box::new(callback))
+    }
+
+    // Notifies the observer registered in `FsOptions` if there is one
+    pub(crate) fn observe<F: FnOnce(&dyn FsObserver)>(&self, f: F) {
+        if let Some(observer) = self.options.observer.0 {
+            f(observer);
+        }
+    }
+
+    // Stores timestamps of a directory entry until the filesystem is flushed. Returns `false` if there is no free slot.
+    pub(crate) fn defer_timestamps(&self, pos: u64, data: &DirFileEntryData) -> bool {
+        let mut slots = self.deferred_timestamps.lock();
+        let index = slots
+            .iter()
+            .position(|slot| matches!(slot, Some((p, _)) if *p == pos))
+            .or_else(|| slots.iter().position(Option::is_none));
+        if let Some(index) = index {
+            slots[index] = Some((pos, data.clone()));
+        }
+        index.is_some()
+    }
+
+    // Drops deferred timestamps of a directory entry which is about to be written as a whole
+    pub(crate) fn discard_deferred_timestamps(&self, pos: u64) {
+        for slot in self.deferred_timestamps.lock().iter_mut() {
+            if matches!(slot, Some((p, _)) if *p == pos) {
+                *slot = None;
+            }
+        }
+    }
+
+    fn flush_deferred_timestamps(&self) -> Result<(), Error<IO::Error>> {
+        let mut slots = self.deferred_timestamps.lock();
+        for slot in slots.iter_mut() {
+            if let Some((pos, data)) = slot {
+                // Only timestamps are updated, other fields could have been changed since the entry was deferred
+                let mut disk = self.disk().lock();
+                let mut raw = [0_u8; DIR_ENTRY_SIZE as usize];
+                disk.seek(SeekFrom::Start(*pos))?;
+                disk.read_exact(&mut raw)?;
+                if data.apply_timestamps(&mut raw) {
+                    disk.seek(SeekFrom::Start(*pos))?;
+                    disk.write_all(&raw)?;
+                } else {
+                    debug!("entry at {} has changed, skipping deferred timestamps", pos);
+                }
+                *slot = None;
+            }
+        }
+        Ok(())
+    }
+
+    // Flushes metadata changes made by an operation if the write-through policy is used
+    pub(crate) fn write_through(&self) -> Result<(), Error<IO::Error>> {
+        if self.options.write_policy == WritePolicy::WriteThrough {
+            self.flush_fs_info()?;
+            self.disk().lock().flush()?;
+        }
         Ok(())
     }
 
     fn flush_fs_info(&self) -> Result<(), Error<IO::Error>> {
-        let mut fs_info = self.fs_info.borrow_mut();
-        if self.fat_type == FatType::Fat32 && fs_info.dirty {
-            let mut disk = self.disk.borrow_mut();
-            let fs_info_sector_offset = self.offset_from_sector(u32::from(self.bpb.fs_info_sector));
-            disk.seek(SeekFrom::Start(fs_info_sector_offset))?;
-            fs_info.serialize(&mut *disk)?;
+        let mut fs_info = self.fs_info.lock();
+        if self.fat_type == FatType::Fat32 && fs_info.dirty && !self.read_only {
+            if self.options.update_fs_info || !fs_info.unknown_on_disk {
+                let mut disk = self.disk().lock();
+                let fs_info_sector_offset = self.offset_from_sector(u32::from(self.bpb.fs_info_sector));
+                disk.seek(SeekFrom::Start(fs_info_sector_offset))?;
+                if self.options.update_fs_info {
+                    fs_info.serialize(&mut *disk)?;
+                    fs_info.unknown_on_disk =
+                        fs_info.free_cluster_count.is_none() && fs_info.next_free_cluster.is_none();
+                } else {
+                    FsInfoSector::default().serialize(&mut *disk)?;
+                    fs_info.unknown_on_disk = true;
+                }
+            }
             fs_info.dirty = false;
         }
         Ok(())
     }
 
     pub(crate) fn set_dirty_flag(&self, dirty: bool) -> Result<(), IO::Error> {
-        // Do not overwrite flags read from BPB on mount
-        let mut flags = self.bpb.status_flags();
+        if self.read_only {
+            // Nothing is written to a read-only volume
+            return Ok(());
+        }
+        // Do not overwrite flags read on mount
+        let mut flags = self.mount_status_flags;
         flags.dirty |= dirty;
         // Check if flags has changed
-        let current_flags = self.current_status_flags.get();
-        if flags == current_flags {
+        let mut current_flags = self.current_status_flags.lock();
+        if flags == *current_flags {
             // Nothing to do
             return Ok(());
         }
@@ -616,10 +2095,54 @@ impl<IO: Read + Write + Seek, TP, OCC> FileSystem<IO, TP, OCC> {
         } else {
             0x025
         };
-        let mut disk = self.disk.borrow_mut();
+        let mut disk = self.disk().lock();
+        // Restore the position afterwards - flags can be updated in the middle of a write operation
+        let pos = disk.seek(io::SeekFrom::Current(0))?;
         disk.seek(io::SeekFrom::Start(offset))?;
         disk.write_u8(encoded)?;
-        self.current_status_flags.set(flags);
+        self.write_fat_status_flags(&mut disk, flags)?;
+        disk.seek(io::SeekFrom::Start(pos))?;
+        *current_flags = flags;
+        Ok(())
+    }
+
+    fn write_fat_status_flags(&self, disk: &mut AlignedStorage<IO>, flags: FsStatusFlags) -> Result<(), IO::Error> {
+        // Flags are stored in the second FAT entry (FAT12 has no place for them). Bits are set when the volume is
+        // clean. Raw storage is used so writing the flags does not mark the volume dirty again.
+        let (entry_offset, dirty_bit, io_error_bit) = match self.fat_type {
+            FatType::Fat12 => return Ok(()),
+            FatType::Fat16 => (2, 1 << 15, 1 << 14),
+            FatType::Fat32 => (4, 1 << 27, 1 << 26),
+        };
+        let sectors_per_fat = self.bpb.sectors_per_fat();
+        let fats = if self.bpb.mirroring_enabled() {
+            0..u32::from(self.bpb.fats)
+        } else {
+            let active_fat = u32::from(self.bpb.active_fat());
+            active_fat..active_fat + 1
+        };
+        for fat in fats {
+            let fat_offset = self.offset_from_sector(self.bpb.reserved_sectors() + fat * sectors_per_fat);
+            disk.seek(SeekFrom::Start(fat_offset + entry_offset))?;
+            let mut raw = if self.fat_type == FatType::Fat16 {
+                u32::from(disk.read_u16_le()?)
+            } else {
+                disk.read_u32_le()?
+            };
+            raw |= dirty_bit | io_error_bit;
+            if flags.dirty {
+                raw &= !dirty_bit;
+            }
+            if flags.io_error {
+                raw &= !io_error_bit;
+            }
+            disk.seek(SeekFrom::Start(fat_offset + entry_offset))?;
+            if self.fat_type == FatType::Fat16 {
+                disk.write_u16_le(raw as u16)?;
+            } else {
+                disk.write_u32_le(raw)?;
+            }
+        }
         Ok(())
     }
 
@@ -633,7 +2156,7 @@ impl<IO: Read + Write + Seek, TP, OCC> FileSystem<IO, TP, OCC> {
                     self.root_dir_sectors,
                     1,
                     &self.bpb,
-                    FsIoAdapter { fs: self },
+                    FsIoAdapter::new(self),
                 )),
                 FatType::Fat32 => DirRawStream::File(File::new(Some(self.bpb.root_dir_first_cluster), None, self)),
             }
@@ -651,14 +2174,98 @@ impl<IO: ReadWriteSeek, TP, OCC: OemCpConverter> FileSystem<IO, TP, OCC> {
     #[cfg(feature = "alloc")]
     pub fn volume_label(&self) -> String {
         // Decode volume label from OEM codepage
-        let volume_label_iter = self.volume_label_as_bytes().iter().copied();
-        let char_iter = volume_label_iter.map(|c| self.options.oem_cp_converter.decode(c));
+        let char_iter = decode_oem_name(self.volume_label_as_bytes(), &self.options.oem_cp_converter);
         // Build string from character iterator
         char_iter.collect()
     }
 }
 
 impl<IO: ReadWriteSeek, TP: TimeProvider, OCC: OemCpConverter> FileSystem<IO, TP, OCC> {
+    /// Registers a callback notified about changes made by this instance to entries of the directory `path`.
+    ///
+    /// `path` is a '/' separated path relative to the root directory, an empty path denotes the root directory.
+    /// Only changes of direct children of the directory are reported (a rename is reported if either the source or
+    /// the destination is in the directory). The callback stays registered if the directory is removed. See
+    /// `watch_all` for restrictions on the callback.
+    ///
+    /// # Errors
+    ///
+    /// Errors that can be returned:
+    ///
+    /// * `Error::NotFound` will be returned if `path` does not point to an existing directory.
+    /// * `Error::InvalidInput` will be returned if `path` points to a file.
+    /// * `Error::Io` will be returned if the underlying storage object returned an I/O error.
+    #[cfg(feature = "alloc")]
+    pub fn watch<F: Fn(&FsEvent<'_>) + Send + Sync + 'static>(
+        &self,
+        path: &str,
+        callback: F,
+    ) -> Result<WatchId, Error<IO::Error>> {
+        let root_dir = self.root_dir();
+        let dir_cluster = if path.trim_matches('/').is_empty() {
+            root_dir.id_cluster()
+        } else {
+            root_dir.open_dir(path)?.id_cluster()
+        };
+        Ok(self.watchers.lock().add(Some(dir_cluster), Box::new(callback)))
+    }
+
+    /// Opens a directory entry identified by `id` without walking its path.
+    ///
+    /// IDs are returned by `DirEntry::id`. Use `DirEntry::to_file` or `DirEntry::to_dir` to open the returned entry.
+    ///
+    /// # Errors
+    ///
+    /// Errors that can be returned:
+    ///
+    /// * `Error::NotFound` will be returned if there is no entry starting at the ID position, e.g. because the entry
+    ///   was deleted, renamed or moved.
+    /// * `Error::InvalidInput` will be returned if the ID contains an invalid cluster number or offset.
+    /// * `Error::Io` will be returned if the underlying storage object returned an I/O error.
+    pub fn entry_by_id(&self, id: EntryId) -> Result<DirEntry<'_, IO, TP, OCC>, Error<IO::Error>> {
+        trace!("FileSystem::entry_by_id {:?}", id);
+        let cluster = id.dir_cluster();
+        let dir = if cluster == 0 {
+            self.root_dir()
+        } else if cluster < RESERVED_FAT_ENTRIES || cluster >= self.total_clusters + RESERVED_FAT_ENTRIES {
+            debug!("Invalid directory cluster {}", cluster);
+            return Err(Error::InvalidInput);
+        } else {
+            Dir::new(DirRawStream::File(File::new(Some(cluster), None, self)), self)
+        };
+        dir.entry_at(id.offset())
+    }
+
+    /// Opens a file by the first cluster of its data for reading.
+    ///
+    /// It is intended for tools which located a file out-of-band, e.g. recovery tools scanning the data area. The
+    /// cluster chain starting at `first_cluster` is followed in the FAT and `size` bytes can be read. The file has no
+    /// directory entry, so writing to it fails with `Error::ReadOnlyFile` and its metadata contains no attributes or
+    /// timestamps.
+    ///
+    /// # Errors
+    ///
+    /// `Error::InvalidInput` will be returned if `first_cluster` is not a valid cluster number.
+    pub fn open_file_by_cluster(
+        &self,
+        first_cluster: u32,
+        size: u32,
+    ) -> Result<File<'_, IO, TP, OCC>, Error<IO::Error>> {
+        trace!("FileSystem::open_file_by_cluster {} {}", first_cluster, size);
+        if first_cluster < RESERVED_FAT_ENTRIES || first_cluster >= self.total_clusters + RESERVED_FAT_ENTRIES {
+            debug!("Invalid cluster {}", first_cluster);
+            return Err(Error::InvalidInput);
+        }
+        let mut data = DirFileEntryData::new([SFN_PADDING; SFN_SIZE], FileAttributes::empty());
+        data.set_first_cluster(Some(first_cluster), self.fat_type);
+        data.set_size(u64::from(size));
+        Ok(File::new(
+            Some(first_cluster),
+            Some(DirEntryEditor::detached(data)),
+            self,
+        ))
+    }
+
     /// Returns a volume label from root directory as `String`.
     ///
     /// It finds file with `VOLUME_ID` attribute and returns its short name.
@@ -679,13 +2286,310 @@ impl<IO: ReadWriteSeek, TP: TimeProvider, OCC: OemCpConverter> FileSystem<IO, TP
                 .map_or(0, |p| p + 1);
             let label_slice = &volume_label[..len];
             // Decode volume label from OEM codepage
-            let volume_label_iter = label_slice.iter().copied();
-            let char_iter = volume_label_iter.map(|c| self.options.oem_cp_converter.decode(c));
+            let char_iter = decode_oem_name(label_slice, &self.options.oem_cp_converter);
             // Build string from character iterator
             Ok(Some(char_iter.collect::<String>()))
         })
     }
 
+    /// Opens existing file.
+    ///
+    /// `path` is a '/' separated file path relative to the root directory.
+    /// It is a shortcut for calling `open_file` on the root directory.
+    ///
+    /// # Errors
+    ///
+    /// Errors that can be returned:
+    ///
+    /// * `Error::NotFound` will be returned if `path` points to a non-existing directory entry.
+    /// * `Error::InvalidInput` will be returned if `path` points to a file that is a directory.
+    /// * `Error::Io` will be returned if the underlying storage object returned an I/O error.
+    pub fn open_file(&self, path: &str) -> Result<File<'_, IO, TP, OCC>, Error<IO::Error>> {
+        self.root_dir().open_file(path)
+    }
+
+    /// Creates new or opens existing file.
+    ///
+    /// `path` is a '/' separated file path relative to the root directory.
+    /// It is a shortcut for calling `create_file` on the root directory.
+    ///
+    /// # Errors
+    ///
+    /// Errors that can be returned:
+    ///
+    /// * `Error::InvalidInput` will be returned if `path` points to an existing file that is a directory.
+    /// * `Error::InvalidFileNameLength` will be returned if the file name is empty or if it is too long.
+    /// * `Error::UnsupportedFileNameCharacter` will be returned if the file name contains an invalid character.
+    /// * `Error::ReservedFileName` will be returned if the file name is a reserved DOS device name and
+    ///   `FsOptions::reject_reserved_names` is enabled.
+    /// * `Error::NotEnoughSpace` will be returned if there is not enough free space to create a new file.
+    /// * `Error::Io` will be returned if the underlying storage object returned an I/O error.
+    pub fn create_file(&self, path: &str) -> Result<File<'_, IO, TP, OCC>, Error<IO::Error>> {
+        self.root_dir().create_file(path)
+    }
+
+    /// Opens existing directory.
+    ///
+    /// `path` is a '/' separated directory path relative to the root directory.
+    /// It is a shortcut for calling `open_dir` on the root directory.
+    ///
+    /// # Errors
+    ///
+    /// Errors that can be returned:
+    ///
+    /// * `Error::NotFound` will be returned if `path` does not point to any existing directory entry.
+    /// * `Error::InvalidInput` will be returned if `path` points to a file that is not a directory.
+    /// * `Error::Io` will be returned if the underlying storage object returned an I/O error.
+    pub fn open_dir(&self, path: &str) -> Result<Dir<'_, IO, TP, OCC>, Error<IO::Error>> {
+        self.root_dir().open_dir(path)
+    }
+
+    /// Creates new directory or opens existing.
+    ///
+    /// `path` is a '/' separated directory path relative to the root directory.
+    /// It is a shortcut for calling `create_dir` on the root directory.
+    ///
+    /// # Errors
+    ///
+    /// Errors that can be returned:
+    ///
+    /// * `Error::InvalidInput` will be returned if `path` points to an existing file that is not a directory.
+    /// * `Error::InvalidFileNameLength` will be returned if the file name is empty or if it is too long.
+    /// * `Error::UnsupportedFileNameCharacter` will be returned if the file name contains an invalid character.
+    /// * `Error::ReservedFileName` will be returned if the file name is a reserved DOS device name and
+    ///   `FsOptions::reject_reserved_names` is enabled.
+    /// * `Error::NotEnoughSpace` will be returned if there is not enough free space to create a new directory.
+    /// * `Error::Io` will be returned if the underlying storage object returned an I/O error.
+    pub fn create_dir(&self, path: &str) -> Result<Dir<'_, IO, TP, OCC>, Error<IO::Error>> {
+        self.root_dir().create_dir(path)
+    }
+
+    /// Removes existing file or directory.
+    ///
+    /// `path` is a '/' separated path relative to the root directory.
+    /// It is a shortcut for calling `remove` on the root directory.
+    ///
+    /// # Errors
+    ///
+    /// Errors that can be returned:
+    ///
+    /// * `Error::NotFound` will be returned if `path` points to a non-existing directory entry.
+    /// * `Error::InvalidInput` will be returned if `path` points to a file that is not a directory.
+    /// * `Error::DirectoryIsNotEmpty` will be returned if the specified directory is not empty.
+    /// * `Error::ReadOnlyFile` will be returned if the entry has the `READ_ONLY` attribute and
+    ///   `FsOptions::enforce_read_only` is enabled.
+    /// * `Error::Io` will be returned if the underlying storage object returned an I/O error.
+    pub fn remove(&self, path: &str) -> Result<(), Error<IO::Error>> {
+        self.root_dir().remove(path)
+    }
+
+    /// Removes existing file or directory making its data unrecoverable.
+    ///
+    /// `path` is a '/' separated path relative to the root directory.
+    /// It is a shortcut for calling `remove_secure` on the root directory.
+    ///
+    /// # Errors
+    ///
+    /// Errors that can be returned:
+    ///
+    /// * `Error::NotFound` will be returned if `path` points to a non-existing directory entry.
+    /// * `Error::InvalidInput` will be returned if `path` points to a file that is not a directory.
+    /// * `Error::DirectoryIsNotEmpty` will be returned if the specified directory is not empty.
+    /// * `Error::ReadOnlyFile` will be returned if the entry has the `READ_ONLY` attribute and
+    ///   `FsOptions::enforce_read_only` is enabled.
+    /// * `Error::Io` will be returned if the underlying storage object returned an I/O error.
+    pub fn remove_secure(&self, path: &str) -> Result<(), Error<IO::Error>> {
+        self.root_dir().remove_secure(path)
+    }
+
+    /// Fills all free clusters with zeros.
+    ///
+    /// It removes leftovers of deleted files from the storage, which also makes the image compress better.
+    /// If `wipe_dir_slack` is set, unused and deleted entries in all directories are cleared too (the deleted entry
+    /// marker is kept).
+    ///
+    /// Returns the number of zeroed clusters.
+    ///
+    /// # Errors
+    ///
+    /// Errors that can be returned:
+    ///
+    /// * `Error::ReadOnlyFilesystem` will be returned if the filesystem is mounted read-only.
+    /// * `Error::Io` will be returned if the underlying storage object returned an I/O error.
+    pub fn wipe_free_space(&self, wipe_dir_slack: bool) -> Result<u32, Error<IO::Error>> {
+        trace!("FileSystem::wipe_free_space");
+        self.check_writable()?;
+        let mut wiped = 0;
+        let mut next = RESERVED_FAT_ENTRIES;
+        loop {
+            // Hold the lock until the cluster is zeroed so it cannot be allocated in the meantime
+            let _guard = self.lock_fat();
+            let cluster = {
+                let mut fat = self.fat_slice();
+                find_next_free_cluster(&mut fat, self.fat_type, next, self.total_clusters)?
+            };
+            let Some(cluster) = cluster else {
+                break;
+            };
+            let mut disk = self.disk().lock();
+            disk.seek(SeekFrom::Start(self.offset_from_cluster(cluster)))?;
+            write_zeros(&mut *disk, u64::from(self.cluster_size()))?;
+            wiped += 1;
+            next = cluster + 1;
+        }
+        if wipe_dir_slack {
+            self.root_dir().wipe_slack()?;
+        }
+        self.disk().lock().flush()?;
+        Ok(wiped)
+    }
+
+    /// Renames or moves existing file or directory.
+    ///
+    /// Both `src_path` and `dst_path` are '/' separated paths relative to the root directory.
+    ///
+    /// # Errors
+    ///
+    /// Errors that can be returned:
+    ///
+    /// * `Error::NotFound` will be returned if `src_path` points to a non-existing directory entry or if `dst_path`
+    ///   stripped from the last component does not point to an existing directory.
+    /// * `Error::AlreadyExists` will be returned if `dst_path` points to an existing directory entry.
+    /// * `Error::Io` will be returned if the underlying storage object returned an I/O error.
+    pub fn rename(&self, src_path: &str, dst_path: &str) -> Result<(), Error<IO::Error>> {
+        let root_dir = self.root_dir();
+        root_dir.rename(src_path, &root_dir, dst_path)
+    }
+
+    /// Renames or moves existing file or directory replacing an existing destination file.
+    ///
+    /// See `Dir::rename_replace`.
+    ///
+    /// # Errors
+    ///
+    /// Errors are the same as for `Dir::rename_replace`.
+    pub fn rename_replace(&self, src_path: &str, dst_path: &str) -> Result<(), Error<IO::Error>> {
+        let root_dir = self.root_dir();
+        root_dir.rename_replace(src_path, &root_dir, dst_path)
+    }
+
+    /// Returns metadata of a file or a directory.
+    ///
+    /// `path` is a '/' separated path relative to the root directory. Path that normalizes to an empty path (e.g. `/`)
+    /// points to the root directory itself.
+    ///
+    /// # Errors
+    ///
+    /// Errors that can be returned:
+    ///
+    /// * `Error::NotFound` will be returned if `path` points to a non-existing directory entry.
+    /// * `Error::InvalidInput` will be returned if a non-final component of `path` is not a directory.
+    /// * `Error::Io` will be returned if the underlying storage object returned an I/O error.
+    pub fn metadata(&self, path: &str) -> Result<Metadata, Error<IO::Error>> {
+        trace!("FileSystem::metadata {}", path);
+        let root_dir = self.root_dir();
+        match root_dir.find_entry_by_path(path)? {
+            Some(e) => e.metadata(),
+            None => root_dir.metadata(),
+        }
+    }
+
+    /// Checks if a file or a directory exists.
+    ///
+    /// `path` is a '/' separated path relative to the root directory.
+    /// It is a shortcut for calling `exists` on the root directory.
+    #[must_use]
+    pub fn exists(&self, path: &str) -> bool {
+        self.root_dir().exists(path)
+    }
+
+    /// Checks if a file or a directory exists and reports errors other than a missing entry.
+    ///
+    /// `path` is a '/' separated path relative to the root directory.
+    /// It is a shortcut for calling `try_exists` on the root directory.
+    ///
+    /// # Errors
+    ///
+    /// Errors are the same as for `Dir::try_exists`.
+    pub fn try_exists(&self, path: &str) -> Result<bool, Error<IO::Error>> {
+        self.root_dir().try_exists(path)
+    }
+
+    /// Checks if `path` points to an existing directory.
+    ///
+    /// `path` is a '/' separated path relative to the root directory.
+    /// It is a shortcut for calling `is_dir` on the root directory.
+    #[must_use]
+    pub fn is_dir(&self, path: &str) -> bool {
+        self.root_dir().is_dir(path)
+    }
+
+    /// Checks if `path` points to an existing file.
+    ///
+    /// `path` is a '/' separated path relative to the root directory.
+    /// It is a shortcut for calling `is_file` on the root directory.
+    #[must_use]
+    pub fn is_file(&self, path: &str) -> bool {
+        self.root_dir().is_file(path)
+    }
+
+    /// Reads the entire contents of a file into a byte vector.
+    ///
+    /// `path` is a '/' separated file path relative to the root directory. It is a convenience function similar to
+    /// `std::fs::read`.
+    ///
+    /// # Errors
+    ///
+    /// Errors that can be returned:
+    ///
+    /// * `Error::NotFound` will be returned if `path` points to a non-existing directory entry.
+    /// * `Error::InvalidInput` will be returned if `path` points to a directory or if the file is too big to fit in
+    ///   memory.
+    /// * `Error::Io` will be returned if the underlying storage object returned an I/O error.
+    #[cfg(feature = "alloc")]
+    pub fn read(&self, path: &str) -> Result<Vec<u8>, Error<IO::Error>> {
+        trace!("FileSystem::read {}", path);
+        let mut file = self.open_file(path)?;
+        let size = usize::try_from(file.size().unwrap_or(0)).map_err(|_| Error::InvalidInput)?;
+        let mut data = vec![0; size];
+        file.read_exact(&mut data)?;
+        Ok(data)
+    }
+
+    /// Reads the entire contents of a file into a string.
+    ///
+    /// `path` is a '/' separated file path relative to the root directory. It is a convenience function similar to
+    /// `std::fs::read_to_string`.
+    ///
+    /// # Errors
+    ///
+    /// Errors are the same as for `FileSystem::read`. Additionally `Error::InvalidInput` will be returned if the
+    /// file contents are not valid UTF-8.
+    #[cfg(feature = "alloc")]
+    pub fn read_to_string(&self, path: &str) -> Result<String, Error<IO::Error>> {
+        String::from_utf8(self.read(path)?).map_err(|_| {
+            debug!("File is not valid UTF-8");
+            Error::InvalidInput
+        })
+    }
+
+    /// Writes a slice as the entire contents of a file.
+    ///
+    /// `path` is a '/' separated file path relative to the root directory. The file is created if it does not exist
+    /// and truncated if it does. It is a convenience function similar to `std::fs::write`.
+    ///
+    /// # Errors
+    ///
+    /// Errors are the same as for `FileSystem::create_file`. Additionally errors of `File::truncate` and `File::write`
+    /// can be returned, e.g. `Error::NotEnoughSpace` if the contents do not fit on the volume.
+    pub fn write<C: AsRef<[u8]>>(&self, path: &str, contents: C) -> Result<(), Error<IO::Error>> {
+        trace!("FileSystem::write {}", path);
+        let mut file = self.create_file(path)?;
+        file.truncate()?;
+        file.write_all(contents.as_ref())?;
+        file.flush()
+    }
+
     /// Returns a volume label from root directory as byte array.
     ///
     /// Label is encoded in the OEM codepage.
@@ -703,6 +2607,10 @@ impl<IO: ReadWriteSeek, TP: TimeProvider, OCC: OemCpConverter> FileSystem<IO, TP
 /// `Drop` implementation tries to unmount the filesystem when dropping.
 impl<IO: ReadWriteSeek, TP, OCC> Drop for FileSystem<IO, TP, OCC> {
     fn drop(&mut self) {
+        // the storage is missing if it has been taken out by `into_inner` after unmounting
+        if self.disk.is_none() {
+            return;
+        }
         if let Err(err) = self.unmount_internal() {
             error!("unmount failed {:?}", err);
         }
@@ -711,6 +2619,15 @@ impl<IO: ReadWriteSeek, TP, OCC> Drop for FileSystem<IO, TP, OCC> {
 
 pub(crate) struct FsIoAdapter<'a, IO: ReadWriteSeek, TP, OCC> {
     fs: &'a FileSystem<IO, TP, OCC>,
+    // The storage can be shared between threads so every operation seeks to the position of this adapter while
+    // holding the storage lock
+    pos: u64,
+}
+
+impl<'a, IO: ReadWriteSeek, TP, OCC> FsIoAdapter<'a, IO, TP, OCC> {
+    fn new(fs: &'a FileSystem<IO, TP, OCC>) -> Self {
+        Self { fs, pos: 0 }
+    }
 }
 
 impl<IO: ReadWriteSeek, TP, OCC> IoBase for FsIoAdapter<'_, IO, TP, OCC> {
@@ -719,51 +2636,200 @@ impl<IO: ReadWriteSeek, TP, OCC> IoBase for FsIoAdapter<'_, IO, TP, OCC> {
 
 impl<IO: ReadWriteSeek, TP, OCC> Read for FsIoAdapter<'_, IO, TP, OCC> {
     fn read(&mut self, buf: &mut [u8]) -> Result<usize, Self::Error> {
-        self.fs.disk.borrow_mut().read(buf)
+        let mut disk = self.fs.disk().lock();
+        disk.seek(SeekFrom::Start(self.pos))?;
+        let n = disk.read(buf)?;
+        self.fs.observe(|o| o.on_storage_read(self.pos, n));
+        self.pos += n as u64;
+        Ok(n)
     }
 }
 
 impl<IO: ReadWriteSeek, TP, OCC> Write for FsIoAdapter<'_, IO, TP, OCC> {
     fn write(&mut self, buf: &[u8]) -> Result<usize, Self::Error> {
-        let size = self.fs.disk.borrow_mut().write(buf)?;
-        if size > 0 {
-            self.fs.set_dirty_flag(true)?;
+        if buf.is_empty() {
+            return Ok(0);
         }
-        Ok(size)
+        // Mark the volume dirty before it is modified
+        self.fs.set_dirty_flag(true)?;
+        let mut disk = self.fs.disk().lock();
+        disk.seek(SeekFrom::Start(self.pos))?;
+        let n = disk.write(buf)?;
+        self.fs.observe(|o| o.on_storage_write(self.pos, n));
+        self.pos += n as u64;
+        Ok(n)
     }
 
     fn flush(&mut self) -> Result<(), Self::Error> {
-        self.fs.disk.borrow_mut().flush()
+        self.fs.disk().lock().flush()?;
+        self.fs.observe(|o| o.on_storage_flush());
+        Ok(())
     }
 }
 
 impl<IO: ReadWriteSeek, TP, OCC> Seek for FsIoAdapter<'_, IO, TP, OCC> {
     fn seek(&mut self, pos: SeekFrom) -> Result<u64, Self::Error> {
-        self.fs.disk.borrow_mut().seek(pos)
+        if let SeekFrom::Start(n) = pos {
+            self.pos = n;
+        } else {
+            let mut disk = self.fs.disk().lock();
+            disk.seek(SeekFrom::Start(self.pos))?;
+            self.pos = disk.seek(pos)?;
+        }
+        Ok(self.pos)
     }
 }
 
 // Note: derive cannot be used because of invalid bounds. See: https://github.com/rust-lang/rust/issues/26925
 impl<IO: ReadWriteSeek, TP, OCC> Clone for FsIoAdapter<'_, IO, TP, OCC> {
     fn clone(&self) -> Self {
-        FsIoAdapter { fs: self.fs }
+        FsIoAdapter {
+            fs: self.fs,
+            pos: self.pos,
+        }
     }
 }
 
 fn fat_slice<S: ReadWriteSeek, B: BorrowMut<S>>(
     io: B,
     bpb: &BiosParameterBlock,
+    read_copy: u8,
+    read_fallback: bool,
+    read_copy_only: bool,
 ) -> impl ReadWriteSeek<Error = Error<S::Error>> {
     let sectors_per_fat = bpb.sectors_per_fat();
     let mirroring_enabled = bpb.mirroring_enabled();
-    let (fat_first_sector, mirrors) = if mirroring_enabled {
+    let (fat_first_sector, mirrors) = if read_copy_only {
+        (bpb.reserved_sectors() + u32::from(read_copy) * sectors_per_fat, 1)
+    } else if mirroring_enabled {
         (bpb.reserved_sectors(), bpb.fats)
     } else {
         let active_fat = u32::from(bpb.active_fat());
         let fat_first_sector = (bpb.reserved_sectors()) + active_fat * sectors_per_fat;
         (fat_first_sector, 1)
     };
-    DiskSlice::from_sectors(fat_first_sector, sectors_per_fat, mirrors, bpb, io)
+    let mut slice = DiskSlice::from_sectors(fat_first_sector, sectors_per_fat, mirrors, bpb, io);
+    if mirrors > 1 {
+        slice.read_mirror = read_copy;
+        slice.read_fallback = read_fallback;
+    }
+    slice
+}
+
+// Copies `len` bytes from `src_offset` to `dst_offset`. Regions can overlap only if `dst_offset` is not lower than
+// `src_offset`.
+fn copy_backward<S: ReadWriteSeek>(disk: &mut S, src_offset: u64, dst_offset: u64, len: u64) -> Result<(), S::Error> {
+    debug_assert!(dst_offset >= src_offset || dst_offset + len <= src_offset);
+    let mut buf = [0_u8; MAX_SECTOR_SIZE];
+    let mut remaining = len;
+    while remaining > 0 {
+        let chunk_len = remaining.min(MAX_SECTOR_SIZE as u64);
+        remaining -= chunk_len;
+        let chunk = &mut buf[..chunk_len as usize];
+        disk.seek(SeekFrom::Start(src_offset + remaining))?;
+        disk.read_exact(chunk)?;
+        disk.seek(SeekFrom::Start(dst_offset + remaining))?;
+        disk.write_all(chunk)?;
+    }
+    Ok(())
+}
+
+fn read_boot_sector<S: ReadWriteSeek>(
+    disk: &mut S,
+    offset: u64,
+    validation: ValidationLevel,
+) -> Result<BiosParameterBlock, Error<S::Error>> {
+    disk.seek(SeekFrom::Start(offset))?;
+    let boot = BootSector::deserialize(disk)?;
+    boot.validate(validation)?;
+    Ok(boot.bpb)
+}
+
+// Compares the volume size with the storage size. In lenient mode a volume bigger than the storage is shrunk
+// (in memory only) if the FAT type does not change.
+fn validate_volume_size<S: ReadWriteSeek>(
+    disk: &mut S,
+    bpb: &mut BiosParameterBlock,
+    validation: ValidationLevel,
+) -> Result<(), Error<S::Error>> {
+    let storage_sectors = disk.seek(SeekFrom::End(0))? / u64::from(bpb.bytes_per_sector);
+    disk.seek(SeekFrom::Start(0))?;
+    let total_sectors = bpb.total_sectors();
+    if storage_sectors >= u64::from(total_sectors) {
+        return Ok(());
+    }
+    warn!(
+        "volume has {} sectors but the storage only {} sectors",
+        total_sectors, storage_sectors
+    );
+    if validation == ValidationLevel::Lenient {
+        // storage_sectors is smaller than total_sectors so it fits in u32
+        let mut shrunk_bpb = bpb.clone();
+        shrunk_bpb.set_total_sectors(storage_sectors as u32);
+        let fat_type = FatType::from_clusters(bpb.total_clusters());
+        if storage_sectors > u64::from(bpb.first_data_sector())
+            && FatType::from_clusters(shrunk_bpb.total_clusters()) == fat_type
+        {
+            *bpb = shrunk_bpb;
+            return Ok(());
+        }
+    }
+    Err(Error::CorruptedFileSystem)
+}
+
+// Looks for the FAT32 backup boot sector. Sector size is unknown so all supported sizes are tried.
+fn read_backup_boot_sector<S: ReadWriteSeek>(
+    disk: &mut S,
+    validation: ValidationLevel,
+) -> Result<BiosParameterBlock, Error<S::Error>> {
+    const BACKUP_BOOT_SECTOR: u64 = 6;
+    let mut bytes_per_sector = 512;
+    while bytes_per_sector <= MAX_SECTOR_SIZE as u16 {
+        let offset = BACKUP_BOOT_SECTOR * u64::from(bytes_per_sector);
+        match read_boot_sector(disk, offset, validation) {
+            Ok(bpb)
+                if bpb.is_fat32()
+                    && bpb.bytes_per_sector == bytes_per_sector
+                    && u64::from(bpb.backup_boot_sector()) == BACKUP_BOOT_SECTOR =>
+            {
+                info!("using backup boot sector");
+                return Ok(bpb);
+            }
+            // Errors are ignored because reads beyond the end of a small volume can fail
+            _ => {}
+        }
+        bytes_per_sector *= 2;
+    }
+    error!("no valid backup boot sector found");
+    Err(Error::CorruptedFileSystem)
+}
+
+// Selects the first FAT copy which can be read and starts with a valid media descriptor
+fn select_fat_copy<S: ReadWriteSeek>(disk: &mut S, bpb: &BiosParameterBlock) -> u8 {
+    if !bpb.mirroring_enabled() {
+        return 0;
+    }
+    for i in 0..bpb.fats {
+        let fat_first_sector = bpb.reserved_sectors() + u32::from(i) * bpb.sectors_per_fat();
+        let media = disk
+            .seek(SeekFrom::Start(bpb.bytes_from_sectors(fat_first_sector)))
+            .and_then(|_| disk.read_u8());
+        match media {
+            Ok(media) if media == bpb.media => {
+                if i > 0 {
+                    warn!("FAT copy 0 is damaged, using copy {}", i);
+                }
+                return i;
+            }
+            Ok(media) => {
+                warn!("invalid media descriptor {:x} in FAT copy {}", media, i);
+            }
+            Err(_) => {
+                warn!("failed to read FAT copy {}", i);
+            }
+        }
+    }
+    0
 }
 
 pub(crate) struct DiskSlice<B, S = B> {
@@ -771,6 +2837,9 @@ pub(crate) struct DiskSlice<B, S = B> {
     size: u64,
     offset: u64,
     mirrors: u8,
+    // Mirror used for reads and a flag enabling reads from other mirrors on I/O errors
+    read_mirror: u8,
+    read_fallback: bool,
     inner: B,
     phantom: PhantomData<S>,
 }
@@ -781,6 +2850,8 @@ impl<B: BorrowMut<S>, S: ReadWriteSeek> DiskSlice<B, S> {
             begin,
             size,
             mirrors,
+            read_mirror: 0,
+            read_fallback: false,
             inner,
             offset: 0,
             phantom: PhantomData,
@@ -799,6 +2870,10 @@ impl<B: BorrowMut<S>, S: ReadWriteSeek> DiskSlice<B, S> {
     pub(crate) fn abs_pos(&self) -> u64 {
         self.begin + self.offset
     }
+
+    pub(crate) fn size(&self) -> u64 {
+        self.size
+    }
 }
 
 // Note: derive cannot be used because of invalid bounds. See: https://github.com/rust-lang/rust/issues/26925
@@ -809,6 +2884,8 @@ impl<B: Clone, S> Clone for DiskSlice<B, S> {
             size: self.size,
             offset: self.offset,
             mirrors: self.mirrors,
+            read_mirror: self.read_mirror,
+            read_fallback: self.read_fallback,
             inner: self.inner.clone(),
             // phantom is needed to add type bounds on the storage type
             phantom: PhantomData,
@@ -824,8 +2901,23 @@ impl<B: BorrowMut<S>, S: Read + Seek> Read for DiskSlice<B, S> {
     fn read(&mut self, buf: &mut [u8]) -> Result<usize, Self::Error> {
         let offset = self.begin + self.offset;
         let read_size = (buf.len() as u64).min(self.size - self.offset) as usize;
-        self.inner.borrow_mut().seek(SeekFrom::Start(offset))?;
-        let size = self.inner.borrow_mut().read(&mut buf[..read_size])?;
+        let storage = self.inner.borrow_mut();
+        let mut read_mirror = |mirror: u8| {
+            storage
+                .seek(SeekFrom::Start(offset + u64::from(mirror) * self.size))
+                .and_then(|_| storage.read(&mut buf[..read_size]))
+        };
+        let mut result = read_mirror(self.read_mirror);
+        if self.read_fallback {
+            for mirror in (0..self.mirrors).filter(|m| *m != self.read_mirror) {
+                if result.is_ok() {
+                    break;
+                }
+                warn!("read from FAT copy failed, trying copy {}", mirror);
+                result = read_mirror(mirror);
+            }
+        }
+        let size = result?;
         self.offset += size as u64;
         Ok(size)
     }
@@ -911,6 +3003,47 @@ pub trait OemCpConverter: Debug {
     /// * `Some(byte)` - The OEM code page byte representing this character
     /// * `None` - The character cannot be represented in this code page
     fn encode(&self, uni_char: char) -> Option<u8>;
+
+    /// Returns `true` if `oem_char` is the first byte of a double-byte character.
+    ///
+    /// Double-byte code pages (DBCS) like Shift-JIS or GBK should override this method and
+    /// `decode_double_byte`. Default implementation returns `false` for all bytes.
+    fn is_lead_byte(&self, oem_char: u8) -> bool {
+        let _ = oem_char;
+        false
+    }
+
+    /// Decodes a double-byte character to a Unicode character.
+    ///
+    /// It is called only for `lead` bytes for which `is_lead_byte` returned `true`. Default implementation returns
+    /// the replacement character (U+FFFD).
+    fn decode_double_byte(&self, lead: u8, trail: u8) -> char {
+        let _ = (lead, trail);
+        '\u{FFFD}'
+    }
+}
+
+/// Decodes a name stored in an OEM code page, pairing lead bytes of double-byte characters with the following byte.
+///
+/// Dot separating the extension in a short name is never treated as a trail byte.
+pub(crate) fn decode_oem_name<'a, OCC: OemCpConverter>(
+    bytes: &'a [u8],
+    oem_cp_converter: &'a OCC,
+) -> impl Iterator<Item = char> + 'a {
+    let mut rest = bytes;
+    core::iter::from_fn(move || {
+        let (&first, remaining) = rest.split_first()?;
+        match remaining.first() {
+            Some(&trail) if trail != b'.' && oem_cp_converter.is_lead_byte(first) => {
+                rest = &remaining[1..];
+                Some(oem_cp_converter.decode_double_byte(first, trail))
+            }
+            _ => {
+                rest = remaining;
+                Some(oem_cp_converter.decode(first))
+            }
+        }
+    })
 }
 
 /// Default implementation of `OemCpConverter` that changes all non-ASCII characters to the replacement character (U+FFFD).
@@ -981,6 +3114,9 @@ pub struct FormatVolumeOptions {
     pub(crate) drive_num: Option<u8>,
     pub(crate) volume_id: u32,
     pub(crate) volume_label: Option<[u8; SFN_SIZE]>,
+    pub(crate) hidden_sectors: u32,
+    pub(crate) reserved_sectors: Option<u16>,
+    pub(crate) boot_code: Option<BootCode>,
 }
 
 impl Default for FormatVolumeOptions {
@@ -998,6 +3134,9 @@ impl Default for FormatVolumeOptions {
             drive_num: None,
             volume_id: 0x1234_5678,
             volume_label: None,
+            hidden_sectors: 0,
+            reserved_sectors: None,
+            boot_code: None,
         }
     }
 }
@@ -1045,16 +3184,18 @@ impl FormatVolumeOptions {
 
     /// Set sector size in bytes
     ///
-    /// Sector size must be a power of two and be in range 512 - 4096.
-    /// Default is `512`.
+    /// Sector size must be a power of two and be in range 512 - 4096 (the upper limit is lower if a
+    /// `max_sector_size_*` feature is enabled). Default is `512`.
     ///
     /// # Panics
     ///
-    /// Panics if `bytes_per_sector` is not a power of two or is lower than `512`.
+    /// Panics if `bytes_per_sector` is not a power of two or is out of the supported range.
     #[must_use]
     pub fn bytes_per_sector(mut self, bytes_per_sector: u16) -> Self {
         assert!(
-            bytes_per_sector.is_power_of_two() && bytes_per_sector >= 512,
+            bytes_per_sector.is_power_of_two()
+                && bytes_per_sector >= 512
+                && usize::from(bytes_per_sector) <= MAX_SECTOR_SIZE,
             "Invalid bytes_per_sector"
         );
         self.bytes_per_sector = bytes_per_sector;
@@ -1135,7 +3276,7 @@ impl FormatVolumeOptions {
 
     /// Set volume ID for Bios Parameters Block
     ///
-    /// Default is `0x12345678`.
+    /// Default is `0x12345678`. Use `generate_volume_id` to create a unique identifier.
     #[must_use]
     pub fn volume_id(mut self, volume_id: u32) -> Self {
         self.volume_id = volume_id;
@@ -1150,6 +3291,66 @@ impl FormatVolumeOptions {
         self.volume_label = Some(volume_label);
         self
     }
+
+    /// Set number of hidden sectors preceding the volume
+    ///
+    /// It should be set to the first sector of the partition when formatting a partition of a bootable disk.
+    /// Default is `0`.
+    #[must_use]
+    pub fn hidden_sectors(mut self, hidden_sectors: u32) -> Self {
+        self.hidden_sectors = hidden_sectors;
+        self
+    }
+
+    /// Set number of reserved sectors preceding the first FAT
+    ///
+    /// Reserved sectors following the Boot Sector are not used by the filesystem, except for the FS Information
+    /// Sector (sector 1) and the backup Boot Sector (sector 6) on FAT32 volumes, so they can hold a second-stage boot
+    /// loader, e.g. starting at sector 1 on FAT12/FAT16 volumes and at sector 8 on FAT32 volumes. Formatting does not
+    /// clear them. At least 1 sector is needed for FAT12/FAT16 and 8 sectors for FAT32, otherwise formatting fails.
+    /// Default is `1` for FAT12/FAT16 and `8` for FAT32.
+    #[must_use]
+    pub fn reserved_sectors(mut self, reserved_sectors: u16) -> Self {
+        self.reserved_sectors = Some(reserved_sectors);
+        self
+    }
+
+    /// Set jump instruction and boot code of the Boot Sector
+    ///
+    /// `bootjmp` is written at the beginning of the Boot Sector and must be a short jump followed by NOP
+    /// (`[0xEB, offset, 0x90]`) or a near jump (`[0xE9, offset_lo, offset_hi]`) landing in the boot code area.
+    /// `boot_code` is written at the beginning of this area, which starts at offset `0x3E` (448 bytes) on FAT12/FAT16
+    /// volumes and at offset `0x5A` (420 bytes) on FAT32 volumes, and the rest of the area is zeroed. Use `fat_type`
+    /// to make the offset predictable. Formatting fails if the jump does not skip the BPB or if the code does not fit.
+    /// Default is a code displaying a message that the disk is not bootable.
+    ///
+    /// # Panics
+    ///
+    /// Panics if `boot_code` is longer than 448 bytes.
+    #[must_use]
+    pub fn boot_code(mut self, bootjmp: [u8; 3], boot_code: &[u8]) -> Self {
+        assert!(boot_code.len() <= BOOT_CODE_SIZE, "Boot code is too long");
+        let mut code = [0; BOOT_CODE_SIZE];
+        code[..boot_code.len()].copy_from_slice(boot_code);
+        self.boot_code = Some(BootCode {
+            bootjmp,
+            code,
+            len: boot_code.len(),
+        });
+        self
+    }
+}
+
+/// Generate a volume identifier (serial number) from a date and time
+///
+/// The same algorithm as in DOS and Windows `FORMAT` command is used, so identifiers generated at different moments
+/// differ. Result can be passed to `FormatVolumeOptions::volume_id` or `FileSystem::set_volume_id`.
+#[must_use]
+pub fn generate_volume_id(date_time: DateTime) -> u32 {
+    let DateTime { date, time } = date_time;
+    let lo = ((date.month << 8) | date.day).wrapping_add((time.sec << 8) | (time.millis / 10));
+    let hi = ((time.hour << 8) | time.min).wrapping_add(date.year);
+    (u32::from(lo) << 16) | u32::from(hi)
 }
 
 /// Create FAT filesystem on a disk or partition (format a volume)
@@ -1178,6 +3379,8 @@ impl FormatVolumeOptions {
 pub fn format_volume<S: ReadWriteSeek>(storage: &mut S, options: FormatVolumeOptions) -> Result<(), Error<S::Error>> {
     trace!("format_volume");
     debug_assert!(storage.seek(SeekFrom::Current(0))? == 0);
+    let mut storage = AlignedStorage::<&mut S, S>::new(storage);
+    let storage = &mut storage;
 
     let total_sectors = if let Some(total_sectors) = options.total_sectors {
         total_sectors
@@ -1194,7 +3397,7 @@ pub fn format_volume<S: ReadWriteSeek>(storage: &mut S, options: FormatVolumeOpt
 
     // Create boot sector, validate and write to storage device
     let (boot, fat_type) = format_boot_sector(&options, total_sectors)?;
-    if boot.validate::<S::Error>(true).is_err() {
+    if boot.validate::<S::Error>(ValidationLevel::Normal).is_err() {
         return Err(Error::InvalidInput);
     }
     boot.serialize(storage)?;
@@ -1209,6 +3412,7 @@ pub fn format_volume<S: ReadWriteSeek>(storage: &mut S, options: FormatVolumeOpt
             free_cluster_count: None,
             next_free_cluster: None,
             dirty: false,
+            unknown_on_disk: true,
         };
         storage.seek(SeekFrom::Start(bpb.bytes_from_sectors(bpb.fs_info_sector())))?;
         fs_info_sector.serialize(storage)?;
@@ -1227,7 +3431,7 @@ pub fn format_volume<S: ReadWriteSeek>(storage: &mut S, options: FormatVolumeOpt
     storage.seek(SeekFrom::Start(fat_pos))?;
     write_zeros(storage, bpb.bytes_from_sectors(sectors_per_all_fats))?;
     {
-        let mut fat_slice = fat_slice::<S, &mut S>(storage, bpb);
+        let mut fat_slice = fat_slice::<AlignedStorage<&mut S, S>, _>(&mut *storage, bpb, 0, false, false);
         let sectors_per_fat = bpb.sectors_per_fat();
         let bytes_per_fat = bpb.bytes_from_sectors(sectors_per_fat);
         format_fat(&mut fat_slice, fat_type, bpb.media, bytes_per_fat, bpb.total_clusters())?;
@@ -1241,7 +3445,7 @@ pub fn format_volume<S: ReadWriteSeek>(storage: &mut S, options: FormatVolumeOpt
     write_zeros(storage, bpb.bytes_from_sectors(root_dir_sectors))?;
     if fat_type == FatType::Fat32 {
         let root_dir_first_cluster = {
-            let mut fat_slice = fat_slice::<S, &mut S>(storage, bpb);
+            let mut fat_slice = fat_slice::<AlignedStorage<&mut S, S>, _>(&mut *storage, bpb, 0, false, false);
             alloc_cluster(&mut fat_slice, fat_type, None, None, 1)?
         };
         assert!(root_dir_first_cluster == bpb.root_dir_first_cluster);
@@ -1277,7 +3481,7 @@ mod tests {
         assert_eq!(FatType::from_clusters(50000), FatType::Fat16);
         assert_eq!(FatType::from_clusters(65524), FatType::Fat16);
         assert_eq!(FatType::from_clusters(65525), FatType::Fat32);
-        assert_eq!(FatType::from_clusters(100000), FatType::Fat32);
+        assert_eq!(FatType::from_clusters(100_000), FatType::Fat32);
     }
 
     #[test]
@@ -1403,7 +3607,7 @@ mod tests {
     fn test_fs_options_new() {
         let options = FsOptions::new();
         assert!(!options.update_accessed_date);
-        assert!(options.strict);
+        assert_eq!(options.validation, ValidationLevel::Normal);
     }
 
     #[test]
@@ -1418,10 +3622,16 @@ mod tests {
     #[test]
     fn test_fs_options_strict() {
         let options = FsOptions::new().strict(false);
-        assert!(!options.strict);
+        assert_eq!(options.validation, ValidationLevel::Lenient);
 
         let options = options.strict(true);
-        assert!(options.strict);
+        assert_eq!(options.validation, ValidationLevel::Normal);
+    }
+
+    #[test]
+    fn test_fs_options_validation() {
+        let options = FsOptions::new().validation(ValidationLevel::Strict);
+        assert_eq!(options.validation, ValidationLevel::Strict);
     }
 
     #[test]
@@ -1483,7 +3693,7 @@ mod tests {
             total_clusters: 2000,
             free_clusters: 1000,
         };
-        let stats2 = stats1.clone();
+        let stats2 = stats1;
         assert_eq!(stats1, stats2);
     }
 
@@ -1522,4 +3732,19 @@ mod tests {
         assert!(debug_str.contains("10000"));
         assert!(debug_str.contains("3000"));
     }
+
+    #[test]
+    fn test_generate_volume_id() {
+        let date_time = DateTime::new(
+            crate::time::Date::new(2021, 3, 14),
+            crate::time::Time::new(15, 9, 26, 530),
+        );
+        // lo = 0x030E + 0x1A35, hi = 0x0F09 + 2021
+        assert_eq!(generate_volume_id(date_time), 0x1D43_16EE);
+        let later = DateTime::new(
+            crate::time::Date::new(2021, 3, 14),
+            crate::time::Time::new(15, 9, 27, 530),
+        );
+        assert_ne!(generate_volume_id(date_time), generate_volume_id(later));
+    }
 }
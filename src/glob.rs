@@ -0,0 +1,294 @@
+//! Wildcard matching of file names.
+//!
+//! This module implements `Dir::find`, which filters entries of a single directory, and `Dir::glob` with
+//! `FileSystem::glob`, which match whole paths and can descend into subdirectories using the `**` component.
+
+#[cfg(not(feature = "std"))]
+use alloc::format;
+#[cfg(not(feature = "std"))]
+use alloc::string::String;
+#[cfg(not(feature = "std"))]
+use alloc::vec::Vec;
+use core::iter;
+
+use crate::dir::{Dir, DirIter};
+use crate::dir_entry::{char_to_uppercase, chars_eq, DirEntry};
+use crate::error::Error;
+use crate::fs::{FileSystem, OemCpConverter, ReadWriteSeek};
+use crate::time::TimeProvider;
+
+const WILDCARD_CHARS: [char; 3] = ['*', '?', '['];
+
+/// An iterator over directory entries matching a wildcard pattern.
+///
+/// This struct is created by the `find` method on `Dir`.
+pub struct FindIter<'a, 'p, IO: ReadWriteSeek, TP, OCC> {
+    iter: DirIter<'a, IO, TP, OCC>,
+    pattern: &'p str,
+}
+
+impl<'a, IO: ReadWriteSeek, TP: TimeProvider, OCC: OemCpConverter> Iterator for FindIter<'a, '_, IO, TP, OCC> {
+    type Item = Result<DirEntry<'a, IO, TP, OCC>, Error<IO::Error>>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        loop {
+            match self.iter.next()? {
+                Ok(e) if !is_special_entry(&e) && entry_matches(&e, self.pattern) => return Some(Ok(e)),
+                Ok(_) => {}
+                Err(err) => return Some(Err(err)),
+            }
+        }
+    }
+}
+
+impl<'a, IO: ReadWriteSeek, TP: TimeProvider, OCC: OemCpConverter> Dir<'a, IO, TP, OCC> {
+    /// Returns an iterator over entries of this directory with names matching a wildcard `pattern`.
+    ///
+    /// `*` matches any sequence of characters, `?` matches a single character and `[...]` matches a single
+    /// character from a set which can contain ranges (e.g. `[a-z0-9]`) and is negated by a leading `!` or `^`.
+    /// Following FAT conventions a pattern ending with `.*` matches names without an extension too (so `*.*` matches
+    /// all entries) and a pattern ending with `.` matches only names without an extension. Patterns are matched
+    /// against long names, ignoring case unless `FsOptions::case_sensitive` is enabled. A pattern without wildcards
+    /// matches the same entry as `open_file` would, including lookups by a short name. The `.` and `..` entries are
+    /// never returned.
+    #[must_use]
+    pub fn find<'p>(&self, pattern: &'p str) -> FindIter<'a, 'p, IO, TP, OCC> {
+        FindIter {
+            iter: self.iter(),
+            pattern,
+        }
+    }
+
+    /// Returns paths and entries of all files and directories matching a glob `pattern`.
+    ///
+    /// `pattern` is a '/' separated path relative to self directory. Its components are matched like patterns of
+    /// `Dir::find`, except `**` which matches any number of nested directories (including none), e.g.
+    /// `boot/**/*.efi` matches `.efi` files in `boot` and in all its subdirectories. A trailing `**` matches all
+    /// entries of the subtree. Returned paths are relative to self directory and use names stored on the volume.
+    /// Entries are returned in directory order, depth first.
+    ///
+    /// # Errors
+    ///
+    /// Errors that can be returned:
+    ///
+    /// * `Error::DirectoryModified` will be returned if a searched directory is modified during the search.
+    /// * `Error::Io` will be returned if the underlying storage object returned an I/O error.
+    #[allow(clippy::type_complexity)]
+    pub fn glob(&self, pattern: &str) -> Result<Vec<(String, DirEntry<'a, IO, TP, OCC>)>, Error<IO::Error>> {
+        trace!("Dir::glob {}", pattern);
+        let mut components = pattern
+            .split('/')
+            .filter(|c| !c.is_empty() && *c != ".")
+            .collect::<Vec<_>>();
+        // consecutive `**` components match the same paths as a single one
+        components.dedup_by(|c1, c2| *c1 == "**" && *c2 == "**");
+        let mut matches = Vec::new();
+        if !components.is_empty() {
+            glob_in(self, "", &components, &mut matches)?;
+        }
+        Ok(matches)
+    }
+}
+
+impl<IO: ReadWriteSeek, TP: TimeProvider, OCC: OemCpConverter> FileSystem<IO, TP, OCC> {
+    /// Returns paths and entries of all files and directories matching a glob `pattern` relative to the root
+    /// directory.
+    ///
+    /// See `Dir::glob` for the pattern syntax.
+    ///
+    /// # Errors
+    ///
+    /// Errors are the same as for `Dir::glob`.
+    #[allow(clippy::type_complexity)]
+    pub fn glob(&self, pattern: &str) -> Result<Vec<(String, DirEntry<'_, IO, TP, OCC>)>, Error<IO::Error>> {
+        self.root_dir().glob(pattern)
+    }
+}
+
+#[allow(clippy::type_complexity)]
+fn glob_in<'a, IO: ReadWriteSeek, TP: TimeProvider, OCC: OemCpConverter>(
+    dir: &Dir<'a, IO, TP, OCC>,
+    prefix: &str,
+    components: &[&str],
+    matches: &mut Vec<(String, DirEntry<'a, IO, TP, OCC>)>,
+) -> Result<(), Error<IO::Error>> {
+    let (component, rest) = components.split_first().expect("components are not empty");
+    let recursive = *component == "**";
+    if recursive && !rest.is_empty() {
+        // `**` matching no directory
+        glob_in(dir, prefix, rest, matches)?;
+    }
+    for r in dir.iter() {
+        let e = r?;
+        if is_special_entry(&e) || !(recursive || entry_matches(&e, component)) {
+            continue;
+        }
+        let path = if prefix.is_empty() {
+            e.file_name()
+        } else {
+            format!("{}/{}", prefix, e.file_name())
+        };
+        if recursive {
+            let sub_dir = e.is_dir().then(|| e.to_dir());
+            if rest.is_empty() {
+                matches.push((path.clone(), e));
+            }
+            if let Some(sub_dir) = sub_dir {
+                glob_in(&sub_dir, &path, components, matches)?;
+            }
+        } else if rest.is_empty() {
+            matches.push((path, e));
+        } else if e.is_dir() {
+            glob_in(&e.to_dir(), &path, rest, matches)?;
+        }
+    }
+    Ok(())
+}
+
+fn is_special_entry<IO: ReadWriteSeek, TP, OCC: OemCpConverter>(e: &DirEntry<'_, IO, TP, OCC>) -> bool {
+    let name = e.short_file_name_as_bytes();
+    name == b"." || name == b".."
+}
+
+fn entry_matches<IO: ReadWriteSeek, TP, OCC: OemCpConverter>(e: &DirEntry<'_, IO, TP, OCC>, pattern: &str) -> bool {
+    if pattern.contains(WILDCARD_CHARS) {
+        matches_pattern(pattern, &e.file_name(), !e.fs.options.case_sensitive)
+    } else {
+        e.eq_name(pattern)
+    }
+}
+
+// Matches a single name against a pattern with FAT conventions for extensions
+fn matches_pattern(pattern: &str, name: &str, ignore_case: bool) -> bool {
+    if let Some(base) = pattern.strip_suffix(".*") {
+        if !name.contains('.') && matches_wildcards(base, name, ignore_case) {
+            return true;
+        }
+    } else if let Some(base) = pattern.strip_suffix('.') {
+        return !name.contains('.') && matches_wildcards(base, name, ignore_case);
+    }
+    matches_wildcards(pattern, name, ignore_case)
+}
+
+fn matches_wildcards(pattern: &str, name: &str, ignore_case: bool) -> bool {
+    let (mut pattern, mut name) = (pattern, name);
+    // Pattern following the last `*` and the part of the name it has not absorbed yet
+    let mut backtrack: Option<(&str, &str)> = None;
+    loop {
+        let mut pattern_chars = pattern.chars();
+        let pattern_char = pattern_chars.next();
+        if pattern_char == Some('*') {
+            pattern = pattern_chars.as_str();
+            backtrack = Some((pattern, name));
+            continue;
+        }
+        let mut name_chars = name.chars();
+        let Some(c) = name_chars.next() else {
+            return pattern.is_empty();
+        };
+        let pattern_rest = match pattern_char {
+            Some('?') => Some(pattern_chars.as_str()),
+            Some('[') => {
+                let class = pattern_chars.as_str();
+                match class_len(class) {
+                    Some(len) => class_matches(&class[..len], c, ignore_case).then(|| &class[len + 1..]),
+                    // unterminated class - `[` is a normal character
+                    None => char_eq('[', c, ignore_case).then_some(class),
+                }
+            }
+            Some(p) => char_eq(p, c, ignore_case).then_some(pattern_chars.as_str()),
+            None => None,
+        };
+        if let Some(rest) = pattern_rest {
+            pattern = rest;
+            name = name_chars.as_str();
+            continue;
+        }
+        // Let the last `*` absorb one more character
+        let Some((star_pattern, star_name)) = backtrack else {
+            return false;
+        };
+        let mut star_name_chars = star_name.chars();
+        if star_name_chars.next().is_none() {
+            return false;
+        }
+        pattern = star_pattern;
+        name = star_name_chars.as_str();
+        backtrack = Some((pattern, name));
+    }
+}
+
+// Returns the length of a character class following `[` excluding the closing `]`
+fn class_len(class: &str) -> Option<usize> {
+    let negation_len = usize::from(class.starts_with(['!', '^']));
+    // `]` directly after the opening bracket is a member of the class
+    let first_len = class[negation_len..].chars().next()?.len_utf8();
+    let start = negation_len + first_len;
+    class[start..].find(']').map(|n| start + n)
+}
+
+fn class_matches(class: &str, c: char, ignore_case: bool) -> bool {
+    let (negated, members) = match class.strip_prefix(['!', '^']) {
+        Some(members) => (true, members),
+        None => (false, class),
+    };
+    let fold = |c: char| {
+        if ignore_case {
+            char_to_uppercase(c).next().unwrap_or(c)
+        } else {
+            c
+        }
+    };
+    let c = fold(c);
+    let mut chars = members.chars();
+    let mut found = false;
+    while let Some(first) = chars.next() {
+        let mut lookahead = chars.clone();
+        let last = match (lookahead.next(), lookahead.next()) {
+            (Some('-'), Some(last)) => {
+                chars = lookahead;
+                last
+            }
+            _ => first,
+        };
+        found |= (fold(first)..=fold(last)).contains(&c);
+    }
+    found != negated
+}
+
+fn char_eq(c1: char, c2: char, ignore_case: bool) -> bool {
+    chars_eq(iter::once(c1), iter::once(c2), ignore_case)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_matches_pattern() {
+        assert!(matches_pattern("*.cfg", "boot.cfg", true));
+        assert!(matches_pattern("*.CFG", "boot.cfg", true));
+        assert!(!matches_pattern("*.CFG", "boot.cfg", false));
+        assert!(!matches_pattern("*.cfg", "boot.cfg.bak", true));
+        assert!(matches_pattern("*a*b?", "xaxxbbc", true));
+        assert!(!matches_pattern("*a*b?", "xaxxb", true));
+        assert!(matches_pattern("*", "", true));
+        assert!(matches_pattern("?ż?", "óżź", true));
+        assert!(matches_pattern("[a-c]x[!0-9]", "BxZ", true));
+        assert!(!matches_pattern("[a-c]x[!0-9]", "bx5", true));
+        assert!(matches_pattern("[]]", "]", true));
+        assert!(matches_pattern("[^a]", "b", true));
+        assert!(matches_pattern("a[b", "a[b", true));
+        assert!(matches_pattern("[a-]", "-", true));
+    }
+
+    #[test]
+    fn test_matches_pattern_fat_extensions() {
+        assert!(matches_pattern("*.*", "README", true));
+        assert!(matches_pattern("*.*", "a.b.c", true));
+        assert!(matches_pattern("read*.*", "README", true));
+        assert!(!matches_pattern("x*.*", "README", true));
+        assert!(matches_pattern("*.", "README", true));
+        assert!(!matches_pattern("*.", "README.md", true));
+    }
+}
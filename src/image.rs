@@ -0,0 +1,473 @@
+#[cfg(feature = "std")]
+use std::path::Path;
+
+use crate::boot_sector::format_boot_sector;
+use crate::checksum::crc32;
+use crate::error::{Error, IoError};
+use crate::fs::{format_volume, FatType, FileSystem, FormatVolumeOptions, FsOptions, ReadWriteSeek};
+use crate::io::{AlignedStorage, IoBase, Read, Seek, SeekFrom, Write};
+
+// Partition tables always use 512-byte logical blocks
+const LBA_SIZE: u64 = 512;
+// The partition starts at 1 MiB like in partitioning tools
+const PARTITION_FIRST_LBA: u64 = 2048;
+const GPT_ENTRIES: u32 = 128;
+const GPT_ENTRY_SIZE: u32 = 128;
+// Number of blocks used by the partition entries array
+const GPT_ENTRIES_LBAS: u64 = (GPT_ENTRIES * GPT_ENTRY_SIZE) as u64 / LBA_SIZE;
+const GPT_HEADER_SIZE: u32 = 92;
+
+const MBR_TYPE_FAT12: u8 = 0x01;
+const MBR_TYPE_FAT16_LBA: u8 = 0x0E;
+const MBR_TYPE_FAT32_LBA: u8 = 0x0C;
+const MBR_TYPE_EFI_SYSTEM: u8 = 0xEF;
+const MBR_TYPE_GPT_PROTECTIVE: u8 = 0xEE;
+
+// Partition type GUIDs in the on-disk byte order
+const GPT_TYPE_EFI_SYSTEM: [u8; 16] = [
+    0x28, 0x73, 0x2A, 0xC1, 0x1F, 0xF8, 0xD2, 0x11, 0xBA, 0x4B, 0x00, 0xA0, 0xC9, 0x3E, 0xC9, 0x3B,
+];
+const GPT_TYPE_BASIC_DATA: [u8; 16] = [
+    0xA2, 0xA0, 0xD0, 0xEB, 0xE5, 0xB9, 0x33, 0x44, 0x87, 0xC0, 0x68, 0xB6, 0xB7, 0x26, 0x99, 0xC7,
+];
+
+/// A storage limited to a range of bytes of another storage, e.g. a partition of a disk.
+///
+/// Position 0 corresponds to `offset` in the inner storage. Reads and writes are limited to `len` bytes. Seeking
+/// before the start of the range fails with the error created by `IoError::new_unexpected_eof_error`.
+pub struct PartitionStorage<S> {
+    inner: S,
+    offset: u64,
+    len: u64,
+    pos: u64,
+}
+
+impl<S> PartitionStorage<S> {
+    /// Creates a new `PartitionStorage` spanning `len` bytes of `inner` starting from `offset`.
+    pub fn new(inner: S, offset: u64, len: u64) -> Self {
+        Self {
+            inner,
+            offset,
+            len,
+            pos: 0,
+        }
+    }
+
+    /// Returns inner storage
+    pub fn into_inner(self) -> S {
+        self.inner
+    }
+}
+
+impl<S: IoBase> IoBase for PartitionStorage<S> {
+    type Error = S::Error;
+
+    fn buffer_alignment(&self) -> usize {
+        self.inner.buffer_alignment()
+    }
+}
+
+impl<S: Read + Seek> Read for PartitionStorage<S> {
+    fn read(&mut self, buf: &mut [u8]) -> Result<usize, Self::Error> {
+        let size = (buf.len() as u64).min(self.len.saturating_sub(self.pos)) as usize;
+        if size == 0 {
+            return Ok(0);
+        }
+        self.inner.seek(SeekFrom::Start(self.offset + self.pos))?;
+        let n = self.inner.read(&mut buf[..size])?;
+        self.pos += n as u64;
+        Ok(n)
+    }
+}
+
+impl<S: Write + Seek> Write for PartitionStorage<S> {
+    fn write(&mut self, buf: &[u8]) -> Result<usize, Self::Error> {
+        let size = (buf.len() as u64).min(self.len.saturating_sub(self.pos)) as usize;
+        if size == 0 {
+            return Ok(0);
+        }
+        self.inner.seek(SeekFrom::Start(self.offset + self.pos))?;
+        let n = self.inner.write(&buf[..size])?;
+        self.pos += n as u64;
+        Ok(n)
+    }
+
+    fn flush(&mut self) -> Result<(), Self::Error> {
+        self.inner.flush()
+    }
+}
+
+impl<S: IoBase> Seek for PartitionStorage<S> {
+    fn seek(&mut self, pos: SeekFrom) -> Result<u64, Self::Error> {
+        let new_pos = match pos {
+            SeekFrom::Start(n) => Some(n),
+            SeekFrom::End(n) => i64::try_from(self.len)
+                .ok()
+                .and_then(|len| len.checked_add(n))
+                .and_then(|n| u64::try_from(n).ok()),
+            SeekFrom::Current(n) => i64::try_from(self.pos)
+                .ok()
+                .and_then(|pos| pos.checked_add(n))
+                .and_then(|n| u64::try_from(n).ok()),
+        };
+        self.pos = new_pos.ok_or_else(S::Error::new_unexpected_eof_error)?;
+        Ok(self.pos)
+    }
+}
+
+/// A partition table created by `ImageBuilder`.
+#[derive(Copy, Clone, Eq, PartialEq, Debug)]
+pub enum PartitionTable {
+    /// The filesystem occupies the whole image (a "superfloppy").
+    None,
+    /// Master Boot Record with one partition.
+    Mbr,
+    /// GUID Partition Table (with a protective MBR) with one partition.
+    Gpt,
+}
+
+/// A builder of disk images containing a single FAT partition.
+///
+/// The partition starts at 1 MiB and ends at the end of the image (before the backup GPT). Partition tables use
+/// 512-byte blocks. The volume ID from the format options is used as the MBR disk signature and as a seed of
+/// GPT GUIDs.
+///
+/// For example the following builder creates a 64 MiB FAT32 EFI System Partition image:
+///
+/// ```no_run
+/// use axfatfs::{FatType, FormatVolumeOptions, ImageBuilder, PartitionTable};
+///
+/// let fs = ImageBuilder::new(64 * 1024 * 1024)
+///     .partition_table(PartitionTable::Gpt)
+///     .efi_system_partition(true)
+///     .format_options(FormatVolumeOptions::new().fat_type(FatType::Fat32))
+///     .create("esp.img")?;
+/// fs.root_dir().create_dir("EFI")?;
+/// # Ok::<(), std::io::Error>(())
+/// ```
+#[derive(Clone, Debug)]
+pub struct ImageBuilder {
+    size: u64,
+    partition_table: PartitionTable,
+    efi_system_partition: bool,
+    format_options: FormatVolumeOptions,
+}
+
+impl ImageBuilder {
+    /// Creates a builder of an image of `size` bytes.
+    #[must_use]
+    pub fn new(size: u64) -> Self {
+        Self {
+            size,
+            partition_table: PartitionTable::Mbr,
+            efi_system_partition: false,
+            format_options: FormatVolumeOptions::new(),
+        }
+    }
+
+    /// Sets the partition table written to the image.
+    ///
+    /// Default is `PartitionTable::Mbr`.
+    #[must_use]
+    pub fn partition_table(mut self, partition_table: PartitionTable) -> Self {
+        self.partition_table = partition_table;
+        self
+    }
+
+    /// If enabled the partition type is EFI System Partition. Otherwise it is a FAT or basic data partition.
+    ///
+    /// Default is `false`.
+    #[must_use]
+    pub fn efi_system_partition(mut self, enabled: bool) -> Self {
+        self.efi_system_partition = enabled;
+        self
+    }
+
+    /// Sets options used to format the partition.
+    ///
+    /// The number of hidden sectors is overwritten with the partition offset. Default is `FormatVolumeOptions::new()`.
+    #[must_use]
+    pub fn format_options(mut self, format_options: FormatVolumeOptions) -> Self {
+        self.format_options = format_options;
+        self
+    }
+
+    fn partition_range(&self) -> (u64, u64) {
+        let lbas = self.size / LBA_SIZE;
+        match self.partition_table {
+            PartitionTable::None => (0, lbas),
+            PartitionTable::Mbr => (PARTITION_FIRST_LBA, lbas.saturating_sub(PARTITION_FIRST_LBA)),
+            // backup GPT header and partition entries occupy the end of the disk
+            PartitionTable::Gpt => (
+                PARTITION_FIRST_LBA,
+                lbas.saturating_sub(PARTITION_FIRST_LBA + GPT_ENTRIES_LBAS + 1),
+            ),
+        }
+    }
+
+    /// Writes the partition table to `storage`, formats the partition and mounts it.
+    ///
+    /// `storage` must be at least as big as the image size. Data outside of the partition table and the filesystem
+    /// structures is not modified.
+    ///
+    /// # Errors
+    ///
+    /// Errors that can be returned:
+    ///
+    /// * `Error::InvalidInput` will be returned if the image size is not a multiple of 512, the partition is too
+    ///   small for the requested format options or the image is too big for the MBR.
+    /// * `Error::Io` will be returned if the underlying storage object returned an I/O error.
+    pub fn build<S: ReadWriteSeek>(self, mut storage: S) -> Result<FileSystem<PartitionStorage<S>>, Error<S::Error>> {
+        trace!("ImageBuilder::build");
+        let (first_lba, lbas) = self.partition_range();
+        let total_sectors = lbas * LBA_SIZE / u64::from(self.format_options.bytes_per_sector);
+        if self.size % LBA_SIZE != 0 || lbas == 0 || total_sectors > u64::from(u32::MAX) {
+            error!("invalid image size {}", self.size);
+            return Err(Error::InvalidInput);
+        }
+        let format_options = self.format_options.clone().hidden_sectors(first_lba as u32);
+        #[allow(clippy::cast_possible_truncation)]
+        let (_, fat_type) = format_boot_sector::<S::Error>(&format_options, total_sectors as u32)?;
+        let mut aligned = AlignedStorage::<&mut S, S>::new(&mut storage);
+        match self.partition_table {
+            PartitionTable::None => {}
+            PartitionTable::Mbr => {
+                let partition_type = if self.efi_system_partition {
+                    MBR_TYPE_EFI_SYSTEM
+                } else {
+                    match fat_type {
+                        FatType::Fat12 => MBR_TYPE_FAT12,
+                        FatType::Fat16 => MBR_TYPE_FAT16_LBA,
+                        FatType::Fat32 => MBR_TYPE_FAT32_LBA,
+                    }
+                };
+                if first_lba + lbas > u64::from(u32::MAX) {
+                    error!("image is too big for MBR");
+                    return Err(Error::InvalidInput);
+                }
+                write_mbr(&mut aligned, format_options.volume_id, partition_type, first_lba, lbas)?;
+            }
+            PartitionTable::Gpt => self.write_gpt(&mut aligned, format_options.volume_id, first_lba, lbas)?,
+        }
+        let mut partition = PartitionStorage::new(storage, first_lba * LBA_SIZE, lbas * LBA_SIZE);
+        format_volume(&mut partition, format_options)?;
+        partition.seek(SeekFrom::Start(0))?;
+        FileSystem::new(partition, FsOptions::new())
+    }
+
+    /// Creates an image file at `path`, writes the partition table, formats the partition and mounts it.
+    ///
+    /// An existing file is truncated.
+    ///
+    /// # Errors
+    ///
+    /// Errors are the same as for `build` and errors returned when creating the file.
+    #[cfg(feature = "std")]
+    pub fn create<P: AsRef<Path>>(
+        self,
+        path: P,
+    ) -> std::io::Result<FileSystem<PartitionStorage<crate::StdIoWrapper<std::fs::File>>>> {
+        let file = std::fs::OpenOptions::new()
+            .read(true)
+            .write(true)
+            .create(true)
+            .truncate(true)
+            .open(path)?;
+        file.set_len(self.size)?;
+        Ok(self.build(crate::StdIoWrapper::new(file))?)
+    }
+
+    fn write_gpt<S: ReadWriteSeek>(
+        &self,
+        storage: &mut S,
+        seed: u32,
+        first_lba: u64,
+        lbas: u64,
+    ) -> Result<(), Error<S::Error>> {
+        let disk_lbas = self.size / LBA_SIZE;
+        let last_lba = disk_lbas - 1;
+        let protective_lbas = (disk_lbas - 1).min(u64::from(u32::MAX));
+        write_mbr(storage, 0, MBR_TYPE_GPT_PROTECTIVE, 1, protective_lbas)?;
+
+        let mut entries = [0_u8; (GPT_ENTRIES * GPT_ENTRY_SIZE) as usize];
+        let (type_guid, name) = if self.efi_system_partition {
+            (GPT_TYPE_EFI_SYSTEM, "EFI System Partition")
+        } else {
+            (GPT_TYPE_BASIC_DATA, "Basic data partition")
+        };
+        entries[..16].copy_from_slice(&type_guid);
+        entries[16..32].copy_from_slice(&generate_guid(seed, 1));
+        entries[32..40].copy_from_slice(&first_lba.to_le_bytes());
+        entries[40..48].copy_from_slice(&(first_lba + lbas - 1).to_le_bytes());
+        for (i, c) in name.encode_utf16().enumerate() {
+            entries[56 + i * 2..58 + i * 2].copy_from_slice(&c.to_le_bytes());
+        }
+        let entries_crc = crc32(&entries);
+
+        let backup_entries_lba = last_lba - GPT_ENTRIES_LBAS;
+        let disk_guid = generate_guid(seed, 0);
+        for (header_lba, alternate_lba, entries_lba) in [(1, last_lba, 2), (last_lba, 1, backup_entries_lba)] {
+            let mut header = [0_u8; LBA_SIZE as usize];
+            header[..8].copy_from_slice(b"EFI PART");
+            header[8..12].copy_from_slice(&0x0001_0000_u32.to_le_bytes());
+            header[12..16].copy_from_slice(&GPT_HEADER_SIZE.to_le_bytes());
+            header[24..32].copy_from_slice(&u64::to_le_bytes(header_lba));
+            header[32..40].copy_from_slice(&u64::to_le_bytes(alternate_lba));
+            header[40..48].copy_from_slice(&(2 + GPT_ENTRIES_LBAS).to_le_bytes());
+            header[48..56].copy_from_slice(&(backup_entries_lba - 1).to_le_bytes());
+            header[56..72].copy_from_slice(&disk_guid);
+            header[72..80].copy_from_slice(&u64::to_le_bytes(entries_lba));
+            header[80..84].copy_from_slice(&GPT_ENTRIES.to_le_bytes());
+            header[84..88].copy_from_slice(&GPT_ENTRY_SIZE.to_le_bytes());
+            header[88..92].copy_from_slice(&entries_crc.to_le_bytes());
+            let header_crc = crc32(&header[..GPT_HEADER_SIZE as usize]);
+            header[16..20].copy_from_slice(&header_crc.to_le_bytes());
+            storage.seek(SeekFrom::Start(header_lba * LBA_SIZE))?;
+            storage.write_all(&header)?;
+            storage.seek(SeekFrom::Start(entries_lba * LBA_SIZE))?;
+            storage.write_all(&entries)?;
+        }
+        Ok(())
+    }
+}
+
+fn write_mbr<S: ReadWriteSeek>(
+    storage: &mut S,
+    disk_signature: u32,
+    partition_type: u8,
+    first_lba: u64,
+    lbas: u64,
+) -> Result<(), Error<S::Error>> {
+    let mut mbr = [0_u8; LBA_SIZE as usize];
+    mbr[440..444].copy_from_slice(&disk_signature.to_le_bytes());
+    let entry = &mut mbr[446..462];
+    // protective MBR entries must not be bootable
+    entry[0] = if partition_type == MBR_TYPE_GPT_PROTECTIVE {
+        0
+    } else {
+        0x80
+    };
+    // CHS addresses are not used, mark them as out of range
+    entry[1..4].copy_from_slice(&[0xFE, 0xFF, 0xFF]);
+    entry[4] = partition_type;
+    entry[5..8].copy_from_slice(&[0xFE, 0xFF, 0xFF]);
+    #[allow(clippy::cast_possible_truncation)]
+    {
+        entry[8..12].copy_from_slice(&(first_lba as u32).to_le_bytes());
+        entry[12..16].copy_from_slice(&(lbas as u32).to_le_bytes());
+    }
+    mbr[510..512].copy_from_slice(&[0x55, 0xAA]);
+    storage.seek(SeekFrom::Start(0))?;
+    storage.write_all(&mbr)?;
+    Ok(())
+}
+
+// Generates a version 4 GUID deterministically from the seed and the GUID index
+fn generate_guid(seed: u32, index: u32) -> [u8; 16] {
+    let mut state = (u64::from(seed) << 32) | u64::from(index);
+    let mut guid = [0_u8; 16];
+    for chunk in guid.chunks_mut(8) {
+        // splitmix64
+        state = state.wrapping_add(0x9E37_79B9_7F4A_7C15);
+        let mut z = state;
+        z = (z ^ (z >> 30)).wrapping_mul(0xBF58_476D_1CE4_E5B9);
+        z = (z ^ (z >> 27)).wrapping_mul(0x94D0_49BB_1331_11EB);
+        chunk.copy_from_slice(&(z ^ (z >> 31)).to_le_bytes());
+    }
+    guid[7] = (guid[7] & 0x0F) | 0x40;
+    guid[8] = (guid[8] & 0x3F) | 0x80;
+    guid
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::StdIoWrapper;
+    use std::io::Cursor;
+
+    const IMAGE_SIZE: u64 = 8 * 1024 * 1024;
+
+    fn build_image(builder: ImageBuilder) -> Vec<u8> {
+        let storage = StdIoWrapper::new(Cursor::new(vec![0_u8; IMAGE_SIZE as usize]));
+        let fs = builder.build(storage).unwrap();
+        fs.root_dir()
+            .create_file("hello.txt")
+            .unwrap()
+            .write_all(b"Hello!")
+            .unwrap();
+        fs.into_inner().unwrap().into_inner().into_inner().into_inner()
+    }
+
+    fn read_partition(image: Vec<u8>, first_lba: u64, lbas: u64) {
+        let storage = StdIoWrapper::new(Cursor::new(image));
+        let partition = PartitionStorage::new(storage, first_lba * LBA_SIZE, lbas * LBA_SIZE);
+        let fs = FileSystem::new(partition, FsOptions::new()).unwrap();
+        let mut buf = [0_u8; 6];
+        fs.open_file("hello.txt").unwrap().read_exact(&mut buf).unwrap();
+        assert_eq!(&buf, b"Hello!");
+        assert!(fs.check().unwrap().is_clean());
+    }
+
+    fn read_u32(image: &[u8], offset: usize) -> u32 {
+        u32::from_le_bytes(image[offset..offset + 4].try_into().unwrap())
+    }
+
+    fn read_u64(image: &[u8], offset: usize) -> u64 {
+        u64::from_le_bytes(image[offset..offset + 8].try_into().unwrap())
+    }
+
+    #[test]
+    fn test_build_mbr_image() {
+        let image = build_image(ImageBuilder::new(IMAGE_SIZE).format_options(FormatVolumeOptions::new().volume_id(7)));
+        assert_eq!(&image[510..512], &[0x55, 0xAA]);
+        assert_eq!(read_u32(&image, 440), 7);
+        assert_eq!(image[446], 0x80);
+        assert_eq!(image[450], MBR_TYPE_FAT16_LBA);
+        assert_eq!(read_u32(&image, 454), 2048);
+        assert_eq!(u64::from(read_u32(&image, 458)), IMAGE_SIZE / LBA_SIZE - 2048);
+        // hidden sectors field of the partition boot sector
+        assert_eq!(read_u32(&image, 2048 * 512 + 0x1C), 2048);
+        read_partition(image, 2048, IMAGE_SIZE / LBA_SIZE - 2048);
+    }
+
+    #[test]
+    fn test_build_gpt_image() {
+        let image = build_image(
+            ImageBuilder::new(IMAGE_SIZE)
+                .partition_table(PartitionTable::Gpt)
+                .efi_system_partition(true),
+        );
+        let last_lba = IMAGE_SIZE / LBA_SIZE - 1;
+        assert_eq!(image[450], MBR_TYPE_GPT_PROTECTIVE);
+        for (header_lba, entries_lba) in [(1, 2), (last_lba, last_lba - 32)] {
+            let header = &image[(header_lba * LBA_SIZE) as usize..((header_lba + 1) * LBA_SIZE) as usize];
+            assert_eq!(&header[..8], b"EFI PART");
+            assert_eq!(read_u64(header, 24), header_lba);
+            assert_eq!(read_u64(header, 72), entries_lba);
+            let mut header_copy = header[..92].to_vec();
+            header_copy[16..20].fill(0);
+            assert_eq!(read_u32(header, 16), crc32(&header_copy));
+            let entries = &image[(entries_lba * LBA_SIZE) as usize..((entries_lba + 32) * LBA_SIZE) as usize];
+            assert_eq!(read_u32(header, 88), crc32(entries));
+            assert_eq!(&entries[..16], &GPT_TYPE_EFI_SYSTEM);
+            assert_eq!(read_u64(entries, 32), 2048);
+            assert_eq!(read_u64(entries, 40), last_lba - 33);
+        }
+        read_partition(image, 2048, IMAGE_SIZE / LBA_SIZE - 2048 - 33);
+    }
+
+    #[test]
+    fn test_build_superfloppy_image() {
+        let image = build_image(ImageBuilder::new(IMAGE_SIZE).partition_table(PartitionTable::None));
+        read_partition(image, 0, IMAGE_SIZE / LBA_SIZE);
+    }
+
+    #[test]
+    fn test_build_invalid_size() {
+        let storage = StdIoWrapper::new(Cursor::new(vec![0_u8; 4096]));
+        assert!(matches!(
+            ImageBuilder::new(1000).build(storage),
+            Err(Error::InvalidInput)
+        ));
+    }
+}
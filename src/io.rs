@@ -1,4 +1,10 @@
+#[cfg(all(not(feature = "std"), feature = "alloc"))]
+use alloc::vec::Vec;
+use core::borrow::BorrowMut;
+use core::marker::PhantomData;
+
 use crate::error::IoError;
+use crate::fs::{MAX_BUFFER_ALIGNMENT, MAX_SECTOR_SIZE};
 
 /// Provides IO error as an associated type.
 ///
@@ -7,6 +13,16 @@ use crate::error::IoError;
 pub trait IoBase {
     /// Type of errors returned by input/output operations.
     type Error: IoError;
+
+    /// Returns the alignment of memory addresses of buffers required by this object (e.g. by a DMA engine).
+    ///
+    /// The filesystem passes only buffers aligned to this value to the storage - an unaligned beginning of a buffer
+    /// passed by the caller and metadata buffers (e.g. FAT entries, directory entries or the boot sector) are copied
+    /// through an internal aligned buffer. It must be a power of two not greater than 4096. Default is `1` (no
+    /// requirement).
+    fn buffer_alignment(&self) -> usize {
+        1
+    }
 }
 
 /// The `Read` trait allows for reading bytes from a source.
@@ -74,6 +90,59 @@ pub trait Read: IoBase {
     }
 }
 
+/// The `BufRead` trait allows for reading bytes from a source through an internal buffer.
+///
+/// It is based on the `std::io::BufRead` trait.
+pub trait BufRead: Read {
+    /// Returns the contents of the internal buffer, filling it with more data from the source if it is empty.
+    ///
+    /// An empty buffer returned indicates that the end of the source has been reached. Returned bytes are not
+    /// consumed - `consume` has to be called to advance the position.
+    ///
+    /// # Errors
+    ///
+    /// An error is returned if filling the buffer failed.
+    fn fill_buf(&mut self) -> Result<&[u8], Self::Error>;
+
+    /// Marks `amt` bytes returned by `fill_buf` as consumed, so they are not returned again.
+    ///
+    /// `amt` should not be greater than the length of the buffer returned by `fill_buf`.
+    fn consume(&mut self, amt: usize);
+
+    /// Reads bytes into `buf` until the `byte` delimiter (included) or the end of the source is reached, returning
+    /// the number of read bytes.
+    ///
+    /// # Errors
+    ///
+    /// Errors returned by `fill_buf` for which `IoError::is_interrupted` returns false are returned. Bytes read
+    /// before the error are appended to `buf`.
+    #[cfg(feature = "alloc")]
+    fn read_until(&mut self, byte: u8, buf: &mut Vec<u8>) -> Result<usize, Self::Error> {
+        let mut read = 0;
+        loop {
+            let (found, used) = {
+                let available = match self.fill_buf() {
+                    Ok(available) => available,
+                    Err(ref e) if e.is_interrupted() => continue,
+                    Err(e) => return Err(e),
+                };
+                if let Some(i) = available.iter().position(|b| *b == byte) {
+                    buf.extend_from_slice(&available[..=i]);
+                    (true, i + 1)
+                } else {
+                    buf.extend_from_slice(available);
+                    (false, available.len())
+                }
+            };
+            self.consume(used);
+            read += used;
+            if found || used == 0 {
+                return Ok(read);
+            }
+        }
+    }
+}
+
 /// The `Write` trait allows for writing bytes into the sink.
 ///
 /// It is based on the `std::io::Write` trait.
@@ -126,6 +195,7 @@ pub trait Write: IoBase {
 ///
 /// It is based on the `std::io::SeekFrom` enum.
 #[derive(Debug, Clone, Copy, PartialEq, Eq)]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
 pub enum SeekFrom {
     /// Sets the offset to the provided number of bytes.
     Start(u64),
@@ -141,7 +211,8 @@ pub enum SeekFrom {
 pub trait Seek: IoBase {
     /// Seek to an offset, in bytes, in a stream.
     ///
-    /// A seek beyond the end of a stream or to a negative position is not allowed.
+    /// A seek to a negative position is not allowed. Whether a seek beyond the end of a stream is allowed depends
+    /// on the implementation.
     ///
     /// If the seek operation completed successfully, this method returns the new position from the start of the
     /// stream. That position can be used later with `SeekFrom::Start`.
@@ -239,6 +310,93 @@ impl<T> From<T> for StdIoWrapper<T> {
     }
 }
 
+// Buffer used to transfer data which is not aligned as required by the storage
+#[repr(C, align(4096))]
+pub(crate) struct AlignedBuffer(pub(crate) [u8; MAX_SECTOR_SIZE]);
+
+const _: () = assert!(core::mem::align_of::<AlignedBuffer>() == MAX_BUFFER_ALIGNMENT);
+
+// Note: bounce functions are not inlined so the buffer does not enlarge the stack frame of the common path
+#[inline(never)]
+fn read_bounced<T: Read>(storage: &mut T, buf: &mut [u8]) -> Result<usize, T::Error> {
+    let mut bounce = AlignedBuffer([0; MAX_SECTOR_SIZE]);
+    let n = storage.read(&mut bounce.0[..buf.len()])?;
+    buf[..n].copy_from_slice(&bounce.0[..n]);
+    Ok(n)
+}
+
+#[inline(never)]
+fn write_bounced<T: Write>(storage: &mut T, buf: &[u8]) -> Result<usize, T::Error> {
+    let mut bounce = AlignedBuffer([0; MAX_SECTOR_SIZE]);
+    bounce.0[..buf.len()].copy_from_slice(buf);
+    storage.write(&bounce.0[..buf.len()])
+}
+
+// A storage wrapper making sure that buffers passed to the inner storage are aligned to its `buffer_alignment`.
+// An unaligned beginning of a buffer is transferred through an aligned buffer on the stack, so the rest of a big
+// buffer is passed directly, and small metadata buffers are copied as a whole.
+pub(crate) struct AlignedStorage<B, S = B> {
+    inner: B,
+    alignment: usize,
+    // phantom is needed to add type bounds on the storage type
+    phantom: PhantomData<S>,
+}
+
+impl<B: BorrowMut<S>, S: IoBase> AlignedStorage<B, S> {
+    pub(crate) fn new(inner: B) -> Self {
+        let alignment = inner.borrow().buffer_alignment();
+        Self {
+            inner,
+            alignment,
+            phantom: PhantomData,
+        }
+    }
+
+    pub(crate) fn into_inner(self) -> B {
+        self.inner
+    }
+
+    // Returns the length of the prefix of `buf` which has to be copied through an aligned buffer
+    fn bounce_len(&self, buf: &[u8]) -> usize {
+        match buf.as_ptr() as usize % self.alignment {
+            0 => 0,
+            misalignment => buf.len().min(self.alignment - misalignment).min(MAX_SECTOR_SIZE),
+        }
+    }
+}
+
+impl<B, S: IoBase> IoBase for AlignedStorage<B, S> {
+    type Error = S::Error;
+}
+
+impl<B: BorrowMut<S>, S: Read> Read for AlignedStorage<B, S> {
+    fn read(&mut self, buf: &mut [u8]) -> Result<usize, Self::Error> {
+        match self.bounce_len(buf) {
+            0 => self.inner.borrow_mut().read(buf),
+            len => read_bounced(self.inner.borrow_mut(), &mut buf[..len]),
+        }
+    }
+}
+
+impl<B: BorrowMut<S>, S: Write> Write for AlignedStorage<B, S> {
+    fn write(&mut self, buf: &[u8]) -> Result<usize, Self::Error> {
+        match self.bounce_len(buf) {
+            0 => self.inner.borrow_mut().write(buf),
+            len => write_bounced(self.inner.borrow_mut(), &buf[..len]),
+        }
+    }
+
+    fn flush(&mut self) -> Result<(), Self::Error> {
+        self.inner.borrow_mut().flush()
+    }
+}
+
+impl<B: BorrowMut<S>, S: Seek> Seek for AlignedStorage<B, S> {
+    fn seek(&mut self, pos: SeekFrom) -> Result<u64, Self::Error> {
+        self.inner.borrow_mut().seek(pos)
+    }
+}
+
 pub(crate) trait ReadLeExt {
     type Error;
     fn read_u8(&mut self) -> Result<u8, Self::Error>;
@@ -320,7 +478,7 @@ mod tests {
     #[test]
     fn test_seek_from_clone_copy() {
         let start = SeekFrom::Start(100);
-        let cloned = start.clone();
+        let cloned = start;
         assert_eq!(start, cloned);
 
         let copied = start;
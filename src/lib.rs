@@ -16,12 +16,13 @@
 //! use std::io::prelude::*;
 //!
 //! fn main() -> std::io::Result<()> {
+//!     # std::fs::create_dir_all("tmp")?;
 //!     std::fs::copy("resources/fat16.img", "tmp/fat.img")?;
 //!     // Initialize a filesystem object
 //!     let img_file = std::fs::OpenOptions::new().read(true).write(true)
 //!         .open("tmp/fat.img")?;
 //!     let buf_stream = fscommon::BufStream::new(img_file);
-//!     let fs = fatfs::FileSystem::new(buf_stream, fatfs::FsOptions::new())?;
+//!     let fs = axfatfs::FileSystem::new(buf_stream, axfatfs::FsOptions::new())?;
 //!     let root_dir = fs.root_dir();
 //!
 //!     // Write a file
@@ -62,20 +63,88 @@ extern crate alloc;
 #[macro_use]
 mod log_macros;
 
+#[cfg(feature = "embedded-io-async")]
+mod async_storage;
+mod block_device;
 mod boot_sector;
+#[cfg(feature = "alloc")]
+mod check;
+mod checksum;
+#[cfg(feature = "alloc")]
+mod compare;
+#[cfg(feature = "alloc")]
+mod copy;
 mod dir;
 mod dir_entry;
 mod error;
+#[cfg(all(feature = "std", feature = "alloc"))]
+mod export;
 mod file;
 mod fs;
+#[cfg(feature = "alloc")]
+mod glob;
+mod image;
 mod io;
+mod memory;
+mod observer;
+mod oem_cp;
+#[cfg(feature = "alloc")]
+mod overlay;
+#[cfg(feature = "std")]
+mod populate;
+#[cfg(feature = "alloc")]
+mod quota;
+#[cfg(feature = "alloc")]
+mod shared;
+mod sync;
 mod table;
+#[cfg(feature = "alloc")]
+pub mod testing;
 mod time;
+#[cfg(feature = "tokio")]
+pub mod tokio;
+#[cfg(feature = "alloc")]
+mod usage;
+#[cfg(all(feature = "std", feature = "alloc"))]
+mod verify;
+#[cfg(feature = "virtio")]
+mod virtio;
+mod watch;
 
+#[cfg(feature = "embedded-io-async")]
+pub use crate::async_storage::*;
+pub use crate::block_device::*;
+pub use crate::boot_sector::*;
+#[cfg(feature = "alloc")]
+pub use crate::check::*;
+pub use crate::checksum::*;
+#[cfg(feature = "alloc")]
+pub use crate::compare::*;
+#[cfg(feature = "alloc")]
+pub use crate::copy::*;
 pub use crate::dir::*;
 pub use crate::dir_entry::*;
 pub use crate::error::*;
 pub use crate::file::*;
 pub use crate::fs::*;
+#[cfg(feature = "alloc")]
+pub use crate::glob::*;
+pub use crate::image::*;
 pub use crate::io::*;
+pub use crate::memory::*;
+pub use crate::observer::*;
+pub use crate::oem_cp::*;
+#[cfg(feature = "alloc")]
+pub use crate::overlay::*;
+#[cfg(feature = "std")]
+pub use crate::populate::*;
+#[cfg(feature = "alloc")]
+pub use crate::quota::*;
+#[cfg(feature = "alloc")]
+pub use crate::shared::*;
 pub use crate::time::*;
+#[cfg(feature = "alloc")]
+pub use crate::usage::*;
+#[cfg(feature = "virtio")]
+pub use crate::virtio::*;
+pub use crate::watch::*;
@@ -0,0 +1,410 @@
+#[cfg(all(not(feature = "std"), feature = "alloc"))]
+use alloc::{boxed::Box, vec, vec::Vec};
+use core::fmt;
+
+#[cfg(feature = "std")]
+use crate::error::Error;
+use crate::error::IoError;
+use crate::io::{IoBase, Read, Seek, SeekFrom, Write};
+
+/// An error returned by in-memory storages.
+#[derive(Copy, Clone, Eq, PartialEq, Debug)]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+pub enum MemoryStorageError {
+    /// A read operation cannot be completed because an end of the storage has been reached prematurely.
+    UnexpectedEof,
+    /// A write operation cannot be completed because an end of the storage has been reached.
+    WriteZero,
+    /// A seek to a negative position was requested.
+    InvalidSeek,
+    /// A write operation cannot be completed because the memory limit of the storage has been reached.
+    NotEnoughSpace,
+}
+
+impl IoError for MemoryStorageError {
+    fn is_interrupted(&self) -> bool {
+        false
+    }
+
+    fn new_unexpected_eof_error() -> Self {
+        MemoryStorageError::UnexpectedEof
+    }
+
+    fn new_write_zero_error() -> Self {
+        MemoryStorageError::WriteZero
+    }
+
+    fn is_out_of_space(&self) -> bool {
+        *self == MemoryStorageError::NotEnoughSpace
+    }
+}
+
+impl fmt::Display for MemoryStorageError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            MemoryStorageError::UnexpectedEof => write!(f, "Unexpected end of file"),
+            MemoryStorageError::WriteZero => write!(f, "Write zero"),
+            MemoryStorageError::InvalidSeek => write!(f, "Invalid seek"),
+            MemoryStorageError::NotEnoughSpace => write!(f, "Not enough space"),
+        }
+    }
+}
+
+#[cfg(feature = "std")]
+impl std::error::Error for MemoryStorageError {}
+
+#[cfg(feature = "std")]
+impl From<MemoryStorageError> for std::io::Error {
+    fn from(error: MemoryStorageError) -> Self {
+        let kind = match error {
+            MemoryStorageError::UnexpectedEof => std::io::ErrorKind::UnexpectedEof,
+            MemoryStorageError::WriteZero => std::io::ErrorKind::WriteZero,
+            MemoryStorageError::InvalidSeek => std::io::ErrorKind::InvalidInput,
+            MemoryStorageError::NotEnoughSpace => std::io::ErrorKind::OutOfMemory,
+        };
+        Self::new(kind, error)
+    }
+}
+
+// Makes `std::io` traits available for files of filesystems using in-memory storages
+#[cfg(feature = "std")]
+impl From<Error<MemoryStorageError>> for std::io::Error {
+    fn from(error: Error<MemoryStorageError>) -> Self {
+        error.map_io(Self::from).into()
+    }
+}
+
+/// A storage keeping the whole filesystem image in memory.
+///
+/// Any buffer providing mutable access to its bytes can be used, e.g. `&mut [u8]`, `[u8; N]` or `Vec<u8>`. The
+/// storage has a fixed size - writes beyond the end of the buffer fail.
+///
+/// For example a filesystem image can be embedded in the program and mounted from RAM:
+///
+/// ```
+/// use axfatfs::{FileSystem, FsOptions, MemoryStorage};
+///
+/// let mut image = include_bytes!("../resources/fat16.img").to_vec();
+/// let fs = FileSystem::new(MemoryStorage::new(image.as_mut_slice()), FsOptions::new())?;
+/// let mut file = fs.root_dir().create_file("hello.txt")?;
+/// # Ok::<(), axfatfs::Error<axfatfs::MemoryStorageError>>(())
+/// ```
+#[derive(Clone, Debug)]
+pub struct MemoryStorage<T> {
+    data: T,
+    pos: u64,
+}
+
+impl<T> MemoryStorage<T> {
+    /// Creates a new `MemoryStorage` instance over `data`.
+    pub fn new(data: T) -> Self {
+        Self { data, pos: 0 }
+    }
+
+    /// Returns a reference to the inner buffer.
+    pub fn get_ref(&self) -> &T {
+        &self.data
+    }
+
+    /// Returns inner buffer
+    pub fn into_inner(self) -> T {
+        self.data
+    }
+}
+
+impl<T: AsRef<[u8]>> MemoryStorage<T> {
+    // Returns the not yet processed part of the buffer range starting at the current position
+    fn remaining_range(&self, len: usize) -> core::ops::Range<usize> {
+        let data_len = self.data.as_ref().len();
+        let start = usize::try_from(self.pos).unwrap_or(data_len).min(data_len);
+        start..start + len.min(data_len - start)
+    }
+}
+
+impl<T> IoBase for MemoryStorage<T> {
+    type Error = MemoryStorageError;
+}
+
+impl<T: AsRef<[u8]>> Read for MemoryStorage<T> {
+    fn read(&mut self, buf: &mut [u8]) -> Result<usize, Self::Error> {
+        let range = self.remaining_range(buf.len());
+        let n = range.len();
+        buf[..n].copy_from_slice(&self.data.as_ref()[range]);
+        self.pos += n as u64;
+        Ok(n)
+    }
+}
+
+impl<T: AsRef<[u8]> + AsMut<[u8]>> Write for MemoryStorage<T> {
+    fn write(&mut self, buf: &[u8]) -> Result<usize, Self::Error> {
+        let range = self.remaining_range(buf.len());
+        let n = range.len();
+        self.data.as_mut()[range].copy_from_slice(&buf[..n]);
+        self.pos += n as u64;
+        Ok(n)
+    }
+
+    fn flush(&mut self) -> Result<(), Self::Error> {
+        Ok(())
+    }
+}
+
+impl<T: AsRef<[u8]>> Seek for MemoryStorage<T> {
+    fn seek(&mut self, pos: SeekFrom) -> Result<u64, Self::Error> {
+        let (base, offset) = match pos {
+            SeekFrom::Start(n) => (n, 0),
+            SeekFrom::End(n) => (self.data.as_ref().len() as u64, n),
+            SeekFrom::Current(n) => (self.pos, n),
+        };
+        let new_pos = if offset >= 0 {
+            base.checked_add(offset.unsigned_abs())
+        } else {
+            base.checked_sub(offset.unsigned_abs())
+        };
+        self.pos = new_pos.ok_or(MemoryStorageError::InvalidSeek)?;
+        Ok(self.pos)
+    }
+}
+
+const RAM_DISK_PAGE_SIZE: usize = 4096;
+
+/// A growable storage keeping data in memory pages allocated on demand.
+///
+/// Pages are allocated only when non-zero data is written to them, unwritten parts read as zeros. Writing past the
+/// end of the storage extends it. This makes it suitable for RAM-backed volumes: a large volume can be formatted
+/// and only the space actually used by files occupies memory.
+///
+/// An optional memory limit bounds the number of allocated pages. Writes needing more memory fail and the
+/// filesystem reports them as `Error::NotEnoughSpace`.
+///
+/// ```
+/// use axfatfs::{format_volume, Error, FileSystem, FormatVolumeOptions, FsOptions, RamDisk, Write};
+///
+/// let mut disk = RamDisk::with_len(64 * 1024 * 1024).memory_limit(1024 * 1024);
+/// format_volume(&mut disk, FormatVolumeOptions::new())?;
+/// let fs = FileSystem::new(disk, FsOptions::new())?;
+/// let mut file = fs.root_dir().create_file("big.bin")?;
+/// let result = (0..1024).try_for_each(|_| file.write_all(&[0xAA; 4096]));
+/// assert!(matches!(result, Err(Error::NotEnoughSpace)));
+/// # Ok::<(), axfatfs::Error<axfatfs::MemoryStorageError>>(())
+/// ```
+#[cfg(feature = "alloc")]
+#[derive(Clone, Debug, Default)]
+pub struct RamDisk {
+    pages: Vec<Option<Box<[u8]>>>,
+    len: u64,
+    pos: u64,
+    allocated: usize,
+    memory_limit: Option<usize>,
+}
+
+#[cfg(feature = "alloc")]
+impl RamDisk {
+    /// Creates a new empty `RamDisk` instance.
+    #[must_use]
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Creates a new `RamDisk` instance of size `len` filled with zeros.
+    ///
+    /// No memory is allocated for the content until it is written.
+    #[must_use]
+    pub fn with_len(len: u64) -> Self {
+        Self { len, ..Self::default() }
+    }
+
+    /// Limits memory used by the content of the storage to `limit` bytes.
+    ///
+    /// The limit is rounded down to the page size. Default is no limit.
+    #[must_use]
+    pub fn memory_limit(mut self, limit: usize) -> Self {
+        self.memory_limit = Some(limit);
+        self
+    }
+
+    /// Returns size of the storage in bytes.
+    #[must_use]
+    pub fn len(&self) -> u64 {
+        self.len
+    }
+
+    /// Returns `true` if the storage has zero size.
+    #[must_use]
+    pub fn is_empty(&self) -> bool {
+        self.len == 0
+    }
+
+    /// Returns number of bytes of memory allocated for the content.
+    #[must_use]
+    pub fn allocated(&self) -> usize {
+        self.allocated
+    }
+
+    fn page_index(pos: u64) -> Option<usize> {
+        usize::try_from(pos / RAM_DISK_PAGE_SIZE as u64).ok()
+    }
+
+    // Returns the page for writing, allocating it if needed
+    fn page_mut(&mut self, index: usize) -> Result<&mut [u8], MemoryStorageError> {
+        if self.pages.len() <= index {
+            self.pages.resize(index + 1, None);
+        }
+        if self.pages[index].is_none() {
+            let new_allocated = self.allocated + RAM_DISK_PAGE_SIZE;
+            if self.memory_limit.map_or(false, |limit| new_allocated > limit) {
+                return Err(MemoryStorageError::NotEnoughSpace);
+            }
+            self.pages[index] = Some(vec![0_u8; RAM_DISK_PAGE_SIZE].into_boxed_slice());
+            self.allocated = new_allocated;
+        }
+        Ok(self.pages[index].as_deref_mut().unwrap())
+    }
+}
+
+#[cfg(feature = "alloc")]
+impl IoBase for RamDisk {
+    type Error = MemoryStorageError;
+}
+
+#[cfg(feature = "alloc")]
+impl Read for RamDisk {
+    fn read(&mut self, buf: &mut [u8]) -> Result<usize, Self::Error> {
+        if self.pos >= self.len {
+            return Ok(0);
+        }
+        let offset = (self.pos % RAM_DISK_PAGE_SIZE as u64) as usize;
+        let available = usize::try_from(self.len - self.pos).unwrap_or(usize::MAX);
+        let n = buf.len().min(RAM_DISK_PAGE_SIZE - offset).min(available);
+        let page = Self::page_index(self.pos)
+            .and_then(|index| self.pages.get(index))
+            .and_then(Option::as_deref);
+        match page {
+            Some(page) => buf[..n].copy_from_slice(&page[offset..offset + n]),
+            None => buf[..n].fill(0),
+        }
+        self.pos += n as u64;
+        Ok(n)
+    }
+}
+
+#[cfg(feature = "alloc")]
+impl Write for RamDisk {
+    fn write(&mut self, buf: &[u8]) -> Result<usize, Self::Error> {
+        let offset = (self.pos % RAM_DISK_PAGE_SIZE as u64) as usize;
+        let n = buf.len().min(RAM_DISK_PAGE_SIZE - offset);
+        let index = Self::page_index(self.pos).ok_or(MemoryStorageError::NotEnoughSpace)?;
+        let is_allocated = self.pages.get(index).map_or(false, Option::is_some);
+        // zeros written to a page which is not allocated do not change its content
+        if is_allocated || buf[..n].iter().any(|b| *b != 0) {
+            self.page_mut(index)?[offset..offset + n].copy_from_slice(&buf[..n]);
+        }
+        self.pos += n as u64;
+        self.len = self.len.max(self.pos);
+        Ok(n)
+    }
+
+    fn flush(&mut self) -> Result<(), Self::Error> {
+        Ok(())
+    }
+}
+
+#[cfg(feature = "alloc")]
+impl Seek for RamDisk {
+    fn seek(&mut self, pos: SeekFrom) -> Result<u64, Self::Error> {
+        let (base, offset) = match pos {
+            SeekFrom::Start(n) => (n, 0),
+            SeekFrom::End(n) => (self.len, n),
+            SeekFrom::Current(n) => (self.pos, n),
+        };
+        let new_pos = if offset >= 0 {
+            base.checked_add(offset.unsigned_abs())
+        } else {
+            base.checked_sub(offset.unsigned_abs())
+        };
+        self.pos = new_pos.ok_or(MemoryStorageError::InvalidSeek)?;
+        Ok(self.pos)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{Error, FileSystem, FsOptions};
+
+    #[test]
+    fn test_memory_storage_slice() {
+        let mut image = std::fs::read("resources/fat16.img").unwrap();
+        let fs = FileSystem::new(MemoryStorage::new(image.as_mut_slice()), FsOptions::new()).unwrap();
+        fs.root_dir()
+            .create_file("memory.txt")
+            .unwrap()
+            .write_all(b"Hello memory!")
+            .unwrap();
+        fs.unmount().unwrap();
+
+        let fs = FileSystem::new(MemoryStorage::new(image), FsOptions::new()).unwrap();
+        let mut content = String::new();
+        // std::io traits are available
+        std::io::Read::read_to_string(&mut fs.open_file("memory.txt").unwrap(), &mut content).unwrap();
+        assert_eq!(content, "Hello memory!");
+        assert!(fs.check().unwrap().is_clean());
+    }
+
+    #[test]
+    fn test_memory_storage_bounds() {
+        let mut storage = MemoryStorage::new([0_u8; 16]);
+        assert_eq!(storage.seek(SeekFrom::End(-2)).unwrap(), 14);
+        assert_eq!(storage.write(&[1, 2, 3]).unwrap(), 2);
+        assert_eq!(storage.write(&[4]).unwrap(), 0);
+        assert!(matches!(storage.write_all(&[4]), Err(MemoryStorageError::WriteZero)));
+        assert_eq!(storage.seek(SeekFrom::Start(100)).unwrap(), 100);
+        assert_eq!(storage.read(&mut [0_u8; 4]).unwrap(), 0);
+        assert_eq!(
+            storage.seek(SeekFrom::Current(-101)),
+            Err(MemoryStorageError::InvalidSeek)
+        );
+        assert_eq!(&storage.get_ref()[13..], &[0, 1, 2]);
+    }
+
+    #[test]
+    fn test_ram_disk_sparse_growth() {
+        let mut disk = RamDisk::new();
+        disk.seek(SeekFrom::Start(10000)).unwrap();
+        disk.write_all(&[0_u8; 5000]).unwrap();
+        assert_eq!(disk.len(), 15000);
+        assert_eq!(disk.allocated(), 0);
+        disk.seek(SeekFrom::Start(4090)).unwrap();
+        disk.write_all(b"page boundary").unwrap();
+        assert_eq!(disk.allocated(), 2 * RAM_DISK_PAGE_SIZE);
+        let mut buf = [0xFF_u8; 20];
+        disk.seek(SeekFrom::Start(4088)).unwrap();
+        disk.read_exact(&mut buf).unwrap();
+        assert_eq!(&buf, b"\0\0page boundary\0\0\0\0\0");
+        disk.seek(SeekFrom::End(-1)).unwrap();
+        assert_eq!(disk.read(&mut buf).unwrap(), 1);
+        assert_eq!(disk.read(&mut buf).unwrap(), 0);
+    }
+
+    #[test]
+    fn test_ram_disk_memory_limit() {
+        let mut disk = RamDisk::with_len(8 * 1024 * 1024).memory_limit(256 * 1024);
+        crate::format_volume(&mut disk, crate::FormatVolumeOptions::new()).unwrap();
+        let fs = FileSystem::new(disk, FsOptions::new()).unwrap();
+        let mut file = fs.root_dir().create_file("big.bin").unwrap();
+        let mut written = 0;
+        let err = loop {
+            match file.write_all(&[0x55; 1024]) {
+                Ok(()) => written += 1024,
+                Err(err) => break err,
+            }
+        };
+        assert!(matches!(err, Error::NotEnoughSpace), "{:?}", err);
+        assert!(written > 0 && written < 256 * 1024);
+        drop(file);
+        // the filesystem stays usable after a failed write
+        fs.root_dir().remove("big.bin").unwrap();
+        fs.root_dir().create_dir("dir").unwrap();
+        assert!(fs.check().unwrap().is_clean());
+    }
+}
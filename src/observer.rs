@@ -0,0 +1,55 @@
+use core::fmt;
+
+/// A directory operation reported to `FsObserver::on_dir_operation`.
+#[derive(Copy, Clone, Eq, PartialEq, Debug)]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+#[non_exhaustive]
+pub enum DirOperation {
+    /// An entry is looked up by name in a directory.
+    Lookup,
+    /// A file or a directory has been created.
+    Create,
+    /// A file or a directory has been removed.
+    Remove,
+    /// A file or a directory has been renamed or moved.
+    Rename,
+}
+
+/// An observer of operations performed by a filesystem.
+///
+/// It is registered using `FsOptions::observer` and can be used to collect metrics or profiling data without this
+/// crate depending on any metrics library. All methods have empty default implementations. They are called
+/// synchronously, sometimes with internal locks held, so they should be cheap and must not access the filesystem.
+/// Storage accesses made during mounting are not reported.
+pub trait FsObserver: Sync {
+    /// Called after `len` bytes have been read from the storage at byte `offset`.
+    fn on_storage_read(&self, _offset: u64, _len: usize) {}
+
+    /// Called after `len` bytes have been written to the storage at byte `offset`.
+    fn on_storage_write(&self, _offset: u64, _len: usize) {}
+
+    /// Called after the storage has been flushed.
+    fn on_storage_flush(&self) {}
+
+    /// Called after a chain of `count` clusters starting at `first_cluster` has been allocated.
+    fn on_clusters_allocated(&self, _first_cluster: u32, _count: u32) {}
+
+    /// Called after `count` clusters have been freed.
+    fn on_clusters_freed(&self, _count: u32) {}
+
+    /// Called when a directory operation is performed on the entry `name`.
+    ///
+    /// Lookups are reported before the directory is searched, other operations after they succeed.
+    fn on_dir_operation(&self, _op: DirOperation, _name: &str) {}
+}
+
+// Optional observer stored in `FsOptions`
+#[derive(Copy, Clone, Default)]
+pub(crate) struct ObserverRef(pub(crate) Option<&'static dyn FsObserver>);
+
+impl fmt::Debug for ObserverRef {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let name = if self.0.is_some() { "Some(..)" } else { "None" };
+        f.write_str(name)
+    }
+}
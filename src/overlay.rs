@@ -0,0 +1,353 @@
+#[cfg(not(feature = "std"))]
+use alloc::{vec, vec::Vec};
+
+use crate::error::Error;
+use crate::fs::{FileSystem, FsOptions};
+use crate::io::{IoBase, Read, Seek, SeekFrom, Write};
+use crate::memory::{MemoryStorageError, RamDisk};
+
+const DEFAULT_OVERLAY_BLOCK_SIZE: u32 = 4096;
+
+/// A copy-on-write storage combining an immutable base image with a delta storage receiving all writes.
+///
+/// The storage is divided into blocks. Blocks which have never been written are read from the base storage. The
+/// first write to a block copies it to the delta storage and all further accesses to the block use the delta storage.
+/// The base storage is only read, so many overlays - e.g. of hypervisor guests - can share one golden image while
+/// each of them sees a private writable view.
+///
+/// Blocks are stored in the delta storage at the same offsets as in the base storage, so a sparse file or a `RamDisk`
+/// only occupies space for modified blocks. The list of modified blocks is kept in memory. The size of the storage
+/// is the size of the base storage - writes beyond its end are not possible.
+///
+/// ```
+/// use axfatfs::{FileSystem, FsOptions, MemoryStorage, OverlayStorage, RamDisk, Write};
+///
+/// let golden_image = include_bytes!("../resources/fat16.img");
+/// let storage = OverlayStorage::new(MemoryStorage::new(&golden_image[..]), RamDisk::new())?;
+/// let fs = FileSystem::new(storage, FsOptions::new())?;
+/// fs.root_dir().create_file("guest.txt")?.write_all(b"private data")?;
+/// let storage = fs.into_inner()?;
+/// assert!(storage.modified_size() > 0);
+/// # Ok::<(), axfatfs::Error<axfatfs::MemoryStorageError>>(())
+/// ```
+#[derive(Clone, Debug)]
+pub struct OverlayStorage<B, D> {
+    base: B,
+    delta: D,
+    len: u64,
+    pos: u64,
+    block_size: u32,
+    // Bitmap of blocks stored in the delta storage, grown on demand
+    modified: Vec<u64>,
+    // Buffer used for merging partially written blocks with the base content
+    block_buf: Vec<u8>,
+}
+
+impl<B: Seek, D> OverlayStorage<B, D> {
+    /// Creates a new `OverlayStorage` instance reading unmodified blocks from `base` and storing modified blocks in
+    /// `delta`.
+    ///
+    /// Existing content of `delta` is ignored - all blocks are unmodified initially.
+    ///
+    /// # Errors
+    ///
+    /// Returns an error if seeking to the end of `base` to determine its size fails.
+    pub fn new(mut base: B, delta: D) -> Result<Self, B::Error> {
+        let len = base.seek(SeekFrom::End(0))?;
+        Ok(Self {
+            base,
+            delta,
+            len,
+            pos: 0,
+            block_size: DEFAULT_OVERLAY_BLOCK_SIZE,
+            modified: Vec::new(),
+            block_buf: Vec::new(),
+        })
+    }
+}
+
+impl<B: Seek> OverlayStorage<B, RamDisk> {
+    /// Creates a new `OverlayStorage` instance keeping modified blocks in a `RamDisk`.
+    ///
+    /// Modifications exist only in memory and are lost when the storage is dropped. A memory limit can be set by
+    /// passing a `RamDisk` configured by `RamDisk::memory_limit` to `OverlayStorage::new` instead.
+    ///
+    /// # Errors
+    ///
+    /// Errors are the same as for `OverlayStorage::new`.
+    pub fn volatile(base: B) -> Result<Self, B::Error> {
+        Self::new(base, RamDisk::new())
+    }
+}
+
+impl<B, D> OverlayStorage<B, D> {
+    /// Sets the size of blocks copied to the delta storage on the first write.
+    ///
+    /// Smaller blocks make copying cheaper, larger blocks make the list of modified blocks smaller. Default is 4096
+    /// bytes. Changing the block size discards all modifications.
+    ///
+    /// # Panics
+    ///
+    /// Panics if `block_size` is zero.
+    #[must_use]
+    pub fn block_size(mut self, block_size: u32) -> Self {
+        assert!(block_size > 0, "Block size must not be zero");
+        self.block_size = block_size;
+        self.modified.clear();
+        self
+    }
+
+    /// Returns size of the storage in bytes.
+    #[must_use]
+    pub fn len(&self) -> u64 {
+        self.len
+    }
+
+    /// Returns `true` if the storage has zero size.
+    #[must_use]
+    pub fn is_empty(&self) -> bool {
+        self.len == 0
+    }
+
+    /// Returns number of bytes of blocks stored in the delta storage.
+    #[must_use]
+    pub fn modified_size(&self) -> u64 {
+        let block_size = u64::from(self.block_size);
+        let blocks: u64 = self.modified.iter().map(|w| u64::from(w.count_ones())).sum();
+        let last_block = self.len.saturating_sub(1) / block_size;
+        if self.is_modified(last_block) {
+            // the last block can be shorter
+            blocks * block_size - ((last_block + 1) * block_size - self.len)
+        } else {
+            blocks * block_size
+        }
+    }
+
+    /// Discards all modifications, so the storage has the content of the base storage again.
+    ///
+    /// Content of the delta storage is not changed, but it is not used until blocks are written again.
+    pub fn discard_changes(&mut self) {
+        self.modified.clear();
+    }
+
+    /// Returns references to the base and the delta storage.
+    pub fn get_ref(&self) -> (&B, &D) {
+        (&self.base, &self.delta)
+    }
+
+    /// Returns the base and the delta storage.
+    pub fn into_parts(self) -> (B, D) {
+        (self.base, self.delta)
+    }
+
+    fn is_modified(&self, block: u64) -> bool {
+        usize::try_from(block / 64)
+            .ok()
+            .and_then(|index| self.modified.get(index))
+            .map_or(false, |w| w & (1 << (block % 64)) != 0)
+    }
+
+    fn set_modified(&mut self, block: u64) {
+        let index = usize::try_from(block / 64).expect("block index fits in usize");
+        if self.modified.len() <= index {
+            self.modified.resize(index + 1, 0);
+        }
+        self.modified[index] |= 1 << (block % 64);
+    }
+
+    // Returns the length of the run of blocks starting at the current position, which are all modified or all
+    // unmodified, limited to `max_len` bytes and the end of the storage
+    fn run_len(&self, max_len: usize) -> (usize, bool) {
+        let block_size = u64::from(self.block_size);
+        let max_end = self.pos + (self.len - self.pos).min(max_len as u64);
+        let mut block = self.pos / block_size;
+        let modified = self.is_modified(block);
+        let mut end = (block + 1) * block_size;
+        while end < max_end && self.is_modified(block + 1) == modified {
+            block += 1;
+            end += block_size;
+        }
+        ((end.min(max_end) - self.pos) as usize, modified)
+    }
+}
+
+impl<B: Read + Seek, TP, OCC> FileSystem<OverlayStorage<B, RamDisk>, TP, OCC>
+where
+    B::Error: From<MemoryStorageError>,
+{
+    /// Creates a new filesystem object instance in a volatile mode.
+    ///
+    /// The filesystem is mounted from an overlay created by `OverlayStorage::volatile`, so it is writable but `base`
+    /// is only read and all changes are discarded when the filesystem is unmounted or dropped. It is useful for live
+    /// images and for exercising write paths without risking the source image.
+    ///
+    /// # Errors
+    ///
+    /// Errors are the same as for `FileSystem::new`.
+    pub fn new_volatile(base: B, options: FsOptions<TP, OCC>) -> Result<Self, Error<B::Error>> {
+        Self::new(OverlayStorage::volatile(base)?, options)
+    }
+}
+
+impl<B: IoBase, D> IoBase for OverlayStorage<B, D> {
+    type Error = B::Error;
+}
+
+impl<B: Read + Seek, D: Read + Seek> Read for OverlayStorage<B, D>
+where
+    B::Error: From<D::Error>,
+{
+    fn read(&mut self, buf: &mut [u8]) -> Result<usize, Self::Error> {
+        if self.pos >= self.len || buf.is_empty() {
+            return Ok(0);
+        }
+        let (n, modified) = self.run_len(buf.len());
+        if modified {
+            self.delta.seek(SeekFrom::Start(self.pos))?;
+            self.delta.read_exact(&mut buf[..n])?;
+        } else {
+            self.base.seek(SeekFrom::Start(self.pos))?;
+            self.base.read_exact(&mut buf[..n])?;
+        }
+        self.pos += n as u64;
+        Ok(n)
+    }
+}
+
+impl<B: Read + Seek, D: Read + Write + Seek> Write for OverlayStorage<B, D>
+where
+    B::Error: From<D::Error>,
+{
+    fn write(&mut self, buf: &[u8]) -> Result<usize, Self::Error> {
+        if self.pos >= self.len || buf.is_empty() {
+            return Ok(0);
+        }
+        let block_size = u64::from(self.block_size);
+        let block = self.pos / block_size;
+        let block_start = block * block_size;
+        let block_len = block_size.min(self.len - block_start) as usize;
+        let (n, modified) = self.run_len(buf.len());
+        let whole_blocks = self.pos == block_start && n >= block_len;
+        if modified || whole_blocks {
+            // blocks are fully overwritten or already copied - no merging is needed
+            let n = if modified || self.pos + n as u64 == self.len {
+                n
+            } else {
+                n / block_len * block_len
+            };
+            self.delta.seek(SeekFrom::Start(self.pos))?;
+            self.delta.write_all(&buf[..n])?;
+            let end = self.pos + n as u64;
+            for b in block..(end + block_size - 1) / block_size {
+                self.set_modified(b);
+            }
+            self.pos = end;
+            return Ok(n);
+        }
+        // copy the block to the delta storage merged with the written data
+        let offset = (self.pos - block_start) as usize;
+        let n = n.min(block_len - offset);
+        if self.block_buf.len() < block_len {
+            self.block_buf = vec![0; block_len];
+        }
+        let block_buf = &mut self.block_buf[..block_len];
+        self.base.seek(SeekFrom::Start(block_start))?;
+        self.base.read_exact(block_buf)?;
+        block_buf[offset..offset + n].copy_from_slice(&buf[..n]);
+        self.delta.seek(SeekFrom::Start(block_start))?;
+        self.delta.write_all(block_buf)?;
+        self.set_modified(block);
+        self.pos += n as u64;
+        Ok(n)
+    }
+
+    fn flush(&mut self) -> Result<(), Self::Error> {
+        Ok(self.delta.flush()?)
+    }
+}
+
+impl<B: Seek, D> Seek for OverlayStorage<B, D> {
+    fn seek(&mut self, pos: SeekFrom) -> Result<u64, Self::Error> {
+        // the base storage has the same size, so it validates the position
+        if let SeekFrom::Current(_) = pos {
+            self.base.seek(SeekFrom::Start(self.pos))?;
+        }
+        self.pos = self.base.seek(pos)?;
+        Ok(self.pos)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{FileSystem, FsOptions, MemoryStorage, MemoryStorageError, RamDisk};
+
+    #[test]
+    fn test_overlay_storage_shared_base() {
+        let image = std::fs::read("resources/fat16.img").unwrap();
+        let storage = OverlayStorage::new(MemoryStorage::new(image.as_slice()), RamDisk::new()).unwrap();
+        let fs = FileSystem::new(storage, FsOptions::new()).unwrap();
+        fs.root_dir()
+            .create_file("guest.txt")
+            .unwrap()
+            .write_all(b"Hello guest!")
+            .unwrap();
+        fs.root_dir().create_dir("guest-dir").unwrap();
+        let storage = fs.into_inner().unwrap();
+        assert!(storage.modified_size() > 0);
+        assert_eq!(storage.get_ref().0.get_ref(), &image.as_slice());
+
+        // the private view keeps the changes
+        let fs = FileSystem::new(storage, FsOptions::new()).unwrap();
+        assert_eq!(fs.read("guest.txt").unwrap(), b"Hello guest!");
+        assert!(fs.check().unwrap().is_clean());
+        let mut storage = fs.into_inner().unwrap();
+
+        // another view of the same base does not see them
+        let other = OverlayStorage::new(MemoryStorage::new(image.as_slice()), RamDisk::new()).unwrap();
+        let fs = FileSystem::new(other, FsOptions::new()).unwrap();
+        assert!(!fs.exists("guest.txt"));
+        drop(fs);
+
+        storage.discard_changes();
+        assert_eq!(storage.modified_size(), 0);
+        let fs = FileSystem::new(storage, FsOptions::new()).unwrap();
+        assert!(!fs.exists("guest.txt"));
+    }
+
+    #[test]
+    fn test_overlay_storage_partial_blocks() {
+        let base = [0x11_u8; 100];
+        let mut storage = OverlayStorage::new(MemoryStorage::new(&base[..]), RamDisk::new())
+            .unwrap()
+            .block_size(16);
+        assert_eq!(storage.len(), 100);
+        storage.seek(SeekFrom::Start(10)).unwrap();
+        storage.write_all(&[0x22; 30]).unwrap();
+        assert_eq!(storage.modified_size(), 48);
+        // a write ending in the last partial block
+        storage.seek(SeekFrom::End(-6)).unwrap();
+        storage.write_all(&[0x33; 6]).unwrap();
+        assert_eq!(storage.write(&[0x44]).unwrap(), 0);
+        assert_eq!(storage.modified_size(), 68);
+
+        let mut buf = [0_u8; 100];
+        storage.seek(SeekFrom::Start(0)).unwrap();
+        storage.read_exact(&mut buf).unwrap();
+        assert_eq!(storage.read(&mut buf).unwrap(), 0);
+        let mut expected = [0x11_u8; 100];
+        expected[10..40].fill(0x22);
+        expected[94..].fill(0x33);
+        assert_eq!(buf, expected);
+        assert_eq!(base, [0x11_u8; 100]);
+
+        assert_eq!(
+            storage.seek(SeekFrom::Current(-101)),
+            Err(MemoryStorageError::InvalidSeek)
+        );
+        storage.discard_changes();
+        storage.seek(SeekFrom::Start(0)).unwrap();
+        storage.read_exact(&mut buf).unwrap();
+        assert_eq!(buf, [0x11_u8; 100]);
+    }
+}
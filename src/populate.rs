@@ -0,0 +1,129 @@
+use std::fs;
+use std::io;
+use std::path::Path;
+
+use crate::dir::Dir;
+use crate::dir_entry::FileAttributes;
+use crate::error::Error;
+use crate::fs::{FileSystem, OemCpConverter, ReadWriteSeek};
+use crate::io::Write;
+use crate::time::{DateTime, TimeProvider};
+
+/// Options for `FileSystem::populate_from`.
+#[derive(Copy, Clone, Debug)]
+pub struct PopulateOptions {
+    pub(crate) preserve_timestamps: bool,
+    pub(crate) preserve_attributes: bool,
+}
+
+impl PopulateOptions {
+    /// Creates populate options with default values.
+    #[must_use]
+    pub fn new() -> Self {
+        Self {
+            preserve_timestamps: true,
+            preserve_attributes: false,
+        }
+    }
+
+    /// If enabled creation, last access and last modification times of host files and directories are copied.
+    /// Otherwise the times come from the `TimeProvider`.
+    ///
+    /// Default is `true`.
+    #[must_use]
+    pub fn preserve_timestamps(mut self, enabled: bool) -> Self {
+        self.preserve_timestamps = enabled;
+        self
+    }
+
+    /// If enabled host files and directories without write permission get the `READ_ONLY` attribute.
+    ///
+    /// Default is `false`.
+    #[must_use]
+    pub fn preserve_attributes(mut self, enabled: bool) -> Self {
+        self.preserve_attributes = enabled;
+        self
+    }
+}
+
+impl Default for PopulateOptions {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl<IO: ReadWriteSeek, TP: TimeProvider, OCC: OemCpConverter> FileSystem<IO, TP, OCC>
+where
+    io::Error: From<Error<IO::Error>>,
+{
+    /// Recursively copies contents of the host directory `host_dir` into the root directory.
+    ///
+    /// Existing files are overwritten and existing directories are merged. Symbolic links are followed and special
+    /// files (e.g. sockets) are skipped. Host times are converted to the local time if the `chrono` feature is
+    /// enabled, otherwise they are stored in UTC.
+    ///
+    /// # Errors
+    ///
+    /// Errors that can be returned:
+    ///
+    /// * `std::io::ErrorKind::InvalidInput` will be returned if a host file name is not valid Unicode or it cannot
+    ///   be used in the filesystem.
+    /// * Errors returned by the host filesystem and errors of filesystem operations converted to `std::io::Error`.
+    pub fn populate_from<P: AsRef<Path>>(&self, host_dir: P, options: PopulateOptions) -> io::Result<()> {
+        trace!("FileSystem::populate_from");
+        populate_dir(&self.root_dir(), host_dir.as_ref(), options)
+    }
+}
+
+fn populate_dir<IO: ReadWriteSeek, TP: TimeProvider, OCC: OemCpConverter>(
+    dir: &Dir<'_, IO, TP, OCC>,
+    host_dir: &Path,
+    options: PopulateOptions,
+) -> io::Result<()>
+where
+    io::Error: From<Error<IO::Error>>,
+{
+    for host_entry in fs::read_dir(host_dir)? {
+        let host_path = host_entry?.path();
+        let name = host_file_name(&host_path)?;
+        let metadata = fs::metadata(&host_path)?;
+        if metadata.is_dir() {
+            let sub_dir = dir.create_dir(name)?;
+            populate_dir(&sub_dir, &host_path, options)?;
+            let mut entry = dir.find_entry_by_path(name)?.ok_or(Error::NotFound)?;
+            if options.preserve_attributes && metadata.permissions().readonly() {
+                entry.set_attributes(entry.attributes() | FileAttributes::READ_ONLY)?;
+            }
+            if options.preserve_timestamps {
+                entry.edit(|e| {
+                    e.set_created(DateTime::from_host_time(metadata.created().ok()));
+                    e.set_accessed(DateTime::from_host_time(metadata.accessed().ok()).date);
+                    e.set_modified(DateTime::from_host_time(metadata.modified().ok()));
+                })?;
+            }
+        } else if metadata.is_file() {
+            let mut file = dir.create_file(name)?;
+            file.truncate()?;
+            io::copy(&mut fs::File::open(&host_path)?, &mut file)?;
+            if options.preserve_attributes && metadata.permissions().readonly() {
+                file.set_attributes(FileAttributes::ARCHIVE | FileAttributes::READ_ONLY);
+            }
+            if options.preserve_timestamps {
+                file.set_created(DateTime::from_host_time(metadata.created().ok()));
+                file.set_accessed(DateTime::from_host_time(metadata.accessed().ok()).date);
+                file.set_modified(DateTime::from_host_time(metadata.modified().ok()));
+            }
+            file.flush()?;
+        } else {
+            warn!("skipping special file {}", host_path.display());
+        }
+    }
+    Ok(())
+}
+
+pub(crate) fn host_file_name(host_path: &Path) -> io::Result<&str> {
+    host_path
+        .file_name()
+        .and_then(|name| name.to_str())
+        .ok_or_else(|| io::Error::new(io::ErrorKind::InvalidInput, "host file name is not valid Unicode"))
+}
@@ -0,0 +1,448 @@
+//! Limiting of space used by directory trees.
+//!
+//! This module implements `QuotaDir` and `QuotaFile` which wrap `Dir` and `File` objects and refuse to allocate
+//! clusters above a limit shared by all handles created from the same quota, so tenants sharing one volume can be
+//! capped.
+
+#[cfg(not(feature = "std"))]
+use alloc::sync::Arc;
+#[cfg(feature = "std")]
+use std::sync::Arc;
+
+use crate::dir::{points_above, Dir};
+use crate::error::Error;
+use crate::file::File;
+use crate::fs::{OemCpConverter, ReadWriteSeek};
+use crate::io::{IoBase, Read, Seek, SeekFrom, Write};
+use crate::sync::Mutex;
+use crate::time::TimeProvider;
+
+// State shared by all handles of a quota
+struct Quota {
+    limit: u64,
+    cluster_size: u64,
+    used: Mutex<u64>,
+}
+
+impl Quota {
+    // Reserves as many whole clusters of `size` bytes as the limit allows and returns the number of reserved bytes
+    fn reserve_up_to(&self, size: u64) -> u64 {
+        let mut used = self.used.lock();
+        let available = self.limit.saturating_sub(*used) / self.cluster_size * self.cluster_size;
+        let reserved = size.min(available);
+        *used += reserved;
+        reserved
+    }
+
+    fn reserve(&self, size: u64) -> bool {
+        let mut used = self.used.lock();
+        if self.limit.saturating_sub(*used) < size {
+            return false;
+        }
+        *used += size;
+        true
+    }
+
+    // Replaces a reservation by the actual usage which can exceed it if an operation allocated more than expected
+    fn settle(&self, reserved: u64, actual: u64) {
+        let mut used = self.used.lock();
+        *used = (*used + actual).saturating_sub(reserved);
+    }
+
+    fn release(&self, size: u64) {
+        self.settle(size, 0);
+    }
+}
+
+/// A directory handle which limits the space allocated in its directory tree.
+///
+/// The quota covers the directory passed to `QuotaDir::new` and all its descendants. It is shared by all
+/// `QuotaDir` and `QuotaFile` handles created from this one. Operations which would allocate clusters above the limit
+/// fail with `Error::NotEnoughSpace`. Usage is counted like `DiskUsage::allocated_size` - in whole clusters, for both
+/// files and directories.
+///
+/// Only changes made through quota handles are accounted, so the directory tree should not be modified using other
+/// handles, including the `Dir` returned by `as_dir` which can be used for reading. Paths pointing above the
+/// directory are rejected, so a tree cannot be escaped using `..` components. Usage of a file is tracked by each
+/// `QuotaFile` handle separately, so a file extended through several handles at once can be counted more than once
+/// until it is truncated or removed.
+pub struct QuotaDir<'a, IO: ReadWriteSeek, TP, OCC> {
+    dir: Dir<'a, IO, TP, OCC>,
+    quota: Arc<Quota>,
+}
+
+impl<'a, IO: ReadWriteSeek, TP, OCC> QuotaDir<'a, IO, TP, OCC> {
+    /// Returns the directory borrowed from this handle.
+    #[must_use]
+    pub fn as_dir(&self) -> &Dir<'a, IO, TP, OCC> {
+        &self.dir
+    }
+
+    /// Returns the maximal number of bytes which can be allocated in the directory tree.
+    #[must_use]
+    pub fn limit(&self) -> u64 {
+        self.quota.limit
+    }
+
+    /// Returns the number of bytes allocated in the directory tree.
+    ///
+    /// It can exceed the limit if the tree was already larger when the quota was created.
+    #[must_use]
+    pub fn used(&self) -> u64 {
+        *self.quota.used.lock()
+    }
+
+    /// Returns the number of bytes which can still be allocated in the directory tree.
+    #[must_use]
+    pub fn available(&self) -> u64 {
+        self.limit().saturating_sub(self.used())
+    }
+}
+
+impl<'a, IO: ReadWriteSeek, TP: TimeProvider, OCC: OemCpConverter> QuotaDir<'a, IO, TP, OCC> {
+    /// Creates a quota of `limit` bytes for the directory tree of `dir`.
+    ///
+    /// The current usage is computed by `Dir::disk_usage`, so the whole tree is read.
+    ///
+    /// # Errors
+    ///
+    /// Errors are the same as for `Dir::disk_usage`.
+    pub fn new(dir: Dir<'a, IO, TP, OCC>, limit: u64) -> Result<Self, Error<IO::Error>> {
+        let used = dir.disk_usage()?.allocated_size();
+        let cluster_size = u64::from(dir.fs().cluster_size());
+        Ok(Self {
+            dir,
+            quota: Arc::new(Quota {
+                limit,
+                cluster_size,
+                used: Mutex::new(used),
+            }),
+        })
+    }
+
+    /// Opens existing subdirectory.
+    ///
+    /// See `Dir::open_dir`.
+    ///
+    /// # Errors
+    ///
+    /// Errors are the same as for `Dir::open_dir`, and `Error::InvalidInput` will be returned if `path` points above
+    /// this directory.
+    pub fn open_dir(&self, path: &str) -> Result<Self, Error<IO::Error>> {
+        check_path(path)?;
+        let dir = self.dir.open_dir(path)?;
+        Ok(self.with_dir(dir))
+    }
+
+    /// Creates new directory or opens existing.
+    ///
+    /// See `Dir::create_dir`. Creating a directory requires a quota of two clusters - one for the new directory and
+    /// one for a possible extension of its parent directory. The unused part is released after the operation.
+    ///
+    /// # Errors
+    ///
+    /// Errors are the same as for `Dir::create_dir`, and additionally:
+    ///
+    /// * `Error::InvalidInput` will be returned if `path` points above this directory.
+    /// * `Error::NotEnoughSpace` will be returned if the directory does not exist and the quota is exceeded.
+    pub fn create_dir(&self, path: &str) -> Result<Self, Error<IO::Error>> {
+        check_path(path)?;
+        let (parent, name) = self.split_parent(path)?;
+        let reserved = 2 * self.quota.cluster_size;
+        if !self.quota.reserve(reserved) {
+            return match parent.open_dir(name) {
+                Err(Error::NotFound) => Err(Error::NotEnoughSpace),
+                r => r.map(|dir| self.with_dir(dir)),
+            };
+        }
+        let dir = self.with_growth(&parent, reserved, |parent| {
+            let (dir, created) = parent.open_or_create_dir(name)?;
+            let new_size = if created { dir.allocated_size()? } else { 0 };
+            Ok((dir, new_size))
+        })?;
+        Ok(self.with_dir(dir))
+    }
+
+    /// Opens existing file.
+    ///
+    /// See `Dir::open_file`.
+    ///
+    /// # Errors
+    ///
+    /// Errors are the same as for `Dir::open_file`, and `Error::InvalidInput` will be returned if `path` points
+    /// above this directory.
+    pub fn open_file(&self, path: &str) -> Result<QuotaFile<'a, IO, TP, OCC>, Error<IO::Error>> {
+        check_path(path)?;
+        let file = self.dir.open_file(path)?;
+        self.with_file(file)
+    }
+
+    /// Creates new or opens existing file.
+    ///
+    /// See `Dir::create_file`. Creating a file requires a quota of one cluster for a possible extension of its parent
+    /// directory. The unused part is released after the operation.
+    ///
+    /// # Errors
+    ///
+    /// Errors are the same as for `Dir::create_file`, and additionally:
+    ///
+    /// * `Error::InvalidInput` will be returned if `path` points above this directory.
+    /// * `Error::NotEnoughSpace` will be returned if the file does not exist and the quota is exceeded.
+    pub fn create_file(&self, path: &str) -> Result<QuotaFile<'a, IO, TP, OCC>, Error<IO::Error>> {
+        check_path(path)?;
+        let (parent, name) = self.split_parent(path)?;
+        let reserved = self.quota.cluster_size;
+        if !self.quota.reserve(reserved) {
+            return match parent.open_file(name) {
+                Err(Error::NotFound) => Err(Error::NotEnoughSpace),
+                r => r.and_then(|file| self.with_file(file)),
+            };
+        }
+        let file = self.with_growth(&parent, reserved, |parent| Ok((parent.create_file(name)?, 0)))?;
+        self.with_file(file)
+    }
+
+    /// Removes existing file or directory and releases its space.
+    ///
+    /// See `Dir::remove`.
+    ///
+    /// # Errors
+    ///
+    /// Errors are the same as for `Dir::remove`, and `Error::InvalidInput` will be returned if `path` points above
+    /// this directory.
+    pub fn remove(&self, path: &str) -> Result<(), Error<IO::Error>> {
+        check_path(path)?;
+        let freed = match self.dir.find_entry_by_path(path)? {
+            Some(e) => e.allocated_size()?,
+            None => 0,
+        };
+        self.dir.remove(path)?;
+        self.quota.release(freed);
+        Ok(())
+    }
+
+    /// Renames or moves existing file or directory inside the directory tree.
+    ///
+    /// See `Dir::rename`. Renaming requires a quota of one cluster for a possible extension of the destination
+    /// directory. The unused part is released after the operation.
+    ///
+    /// # Errors
+    ///
+    /// Errors are the same as for `Dir::rename`, and additionally:
+    ///
+    /// * `Error::InvalidInput` will be returned if a path points above its directory or if `dst_dir` has been
+    ///   created from a different quota.
+    /// * `Error::NotEnoughSpace` will be returned if the quota is exceeded.
+    pub fn rename(&self, src_path: &str, dst_dir: &Self, dst_path: &str) -> Result<(), Error<IO::Error>> {
+        check_path(src_path)?;
+        check_path(dst_path)?;
+        if !Arc::ptr_eq(&self.quota, &dst_dir.quota) {
+            error!("Destination directory has a different quota");
+            return Err(Error::InvalidInput);
+        }
+        let (dst_parent, dst_name) = dst_dir.split_parent(dst_path)?;
+        let reserved = self.quota.cluster_size;
+        if !self.quota.reserve(reserved) {
+            return Err(Error::NotEnoughSpace);
+        }
+        self.with_growth(&dst_parent, reserved, |dst_parent| {
+            self.dir.rename(src_path, dst_parent, dst_name)?;
+            Ok(((), 0))
+        })
+    }
+
+    fn with_dir(&self, dir: Dir<'a, IO, TP, OCC>) -> Self {
+        Self {
+            dir,
+            quota: Arc::clone(&self.quota),
+        }
+    }
+
+    fn with_file(&self, file: File<'a, IO, TP, OCC>) -> Result<QuotaFile<'a, IO, TP, OCC>, Error<IO::Error>> {
+        Ok(QuotaFile {
+            allocated: file.allocated_size()?,
+            file,
+            quota: Arc::clone(&self.quota),
+        })
+    }
+
+    // Opens the parent directory of the entry `path` points to and returns it with the entry name
+    #[allow(clippy::type_complexity)]
+    fn split_parent<'p>(&self, path: &'p str) -> Result<(Dir<'a, IO, TP, OCC>, &'p str), Error<IO::Error>> {
+        let path = path.trim_end_matches('/');
+        match path.rsplit_once('/') {
+            Some((parent_path, name)) => Ok((self.dir.open_dir(parent_path)?, name)),
+            None => Ok((self.dir.clone(), path)),
+        }
+    }
+
+    // Performs an operation which can extend `dir` and settles `reserved` bytes with the size of the extension and
+    // the size allocated for the new entry returned by the operation
+    fn with_growth<T>(
+        &self,
+        dir: &Dir<'a, IO, TP, OCC>,
+        reserved: u64,
+        op: impl FnOnce(&Dir<'a, IO, TP, OCC>) -> Result<(T, u64), Error<IO::Error>>,
+    ) -> Result<T, Error<IO::Error>> {
+        let result = dir.allocated_size().and_then(|old_size| {
+            let (value, entry_size) = op(dir)?;
+            let new_size = dir.allocated_size()?;
+            Ok((value, new_size.saturating_sub(old_size) + entry_size))
+        });
+        match result {
+            Ok((value, allocated)) => {
+                self.quota.settle(reserved, allocated);
+                Ok(value)
+            }
+            Err(err) => {
+                self.quota.release(reserved);
+                Err(err)
+            }
+        }
+    }
+}
+
+impl<IO: ReadWriteSeek, TP: TimeProvider, OCC: OemCpConverter> Clone for QuotaDir<'_, IO, TP, OCC> {
+    fn clone(&self) -> Self {
+        self.with_dir(self.dir.clone())
+    }
+}
+
+fn check_path<E>(path: &str) -> Result<(), Error<E>> {
+    if points_above(path) {
+        error!("Path points above the quota directory");
+        return Err(Error::InvalidInput);
+    }
+    Ok(())
+}
+
+/// A file handle which counts clusters allocated by writes against the quota of its `QuotaDir`.
+///
+/// The file is read and written using the `Read`, `Write` and `Seek` traits. A write which would exceed the quota is
+/// shortened to the allocated clusters and the clusters the quota still allows, and fails with
+/// `Error::NotEnoughSpace` if no byte can be written. Other operations can be performed on a `File` borrowed using
+/// `as_file`.
+pub struct QuotaFile<'a, IO: ReadWriteSeek, TP, OCC> {
+    file: File<'a, IO, TP, OCC>,
+    quota: Arc<Quota>,
+    // Size of the cluster chain charged to the quota
+    allocated: u64,
+}
+
+impl<'a, IO: ReadWriteSeek, TP, OCC> QuotaFile<'a, IO, TP, OCC> {
+    /// Returns the file borrowed from this handle.
+    #[must_use]
+    pub fn as_file(&self) -> &File<'a, IO, TP, OCC> {
+        &self.file
+    }
+
+    // Charges the actual size of the cluster chain after an operation which could change it unpredictably
+    fn sync_allocated(&mut self) -> Result<(), Error<IO::Error>> {
+        let allocated = self.file.allocated_size()?;
+        self.quota.settle(self.allocated, allocated);
+        self.allocated = allocated;
+        Ok(())
+    }
+}
+
+impl<IO: ReadWriteSeek, TP: TimeProvider, OCC> QuotaFile<'_, IO, TP, OCC> {
+    /// Truncate file in current position and release freed clusters.
+    ///
+    /// See `File::truncate`.
+    ///
+    /// # Errors
+    ///
+    /// Errors are the same as for `File::truncate`.
+    pub fn truncate(&mut self) -> Result<(), Error<IO::Error>> {
+        let result = self.file.truncate();
+        self.sync_allocated()?;
+        result
+    }
+}
+
+impl<IO: ReadWriteSeek, TP, OCC> IoBase for QuotaFile<'_, IO, TP, OCC> {
+    type Error = Error<IO::Error>;
+}
+
+impl<IO: ReadWriteSeek, TP: TimeProvider, OCC> Read for QuotaFile<'_, IO, TP, OCC> {
+    fn read(&mut self, buf: &mut [u8]) -> Result<usize, Self::Error> {
+        self.file.read(buf)
+    }
+}
+
+impl<IO: ReadWriteSeek, TP: TimeProvider, OCC> Write for QuotaFile<'_, IO, TP, OCC> {
+    fn write(&mut self, buf: &[u8]) -> Result<usize, Self::Error> {
+        if buf.is_empty() {
+            return self.file.write(buf);
+        }
+        let cluster_size = self.quota.cluster_size;
+        // position includes a gap after the end of the file which is filled by the write
+        let pos = self.file.seek(SeekFrom::Current(0))?;
+        let end = pos + buf.len() as u64;
+        let needed = ((end + cluster_size - 1) / cluster_size * cluster_size).saturating_sub(self.allocated);
+        let reserved = self.quota.reserve_up_to(needed);
+        let allowed_end = self.allocated + reserved;
+        if allowed_end <= pos {
+            self.quota.release(reserved);
+            return Err(Error::NotEnoughSpace);
+        }
+        let len = usize::try_from(allowed_end - pos).map_or(buf.len(), |n| n.min(buf.len()));
+        match self.file.write(&buf[..len]) {
+            Ok(n) => {
+                // clusters of the reserved range not filled yet are allocated by following writes
+                self.allocated += reserved;
+                Ok(n)
+            }
+            Err(err) => {
+                // a part of the gap could have been allocated before the failure
+                self.allocated += reserved;
+                self.sync_allocated()?;
+                Err(err)
+            }
+        }
+    }
+
+    fn flush(&mut self) -> Result<(), Self::Error> {
+        self.file.flush()
+    }
+}
+
+impl<IO: ReadWriteSeek, TP, OCC> Seek for QuotaFile<'_, IO, TP, OCC> {
+    fn seek(&mut self, pos: SeekFrom) -> Result<u64, Self::Error> {
+        self.file.seek(pos)
+    }
+}
+
+#[cfg(feature = "std")]
+impl<IO: ReadWriteSeek, TP: TimeProvider, OCC> std::io::Read for QuotaFile<'_, IO, TP, OCC>
+where
+    std::io::Error: From<Error<IO::Error>>,
+{
+    fn read(&mut self, buf: &mut [u8]) -> std::io::Result<usize> {
+        Ok(Read::read(self, buf)?)
+    }
+}
+
+#[cfg(feature = "std")]
+impl<IO: ReadWriteSeek, TP: TimeProvider, OCC> std::io::Write for QuotaFile<'_, IO, TP, OCC>
+where
+    std::io::Error: From<Error<IO::Error>>,
+{
+    fn write(&mut self, buf: &[u8]) -> std::io::Result<usize> {
+        Ok(Write::write(self, buf)?)
+    }
+
+    fn flush(&mut self) -> std::io::Result<()> {
+        Ok(Write::flush(self)?)
+    }
+}
+
+#[cfg(feature = "std")]
+impl<IO: ReadWriteSeek, TP, OCC> std::io::Seek for QuotaFile<'_, IO, TP, OCC>
+where
+    std::io::Error: From<Error<IO::Error>>,
+{
+    fn seek(&mut self, pos: std::io::SeekFrom) -> std::io::Result<u64> {
+        Ok(Seek::seek(self, pos.into())?)
+    }
+}
@@ -0,0 +1,368 @@
+#[cfg(not(feature = "std"))]
+use alloc::sync::Arc;
+use core::mem;
+#[cfg(feature = "std")]
+use std::sync::Arc;
+
+use crate::copy::CopyOptions;
+use crate::dir::{Dir, DirIter};
+use crate::dir_entry::FileAttributes;
+use crate::error::Error;
+use crate::file::File;
+use crate::fs::{FileSystem, OemCpConverter, ReadWriteSeek};
+use crate::io::{IoBase, Read, Seek, SeekFrom, Write};
+use crate::time::{Date, DateTime, TimeProvider};
+
+/// A cloneable handle to a `FileSystem` shared by reference counting.
+///
+/// Unlike `Dir` and `File` which borrow the filesystem, `SharedDir` and `SharedFile` handles created from it keep
+/// the filesystem alive, so they have no lifetime parameter and can be stored in long-lived structures. The
+/// filesystem is unmounted when the last handle is dropped.
+///
+/// All methods of `FileSystem` taking `&self` are available through `Deref`.
+pub struct SharedFileSystem<IO: ReadWriteSeek + 'static, TP: 'static, OCC: 'static> {
+    fs: Arc<FileSystem<IO, TP, OCC>>,
+}
+
+impl<IO: ReadWriteSeek + 'static, TP: 'static, OCC: 'static> SharedFileSystem<IO, TP, OCC> {
+    /// Creates a new `SharedFileSystem` owning `fs`.
+    pub fn new(fs: FileSystem<IO, TP, OCC>) -> Self {
+        Self { fs: Arc::new(fs) }
+    }
+
+    /// Returns the root directory.
+    #[must_use]
+    pub fn root_dir(&self) -> SharedDir<IO, TP, OCC> {
+        let dir = self.fs.root_dir();
+        // SAFETY: the directory borrows the filesystem owned by the same `SharedDir` object
+        unsafe { SharedDir::new(Arc::clone(&self.fs), dir) }
+    }
+}
+
+impl<IO: ReadWriteSeek + 'static, TP: 'static, OCC: 'static> Clone for SharedFileSystem<IO, TP, OCC> {
+    fn clone(&self) -> Self {
+        Self {
+            fs: Arc::clone(&self.fs),
+        }
+    }
+}
+
+impl<IO: ReadWriteSeek + 'static, TP: 'static, OCC: 'static> core::ops::Deref for SharedFileSystem<IO, TP, OCC> {
+    type Target = FileSystem<IO, TP, OCC>;
+
+    fn deref(&self) -> &Self::Target {
+        &self.fs
+    }
+}
+
+/// A directory handle which keeps its filesystem alive.
+///
+/// Operations not provided by this type can be performed on a `Dir` borrowed using `as_dir`.
+pub struct SharedDir<IO: ReadWriteSeek + 'static, TP: 'static, OCC: 'static> {
+    // Note: the directory must be dropped before the filesystem it borrows
+    dir: Dir<'static, IO, TP, OCC>,
+    fs: Arc<FileSystem<IO, TP, OCC>>,
+}
+
+impl<IO: ReadWriteSeek + 'static, TP: 'static, OCC: 'static> SharedDir<IO, TP, OCC> {
+    // Safety: `dir` must belong to `fs`
+    unsafe fn new(fs: Arc<FileSystem<IO, TP, OCC>>, dir: Dir<'_, IO, TP, OCC>) -> Self {
+        // The filesystem is never moved out of the `Arc` which is kept alive together with the directory, so the
+        // borrow stays valid. The directory is never exposed with the `'static` lifetime.
+        let dir = mem::transmute::<Dir<'_, IO, TP, OCC>, Dir<'static, IO, TP, OCC>>(dir);
+        Self { dir, fs }
+    }
+
+    /// Returns the directory borrowed from this handle.
+    #[must_use]
+    pub fn as_dir(&self) -> &Dir<'_, IO, TP, OCC> {
+        &self.dir
+    }
+
+    /// Returns a handle of the filesystem containing this directory.
+    #[must_use]
+    pub fn file_system(&self) -> SharedFileSystem<IO, TP, OCC> {
+        SharedFileSystem {
+            fs: Arc::clone(&self.fs),
+        }
+    }
+
+    /// Creates directory entries iterator.
+    #[must_use]
+    #[allow(clippy::iter_not_returning_iterator)]
+    pub fn iter(&self) -> DirIter<'_, IO, TP, OCC> {
+        self.dir.iter()
+    }
+}
+
+impl<IO: ReadWriteSeek + 'static, TP: TimeProvider + 'static, OCC: OemCpConverter + 'static> SharedDir<IO, TP, OCC> {
+    /// Opens existing subdirectory.
+    ///
+    /// See `Dir::open_dir`.
+    ///
+    /// # Errors
+    ///
+    /// Errors are the same as for `Dir::open_dir`.
+    pub fn open_dir(&self, path: &str) -> Result<Self, Error<IO::Error>> {
+        let dir = self.dir.open_dir(path)?;
+        // SAFETY: the directory belongs to the filesystem of this directory
+        Ok(unsafe { Self::new(Arc::clone(&self.fs), dir) })
+    }
+
+    /// Creates new directory or opens existing.
+    ///
+    /// See `Dir::create_dir`.
+    ///
+    /// # Errors
+    ///
+    /// Errors are the same as for `Dir::create_dir`.
+    pub fn create_dir(&self, path: &str) -> Result<Self, Error<IO::Error>> {
+        let dir = self.dir.create_dir(path)?;
+        // SAFETY: the directory belongs to the filesystem of this directory
+        Ok(unsafe { Self::new(Arc::clone(&self.fs), dir) })
+    }
+
+    /// Opens existing file.
+    ///
+    /// See `Dir::open_file`.
+    ///
+    /// # Errors
+    ///
+    /// Errors are the same as for `Dir::open_file`.
+    pub fn open_file(&self, path: &str) -> Result<SharedFile<IO, TP, OCC>, Error<IO::Error>> {
+        let file = self.dir.open_file(path)?;
+        // SAFETY: the file belongs to the filesystem of this directory
+        Ok(unsafe { SharedFile::new(Arc::clone(&self.fs), file) })
+    }
+
+    /// Creates new or opens existing file.
+    ///
+    /// See `Dir::create_file`.
+    ///
+    /// # Errors
+    ///
+    /// Errors are the same as for `Dir::create_file`.
+    pub fn create_file(&self, path: &str) -> Result<SharedFile<IO, TP, OCC>, Error<IO::Error>> {
+        let file = self.dir.create_file(path)?;
+        // SAFETY: the file belongs to the filesystem of this directory
+        Ok(unsafe { SharedFile::new(Arc::clone(&self.fs), file) })
+    }
+
+    /// Removes existing file or directory.
+    ///
+    /// See `Dir::remove`.
+    ///
+    /// # Errors
+    ///
+    /// Errors are the same as for `Dir::remove`.
+    pub fn remove(&self, path: &str) -> Result<(), Error<IO::Error>> {
+        self.dir.remove(path)
+    }
+
+    /// Renames or moves existing file or directory.
+    ///
+    /// See `Dir::rename`.
+    ///
+    /// # Errors
+    ///
+    /// Errors are the same as for `Dir::rename`.
+    pub fn rename(&self, src_path: &str, dst_dir: &Self, dst_path: &str) -> Result<(), Error<IO::Error>> {
+        self.dir.rename(src_path, &dst_dir.dir, dst_path)
+    }
+
+    /// Renames or moves existing file or directory replacing an existing destination file.
+    ///
+    /// See `Dir::rename_replace`.
+    ///
+    /// # Errors
+    ///
+    /// Errors are the same as for `Dir::rename_replace`.
+    pub fn rename_replace(&self, src_path: &str, dst_dir: &Self, dst_path: &str) -> Result<(), Error<IO::Error>> {
+        self.dir.rename_replace(src_path, &dst_dir.dir, dst_path)
+    }
+
+    /// Copies a file inside the filesystem and returns the number of copied bytes.
+    ///
+    /// See `Dir::copy_file`.
+    ///
+    /// # Errors
+    ///
+    /// Errors are the same as for `Dir::copy_file`.
+    pub fn copy_file(
+        &self,
+        src_path: &str,
+        dst_dir: &Self,
+        dst_path: &str,
+        options: CopyOptions,
+    ) -> Result<u64, Error<IO::Error>> {
+        self.dir.copy_file(src_path, &dst_dir.dir, dst_path, options)
+    }
+}
+
+impl<IO: ReadWriteSeek + 'static, TP: TimeProvider + 'static, OCC: OemCpConverter + 'static> Clone
+    for SharedDir<IO, TP, OCC>
+{
+    fn clone(&self) -> Self {
+        Self {
+            dir: self.dir.clone(),
+            fs: Arc::clone(&self.fs),
+        }
+    }
+}
+
+/// A file handle which keeps its filesystem alive.
+///
+/// The file is read and written using the `Read`, `Write` and `Seek` traits. Other operations can be performed on
+/// a `File` borrowed using `as_file`.
+pub struct SharedFile<IO: ReadWriteSeek + 'static, TP: 'static, OCC: 'static> {
+    // Note: the file must be dropped before the filesystem it borrows
+    file: File<'static, IO, TP, OCC>,
+    fs: Arc<FileSystem<IO, TP, OCC>>,
+}
+
+impl<IO: ReadWriteSeek + 'static, TP: 'static, OCC: 'static> SharedFile<IO, TP, OCC> {
+    // Safety: `file` must belong to `fs`
+    unsafe fn new(fs: Arc<FileSystem<IO, TP, OCC>>, file: File<'_, IO, TP, OCC>) -> Self {
+        // See `SharedDir::new`
+        let file = mem::transmute::<File<'_, IO, TP, OCC>, File<'static, IO, TP, OCC>>(file);
+        Self { file, fs }
+    }
+
+    /// Returns the file borrowed from this handle.
+    #[must_use]
+    pub fn as_file(&self) -> &File<'_, IO, TP, OCC> {
+        &self.file
+    }
+
+    /// Returns a handle of the filesystem containing this file.
+    #[must_use]
+    pub fn file_system(&self) -> SharedFileSystem<IO, TP, OCC> {
+        SharedFileSystem {
+            fs: Arc::clone(&self.fs),
+        }
+    }
+
+    /// Flushes data of this file to the storage.
+    ///
+    /// See `File::sync_data`.
+    ///
+    /// # Errors
+    ///
+    /// Errors are the same as for `File::sync_data`.
+    pub fn sync_data(&mut self) -> Result<(), Error<IO::Error>> {
+        self.file.sync_data()
+    }
+
+    /// Flushes data and metadata of this file to the storage.
+    ///
+    /// See `File::sync_all`.
+    ///
+    /// # Errors
+    ///
+    /// Errors are the same as for `File::sync_all`.
+    pub fn sync_all(&mut self) -> Result<(), Error<IO::Error>> {
+        self.file.sync_all()
+    }
+
+    /// Sets date and time of creation for this file.
+    pub fn set_created(&mut self, date_time: DateTime) {
+        self.file.set_created(date_time);
+    }
+
+    /// Sets date of last access for this file.
+    pub fn set_accessed(&mut self, date: Date) {
+        self.file.set_accessed(date);
+    }
+
+    /// Sets date and time of last modification for this file.
+    pub fn set_modified(&mut self, date_time: DateTime) {
+        self.file.set_modified(date_time);
+    }
+
+    /// Sets attributes of this file.
+    pub fn set_attributes(&mut self, attrs: FileAttributes) {
+        self.file.set_attributes(attrs);
+    }
+}
+
+impl<IO: ReadWriteSeek + 'static, TP: TimeProvider + 'static, OCC: 'static> SharedFile<IO, TP, OCC> {
+    /// Truncate file in current position.
+    ///
+    /// See `File::truncate`.
+    ///
+    /// # Errors
+    ///
+    /// Errors are the same as for `File::truncate`.
+    pub fn truncate(&mut self) -> Result<(), Error<IO::Error>> {
+        self.file.truncate()
+    }
+}
+
+impl<IO: ReadWriteSeek + 'static, TP: 'static, OCC: 'static> Clone for SharedFile<IO, TP, OCC> {
+    fn clone(&self) -> Self {
+        Self {
+            file: self.file.clone(),
+            fs: Arc::clone(&self.fs),
+        }
+    }
+}
+
+impl<IO: ReadWriteSeek + 'static, TP: 'static, OCC: 'static> IoBase for SharedFile<IO, TP, OCC> {
+    type Error = Error<IO::Error>;
+}
+
+impl<IO: ReadWriteSeek + 'static, TP: TimeProvider + 'static, OCC: 'static> Read for SharedFile<IO, TP, OCC> {
+    fn read(&mut self, buf: &mut [u8]) -> Result<usize, Self::Error> {
+        self.file.read(buf)
+    }
+}
+
+impl<IO: ReadWriteSeek + 'static, TP: TimeProvider + 'static, OCC: 'static> Write for SharedFile<IO, TP, OCC> {
+    fn write(&mut self, buf: &[u8]) -> Result<usize, Self::Error> {
+        self.file.write(buf)
+    }
+
+    fn flush(&mut self) -> Result<(), Self::Error> {
+        self.file.flush()
+    }
+}
+
+impl<IO: ReadWriteSeek + 'static, TP: 'static, OCC: 'static> Seek for SharedFile<IO, TP, OCC> {
+    fn seek(&mut self, pos: SeekFrom) -> Result<u64, Self::Error> {
+        self.file.seek(pos)
+    }
+}
+
+#[cfg(feature = "std")]
+impl<IO: ReadWriteSeek + 'static, TP: TimeProvider + 'static, OCC: 'static> std::io::Read for SharedFile<IO, TP, OCC>
+where
+    std::io::Error: From<Error<IO::Error>>,
+{
+    fn read(&mut self, buf: &mut [u8]) -> std::io::Result<usize> {
+        std::io::Read::read(&mut self.file, buf)
+    }
+}
+
+#[cfg(feature = "std")]
+impl<IO: ReadWriteSeek + 'static, TP: TimeProvider + 'static, OCC: 'static> std::io::Write for SharedFile<IO, TP, OCC>
+where
+    std::io::Error: From<Error<IO::Error>>,
+{
+    fn write(&mut self, buf: &[u8]) -> std::io::Result<usize> {
+        std::io::Write::write(&mut self.file, buf)
+    }
+
+    fn flush(&mut self) -> std::io::Result<()> {
+        std::io::Write::flush(&mut self.file)
+    }
+}
+
+#[cfg(feature = "std")]
+impl<IO: ReadWriteSeek + 'static, TP: TimeProvider + 'static, OCC: 'static> std::io::Seek for SharedFile<IO, TP, OCC>
+where
+    std::io::Error: From<Error<IO::Error>>,
+{
+    fn seek(&mut self, pos: std::io::SeekFrom) -> std::io::Result<u64> {
+        std::io::Seek::seek(&mut self.file, pos)
+    }
+}
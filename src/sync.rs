@@ -0,0 +1,373 @@
+//! Locking primitives protecting the state shared by all users of a filesystem.
+//!
+//! The standard library locks are used if the `std` feature is enabled, otherwise (or if the `spinlock` feature is
+//! enabled) simple spinlocks based on `lock_api` are used, so the filesystem can be shared between cores in `no_std`
+//! environments like kernels.
+
+#[cfg(any(not(feature = "std"), feature = "spinlock"))]
+mod imp {
+    use core::sync::atomic::{AtomicBool, AtomicUsize, Ordering};
+
+    pub(crate) struct RawSpinlock {
+        locked: AtomicBool,
+    }
+
+    // SAFETY: the lock is acquired with `Acquire` ordering and released with `Release` ordering so only one owner can
+    // access the protected data at a time
+    unsafe impl lock_api::RawMutex for RawSpinlock {
+        #[allow(clippy::declare_interior_mutable_const)]
+        const INIT: Self = Self {
+            locked: AtomicBool::new(false),
+        };
+
+        type GuardMarker = lock_api::GuardSend;
+
+        fn lock(&self) {
+            while !self.try_lock() {
+                // Wait without writing to the cache line until the lock looks free
+                while self.locked.load(Ordering::Relaxed) {
+                    core::hint::spin_loop();
+                }
+            }
+        }
+
+        fn try_lock(&self) -> bool {
+            self.locked
+                .compare_exchange_weak(false, true, Ordering::Acquire, Ordering::Relaxed)
+                .is_ok()
+        }
+
+        unsafe fn unlock(&self) {
+            self.locked.store(false, Ordering::Release);
+        }
+    }
+
+    // State of `RawSpinRwLock` when it is locked exclusively, other values are numbers of readers
+    const WRITER: usize = usize::MAX;
+
+    // Readers are not blocked by waiting writers so a writer can starve if the lock is never free
+    pub(crate) struct RawSpinRwLock {
+        state: AtomicUsize,
+    }
+
+    // SAFETY: the lock is acquired with `Acquire` ordering and released with `Release` ordering. Readers are admitted
+    // only when there is no writer and a writer only when there are no readers.
+    unsafe impl lock_api::RawRwLock for RawSpinRwLock {
+        #[allow(clippy::declare_interior_mutable_const)]
+        const INIT: Self = Self {
+            state: AtomicUsize::new(0),
+        };
+
+        type GuardMarker = lock_api::GuardSend;
+
+        fn lock_shared(&self) {
+            while !self.try_lock_shared() {
+                core::hint::spin_loop();
+            }
+        }
+
+        fn try_lock_shared(&self) -> bool {
+            let state = self.state.load(Ordering::Relaxed);
+            state < WRITER - 1
+                && self
+                    .state
+                    .compare_exchange_weak(state, state + 1, Ordering::Acquire, Ordering::Relaxed)
+                    .is_ok()
+        }
+
+        unsafe fn unlock_shared(&self) {
+            self.state.fetch_sub(1, Ordering::Release);
+        }
+
+        fn lock_exclusive(&self) {
+            while !self.try_lock_exclusive() {
+                core::hint::spin_loop();
+            }
+        }
+
+        fn try_lock_exclusive(&self) -> bool {
+            self.state
+                .compare_exchange_weak(0, WRITER, Ordering::Acquire, Ordering::Relaxed)
+                .is_ok()
+        }
+
+        unsafe fn unlock_exclusive(&self) {
+            self.state.store(0, Ordering::Release);
+        }
+    }
+
+    pub(crate) type RawMutex<T> = lock_api::Mutex<RawSpinlock, T>;
+    pub(crate) type MutexGuard<'a, T> = lock_api::MutexGuard<'a, RawSpinlock, T>;
+    pub(crate) type RawRwLock<T> = lock_api::RwLock<RawSpinRwLock, T>;
+    pub(crate) type RwLockReadGuard<'a, T> = lock_api::RwLockReadGuard<'a, RawSpinRwLock, T>;
+    pub(crate) type RwLockWriteGuard<'a, T> = lock_api::RwLockWriteGuard<'a, RawSpinRwLock, T>;
+
+    pub(crate) fn lock<T>(mutex: &RawMutex<T>) -> MutexGuard<'_, T> {
+        mutex.lock()
+    }
+
+    pub(crate) fn read<T>(rw_lock: &RawRwLock<T>) -> RwLockReadGuard<'_, T> {
+        rw_lock.read()
+    }
+
+    pub(crate) fn write<T>(rw_lock: &RawRwLock<T>) -> RwLockWriteGuard<'_, T> {
+        rw_lock.write()
+    }
+
+    pub(crate) fn get_mut<T>(mutex: &mut RawMutex<T>) -> &mut T {
+        mutex.get_mut()
+    }
+
+    pub(crate) fn into_inner<T>(mutex: RawMutex<T>) -> T {
+        mutex.into_inner()
+    }
+}
+
+#[cfg(all(feature = "std", not(feature = "spinlock")))]
+mod imp {
+    use std::sync::PoisonError;
+
+    pub(crate) type RawMutex<T> = std::sync::Mutex<T>;
+    pub(crate) type MutexGuard<'a, T> = std::sync::MutexGuard<'a, T>;
+    pub(crate) type RawRwLock<T> = std::sync::RwLock<T>;
+    pub(crate) type RwLockReadGuard<'a, T> = std::sync::RwLockReadGuard<'a, T>;
+    pub(crate) type RwLockWriteGuard<'a, T> = std::sync::RwLockWriteGuard<'a, T>;
+
+    // Poisoning is ignored like in the spinlock implementation - an operation interrupted by a panic leaves the
+    // filesystem in the same state as an operation failed because of an I/O error
+    pub(crate) fn lock<T>(mutex: &RawMutex<T>) -> MutexGuard<'_, T> {
+        mutex.lock().unwrap_or_else(PoisonError::into_inner)
+    }
+
+    pub(crate) fn get_mut<T>(mutex: &mut RawMutex<T>) -> &mut T {
+        mutex.get_mut().unwrap_or_else(PoisonError::into_inner)
+    }
+
+    pub(crate) fn into_inner<T>(mutex: RawMutex<T>) -> T {
+        mutex.into_inner().unwrap_or_else(PoisonError::into_inner)
+    }
+
+    pub(crate) fn read<T>(rw_lock: &RawRwLock<T>) -> RwLockReadGuard<'_, T> {
+        rw_lock.read().unwrap_or_else(PoisonError::into_inner)
+    }
+
+    pub(crate) fn write<T>(rw_lock: &RawRwLock<T>) -> RwLockWriteGuard<'_, T> {
+        rw_lock.write().unwrap_or_else(PoisonError::into_inner)
+    }
+}
+
+pub(crate) use imp::{MutexGuard, RwLockReadGuard, RwLockWriteGuard};
+
+/// A mutual exclusion primitive used for the filesystem state.
+pub(crate) struct Mutex<T> {
+    inner: imp::RawMutex<T>,
+}
+
+impl<T> Mutex<T> {
+    pub(crate) fn new(value: T) -> Self {
+        Self {
+            inner: imp::RawMutex::new(value),
+        }
+    }
+
+    /// Acquires the lock, blocking the current thread until it is available.
+    ///
+    /// The lock is not reentrant - locking it again in the same thread deadlocks.
+    pub(crate) fn lock(&self) -> MutexGuard<'_, T> {
+        imp::lock(&self.inner)
+    }
+
+    pub(crate) fn get_mut(&mut self) -> &mut T {
+        imp::get_mut(&mut self.inner)
+    }
+
+    pub(crate) fn into_inner(self) -> T {
+        imp::into_inner(self.inner)
+    }
+}
+
+/// A reader-writer lock used for the filesystem state.
+pub(crate) struct RwLock<T> {
+    inner: imp::RawRwLock<T>,
+}
+
+impl<T> RwLock<T> {
+    pub(crate) fn new(value: T) -> Self {
+        Self {
+            inner: imp::RawRwLock::new(value),
+        }
+    }
+
+    /// Acquires the lock with shared access, blocking the current thread until there is no writer.
+    ///
+    /// The lock is not reentrant - locking it again in the same thread can deadlock.
+    pub(crate) fn read(&self) -> RwLockReadGuard<'_, T> {
+        imp::read(&self.inner)
+    }
+
+    /// Acquires the lock with exclusive access, blocking the current thread until it is available.
+    pub(crate) fn write(&self) -> RwLockWriteGuard<'_, T> {
+        imp::write(&self.inner)
+    }
+}
+
+// Number of locks in a `LockTable`
+const LOCK_TABLE_SIZE: usize = 32;
+
+// Maps a key to a lock of `LockTable`
+fn table_index(key: u64) -> usize {
+    // Fibonacci hashing spreads keys which are multiples of the entry or cluster size
+    (key.wrapping_mul(0x9E37_79B9_7F4A_7C15) >> 32) as usize % LOCK_TABLE_SIZE
+}
+
+/// A fixed set of reader-writer locks for objects identified by a numeric key.
+///
+/// Objects are mapped to locks by a hash of the key, so unrelated objects may share a lock. It limits the memory
+/// usage but means that a thread must not lock two objects from the same table unless `write_pair` is used.
+pub(crate) struct LockTable {
+    locks: [RwLock<()>; LOCK_TABLE_SIZE],
+}
+
+impl LockTable {
+    pub(crate) fn new() -> Self {
+        Self {
+            locks: core::array::from_fn(|_| RwLock::new(())),
+        }
+    }
+
+    pub(crate) fn read(&self, key: u64) -> RwLockReadGuard<'_, ()> {
+        self.locks[table_index(key)].read()
+    }
+
+    pub(crate) fn write(&self, key: u64) -> RwLockWriteGuard<'_, ()> {
+        self.locks[table_index(key)].write()
+    }
+
+    /// Locks two objects for writing. Locks are always acquired in the same order so two threads locking the same
+    /// pair cannot deadlock.
+    #[allow(clippy::type_complexity)]
+    pub(crate) fn write_pair(
+        &self,
+        key1: u64,
+        key2: u64,
+    ) -> (RwLockWriteGuard<'_, ()>, Option<RwLockWriteGuard<'_, ()>>) {
+        let (index1, index2) = (table_index(key1), table_index(key2));
+        if index1 == index2 {
+            (self.locks[index1].write(), None)
+        } else {
+            let (first, second) = (index1.min(index2), index1.max(index2));
+            let guard = self.locks[first].write();
+            (guard, Some(self.locks[second].write()))
+        }
+    }
+}
+
+// Number of directories which can be iterated at the same time with exact change detection
+const TRACKED_DIRS: usize = 16;
+
+#[derive(Copy, Clone, Default)]
+struct TrackedDir {
+    key: u64,
+    // Number of iterators of the directory, the slot is free if it is zero
+    iterators: usize,
+    generation: usize,
+}
+
+#[derive(Default)]
+struct ChangeTrackerState {
+    dirs: [TrackedDir; TRACKED_DIRS],
+    // Iterators which did not get a slot and are notified about changes of all directories
+    overflow_iterators: usize,
+    overflow_generation: usize,
+}
+
+/// Position of an iterator in `ChangeTracker` and the generation observed when the iteration started.
+#[derive(Copy, Clone)]
+pub(crate) struct TrackedIteration {
+    slot: Option<usize>,
+    generation: usize,
+}
+
+/// Detects changes of directories made while they are iterated.
+///
+/// Only directories with running iterators are tracked, so changes of other directories cost a single lookup. If
+/// more directories are iterated at the same time than there are slots, the remaining iterators are conservatively
+/// notified about changes of any directory.
+pub(crate) struct ChangeTracker {
+    state: Mutex<ChangeTrackerState>,
+}
+
+impl ChangeTracker {
+    pub(crate) fn new() -> Self {
+        Self {
+            state: Mutex::new(ChangeTrackerState::default()),
+        }
+    }
+
+    pub(crate) fn start(&self, key: u64) -> TrackedIteration {
+        let mut state = self.state.lock();
+        let slot = state
+            .dirs
+            .iter()
+            .position(|d| d.iterators > 0 && d.key == key)
+            .or_else(|| state.dirs.iter().position(|d| d.iterators == 0));
+        if let Some(index) = slot {
+            let dir = &mut state.dirs[index];
+            if dir.iterators == 0 {
+                *dir = TrackedDir {
+                    key,
+                    iterators: 0,
+                    generation: 0,
+                };
+            }
+            dir.iterators += 1;
+            TrackedIteration {
+                slot,
+                generation: dir.generation,
+            }
+        } else {
+            state.overflow_iterators += 1;
+            TrackedIteration {
+                slot,
+                generation: state.overflow_generation,
+            }
+        }
+    }
+
+    // Registers a copy of an iteration which reports the same changes
+    pub(crate) fn fork(&self, iteration: &TrackedIteration) -> TrackedIteration {
+        let mut state = self.state.lock();
+        match iteration.slot {
+            Some(index) => state.dirs[index].iterators += 1,
+            None => state.overflow_iterators += 1,
+        }
+        *iteration
+    }
+
+    pub(crate) fn stop(&self, iteration: &TrackedIteration) {
+        let mut state = self.state.lock();
+        match iteration.slot {
+            Some(index) => state.dirs[index].iterators -= 1,
+            None => state.overflow_iterators -= 1,
+        }
+    }
+
+    pub(crate) fn is_changed(&self, iteration: &TrackedIteration) -> bool {
+        let state = self.state.lock();
+        let generation = match iteration.slot {
+            Some(index) => state.dirs[index].generation,
+            None => state.overflow_generation,
+        };
+        generation != iteration.generation
+    }
+
+    pub(crate) fn record_change(&self, key: u64) {
+        let mut state = self.state.lock();
+        if let Some(dir) = state.dirs.iter_mut().find(|d| d.iterators > 0 && d.key == key) {
+            dir.generation = dir.generation.wrapping_add(1);
+        }
+        if state.overflow_iterators > 0 {
+            state.overflow_generation = state.overflow_generation.wrapping_add(1);
+        }
+    }
+}
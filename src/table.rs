@@ -27,7 +27,7 @@ type Fat32 = Fat<u32>;
 pub const RESERVED_FAT_ENTRIES: u32 = 2;
 
 #[derive(Copy, Clone, Eq, PartialEq, Debug)]
-enum FatValue {
+pub(crate) enum FatValue {
     Free,
     Data(u32),
     Bad,
@@ -72,7 +72,7 @@ trait FatTrait {
         Error<E>: From<S::Error>;
 }
 
-fn read_fat<S, E>(fat: &mut S, fat_type: FatType, cluster: u32) -> Result<FatValue, Error<E>>
+pub(crate) fn read_fat<S, E>(fat: &mut S, fat_type: FatType, cluster: u32) -> Result<FatValue, Error<E>>
 where
     S: Read + Seek,
     E: IoError,
@@ -85,7 +85,7 @@ where
     }
 }
 
-fn write_fat<S, E>(fat: &mut S, fat_type: FatType, cluster: u32, value: FatValue) -> Result<(), Error<E>>
+pub(crate) fn write_fat<S, E>(fat: &mut S, fat_type: FatType, cluster: u32, value: FatValue) -> Result<(), Error<E>>
 where
     S: Read + Write + Seek,
     E: IoError,
@@ -201,6 +201,41 @@ where
     }
 }
 
+pub(crate) fn find_first_free_cluster<S, E>(
+    fat: &mut S,
+    fat_type: FatType,
+    total_clusters: u32,
+) -> Result<Option<u32>, Error<E>>
+where
+    S: Read + Seek,
+    E: IoError,
+    Error<E>: From<S::Error>,
+{
+    find_next_free_cluster(fat, fat_type, RESERVED_FAT_ENTRIES, total_clusters)
+}
+
+pub(crate) fn find_next_free_cluster<S, E>(
+    fat: &mut S,
+    fat_type: FatType,
+    start_cluster: u32,
+    total_clusters: u32,
+) -> Result<Option<u32>, Error<E>>
+where
+    S: Read + Seek,
+    E: IoError,
+    Error<E>: From<S::Error>,
+{
+    let end_cluster = total_clusters + RESERVED_FAT_ENTRIES;
+    if start_cluster >= end_cluster {
+        return Ok(None);
+    }
+    match find_free_cluster(fat, fat_type, start_cluster, end_cluster) {
+        Ok(n) => Ok(Some(n)),
+        Err(Error::NotEnoughSpace) => Ok(None),
+        Err(e) => Err(e),
+    }
+}
+
 pub(crate) fn format_fat<S, E>(
     fat: &mut S,
     fat_type: FatType,
@@ -657,6 +692,7 @@ where
             self.cluster = match get_next_cluster(self.fat.borrow_mut(), self.fat_type, current_cluster) {
                 Ok(next_cluster) => next_cluster,
                 Err(err) => {
+                    error!("reading FAT entry of cluster {} failed: {:?}", current_cluster, err);
                     self.err = true;
                     return Some(Err(err));
                 }
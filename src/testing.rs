@@ -0,0 +1,349 @@
+//! Storage wrappers useful for testing code built on top of this crate.
+//!
+//! `FaultyStorage` simulates a device with a volatile write cache which can fail writes or lose power. It can be
+//! used to systematically check if the filesystem stays consistent when an operation is interrupted at any point.
+
+#[cfg(not(feature = "std"))]
+use alloc::rc::Rc;
+#[cfg(not(feature = "std"))]
+use alloc::vec::Vec;
+use core::cell::RefCell;
+use core::fmt;
+#[cfg(feature = "std")]
+use std::rc::Rc;
+
+use crate::error::IoError;
+use crate::io::{IoBase, Read, Seek, SeekFrom, Write};
+
+// Granularity of writes persisted in an arbitrary order on a power cut
+const SECTOR_SIZE: u64 = 512;
+
+/// An error returned by `FaultyStorage`.
+#[derive(Debug)]
+pub enum FaultError<E> {
+    /// A write failure was injected by `FaultyStorage::fail_nth_write`.
+    Injected,
+    /// The storage has lost power and does not respond until `FaultyStorage::restore_power` is called.
+    PowerCut,
+    /// The wrapped storage returned an error.
+    Io(E),
+}
+
+impl<E: IoError> IoError for FaultError<E> {
+    fn is_interrupted(&self) -> bool {
+        match self {
+            FaultError::Io(io_error) => io_error.is_interrupted(),
+            _ => false,
+        }
+    }
+
+    fn new_unexpected_eof_error() -> Self {
+        FaultError::Io(E::new_unexpected_eof_error())
+    }
+
+    fn new_write_zero_error() -> Self {
+        FaultError::Io(E::new_write_zero_error())
+    }
+}
+
+impl<E: fmt::Display> fmt::Display for FaultError<E> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            FaultError::Injected => write!(f, "Injected write failure"),
+            FaultError::PowerCut => write!(f, "Storage has lost power"),
+            FaultError::Io(io_error) => write!(f, "IO error: {}", io_error),
+        }
+    }
+}
+
+#[cfg(feature = "std")]
+impl<E: std::error::Error + 'static> std::error::Error for FaultError<E> {
+    fn source(&self) -> Option<&(dyn std::error::Error + 'static)> {
+        if let FaultError::Io(io_error) = self {
+            Some(io_error)
+        } else {
+            None
+        }
+    }
+}
+
+struct PendingWrite {
+    offset: u64,
+    data: Vec<u8>,
+}
+
+struct FaultyStorageState<T> {
+    inner: T,
+    pos: u64,
+    pending: Vec<PendingWrite>,
+    writes: u64,
+    flushes: u64,
+    fail_write: Option<u64>,
+    power_cut_after: Option<u64>,
+    reorder_seed: Option<u64>,
+    powered: bool,
+}
+
+impl<T: Read + Write + Seek> FaultyStorageState<T> {
+    fn read(&mut self, buf: &mut [u8]) -> Result<usize, FaultError<T::Error>> {
+        if !self.powered {
+            return Err(FaultError::PowerCut);
+        }
+        self.inner.seek(SeekFrom::Start(self.pos)).map_err(FaultError::Io)?;
+        let n = self.inner.read(buf).map_err(FaultError::Io)?;
+        // Data which is still in the write cache takes precedence over the storage content
+        let start = self.pos;
+        let end = start + n as u64;
+        for write in &self.pending {
+            let write_end = write.offset + write.data.len() as u64;
+            if write.offset < end && write_end > start {
+                let from = write.offset.max(start);
+                let to = write_end.min(end);
+                buf[(from - start) as usize..(to - start) as usize]
+                    .copy_from_slice(&write.data[(from - write.offset) as usize..(to - write.offset) as usize]);
+            }
+        }
+        self.pos = end;
+        Ok(n)
+    }
+
+    fn write(&mut self, buf: &[u8]) -> Result<usize, FaultError<T::Error>> {
+        if !self.powered {
+            return Err(FaultError::PowerCut);
+        }
+        self.writes += 1;
+        if self.fail_write == Some(self.writes) {
+            return Err(FaultError::Injected);
+        }
+        self.pending.push(PendingWrite {
+            offset: self.pos,
+            data: buf.to_vec(),
+        });
+        self.pos += buf.len() as u64;
+        if self.power_cut_after == Some(self.writes) {
+            self.power_cut();
+        }
+        Ok(buf.len())
+    }
+
+    fn flush(&mut self) -> Result<(), FaultError<T::Error>> {
+        if !self.powered {
+            return Err(FaultError::PowerCut);
+        }
+        self.flushes += 1;
+        for write in core::mem::take(&mut self.pending) {
+            self.persist(write.offset, &write.data)?;
+        }
+        self.inner.flush().map_err(FaultError::Io)
+    }
+
+    fn seek(&mut self, pos: SeekFrom) -> Result<u64, FaultError<T::Error>> {
+        if !self.powered {
+            return Err(FaultError::PowerCut);
+        }
+        let new_pos = match pos {
+            SeekFrom::Start(n) => Some(n),
+            SeekFrom::Current(n) if n >= 0 => self.pos.checked_add(n.unsigned_abs()),
+            SeekFrom::Current(n) => self.pos.checked_sub(n.unsigned_abs()),
+            SeekFrom::End(_) => Some(self.inner.seek(pos).map_err(FaultError::Io)?),
+        };
+        self.pos = new_pos.ok_or_else(|| FaultError::Io(T::Error::new_unexpected_eof_error()))?;
+        Ok(self.pos)
+    }
+
+    fn persist(&mut self, offset: u64, data: &[u8]) -> Result<(), FaultError<T::Error>> {
+        self.inner.seek(SeekFrom::Start(offset)).map_err(FaultError::Io)?;
+        self.inner.write_all(data).map_err(FaultError::Io)
+    }
+
+    fn power_cut(&mut self) {
+        self.powered = false;
+        let pending = core::mem::take(&mut self.pending);
+        let Some(seed) = self.reorder_seed else {
+            return;
+        };
+        // Split cached writes into sectors and persist a random subset of them in a random order
+        let mut sectors = Vec::new();
+        for write in &pending {
+            let mut offset = write.offset;
+            let end = write.offset + write.data.len() as u64;
+            while offset < end {
+                let sector_end = ((offset / SECTOR_SIZE + 1) * SECTOR_SIZE).min(end);
+                let data_range = (offset - write.offset) as usize..(sector_end - write.offset) as usize;
+                sectors.push((offset, &write.data[data_range]));
+                offset = sector_end;
+            }
+        }
+        let mut rng = XorShift(seed | 1);
+        for i in (1..sectors.len()).rev() {
+            sectors.swap(i, (rng.next_u64() % (i as u64 + 1)) as usize);
+        }
+        for (offset, data) in sectors {
+            if rng.next_u64() % 2 == 0 && self.persist(offset, data).is_err() {
+                // Write errors are ignored - the power is already lost
+                warn!("failed to persist sector at {} on power cut", offset);
+            }
+        }
+        let _ = self.inner.flush();
+    }
+}
+
+struct XorShift(u64);
+
+impl XorShift {
+    fn next_u64(&mut self) -> u64 {
+        self.0 ^= self.0 << 13;
+        self.0 ^= self.0 >> 7;
+        self.0 ^= self.0 << 17;
+        self.0
+    }
+}
+
+/// A storage wrapper injecting faults for crash-consistency testing.
+///
+/// Writes are kept in a simulated volatile cache until `flush` is called. A power cut drops the cache content
+/// (or persists a random part of it, see `reorder_on_power_cut`) and makes all following operations fail with
+/// `FaultError::PowerCut`.
+///
+/// Clones share the same storage and faults configuration, so a clone can be kept to control the faults and to
+/// remount the storage after the `FileSystem` owning the other clone has been dropped. Every clone has its own
+/// stream position.
+pub struct FaultyStorage<T> {
+    state: Rc<RefCell<FaultyStorageState<T>>>,
+    pos: u64,
+}
+
+impl<T> Clone for FaultyStorage<T> {
+    fn clone(&self) -> Self {
+        Self {
+            state: Rc::clone(&self.state),
+            pos: self.pos,
+        }
+    }
+}
+
+impl<T: Read + Write + Seek> FaultyStorage<T> {
+    /// Creates a new `FaultyStorage` wrapping `inner`. No faults are injected until configured.
+    pub fn new(inner: T) -> Self {
+        Self {
+            state: Rc::new(RefCell::new(FaultyStorageState {
+                inner,
+                pos: 0,
+                pending: Vec::new(),
+                writes: 0,
+                flushes: 0,
+                fail_write: None,
+                power_cut_after: None,
+                reorder_seed: None,
+                powered: true,
+            })),
+            pos: 0,
+        }
+    }
+
+    /// Makes the `n`-th write counting from now (starting from 1) fail with `FaultError::Injected`.
+    ///
+    /// The failed write does not change the storage content.
+    pub fn fail_nth_write(&self, n: u64) {
+        let mut state = self.state.borrow_mut();
+        state.fail_write = Some(state.writes + n);
+    }
+
+    /// Cuts the power right after the `n`-th write counting from now (starting from 1) is cached.
+    pub fn power_cut_after_writes(&self, n: u64) {
+        let mut state = self.state.borrow_mut();
+        state.power_cut_after = Some(state.writes + n);
+    }
+
+    /// Cuts the power immediately.
+    pub fn power_cut(&self) {
+        self.state.borrow_mut().power_cut();
+    }
+
+    /// Sets what happens with writes which were not flushed when the power is cut.
+    ///
+    /// If `seed` is `None` all of them are lost (the default). Otherwise the writes are split into 512-byte
+    /// sectors and a pseudo-random subset of the sectors chosen using `seed` is persisted in a pseudo-random order.
+    pub fn reorder_on_power_cut(&self, seed: Option<u64>) {
+        self.state.borrow_mut().reorder_seed = seed;
+    }
+
+    /// Restores the power after a power cut.
+    ///
+    /// Pending fault triggers are cleared.
+    pub fn restore_power(&self) {
+        let mut state = self.state.borrow_mut();
+        state.powered = true;
+        state.fail_write = None;
+        state.power_cut_after = None;
+    }
+
+    /// Checks if the storage has power.
+    #[must_use]
+    pub fn is_powered(&self) -> bool {
+        self.state.borrow().powered
+    }
+
+    /// Returns the number of writes issued to this storage including failed ones.
+    #[must_use]
+    pub fn writes(&self) -> u64 {
+        self.state.borrow().writes
+    }
+
+    /// Returns the number of successful flushes of this storage.
+    #[must_use]
+    pub fn flushes(&self) -> u64 {
+        self.state.borrow().flushes
+    }
+
+    // Runs `f` using the stream position of this clone
+    fn with_state<R>(&mut self, f: impl FnOnce(&mut FaultyStorageState<T>) -> R) -> R {
+        let mut state = self.state.borrow_mut();
+        state.pos = self.pos;
+        let result = f(&mut state);
+        self.pos = state.pos;
+        result
+    }
+
+    /// Returns the wrapped storage. Writes which were not flushed are lost.
+    ///
+    /// # Errors
+    ///
+    /// `self` is returned back if other clones of this storage exist.
+    pub fn try_into_inner(self) -> Result<T, Self> {
+        match Rc::try_unwrap(self.state) {
+            Ok(state) => Ok(state.into_inner().inner),
+            Err(state) => Err(Self { state, pos: self.pos }),
+        }
+    }
+}
+
+impl<T: IoBase> IoBase for FaultyStorage<T> {
+    type Error = FaultError<T::Error>;
+
+    fn buffer_alignment(&self) -> usize {
+        self.state.borrow().inner.buffer_alignment()
+    }
+}
+
+impl<T: Read + Write + Seek> Read for FaultyStorage<T> {
+    fn read(&mut self, buf: &mut [u8]) -> Result<usize, Self::Error> {
+        self.with_state(|state| state.read(buf))
+    }
+}
+
+impl<T: Read + Write + Seek> Write for FaultyStorage<T> {
+    fn write(&mut self, buf: &[u8]) -> Result<usize, Self::Error> {
+        self.with_state(|state| state.write(buf))
+    }
+
+    fn flush(&mut self) -> Result<(), Self::Error> {
+        self.with_state(FaultyStorageState::flush)
+    }
+}
+
+impl<T: Read + Write + Seek> Seek for FaultyStorage<T> {
+    fn seek(&mut self, pos: SeekFrom) -> Result<u64, Self::Error> {
+        self.with_state(|state| state.seek(pos))
+    }
+}
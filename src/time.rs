@@ -1,4 +1,4 @@
-#[cfg(feature = "chrono")]
+#[cfg(any(feature = "chrono", feature = "time"))]
 use core::convert::TryFrom;
 use core::fmt::Debug;
 
@@ -16,6 +16,7 @@ const MAX_DAY: u16 = 31;
 ///
 /// Used by `DirEntry` time-related methods.
 #[derive(Copy, Clone, Eq, PartialEq, Debug)]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
 #[non_exhaustive]
 pub struct Date {
     /// Full year - [1980, 2107]
@@ -58,6 +59,7 @@ impl Date {
 ///
 /// Used by `DirEntry` time-related methods.
 #[derive(Copy, Clone, Eq, PartialEq, Debug)]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
 #[non_exhaustive]
 pub struct Time {
     /// Hours after midnight - [0, 23]
@@ -67,6 +69,9 @@ pub struct Time {
     /// Seconds after the minute - [0, 59]
     pub sec: u16,
     /// Milliseconds after the second - [0, 999]
+    ///
+    /// FAT stores creation time with 10ms resolution (in the creation time tenths field). Other time fields have 2s
+    /// resolution and do not store milliseconds.
     pub millis: u16,
 }
 
@@ -91,6 +96,8 @@ impl Time {
     }
 
     pub(crate) fn decode(dos_time: u16, dos_time_hi_res: u8) -> Self {
+        // hi-res field counts 10ms units in the range [0, 199] - ignore invalid values so seconds stay in range
+        let dos_time_hi_res = if dos_time_hi_res < 200 { dos_time_hi_res } else { 0 };
         let hour = dos_time >> 11;
         let min = (dos_time >> 5) & 0x3F;
         let sec = (dos_time & 0x1F) * 2 + u16::from(dos_time_hi_res / 100);
@@ -111,6 +118,7 @@ impl Time {
 ///
 /// Used by `DirEntry` time-related methods.
 #[derive(Copy, Clone, Eq, PartialEq, Debug)]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
 #[non_exhaustive]
 pub struct DateTime {
     /// A date part
@@ -132,6 +140,94 @@ impl DateTime {
     }
 }
 
+// Conversions of host times. DOS date-times are local times if the `chrono` feature is enabled, otherwise they are
+// treated as UTC.
+#[cfg(feature = "std")]
+impl DateTime {
+    // Converts a host time clamped to the supported range, missing times are replaced by the minimal date-time
+    pub(crate) fn from_host_time(time: Option<std::time::SystemTime>) -> Self {
+        let min = Self::new(Date::new(MIN_YEAR, MIN_MONTH, MIN_DAY), Time::new(0, 0, 0, 0));
+        let Some(time) = time else {
+            return min;
+        };
+        let (secs, millis) = match time.duration_since(std::time::UNIX_EPOCH) {
+            Ok(duration) => (
+                i64::try_from(duration.as_secs()).unwrap_or(i64::MAX),
+                duration.subsec_millis(),
+            ),
+            Err(_) => return min,
+        };
+        #[cfg(feature = "chrono")]
+        let secs = {
+            use chrono::Offset;
+            let local = chrono::DateTime::<chrono::Local>::from(time);
+            secs.saturating_add(i64::from(local.offset().fix().local_minus_utc()))
+        };
+        // See http://howardhinnant.github.io/date_algorithms.html
+        let days = secs.div_euclid(86400) + 719_468;
+        let secs_of_day = secs.rem_euclid(86400);
+        let era = days.div_euclid(146_097);
+        let day_of_era = days.rem_euclid(146_097);
+        let year_of_era = (day_of_era - day_of_era / 1460 + day_of_era / 36524 - day_of_era / 146_096) / 365;
+        let day_of_year = day_of_era - (365 * year_of_era + year_of_era / 4 - year_of_era / 100);
+        let shifted_month = (5 * day_of_year + 2) / 153;
+        let day = day_of_year - (153 * shifted_month + 2) / 5 + 1;
+        let month = if shifted_month < 10 {
+            shifted_month + 3
+        } else {
+            shifted_month - 9
+        };
+        let year = year_of_era + era * 400 + i64::from(month <= 2);
+        if year < i64::from(MIN_YEAR) {
+            return min;
+        }
+        if year > i64::from(MAX_YEAR) {
+            return Self::new(Date::new(MAX_YEAR, MAX_MONTH, MAX_DAY), Time::new(23, 59, 59, 999));
+        }
+        // Values are in range so casts are lossless
+        #[allow(clippy::cast_possible_truncation, clippy::cast_sign_loss)]
+        Self::new(
+            Date::new(year as u16, month as u16, day as u16),
+            Time::new(
+                (secs_of_day / 3600) as u16,
+                (secs_of_day / 60 % 60) as u16,
+                (secs_of_day % 60) as u16,
+                millis as u16,
+            ),
+        )
+    }
+
+    // Returns the number of seconds since the Unix epoch
+    pub(crate) fn to_unix_time(self) -> i64 {
+        let (year, month, day) = (
+            i64::from(self.date.year),
+            i64::from(self.date.month),
+            i64::from(self.date.day),
+        );
+        // See http://howardhinnant.github.io/date_algorithms.html
+        let year = if month <= 2 { year - 1 } else { year };
+        let era = year.div_euclid(400);
+        let year_of_era = year.rem_euclid(400);
+        let shifted_month = if month > 2 { month - 3 } else { month + 9 };
+        let day_of_year = (153 * shifted_month + 2) / 5 + day - 1;
+        let day_of_era = year_of_era * 365 + year_of_era / 4 - year_of_era / 100 + day_of_year;
+        let days = era * 146_097 + day_of_era - 719_468;
+        let secs =
+            days * 86400 + i64::from(self.time.hour) * 3600 + i64::from(self.time.min) * 60 + i64::from(self.time.sec);
+        #[cfg(feature = "chrono")]
+        {
+            use chrono::TimeZone;
+            if let chrono::LocalResult::Single(local) | chrono::LocalResult::Ambiguous(local, _) =
+                chrono::Local.from_local_datetime(&chrono::NaiveDateTime::from(self))
+            {
+                use chrono::Offset;
+                return secs - i64::from(local.offset().fix().local_minus_utc());
+            }
+        }
+        secs
+    }
+}
+
 #[cfg(feature = "chrono")]
 impl From<Date> for chrono::NaiveDate {
     fn from(date: Date) -> Self {
@@ -184,6 +280,82 @@ impl From<chrono::NaiveDateTime> for DateTime {
     }
 }
 
+/// An error returned when a date or time cannot be converted because it is out of the range supported by
+/// the destination type.
+#[cfg(feature = "time")]
+#[derive(Copy, Clone, Eq, PartialEq, Debug)]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+pub struct OutOfRangeError;
+
+#[cfg(feature = "time")]
+impl core::fmt::Display for OutOfRangeError {
+    fn fmt(&self, f: &mut core::fmt::Formatter<'_>) -> core::fmt::Result {
+        write!(f, "Date or time out of range")
+    }
+}
+
+#[cfg(all(feature = "time", feature = "std"))]
+impl std::error::Error for OutOfRangeError {}
+
+#[cfg(feature = "time")]
+impl TryFrom<Date> for time::Date {
+    type Error = OutOfRangeError;
+
+    fn try_from(date: Date) -> Result<Self, Self::Error> {
+        let month = u8::try_from(date.month)
+            .ok()
+            .and_then(|m| time::Month::try_from(m).ok())
+            .ok_or(OutOfRangeError)?;
+        let day = u8::try_from(date.day).map_err(|_| OutOfRangeError)?;
+        time::Date::from_calendar_date(i32::from(date.year), month, day).map_err(|_| OutOfRangeError)
+    }
+}
+
+#[cfg(feature = "time")]
+impl TryFrom<DateTime> for time::PrimitiveDateTime {
+    type Error = OutOfRangeError;
+
+    fn try_from(date_time: DateTime) -> Result<Self, Self::Error> {
+        let date = time::Date::try_from(date_time.date)?;
+        let Time { hour, min, sec, millis } = date_time.time;
+        let time = time::Time::from_hms_milli(hour as u8, min as u8, sec as u8, millis).map_err(|_| OutOfRangeError)?;
+        Ok(time::PrimitiveDateTime::new(date, time))
+    }
+}
+
+#[cfg(feature = "time")]
+impl TryFrom<time::Date> for Date {
+    type Error = OutOfRangeError;
+
+    fn try_from(date: time::Date) -> Result<Self, Self::Error> {
+        let year = u16::try_from(date.year()).map_err(|_| OutOfRangeError)?;
+        if !(MIN_YEAR..=MAX_YEAR).contains(&year) {
+            return Err(OutOfRangeError);
+        }
+        Ok(Self {
+            year,
+            month: u16::from(u8::from(date.month())),
+            day: u16::from(date.day()),
+        })
+    }
+}
+
+#[cfg(feature = "time")]
+impl TryFrom<time::PrimitiveDateTime> for DateTime {
+    type Error = OutOfRangeError;
+
+    fn try_from(date_time: time::PrimitiveDateTime) -> Result<Self, Self::Error> {
+        let date = Date::try_from(date_time.date())?;
+        let time = Time {
+            hour: u16::from(date_time.hour()),
+            min: u16::from(date_time.minute()),
+            sec: u16::from(date_time.second()),
+            millis: date_time.millisecond(),
+        };
+        Ok(Self::new(date, time))
+    }
+}
+
 /// A current time and date provider.
 ///
 /// Provides a custom implementation for a time resolution used when updating directory entry time fields.
@@ -237,6 +409,38 @@ impl TimeProvider for ChronoTimeProvider {
     }
 }
 
+/// `TimeProvider` implementation that returns current time retrieved from `time` crate.
+///
+/// Local time is used if the local offset can be determined. Otherwise UTC time is used.
+/// If the current date is out of the range supported by FAT DOS minimal date-time is returned.
+#[cfg(all(feature = "time", feature = "std"))]
+#[derive(Debug, Clone, Copy, Default)]
+pub struct TimeCrateTimeProvider {
+    _dummy: (),
+}
+
+#[cfg(all(feature = "time", feature = "std"))]
+impl TimeCrateTimeProvider {
+    /// Creates a new `TimeCrateTimeProvider` instance.
+    #[must_use]
+    pub fn new() -> Self {
+        Self { _dummy: () }
+    }
+}
+
+#[cfg(all(feature = "time", feature = "std"))]
+impl TimeProvider for TimeCrateTimeProvider {
+    fn get_current_date(&self) -> Date {
+        self.get_current_date_time().date
+    }
+
+    fn get_current_date_time(&self) -> DateTime {
+        let now = time::OffsetDateTime::now_local().unwrap_or_else(|_| time::OffsetDateTime::now_utc());
+        DateTime::try_from(time::PrimitiveDateTime::new(now.date(), now.time()))
+            .unwrap_or_else(|_| DateTime::new(Date::new(MIN_YEAR, MIN_MONTH, MIN_DAY), Time::new(0, 0, 0, 0)))
+    }
+}
+
 /// `TimeProvider` implementation that always returns DOS minimal date-time (1980-01-01 00:00:00).
 #[derive(Debug, Clone, Copy, Default)]
 pub struct NullTimeProvider {
@@ -270,7 +474,9 @@ pub type DefaultTimeProvider = NullTimeProvider;
 
 #[cfg(test)]
 mod tests {
-    use super::{Date, DateTime, Time};
+    #[cfg(any(feature = "chrono", feature = "time"))]
+    use super::DateTime;
+    use super::{Date, Time};
 
     #[test]
     fn date_new_no_panic_1980() {
@@ -278,7 +484,7 @@ mod tests {
     }
 
     #[test]
-    #[should_panic]
+    #[should_panic(expected = "year out of range")]
     fn date_new_panic_year_1979() {
         let _ = Date::new(1979, 12, 31);
     }
@@ -289,7 +495,7 @@ mod tests {
     }
 
     #[test]
-    #[should_panic]
+    #[should_panic(expected = "year out of range")]
     fn date_new_panic_year_2108() {
         let _ = Date::new(2108, 1, 1);
     }
@@ -318,6 +524,32 @@ mod tests {
         assert_eq!(t3, Time::decode(x3, y3));
     }
 
+    #[test]
+    fn time_decode_hi_res_round_trip() {
+        for hi_res in 0..200 {
+            let t = Time::decode(30830, hi_res);
+            assert_eq!(t.encode(), (30830, hi_res));
+        }
+        // invalid hi-res values are ignored
+        assert_eq!(Time::decode(30830, 200), Time::new(15, 3, 28, 0));
+        assert_eq!(Time::decode(30830, 255), Time::new(15, 3, 28, 0));
+    }
+
+    #[cfg(feature = "time")]
+    #[test]
+    fn date_time_time_crate_round_trip() {
+        use core::convert::TryFrom;
+        let date_time = DateTime::new(Date::new(2016, 12, 31), Time::new(23, 59, 59, 990));
+        let primitive = time::PrimitiveDateTime::try_from(date_time).unwrap();
+        assert_eq!(primitive.millisecond(), 990);
+        assert_eq!(DateTime::try_from(primitive).unwrap(), date_time);
+        // DOS date decoded from zeroed fields is invalid
+        assert!(time::Date::try_from(Date::decode(0)).is_err());
+        let out_of_range = time::Date::from_calendar_date(1979, time::Month::December, 31).unwrap();
+        assert!(Date::try_from(out_of_range).is_err());
+    }
+
+    #[cfg(feature = "chrono")]
     #[test]
     fn date_time_from_chrono_leap_second() {
         let chrono_date_time = chrono::NaiveDate::from_ymd_opt(2016, 12, 31)
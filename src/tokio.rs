@@ -0,0 +1,177 @@
+//! Adapters for using this crate in `tokio` based applications.
+//!
+//! The filesystem API is synchronous, so the adapters perform blocking I/O when polled. Run them on a thread which
+//! may block (e.g. inside `tokio::task::spawn_blocking` or `tokio::task::block_in_place`) if the storage is slow.
+
+use core::pin::Pin;
+use core::task::{Context, Poll};
+use std::io;
+
+use ::tokio::io::{AsyncRead, AsyncSeek, AsyncWrite, ReadBuf};
+
+use crate::file::File;
+use crate::fs::ReadWriteSeek;
+use crate::io::{IoBase, Read, Seek, SeekFrom, Write};
+use crate::time::TimeProvider;
+
+/// A storage wrapper around `tokio::fs::File`.
+///
+/// It converts the file into a standard library file which is accessed synchronously.
+pub struct TokioStorage {
+    inner: std::fs::File,
+}
+
+impl TokioStorage {
+    /// Creates a new `TokioStorage` instance from the provided `tokio` file.
+    ///
+    /// Waits for all pending operations of the file to complete.
+    pub async fn new(file: ::tokio::fs::File) -> Self {
+        Self {
+            inner: file.into_std().await,
+        }
+    }
+
+    /// Returns the wrapped storage as a `tokio` file.
+    #[must_use]
+    pub fn into_inner(self) -> ::tokio::fs::File {
+        ::tokio::fs::File::from_std(self.inner)
+    }
+}
+
+impl IoBase for TokioStorage {
+    type Error = io::Error;
+}
+
+impl Read for TokioStorage {
+    fn read(&mut self, buf: &mut [u8]) -> Result<usize, Self::Error> {
+        io::Read::read(&mut self.inner, buf)
+    }
+}
+
+impl Write for TokioStorage {
+    fn write(&mut self, buf: &[u8]) -> Result<usize, Self::Error> {
+        io::Write::write(&mut self.inner, buf)
+    }
+
+    fn flush(&mut self) -> Result<(), Self::Error> {
+        io::Write::flush(&mut self.inner)
+    }
+}
+
+impl Seek for TokioStorage {
+    fn seek(&mut self, pos: SeekFrom) -> Result<u64, Self::Error> {
+        io::Seek::seek(&mut self.inner, pos.into())
+    }
+}
+
+/// An adapter implementing `tokio` I/O traits for a `File`.
+///
+/// Operations are performed synchronously when the adapter is polled.
+pub struct TokioFile<'a, IO: ReadWriteSeek, TP, OCC> {
+    inner: File<'a, IO, TP, OCC>,
+    seek_result: Option<io::Result<u64>>,
+}
+
+impl<'a, IO: ReadWriteSeek, TP, OCC> TokioFile<'a, IO, TP, OCC> {
+    /// Creates a new `TokioFile` instance that wraps the provided `file`.
+    #[must_use]
+    pub fn new(file: File<'a, IO, TP, OCC>) -> Self {
+        Self {
+            inner: file,
+            seek_result: None,
+        }
+    }
+
+    /// Returns the wrapped file.
+    #[must_use]
+    pub fn into_inner(self) -> File<'a, IO, TP, OCC> {
+        self.inner
+    }
+}
+
+impl<IO: ReadWriteSeek, TP: TimeProvider, OCC> AsyncRead for TokioFile<'_, IO, TP, OCC>
+where
+    io::Error: From<crate::error::Error<IO::Error>>,
+{
+    fn poll_read(self: Pin<&mut Self>, _cx: &mut Context<'_>, buf: &mut ReadBuf<'_>) -> Poll<io::Result<()>> {
+        let this = self.get_mut();
+        let n = io::Read::read(&mut this.inner, buf.initialize_unfilled())?;
+        buf.advance(n);
+        Poll::Ready(Ok(()))
+    }
+}
+
+impl<IO: ReadWriteSeek, TP: TimeProvider, OCC> AsyncWrite for TokioFile<'_, IO, TP, OCC>
+where
+    io::Error: From<crate::error::Error<IO::Error>>,
+{
+    fn poll_write(self: Pin<&mut Self>, _cx: &mut Context<'_>, buf: &[u8]) -> Poll<io::Result<usize>> {
+        Poll::Ready(io::Write::write(&mut self.get_mut().inner, buf))
+    }
+
+    fn poll_flush(self: Pin<&mut Self>, _cx: &mut Context<'_>) -> Poll<io::Result<()>> {
+        Poll::Ready(io::Write::flush(&mut self.get_mut().inner))
+    }
+
+    fn poll_shutdown(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<io::Result<()>> {
+        self.poll_flush(cx)
+    }
+}
+
+impl<IO: ReadWriteSeek, TP: TimeProvider, OCC> AsyncSeek for TokioFile<'_, IO, TP, OCC>
+where
+    io::Error: From<crate::error::Error<IO::Error>>,
+{
+    fn start_seek(self: Pin<&mut Self>, position: io::SeekFrom) -> io::Result<()> {
+        let this = self.get_mut();
+        this.seek_result = Some(io::Seek::seek(&mut this.inner, position));
+        Ok(())
+    }
+
+    fn poll_complete(self: Pin<&mut Self>, _cx: &mut Context<'_>) -> Poll<io::Result<u64>> {
+        let this = self.get_mut();
+        match this.seek_result.take() {
+            Some(result) => Poll::Ready(result),
+            None => Poll::Ready(io::Seek::stream_position(&mut this.inner)),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use ::tokio::io::{AsyncReadExt, AsyncSeekExt, AsyncWriteExt};
+
+    use super::*;
+    use crate::{FileSystem, FsOptions};
+
+    #[::tokio::test]
+    async fn test_tokio_adapters() {
+        std::fs::create_dir_all("tmp").unwrap();
+        let path = "tmp/tokio-fat16.img";
+        std::fs::copy("resources/fat16.img", path).unwrap();
+        let file = ::tokio::fs::OpenOptions::new()
+            .read(true)
+            .write(true)
+            .open(path)
+            .await
+            .unwrap();
+        let fs = FileSystem::new(TokioStorage::new(file).await, FsOptions::new()).unwrap();
+
+        let mut file = TokioFile::new(fs.root_dir().create_file("tokio.txt").unwrap());
+        file.write_all(b"Hello tokio!").await.unwrap();
+        file.flush().await.unwrap();
+        assert_eq!(file.seek(io::SeekFrom::Start(6)).await.unwrap(), 6);
+        let mut content = String::new();
+        file.read_to_string(&mut content).await.unwrap();
+        assert_eq!(content, "tokio!");
+        drop(file);
+
+        let mut file = TokioFile::new(fs.root_dir().open_file("short.txt").unwrap());
+        let mut content = String::new();
+        file.read_to_string(&mut content).await.unwrap();
+        assert_eq!(content, "Rust is cool!\n");
+        drop(file);
+        fs.unmount().unwrap();
+        std::fs::remove_file(path).unwrap();
+    }
+}
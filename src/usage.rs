@@ -0,0 +1,121 @@
+//! Disk usage of directory trees.
+//!
+//! This module implements `Dir::disk_usage` which sums sizes of files and directories in a subtree similarly to
+//! the `du` tool.
+
+#[cfg(not(feature = "std"))]
+use alloc::string::String;
+#[cfg(not(feature = "std"))]
+use alloc::vec::Vec;
+
+use crate::dir::Dir;
+use crate::error::Error;
+use crate::fs::{OemCpConverter, ReadWriteSeek};
+use crate::time::TimeProvider;
+
+/// Disk usage of a directory tree returned by `Dir::disk_usage` and `Dir::disk_usage_breakdown`.
+#[derive(Clone, Debug, Default, PartialEq, Eq)]
+pub struct DiskUsage {
+    file_size: u64,
+    allocated_size: u64,
+    files: u64,
+    dirs: u64,
+    children: Vec<(String, DiskUsage)>,
+}
+
+impl DiskUsage {
+    /// Sum of sizes of all files in the tree.
+    #[must_use]
+    pub fn file_size(&self) -> u64 {
+        self.file_size
+    }
+
+    /// Number of bytes occupied on disk by all files and directories in the tree, including the directory itself.
+    ///
+    /// It is a multiple of the cluster size. The root directory of FAT12/FAT16 volumes is stored outside of the data
+    /// area and is not included.
+    #[must_use]
+    pub fn allocated_size(&self) -> u64 {
+        self.allocated_size
+    }
+
+    /// Number of files in the tree.
+    #[must_use]
+    pub fn files(&self) -> u64 {
+        self.files
+    }
+
+    /// Number of directories in the tree, excluding the directory itself.
+    #[must_use]
+    pub fn dirs(&self) -> u64 {
+        self.dirs
+    }
+
+    /// Names and usage of subdirectories of the directory in directory order.
+    ///
+    /// It is empty unless the usage has been returned by `Dir::disk_usage_breakdown` with a non-zero depth.
+    #[must_use]
+    pub fn children(&self) -> &[(String, DiskUsage)] {
+        &self.children
+    }
+
+    fn add(&mut self, other: &DiskUsage) {
+        self.file_size += other.file_size;
+        self.allocated_size += other.allocated_size;
+        self.files += other.files;
+        self.dirs += other.dirs + 1;
+    }
+}
+
+impl<IO: ReadWriteSeek, TP: TimeProvider, OCC: OemCpConverter> Dir<'_, IO, TP, OCC> {
+    /// Returns the disk usage of this directory and all its subdirectories.
+    ///
+    /// The tree is walked once. Sizes of files come from their directory entries and allocated sizes from the lengths
+    /// of their cluster chains, so no file is opened.
+    ///
+    /// # Errors
+    ///
+    /// Errors that can be returned:
+    ///
+    /// * `Error::DirectoryModified` will be returned if a directory in the tree is modified during the walk.
+    /// * `Error::Io` will be returned if the underlying storage object returned an I/O error.
+    pub fn disk_usage(&self) -> Result<DiskUsage, Error<IO::Error>> {
+        self.disk_usage_breakdown(0)
+    }
+
+    /// Returns the disk usage of this directory with a breakdown into subdirectories up to `depth` levels deep.
+    ///
+    /// Works like `disk_usage`, but `DiskUsage::children` of the returned usage contains usage of subdirectories of
+    /// this directory if `depth` is not zero, their children usage of their subdirectories if `depth` is greater than
+    /// one and so on.
+    ///
+    /// # Errors
+    ///
+    /// Errors are the same as for `Dir::disk_usage`.
+    pub fn disk_usage_breakdown(&self, depth: u32) -> Result<DiskUsage, Error<IO::Error>> {
+        trace!("Dir::disk_usage_breakdown {}", depth);
+        let mut usage = DiskUsage {
+            allocated_size: self.allocated_size()?,
+            ..DiskUsage::default()
+        };
+        for r in self.iter() {
+            let e = r?;
+            if !e.is_dir() {
+                usage.files += 1;
+                usage.file_size += e.len();
+                usage.allocated_size += e.allocated_size()?;
+                continue;
+            }
+            let name = e.short_file_name_as_bytes();
+            if name == b"." || name == b".." {
+                continue;
+            }
+            let sub_usage = e.to_dir().disk_usage_breakdown(depth.saturating_sub(1))?;
+            usage.add(&sub_usage);
+            if depth > 0 {
+                usage.children.push((e.file_name(), sub_usage));
+            }
+        }
+        Ok(usage)
+    }
+}
@@ -0,0 +1,148 @@
+use std::fs;
+use std::io;
+use std::path::{Path, PathBuf};
+
+use crate::compare::{join_path, ChangeReasons, CompareOptions, Difference};
+use crate::dir::Dir;
+use crate::dir_entry::DirEntry;
+use crate::error::Error;
+use crate::fs::{FileSystem, OemCpConverter, ReadWriteSeek};
+use crate::populate::host_file_name;
+use crate::time::{DateTime, TimeProvider};
+
+const VERIFY_BUFFER_SIZE: usize = 4096;
+
+impl<IO: ReadWriteSeek, TP: TimeProvider, OCC: OemCpConverter> FileSystem<IO, TP, OCC>
+where
+    io::Error: From<Error<IO::Error>>,
+{
+    /// Compares the root directory tree with the host directory `host_dir` and returns the differences.
+    ///
+    /// Works like `compare` with the host directory as the second tree, so `Difference::Added` entries exist only on
+    /// the host and `Difference::Removed` entries exist only in the filesystem. Entries existing only on the host are
+    /// reported in the order of their names. Symbolic links are followed and special files are skipped like in
+    /// `populate_from`. Host modification times are converted like in `populate_from` and truncated to the
+    /// resolution of FAT modification times, so a tree populated with preserved timestamps matches its source.
+    ///
+    /// # Errors
+    ///
+    /// Errors that can be returned:
+    ///
+    /// * `std::io::ErrorKind::InvalidInput` will be returned if a host file name is not valid Unicode.
+    /// * Errors returned by the host filesystem and errors of filesystem operations converted to `std::io::Error`.
+    pub fn verify_against<P: AsRef<Path>>(&self, host_dir: P, options: CompareOptions) -> io::Result<Vec<Difference>> {
+        trace!("FileSystem::verify_against");
+        let mut differences = Vec::new();
+        verify_dir(&self.root_dir(), host_dir.as_ref(), "", options, &mut differences)?;
+        Ok(differences)
+    }
+}
+
+fn verify_dir<IO: ReadWriteSeek, TP: TimeProvider, OCC: OemCpConverter>(
+    dir: &Dir<'_, IO, TP, OCC>,
+    host_dir: &Path,
+    prefix: &str,
+    options: CompareOptions,
+    differences: &mut Vec<Difference>,
+) -> io::Result<()>
+where
+    io::Error: From<Error<IO::Error>>,
+{
+    let mut host_entries: Vec<(String, PathBuf, fs::Metadata)> = Vec::new();
+    for host_entry in fs::read_dir(host_dir)? {
+        let host_path = host_entry?.path();
+        let metadata = fs::metadata(&host_path)?;
+        if metadata.is_dir() || metadata.is_file() {
+            host_entries.push((host_file_name(&host_path)?.to_owned(), host_path, metadata));
+        } else {
+            warn!("skipping special file {}", host_path.display());
+        }
+    }
+    host_entries.sort_by(|(name1, ..), (name2, ..)| name1.cmp(name2));
+    let mut matched = vec![false; host_entries.len()];
+    for r in dir.iter() {
+        let e = r?;
+        let name = e.short_file_name_as_bytes();
+        if name == b"." || name == b".." {
+            continue;
+        }
+        let path = join_path(prefix, &e.file_name());
+        let Some(index) = host_entries.iter().position(|(host_name, ..)| e.eq_name(host_name)) else {
+            differences.push(Difference::Removed {
+                path,
+                is_dir: e.is_dir(),
+            });
+            continue;
+        };
+        matched[index] = true;
+        let (_, host_path, metadata) = &host_entries[index];
+        let reasons = if e.is_dir() != metadata.is_dir() {
+            ChangeReasons::KIND
+        } else if e.is_dir() {
+            verify_dir(&e.to_dir(), host_path, &path, options, differences)?;
+            ChangeReasons::empty()
+        } else {
+            verify_file(&e, host_path, metadata, options)?
+        };
+        if !reasons.is_empty() {
+            differences.push(Difference::Changed { path, reasons });
+        }
+    }
+    for ((host_name, _, metadata), _) in host_entries.iter().zip(matched).filter(|(_, m)| !m) {
+        differences.push(Difference::Added {
+            path: join_path(prefix, host_name),
+            is_dir: metadata.is_dir(),
+        });
+    }
+    Ok(())
+}
+
+fn verify_file<IO: ReadWriteSeek, TP: TimeProvider, OCC: OemCpConverter>(
+    e: &DirEntry<'_, IO, TP, OCC>,
+    host_path: &Path,
+    metadata: &fs::Metadata,
+    options: CompareOptions,
+) -> io::Result<ChangeReasons>
+where
+    io::Error: From<Error<IO::Error>>,
+{
+    let mut reasons = ChangeReasons::empty();
+    if e.len() != metadata.len() {
+        reasons |= ChangeReasons::SIZE;
+    } else if options.compare_content && !same_host_content(e, host_path)? {
+        reasons |= ChangeReasons::CONTENT;
+    }
+    if options.compare_modified {
+        // modification times have no sub-second part and 2 seconds resolution
+        let host_modified = DateTime::from_host_time(metadata.modified().ok());
+        let host_modified = DateTime::decode(host_modified.date.encode(), host_modified.time.encode().0, 0);
+        if e.modified() != host_modified {
+            reasons |= ChangeReasons::MODIFIED;
+        }
+    }
+    Ok(reasons)
+}
+
+fn same_host_content<IO: ReadWriteSeek, TP: TimeProvider, OCC: OemCpConverter>(
+    e: &DirEntry<'_, IO, TP, OCC>,
+    host_path: &Path,
+) -> io::Result<bool>
+where
+    io::Error: From<Error<IO::Error>>,
+{
+    let mut file = e.to_file();
+    let mut host_file = fs::File::open(host_path)?;
+    let mut buf = [0_u8; VERIFY_BUFFER_SIZE];
+    let mut host_buf = [0_u8; VERIFY_BUFFER_SIZE];
+    let mut remaining = e.len();
+    while remaining > 0 {
+        let n = usize::try_from(remaining).map_or(VERIFY_BUFFER_SIZE, |r| r.min(VERIFY_BUFFER_SIZE));
+        io::Read::read_exact(&mut file, &mut buf[..n])?;
+        io::Read::read_exact(&mut host_file, &mut host_buf[..n])?;
+        if buf[..n] != host_buf[..n] {
+            return Ok(false);
+        }
+        remaining -= n as u64;
+    }
+    Ok(true)
+}
@@ -0,0 +1,186 @@
+use core::convert::TryFrom;
+use core::marker::PhantomData;
+use core::ptr::NonNull;
+
+use virtio_drivers::device::blk::{BlkReq, BlkResp, VirtIOBlk, SECTOR_SIZE};
+use virtio_drivers::transport::Transport;
+use virtio_drivers::{BufferDirection, Error, Hal, PhysAddr, PAGE_SIZE};
+
+use crate::block_device::BlockDevice;
+
+// Maximal number of requests submitted to the virtqueue at once. Every request uses up to 3 descriptors of the
+// 16-entry queue.
+const MAX_BATCH: usize = 4;
+// Size of data transferred by a single request
+const SEGMENT_SIZE: usize = 4 * PAGE_SIZE;
+// Size of the DMA buffer - bigger transfers are split into multiple batches
+const DMA_BUFFER_SIZE: usize = MAX_BATCH * SEGMENT_SIZE;
+
+// Physically contiguous memory shared with the device
+struct DmaBuffer<H: Hal> {
+    paddr: PhysAddr,
+    vaddr: NonNull<u8>,
+    phantom: PhantomData<H>,
+}
+
+impl<H: Hal> DmaBuffer<H> {
+    fn new() -> Result<Self, Error> {
+        let (paddr, vaddr) = H::dma_alloc(DMA_BUFFER_SIZE / PAGE_SIZE, BufferDirection::Both);
+        if paddr == 0 {
+            return Err(Error::DmaError);
+        }
+        Ok(Self {
+            paddr,
+            vaddr,
+            phantom: PhantomData,
+        })
+    }
+
+    // Returns the given range of the buffer.
+    //
+    // Safety: the range must not be used by the device and no other reference to it may exist.
+    #[allow(clippy::mut_from_ref)]
+    unsafe fn slice(&self, offset: usize, len: usize) -> &mut [u8] {
+        debug_assert!(offset + len <= DMA_BUFFER_SIZE);
+        core::slice::from_raw_parts_mut(self.vaddr.as_ptr().add(offset), len)
+    }
+}
+
+impl<H: Hal> Drop for DmaBuffer<H> {
+    fn drop(&mut self) {
+        // SAFETY: the memory was allocated by `dma_alloc` in `new` using the same number of pages
+        let err = unsafe { H::dma_dealloc(self.paddr, self.vaddr, DMA_BUFFER_SIZE / PAGE_SIZE) };
+        if err != 0 {
+            error!("failed to deallocate virtio-blk DMA buffer: {}", err);
+        }
+    }
+}
+
+// SAFETY: the DMA buffer is owned by a single device object and it is only accessed through `&mut self` methods
+unsafe impl<H: Hal> Send for DmaBuffer<H> {}
+// SAFETY: `&DmaBuffer` does not allow any access to the memory
+unsafe impl<H: Hal> Sync for DmaBuffer<H> {}
+
+/// A virtio-blk device driven by the `virtio-drivers` crate.
+///
+/// Implements `BlockDevice`, so it can be used as storage through `BlockDeviceStorage` on bare virtio, without an OS
+/// block layer. Data is transferred through a 64 KiB DMA buffer allocated using the `Hal` implementation, so buffers
+/// passed by the filesystem do not need to be accessible by the device. A transfer is split into 16 KiB requests
+/// which are submitted to the virtqueue together and completed in any order. Requests are polled for completion,
+/// interrupts are not used.
+pub struct VirtIoBlockDevice<H: Hal, T: Transport> {
+    blk: VirtIOBlk<H, T>,
+    dma: DmaBuffer<H>,
+}
+
+impl<H: Hal, T: Transport> VirtIoBlockDevice<H, T> {
+    /// Creates a new `VirtIoBlockDevice` instance using an initialized virtio-blk driver.
+    ///
+    /// # Errors
+    ///
+    /// `Error::DmaError` will be returned if the DMA buffer cannot be allocated.
+    pub fn new(blk: VirtIOBlk<H, T>) -> Result<Self, Error> {
+        Ok(Self {
+            blk,
+            dma: DmaBuffer::new()?,
+        })
+    }
+
+    /// Returns inner virtio-blk driver
+    pub fn into_inner(self) -> VirtIOBlk<H, T> {
+        self.blk
+    }
+
+    // Transfers `len` bytes between the DMA buffer and consecutive blocks starting from `block_id`. Requests are
+    // always completed before returning, so the DMA buffer is not used by the device anymore, even on error.
+    fn transfer(&mut self, block_id: u64, len: usize, write: bool) -> Result<(), Error> {
+        let block_id = usize::try_from(block_id).map_err(|_| Error::InvalidParam)?;
+        let mut requests: [BlkReq; MAX_BATCH] = Default::default();
+        let mut responses: [BlkResp; MAX_BATCH] = Default::default();
+        let mut tokens: [Option<u16>; MAX_BATCH] = [None; MAX_BATCH];
+        let mut result = Ok(());
+        for (i, offset) in (0..len).step_by(SEGMENT_SIZE).enumerate() {
+            let segment_block_id = block_id + offset / SECTOR_SIZE;
+            // SAFETY: segments do not overlap and the segment is not accessed until the request is completed
+            let submitted = unsafe {
+                let buf = self.dma.slice(offset, SEGMENT_SIZE.min(len - offset));
+                if write {
+                    self.blk
+                        .write_blocks_nb(segment_block_id, &mut requests[i], buf, &mut responses[i])
+                } else {
+                    self.blk
+                        .read_blocks_nb(segment_block_id, &mut requests[i], buf, &mut responses[i])
+                }
+            };
+            match submitted {
+                Ok(token) => tokens[i] = Some(token),
+                Err(err) => {
+                    result = Err(err);
+                    break;
+                }
+            }
+        }
+        while tokens.iter().any(Option::is_some) {
+            let token = loop {
+                if let Some(token) = self.blk.peek_used() {
+                    break token;
+                }
+                core::hint::spin_loop();
+            };
+            let i = tokens.iter().position(|t| *t == Some(token)).ok_or(Error::WrongToken)?;
+            tokens[i] = None;
+            let offset = i * SEGMENT_SIZE;
+            // SAFETY: the same buffers are passed as when the request was submitted
+            let completed = unsafe {
+                let buf = self.dma.slice(offset, SEGMENT_SIZE.min(len - offset));
+                if write {
+                    self.blk
+                        .complete_write_blocks(token, &requests[i], buf, &mut responses[i])
+                } else {
+                    self.blk
+                        .complete_read_blocks(token, &requests[i], buf, &mut responses[i])
+                }
+            };
+            result = result.and(completed);
+        }
+        result
+    }
+}
+
+impl<H: Hal, T: Transport> BlockDevice for VirtIoBlockDevice<H, T> {
+    type Error = Error;
+
+    fn num_blocks(&self) -> u64 {
+        self.blk.capacity()
+    }
+
+    fn block_size(&self) -> usize {
+        SECTOR_SIZE
+    }
+
+    fn read_block(&mut self, block_id: u64, buf: &mut [u8]) -> Result<(), Self::Error> {
+        let mut block_id = block_id;
+        for chunk in buf.chunks_mut(DMA_BUFFER_SIZE) {
+            self.transfer(block_id, chunk.len(), false)?;
+            // SAFETY: all requests have been completed
+            chunk.copy_from_slice(unsafe { self.dma.slice(0, chunk.len()) });
+            block_id += (chunk.len() / SECTOR_SIZE) as u64;
+        }
+        Ok(())
+    }
+
+    fn write_block(&mut self, block_id: u64, buf: &[u8]) -> Result<(), Self::Error> {
+        let mut block_id = block_id;
+        for chunk in buf.chunks(DMA_BUFFER_SIZE) {
+            // SAFETY: the device does not use the DMA buffer between transfers
+            unsafe { self.dma.slice(0, chunk.len()) }.copy_from_slice(chunk);
+            self.transfer(block_id, chunk.len(), true)?;
+            block_id += (chunk.len() / SECTOR_SIZE) as u64;
+        }
+        Ok(())
+    }
+
+    fn flush(&mut self) -> Result<(), Self::Error> {
+        self.blk.flush()
+    }
+}
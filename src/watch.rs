@@ -0,0 +1,117 @@
+#[cfg(all(not(feature = "std"), feature = "alloc"))]
+use alloc::{boxed::Box, vec::Vec};
+
+use crate::dir_entry::EntryId;
+
+/// A change made by this crate to a directory or a file.
+///
+/// Events are delivered to callbacks registered by `FileSystem::watch` and `FileSystem::watch_all` after the change
+/// has been made. Entries are identified by `EntryId` values which are stable as long as an entry stays in place, so
+/// an observer can map them to its own copy of the directory tree. Names are the names passed to the operation and
+/// can differ in case from names stored on the volume for removed and renamed entries.
+#[derive(Clone, Debug, PartialEq, Eq)]
+#[non_exhaustive]
+pub enum FsEvent<'a> {
+    /// A file or a directory has been created.
+    Created {
+        /// ID of the new entry.
+        id: EntryId,
+        /// Name of the new entry.
+        name: &'a str,
+        /// `true` if a directory has been created.
+        is_dir: bool,
+    },
+    /// Content or size of a file has been changed by a write or a truncation.
+    Modified {
+        /// ID of the modified file entry.
+        id: EntryId,
+    },
+    /// A file or a directory has been renamed or moved.
+    Renamed {
+        /// ID of the entry before the operation.
+        old_id: EntryId,
+        /// Name of the entry before the operation.
+        old_name: &'a str,
+        /// ID of the entry after the operation.
+        new_id: EntryId,
+        /// Name of the entry after the operation.
+        new_name: &'a str,
+    },
+    /// A file or a directory has been removed.
+    Removed {
+        /// ID of the removed entry.
+        id: EntryId,
+        /// Name of the removed entry.
+        name: &'a str,
+    },
+}
+
+impl FsEvent<'_> {
+    // Checks if the event changes the directory identified by `dir_cluster` (0 for the root directory)
+    #[cfg(feature = "alloc")]
+    fn affects_dir(&self, dir_cluster: u32) -> bool {
+        match self {
+            FsEvent::Created { id, .. } | FsEvent::Modified { id } | FsEvent::Removed { id, .. } => {
+                id.dir_cluster() == dir_cluster
+            }
+            FsEvent::Renamed { old_id, new_id, .. } => {
+                old_id.dir_cluster() == dir_cluster || new_id.dir_cluster() == dir_cluster
+            }
+        }
+    }
+}
+
+/// A handle of a callback registered by `FileSystem::watch` or `FileSystem::watch_all`.
+#[derive(Copy, Clone, Debug, PartialEq, Eq, Hash)]
+pub struct WatchId(u64);
+
+#[cfg(feature = "alloc")]
+type WatchCallback = Box<dyn Fn(&FsEvent<'_>) + Send + Sync>;
+
+#[cfg(feature = "alloc")]
+struct Watcher {
+    id: WatchId,
+    // Watched directory or `None` if all changes are reported
+    dir_cluster: Option<u32>,
+    callback: WatchCallback,
+}
+
+// Registry of callbacks notified about changes
+#[cfg(feature = "alloc")]
+#[derive(Default)]
+pub(crate) struct Watchers {
+    next_id: u64,
+    list: Vec<Watcher>,
+}
+
+#[cfg(feature = "alloc")]
+impl Watchers {
+    pub(crate) fn add(&mut self, dir_cluster: Option<u32>, callback: WatchCallback) -> WatchId {
+        let id = WatchId(self.next_id);
+        self.next_id += 1;
+        self.list.push(Watcher {
+            id,
+            dir_cluster,
+            callback,
+        });
+        id
+    }
+
+    pub(crate) fn remove(&mut self, id: WatchId) -> bool {
+        let len = self.list.len();
+        self.list.retain(|w| w.id != id);
+        self.list.len() != len
+    }
+
+    pub(crate) fn is_empty(&self) -> bool {
+        self.list.is_empty()
+    }
+
+    pub(crate) fn notify(&self, event: &FsEvent<'_>) {
+        for watcher in &self.list {
+            if watcher.dir_cluster.map_or(true, |c| event.affects_dir(c)) {
+                (watcher.callback)(event);
+            }
+        }
+    }
+}
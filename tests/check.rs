@@ -0,0 +1,476 @@
+#![cfg(feature = "std")]
+
+use std::fs;
+use std::io::{self, prelude::*};
+
+use axfatfs::{CheckProblem, FsOptions, RepairAction, RepairOptions, StdIoWrapper};
+use fscommon::BufStream;
+
+const FAT12_IMG: &str = "fat12.img";
+const FAT16_IMG: &str = "fat16.img";
+const FAT32_IMG: &str = "fat32.img";
+const IMG_DIR: &str = "resources";
+const TMP_DIR: &str = "tmp";
+const TEST_STR: &str = "Hi there Rust programmer!\n";
+
+type FileSystem = axfatfs::FileSystem<StdIoWrapper<BufStream<fs::File>>>;
+
+fn call_with_tmp_img<F: Fn(&str)>(f: F, filename: &str, test_seq: u32) {
+    let _ = env_logger::builder().is_test(true).try_init();
+    let img_path = format!("{}/{}", IMG_DIR, filename);
+    let tmp_path = format!("{}/{}-{}", TMP_DIR, test_seq, filename);
+    fs::create_dir(TMP_DIR).ok();
+    fs::copy(img_path, &tmp_path).unwrap();
+    f(tmp_path.as_str());
+    fs::remove_file(tmp_path).unwrap();
+}
+
+fn open_filesystem_rw(tmp_path: &str) -> FileSystem {
+    let file = fs::OpenOptions::new().read(true).write(true).open(tmp_path).unwrap();
+    FileSystem::new(BufStream::new(file), FsOptions::new()).unwrap()
+}
+
+fn read_u16(buf: &[u8], offset: usize) -> u16 {
+    u16::from_le_bytes([buf[offset], buf[offset + 1]])
+}
+
+// Returns byte offset of the first FAT and size of a FAT entry (FAT16 and FAT32 only)
+fn fat_layout(tmp_path: &str) -> (u64, u64) {
+    let mut boot = [0_u8; 512];
+    fs::File::open(tmp_path).unwrap().read_exact(&mut boot).unwrap();
+    let bytes_per_sector = u64::from(read_u16(&boot, 11));
+    let reserved_sectors = u64::from(read_u16(&boot, 14));
+    let entry_size = if read_u16(&boot, 22) == 0 { 4 } else { 2 };
+    (reserved_sectors * bytes_per_sector, entry_size)
+}
+
+fn read_fat_entry(tmp_path: &str, cluster: u32) -> u32 {
+    let (fat_offset, entry_size) = fat_layout(tmp_path);
+    let mut file = fs::File::open(tmp_path).unwrap();
+    file.seek(io::SeekFrom::Start(fat_offset + u64::from(cluster) * entry_size))
+        .unwrap();
+    let mut bytes = [0_u8; 4];
+    file.read_exact(&mut bytes[..entry_size as usize]).unwrap();
+    u32::from_le_bytes(bytes)
+}
+
+// Writes a raw value into all FAT copies
+fn write_fat_entry(tmp_path: &str, cluster: u32, value: u32) {
+    let mut boot = [0_u8; 512];
+    let mut file = fs::OpenOptions::new().read(true).write(true).open(tmp_path).unwrap();
+    file.read_exact(&mut boot).unwrap();
+    let (fat_offset, entry_size) = fat_layout(tmp_path);
+    let sectors_per_fat = if entry_size == 4 {
+        u64::from(u32::from_le_bytes([boot[36], boot[37], boot[38], boot[39]]))
+    } else {
+        u64::from(read_u16(&boot, 22))
+    };
+    let bytes_per_fat = sectors_per_fat * u64::from(read_u16(&boot, 11));
+    for fat in 0..u64::from(boot[16]) {
+        file.seek(io::SeekFrom::Start(
+            fat_offset + fat * bytes_per_fat + u64::from(cluster) * entry_size,
+        ))
+        .unwrap();
+        let bytes = value.to_le_bytes();
+        file.write_all(&bytes[..entry_size as usize]).unwrap();
+    }
+}
+
+fn end_of_chain(tmp_path: &str) -> u32 {
+    if fat_layout(tmp_path).1 == 4 {
+        0x0FFF_FFFF
+    } else {
+        0xFFFF
+    }
+}
+
+// Creates a file occupying given number of clusters and returns its first cluster
+fn create_file(fs: &FileSystem, path: &str, clusters: u32) -> u32 {
+    let mut file = fs.create_file(path).unwrap();
+    let len = fs.cluster_size() * clusters;
+    file.write_all(&vec![b'x'; len as usize]).unwrap();
+    drop(file);
+    fs.metadata(path).unwrap().first_cluster().unwrap()
+}
+
+fn test_check_clean(tmp_path: &str) {
+    let fs = open_filesystem_rw(tmp_path);
+    let report = fs.check().unwrap();
+    assert!(report.is_clean(), "{:?}", report.problems());
+    assert!(report.files() > 0);
+    assert!(report.dirs() > 1);
+    let stats = fs.stats().unwrap();
+    assert_eq!(report.free_clusters(), stats.free_clusters());
+    assert_eq!(report.used_clusters() + report.free_clusters(), stats.total_clusters());
+    assert_eq!(report.bad_clusters(), 0);
+}
+
+#[test]
+fn test_check_clean_fat12() {
+    call_with_tmp_img(test_check_clean, FAT12_IMG, 5001)
+}
+
+#[test]
+fn test_check_clean_fat16() {
+    call_with_tmp_img(test_check_clean, FAT16_IMG, 5001)
+}
+
+#[test]
+fn test_check_clean_fat32() {
+    call_with_tmp_img(test_check_clean, FAT32_IMG, 5001)
+}
+
+fn test_check_chain_problems(tmp_path: &str) {
+    let (a, b, c, free) = {
+        let fs = open_filesystem_rw(tmp_path);
+        let a = create_file(&fs, "a.bin", 2);
+        let b = create_file(&fs, "b.bin", 1);
+        let c = create_file(&fs, "c.bin", 3);
+        // Allocate and free a cluster to get a free cluster number
+        let free = create_file(&fs, "free.bin", 1);
+        fs.remove("free.bin").unwrap();
+        fs.unmount().unwrap();
+        (a, b, c, free)
+    };
+    let eoc = end_of_chain(tmp_path);
+    let a2 = read_fat_entry(tmp_path, a);
+    let c2 = read_fat_entry(tmp_path, c);
+    let c_last = read_fat_entry(tmp_path, c2);
+    // a.bin: chain shortened to one cluster - the second cluster becomes lost
+    write_fat_entry(tmp_path, a, eoc);
+    // b.bin: points to the last cluster of c.bin
+    write_fat_entry(tmp_path, b, c_last);
+    // free cluster marked as allocated
+    write_fat_entry(tmp_path, free, eoc);
+
+    let fs = open_filesystem_rw(tmp_path);
+    let report = fs.check().unwrap();
+    let problems = report.problems();
+    assert!(problems.contains(&CheckProblem::SizeMismatch {
+        path: "/a.bin".into(),
+        size: u64::from(fs.cluster_size()) * 2,
+        clusters: 1,
+    }));
+    assert!(problems.contains(&CheckProblem::LostChain {
+        first_cluster: a2,
+        clusters: 1,
+    }));
+    assert!(problems.contains(&CheckProblem::LostChain {
+        first_cluster: free,
+        clusters: 1,
+    }));
+    assert!(problems.iter().any(|p| matches!(
+        p,
+        CheckProblem::CrossLinked { cluster, .. } if *cluster == c_last
+    )));
+
+    // Break chain of c.bin by freeing its second cluster
+    drop(fs);
+    write_fat_entry(tmp_path, c2, 0);
+    let fs = open_filesystem_rw(tmp_path);
+    let problems = fs.check().unwrap().problems().to_vec();
+    assert!(problems.contains(&CheckProblem::BrokenChain {
+        path: "/c.bin".into(),
+        cluster: c,
+    }));
+
+    // Invalid value and a loop
+    drop(fs);
+    write_fat_entry(tmp_path, c, 1);
+    write_fat_entry(tmp_path, a, a);
+    let fs = open_filesystem_rw(tmp_path);
+    let problems = fs.check().unwrap().problems().to_vec();
+    assert!(problems.contains(&CheckProblem::InvalidFatEntry { cluster: c, value: 1 }));
+    assert!(problems.contains(&CheckProblem::ChainLoop {
+        path: "/a.bin".into(),
+        cluster: a,
+    }));
+}
+
+#[test]
+fn test_check_chain_problems_fat16() {
+    call_with_tmp_img(test_check_chain_problems, FAT16_IMG, 5002)
+}
+
+#[test]
+fn test_check_chain_problems_fat32() {
+    call_with_tmp_img(test_check_chain_problems, FAT32_IMG, 5002)
+}
+
+fn test_check_malformed_lfn(tmp_path: &str) {
+    {
+        let fs = open_filesystem_rw(tmp_path);
+        fs.create_dir("dir").unwrap();
+        let mut file = fs.create_file("dir/a long file name.txt").unwrap();
+        file.write_all(TEST_STR.as_bytes()).unwrap();
+        drop(file);
+        fs.unmount().unwrap();
+    }
+    // Corrupt checksum of the LFN entry
+    let data = fs::read(tmp_path).unwrap();
+    let pos = data
+        .chunks_exact(32)
+        .position(|e| e[11] == 0x0F && e[1] == u16::from(b'a').to_le_bytes()[0] && e[3] == b' ')
+        .unwrap();
+    let mut file = fs::OpenOptions::new().write(true).open(tmp_path).unwrap();
+    file.seek(io::SeekFrom::Start(pos as u64 * 32 + 13)).unwrap();
+    file.write_all(&[data[pos * 32 + 13].wrapping_add(1)]).unwrap();
+    drop(file);
+
+    let fs = open_filesystem_rw(tmp_path);
+    let report = fs.check().unwrap();
+    let problems = report.problems();
+    assert_eq!(problems.len(), 1, "{:?}", problems);
+    assert!(matches!(
+        &problems[0],
+        CheckProblem::MalformedLfn { path, entries: 2, .. } if path == "/dir"
+    ));
+}
+
+#[test]
+fn test_check_malformed_lfn_fat12() {
+    call_with_tmp_img(test_check_malformed_lfn, FAT12_IMG, 5003)
+}
+
+#[test]
+fn test_check_malformed_lfn_fat32() {
+    call_with_tmp_img(test_check_malformed_lfn, FAT32_IMG, 5003)
+}
+
+// Creates files with a shortened, a cross-linked and a broken chain and a lost cluster
+fn corrupt_chains(tmp_path: &str) {
+    let (a, b, c, free) = {
+        let fs = open_filesystem_rw(tmp_path);
+        fs.create_dir("dir").unwrap();
+        let a = create_file(&fs, "a.bin", 2);
+        let b = create_file(&fs, "b.bin", 1);
+        let c = create_file(&fs, "dir/c.bin", 3);
+        let free = create_file(&fs, "free.bin", 1);
+        fs.remove("free.bin").unwrap();
+        fs.unmount().unwrap();
+        (a, b, c, free)
+    };
+    let eoc = end_of_chain(tmp_path);
+    let c2 = read_fat_entry(tmp_path, c);
+    let c_last = read_fat_entry(tmp_path, c2);
+    write_fat_entry(tmp_path, a, eoc);
+    write_fat_entry(tmp_path, b, c_last);
+    write_fat_entry(tmp_path, c2, 0);
+    write_fat_entry(tmp_path, free, eoc);
+}
+
+fn test_repair_dry_run(tmp_path: &str) {
+    corrupt_chains(tmp_path);
+    let before = fs::read(tmp_path).unwrap();
+    {
+        let fs = open_filesystem_rw(tmp_path);
+        let report = fs.repair(RepairOptions::new().dry_run(true)).unwrap();
+        assert!(report.is_dry_run());
+        assert!(!report.check_report().is_clean());
+        assert!(report.actions().contains(&RepairAction::SetSize {
+            path: "/a.bin".into(),
+            old_size: u64::from(fs.cluster_size()) * 2,
+            new_size: u64::from(fs.cluster_size()),
+        }));
+        assert!(report.actions().contains(&RepairAction::TruncateChain {
+            path: "/dir/c.bin".into(),
+            clusters: 1,
+        }));
+        assert!(!fs.check().unwrap().is_clean());
+    }
+    assert!(fs::read(tmp_path).unwrap() == before);
+}
+
+#[test]
+fn test_repair_dry_run_fat16() {
+    call_with_tmp_img(test_repair_dry_run, FAT16_IMG, 5004)
+}
+
+#[test]
+fn test_repair_dry_run_fat32() {
+    call_with_tmp_img(test_repair_dry_run, FAT32_IMG, 5004)
+}
+
+fn test_repair_chains(tmp_path: &str) {
+    corrupt_chains(tmp_path);
+    {
+        let fs = open_filesystem_rw(tmp_path);
+        let report = fs.repair(RepairOptions::new()).unwrap();
+        assert!(!report.is_dry_run());
+        assert!(report
+            .actions()
+            .iter()
+            .any(|a| matches!(a, RepairAction::FreeLostChain { .. })));
+        fs.unmount().unwrap();
+    }
+    let fs = open_filesystem_rw(tmp_path);
+    let report = fs.check().unwrap();
+    assert!(report.is_clean(), "{:?}", report.problems());
+    let cluster_size = u64::from(fs.cluster_size());
+    assert_eq!(fs.metadata("a.bin").unwrap().len(), cluster_size);
+    assert_eq!(fs.metadata("dir/c.bin").unwrap().len(), cluster_size);
+    let mut buf = Vec::new();
+    fs.open_file("dir/c.bin").unwrap().read_to_end(&mut buf).unwrap();
+    assert_eq!(buf, vec![b'x'; cluster_size as usize]);
+    assert_eq!(fs.stats().unwrap().free_clusters(), report.free_clusters());
+}
+
+#[test]
+fn test_repair_chains_fat16() {
+    call_with_tmp_img(test_repair_chains, FAT16_IMG, 5005)
+}
+
+#[test]
+fn test_repair_chains_fat32() {
+    call_with_tmp_img(test_repair_chains, FAT32_IMG, 5005)
+}
+
+fn test_repair_malformed_lfn(tmp_path: &str) {
+    test_check_malformed_lfn(tmp_path);
+    {
+        let fs = open_filesystem_rw(tmp_path);
+        let report = fs.repair(RepairOptions::new()).unwrap();
+        assert!(matches!(
+            report.actions(),
+            [RepairAction::RemoveLfnEntries { entries: 2, .. }]
+        ));
+        fs.unmount().unwrap();
+    }
+    let fs = open_filesystem_rw(tmp_path);
+    assert!(fs.check().unwrap().is_clean());
+    let names = fs
+        .root_dir()
+        .open_dir("dir")
+        .unwrap()
+        .iter()
+        .map(|e| e.unwrap().file_name())
+        .collect::<Vec<_>>();
+    assert_eq!(names, [".", "..", "ALONGF~1.TXT"]);
+}
+
+#[test]
+fn test_repair_malformed_lfn_fat12() {
+    call_with_tmp_img(test_repair_malformed_lfn, FAT12_IMG, 5006)
+}
+
+#[test]
+fn test_repair_malformed_lfn_fat32() {
+    call_with_tmp_img(test_repair_malformed_lfn, FAT32_IMG, 5006)
+}
+
+// Leaves LFN entries not followed by a short entry, like implementations not supporting LFN do when deleting files
+fn test_repair_orphaned_lfn(tmp_path: &str) {
+    {
+        let fs = open_filesystem_rw(tmp_path);
+        fs.create_dir("dir").unwrap();
+        fs.create_file("dir/a long file name.txt").unwrap();
+        fs.unmount().unwrap();
+    }
+    let data = fs::read(tmp_path).unwrap();
+    let pos = data
+        .chunks_exact(32)
+        .position(|e| e.starts_with(b"ALONGF~1TXT"))
+        .unwrap();
+    let mut file = fs::OpenOptions::new().write(true).open(tmp_path).unwrap();
+    file.seek(io::SeekFrom::Start(pos as u64 * 32)).unwrap();
+    file.write_all(&[0xE5]).unwrap();
+    drop(file);
+
+    {
+        let fs = open_filesystem_rw(tmp_path);
+        let report = fs.repair(RepairOptions::new()).unwrap();
+        assert!(matches!(
+            report.check_report().problems(),
+            [CheckProblem::MalformedLfn { path, entries: 2, .. }] if path == "/dir"
+        ));
+        assert!(matches!(
+            report.actions(),
+            [RepairAction::RemoveLfnEntries { entries: 2, .. }]
+        ));
+        fs.unmount().unwrap();
+    }
+    let data = fs::read(tmp_path).unwrap();
+    let lfn_entries = &data[(pos - 2) * 32..pos * 32];
+    assert!(lfn_entries.chunks_exact(32).all(|e| e[0] == 0xE5));
+    let fs = open_filesystem_rw(tmp_path);
+    assert!(fs.check().unwrap().is_clean());
+}
+
+#[test]
+fn test_repair_orphaned_lfn_fat16() {
+    call_with_tmp_img(test_repair_orphaned_lfn, FAT16_IMG, 5008)
+}
+
+fn test_repair_recover_lost_chains(tmp_path: &str) {
+    let (a, b) = {
+        let fs = open_filesystem_rw(tmp_path);
+        fs.create_dir("FOUND.000").unwrap();
+        let a = create_file(&fs, "a.bin", 3);
+        let b = create_file(&fs, "b.bin", 1);
+        fs.unmount().unwrap();
+        (a, b)
+    };
+    // Make both files lost by marking their directory entries as deleted
+    let data = fs::read(tmp_path).unwrap();
+    let mut file = fs::OpenOptions::new().write(true).open(tmp_path).unwrap();
+    for pos in data
+        .chunks_exact(32)
+        .enumerate()
+        .filter(|(_, e)| e[11] != 0x0F && (&e[..11] == b"A       BIN" || &e[..11] == b"B       BIN"))
+        .map(|(i, _)| i)
+    {
+        // Delete the LFN entry too if there is one
+        if data[(pos - 1) * 32 + 11] == 0x0F {
+            file.seek(io::SeekFrom::Start((pos as u64 - 1) * 32)).unwrap();
+            file.write_all(&[0xE5]).unwrap();
+        }
+        file.seek(io::SeekFrom::Start(pos as u64 * 32)).unwrap();
+        file.write_all(&[0xE5]).unwrap();
+    }
+    drop(file);
+
+    {
+        let fs = open_filesystem_rw(tmp_path);
+        let report = fs.repair(RepairOptions::new().recover_lost_chains(true)).unwrap();
+        assert_eq!(
+            report.actions(),
+            [
+                RepairAction::RecoverLostChain {
+                    first_cluster: a,
+                    clusters: 3,
+                    path: "/FOUND.001/FILE0000.CHK".into(),
+                },
+                RepairAction::RecoverLostChain {
+                    first_cluster: b,
+                    clusters: 1,
+                    path: "/FOUND.001/FILE0001.CHK".into(),
+                },
+            ]
+        );
+        fs.unmount().unwrap();
+    }
+    let fs = open_filesystem_rw(tmp_path);
+    let report = fs.check().unwrap();
+    assert!(report.is_clean(), "{:?}", report.problems());
+    let cluster_size = fs.cluster_size() as usize;
+    let mut buf = Vec::new();
+    fs.open_file("FOUND.001/FILE0000.CHK")
+        .unwrap()
+        .read_to_end(&mut buf)
+        .unwrap();
+    assert_eq!(buf, vec![b'x'; cluster_size * 3]);
+    assert_eq!(
+        fs.metadata("FOUND.001/FILE0001.CHK").unwrap().len(),
+        cluster_size as u64
+    );
+}
+
+#[test]
+fn test_repair_recover_lost_chains_fat12() {
+    call_with_tmp_img(test_repair_recover_lost_chains, FAT12_IMG, 5007)
+}
+
+#[test]
+fn test_repair_recover_lost_chains_fat32() {
+    call_with_tmp_img(test_repair_recover_lost_chains, FAT32_IMG, 5007)
+}
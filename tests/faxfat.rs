@@ -0,0 +1,109 @@
+#![cfg(feature = "cli")]
+use std::fs;
+use std::io::Write;
+use std::process::{Command, Output};
+
+const IMG_DIR: &str = "resources";
+const TMP_DIR: &str = "tmp";
+
+fn call_with_tmp_img<F: Fn(&str)>(f: F, filename: &str, test_seq: u32) {
+    let img_path = format!("{}/{}", IMG_DIR, filename);
+    let tmp_path = format!("{}/faxfat-{}-{}", TMP_DIR, test_seq, filename);
+    fs::create_dir(TMP_DIR).ok();
+    fs::copy(img_path, &tmp_path).unwrap();
+    f(tmp_path.as_str());
+    fs::remove_file(tmp_path).unwrap();
+}
+
+fn faxfat(img_path: &str, args: &[&str]) -> Output {
+    Command::new(env!("CARGO_BIN_EXE_faxfat"))
+        .arg(img_path)
+        .args(args)
+        .output()
+        .unwrap()
+}
+
+// Points the FAT entry of `cluster` of a FAT32 volume to the first free cluster, breaking the chain
+fn break_chain(img_path: &str, cluster: u32) {
+    let mut data = fs::read(img_path).unwrap();
+    let bytes_per_sector = u16::from_le_bytes([data[11], data[12]]) as usize;
+    let reserved_sectors = u16::from_le_bytes([data[14], data[15]]) as usize;
+    let fat_offset = bytes_per_sector * reserved_sectors;
+    let entry_offset = |cluster: u32| fat_offset + cluster as usize * 4;
+    let free_cluster = (2..)
+        .find(|c| data[entry_offset(*c)..entry_offset(*c) + 4] == [0; 4])
+        .unwrap();
+    data[entry_offset(cluster)..entry_offset(cluster) + 4].copy_from_slice(&free_cluster.to_le_bytes());
+    fs::write(img_path, data).unwrap();
+}
+
+#[test]
+fn test_cp_in_cat_ls() {
+    call_with_tmp_img(
+        |tmp_path| {
+            let src_path = format!("{}.src", tmp_path);
+            fs::write(&src_path, b"Hello from the host!").unwrap();
+            assert!(faxfat(tmp_path, &["mkdir", "/host"]).status.success());
+            assert!(faxfat(tmp_path, &["cp-in", &src_path, "/host/hello.txt"])
+                .status
+                .success());
+            fs::remove_file(&src_path).unwrap();
+
+            let output = faxfat(tmp_path, &["cat", "/host/hello.txt"]);
+            assert!(output.status.success());
+            assert_eq!(output.stdout, b"Hello from the host!");
+            let output = faxfat(tmp_path, &["ls", "/host"]);
+            assert!(output.status.success());
+            assert!(String::from_utf8(output.stdout).unwrap().contains("hello.txt"));
+
+            let output = faxfat(tmp_path, &["cat", "/host/missing.txt"]);
+            assert!(!output.status.success());
+            assert!(!output.stderr.is_empty());
+        },
+        "fat32.img",
+        1,
+    );
+}
+
+#[test]
+fn test_fsck_exit_status() {
+    call_with_tmp_img(
+        |tmp_path| {
+            assert!(faxfat(tmp_path, &["fsck"]).status.success());
+            // Create a directory spanning multiple clusters and break its chain after the first cluster
+            let first_cluster = {
+                let fs = axfatfs::FileSystem::new(
+                    fs::File::options().read(true).write(true).open(tmp_path).unwrap(),
+                    axfatfs::FsOptions::new(),
+                )
+                .unwrap();
+                let dir = fs.create_dir("big").unwrap();
+                for i in 0..40 {
+                    dir.create_file(&format!("file{}.txt", i))
+                        .unwrap()
+                        .write_all(b"data")
+                        .unwrap();
+                }
+                fs.metadata("big").unwrap().first_cluster().unwrap()
+            };
+            break_chain(tmp_path, first_cluster);
+            let output = faxfat(tmp_path, &["fsck"]);
+            assert!(!output.status.success());
+            assert!(String::from_utf8(output.stdout)
+                .unwrap()
+                .contains("big: cluster chain broken after"));
+
+            // Files in the truncated part of the directory become lost chains found only by the next check
+            let output = faxfat(tmp_path, &["fsck", "--repair"]);
+            let stdout = String::from_utf8(output.stdout).unwrap();
+            assert!(!output.status.success());
+            assert!(stdout.contains("big: truncate cluster chain"));
+            assert!(stdout.contains("lost chain"));
+
+            assert!(faxfat(tmp_path, &["fsck", "--repair"]).status.success());
+            assert!(faxfat(tmp_path, &["fsck"]).status.success());
+        },
+        "fat32.img",
+        2,
+    );
+}
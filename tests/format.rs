@@ -1,32 +1,34 @@
+#![cfg(feature = "std")]
+
 use std::io;
 use std::io::prelude::*;
 
-use fatfs::{FatType, StdIoWrapper};
+use axfatfs::{FatType, StdIoWrapper};
 use fscommon::BufStream;
 
 const KB: u64 = 1024;
 const MB: u64 = KB * 1024;
 const TEST_STR: &str = "Hi there Rust programmer!\n";
 
-type FileSystem = fatfs::FileSystem<StdIoWrapper<BufStream<io::Cursor<Vec<u8>>>>>;
+type FileSystem = axfatfs::FileSystem<StdIoWrapper<BufStream<io::Cursor<Vec<u8>>>>>;
 
 fn init_logger() {
     let _ = env_logger::builder().is_test(true).try_init();
 }
 
-fn format_fs(opts: fatfs::FormatVolumeOptions, total_bytes: u64) -> FileSystem {
+fn format_fs(opts: axfatfs::FormatVolumeOptions, total_bytes: u64) -> FileSystem {
     init_logger();
     // Init storage to 0xD1 bytes (value has been choosen to be parsed as normal file)
     let storage_vec: Vec<u8> = vec![0xD1_u8; total_bytes as usize];
     let storage_cur = io::Cursor::new(storage_vec);
-    let mut buffered_stream = fatfs::StdIoWrapper::from(BufStream::new(storage_cur));
-    fatfs::format_volume(&mut buffered_stream, opts).expect("format volume");
-    fatfs::FileSystem::new(buffered_stream, fatfs::FsOptions::new()).expect("open fs")
+    let mut buffered_stream = axfatfs::StdIoWrapper::from(BufStream::new(storage_cur));
+    axfatfs::format_volume(&mut buffered_stream, opts).expect("format volume");
+    axfatfs::FileSystem::new(buffered_stream, axfatfs::FsOptions::new()).expect("open fs")
 }
 
 fn basic_fs_test(fs: &FileSystem) {
     let stats = fs.stats().expect("stats");
-    if fs.fat_type() == fatfs::FatType::Fat32 {
+    if fs.fat_type() == axfatfs::FatType::Fat32 {
         // On FAT32 one cluster is allocated for root directory
         assert_eq!(stats.total_clusters(), stats.free_clusters() + 1);
     } else {
@@ -73,7 +75,7 @@ fn basic_fs_test(fs: &FileSystem) {
     assert_eq!(filenames, ["subdir1", "new-name.txt"]);
 }
 
-fn test_format_fs(opts: fatfs::FormatVolumeOptions, total_bytes: u64) -> FileSystem {
+fn test_format_fs(opts: axfatfs::FormatVolumeOptions, total_bytes: u64) -> FileSystem {
     let fs = format_fs(opts, total_bytes);
     basic_fs_test(&fs);
     fs
@@ -82,47 +84,47 @@ fn test_format_fs(opts: fatfs::FormatVolumeOptions, total_bytes: u64) -> FileSys
 #[test]
 fn test_format_1mb() {
     let total_bytes = MB;
-    let opts = fatfs::FormatVolumeOptions::new();
+    let opts = axfatfs::FormatVolumeOptions::new();
     let fs = test_format_fs(opts, total_bytes);
-    assert_eq!(fs.fat_type(), fatfs::FatType::Fat12);
+    assert_eq!(fs.fat_type(), axfatfs::FatType::Fat12);
 }
 
 #[test]
 fn test_format_8mb_1fat() {
     let total_bytes = 8 * MB;
-    let opts = fatfs::FormatVolumeOptions::new().fats(1);
+    let opts = axfatfs::FormatVolumeOptions::new().fats(1);
     let fs = test_format_fs(opts, total_bytes);
-    assert_eq!(fs.fat_type(), fatfs::FatType::Fat16);
+    assert_eq!(fs.fat_type(), axfatfs::FatType::Fat16);
 }
 
 #[test]
 fn test_format_50mb() {
     let total_bytes = 50 * MB;
-    let opts = fatfs::FormatVolumeOptions::new();
+    let opts = axfatfs::FormatVolumeOptions::new();
     let fs = test_format_fs(opts, total_bytes);
-    assert_eq!(fs.fat_type(), fatfs::FatType::Fat16);
+    assert_eq!(fs.fat_type(), axfatfs::FatType::Fat16);
 }
 
 #[test]
 fn test_format_2gb_512sec() {
     let total_bytes = 2 * 1024 * MB;
-    let opts = fatfs::FormatVolumeOptions::new();
+    let opts = axfatfs::FormatVolumeOptions::new();
     let fs = test_format_fs(opts, total_bytes);
-    assert_eq!(fs.fat_type(), fatfs::FatType::Fat32);
+    assert_eq!(fs.fat_type(), axfatfs::FatType::Fat32);
 }
 
 #[test]
 fn test_format_1gb_4096sec() {
     let total_bytes = 1024 * MB;
-    let opts = fatfs::FormatVolumeOptions::new().bytes_per_sector(4096);
+    let opts = axfatfs::FormatVolumeOptions::new().bytes_per_sector(4096);
     let fs = test_format_fs(opts, total_bytes);
-    assert_eq!(fs.fat_type(), fatfs::FatType::Fat32);
+    assert_eq!(fs.fat_type(), axfatfs::FatType::Fat32);
 }
 
 #[test]
 fn test_format_empty_volume_label() {
     let total_bytes = 2 * 1024 * MB;
-    let opts = fatfs::FormatVolumeOptions::new();
+    let opts = axfatfs::FormatVolumeOptions::new();
     let fs = test_format_fs(opts, total_bytes);
     assert_eq!(fs.volume_label(), "NO NAME");
     assert_eq!(fs.read_volume_label_from_root_dir().unwrap(), None);
@@ -131,7 +133,7 @@ fn test_format_empty_volume_label() {
 #[test]
 fn test_format_volume_label_and_id() {
     let total_bytes = 2 * 1024 * MB;
-    let opts = fatfs::FormatVolumeOptions::new()
+    let opts = axfatfs::FormatVolumeOptions::new()
         .volume_id(1234)
         .volume_label(*b"VOLUMELABEL");
     let fs = test_format_fs(opts, total_bytes);
@@ -147,7 +149,7 @@ fn test_format_volume_label_and_id() {
 fn test_zero_root_dir_clusters() {
     init_logger();
     let total_bytes = 33 * MB;
-    let opts = fatfs::FormatVolumeOptions::new().fat_type(FatType::Fat32);
+    let opts = axfatfs::FormatVolumeOptions::new().fat_type(FatType::Fat32);
     let fs = format_fs(opts, total_bytes);
     let root_dir = fs.root_dir();
 
@@ -158,3 +160,82 @@ fn test_zero_root_dir_clusters() {
     }
     assert_eq!(root_dir.iter().count(), files_to_create);
 }
+
+#[test]
+fn test_format_boot_code_and_reserved_sectors() {
+    init_logger();
+    // "jmp $" after a short jump over the BPB
+    let boot_code = [0xEB, 0xFE];
+    let loader = [0x5A_u8; 2048];
+    for (fat_type, total_bytes, code_offset, reserved_sectors, loader_sector) in
+        [(FatType::Fat12, MB, 0x3E, 5, 1), (FatType::Fat32, 33 * MB, 0x5A, 12, 8)]
+    {
+        let opts = axfatfs::FormatVolumeOptions::new()
+            .fat_type(fat_type)
+            .reserved_sectors(reserved_sectors)
+            .boot_code([0xEB, (code_offset - 2) as u8, 0x90], &boot_code);
+        let mut storage = axfatfs::StdIoWrapper::from(io::Cursor::new(vec![0_u8; total_bytes as usize]));
+        axfatfs::format_volume(&mut storage, opts).unwrap();
+        // store a second-stage loader in the reserved sectors
+        let mut disk = storage.into_inner();
+        disk.seek(io::SeekFrom::Start(loader_sector * 512)).unwrap();
+        disk.write_all(&loader).unwrap();
+        disk.seek(io::SeekFrom::Start(0)).unwrap();
+
+        let fs = axfatfs::FileSystem::new(disk, axfatfs::FsOptions::new()).unwrap();
+        assert_eq!(fs.bpb().reserved_sectors(), u32::from(reserved_sectors));
+        fs.root_dir()
+            .create_file("test.txt")
+            .unwrap()
+            .write_all(TEST_STR.as_bytes())
+            .unwrap();
+        assert!(fs.check().unwrap().is_clean());
+        let image = fs.into_inner().unwrap().into_inner().into_inner();
+        assert_eq!(image[0], 0xEB);
+        assert_eq!(image[1], (code_offset - 2) as u8);
+        assert_eq!(&image[code_offset..code_offset + boot_code.len()], &boot_code);
+        assert_eq!(image[code_offset + boot_code.len()], 0);
+        assert_eq!(&image[510..512], &[0x55, 0xAA]);
+        let loader_pos = loader_sector as usize * 512;
+        assert_eq!(&image[loader_pos..loader_pos + loader.len()], &loader);
+    }
+}
+
+#[test]
+fn test_format_invalid_boot_code() {
+    init_logger();
+    let format = |opts: axfatfs::FormatVolumeOptions| {
+        let mut storage = axfatfs::StdIoWrapper::from(io::Cursor::new(vec![0_u8; MB as usize]));
+        axfatfs::format_volume(&mut storage, opts.fat_type(FatType::Fat12))
+    };
+    // jump into the BPB
+    let opts = axfatfs::FormatVolumeOptions::new().boot_code([0xEB, 0x10, 0x90], &[0xF4]);
+    assert!(matches!(format(opts), Err(axfatfs::Error::InvalidInput)));
+    // not a jump
+    let opts = axfatfs::FormatVolumeOptions::new().boot_code([0x90, 0x90, 0x90], &[0xF4]);
+    assert!(matches!(format(opts), Err(axfatfs::Error::InvalidInput)));
+    // near jump into the boot code area
+    let opts = axfatfs::FormatVolumeOptions::new().boot_code([0xE9, 0x3B, 0x00], &[0xF4]);
+    assert!(format(opts).is_ok());
+    // jump over the boot signature
+    let opts = axfatfs::FormatVolumeOptions::new().boot_code([0xE9, 0xFB, 0x01], &[0xF4]);
+    assert!(matches!(format(opts), Err(axfatfs::Error::InvalidInput)));
+    // FAT32 boot code area is smaller
+    let mut storage = axfatfs::StdIoWrapper::from(io::Cursor::new(vec![0_u8; 33 * MB as usize]));
+    let opts = axfatfs::FormatVolumeOptions::new()
+        .fat_type(FatType::Fat32)
+        .boot_code([0xEB, 0x58, 0x90], &[0xF4; 448]);
+    assert!(matches!(
+        axfatfs::format_volume(&mut storage, opts),
+        Err(axfatfs::Error::InvalidInput)
+    ));
+    // too few reserved sectors
+    let mut storage = axfatfs::StdIoWrapper::from(io::Cursor::new(vec![0_u8; 33 * MB as usize]));
+    let opts = axfatfs::FormatVolumeOptions::new()
+        .fat_type(FatType::Fat32)
+        .reserved_sectors(4);
+    assert!(matches!(
+        axfatfs::format_volume(&mut storage, opts),
+        Err(axfatfs::Error::InvalidInput)
+    ));
+}
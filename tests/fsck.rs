@@ -1,28 +1,30 @@
-#![cfg(target_os = "linux")]
-use fatfs::Write;
+#![cfg(all(target_os = "linux", feature = "std"))]
+use axfatfs::Write;
 
 const KB: u32 = 1024;
 const MB: u32 = KB * 1024;
 
 #[test]
+#[ignore = "requires fsck.vfat from dosfstools, run with `cargo test --test fsck -- --ignored`"]
 fn test_fsck_1mb() {
     let _ = env_logger::builder().is_test(true).try_init();
 
-    let mut image = std::fs::OpenOptions::new()
+    let image = std::fs::OpenOptions::new()
         .write(true)
         .read(true)
         .create(true)
+        .truncate(true)
         .open("/tmp/test.img")
         .expect("open temporary image file");
     image.set_len(MB as u64).expect("set_len on temp file");
 
-    fatfs::format_volume(
-        &mut fatfs::StdIoWrapper::from(image.try_clone().expect("clone tempfile")),
-        fatfs::FormatVolumeOptions::new().total_sectors(MB / 512),
+    axfatfs::format_volume(
+        &mut axfatfs::StdIoWrapper::from(image.try_clone().expect("clone tempfile")),
+        axfatfs::FormatVolumeOptions::new().total_sectors(MB / 512),
     )
     .expect("format volume");
 
-    let fs = fatfs::FileSystem::new(image, fatfs::FsOptions::new()).expect("open fs");
+    let fs = axfatfs::FileSystem::new(image, axfatfs::FsOptions::new()).expect("open fs");
     fs.root_dir().create_dir("dir1").expect("create dir1");
     fs.root_dir()
         .create_file("root file.bin")
@@ -38,11 +40,10 @@ fn test_fsck_1mb() {
     core::mem::drop(dir2);
     core::mem::drop(fs);
 
-    let fsck_status = std::process::Command::new("fsck.vfat")
-        .args(&["-n", "/tmp/test.img"])
+    let mut fsck = std::process::Command::new("fsck.vfat")
+        .args(["-n", "/tmp/test.img"])
         .spawn()
-        .expect("spawn fsck")
-        .wait()
-        .expect("wait on fsck");
+        .expect("spawn fsck.vfat");
+    let fsck_status = fsck.wait().expect("wait on fsck");
     assert!(fsck_status.success(), "fsck was not successful ({fsck_status:?})");
 }
@@ -1,3 +1,5 @@
+#![cfg(feature = "std")]
+
 //! System tests: Integration tests for FAT filesystem library
 //!
 //! These tests validate the filesystem's behavior in realistic scenarios,
@@ -7,7 +9,7 @@ use std::fs;
 use std::io::{self, Read, Seek, Write};
 use std::str;
 
-use fatfs::{FsOptions, StdIoWrapper};
+use axfatfs::{FsOptions, StdIoWrapper};
 use fscommon::BufStream;
 
 const FAT12_IMG: &str = "fat12.img";
@@ -16,7 +18,7 @@ const FAT32_IMG: &str = "fat32.img";
 const IMG_DIR: &str = "resources";
 const TMP_DIR: &str = "tmp";
 
-type FileSystem = fatfs::FileSystem<StdIoWrapper<BufStream<fs::File>>>;
+type FileSystem = axfatfs::FileSystem<StdIoWrapper<BufStream<fs::File>>>;
 
 fn setup_tmp_img(filename: &str, test_seq: u32) -> String {
     let _ = env_logger::builder().is_test(true).try_init();
@@ -206,7 +208,7 @@ fn test_large_file_operations(filename: &str, test_seq: u32) {
 
     // Verify file size
     file.seek(io::SeekFrom::End(0)).unwrap();
-    let pos = file.seek(io::SeekFrom::Current(0)).unwrap();
+    let pos = file.stream_position().unwrap();
     assert_eq!(pos, large_content.len() as u64);
 
     // Read and verify content
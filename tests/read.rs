@@ -1,9 +1,14 @@
+#![cfg(feature = "std")]
+
 use std::fs;
 use std::io::prelude::*;
 use std::io::SeekFrom;
 use std::str;
 
-use fatfs::{FatType, FsOptions, StdIoWrapper};
+use axfatfs::{
+    ChangeReasons, CompareOptions, Date, DateTime, DefaultTimeProvider, Difference, FatType, FsOptions,
+    LossyOemCpConverter, StdIoWrapper, Time, MAX_FILE_NAME_UTF8_LEN, MAX_SHORT_FILE_NAME_UTF8_LEN,
+};
 use fscommon::BufStream;
 
 const TEST_TEXT: &str = "Rust is cool!\n";
@@ -11,13 +16,21 @@ const FAT12_IMG: &str = "resources/fat12.img";
 const FAT16_IMG: &str = "resources/fat16.img";
 const FAT32_IMG: &str = "resources/fat32.img";
 
-type FileSystem = fatfs::FileSystem<StdIoWrapper<BufStream<fs::File>>>;
+type FileSystem = axfatfs::FileSystem<StdIoWrapper<BufStream<fs::File>>>;
 
 fn call_with_fs<F: Fn(FileSystem)>(f: F, filename: &str) {
+    call_with_fs_options(f, filename, FsOptions::new());
+}
+
+fn call_with_fs_options<F: Fn(FileSystem)>(
+    f: F,
+    filename: &str,
+    options: FsOptions<DefaultTimeProvider, LossyOemCpConverter>,
+) {
     let _ = env_logger::builder().is_test(true).try_init();
     let file = fs::File::open(filename).unwrap();
     let buf_file = BufStream::new(file);
-    let fs = FileSystem::new(buf_file, FsOptions::new()).unwrap();
+    let fs = FileSystem::new(buf_file, options).unwrap();
     f(fs);
 }
 
@@ -48,6 +61,37 @@ fn test_root_dir_fat32() {
     call_with_fs(test_root_dir, FAT32_IMG)
 }
 
+fn test_file_name_to_buf(fs: FileSystem) {
+    let root_dir = fs.root_dir();
+    let mut buf = [0_u8; MAX_FILE_NAME_UTF8_LEN];
+    let mut short_buf = [0_u8; MAX_SHORT_FILE_NAME_UTF8_LEN];
+    for e in root_dir.iter().map(|r| r.unwrap()) {
+        assert_eq!(e.file_name_to_buf(&mut buf), Some(e.file_name().as_str()));
+        assert_eq!(
+            e.short_file_name_to_buf(&mut short_buf),
+            Some(e.short_file_name().as_str())
+        );
+    }
+    let e = root_dir.iter().map(|r| r.unwrap()).last().unwrap();
+    assert_eq!(e.file_name_to_buf(&mut buf[..18]), Some("very-long-dir-name"));
+    assert_eq!(e.file_name_to_buf(&mut buf[..17]), None);
+}
+
+#[test]
+fn test_file_name_to_buf_fat12() {
+    call_with_fs(test_file_name_to_buf, FAT12_IMG)
+}
+
+#[test]
+fn test_file_name_to_buf_fat16() {
+    call_with_fs(test_file_name_to_buf, FAT16_IMG)
+}
+
+#[test]
+fn test_file_name_to_buf_fat32() {
+    call_with_fs(test_file_name_to_buf, FAT32_IMG)
+}
+
 fn test_read_seek_short_file(fs: FileSystem) {
     let root_dir = fs.root_dir();
     let mut short_file = root_dir.open_file("short.txt").unwrap();
@@ -60,7 +104,7 @@ fn test_read_seek_short_file(fs: FileSystem) {
     short_file.read_exact(&mut buf2).unwrap();
     assert_eq!(str::from_utf8(&buf2).unwrap(), &TEST_TEXT[5..10]);
 
-    assert_eq!(short_file.seek(SeekFrom::Start(1000)).unwrap(), TEST_TEXT.len() as u64);
+    assert_eq!(short_file.seek(SeekFrom::Start(1000)).unwrap(), 1000);
     let mut buf2 = [0; 5];
     assert_eq!(short_file.read(&mut buf2).unwrap(), 0);
 }
@@ -109,6 +153,42 @@ fn test_read_long_file_fat32() {
     call_with_fs(test_read_long_file, FAT32_IMG)
 }
 
+fn test_read_at(fs: FileSystem) {
+    let root_dir = fs.root_dir();
+    let mut long_file = root_dir.open_file("long.txt").unwrap();
+    let expected = TEST_TEXT.repeat(1000);
+    long_file.seek(SeekFrom::Start(100)).unwrap();
+    let mut buf = [0; 4000];
+    assert_eq!(long_file.read_at(&mut buf, 2017).unwrap(), buf.len());
+    assert_eq!(str::from_utf8(&buf).unwrap(), &expected[2017..6017]);
+    // current position is not changed
+    assert_eq!(long_file.stream_position().unwrap(), 100);
+    let mut buf2 = [0; 10];
+    long_file.read_exact(&mut buf2).unwrap();
+    assert_eq!(str::from_utf8(&buf2).unwrap(), &expected[100..110]);
+    // reading near and after the end of file
+    let len = expected.len() as u64;
+    assert_eq!(long_file.read_at(&mut buf2, len - 4).unwrap(), 4);
+    assert_eq!(str::from_utf8(&buf2[..4]).unwrap(), &expected[expected.len() - 4..]);
+    assert_eq!(long_file.read_at(&mut buf2, len).unwrap(), 0);
+    assert_eq!(long_file.read_at(&mut buf2, len + 100).unwrap(), 0);
+}
+
+#[test]
+fn test_read_at_fat12() {
+    call_with_fs(test_read_at, FAT12_IMG)
+}
+
+#[test]
+fn test_read_at_fat16() {
+    call_with_fs(test_read_at, FAT16_IMG)
+}
+
+#[test]
+fn test_read_at_fat32() {
+    call_with_fs(test_read_at, FAT32_IMG)
+}
+
 fn test_get_dir_by_path(fs: FileSystem) {
     let root_dir = fs.root_dir();
     let dir = root_dir.open_dir("very/long/path/").unwrap();
@@ -185,6 +265,332 @@ fn test_get_file_by_path_fat32() {
     call_with_fs(test_get_file_by_path, FAT32_IMG)
 }
 
+fn test_open_by_short_name(fs: FileSystem) {
+    let root_dir = fs.root_dir();
+    let mut file = root_dir.open_file_short("VERY-L~1/very-l~1.txt").unwrap();
+    let mut buf = Vec::new();
+    file.read_to_end(&mut buf).unwrap();
+    assert_eq!(str::from_utf8(&buf).unwrap(), TEST_TEXT);
+    root_dir.open_file_short("VERY/LONG/PATH/TEST.TXT").unwrap();
+    let dir = root_dir.open_dir_short("VERY-L~1").unwrap();
+    dir.open_file_short("VERY-L~1.TXT").unwrap();
+
+    // long names are not matched
+    assert!(matches!(
+        root_dir.open_dir_short("very-long-dir-name"),
+        Err(axfatfs::Error::NotFound)
+    ));
+    assert!(matches!(
+        root_dir.open_file_short("VERY-L~1/very-long-file-name.txt"),
+        Err(axfatfs::Error::NotFound)
+    ));
+    // entry kind is checked
+    assert!(matches!(
+        root_dir.open_file_short("VERY-L~1"),
+        Err(axfatfs::Error::InvalidInput)
+    ));
+    assert!(matches!(
+        root_dir.open_dir_short("LONG.TXT"),
+        Err(axfatfs::Error::InvalidInput)
+    ));
+}
+
+#[test]
+fn test_open_by_short_name_fat12() {
+    call_with_fs(test_open_by_short_name, FAT12_IMG)
+}
+
+#[test]
+fn test_open_by_short_name_fat16() {
+    call_with_fs(test_open_by_short_name, FAT16_IMG)
+}
+
+#[test]
+fn test_open_by_short_name_fat32() {
+    call_with_fs(test_open_by_short_name, FAT32_IMG)
+}
+
+fn test_open_file_by_cluster(fs: FileSystem) {
+    let metadata = fs.metadata("long.txt").unwrap();
+    let first_cluster = metadata.first_cluster().unwrap();
+    let len = metadata.len() as u32;
+    let mut file = fs.open_file_by_cluster(first_cluster, len).unwrap();
+    let mut buf = Vec::new();
+    file.read_to_end(&mut buf).unwrap();
+    assert_eq!(str::from_utf8(&buf).unwrap(), TEST_TEXT.repeat(1000));
+    assert_eq!(file.metadata().unwrap().len(), u64::from(len));
+    assert_eq!(file.metadata().unwrap().first_cluster(), Some(first_cluster));
+
+    // reading stops at the given size
+    let mut file = fs.open_file_by_cluster(first_cluster, 20).unwrap();
+    let mut buf = Vec::new();
+    file.read_to_end(&mut buf).unwrap();
+    assert_eq!(str::from_utf8(&buf).unwrap(), &TEST_TEXT.repeat(2)[..20]);
+
+    // file without a directory entry cannot be written
+    assert!(matches!(file.write_at(b"x", 0), Err(axfatfs::Error::ReadOnlyFile)));
+    assert!(matches!(file.truncate(), Err(axfatfs::Error::ReadOnlyFile)));
+
+    assert!(matches!(
+        fs.open_file_by_cluster(1, 10),
+        Err(axfatfs::Error::InvalidInput)
+    ));
+    assert!(matches!(
+        fs.open_file_by_cluster(u32::MAX, 10),
+        Err(axfatfs::Error::InvalidInput)
+    ));
+}
+
+#[test]
+fn test_open_file_by_cluster_fat12() {
+    call_with_fs(test_open_file_by_cluster, FAT12_IMG)
+}
+
+#[test]
+fn test_open_file_by_cluster_fat16() {
+    call_with_fs(test_open_file_by_cluster, FAT16_IMG)
+}
+
+#[test]
+fn test_open_file_by_cluster_fat32() {
+    call_with_fs(test_open_file_by_cluster, FAT32_IMG)
+}
+
+fn test_fs_path_api(fs: FileSystem) {
+    let mut file = fs.open_file("/very/long/path/test.txt").unwrap();
+    let mut buf = Vec::new();
+    file.read_to_end(&mut buf).unwrap();
+    assert_eq!(str::from_utf8(&buf).unwrap(), TEST_TEXT);
+
+    let dir = fs.open_dir("very/long/path").unwrap();
+    let names = dir.iter().map(|r| r.unwrap().file_name()).collect::<Vec<String>>();
+    assert_eq!(names, [".", "..", "test.txt"]);
+
+    let metadata = fs.metadata("long.txt").unwrap();
+    assert!(metadata.is_file());
+    assert_eq!(metadata.len(), TEST_TEXT.len() as u64 * 1000);
+    let entry = fs.root_dir().iter().map(|r| r.unwrap()).next().unwrap();
+    assert_eq!(metadata.modified(), entry.modified());
+    assert_eq!(metadata.attributes(), entry.attributes());
+
+    assert!(fs.metadata("very/long").unwrap().is_dir());
+    assert!(fs.metadata("/").unwrap().is_dir());
+    assert!(fs.metadata("").unwrap().is_dir());
+    assert!(matches!(fs.metadata("very/missing"), Err(axfatfs::Error::NotFound)));
+    assert!(matches!(
+        fs.metadata("long.txt/test.txt"),
+        Err(axfatfs::Error::InvalidInput)
+    ));
+}
+
+#[test]
+fn test_fs_path_api_fat12() {
+    call_with_fs(test_fs_path_api, FAT12_IMG)
+}
+
+#[test]
+fn test_fs_path_api_fat16() {
+    call_with_fs(test_fs_path_api, FAT16_IMG)
+}
+
+#[test]
+fn test_fs_path_api_fat32() {
+    call_with_fs(test_fs_path_api, FAT32_IMG)
+}
+
+fn test_bpb(fs: FileSystem) {
+    let bpb = fs.bpb();
+    assert_eq!(bpb.bytes_per_sector(), 512);
+    assert_eq!(bpb.sectors_per_cluster(), 1);
+    assert_eq!(bpb.fats(), 2);
+    assert_eq!(bpb.media(), 0xF8);
+    assert_eq!(bpb.ext_sig(), 0x29);
+    assert_eq!(bpb.volume_label(), b"Test!      ");
+    assert_eq!(bpb.volume_id(), fs.volume_id());
+    assert_eq!(bpb.cluster_size(), fs.cluster_size());
+    assert_eq!(bpb.total_clusters(), fs.stats().unwrap().total_clusters());
+    assert!(bpb.mirroring_enabled());
+    assert_eq!(bpb.active_fat(), 0);
+    assert!(!bpb.status_flags().dirty());
+    let expected = match fs.fat_type() {
+        FatType::Fat12 => (b"FAT12   ", 512, 2000, 1, 6, 0),
+        FatType::Fat16 => (b"FAT16   ", 512, 5000, 1, 20, 0),
+        FatType::Fat32 => (b"FAT32   ", 0, 68000, 32, 0, 2),
+    };
+    let (fs_type_label, root_entries, total_sectors, reserved_sectors, sectors_per_fat_16, root_cluster) = expected;
+    assert_eq!(bpb.fs_type_label(), fs_type_label);
+    assert_eq!(bpb.root_entries(), root_entries);
+    assert_eq!(bpb.total_sectors(), total_sectors);
+    assert_eq!(bpb.reserved_sectors(), reserved_sectors);
+    assert_eq!(bpb.root_dir_first_cluster(), root_cluster);
+    if sectors_per_fat_16 != 0 {
+        assert_eq!(bpb.sectors_per_fat(), sectors_per_fat_16);
+        assert_eq!(bpb.root_dir_sectors(), 32);
+    } else {
+        assert_eq!(bpb.root_dir_sectors(), 0);
+        assert_eq!(bpb.fs_info_sector(), 1);
+    }
+    assert_eq!(
+        bpb.first_data_sector(),
+        bpb.reserved_sectors() + u32::from(bpb.fats()) * bpb.sectors_per_fat() + bpb.root_dir_sectors()
+    );
+}
+
+#[test]
+fn test_bpb_fat12() {
+    call_with_fs(test_bpb, FAT12_IMG)
+}
+
+#[test]
+fn test_bpb_fat16() {
+    call_with_fs(test_bpb, FAT16_IMG)
+}
+
+#[test]
+fn test_bpb_fat32() {
+    call_with_fs(test_bpb, FAT32_IMG)
+}
+
+fn test_path_normalization(fs: FileSystem) {
+    let root_dir = fs.root_dir();
+    let root_names = root_dir.iter().map(|r| r.unwrap().file_name()).collect::<Vec<String>>();
+    for path in [
+        "//very/./long//path/test.txt",
+        "very/../very/long/path/./test.txt",
+        "../very/long/path/test.txt",
+    ] {
+        let mut file = root_dir.open_file(path).unwrap();
+        let mut buf = Vec::new();
+        file.read_to_end(&mut buf).unwrap();
+        assert_eq!(str::from_utf8(&buf).unwrap(), TEST_TEXT);
+    }
+    for path in ["", "/", ".", "..", "very/..", "very/long/../../."] {
+        let dir = root_dir.open_dir(path).unwrap();
+        let names = dir.iter().map(|r| r.unwrap().file_name()).collect::<Vec<String>>();
+        assert_eq!(names, root_names);
+    }
+    // `..` components not cancelled lexically use on-disk entries
+    let dir = root_dir.open_dir("very/long/path").unwrap();
+    let names = dir
+        .open_dir("../..")
+        .unwrap()
+        .iter()
+        .map(|r| r.unwrap().file_name())
+        .collect::<Vec<String>>();
+    assert_eq!(names, [".", "..", "long"]);
+    assert!(fs.metadata("very/long/..").unwrap().is_dir());
+    assert!(fs.metadata("long.txt/..").unwrap().is_dir());
+}
+
+fn test_path_reject_root_escape(fs: FileSystem) {
+    let root_dir = fs.root_dir();
+    assert!(matches!(root_dir.open_dir(".."), Err(axfatfs::Error::InvalidInput)));
+    assert!(matches!(
+        root_dir.open_file("very/../../long.txt"),
+        Err(axfatfs::Error::InvalidInput)
+    ));
+    root_dir.open_file("very/../long.txt").unwrap();
+    // escaping above subdirectory is allowed
+    root_dir.open_dir("very/long").unwrap().open_dir("..").unwrap();
+}
+
+#[test]
+fn test_path_normalization_fat12() {
+    call_with_fs(test_path_normalization, FAT12_IMG)
+}
+
+#[test]
+fn test_path_normalization_fat16() {
+    call_with_fs(test_path_normalization, FAT16_IMG)
+}
+
+#[test]
+fn test_path_normalization_fat32() {
+    call_with_fs(test_path_normalization, FAT32_IMG)
+}
+
+#[test]
+fn test_path_reject_root_escape_fat12() {
+    call_with_fs_options(
+        test_path_reject_root_escape,
+        FAT12_IMG,
+        FsOptions::new().reject_root_escape(true),
+    )
+}
+
+#[test]
+fn test_path_reject_root_escape_fat16() {
+    call_with_fs_options(
+        test_path_reject_root_escape,
+        FAT16_IMG,
+        FsOptions::new().reject_root_escape(true),
+    )
+}
+
+#[test]
+fn test_path_reject_root_escape_fat32() {
+    call_with_fs_options(
+        test_path_reject_root_escape,
+        FAT32_IMG,
+        FsOptions::new().reject_root_escape(true),
+    )
+}
+
+fn test_metadata(fs: FileSystem) {
+    let cluster_size = u64::from(fs.cluster_size());
+    let root_dir = fs.root_dir();
+    let entry = root_dir.iter().map(|r| r.unwrap()).next().unwrap();
+    assert_eq!(entry.file_name(), "long.txt");
+    let entry_metadata = entry.metadata().unwrap();
+    let file_metadata = root_dir.open_file("long.txt").unwrap().metadata().unwrap();
+    for metadata in [&entry_metadata, &file_metadata] {
+        assert!(metadata.is_file());
+        assert_eq!(metadata.len(), entry.len());
+        assert_eq!(metadata.attributes(), entry.attributes());
+        assert_eq!(metadata.created(), entry.created());
+        assert_eq!(metadata.accessed(), entry.accessed());
+        assert_eq!(metadata.modified(), entry.modified());
+        assert!(metadata.first_cluster().is_some());
+        assert_eq!(
+            metadata.allocated_size(),
+            (metadata.len() + cluster_size - 1) / cluster_size * cluster_size
+        );
+    }
+
+    let dir_metadata = root_dir.open_dir("very").unwrap().metadata().unwrap();
+    assert!(dir_metadata.is_dir());
+    assert_eq!(dir_metadata.len(), 0);
+    assert_eq!(dir_metadata.allocated_size(), cluster_size);
+    assert_eq!(
+        dir_metadata.first_cluster(),
+        fs.metadata("very").unwrap().first_cluster()
+    );
+
+    let root_metadata = root_dir.metadata().unwrap();
+    assert!(root_metadata.is_dir());
+    assert!(root_metadata.allocated_size() > 0);
+    if fs.fat_type() == FatType::Fat32 {
+        assert!(root_metadata.first_cluster().is_some());
+    } else {
+        assert_eq!(root_metadata.first_cluster(), None);
+    }
+}
+
+#[test]
+fn test_metadata_fat12() {
+    call_with_fs(test_metadata, FAT12_IMG)
+}
+
+#[test]
+fn test_metadata_fat16() {
+    call_with_fs(test_metadata, FAT16_IMG)
+}
+
+#[test]
+fn test_metadata_fat32() {
+    call_with_fs(test_metadata, FAT32_IMG)
+}
+
 fn test_volume_metadata(fs: FileSystem, fat_type: FatType) {
     assert_eq!(fs.volume_id(), 0x1234_5678);
     assert_eq!(fs.volume_label(), "Test!");
@@ -290,3 +696,329 @@ fn test_multi_thread() {
         FAT32_IMG,
     )
 }
+
+fn test_find_and_glob(fs: FileSystem) {
+    let root_dir = fs.root_dir();
+    let names = |pattern| {
+        root_dir
+            .find(pattern)
+            .map(|r| r.unwrap().file_name())
+            .collect::<Vec<_>>()
+    };
+    assert_eq!(names("*.TXT"), ["long.txt", "short.txt"]);
+    assert_eq!(names("very*"), ["very", "very-long-dir-name"]);
+    assert_eq!(names("*.*"), ["long.txt", "short.txt", "very", "very-long-dir-name"]);
+    assert_eq!(names("*."), ["very", "very-long-dir-name"]);
+    assert_eq!(names("[ls]???.txt"), ["long.txt"]);
+    assert_eq!(names("VERY-L~1"), ["very-long-dir-name"]);
+    assert!(names("*.cfg").is_empty());
+
+    let paths = |pattern| {
+        fs.glob(pattern)
+            .unwrap()
+            .into_iter()
+            .map(|(path, _)| path)
+            .collect::<Vec<_>>()
+    };
+    assert_eq!(paths("very/**/*.txt"), ["very/long/path/test.txt"]);
+    assert_eq!(
+        paths("**/*.txt"),
+        [
+            "long.txt",
+            "short.txt",
+            "very/long/path/test.txt",
+            "very-long-dir-name/very-long-file-name.txt"
+        ]
+    );
+    assert_eq!(
+        paths("very/**"),
+        ["very/long", "very/long/path", "very/long/path/test.txt"]
+    );
+    assert_eq!(paths("*/*/path"), ["very/long/path"]);
+    assert_eq!(paths("/VERY/long/path/test.txt"), ["very/long/path/test.txt"]);
+    assert!(paths("very/*.txt").is_empty());
+    let matches = root_dir.open_dir("very").unwrap().glob("**/test.*").unwrap();
+    assert_eq!(matches.len(), 1);
+    assert_eq!(matches[0].0, "long/path/test.txt");
+    assert_eq!(matches[0].1.len(), TEST_TEXT.len() as u64);
+}
+
+#[test]
+fn test_find_and_glob_fat12() {
+    call_with_fs(test_find_and_glob, FAT12_IMG)
+}
+
+#[test]
+fn test_find_and_glob_fat16() {
+    call_with_fs(test_find_and_glob, FAT16_IMG)
+}
+
+#[test]
+fn test_find_and_glob_fat32() {
+    call_with_fs(test_find_and_glob, FAT32_IMG)
+}
+
+fn test_exists(fs: FileSystem) {
+    let root_dir = fs.root_dir();
+    assert!(fs.exists("long.txt"));
+    assert!(fs.exists("LONG.TXT"));
+    assert!(fs.exists("/very/long/path/test.txt"));
+    assert!(fs.exists("VERY-L~1/very-long-file-name.txt"));
+    assert!(fs.exists("/"));
+    assert!(!fs.exists("missing.txt"));
+    assert!(!fs.exists("very/missing/test.txt"));
+    assert!(!fs.exists("long.txt/abc"));
+    assert!(fs.try_exists("very/long").unwrap());
+    assert!(!fs.try_exists("very/missing").unwrap());
+    assert!(fs.try_exists("long.txt/abc").is_err());
+
+    assert!(fs.is_dir("very/long"));
+    assert!(fs.is_dir(""));
+    assert!(!fs.is_dir("long.txt"));
+    assert!(!fs.is_dir("missing"));
+    assert!(fs.is_file("short.txt"));
+    assert!(!fs.is_file("very"));
+    assert!(!fs.is_file("missing.txt"));
+
+    let dir = root_dir.open_dir("very/long").unwrap();
+    assert!(dir.exists("path/test.txt"));
+    assert!(dir.is_file("path/test.txt"));
+    assert!(dir.is_dir("path"));
+    assert!(dir.is_dir(".."));
+    assert!(!dir.exists("test.txt"));
+}
+
+#[test]
+fn test_exists_fat12() {
+    call_with_fs(test_exists, FAT12_IMG)
+}
+
+#[test]
+fn test_exists_fat16() {
+    call_with_fs(test_exists, FAT16_IMG)
+}
+
+#[test]
+fn test_exists_fat32() {
+    call_with_fs(test_exists, FAT32_IMG)
+}
+
+fn test_dir_entry_allocation(fs: FileSystem) {
+    let cluster_size = u64::from(fs.cluster_size());
+    for r in fs
+        .open_dir("very/long/path")
+        .unwrap()
+        .iter()
+        .chain(fs.root_dir().iter())
+    {
+        let e = r.unwrap();
+        let metadata = e.metadata().unwrap();
+        assert_eq!(e.first_cluster(), metadata.first_cluster());
+        assert_eq!(e.allocated_size().unwrap(), metadata.allocated_size());
+        assert_eq!(
+            e.allocated_size().unwrap(),
+            u64::from(e.cluster_count().unwrap()) * cluster_size
+        );
+        assert!(e.allocated_size().unwrap() >= e.len());
+        if e.file_name() == ".." {
+            assert_eq!(e.cluster_count().unwrap(), 1);
+        }
+    }
+    let e = fs
+        .root_dir()
+        .iter()
+        .map(|r| r.unwrap())
+        .find(|e| e.file_name() == "long.txt")
+        .unwrap();
+    assert_eq!(
+        e.cluster_count().unwrap() as u64,
+        (e.len() + cluster_size - 1) / cluster_size
+    );
+}
+
+#[test]
+fn test_dir_entry_allocation_fat12() {
+    call_with_fs(test_dir_entry_allocation, FAT12_IMG)
+}
+
+#[test]
+fn test_dir_entry_allocation_fat16() {
+    call_with_fs(test_dir_entry_allocation, FAT16_IMG)
+}
+
+#[test]
+fn test_dir_entry_allocation_fat32() {
+    call_with_fs(test_dir_entry_allocation, FAT32_IMG)
+}
+
+fn test_disk_usage(fs: FileSystem) {
+    let stats = fs.stats().unwrap();
+    let cluster_size = u64::from(stats.cluster_size());
+    let usage = fs.root_dir().disk_usage().unwrap();
+    // on a consistent volume all used clusters belong to the tree
+    assert_eq!(
+        usage.allocated_size(),
+        u64::from(stats.total_clusters() - stats.free_clusters()) * cluster_size
+    );
+    assert_eq!(usage.files(), 4);
+    assert_eq!(usage.dirs(), 4);
+    let file_size = [
+        "long.txt",
+        "short.txt",
+        "very/long/path/test.txt",
+        "very-long-dir-name/very-long-file-name.txt",
+    ]
+    .iter()
+    .map(|path| fs.metadata(path).unwrap().len())
+    .sum::<u64>();
+    assert_eq!(usage.file_size(), file_size);
+    assert!(usage.children().is_empty());
+
+    let breakdown = fs.root_dir().disk_usage_breakdown(1).unwrap();
+    assert_eq!(breakdown.allocated_size(), usage.allocated_size());
+    let names = breakdown
+        .children()
+        .iter()
+        .map(|(name, _)| name.as_str())
+        .collect::<Vec<_>>();
+    assert_eq!(names, ["very", "very-long-dir-name"]);
+    let very = &breakdown.children()[0].1;
+    assert_eq!((very.files(), very.dirs()), (1, 2));
+    assert_eq!(very.allocated_size(), 3 * cluster_size + cluster_size);
+    assert!(very.children().is_empty());
+    assert_eq!(*very, fs.open_dir("very").unwrap().disk_usage().unwrap());
+
+    let deep = fs.root_dir().disk_usage_breakdown(3).unwrap();
+    let path_usage = &deep.children()[0].1.children()[0].1.children()[0];
+    assert_eq!(path_usage.0, "path");
+    assert_eq!(path_usage.1.files(), 1);
+}
+
+#[test]
+fn test_disk_usage_fat12() {
+    call_with_fs(test_disk_usage, FAT12_IMG)
+}
+
+#[test]
+fn test_disk_usage_fat16() {
+    call_with_fs(test_disk_usage, FAT16_IMG)
+}
+
+#[test]
+fn test_disk_usage_fat32() {
+    call_with_fs(test_disk_usage, FAT32_IMG)
+}
+
+fn test_volatile(filename: &str) {
+    let original = fs::read(filename).unwrap();
+    let file = fs::File::open(filename).unwrap();
+    let fs = axfatfs::FileSystem::new_volatile(StdIoWrapper::new(file), FsOptions::new()).unwrap();
+    fs.write("volatile.txt", TEST_TEXT).unwrap();
+    fs.root_dir().remove("short.txt").unwrap();
+    fs.root_dir().create_dir("very/new-dir").unwrap();
+    assert_eq!(fs.read_to_string("volatile.txt").unwrap(), TEST_TEXT);
+    assert!(!fs.exists("short.txt"));
+    assert!(fs.check().unwrap().is_clean());
+    fs.unmount().unwrap();
+    // the image is only read
+    assert_eq!(fs::read(filename).unwrap(), original);
+    let file = fs::File::open(filename).unwrap();
+    let fs = axfatfs::FileSystem::new_volatile(StdIoWrapper::new(file), FsOptions::new()).unwrap();
+    assert!(!fs.exists("volatile.txt"));
+    assert_eq!(fs.read_to_string("short.txt").unwrap(), TEST_TEXT);
+}
+
+#[test]
+fn test_volatile_fat12() {
+    test_volatile(FAT12_IMG)
+}
+
+#[test]
+fn test_volatile_fat16() {
+    test_volatile(FAT16_IMG)
+}
+
+#[test]
+fn test_volatile_fat32() {
+    test_volatile(FAT32_IMG)
+}
+
+fn test_compare(filename: &str) {
+    let fs_a = FileSystem::new(BufStream::new(fs::File::open(filename).unwrap()), FsOptions::new()).unwrap();
+    let file = fs::File::open(filename).unwrap();
+    let fs_b = axfatfs::FileSystem::new_volatile(StdIoWrapper::new(file), FsOptions::new()).unwrap();
+    assert!(axfatfs::compare(&fs_a, &fs_b, CompareOptions::new())
+        .unwrap()
+        .is_empty());
+
+    let root_dir = fs_b.root_dir();
+    root_dir.remove("long.txt").unwrap();
+    let modified = root_dir.open_file("short.txt").unwrap().metadata().unwrap().modified();
+    let mut file = root_dir.create_file("short.txt").unwrap();
+    file.write_all(TEST_TEXT.to_uppercase().as_bytes()).unwrap();
+    file.set_modified(modified);
+    drop(file);
+    let date_time = DateTime::new(Date::new(2020, 1, 1), Time::new(12, 0, 0, 0));
+    root_dir
+        .open_file("very/long/path/test.txt")
+        .unwrap()
+        .set_modified(date_time);
+    root_dir.create_dir("very/new-dir").unwrap();
+    root_dir.remove("very-long-dir-name/very-long-file-name.txt").unwrap();
+    root_dir
+        .create_dir("very-long-dir-name/very-long-file-name.txt")
+        .unwrap();
+    fs_b.write("new.txt", TEST_TEXT).unwrap();
+
+    let differences = axfatfs::compare(&fs_a, &fs_b, CompareOptions::new()).unwrap();
+    let changed = |path: &str, reasons| Difference::Changed {
+        path: path.to_string(),
+        reasons,
+    };
+    assert_eq!(
+        differences,
+        [
+            Difference::Removed {
+                path: "long.txt".to_string(),
+                is_dir: false
+            },
+            changed("short.txt", ChangeReasons::CONTENT),
+            changed("very/long/path/test.txt", ChangeReasons::MODIFIED),
+            Difference::Added {
+                path: "very/new-dir".to_string(),
+                is_dir: true
+            },
+            changed("very-long-dir-name/very-long-file-name.txt", ChangeReasons::KIND),
+            Difference::Added {
+                path: "new.txt".to_string(),
+                is_dir: false
+            },
+        ]
+    );
+    let options = CompareOptions::new().compare_modified(false).compare_content(false);
+    let paths = fs_a
+        .root_dir()
+        .open_dir("very")
+        .unwrap()
+        .compare(&root_dir.open_dir("very").unwrap(), options)
+        .unwrap()
+        .iter()
+        .map(|d| d.path().to_string())
+        .collect::<Vec<_>>();
+    assert_eq!(paths, ["new-dir"]);
+}
+
+#[test]
+fn test_compare_fat12() {
+    test_compare(FAT12_IMG)
+}
+
+#[test]
+fn test_compare_fat16() {
+    test_compare(FAT16_IMG)
+}
+
+#[test]
+fn test_compare_fat32() {
+    test_compare(FAT32_IMG)
+}
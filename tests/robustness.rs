@@ -1,3 +1,5 @@
+#![cfg(feature = "std")]
+
 //! Functional tests: Error scenarios, boundary conditions, and filesystem robustness tests
 //!
 //! These tests simulate various abnormal situations and boundary conditions,
@@ -5,9 +7,10 @@
 
 use std::fs;
 use std::io::{self, Read, Seek, Write};
+use std::mem;
 use std::str;
 
-use fatfs::{FsOptions, StdIoWrapper};
+use axfatfs::{Date, FsOptions, StdIoWrapper, WritePolicy};
 use fscommon::BufStream;
 
 const FAT12_IMG: &str = "fat12.img";
@@ -17,7 +20,7 @@ const IMG_DIR: &str = "resources";
 const TMP_DIR: &str = "tmp";
 const TEST_STR: &str = "Hi there Rust programmer!\n";
 
-type FileSystem = fatfs::FileSystem<StdIoWrapper<BufStream<fs::File>>>;
+type FileSystem = axfatfs::FileSystem<StdIoWrapper<BufStream<fs::File>>>;
 
 fn call_with_tmp_img<F: Fn(&str)>(f: F, filename: &str, test_seq: u32) {
     let _ = env_logger::builder().is_test(true).try_init();
@@ -263,7 +266,7 @@ fn test_seek_to_end(fs: FileSystem) {
 
     // Seek to end
     file.seek(io::SeekFrom::End(0)).unwrap();
-    let pos = file.seek(io::SeekFrom::Current(0)).unwrap();
+    let pos = file.stream_position().unwrap();
     assert_eq!(pos, content.len() as u64);
 
     // Seek to beginning
@@ -419,8 +422,8 @@ fn test_deep_directory_nesting(fs: FileSystem) {
     file.flush().unwrap();
 
     // Reopen deep directory from root
-    let path = format!("level0/level1/level2/deep_file.txt");
-    let mut file = root_dir.open_file(&path).unwrap();
+    let path = "level0/level1/level2/deep_file.txt";
+    let mut file = root_dir.open_file(path).unwrap();
     let mut buf = Vec::new();
     file.read_to_end(&mut buf).unwrap();
     assert_eq!(str::from_utf8(&buf).unwrap(), "Deep content");
@@ -724,14 +727,14 @@ fn test_invalid_seek(fs: FileSystem) {
 
     // Test SeekFrom::Start(0)
     file.seek(io::SeekFrom::Start(0)).unwrap();
-    let pos = file.seek(io::SeekFrom::Current(0)).unwrap();
+    let pos = file.stream_position().unwrap();
     assert_eq!(pos, 0);
 
     // Test SeekFrom::End(0) - move to end of file
-    file.seek(io::SeekFrom::End(0)).unwrap();
-    let pos = file.seek(io::SeekFrom::Current(0)).unwrap();
+    let end = file.seek(io::SeekFrom::End(0)).unwrap();
+    let pos = file.stream_position().unwrap();
     // Position should be at end of file
-    assert!(pos >= 0);
+    assert_eq!(pos, end);
 }
 
 /// Test reading beyond file size
@@ -761,3 +764,201 @@ fn test_read_beyond_file(fs: FileSystem) {
     // Should return 0 (EOF)
     assert_eq!(bytes_read, 0);
 }
+
+// ============================================================================
+// Fault Injection Tests
+// ============================================================================
+
+type FaultyStorage = axfatfs::testing::FaultyStorage<StdIoWrapper<io::Cursor<Vec<u8>>>>;
+
+fn load_faulty_storage(filename: &str) -> FaultyStorage {
+    let _ = env_logger::builder().is_test(true).try_init();
+    let data = fs::read(format!("{}/{}", IMG_DIR, filename)).unwrap();
+    FaultyStorage::new(StdIoWrapper::new(io::Cursor::new(data)))
+}
+
+fn write_file_and_unmount(
+    storage: &FaultyStorage,
+) -> Result<(), axfatfs::Error<axfatfs::testing::FaultError<io::Error>>> {
+    let fs = axfatfs::FileSystem::new(storage.clone(), FsOptions::new())?;
+    let mut file = fs.root_dir().create_file("crash.txt")?;
+    for _ in 0..100 {
+        axfatfs::Write::write_all(&mut file, TEST_STR.as_bytes())?;
+    }
+    drop(file);
+    fs.unmount()
+}
+
+fn test_injected_write_failure(filename: &str) {
+    let storage = load_faulty_storage(filename);
+    storage.fail_nth_write(3);
+    let result = write_file_and_unmount(&storage);
+    assert!(matches!(
+        result,
+        Err(axfatfs::Error::Io(axfatfs::testing::FaultError::Injected))
+    ));
+    // Only a single write fails
+    write_file_and_unmount(&storage).unwrap();
+}
+
+#[test]
+fn test_injected_write_failure_fat12() {
+    test_injected_write_failure(FAT12_IMG)
+}
+
+#[test]
+fn test_injected_write_failure_fat32() {
+    test_injected_write_failure(FAT32_IMG)
+}
+
+fn test_power_cut_at_every_write(filename: &str, reorder_seed: Option<u64>) {
+    let reference = load_faulty_storage(filename);
+    write_file_and_unmount(&reference).unwrap();
+    let writes = reference.writes();
+    assert!(writes > 0);
+    assert!(reference.flushes() > 0);
+
+    for n in 1..=writes {
+        let storage = load_faulty_storage(filename);
+        storage.reorder_on_power_cut(reorder_seed);
+        storage.power_cut_after_writes(n);
+        let result = write_file_and_unmount(&storage);
+        assert_eq!(result.is_ok(), storage.is_powered());
+        storage.restore_power();
+        // The volume must stay mountable and checkable
+        let fs = axfatfs::FileSystem::new(storage.clone(), FsOptions::new()).unwrap();
+        fs.check().unwrap();
+        drop(fs);
+        assert!(storage.try_into_inner().is_ok());
+    }
+}
+
+#[test]
+fn test_power_cut_at_every_write_fat16() {
+    test_power_cut_at_every_write(FAT16_IMG, None)
+}
+
+#[test]
+fn test_power_cut_at_every_write_reordered_fat32() {
+    test_power_cut_at_every_write(FAT32_IMG, Some(5))
+}
+
+fn test_write_policy(filename: &str) {
+    let storage = load_faulty_storage(filename);
+    let options = FsOptions::new().write_policy(WritePolicy::WriteThrough);
+    let fs = axfatfs::FileSystem::new(storage.clone(), options).unwrap();
+    let mut file = fs.root_dir().create_file("policy.txt").unwrap();
+    let flushes = storage.flushes();
+    assert!(flushes > 0);
+    axfatfs::Write::write_all(&mut file, TEST_STR.as_bytes()).unwrap();
+    assert!(storage.flushes() > flushes);
+    // Data written through survives a power cut before the file is closed
+    storage.power_cut();
+    storage.restore_power();
+    mem::forget(file);
+    mem::forget(fs);
+    let fs = axfatfs::FileSystem::new(storage.clone(), FsOptions::new()).unwrap();
+    let mut file = fs.root_dir().open_file("policy.txt").unwrap();
+    let mut buf = [0_u8; TEST_STR.len()];
+    axfatfs::Read::read_exact(&mut file, &mut buf).unwrap();
+    assert_eq!(&buf, TEST_STR.as_bytes());
+    drop(file);
+    fs.unmount().unwrap();
+
+    // Flushing a file does not flush the storage until unmount
+    let options = FsOptions::new().write_policy(WritePolicy::OnUnmount);
+    let fs = axfatfs::FileSystem::new(storage.clone(), options).unwrap();
+    let flushes = storage.flushes();
+    let mut file = fs.root_dir().create_file("policy2.txt").unwrap();
+    axfatfs::Write::write_all(&mut file, TEST_STR.as_bytes()).unwrap();
+    axfatfs::Write::flush(&mut file).unwrap();
+    drop(file);
+    assert_eq!(storage.flushes(), flushes);
+    fs.unmount().unwrap();
+    assert!(storage.flushes() > flushes);
+}
+
+#[test]
+fn test_write_policy_fat12() {
+    test_write_policy(FAT12_IMG)
+}
+
+#[test]
+fn test_write_policy_fat32() {
+    test_write_policy(FAT32_IMG)
+}
+
+fn test_lazy_timestamps(filename: &str) {
+    let storage = load_faulty_storage(filename);
+    let options = FsOptions::new().update_accessed_date(true).lazy_timestamps(true);
+    let fs = axfatfs::FileSystem::new(storage.clone(), options).unwrap();
+    let writes = storage.writes();
+    let mut buf = [0_u8; 4];
+    for _ in 0..10 {
+        let mut file = fs.root_dir().open_file("short.txt").unwrap();
+        axfatfs::Read::read_exact(&mut file, &mut buf).unwrap();
+    }
+    let mut file = fs.root_dir().open_file("long.txt").unwrap();
+    file.set_accessed(Date::new(2002, 3, 4));
+    drop(file);
+    // Nothing is written until the filesystem is flushed
+    assert_eq!(storage.writes(), writes);
+    fs.unmount().unwrap();
+    assert!(storage.writes() > writes);
+
+    let fs = axfatfs::FileSystem::new(storage.clone(), FsOptions::new()).unwrap();
+    let accessed = |name: &str| {
+        fs.root_dir()
+            .iter()
+            .map(Result::unwrap)
+            .find(|e| e.file_name() == name)
+            .unwrap()
+            .accessed()
+    };
+    assert_eq!(accessed("long.txt"), Date::new(2002, 3, 4));
+    let today = axfatfs::TimeProvider::get_current_date(&axfatfs::DefaultTimeProvider::new());
+    assert_eq!(accessed("short.txt"), today);
+    assert!(fs.check().unwrap().is_clean());
+}
+
+#[test]
+fn test_lazy_timestamps_fat12() {
+    test_lazy_timestamps(FAT12_IMG)
+}
+
+#[test]
+fn test_lazy_timestamps_fat32() {
+    test_lazy_timestamps(FAT32_IMG)
+}
+
+fn test_contiguous_io(filename: &str) {
+    let storage = load_faulty_storage(filename);
+    let fs = axfatfs::FileSystem::new(storage.clone(), FsOptions::new()).unwrap();
+    let cluster_size = fs.cluster_size() as usize;
+    let data: Vec<u8> = (0..cluster_size * 16).map(|i| (i % 251) as u8).collect();
+    let mut file = fs.root_dir().create_file("contiguous.bin").unwrap();
+    axfatfs::Write::write_all(&mut file, &data).unwrap();
+    axfatfs::Seek::seek(&mut file, axfatfs::SeekFrom::Start(0)).unwrap();
+    // Every run of physically contiguous clusters is written using a single request
+    let runs = file.extents().map(Result::unwrap).count();
+    assert!(runs < 16);
+    let writes = storage.writes();
+    axfatfs::Write::write_all(&mut file, &data).unwrap();
+    assert_eq!(storage.writes(), writes + runs as u64);
+    axfatfs::Seek::seek(&mut file, axfatfs::SeekFrom::Start(100)).unwrap();
+    let mut buf = vec![0_u8; data.len() - 100];
+    axfatfs::Read::read_exact(&mut file, &mut buf).unwrap();
+    assert_eq!(buf, &data[100..]);
+    drop(file);
+    fs.unmount().unwrap();
+}
+
+#[test]
+fn test_contiguous_io_fat12() {
+    test_contiguous_io(FAT12_IMG)
+}
+
+#[test]
+fn test_contiguous_io_fat32() {
+    test_contiguous_io(FAT32_IMG)
+}
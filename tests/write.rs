@@ -1,10 +1,20 @@
+#![cfg(feature = "std")]
+
+use std::cell::Cell;
 use std::fs;
 use std::io;
 use std::io::prelude::*;
 use std::mem;
+use std::rc::Rc;
 use std::str;
+use std::sync::atomic::{AtomicU32, AtomicU64, Ordering};
+use std::sync::{Arc, Mutex};
 
-use fatfs::{FsOptions, StdIoWrapper};
+use axfatfs::{
+    ChangeReasons, CompareOptions, CopyOptions, Date, DateTime, Difference, DirOperation, DirtyVolumePolicy, EntryId,
+    FileAttributes, FsObserver, FsOptions, LfnChecksumPolicy, LongNamePolicy, PopulateOptions, SharedFileSystem,
+    ShortNameTailPolicy, StdIoWrapper, TableOemCpConverter, Time, TrailingDotsPolicy, ValidationLevel,
+};
 use fscommon::BufStream;
 
 const FAT12_IMG: &str = "fat12.img";
@@ -15,7 +25,7 @@ const TMP_DIR: &str = "tmp";
 const TEST_STR: &str = "Hi there Rust programmer!\n";
 const TEST_STR2: &str = "Rust is cool!\n";
 
-type FileSystem = fatfs::FileSystem<StdIoWrapper<BufStream<fs::File>>>;
+type FileSystem = axfatfs::FileSystem<StdIoWrapper<BufStream<fs::File>>>;
 
 fn call_with_tmp_img<F: Fn(&str)>(f: F, filename: &str, test_seq: u32) {
     let _ = env_logger::builder().is_test(true).try_init();
@@ -400,3 +410,3357 @@ fn test_multiple_files_in_directory_fat16() {
 fn test_multiple_files_in_directory_fat32() {
     call_with_fs(test_multiple_files_in_directory, FAT32_IMG, 8)
 }
+
+fn test_write_at(fs: FileSystem) {
+    let root_dir = fs.root_dir();
+    let mut file = root_dir.create_file("write-at.txt").unwrap();
+    file.write_all(TEST_STR.as_bytes()).unwrap();
+    let pos = file.stream_position().unwrap();
+    // overwrite inside the file
+    assert_eq!(file.write_at(b"Go", 0).unwrap(), 2);
+    assert_eq!(file.stream_position().unwrap(), pos);
+    // extend the file past the cluster boundary
+    let long_str = TEST_STR.repeat(1000);
+    assert_eq!(file.write_at(long_str.as_bytes(), pos).unwrap(), long_str.len());
+    assert_eq!(file.stream_position().unwrap(), pos);
+    // writing after the end of the file fills the gap with zeros
+    let len = pos + long_str.len() as u64;
+    assert_eq!(file.write_at(b"x", len + 1).unwrap(), 1);
+    file.write_all(b"!").unwrap();
+
+    let mut expected = String::from("Go") + &TEST_STR[2..] + &long_str + "\0x";
+    expected.replace_range(pos as usize..pos as usize + 1, "!");
+    let mut buf = Vec::new();
+    file.seek(io::SeekFrom::Start(0)).unwrap();
+    file.read_to_end(&mut buf).unwrap();
+    assert_eq!(str::from_utf8(&buf).unwrap(), expected);
+    assert_eq!(buf.len() as u64, len + 2);
+}
+
+#[test]
+fn test_write_at_fat12() {
+    call_with_fs(test_write_at, FAT12_IMG, 9)
+}
+
+#[test]
+fn test_write_at_fat16() {
+    call_with_fs(test_write_at, FAT16_IMG, 9)
+}
+
+#[test]
+fn test_write_at_fat32() {
+    call_with_fs(test_write_at, FAT32_IMG, 9)
+}
+
+fn test_seek_past_end(fs: FileSystem) {
+    let root_dir = fs.root_dir();
+    let cluster_size = u64::from(fs.cluster_size());
+    let mut file = root_dir.create_file("sparse.txt").unwrap();
+    file.write_all(TEST_STR.as_bytes()).unwrap();
+    // seeking does not extend the file and reading returns no data
+    let pos = cluster_size * 3 + 10;
+    assert_eq!(file.seek(io::SeekFrom::Start(pos)).unwrap(), pos);
+    assert_eq!(file.seek(io::SeekFrom::Current(-5)).unwrap(), pos - 5);
+    assert_eq!(file.seek(io::SeekFrom::Current(5)).unwrap(), pos);
+    let mut buf = [0_u8; 8];
+    assert_eq!(file.read(&mut buf).unwrap(), 0);
+    assert_eq!(file.write(&[]).unwrap(), 0);
+    assert_eq!(file.metadata().unwrap().len(), TEST_STR.len() as u64);
+    assert_eq!(file.seek(io::SeekFrom::End(0)).unwrap(), TEST_STR.len() as u64);
+    // writing fills the gap with zeros
+    file.seek(io::SeekFrom::End(pos as i64)).unwrap();
+    file.write_all(TEST_STR2.as_bytes()).unwrap();
+    let len = TEST_STR.len() as u64 + pos + TEST_STR2.len() as u64;
+    assert_eq!(file.stream_position().unwrap(), len);
+    // truncating beyond the end of the file extends it
+    file.seek(io::SeekFrom::Current(3)).unwrap();
+    file.truncate().unwrap();
+    drop(file);
+
+    let mut expected = TEST_STR.as_bytes().to_vec();
+    expected.resize(expected.len() + pos as usize, 0);
+    expected.extend_from_slice(TEST_STR2.as_bytes());
+    expected.extend_from_slice(&[0; 3]);
+    let mut file = root_dir.open_file("sparse.txt").unwrap();
+    let mut buf = Vec::new();
+    file.read_to_end(&mut buf).unwrap();
+    assert_eq!(buf, expected);
+    assert_eq!(file.metadata().unwrap().len(), len + 3);
+    assert!(fs.check().unwrap().is_clean());
+}
+
+#[test]
+fn test_seek_past_end_fat12() {
+    call_with_fs(test_seek_past_end, FAT12_IMG, 56)
+}
+
+#[test]
+fn test_seek_past_end_fat16() {
+    call_with_fs(test_seek_past_end, FAT16_IMG, 56)
+}
+
+#[test]
+fn test_seek_past_end_fat32() {
+    call_with_fs(test_seek_past_end, FAT32_IMG, 56)
+}
+
+fn test_fs_path_api(fs: FileSystem) {
+    fs.create_dir("/new-dir").unwrap();
+    fs.create_dir("new-dir/sub-dir").unwrap();
+    let mut file = fs.create_file("new-dir/sub-dir/file.txt").unwrap();
+    file.write_all(TEST_STR.as_bytes()).unwrap();
+    let file_metadata = file.metadata().unwrap();
+    assert_eq!(file_metadata.len(), TEST_STR.len() as u64);
+    assert_eq!(file_metadata.allocated_size(), u64::from(fs.cluster_size()));
+    drop(file);
+    let metadata = fs.metadata("new-dir/sub-dir/file.txt").unwrap();
+    assert_eq!(metadata.len(), TEST_STR.len() as u64);
+    assert_eq!(metadata.first_cluster(), file_metadata.first_cluster());
+
+    fs.rename("new-dir/sub-dir/file.txt", "new-dir/moved.txt").unwrap();
+    let mut buf = Vec::new();
+    fs.open_file("new-dir/moved.txt")
+        .unwrap()
+        .read_to_end(&mut buf)
+        .unwrap();
+    assert_eq!(str::from_utf8(&buf).unwrap(), TEST_STR);
+
+    assert!(fs.remove("new-dir").is_err());
+    fs.remove("new-dir/moved.txt").unwrap();
+    fs.remove("new-dir/sub-dir").unwrap();
+    fs.remove("new-dir").unwrap();
+    assert!(fs.metadata("new-dir").is_err());
+}
+
+#[test]
+fn test_fs_path_api_fat12() {
+    call_with_fs(test_fs_path_api, FAT12_IMG, 10)
+}
+
+#[test]
+fn test_fs_path_api_fat16() {
+    call_with_fs(test_fs_path_api, FAT16_IMG, 10)
+}
+
+#[test]
+fn test_fs_path_api_fat32() {
+    call_with_fs(test_fs_path_api, FAT32_IMG, 10)
+}
+
+fn test_set_attributes(fs: FileSystem) {
+    let root_dir = fs.root_dir();
+    {
+        let mut file = root_dir.open_file("short.txt").unwrap();
+        file.set_attributes(FileAttributes::HIDDEN | FileAttributes::SYSTEM | FileAttributes::DIRECTORY);
+    }
+    let find_entry = |name: &str| {
+        root_dir
+            .iter()
+            .map(|r| r.unwrap())
+            .find(|e| e.file_name() == name)
+            .unwrap()
+    };
+    let entry = find_entry("short.txt");
+    assert_eq!(entry.attributes(), FileAttributes::HIDDEN | FileAttributes::SYSTEM);
+    assert!(entry.is_file());
+
+    let mut entry = find_entry("very");
+    entry.set_attributes(FileAttributes::READ_ONLY).unwrap();
+    assert_eq!(
+        entry.attributes(),
+        FileAttributes::DIRECTORY | FileAttributes::READ_ONLY
+    );
+    let entry = find_entry("very");
+    assert_eq!(
+        entry.attributes(),
+        FileAttributes::DIRECTORY | FileAttributes::READ_ONLY
+    );
+    assert!(entry.is_dir());
+    root_dir.open_dir("very/long/path").unwrap();
+}
+
+#[test]
+fn test_set_attributes_fat12() {
+    call_with_fs(test_set_attributes, FAT12_IMG, 11)
+}
+
+#[test]
+fn test_set_attributes_fat16() {
+    call_with_fs(test_set_attributes, FAT16_IMG, 11)
+}
+
+#[test]
+fn test_set_attributes_fat32() {
+    call_with_fs(test_set_attributes, FAT32_IMG, 11)
+}
+
+fn test_read_only_attribute(tmp_path: &str) {
+    let file = fs::OpenOptions::new().read(true).write(true).open(tmp_path).unwrap();
+    let fs = FileSystem::new(BufStream::new(file), FsOptions::new().enforce_read_only(true)).unwrap();
+    let root_dir = fs.root_dir();
+    let mut entry = root_dir
+        .iter()
+        .map(|r| r.unwrap())
+        .find(|e| e.file_name() == "short.txt")
+        .unwrap();
+    entry.set_attributes(FileAttributes::READ_ONLY).unwrap();
+
+    let mut file = root_dir.open_file("short.txt").unwrap();
+    assert!(matches!(file.write(b"x"), Err(e) if e.kind() == io::ErrorKind::PermissionDenied));
+    assert!(matches!(file.truncate(), Err(axfatfs::Error::ReadOnlyFile)));
+    let mut buf = Vec::new();
+    file.read_to_end(&mut buf).unwrap();
+    assert_eq!(str::from_utf8(&buf).unwrap(), TEST_STR2);
+    assert!(matches!(
+        root_dir.remove("short.txt"),
+        Err(axfatfs::Error::ReadOnlyFile)
+    ));
+
+    // clearing the attribute makes the file writable again
+    file.set_attributes(FileAttributes::empty());
+    file.write_all(b"x").unwrap();
+    drop(file);
+    entry.set_attributes(FileAttributes::READ_ONLY).unwrap();
+    drop(root_dir);
+    fs.unmount().unwrap();
+
+    // the attribute is ignored by default
+    let fs = open_filesystem_rw(tmp_path);
+    let mut file = fs.root_dir().open_file("short.txt").unwrap();
+    file.write_all(b"x").unwrap();
+    drop(file);
+    fs.root_dir().remove("short.txt").unwrap();
+}
+
+#[test]
+fn test_read_only_attribute_fat12() {
+    call_with_tmp_img(test_read_only_attribute, FAT12_IMG, 12)
+}
+
+#[test]
+fn test_read_only_attribute_fat16() {
+    call_with_tmp_img(test_read_only_attribute, FAT16_IMG, 12)
+}
+
+#[test]
+fn test_read_only_attribute_fat32() {
+    call_with_tmp_img(test_read_only_attribute, FAT32_IMG, 12)
+}
+
+fn test_set_timestamps(fs: FileSystem) {
+    let root_dir = fs.root_dir();
+    let created = DateTime::new(Date::new(1999, 12, 31), Time::new(23, 59, 58, 990));
+    let modified = DateTime::new(Date::new(2001, 2, 3), Time::new(4, 5, 6, 0));
+    let accessed = Date::new(2002, 3, 4);
+    {
+        let mut file = root_dir.create_file("timestamps.txt").unwrap();
+        file.write_all(TEST_STR.as_bytes()).unwrap();
+        file.set_created(created);
+        file.set_modified(modified);
+        file.set_accessed(accessed);
+    }
+    let find_entry = |name: &str| {
+        root_dir
+            .iter()
+            .map(|r| r.unwrap())
+            .find(|e| e.file_name() == name)
+            .unwrap()
+    };
+    let entry = find_entry("timestamps.txt");
+    assert_eq!(entry.created(), created);
+    assert_eq!(entry.modified(), modified);
+    assert_eq!(entry.accessed(), accessed);
+
+    let mut entry = find_entry("very");
+    entry.set_created(modified).unwrap();
+    entry.set_modified(created).unwrap();
+    entry.set_accessed(accessed).unwrap();
+    let entry = find_entry("very");
+    assert_eq!(entry.created(), modified);
+    // modification time has 2s resolution
+    assert_eq!(
+        entry.modified(),
+        DateTime::new(Date::new(1999, 12, 31), Time::new(23, 59, 58, 0))
+    );
+    assert_eq!(entry.accessed(), accessed);
+}
+
+#[test]
+fn test_set_timestamps_fat12() {
+    call_with_fs(test_set_timestamps, FAT12_IMG, 13)
+}
+
+#[test]
+fn test_set_timestamps_fat16() {
+    call_with_fs(test_set_timestamps, FAT16_IMG, 13)
+}
+
+#[test]
+fn test_set_timestamps_fat32() {
+    call_with_fs(test_set_timestamps, FAT32_IMG, 13)
+}
+
+fn test_created_time_precision(tmp_path: &str) {
+    let created = DateTime::new(Date::new(2020, 5, 17), Time::new(13, 37, 41, 730));
+    {
+        let fs = open_filesystem_rw(tmp_path);
+        let mut file = fs.root_dir().create_file("precise.txt").unwrap();
+        file.set_created(created);
+        drop(file);
+        fs.unmount().unwrap();
+    }
+    let fs = open_filesystem_rw(tmp_path);
+    let entry = fs
+        .root_dir()
+        .iter()
+        .map(|r| r.unwrap())
+        .find(|e| e.file_name() == "precise.txt")
+        .unwrap();
+    assert_eq!(entry.created(), created);
+    assert_eq!(fs.metadata("precise.txt").unwrap().created(), created);
+}
+
+#[test]
+fn test_created_time_precision_fat12() {
+    call_with_tmp_img(test_created_time_precision, FAT12_IMG, 14)
+}
+
+#[test]
+fn test_created_time_precision_fat16() {
+    call_with_tmp_img(test_created_time_precision, FAT16_IMG, 14)
+}
+
+#[test]
+fn test_created_time_precision_fat32() {
+    call_with_tmp_img(test_created_time_precision, FAT32_IMG, 14)
+}
+
+fn test_set_volume_id(tmp_path: &str) {
+    {
+        let mut fs = open_filesystem_rw(tmp_path);
+        fs.set_volume_id(0xDEAD_BEEF).unwrap();
+        assert_eq!(fs.volume_id(), 0xDEAD_BEEF);
+        fs.unmount().unwrap();
+    }
+    let fs = open_filesystem_rw(tmp_path);
+    assert_eq!(fs.volume_id(), 0xDEAD_BEEF);
+}
+
+#[test]
+fn test_set_volume_id_fat12() {
+    call_with_tmp_img(test_set_volume_id, FAT12_IMG, 15)
+}
+
+#[test]
+fn test_set_volume_id_fat16() {
+    call_with_tmp_img(test_set_volume_id, FAT16_IMG, 15)
+}
+
+#[test]
+fn test_set_volume_id_fat32() {
+    call_with_tmp_img(test_set_volume_id, FAT32_IMG, 15)
+}
+
+fn test_recompute_fsinfo(tmp_path: &str) {
+    let free_clusters = {
+        let fs = open_filesystem_rw(tmp_path);
+        let mut file = fs.root_dir().create_file("big.bin").unwrap();
+        file.write_all(&[0xAB_u8; 10000]).unwrap();
+        drop(file);
+        fs.root_dir().remove("short.txt").unwrap();
+        let free_clusters = fs.stats().unwrap().free_clusters();
+        assert_eq!(fs.recompute_fsinfo().unwrap(), free_clusters);
+        fs.unmount().unwrap();
+        free_clusters
+    };
+    if tmp_path.ends_with(FAT32_IMG) {
+        // Store a wrong (but not obviously invalid) value in the FSInfo sector
+        let mut file = fs::OpenOptions::new().read(true).write(true).open(tmp_path).unwrap();
+        file.seek(io::SeekFrom::Start(512 + 488)).unwrap();
+        file.write_all(&1_u32.to_le_bytes()).unwrap();
+    }
+    let fs = open_filesystem_rw(tmp_path);
+    assert_eq!(fs.recompute_fsinfo().unwrap(), free_clusters);
+    assert_eq!(fs.stats().unwrap().free_clusters(), free_clusters);
+    fs.unmount().unwrap();
+    let fs = open_filesystem_rw(tmp_path);
+    assert_eq!(fs.stats().unwrap().free_clusters(), free_clusters);
+}
+
+#[test]
+fn test_recompute_fsinfo_fat12() {
+    call_with_tmp_img(test_recompute_fsinfo, FAT12_IMG, 16)
+}
+
+#[test]
+fn test_recompute_fsinfo_fat16() {
+    call_with_tmp_img(test_recompute_fsinfo, FAT16_IMG, 16)
+}
+
+#[test]
+fn test_recompute_fsinfo_fat32() {
+    call_with_tmp_img(test_recompute_fsinfo, FAT32_IMG, 16)
+}
+
+fn test_fs_info_options(tmp_path: &str) {
+    let read_fs_info = || {
+        let mut file = fs::File::open(tmp_path).unwrap();
+        file.seek(io::SeekFrom::Start(512 + 488)).unwrap();
+        let mut buf = [0_u8; 8];
+        file.read_exact(&mut buf).unwrap();
+        buf
+    };
+    let open_fs = |options| {
+        let file = fs::OpenOptions::new().read(true).write(true).open(tmp_path).unwrap();
+        FileSystem::new(BufStream::new(file), options).unwrap()
+    };
+    let free_clusters = {
+        let fs = open_fs(FsOptions::new());
+        fs.stats().unwrap().free_clusters()
+    };
+
+    // Store a wrong value in the FSInfo sector - it should not be used
+    {
+        let mut file = fs::OpenOptions::new().read(true).write(true).open(tmp_path).unwrap();
+        file.seek(io::SeekFrom::Start(512 + 488)).unwrap();
+        file.write_all(&1_u32.to_le_bytes()).unwrap();
+    }
+    {
+        let fs = open_fs(FsOptions::new().ignore_fs_info(true));
+        assert_eq!(fs.stats().unwrap().free_clusters(), free_clusters);
+    }
+
+    // Values are invalidated on disk instead of being updated
+    {
+        let fs = open_fs(FsOptions::new().update_fs_info(false));
+        fs.root_dir()
+            .create_file("new.txt")
+            .unwrap()
+            .write_all(TEST_STR.as_bytes())
+            .unwrap();
+        fs.unmount().unwrap();
+    }
+    assert_eq!(read_fs_info(), [0xFF_u8; 8]);
+    {
+        let fs = open_fs(FsOptions::new());
+        assert_eq!(fs.stats().unwrap().free_clusters(), free_clusters - 1);
+    }
+}
+
+#[test]
+fn test_fs_info_options_fat32() {
+    call_with_tmp_img(test_fs_info_options, FAT32_IMG, 17)
+}
+
+fn test_dirty_flag_cleared_on_flush(tmp_path: &str) {
+    let fs = open_filesystem_rw(tmp_path);
+    fs.root_dir()
+        .create_file("new.txt")
+        .unwrap()
+        .write_all(TEST_STR.as_bytes())
+        .unwrap();
+    // Volume is marked dirty on the first modification
+    assert!(fs.read_status_flags().unwrap().dirty());
+    fs.flush().unwrap();
+    assert!(!fs.read_status_flags().unwrap().dirty());
+    // Next modification marks it dirty again
+    fs.root_dir().remove("new.txt").unwrap();
+    assert!(fs.read_status_flags().unwrap().dirty());
+    fs.unmount().unwrap();
+    let fs = open_filesystem_rw(tmp_path);
+    assert!(!fs.read_status_flags().unwrap().dirty());
+}
+
+#[test]
+fn test_dirty_flag_cleared_on_flush_fat12() {
+    call_with_tmp_img(test_dirty_flag_cleared_on_flush, FAT12_IMG, 18)
+}
+
+#[test]
+fn test_dirty_flag_cleared_on_flush_fat16() {
+    call_with_tmp_img(test_dirty_flag_cleared_on_flush, FAT16_IMG, 18)
+}
+
+#[test]
+fn test_dirty_flag_cleared_on_flush_fat32() {
+    call_with_tmp_img(test_dirty_flag_cleared_on_flush, FAT32_IMG, 18)
+}
+
+fn test_dirty_volume_policy(tmp_path: &str) {
+    let open_fs = |policy| {
+        let file = fs::OpenOptions::new().read(true).write(true).open(tmp_path).unwrap();
+        FileSystem::new(BufStream::new(file), FsOptions::new().on_dirty(policy))
+    };
+    {
+        let fs = open_fs(DirtyVolumePolicy::Error).unwrap();
+        assert!(!fs.mount_status_flags().dirty());
+        assert!(!fs.is_read_only());
+        fs.root_dir().create_file("abc.txt").unwrap();
+        // Flags read on mount do not change
+        assert!(!fs.mount_status_flags().dirty());
+        mem::forget(fs);
+    }
+    assert!(matches!(
+        open_fs(DirtyVolumePolicy::Error),
+        Err(axfatfs::Error::DirtyVolume)
+    ));
+
+    let fs = open_fs(DirtyVolumePolicy::ReadOnly).unwrap();
+    assert!(fs.mount_status_flags().dirty());
+    assert!(fs.is_read_only());
+    let root_dir = fs.root_dir();
+    let mut file = root_dir.open_file("short.txt").unwrap();
+    let mut content = String::new();
+    file.read_to_string(&mut content).unwrap();
+    assert_eq!(content, TEST_STR2);
+    let err = file.write(b"x").unwrap_err();
+    assert_eq!(err.kind(), io::ErrorKind::PermissionDenied);
+    assert!(matches!(
+        root_dir.create_file("new.txt"),
+        Err(axfatfs::Error::ReadOnlyFilesystem)
+    ));
+    assert!(matches!(
+        root_dir.create_dir("new"),
+        Err(axfatfs::Error::ReadOnlyFilesystem)
+    ));
+    assert!(matches!(
+        root_dir.remove("abc.txt"),
+        Err(axfatfs::Error::ReadOnlyFilesystem)
+    ));
+    assert!(matches!(
+        root_dir.rename("abc.txt", &root_dir, "def.txt"),
+        Err(axfatfs::Error::ReadOnlyFilesystem)
+    ));
+    // Existing files can still be opened
+    root_dir.create_file("abc.txt").unwrap();
+
+    let fs = open_fs(DirtyVolumePolicy::Mount).unwrap();
+    assert!(fs.mount_status_flags().dirty());
+    assert!(!fs.is_read_only());
+}
+
+#[test]
+fn test_dirty_volume_policy_fat12() {
+    call_with_tmp_img(test_dirty_volume_policy, FAT12_IMG, 19)
+}
+
+#[test]
+fn test_dirty_volume_policy_fat16() {
+    call_with_tmp_img(test_dirty_volume_policy, FAT16_IMG, 19)
+}
+
+#[test]
+fn test_dirty_volume_policy_fat32() {
+    call_with_tmp_img(test_dirty_volume_policy, FAT32_IMG, 19)
+}
+
+fn test_undelete(fs: FileSystem) {
+    let root_dir = fs.root_dir();
+    let data = vec![b'z'; fs.cluster_size() as usize * 3 - 10];
+    root_dir.create_dir("sub").unwrap();
+    root_dir.create_file("recover.bin").unwrap().write_all(&data).unwrap();
+    root_dir.create_file("lost.bin").unwrap().write_all(&data).unwrap();
+    root_dir.remove("lost.bin").unwrap();
+    // Reuse clusters of lost.bin
+    root_dir.create_file("sub/new.bin").unwrap().write_all(b"new").unwrap();
+    root_dir.remove("recover.bin").unwrap();
+    let free_clusters = fs.stats().unwrap().free_clusters();
+
+    let deleted = root_dir
+        .deleted_entries()
+        .map(|r| r.unwrap())
+        .filter(|e| e.len() == data.len() as u64)
+        .collect::<Vec<_>>();
+    let recover_entry = deleted
+        .iter()
+        .find(|e| e.short_file_name_as_bytes()[1..].eq_ignore_ascii_case(b"ECOVER.BIN"))
+        .unwrap();
+    let lost_entry = deleted
+        .iter()
+        .find(|e| e.short_file_name_as_bytes()[1..].eq_ignore_ascii_case(b"OST.BIN"))
+        .unwrap();
+    assert!(root_dir.iter().all(|e| e.unwrap().file_name() != "recover.bin"));
+
+    assert!(matches!(
+        root_dir.undelete(recover_entry, "sub"),
+        Err(axfatfs::Error::AlreadyExists)
+    ));
+    assert!(matches!(
+        root_dir.undelete(lost_entry, "lost.bin"),
+        Err(axfatfs::Error::DataOverwritten)
+    ));
+    let restored = root_dir.undelete(recover_entry, "restored.bin").unwrap();
+    assert_eq!(restored.file_name(), "restored.bin");
+    assert_eq!(fs.stats().unwrap().free_clusters(), free_clusters - 3);
+    let mut buf = Vec::new();
+    root_dir
+        .open_file("restored.bin")
+        .unwrap()
+        .read_to_end(&mut buf)
+        .unwrap();
+    assert_eq!(buf, data);
+    // The same entry cannot be restored twice - its slot has been reused by the restored entry
+    assert!(matches!(
+        root_dir.undelete(recover_entry, "restored2.bin"),
+        Err(axfatfs::Error::NotFound)
+    ));
+    // Restored file can be removed again
+    root_dir.remove("restored.bin").unwrap();
+    assert_eq!(fs.stats().unwrap().free_clusters(), free_clusters);
+}
+
+#[test]
+fn test_undelete_fat12() {
+    call_with_fs(test_undelete, FAT12_IMG, 20)
+}
+
+#[test]
+fn test_undelete_fat16() {
+    call_with_fs(test_undelete, FAT16_IMG, 20)
+}
+
+#[test]
+fn test_undelete_fat32() {
+    call_with_fs(test_undelete, FAT32_IMG, 20)
+}
+
+// Storage returning errors for writes (and optionally reads) touching the given byte range
+struct FailingStorage {
+    inner: BufStream<fs::File>,
+    fail_range: Rc<Cell<(u64, u64)>>,
+    fail_reads: bool,
+    failures: Rc<Cell<u32>>,
+}
+
+impl Read for FailingStorage {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        let pos = self.inner.stream_position()?;
+        let (start, end) = self.fail_range.get();
+        if self.fail_reads && pos < end && pos + buf.len() as u64 > start {
+            self.failures.set(self.failures.get() + 1);
+            return Err(io::Error::new(io::ErrorKind::Other, "read failed"));
+        }
+        self.inner.read(buf)
+    }
+}
+
+impl Write for FailingStorage {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        let pos = self.inner.stream_position()?;
+        let (start, end) = self.fail_range.get();
+        if pos < end && pos + buf.len() as u64 > start {
+            self.failures.set(self.failures.get() + 1);
+            return Err(io::Error::new(io::ErrorKind::Other, "write failed"));
+        }
+        self.inner.write(buf)
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        self.inner.flush()
+    }
+}
+
+impl Seek for FailingStorage {
+    fn seek(&mut self, pos: io::SeekFrom) -> io::Result<u64> {
+        self.inner.seek(pos)
+    }
+}
+
+// Returns byte offset of a cluster computed from the boot sector
+fn cluster_offset(tmp_path: &str, cluster: u32) -> u64 {
+    let mut boot = [0_u8; 512];
+    fs::File::open(tmp_path).unwrap().read_exact(&mut boot).unwrap();
+    let read_u16 = |offset: usize| u64::from(u16::from_le_bytes([boot[offset], boot[offset + 1]]));
+    let bytes_per_sector = read_u16(11);
+    let sectors_per_cluster = u64::from(boot[13]);
+    let sectors_per_fat = match read_u16(22) {
+        0 => u64::from(u32::from_le_bytes([boot[36], boot[37], boot[38], boot[39]])),
+        n => n,
+    };
+    let root_dir_sectors = (read_u16(17) * 32 + bytes_per_sector - 1) / bytes_per_sector;
+    let first_data_sector = read_u16(14) + u64::from(boot[16]) * sectors_per_fat + root_dir_sectors;
+    (first_data_sector + u64::from(cluster - 2) * sectors_per_cluster) * bytes_per_sector
+}
+
+fn test_bad_clusters(tmp_path: &str) {
+    let fail_range = Rc::new(Cell::new((0, 0)));
+    let failures = Rc::new(Cell::new(0));
+    let storage = FailingStorage {
+        inner: BufStream::new(fs::OpenOptions::new().read(true).write(true).open(tmp_path).unwrap()),
+        fail_range: fail_range.clone(),
+        fail_reads: false,
+        failures: failures.clone(),
+    };
+    let options = FsOptions::new().mark_bad_after_write_failures(2);
+    let fs = axfatfs::FileSystem::new(StdIoWrapper::from(storage), options).unwrap();
+    let root_dir = fs.root_dir();
+
+    // Free cluster which will be allocated next
+    root_dir.create_file("probe.txt").unwrap().write_all(b"x").unwrap();
+    let cluster = fs.metadata("probe.txt").unwrap().first_cluster().unwrap();
+    root_dir.remove("probe.txt").unwrap();
+    let offset = cluster_offset(tmp_path, cluster);
+    fail_range.set((offset, offset + u64::from(fs.cluster_size())));
+
+    let free_clusters = fs.stats().unwrap().free_clusters();
+    let mut file = root_dir.create_file("data.txt").unwrap();
+    file.write_all(TEST_STR.as_bytes()).unwrap();
+    drop(file);
+    assert_eq!(failures.get(), 2);
+    assert!(fs.is_cluster_bad(cluster).unwrap());
+    assert_ne!(fs.metadata("data.txt").unwrap().first_cluster(), Some(cluster));
+    assert_eq!(fs.stats().unwrap().free_clusters(), free_clusters - 2);
+    let mut content = String::new();
+    root_dir
+        .open_file("data.txt")
+        .unwrap()
+        .read_to_string(&mut content)
+        .unwrap();
+    assert_eq!(content, TEST_STR);
+
+    // Used clusters cannot be marked as bad
+    let used_cluster = fs.metadata("data.txt").unwrap().first_cluster().unwrap();
+    assert!(matches!(
+        fs.mark_cluster_bad(used_cluster),
+        Err(axfatfs::Error::InvalidInput)
+    ));
+    assert!(!fs.is_cluster_bad(used_cluster).unwrap());
+    fs.mark_cluster_bad(cluster).unwrap();
+
+    // Marked free cluster is skipped by the allocator
+    root_dir.create_file("probe.txt").unwrap().write_all(b"x").unwrap();
+    let next_cluster = fs.metadata("probe.txt").unwrap().first_cluster().unwrap();
+    root_dir.remove("probe.txt").unwrap();
+    fs.mark_cluster_bad(next_cluster).unwrap();
+    root_dir.create_file("new.txt").unwrap().write_all(b"x").unwrap();
+    assert_ne!(fs.metadata("new.txt").unwrap().first_cluster(), Some(next_cluster));
+    let report = fs.check().unwrap();
+    assert!(report.is_clean(), "{:?}", report.problems());
+    assert_eq!(report.bad_clusters(), 2);
+
+    // A failing storage does not use up free clusters
+    let mut file = root_dir.create_file("failing.txt").unwrap();
+    fail_range.set((cluster_offset(tmp_path, 2), u64::MAX));
+    failures.set(0);
+    assert!(file.write(TEST_STR.as_bytes()).is_err());
+    assert_eq!(failures.get(), 6);
+    fail_range.set((0, 0));
+    drop(file);
+    assert_eq!(fs.check().unwrap().bad_clusters(), 4);
+}
+
+#[test]
+fn test_bad_clusters_fat12() {
+    call_with_tmp_img(test_bad_clusters, FAT12_IMG, 21)
+}
+
+#[test]
+fn test_bad_clusters_fat16() {
+    call_with_tmp_img(test_bad_clusters, FAT16_IMG, 21)
+}
+
+#[test]
+fn test_bad_clusters_fat32() {
+    call_with_tmp_img(test_bad_clusters, FAT32_IMG, 21)
+}
+
+// Returns byte offset and size of the first FAT
+fn fat_region(tmp_path: &str) -> (u64, u64) {
+    let mut boot = [0_u8; 512];
+    fs::File::open(tmp_path).unwrap().read_exact(&mut boot).unwrap();
+    let read_u16 = |offset: usize| u64::from(u16::from_le_bytes([boot[offset], boot[offset + 1]]));
+    let sectors_per_fat = match read_u16(22) {
+        0 => u64::from(u32::from_le_bytes([boot[36], boot[37], boot[38], boot[39]])),
+        n => n,
+    };
+    (read_u16(14) * read_u16(11), sectors_per_fat * read_u16(11))
+}
+
+fn patch_image(tmp_path: &str, offset: u64, data: &[u8]) {
+    let mut file = fs::OpenOptions::new().write(true).open(tmp_path).unwrap();
+    file.seek(io::SeekFrom::Start(offset)).unwrap();
+    file.write_all(data).unwrap();
+}
+
+fn test_fat_mirrors(tmp_path: &str) {
+    let (fat_offset, fat_size) = fat_region(tmp_path);
+    let read_fats = || {
+        let data = fs::read(tmp_path).unwrap();
+        let fat_start = fat_offset as usize;
+        let fat_end = fat_start + fat_size as usize;
+        (
+            data[fat_start..fat_end].to_vec(),
+            data[fat_end..fat_end + fat_size as usize].to_vec(),
+        )
+    };
+    {
+        let fs = open_filesystem_rw(tmp_path);
+        assert_eq!(fs.verify_fats().unwrap(), 0);
+        fs.unmount().unwrap();
+    }
+    let (fat, _) = read_fats();
+
+    // Damaged second copy is fixed by copying the first one
+    patch_image(tmp_path, fat_offset + fat_size + 100, &[0xAA; 4]);
+    {
+        let fs = open_filesystem_rw(tmp_path);
+        assert_eq!(fs.verify_fats().unwrap(), 1);
+        assert_eq!(fs.sync_fats().unwrap(), 1);
+        assert_eq!(fs.verify_fats().unwrap(), 0);
+        fs.unmount().unwrap();
+    }
+    assert_eq!(read_fats(), (fat.clone(), fat.clone()));
+
+    // First copy with an invalid media descriptor is replaced by the second one
+    patch_image(tmp_path, fat_offset, &[0x00]);
+    {
+        let file = fs::OpenOptions::new().read(true).write(true).open(tmp_path).unwrap();
+        let options = FsOptions::new().fat_mirror_fallback(true);
+        let fs = FileSystem::new(BufStream::new(file), options).unwrap();
+        assert_eq!(fs.verify_fats().unwrap(), 1);
+        assert_eq!(fs.sync_fats().unwrap(), 1);
+        fs.unmount().unwrap();
+    }
+    assert_eq!(read_fats(), (fat.clone(), fat));
+
+    // Reads fall back to the second copy on I/O errors
+    let open_failing_fs = |fallback: bool| {
+        let storage = FailingStorage {
+            inner: BufStream::new(fs::OpenOptions::new().read(true).write(true).open(tmp_path).unwrap()),
+            fail_range: Rc::new(Cell::new((fat_offset, fat_offset + fat_size))),
+            fail_reads: true,
+            failures: Rc::new(Cell::new(0)),
+        };
+        let options = FsOptions::new().fat_mirror_fallback(fallback);
+        axfatfs::FileSystem::new(StdIoWrapper::from(storage), options)
+    };
+    let fs = open_failing_fs(true).unwrap();
+    let mut content = String::new();
+    fs.root_dir()
+        .open_file("long.txt")
+        .unwrap()
+        .read_to_string(&mut content)
+        .unwrap();
+    assert_eq!(content.len(), 14000);
+    assert_eq!(fs.verify_fats().unwrap(), fat_size as u32 / 512);
+    drop(fs);
+    // Without the fallback FAT12 volume is mounted (FAT is not read on mount) but reading fails
+    if let Ok(fs) = open_failing_fs(false) {
+        let mut file = fs.root_dir().open_file("long.txt").unwrap();
+        assert!(file.read_to_string(&mut content).is_err());
+    }
+}
+
+#[test]
+fn test_fat_mirrors_fat12() {
+    call_with_tmp_img(test_fat_mirrors, FAT12_IMG, 22)
+}
+
+#[test]
+fn test_fat_mirrors_fat16() {
+    call_with_tmp_img(test_fat_mirrors, FAT16_IMG, 22)
+}
+
+#[test]
+fn test_fat_mirrors_fat32() {
+    call_with_tmp_img(test_fat_mirrors, FAT32_IMG, 22)
+}
+
+fn test_backup_boot_sector(tmp_path: &str) {
+    let open_fs = || {
+        let file = fs::OpenOptions::new().read(true).write(true).open(tmp_path).unwrap();
+        FileSystem::new(BufStream::new(file), FsOptions::new())
+    };
+    let boot_sectors = || {
+        let data = fs::read(tmp_path).unwrap();
+        (data[..512].to_vec(), data[6 * 512..7 * 512].to_vec())
+    };
+    let (boot, backup) = boot_sectors();
+    assert_eq!(boot, backup);
+
+    // Damage boot sector signature
+    patch_image(tmp_path, 510, &[0x00, 0x00]);
+    {
+        let fs = open_fs().unwrap();
+        assert!(fs.is_boot_sector_from_backup());
+        let mut content = String::new();
+        fs.root_dir()
+            .open_file("short.txt")
+            .unwrap()
+            .read_to_string(&mut content)
+            .unwrap();
+        assert_eq!(content, TEST_STR2);
+        fs.restore_boot_sector().unwrap();
+        fs.unmount().unwrap();
+    }
+    assert_eq!(boot_sectors(), (boot.clone(), backup));
+    let fs = open_fs().unwrap();
+    assert!(!fs.is_boot_sector_from_backup());
+    drop(fs);
+
+    // Both boot sectors damaged
+    patch_image(tmp_path, 510, &[0x00, 0x00]);
+    patch_image(tmp_path, 6 * 512 + 510, &[0x00, 0x00]);
+    assert!(matches!(open_fs(), Err(axfatfs::Error::CorruptedFileSystem)));
+}
+
+#[test]
+fn test_backup_boot_sector_fat32() {
+    call_with_tmp_img(test_backup_boot_sector, FAT32_IMG, 23)
+}
+
+fn test_no_backup_boot_sector(tmp_path: &str) {
+    {
+        let fs = open_filesystem_rw(tmp_path);
+        assert!(matches!(fs.restore_boot_sector(), Err(axfatfs::Error::InvalidInput)));
+    }
+    patch_image(tmp_path, 510, &[0x00, 0x00]);
+    let file = fs::OpenOptions::new().read(true).write(true).open(tmp_path).unwrap();
+    let result = FileSystem::new(BufStream::new(file), FsOptions::new());
+    assert!(matches!(result, Err(axfatfs::Error::CorruptedFileSystem)));
+}
+
+#[test]
+fn test_no_backup_boot_sector_fat16() {
+    call_with_tmp_img(test_no_backup_boot_sector, FAT16_IMG, 23)
+}
+
+fn test_remove_secure(tmp_path: &str) {
+    let contains = |needle: &[u8]| fs::read(tmp_path).unwrap().windows(needle.len()).any(|w| w == needle);
+    let pattern = b"SECRET-GUEST-DATA-PATTERN-0123456789".repeat(50);
+    let lfn_name: Vec<u8> = "secret-name.bin"
+        .encode_utf16()
+        .take(5)
+        .flat_map(u16::to_le_bytes)
+        .collect();
+    {
+        let fs = open_filesystem_rw(tmp_path);
+        fs.root_dir()
+            .create_file("secret-name.bin")
+            .unwrap()
+            .write_all(&pattern)
+            .unwrap();
+        fs.root_dir()
+            .create_file("kept.bin")
+            .unwrap()
+            .write_all(&pattern[..100])
+            .unwrap();
+        fs.unmount().unwrap();
+    }
+    assert!(contains(&pattern[..100]));
+    assert!(contains(&lfn_name));
+    {
+        let fs = open_filesystem_rw(tmp_path);
+        let free_clusters = fs.stats().unwrap().free_clusters();
+        fs.remove_secure("secret-name.bin").unwrap();
+        assert_eq!(fs.stats().unwrap().free_clusters(), free_clusters + 4);
+        // Plain remove leaves data on disk
+        fs.remove("kept.bin").unwrap();
+        let names: Vec<String> = fs.root_dir().iter().map(|r| r.unwrap().file_name()).collect();
+        assert!(!names.iter().any(|n| n == "secret-name.bin" || n == "kept.bin"));
+        assert!(matches!(
+            fs.remove_secure("secret-name.bin"),
+            Err(axfatfs::Error::NotFound)
+        ));
+        fs.unmount().unwrap();
+    }
+    assert!(contains(&pattern[..100]));
+    assert!(!contains(&pattern[100..]));
+    assert!(!contains(&lfn_name));
+}
+
+#[test]
+fn test_remove_secure_fat12() {
+    call_with_tmp_img(test_remove_secure, FAT12_IMG, 24)
+}
+
+#[test]
+fn test_remove_secure_fat16() {
+    call_with_tmp_img(test_remove_secure, FAT16_IMG, 24)
+}
+
+#[test]
+fn test_remove_secure_fat32() {
+    call_with_tmp_img(test_remove_secure, FAT32_IMG, 24)
+}
+
+fn test_wipe_free_space(tmp_path: &str) {
+    let contains = |needle: &[u8]| fs::read(tmp_path).unwrap().windows(needle.len()).any(|w| w == needle);
+    let pattern = b"LEAKED-GUEST-DATA-PATTERN-0123456789".repeat(50);
+    let lfn_name: Vec<u8> = "leaked-name.bin"
+        .encode_utf16()
+        .take(5)
+        .flat_map(u16::to_le_bytes)
+        .collect();
+    {
+        let fs = open_filesystem_rw(tmp_path);
+        fs.create_dir("subdir").unwrap();
+        fs.create_file("subdir/leaked-name.bin")
+            .unwrap()
+            .write_all(&pattern)
+            .unwrap();
+        fs.remove("subdir/leaked-name.bin").unwrap();
+        fs.unmount().unwrap();
+    }
+    assert!(contains(&pattern));
+    assert!(contains(&lfn_name));
+    {
+        let fs = open_filesystem_rw(tmp_path);
+        let free_clusters = fs.stats().unwrap().free_clusters();
+        assert_eq!(fs.wipe_free_space(false).unwrap(), free_clusters);
+        fs.unmount().unwrap();
+    }
+    assert!(!contains(&pattern[..100]));
+    assert!(contains(&lfn_name));
+    {
+        let fs = open_filesystem_rw(tmp_path);
+        fs.wipe_free_space(true).unwrap();
+        let mut content = String::new();
+        fs.root_dir()
+            .open_file("short.txt")
+            .unwrap()
+            .read_to_string(&mut content)
+            .unwrap();
+        assert_eq!(content, TEST_STR2);
+        assert_eq!(fs.root_dir().open_dir("subdir").unwrap().iter().count(), 2);
+        fs.unmount().unwrap();
+    }
+    assert!(!contains(&lfn_name));
+}
+
+#[test]
+fn test_wipe_free_space_fat12() {
+    call_with_tmp_img(test_wipe_free_space, FAT12_IMG, 25)
+}
+
+#[test]
+fn test_wipe_free_space_fat16() {
+    call_with_tmp_img(test_wipe_free_space, FAT16_IMG, 25)
+}
+
+#[test]
+fn test_wipe_free_space_fat32() {
+    call_with_tmp_img(test_wipe_free_space, FAT32_IMG, 25)
+}
+
+fn test_grow(tmp_path: &str, added_sectors: u32) {
+    let old_total_sectors = (fs::metadata(tmp_path).unwrap().len() / 512) as u32;
+    let total_sectors = old_total_sectors + added_sectors;
+    let (old_stats, long_txt) = {
+        let mut fs = open_filesystem_rw(tmp_path);
+        // Storage has not been enlarged yet
+        assert!(matches!(fs.grow(total_sectors), Err(axfatfs::Error::UnexpectedEof)));
+        assert!(matches!(
+            fs.grow(old_total_sectors - 1),
+            Err(axfatfs::Error::InvalidInput)
+        ));
+        let mut long_txt = Vec::new();
+        fs.root_dir()
+            .open_file("long.txt")
+            .unwrap()
+            .read_to_end(&mut long_txt)
+            .unwrap();
+        (fs.stats().unwrap(), long_txt)
+    };
+    fs::OpenOptions::new()
+        .write(true)
+        .open(tmp_path)
+        .unwrap()
+        .set_len(u64::from(total_sectors) * 512)
+        .unwrap();
+    {
+        let mut fs = open_filesystem_rw(tmp_path);
+        fs.grow(total_sectors).unwrap();
+        let stats = fs.stats().unwrap();
+        assert!(stats.total_clusters() > old_stats.total_clusters());
+        assert_eq!(
+            stats.free_clusters() - old_stats.free_clusters(),
+            stats.total_clusters() - old_stats.total_clusters()
+        );
+        fs.unmount().unwrap();
+    }
+    let fs = open_filesystem_rw(tmp_path);
+    let stats = fs.stats().unwrap();
+    let mut content = Vec::new();
+    fs.root_dir()
+        .open_file("long.txt")
+        .unwrap()
+        .read_to_end(&mut content)
+        .unwrap();
+    assert_eq!(content, long_txt);
+    // Fill all clusters including the added ones
+    let mut file = fs.root_dir().create_file("big.bin").unwrap();
+    let cluster = vec![0xAB_u8; stats.cluster_size() as usize];
+    for _ in 0..stats.free_clusters() {
+        file.write_all(&cluster).unwrap();
+    }
+    assert!(file.write_all(&cluster).is_err());
+    drop(file);
+    assert_eq!(fs.stats().unwrap().free_clusters(), 0);
+    let report = fs.check().unwrap();
+    assert!(report.is_clean(), "{:?}", report.problems());
+}
+
+#[test]
+fn test_grow_fat32() {
+    call_with_tmp_img(|tmp_path| test_grow(tmp_path, 2048), FAT32_IMG, 26)
+}
+
+#[test]
+fn test_grow_fat32_without_fat_extension() {
+    call_with_tmp_img(|tmp_path| test_grow(tmp_path, 10), FAT32_IMG, 27)
+}
+
+#[test]
+fn test_grow_fat16() {
+    call_with_tmp_img(
+        |tmp_path| {
+            let mut fs = open_filesystem_rw(tmp_path);
+            assert!(matches!(fs.grow(10000), Err(axfatfs::Error::InvalidInput)));
+        },
+        FAT16_IMG,
+        26,
+    )
+}
+
+fn test_tfat(tmp_path: &str, status_flags_offset: Option<usize>) {
+    let (fat_offset, fat_size) = fat_region(tmp_path);
+    let read_fats = || {
+        let data = fs::read(tmp_path).unwrap();
+        let fat_start = fat_offset as usize;
+        let fat_end = fat_start + fat_size as usize;
+        let mut fat0 = data[fat_start..fat_end].to_vec();
+        let mut fat1 = data[fat_end..fat_end + fat_size as usize].to_vec();
+        // Ignore the status flags which are written to all copies
+        if let Some(offset) = status_flags_offset {
+            fat0[offset] = 0;
+            fat1[offset] = 0;
+        }
+        (fat0, fat1)
+    };
+    let open_tfat = || {
+        let file = fs::OpenOptions::new().read(true).write(true).open(tmp_path).unwrap();
+        axfatfs::FileSystem::new(file, FsOptions::new().tfat(true)).unwrap()
+    };
+    let (committed, _) = read_fats();
+
+    // Interrupted session - changes are made only in the working copy
+    let fs = open_tfat();
+    fs.root_dir()
+        .create_file("tfat.txt")
+        .unwrap()
+        .write_all(TEST_STR.as_bytes())
+        .unwrap();
+    mem::forget(fs);
+    let (fat0, fat1) = read_fats();
+    assert_eq!(fat0, committed);
+    assert_ne!(fat1, committed);
+
+    // Uncommitted changes are rolled back on mount
+    let fs = open_tfat();
+    assert_eq!(read_fats(), (committed.clone(), committed.clone()));
+    fs.remove("tfat.txt").unwrap();
+    fs.unmount().unwrap();
+
+    // Changes are committed on unmount
+    let fs = open_tfat();
+    fs.root_dir()
+        .create_file("tfat.txt")
+        .unwrap()
+        .write_all(TEST_STR.as_bytes())
+        .unwrap();
+    fs.unmount().unwrap();
+    let (fat0, fat1) = read_fats();
+    assert_eq!(fat0, fat1);
+    assert_ne!(fat0, committed);
+    let fs = open_filesystem_rw(tmp_path);
+    assert_eq!(fs.verify_fats().unwrap(), 0);
+    let mut content = String::new();
+    fs.root_dir()
+        .open_file("tfat.txt")
+        .unwrap()
+        .read_to_string(&mut content)
+        .unwrap();
+    assert_eq!(content, TEST_STR);
+}
+
+#[test]
+fn test_tfat_fat12() {
+    call_with_tmp_img(|tmp_path| test_tfat(tmp_path, None), FAT12_IMG, 28)
+}
+
+#[test]
+fn test_tfat_fat16() {
+    call_with_tmp_img(|tmp_path| test_tfat(tmp_path, Some(3)), FAT16_IMG, 28)
+}
+
+#[test]
+fn test_tfat_fat32() {
+    call_with_tmp_img(|tmp_path| test_tfat(tmp_path, Some(7)), FAT32_IMG, 28)
+}
+
+#[test]
+fn test_fs_is_send_and_sync() {
+    fn assert_send_sync<T: Send + Sync>() {}
+    assert_send_sync::<FileSystem>();
+    type Storage = StdIoWrapper<BufStream<fs::File>>;
+    type TimeProvider = axfatfs::DefaultTimeProvider;
+    type OemCpConverter = axfatfs::LossyOemCpConverter;
+    assert_send_sync::<axfatfs::File<Storage, TimeProvider, OemCpConverter>>();
+    assert_send_sync::<axfatfs::Dir<Storage, TimeProvider, OemCpConverter>>();
+}
+
+fn test_concurrent_writes(fs: FileSystem) {
+    const THREADS: usize = 4;
+    const FILES: usize = 8;
+    let root_dir = fs.root_dir();
+    std::thread::scope(|s| {
+        for t in 0..THREADS {
+            let root_dir = &root_dir;
+            s.spawn(move || {
+                let dir = root_dir.create_dir(&format!("thread-{}", t)).unwrap();
+                for i in 0..FILES {
+                    let mut file = dir.create_file(&format!("file-{}.txt", i)).unwrap();
+                    // Interleave cluster allocations of all threads
+                    for _ in 0..20 {
+                        write!(file, "{} {} {}", t, i, TEST_STR).unwrap();
+                    }
+                    // Shared files in the root directory are created by all threads
+                    root_dir.create_file(&format!("shared-{}.txt", i)).unwrap();
+                }
+            });
+        }
+    });
+    for t in 0..THREADS {
+        for i in 0..FILES {
+            let mut file = root_dir.open_file(&format!("thread-{}/file-{}.txt", t, i)).unwrap();
+            let mut content = String::new();
+            file.read_to_string(&mut content).unwrap();
+            assert_eq!(content, format!("{} {} {}", t, i, TEST_STR).repeat(20));
+        }
+    }
+    let shared = root_dir
+        .iter()
+        .map(|r| r.unwrap().file_name())
+        .filter(|name| name.starts_with("shared-"))
+        .count();
+    assert_eq!(shared, FILES);
+    assert!(fs.check().unwrap().is_clean());
+}
+
+#[test]
+fn test_concurrent_writes_fat12() {
+    call_with_fs(test_concurrent_writes, FAT12_IMG, 29)
+}
+
+#[test]
+fn test_concurrent_writes_fat16() {
+    call_with_fs(test_concurrent_writes, FAT16_IMG, 29)
+}
+
+#[test]
+fn test_concurrent_writes_fat32() {
+    call_with_fs(test_concurrent_writes, FAT32_IMG, 29)
+}
+
+fn test_concurrent_create_remove(fs: FileSystem) {
+    const THREADS: usize = 4;
+    let root_dir = fs.root_dir();
+    let parent = root_dir.create_dir("parent").unwrap();
+    std::thread::scope(|s| {
+        for t in 0..THREADS {
+            let parent = &parent;
+            s.spawn(move || {
+                for i in 0..10 {
+                    let name = format!("dir-{}-{}", t, i);
+                    let dir = parent.create_dir(&name).unwrap();
+                    dir.create_file("file.txt")
+                        .unwrap()
+                        .write_all(TEST_STR.as_bytes())
+                        .unwrap();
+                    assert!(matches!(parent.remove(&name), Err(axfatfs::Error::DirectoryIsNotEmpty)));
+                    dir.remove("file.txt").unwrap();
+                    parent.remove(&name).unwrap();
+                }
+                parent.create_dir(&format!("kept-{}", t)).unwrap();
+            });
+        }
+    });
+    let names = parent
+        .iter()
+        .map(|r| r.unwrap().file_name())
+        .filter(|name| name != "." && name != "..")
+        .count();
+    assert_eq!(names, THREADS);
+    assert!(fs.check().unwrap().is_clean());
+}
+
+#[test]
+fn test_concurrent_create_remove_fat16() {
+    call_with_fs(test_concurrent_create_remove, FAT16_IMG, 30)
+}
+
+#[test]
+fn test_concurrent_create_remove_fat32() {
+    call_with_fs(test_concurrent_create_remove, FAT32_IMG, 30)
+}
+
+fn test_concurrent_reads(fs: FileSystem) {
+    const FILES: usize = 30;
+    let root_dir = fs.root_dir();
+    let long_name = |i: usize| format!("concurrently created file {}.txt", i);
+    std::thread::scope(|s| {
+        s.spawn(|| {
+            for i in 0..FILES {
+                let mut file = root_dir.create_file(&long_name(i)).unwrap();
+                file.write_all(TEST_STR.as_bytes()).unwrap();
+            }
+        });
+        for _ in 0..3 {
+            s.spawn(|| {
+                for _ in 0..FILES {
+                    // Long names of entries being written must never be seen partially
+                    for r in root_dir.iter() {
+                        let name = match r {
+                            // iteration is interrupted when an entry is added
+                            Err(axfatfs::Error::DirectoryModified) => break,
+                            r => r.unwrap().file_name(),
+                        };
+                        assert!(!name.contains('~'), "incomplete entry {}", name);
+                    }
+                    let mut content = String::new();
+                    root_dir
+                        .open_file("short.txt")
+                        .unwrap()
+                        .read_to_string(&mut content)
+                        .unwrap();
+                    assert_eq!(content, TEST_STR2);
+                }
+            });
+        }
+    });
+    for i in 0..FILES {
+        let mut content = String::new();
+        root_dir
+            .open_file(&long_name(i))
+            .unwrap()
+            .read_to_string(&mut content)
+            .unwrap();
+        assert_eq!(content, TEST_STR);
+    }
+}
+
+#[test]
+fn test_concurrent_reads_fat12() {
+    call_with_fs(test_concurrent_reads, FAT12_IMG, 31)
+}
+
+#[test]
+fn test_concurrent_reads_fat32() {
+    call_with_fs(test_concurrent_reads, FAT32_IMG, 31)
+}
+
+fn test_shared_file_system(fs: FileSystem) {
+    let fs = SharedFileSystem::new(fs);
+    let dir = fs.root_dir().create_dir("shared").unwrap();
+    let mut file = dir.create_file("shared.txt").unwrap();
+    // Handles are not bound to a borrow of the filesystem so they can be moved to any thread
+    let writer = std::thread::spawn(move || {
+        file.write_all(TEST_STR.as_bytes()).unwrap();
+        file
+    });
+    let mut file = writer.join().unwrap();
+    drop(fs);
+    file.seek(io::SeekFrom::Start(0)).unwrap();
+    let mut content = String::new();
+    file.read_to_string(&mut content).unwrap();
+    assert_eq!(content, TEST_STR);
+    drop(file);
+    let mut content = String::new();
+    dir.open_file("shared.txt")
+        .unwrap()
+        .read_to_string(&mut content)
+        .unwrap();
+    assert_eq!(content, TEST_STR);
+    assert!(dir.file_system().check().unwrap().is_clean());
+}
+
+#[test]
+fn test_shared_file_system_fat12() {
+    call_with_fs(test_shared_file_system, FAT12_IMG, 32)
+}
+
+#[test]
+fn test_shared_file_system_fat32() {
+    call_with_fs(test_shared_file_system, FAT32_IMG, 32)
+}
+
+fn test_into_inner(fs: FileSystem) {
+    let mut file = fs.root_dir().create_file("into_inner.txt").unwrap();
+    file.write_all(TEST_STR.as_bytes()).unwrap();
+    drop(file);
+    let storage = fs.into_inner().unwrap();
+    let fs = FileSystem::new(storage, FsOptions::new()).unwrap();
+    let mut content = String::new();
+    fs.root_dir()
+        .open_file("into_inner.txt")
+        .unwrap()
+        .read_to_string(&mut content)
+        .unwrap();
+    assert_eq!(content, TEST_STR);
+    assert!(fs.check().unwrap().is_clean());
+}
+
+#[test]
+fn test_into_inner_fat12() {
+    call_with_fs(test_into_inner, FAT12_IMG, 33)
+}
+
+#[test]
+fn test_into_inner_fat32() {
+    call_with_fs(test_into_inner, FAT32_IMG, 33)
+}
+
+fn test_seek_fragmented_file(fs: FileSystem) {
+    let cluster_size = fs.cluster_size() as usize;
+    let root_dir = fs.root_dir();
+    let mut file1 = root_dir.create_file("fragmented1.bin").unwrap();
+    let mut file2 = root_dir.create_file("fragmented2.bin").unwrap();
+    let data: Vec<u8> = (0..cluster_size * 12).map(|i| (i % 253) as u8).collect();
+    // Interleaved writes make cluster chains of both files fragmented
+    for chunk in data.chunks(cluster_size * 2) {
+        file1.write_all(chunk).unwrap();
+        file2.write_all(chunk).unwrap();
+    }
+    let mut buf = [0_u8; 10];
+    for &pos in &[
+        data.len() - 10,
+        5,
+        cluster_size * 7 + 3,
+        cluster_size * 2,
+        data.len() / 2,
+        0,
+    ] {
+        file1.seek(io::SeekFrom::Start(pos as u64)).unwrap();
+        file1.read_exact(&mut buf).unwrap();
+        assert_eq!(&buf, &data[pos..pos + 10]);
+    }
+    // Seeking after truncation uses the shortened chain
+    file1.seek(io::SeekFrom::Start((cluster_size * 3) as u64)).unwrap();
+    file1.truncate().unwrap();
+    file1.write_all(&data[..cluster_size * 2]).unwrap();
+    let pos = cluster_size * 4 + 1;
+    file1.seek(io::SeekFrom::Start(pos as u64)).unwrap();
+    file1.read_exact(&mut buf).unwrap();
+    assert_eq!(&buf, &data[pos - cluster_size * 3..pos - cluster_size * 3 + 10]);
+    // Truncation through another handle invalidates the cached chain, freed clusters can belong to other files
+    file2.flush().unwrap();
+    file2.seek(io::SeekFrom::Start((cluster_size * 7) as u64)).unwrap();
+    file2.read_exact(&mut buf).unwrap();
+    let mut file2_other = root_dir.open_file("fragmented2.bin").unwrap();
+    file2_other.seek(io::SeekFrom::Start(cluster_size as u64)).unwrap();
+    file2_other.truncate().unwrap();
+    drop(file2_other);
+    root_dir
+        .create_file("other.bin")
+        .unwrap()
+        .write_all(&vec![0xAA; data.len()])
+        .unwrap();
+    let pos = file2.seek(io::SeekFrom::Start((cluster_size * 5) as u64)).unwrap();
+    assert_eq!(pos, cluster_size as u64);
+    drop(file1);
+    drop(file2);
+    assert!(fs.check().unwrap().is_clean());
+}
+
+#[test]
+fn test_seek_fragmented_file_fat12() {
+    call_with_fs(test_seek_fragmented_file, FAT12_IMG, 34)
+}
+
+#[test]
+fn test_seek_fragmented_file_fat32() {
+    call_with_fs(test_seek_fragmented_file, FAT32_IMG, 34)
+}
+
+#[cfg(feature = "chrono")]
+fn test_populate_from(fs: FileSystem) {
+    let host_dir = format!("{}/populate-{:?}", TMP_DIR, fs.fat_type());
+    fs::create_dir_all(format!("{}/boot/grub", host_dir)).unwrap();
+    fs::write(format!("{}/boot/kernel.bin", host_dir), TEST_STR).unwrap();
+    fs::write(format!("{}/boot/grub/grub.cfg", host_dir), TEST_STR2).unwrap();
+    fs::write(format!("{}/short.txt", host_dir), TEST_STR2).unwrap();
+    let host_modified = fs::metadata(format!("{}/boot/kernel.bin", host_dir))
+        .unwrap()
+        .modified()
+        .unwrap();
+    let host_modified = DateTime::from(chrono::DateTime::<chrono::Local>::from(host_modified).naive_local());
+    let mut permissions = fs::metadata(format!("{}/short.txt", host_dir)).unwrap().permissions();
+    permissions.set_readonly(true);
+    fs::set_permissions(format!("{}/short.txt", host_dir), permissions).unwrap();
+
+    fs.populate_from(&host_dir, PopulateOptions::new().preserve_attributes(true))
+        .unwrap();
+    fs::remove_dir_all(&host_dir).unwrap();
+
+    let mut content = String::new();
+    fs.open_file("boot/grub/grub.cfg")
+        .unwrap()
+        .read_to_string(&mut content)
+        .unwrap();
+    assert_eq!(content, TEST_STR2);
+    // existing file is overwritten
+    content.clear();
+    let mut file = fs.open_file("short.txt").unwrap();
+    file.read_to_string(&mut content).unwrap();
+    assert_eq!(content, TEST_STR2);
+    assert!(file
+        .metadata()
+        .unwrap()
+        .attributes()
+        .contains(FileAttributes::READ_ONLY));
+    let kernel = fs
+        .open_dir("boot")
+        .unwrap()
+        .iter()
+        .map(|r| r.unwrap())
+        .find(|e| e.file_name() == "kernel.bin")
+        .unwrap();
+    assert_eq!(kernel.len(), TEST_STR.len() as u64);
+    assert_eq!(kernel.modified().date, host_modified.date);
+    assert_eq!(kernel.modified().time.hour, host_modified.time.hour);
+    assert_eq!(kernel.modified().time.min, host_modified.time.min);
+    assert_eq!(kernel.modified().time.sec, host_modified.time.sec / 2 * 2);
+    assert!(fs.check().unwrap().is_clean());
+}
+
+#[test]
+#[cfg(feature = "chrono")]
+fn test_populate_from_fat12() {
+    call_with_fs(test_populate_from, FAT12_IMG, 35)
+}
+
+#[test]
+#[cfg(feature = "chrono")]
+fn test_populate_from_fat32() {
+    call_with_fs(test_populate_from, FAT32_IMG, 35)
+}
+
+#[cfg(feature = "chrono")]
+fn test_export_tar(fs: FileSystem) {
+    let long_name = "a-very-long-directory-name-which-does-not-fit-in-the-ustar-header-even-with-the-prefix-field";
+    let long_dir = fs.create_dir(long_name).unwrap().create_dir(long_name).unwrap();
+    long_dir
+        .create_file("file.txt")
+        .unwrap()
+        .write_all(TEST_STR.as_bytes())
+        .unwrap();
+    let mut file = fs.root_dir().open_file("short.txt").unwrap();
+    let modified = DateTime::new(Date::new(2017, 9, 24), Time::new(19, 59, 4, 0));
+    file.set_modified(modified);
+    file.set_attributes(FileAttributes::READ_ONLY);
+    drop(file);
+
+    let mut archive_data = Vec::new();
+    fs.root_dir().export_tar(&mut archive_data).unwrap();
+    assert_eq!(archive_data.len() % 512, 0);
+
+    let mut archive = tar::Archive::new(archive_data.as_slice());
+    let mut entries = Vec::new();
+    for r in archive.entries().unwrap() {
+        let mut entry = r.unwrap();
+        let path = entry.path().unwrap().to_str().unwrap().to_owned();
+        let mut content = String::new();
+        entry.read_to_string(&mut content).unwrap();
+        if path == "short.txt" {
+            assert_eq!(entry.header().mode().unwrap(), 0o444);
+            let mtime = chrono::NaiveDateTime::from(modified)
+                .and_local_timezone(chrono::Local)
+                .unwrap()
+                .timestamp();
+            assert_eq!(entry.header().mtime().unwrap(), mtime as u64);
+        }
+        entries.push((path, entry.header().entry_type().is_dir(), content));
+    }
+    let long_path = format!("{}/{}/file.txt", long_name, long_name);
+    assert!(entries.contains(&("short.txt".to_owned(), false, TEST_STR2.to_owned())));
+    assert!(entries.contains(&("very/long/path/test.txt".to_owned(), false, TEST_STR2.to_owned())));
+    assert!(entries.contains(&("very/long/path/".to_owned(), true, String::new())));
+    assert!(entries.contains(&(long_path, false, TEST_STR.to_owned())));
+    assert!(!entries.iter().any(|(path, ..)| path.ends_with('.')));
+}
+
+#[test]
+#[cfg(feature = "chrono")]
+fn test_export_tar_fat12() {
+    call_with_fs(test_export_tar, FAT12_IMG, 36)
+}
+
+#[test]
+#[cfg(feature = "chrono")]
+fn test_export_tar_fat32() {
+    call_with_fs(test_export_tar, FAT32_IMG, 36)
+}
+
+fn test_validation_levels(tmp_path: &str) {
+    let open_fs = |validation| {
+        let file = fs::OpenOptions::new().read(true).write(true).open(tmp_path).unwrap();
+        FileSystem::new(BufStream::new(file), FsOptions::new().validation(validation))
+    };
+    let read_short = |fs: &FileSystem| {
+        let mut content = String::new();
+        fs.root_dir()
+            .open_file("short.txt")
+            .unwrap()
+            .read_to_string(&mut content)
+            .unwrap();
+        content
+    };
+    // Bogus drive number
+    patch_image(tmp_path, 0x24, &[0x42]);
+    assert!(open_fs(ValidationLevel::Normal).is_ok());
+    assert!(matches!(
+        open_fs(ValidationLevel::Strict),
+        Err(axfatfs::Error::CorruptedFileSystem)
+    ));
+    patch_image(tmp_path, 0x24, &[0x80]);
+    assert!(open_fs(ValidationLevel::Strict).is_ok());
+
+    // Volume one sector bigger than the storage
+    let len = fs::metadata(tmp_path).unwrap().len();
+    fs::OpenOptions::new()
+        .write(true)
+        .open(tmp_path)
+        .unwrap()
+        .set_len(len - 512)
+        .unwrap();
+    assert!(matches!(
+        open_fs(ValidationLevel::Strict),
+        Err(axfatfs::Error::CorruptedFileSystem)
+    ));
+    assert_eq!(read_short(&open_fs(ValidationLevel::Normal).unwrap()), TEST_STR2);
+    {
+        let fs = open_fs(ValidationLevel::Lenient).unwrap();
+        assert_eq!(read_short(&fs), TEST_STR2);
+        fs.root_dir()
+            .create_file("new.txt")
+            .unwrap()
+            .write_all(TEST_STR.as_bytes())
+            .unwrap();
+        fs.unmount().unwrap();
+    }
+
+    // Missing boot sector signature
+    patch_image(tmp_path, 510, &[0x00, 0x00]);
+    assert!(matches!(
+        open_fs(ValidationLevel::Normal),
+        Err(axfatfs::Error::CorruptedFileSystem)
+    ));
+    let fs = open_fs(ValidationLevel::Lenient).unwrap();
+    assert_eq!(read_short(&fs), TEST_STR2);
+    let mut content = String::new();
+    fs.root_dir()
+        .open_file("new.txt")
+        .unwrap()
+        .read_to_string(&mut content)
+        .unwrap();
+    assert_eq!(content, TEST_STR);
+}
+
+#[test]
+fn test_validation_levels_fat16() {
+    call_with_tmp_img(test_validation_levels, FAT16_IMG, 37)
+}
+
+fn test_read_only_mount(tmp_path: &str) {
+    let image = fs::read(tmp_path).unwrap();
+    {
+        let file = fs::OpenOptions::new().read(true).write(true).open(tmp_path).unwrap();
+        let options = FsOptions::new().read_only(true).update_accessed_date(true);
+        let mut fs = FileSystem::new(BufStream::new(file), options).unwrap();
+        assert!(fs.is_read_only());
+        let is_read_only_err =
+            |r: Result<(), axfatfs::Error<io::Error>>| matches!(r, Err(axfatfs::Error::ReadOnlyFilesystem));
+        let root_dir = fs.root_dir();
+        let mut file = root_dir.open_file("short.txt").unwrap();
+        let mut content = String::new();
+        file.read_to_string(&mut content).unwrap();
+        assert_eq!(content, TEST_STR2);
+        assert_eq!(file.write(b"x").unwrap_err().kind(), io::ErrorKind::PermissionDenied);
+        assert!(is_read_only_err(file.truncate()));
+        file.set_modified(DateTime::new(Date::new(2020, 1, 1), Time::new(0, 0, 0, 0)));
+        assert!(is_read_only_err(axfatfs::Write::flush(&mut file)));
+        drop(file);
+        assert!(is_read_only_err(root_dir.create_file("new.txt").map(drop)));
+        assert!(is_read_only_err(root_dir.create_dir("new").map(drop)));
+        assert!(is_read_only_err(root_dir.remove("short.txt")));
+        assert!(is_read_only_err(root_dir.rename("short.txt", &root_dir, "new.txt")));
+        let mut entry = root_dir
+            .iter()
+            .map(Result::unwrap)
+            .find(|e| e.file_name() == "short.txt")
+            .unwrap();
+        assert!(is_read_only_err(entry.set_attributes(FileAttributes::HIDDEN)));
+        drop(root_dir);
+        assert!(is_read_only_err(fs.wipe_free_space(true).map(drop)));
+        assert!(is_read_only_err(fs.repair(axfatfs::RepairOptions::new()).map(drop)));
+        assert!(is_read_only_err(fs.set_volume_id(0x1234_5678)));
+        fs.recompute_fsinfo().unwrap();
+        fs.unmount().unwrap();
+    }
+    assert!(fs::read(tmp_path).unwrap() == image, "image was modified");
+}
+
+#[test]
+fn test_read_only_mount_fat16() {
+    call_with_tmp_img(test_read_only_mount, FAT16_IMG, 38)
+}
+
+#[test]
+fn test_read_only_mount_fat32() {
+    call_with_tmp_img(test_read_only_mount, FAT32_IMG, 38)
+}
+
+fn test_lfn_checksum_policy(tmp_path: &str) {
+    // Damage checksum in both LFN entries of "very-long-dir-name"
+    let image = fs::read(tmp_path).unwrap();
+    let sfn_pos = image.windows(11).position(|w| w == b"VERY-L~1   ").unwrap();
+    let lfn_checksum = image[sfn_pos - 32 + 13].wrapping_add(1);
+    patch_image(tmp_path, (sfn_pos - 32 + 13) as u64, &[lfn_checksum]);
+    patch_image(tmp_path, (sfn_pos - 64 + 13) as u64, &[lfn_checksum]);
+
+    let open_fs = |policy| {
+        let file = fs::OpenOptions::new().read(true).write(true).open(tmp_path).unwrap();
+        FileSystem::new(BufStream::new(file), FsOptions::new().lfn_checksum_policy(policy)).unwrap()
+    };
+    let root_names = |fs: &FileSystem| {
+        fs.root_dir()
+            .iter()
+            .map(|r| r.map(|e| e.file_name()))
+            .collect::<Result<Vec<_>, _>>()
+    };
+    let names = root_names(&open_fs(LfnChecksumPolicy::UseShortName)).unwrap();
+    assert_eq!(names, ["long.txt", "short.txt", "very", "VERY-L~1"]);
+    let names = root_names(&open_fs(LfnChecksumPolicy::UseLongName)).unwrap();
+    assert_eq!(names, ["long.txt", "short.txt", "very", "very-long-dir-name"]);
+    assert!(matches!(
+        root_names(&open_fs(LfnChecksumPolicy::Error)),
+        Err(axfatfs::Error::CorruptedFileSystem)
+    ));
+}
+
+#[test]
+fn test_lfn_checksum_policy_fat12() {
+    call_with_tmp_img(test_lfn_checksum_policy, FAT12_IMG, 39)
+}
+
+#[test]
+fn test_lfn_checksum_policy_fat32() {
+    call_with_tmp_img(test_lfn_checksum_policy, FAT32_IMG, 39)
+}
+
+fn test_long_name_policy(tmp_path: &str) {
+    let open_fs = |policy| {
+        let file = fs::OpenOptions::new().read(true).write(true).open(tmp_path).unwrap();
+        FileSystem::new(BufStream::new(file), FsOptions::new().long_name_policy(policy)).unwrap()
+    };
+    {
+        let fs = open_fs(LongNamePolicy::Shorten);
+        let root_dir = fs.root_dir();
+        root_dir.create_file("a long file name.txt").unwrap();
+        root_dir.create_file("readme.txt").unwrap();
+        root_dir.create_dir("Mixed.Dir").unwrap();
+        root_dir.rename("long.txt", &root_dir, "renamed long.txt").unwrap();
+        drop(root_dir);
+        fs.unmount().unwrap();
+    }
+    {
+        let fs = open_fs(LongNamePolicy::Create);
+        let entries = fs.root_dir().iter().map(Result::unwrap).collect::<Vec<_>>();
+        let names = entries.iter().map(axfatfs::DirEntry::file_name).collect::<Vec<_>>();
+        assert_eq!(
+            names,
+            [
+                "RENAME~1.TXT",
+                "short.txt",
+                "very",
+                "very-long-dir-name",
+                "ALONGF~1.TXT",
+                "readme.txt",
+                "MIXED.DIR"
+            ]
+        );
+        // new and renamed entries have no long names
+        assert!(entries[4..].iter().all(|e| e.long_file_name_as_ucs2_units().is_none()));
+        assert!(entries[0].long_file_name_as_ucs2_units().is_none());
+        assert_eq!(entries[5].short_file_name_as_bytes(), b"README.TXT");
+        assert!(fs.open_file("A long file name.txt").is_err());
+        fs.open_file("alongf~1.txt").unwrap();
+    }
+    let fs = open_fs(LongNamePolicy::Reject);
+    let root_dir = fs.root_dir();
+    assert!(matches!(
+        root_dir.create_file("a long file name.txt"),
+        Err(axfatfs::Error::InvalidFileNameLength)
+    ));
+    assert!(matches!(
+        root_dir.create_dir("a+b"),
+        Err(axfatfs::Error::UnsupportedFileNameCharacter)
+    ));
+    assert!(matches!(
+        root_dir.rename("short.txt", &root_dir, "longer name.txt"),
+        Err(axfatfs::Error::InvalidFileNameLength)
+    ));
+    // existing entries can be opened using their long names
+    root_dir.create_dir("very-long-dir-name").unwrap();
+    root_dir.create_file("new.txt").unwrap();
+}
+
+#[test]
+fn test_long_name_policy_fat16() {
+    call_with_tmp_img(test_long_name_policy, FAT16_IMG, 40)
+}
+
+#[test]
+fn test_long_name_policy_fat32() {
+    call_with_tmp_img(test_long_name_policy, FAT32_IMG, 40)
+}
+
+fn test_oem_cp_converter(tmp_path: &str) {
+    {
+        let fs = open_filesystem_rw(tmp_path);
+        fs.root_dir().create_file("CAFE.TXT").unwrap();
+        fs.unmount().unwrap();
+    }
+    // replace the last letter of the base name by 'É' in code page 850 like a localized system would
+    let image = fs::read(tmp_path).unwrap();
+    let offset = image.windows(11).position(|w| w == b"CAFE    TXT").unwrap();
+    patch_image(tmp_path, offset as u64 + 3, &[0x90]);
+    {
+        let fs = open_filesystem_rw(tmp_path);
+        let names: Vec<String> = fs.root_dir().iter().map(|e| e.unwrap().file_name()).collect();
+        assert!(names.contains(&String::from("CAF\u{FFFD}.TXT")));
+    }
+    let file = fs::OpenOptions::new().read(true).write(true).open(tmp_path).unwrap();
+    let options = FsOptions::new().oem_cp_converter(TableOemCpConverter::cp850());
+    let fs = axfatfs::FileSystem::new(BufStream::new(file), options).unwrap();
+    let root_dir = fs.root_dir();
+    let entry = root_dir
+        .iter()
+        .map(Result::unwrap)
+        .find(|e| e.file_name() == "CAFÉ.TXT");
+    assert_eq!(entry.unwrap().short_file_name_as_bytes(), b"CAF\x90.TXT");
+    // names differing in the case of non-ASCII characters only match with the unicode feature
+    #[cfg(feature = "unicode")]
+    root_dir.open_file("café.txt").unwrap();
+}
+
+#[test]
+fn test_oem_cp_converter_fat12() {
+    call_with_tmp_img(test_oem_cp_converter, FAT12_IMG, 41)
+}
+
+#[test]
+fn test_oem_cp_converter_fat16() {
+    call_with_tmp_img(test_oem_cp_converter, FAT16_IMG, 41)
+}
+
+#[test]
+fn test_oem_cp_converter_fat32() {
+    call_with_tmp_img(test_oem_cp_converter, FAT32_IMG, 41)
+}
+
+#[cfg(feature = "normalization")]
+fn test_normalize_names(tmp_path: &str) {
+    let open_fs = |normalize| {
+        let file = fs::OpenOptions::new().read(true).write(true).open(tmp_path).unwrap();
+        FileSystem::new(BufStream::new(file), FsOptions::new().normalize_names(normalize)).unwrap()
+    };
+    {
+        // decomposed name written without normalization
+        let fs = open_fs(false);
+        fs.root_dir().create_file("re\u{301}sume\u{301}.txt").unwrap();
+        assert!(fs.root_dir().open_file("r\u{E9}sum\u{E9}.txt").is_err());
+        fs.unmount().unwrap();
+    }
+    let fs = open_fs(true);
+    let root_dir = fs.root_dir();
+    root_dir.open_file("R\u{C9}SUM\u{C9}.TXT").unwrap();
+    root_dir.create_file("cafe\u{301}.txt").unwrap();
+    let names: Vec<String> = root_dir.iter().map(|e| e.unwrap().file_name()).collect();
+    assert!(names.contains(&String::from("caf\u{E9}.txt")));
+    assert!(names.contains(&String::from("re\u{301}sume\u{301}.txt")));
+    root_dir.open_file("caf\u{E9}.txt").unwrap();
+    root_dir.open_file("CAFE\u{301}.TXT").unwrap();
+}
+
+#[test]
+#[cfg(feature = "normalization")]
+fn test_normalize_names_fat16() {
+    call_with_tmp_img(test_normalize_names, FAT16_IMG, 42)
+}
+
+#[test]
+#[cfg(feature = "normalization")]
+fn test_normalize_names_fat32() {
+    call_with_tmp_img(test_normalize_names, FAT32_IMG, 42)
+}
+
+fn test_case_sensitive(tmp_path: &str) {
+    let file = fs::OpenOptions::new().read(true).write(true).open(tmp_path).unwrap();
+    let fs = FileSystem::new(BufStream::new(file), FsOptions::new().case_sensitive(true)).unwrap();
+    let root_dir = fs.root_dir();
+    assert!(root_dir.open_file("Long.txt").is_err());
+    root_dir.open_file("long.txt").unwrap();
+    // short name alias
+    root_dir.open_file("LONG.TXT").unwrap();
+    root_dir.open_file("short.txt").unwrap();
+    assert!(root_dir.open_file("Short.txt").is_err());
+    root_dir.create_file("Config.txt").unwrap().write_all(b"upper").unwrap();
+    root_dir.create_file("config.txt").unwrap().write_all(b"lower").unwrap();
+    let mut content = String::new();
+    root_dir
+        .open_file("Config.txt")
+        .unwrap()
+        .read_to_string(&mut content)
+        .unwrap();
+    assert_eq!(content, "upper");
+    content.clear();
+    root_dir
+        .open_file("config.txt")
+        .unwrap()
+        .read_to_string(&mut content)
+        .unwrap();
+    assert_eq!(content, "lower");
+    assert!(root_dir.open_file("CONFIG.txt").is_err());
+    let short_names: Vec<String> = root_dir
+        .iter()
+        .map(|e| e.unwrap())
+        .filter(|e| e.file_name().eq_ignore_ascii_case("config.txt"))
+        .map(|e| e.short_file_name())
+        .collect();
+    assert_eq!(short_names, ["CONFIG.TXT", "CONFIG~1.TXT"]);
+    root_dir.remove("config.txt").unwrap();
+    root_dir.open_file("Config.txt").unwrap();
+}
+
+#[test]
+fn test_case_sensitive_fat16() {
+    call_with_tmp_img(test_case_sensitive, FAT16_IMG, 43)
+}
+
+#[test]
+fn test_case_sensitive_fat32() {
+    call_with_tmp_img(test_case_sensitive, FAT32_IMG, 43)
+}
+
+fn test_reject_reserved_names(tmp_path: &str) {
+    {
+        let fs = open_filesystem_rw(tmp_path);
+        fs.root_dir().create_file("nul.txt").unwrap();
+        fs.unmount().unwrap();
+    }
+    let file = fs::OpenOptions::new().read(true).write(true).open(tmp_path).unwrap();
+    let fs = FileSystem::new(BufStream::new(file), FsOptions::new().reject_reserved_names(true)).unwrap();
+    let root_dir = fs.root_dir();
+    for name in ["CON", "prn.txt", "Aux.tar.gz", "nul ", "COM1", "lpt9.log"] {
+        assert!(matches!(
+            root_dir.create_file(name),
+            Err(axfatfs::Error::ReservedFileName)
+        ));
+    }
+    assert!(matches!(
+        root_dir.create_dir("com5"),
+        Err(axfatfs::Error::ReservedFileName)
+    ));
+    assert!(matches!(
+        root_dir.rename("long.txt", &root_dir, "LPT1.TXT"),
+        Err(axfatfs::Error::ReservedFileName)
+    ));
+    for name in ["CONSOLE", "COM0", "LPT10", "NUL_", "my.con"] {
+        root_dir.create_file(name).unwrap();
+    }
+    // existing entries can be opened
+    root_dir.create_file("NUL.TXT").unwrap();
+    root_dir.open_file("nul.txt").unwrap();
+}
+
+#[test]
+fn test_reject_reserved_names_fat16() {
+    call_with_tmp_img(test_reject_reserved_names, FAT16_IMG, 44)
+}
+
+#[test]
+fn test_reject_reserved_names_fat32() {
+    call_with_tmp_img(test_reject_reserved_names, FAT32_IMG, 44)
+}
+
+fn test_trailing_dots_policy(tmp_path: &str) {
+    let open_fs = |policy| {
+        let file = fs::OpenOptions::new().read(true).write(true).open(tmp_path).unwrap();
+        FileSystem::new(BufStream::new(file), FsOptions::new().trailing_dots_policy(policy)).unwrap()
+    };
+    {
+        let fs = open_fs(TrailingDotsPolicy::Allow);
+        fs.root_dir().create_file("allowed. .").unwrap();
+        assert!(fs.root_dir().open_file("allowed").is_err());
+        fs.unmount().unwrap();
+    }
+    {
+        let fs = open_fs(TrailingDotsPolicy::Reject);
+        let root_dir = fs.root_dir();
+        assert!(matches!(
+            root_dir.create_file("rejected."),
+            Err(axfatfs::Error::UnsupportedFileNameCharacter)
+        ));
+        assert!(matches!(
+            root_dir.create_dir("rejected "),
+            Err(axfatfs::Error::UnsupportedFileNameCharacter)
+        ));
+        assert!(matches!(
+            root_dir.rename("long.txt", &root_dir, "long.txt."),
+            Err(axfatfs::Error::UnsupportedFileNameCharacter)
+        ));
+        // existing entries can be opened
+        root_dir.open_file("allowed. .").unwrap();
+    }
+    let fs = open_fs(TrailingDotsPolicy::Strip);
+    let root_dir = fs.root_dir();
+    root_dir.create_dir("stripped dir..").unwrap();
+    root_dir.create_file("stripped dir. /file.txt. ").unwrap();
+    root_dir.open_file("stripped dir/file.txt").unwrap();
+    root_dir.open_file("long.txt...").unwrap();
+    assert!(matches!(
+        root_dir.create_file(". ."),
+        Err(axfatfs::Error::InvalidFileNameLength)
+    ));
+    let names: Vec<String> = root_dir.iter().map(|e| e.unwrap().file_name()).collect();
+    assert!(names.contains(&String::from("stripped dir")));
+    assert!(names.contains(&String::from("allowed. .")));
+    assert!(root_dir.open_file("./long.txt").is_ok());
+}
+
+#[test]
+fn test_trailing_dots_policy_fat16() {
+    call_with_tmp_img(test_trailing_dots_policy, FAT16_IMG, 45)
+}
+
+#[test]
+fn test_trailing_dots_policy_fat32() {
+    call_with_tmp_img(test_trailing_dots_policy, FAT32_IMG, 45)
+}
+
+fn test_short_name_tail_policy(tmp_path: &str) {
+    let open_fs = |policy| {
+        let file = fs::OpenOptions::new().read(true).write(true).open(tmp_path).unwrap();
+        FileSystem::new(BufStream::new(file), FsOptions::new().short_name_tail_policy(policy)).unwrap()
+    };
+    {
+        let fs = open_fs(ShortNameTailPolicy::Numeric);
+        let dir = fs.root_dir().create_dir("numeric").unwrap();
+        for i in 0..120 {
+            dir.create_file(&format!("imported file {}.txt", i)).unwrap();
+        }
+        let short_names: Vec<String> = dir.iter().skip(2).map(|e| e.unwrap().short_file_name()).collect();
+        assert_eq!(short_names[0], "IMPORT~1.TXT");
+        assert_eq!(short_names[9], "IMPOR~10.TXT");
+        assert_eq!(short_names[119], "IMPO~120.TXT");
+        drop(dir);
+        fs.unmount().unwrap();
+    }
+    {
+        let fs = open_fs(ShortNameTailPolicy::Custom(|_, attempt| 500 + attempt * 7));
+        let dir = fs.root_dir().open_dir("numeric").unwrap();
+        dir.create_file("imported file x.txt").unwrap();
+        dir.create_file("imported file y.txt").unwrap();
+        let short_names: Vec<String> = dir.iter().skip(122).map(|e| e.unwrap().short_file_name()).collect();
+        assert_eq!(short_names, ["IMPO~500.TXT", "IMPO~507.TXT"]);
+        dir.open_file("imported file 5.txt").unwrap();
+    }
+    let fs = open_fs(ShortNameTailPolicy::Custom(|_, _| 0));
+    assert!(matches!(
+        fs.root_dir().create_file("invalid tail name.txt"),
+        Err(axfatfs::Error::InvalidInput)
+    ));
+}
+
+#[test]
+fn test_short_name_tail_policy_fat16() {
+    call_with_tmp_img(test_short_name_tail_policy, FAT16_IMG, 46)
+}
+
+#[test]
+fn test_short_name_tail_policy_fat32() {
+    call_with_tmp_img(test_short_name_tail_policy, FAT32_IMG, 46)
+}
+
+fn test_short_name_for(fs: FileSystem) {
+    let root_dir = fs.root_dir();
+    assert_eq!(root_dir.short_name_for("very-long-dir-name").unwrap(), "VERY-L~1");
+    assert_eq!(root_dir.short_name_for("long.txt").unwrap(), "LONG.TXT");
+    assert_eq!(root_dir.short_name_for("very/long/path/test.txt").unwrap(), "TEST.TXT");
+    assert_eq!(
+        root_dir.short_name_for("very-long-file-name.txt").unwrap(),
+        "VERY-L~1.TXT"
+    );
+    assert_eq!(root_dir.short_name_for("readme.txt").unwrap(), "README.TXT");
+    // nothing is created
+    assert!(root_dir.open_file("readme.txt").is_err());
+    let file = root_dir.create_file("very-long-file-name.txt").unwrap();
+    drop(file);
+    assert_eq!(
+        root_dir.short_name_for("very-long-file-name.txt").unwrap(),
+        "VERY-L~1.TXT"
+    );
+    assert_eq!(
+        root_dir.short_name_for("very-long-file-name2.txt").unwrap(),
+        "VERY-L~2.TXT"
+    );
+    assert!(matches!(root_dir.short_name_for("a/b"), Err(axfatfs::Error::NotFound)));
+    assert!(matches!(
+        root_dir.short_name_for("a*b"),
+        Err(axfatfs::Error::UnsupportedFileNameCharacter)
+    ));
+}
+
+#[test]
+fn test_short_name_for_fat16() {
+    call_with_fs(test_short_name_for, FAT16_IMG, 47)
+}
+
+#[test]
+fn test_short_name_for_fat32() {
+    call_with_fs(test_short_name_for, FAT32_IMG, 47)
+}
+
+fn test_nt_lowercase_flags(tmp_path: &str) {
+    {
+        let fs = open_filesystem_rw(tmp_path);
+        let root_dir = fs.root_dir();
+        root_dir.create_file("readme.txt").unwrap();
+        root_dir.create_file("UPPER.TXT").unwrap();
+        root_dir.create_file("lower.TXT").unwrap();
+        root_dir.create_dir("mydir").unwrap();
+        root_dir.create_file("Mixed.txt").unwrap();
+        root_dir.create_file("dot.").unwrap();
+        drop(root_dir);
+        fs.unmount().unwrap();
+    }
+    let fs = open_filesystem_rw(tmp_path);
+    let entries = fs.root_dir().iter().skip(4).map(Result::unwrap).collect::<Vec<_>>();
+    let names = entries.iter().map(axfatfs::DirEntry::file_name).collect::<Vec<_>>();
+    assert_eq!(
+        names,
+        ["readme.txt", "UPPER.TXT", "lower.TXT", "mydir", "Mixed.txt", "dot."]
+    );
+    let short_names = entries
+        .iter()
+        .map(axfatfs::DirEntry::short_file_name)
+        .collect::<Vec<_>>();
+    assert_eq!(
+        short_names,
+        ["README.TXT", "UPPER.TXT", "LOWER.TXT", "MYDIR", "MIXED.TXT", "DOT"]
+    );
+    // names stored exactly in short entries have no long names
+    assert!(entries[..4].iter().all(|e| e.long_file_name_as_ucs2_units().is_none()));
+    assert!(entries[4..].iter().all(|e| e.long_file_name_as_ucs2_units().is_some()));
+    fs.root_dir().open_file("README.TXT").unwrap();
+    fs.root_dir().open_dir("MyDir").unwrap();
+}
+
+#[test]
+fn test_nt_lowercase_flags_fat16() {
+    call_with_tmp_img(test_nt_lowercase_flags, FAT16_IMG, 48)
+}
+
+#[test]
+fn test_nt_lowercase_flags_fat32() {
+    call_with_tmp_img(test_nt_lowercase_flags, FAT32_IMG, 48)
+}
+
+fn test_rename_replace(fs: FileSystem) {
+    let root_dir = fs.root_dir();
+    let stats = fs.stats().unwrap();
+    let mut file = root_dir.create_file("new.txt").unwrap();
+    file.write_all(TEST_STR.repeat(1000).as_bytes()).unwrap();
+    drop(file);
+
+    root_dir.rename_replace("new.txt", &root_dir, "short.txt").unwrap();
+    let names = root_dir.iter().map(|r| r.unwrap().file_name()).collect::<Vec<_>>();
+    assert_eq!(names, ["long.txt", "short.txt", "very", "very-long-dir-name"]);
+    let mut buf = Vec::new();
+    root_dir.open_file("short.txt").unwrap().read_to_end(&mut buf).unwrap();
+    assert_eq!(str::from_utf8(&buf).unwrap(), TEST_STR.repeat(1000));
+
+    // replace across directories
+    root_dir
+        .rename_replace("very/long/path/test.txt", &root_dir, "short.txt")
+        .unwrap();
+    assert!(root_dir.open_file("very/long/path/test.txt").is_err());
+    let mut buf = Vec::new();
+    root_dir.open_file("short.txt").unwrap().read_to_end(&mut buf).unwrap();
+    assert_eq!(str::from_utf8(&buf).unwrap(), TEST_STR2);
+
+    // non-existing destination works like rename
+    root_dir.rename_replace("short.txt", &root_dir, "renamed.txt").unwrap();
+    root_dir.open_file("renamed.txt").unwrap();
+    assert!(root_dir.rename_replace("renamed.txt", &root_dir, "renamed.txt").is_ok());
+
+    // directories are not replaced
+    assert!(matches!(
+        root_dir.rename_replace("renamed.txt", &root_dir, "very"),
+        Err(axfatfs::Error::InvalidInput)
+    ));
+    assert!(matches!(
+        root_dir.rename_replace("very", &root_dir, "long.txt"),
+        Err(axfatfs::Error::InvalidInput)
+    ));
+    root_dir.open_file("renamed.txt").unwrap();
+    root_dir.open_file("long.txt").unwrap();
+
+    // data of replaced files has been freed
+    let new_stats = fs.stats().unwrap();
+    assert_eq!(new_stats.free_clusters(), stats.free_clusters() + 1);
+    assert!(fs.check().unwrap().is_clean());
+}
+
+#[test]
+fn test_rename_replace_fat12() {
+    call_with_fs(test_rename_replace, FAT12_IMG, 49)
+}
+
+#[test]
+fn test_rename_replace_fat16() {
+    call_with_fs(test_rename_replace, FAT16_IMG, 49)
+}
+
+#[test]
+fn test_rename_replace_fat32() {
+    call_with_fs(test_rename_replace, FAT32_IMG, 49)
+}
+
+fn test_copy_file(fs: FileSystem) {
+    let root_dir = fs.root_dir();
+    let data = TEST_STR.repeat(10000);
+    let mut file = root_dir.create_file("big.txt").unwrap();
+    file.write_all(data.as_bytes()).unwrap();
+    let modified = DateTime::new(Date::new(2010, 4, 12), Time::new(11, 10, 0, 0));
+    file.set_modified(modified);
+    file.set_attributes(FileAttributes::ARCHIVE | FileAttributes::HIDDEN);
+    drop(file);
+
+    let dst_dir = root_dir.open_dir("very/long/path").unwrap();
+    let copied = root_dir
+        .copy_file("big.txt", &dst_dir, "copy.txt", CopyOptions::new())
+        .unwrap();
+    assert_eq!(copied, data.len() as u64);
+    let mut buf = Vec::new();
+    dst_dir.open_file("copy.txt").unwrap().read_to_end(&mut buf).unwrap();
+    assert_eq!(str::from_utf8(&buf).unwrap(), data);
+    let e = dst_dir
+        .iter()
+        .map(Result::unwrap)
+        .find(|e| e.file_name() == "copy.txt")
+        .unwrap();
+    assert_eq!(e.modified(), modified);
+    assert!(!e.attributes().contains(FileAttributes::HIDDEN));
+
+    // existing destination
+    assert!(matches!(
+        root_dir.copy_file("short.txt", &dst_dir, "copy.txt", CopyOptions::new()),
+        Err(axfatfs::Error::AlreadyExists)
+    ));
+    let options = CopyOptions::new()
+        .overwrite(true)
+        .preserve_attributes(true)
+        .preserve_timestamps(false);
+    root_dir.copy_file("big.txt", &dst_dir, "test.txt", options).unwrap();
+    let e = dst_dir
+        .iter()
+        .map(Result::unwrap)
+        .find(|e| e.file_name() == "test.txt")
+        .unwrap();
+    assert_eq!(e.len(), data.len() as u64);
+    assert_ne!(e.modified(), modified);
+    assert_eq!(e.attributes(), FileAttributes::ARCHIVE | FileAttributes::HIDDEN);
+
+    // invalid sources and destinations
+    assert!(matches!(
+        root_dir.copy_file("big.txt", &root_dir, "BIG.TXT", options),
+        Err(axfatfs::Error::InvalidInput)
+    ));
+    assert!(matches!(
+        root_dir.copy_file("very", &root_dir, "dir-copy", CopyOptions::new()),
+        Err(axfatfs::Error::InvalidInput)
+    ));
+    assert!(matches!(
+        root_dir.copy_file("big.txt", &root_dir, "very", options),
+        Err(axfatfs::Error::InvalidInput)
+    ));
+    assert!(matches!(
+        root_dir.copy_file("missing.txt", &root_dir, "copy.txt", CopyOptions::new()),
+        Err(axfatfs::Error::NotFound)
+    ));
+    assert!(fs.check().unwrap().is_clean());
+}
+
+#[test]
+fn test_copy_file_fat12() {
+    call_with_fs(test_copy_file, FAT12_IMG, 50)
+}
+
+#[test]
+fn test_copy_file_fat16() {
+    call_with_fs(test_copy_file, FAT16_IMG, 50)
+}
+
+#[test]
+fn test_copy_file_fat32() {
+    call_with_fs(test_copy_file, FAT32_IMG, 50)
+}
+
+fn test_copy_range_from(fs: FileSystem) {
+    let root_dir = fs.root_dir();
+    let data = (0..100_000_u32).map(|i| (i % 251) as u8).collect::<Vec<_>>();
+    let mut src = root_dir.create_file("src.bin").unwrap();
+    src.write_all(&data).unwrap();
+    src.seek(io::SeekFrom::Start(10)).unwrap();
+    let mut dst = root_dir.create_file("dst.bin").unwrap();
+
+    assert_eq!(dst.copy_range_from(&mut src, 0, 0, 70_000).unwrap(), 70_000);
+    // copy into the middle of the file and extend it
+    assert_eq!(dst.copy_range_from(&mut src, 1000, 60_000, 20_000).unwrap(), 20_000);
+    // copying stops at the end of the source file
+    assert_eq!(dst.copy_range_from(&mut src, 90_000, 80_000, 50_000).unwrap(), 10_000);
+    assert_eq!(dst.copy_range_from(&mut src, 200_000, 0, 10).unwrap(), 0);
+    // positions are not changed
+    assert_eq!(src.stream_position().unwrap(), 10);
+    assert_eq!(dst.stream_position().unwrap(), 0);
+
+    let mut expected = data[..60_000].to_vec();
+    expected.extend_from_slice(&data[1000..21_000]);
+    expected.extend_from_slice(&data[90_000..]);
+    let mut buf = Vec::new();
+    dst.read_to_end(&mut buf).unwrap();
+    assert_eq!(buf, expected);
+
+    assert!(matches!(
+        dst.copy_range_from(&mut src, 0, u64::MAX, 10),
+        Err(axfatfs::Error::InvalidInput)
+    ));
+    src.flush().unwrap();
+    let mut src2 = root_dir.open_file("src.bin").unwrap();
+    assert!(matches!(
+        src.copy_range_from(&mut src2, 0, 0, 10),
+        Err(axfatfs::Error::InvalidInput)
+    ));
+}
+
+#[test]
+fn test_copy_range_from_fat12() {
+    call_with_fs(test_copy_range_from, FAT12_IMG, 51)
+}
+
+#[test]
+fn test_copy_range_from_fat16() {
+    call_with_fs(test_copy_range_from, FAT16_IMG, 51)
+}
+
+#[test]
+fn test_copy_range_from_fat32() {
+    call_with_fs(test_copy_range_from, FAT32_IMG, 51)
+}
+
+fn test_copy_to_other_fs(tmp_path: &str) {
+    let src_tmp_path = format!("{}-src", tmp_path);
+    fs::copy(format!("{}/{}", IMG_DIR, FAT12_IMG), &src_tmp_path).unwrap();
+    let src_file = fs::OpenOptions::new()
+        .read(true)
+        .write(true)
+        .open(&src_tmp_path)
+        .unwrap();
+    let src_fs = FileSystem::new(BufStream::new(src_file), FsOptions::new()).unwrap();
+    let dst_file = fs::OpenOptions::new().read(true).write(true).open(tmp_path).unwrap();
+    let dst_options = FsOptions::new().long_name_policy(LongNamePolicy::Shorten);
+    let dst_fs = FileSystem::new(BufStream::new(dst_file), dst_options).unwrap();
+    let src_root = src_fs.root_dir();
+    let dst_root = dst_fs.root_dir();
+    let src_len = |path| src_root.open_file(path).unwrap().metadata().unwrap().len();
+
+    // a single file
+    let copied = src_root
+        .copy_to("long.txt", &dst_root, "copy.txt", CopyOptions::new())
+        .unwrap();
+    assert_eq!(copied, src_len("long.txt"));
+    // a directory tree with long names shortened by the destination filesystem
+    dst_root.create_dir("backup").unwrap();
+    let copied = src_root.copy_to("/", &dst_root, "backup", CopyOptions::new()).unwrap();
+    let total_len = src_len("long.txt")
+        + src_len("short.txt")
+        + src_len("very/long/path/test.txt")
+        + src_len("very-long-dir-name/very-long-file-name.txt");
+    assert_eq!(copied, total_len);
+    let backup_dir = dst_root.open_dir("backup").unwrap();
+    let names = backup_dir.iter().map(|r| r.unwrap().file_name()).collect::<Vec<_>>();
+    assert_eq!(names, [".", "..", "long.txt", "short.txt", "very", "VERY-L~1"]);
+    let mut buf = Vec::new();
+    backup_dir
+        .open_file("very/long/path/test.txt")
+        .unwrap()
+        .read_to_end(&mut buf)
+        .unwrap();
+    assert_eq!(str::from_utf8(&buf).unwrap(), TEST_STR2);
+    let src_dir_entry = src_root
+        .iter()
+        .map(Result::unwrap)
+        .find(|e| e.file_name() == "very")
+        .unwrap();
+    let dst_dir_entry = backup_dir
+        .iter()
+        .map(Result::unwrap)
+        .find(|e| e.file_name() == "very")
+        .unwrap();
+    assert_eq!(dst_dir_entry.modified(), src_dir_entry.modified());
+    assert_eq!(dst_dir_entry.created(), src_dir_entry.created());
+
+    // existing files are not overwritten by default
+    assert!(matches!(
+        src_root.copy_to("/", &dst_root, "backup", CopyOptions::new()),
+        Err(axfatfs::Error::AlreadyExists)
+    ));
+    src_root
+        .copy_to("/", &dst_root, "backup", CopyOptions::new().overwrite(true))
+        .unwrap();
+    assert!(matches!(
+        src_root.copy_to("very", &dst_root, "copy.txt", CopyOptions::new()),
+        Err(axfatfs::Error::InvalidInput)
+    ));
+
+    // copying a directory into its own subdirectory does not copy the destination again
+    src_root
+        .copy_to("very", &src_root, "very/long/copy", CopyOptions::new())
+        .unwrap();
+    let names = src_root
+        .open_dir("very/long/copy/long")
+        .unwrap()
+        .iter()
+        .map(|r| r.unwrap().file_name())
+        .collect::<Vec<_>>();
+    assert_eq!(names, [".", "..", "path"]);
+
+    assert!(dst_fs.check().unwrap().is_clean());
+    assert!(src_fs.check().unwrap().is_clean());
+    drop(src_root);
+    drop(dst_root);
+    drop(backup_dir);
+    src_fs.unmount().unwrap();
+    dst_fs.unmount().unwrap();
+    fs::remove_file(src_tmp_path).unwrap();
+}
+
+#[test]
+fn test_copy_to_other_fs_fat16() {
+    call_with_tmp_img(test_copy_to_other_fs, FAT16_IMG, 52)
+}
+
+#[test]
+fn test_copy_to_other_fs_fat32() {
+    call_with_tmp_img(test_copy_to_other_fs, FAT32_IMG, 52)
+}
+
+fn test_entry_by_id(fs: FileSystem) {
+    let root_dir = fs.root_dir();
+    let find_id = |dir: &str, name: &str| {
+        let dir = if dir.is_empty() {
+            root_dir.clone()
+        } else {
+            root_dir.open_dir(dir).unwrap()
+        };
+        dir.iter()
+            .map(|r| r.unwrap())
+            .find(|e| e.file_name() == name)
+            .unwrap()
+            .id()
+    };
+    let id = find_id("very/long/path", "test.txt");
+    assert_eq!(EntryId::from_u64(id.to_u64()), id);
+    let mut buf = Vec::new();
+    fs.entry_by_id(id).unwrap().to_file().read_to_end(&mut buf).unwrap();
+    assert_eq!(str::from_utf8(&buf).unwrap(), TEST_STR2);
+
+    // root directory entries and directories
+    let dir_id = find_id("", "very-long-dir-name");
+    let dir_entry = fs.entry_by_id(dir_id).unwrap();
+    assert_eq!(dir_entry.file_name(), "very-long-dir-name");
+    let names = dir_entry
+        .to_dir()
+        .iter()
+        .map(|r| r.unwrap().file_name())
+        .collect::<Vec<_>>();
+    assert_eq!(names, [".", "..", "very-long-file-name.txt"]);
+
+    // new entries
+    root_dir.create_file("very/new-file.txt").unwrap();
+    let new_id = find_id("very", "new-file.txt");
+    assert_eq!(fs.entry_by_id(new_id).unwrap().file_name(), "new-file.txt");
+
+    // IDs survive changes of other entries
+    root_dir.remove("short.txt").unwrap();
+    fs.entry_by_id(id).unwrap();
+    assert_eq!(fs.entry_by_id(dir_id).unwrap().file_name(), "very-long-dir-name");
+
+    // renamed and removed entries are not found
+    root_dir
+        .rename("very/long/path/test.txt", &root_dir, "moved.txt")
+        .unwrap();
+    assert!(matches!(fs.entry_by_id(id), Err(axfatfs::Error::NotFound)));
+    let moved_id = find_id("", "moved.txt");
+    assert_eq!(fs.entry_by_id(moved_id).unwrap().file_name(), "moved.txt");
+    root_dir.remove("moved.txt").unwrap();
+    assert!(matches!(fs.entry_by_id(moved_id), Err(axfatfs::Error::NotFound)));
+
+    // invalid IDs
+    let bad_cluster = EntryId::from_u64((u64::from(u32::MAX) << 32) | 32);
+    assert!(matches!(fs.entry_by_id(bad_cluster), Err(axfatfs::Error::InvalidInput)));
+    let misaligned = EntryId::from_u64(dir_id.to_u64() + 1);
+    assert!(matches!(fs.entry_by_id(misaligned), Err(axfatfs::Error::InvalidInput)));
+    let past_end = EntryId::from_u64(dir_id.to_u64() & !u64::from(u32::MAX) | 0x10_0000);
+    assert!(matches!(fs.entry_by_id(past_end), Err(axfatfs::Error::NotFound)));
+}
+
+#[test]
+fn test_entry_by_id_fat12() {
+    call_with_fs(test_entry_by_id, FAT12_IMG, 53)
+}
+
+#[test]
+fn test_entry_by_id_fat16() {
+    call_with_fs(test_entry_by_id, FAT16_IMG, 53)
+}
+
+#[test]
+fn test_entry_by_id_fat32() {
+    call_with_fs(test_entry_by_id, FAT32_IMG, 53)
+}
+
+fn test_extents(tmp_path: &str) {
+    let fs = open_filesystem_rw(tmp_path);
+    let cluster_size = fs.cluster_size() as usize;
+    let data = (0..cluster_size * 7 + 100).map(|i| (i % 251) as u8).collect::<Vec<_>>();
+    let root_dir = fs.root_dir();
+    let mut file = root_dir.create_file("fragmented.bin").unwrap();
+    let mut other = root_dir.create_file("other.bin").unwrap();
+    // interleave clusters of both files
+    for chunk in data[..cluster_size * 3].chunks(cluster_size) {
+        file.write_all(chunk).unwrap();
+        other.write_all(chunk).unwrap();
+    }
+    file.write_all(&data[cluster_size * 3..]).unwrap();
+    file.flush().unwrap();
+    other.flush().unwrap();
+
+    let extents = file.extents().map(Result::unwrap).collect::<Vec<_>>();
+    let sizes = extents.iter().map(|e| e.size as usize).collect::<Vec<_>>();
+    assert_eq!(
+        sizes,
+        [cluster_size, cluster_size, cluster_size, cluster_size * 4 + 100]
+    );
+    for w in extents.windows(2) {
+        assert_ne!(w[0].offset + u64::from(w[0].size), w[1].offset);
+    }
+    drop(file);
+    drop(other);
+    drop(root_dir);
+    fs.unmount().unwrap();
+
+    // extents point to the file data in the image
+    let image = fs::read(tmp_path).unwrap();
+    let mut read_back = Vec::new();
+    for e in &extents {
+        let start = (e.start_block(512) * 512) as usize;
+        assert_eq!(start as u64, e.offset);
+        let len = e.block_count(512) as usize * 512;
+        read_back.extend_from_slice(&image[start..start + len][..e.size as usize]);
+    }
+    assert_eq!(read_back, data);
+}
+
+#[test]
+fn test_extents_fat12() {
+    call_with_tmp_img(test_extents, FAT12_IMG, 54)
+}
+
+#[test]
+fn test_extents_fat16() {
+    call_with_tmp_img(test_extents, FAT16_IMG, 54)
+}
+
+#[test]
+fn test_extents_fat32() {
+    call_with_tmp_img(test_extents, FAT32_IMG, 54)
+}
+
+fn test_sync(tmp_path: &str) {
+    // opens the image again to see what has been stored
+    let stored = |name: &str| {
+        let file = fs::File::open(tmp_path).unwrap();
+        let fs = FileSystem::new(BufStream::new(file), FsOptions::new().read_only(true)).unwrap();
+        let mut file = fs.root_dir().open_file(name).unwrap();
+        let mut buf = Vec::new();
+        file.read_to_end(&mut buf).unwrap();
+        (buf, file.metadata().unwrap().modified())
+    };
+    let modified = DateTime::new(Date::new(2010, 5, 4), Time::new(3, 2, 10, 0));
+    let fs = open_filesystem_rw(tmp_path);
+    let mut file = fs.root_dir().create_file("sync.txt").unwrap();
+    file.write_all(TEST_STR.as_bytes()).unwrap();
+    file.set_modified(modified);
+    // the size is stored by sync_data
+    file.sync_data().unwrap();
+    let (data, stored_modified) = stored("sync.txt");
+    assert_eq!(data, TEST_STR.as_bytes());
+    assert_eq!(stored_modified, modified);
+    // overwriting data in place does not write the entry
+    file.seek(io::SeekFrom::Start(0)).unwrap();
+    file.write_all(b"Go").unwrap();
+    file.set_modified(DateTime::new(Date::new(2011, 5, 4), Time::new(3, 2, 10, 0)));
+    file.sync_data().unwrap();
+    let (data, stored_modified) = stored("sync.txt");
+    assert_eq!(&data[..2], b"Go");
+    assert_eq!(stored_modified, modified);
+    // timestamps are stored by sync_all
+    file.sync_all().unwrap();
+    let (_, stored_modified) = stored("sync.txt");
+    assert_eq!(stored_modified.date, Date::new(2011, 5, 4));
+}
+
+#[test]
+fn test_sync_fat12() {
+    call_with_tmp_img(test_sync, FAT12_IMG, 57)
+}
+
+#[test]
+fn test_sync_fat16() {
+    call_with_tmp_img(test_sync, FAT16_IMG, 57)
+}
+
+#[test]
+fn test_sync_fat32() {
+    call_with_tmp_img(test_sync, FAT32_IMG, 57)
+}
+
+#[cfg(feature = "unsafe_raw")]
+fn test_fat_entry(fs: FileSystem) {
+    use axfatfs::FatEntry;
+
+    // follow the chain of a file
+    let metadata = fs.metadata("long.txt").unwrap();
+    let mut cluster = metadata.first_cluster().unwrap();
+    let mut num_clusters = 1_u32;
+    while let FatEntry::Next(n) = fs.fat_entry(cluster).unwrap() {
+        cluster = n;
+        num_clusters += 1;
+    }
+    assert_eq!(fs.fat_entry(cluster).unwrap(), FatEntry::EndOfChain);
+    assert_eq!(
+        u64::from(num_clusters) * u64::from(fs.cluster_size()),
+        metadata.allocated_size()
+    );
+
+    // edit entries of neighbouring free clusters (packed into shared bytes on FAT12)
+    let free_clusters = fs.stats().unwrap().free_clusters();
+    let first = (2..)
+        .find(|&n| fs.fat_entry(n).unwrap() == FatEntry::Free && fs.fat_entry(n + 1).unwrap() == FatEntry::Free)
+        .unwrap();
+    fs.set_fat_entry(first, FatEntry::Next(first + 1)).unwrap();
+    fs.set_fat_entry(first + 1, FatEntry::Bad).unwrap();
+    assert_eq!(fs.fat_entry(first).unwrap(), FatEntry::Next(first + 1));
+    assert_eq!(fs.fat_entry(first + 1).unwrap(), FatEntry::Bad);
+    assert_eq!(fs.stats().unwrap().free_clusters(), free_clusters - 2);
+    fs.set_fat_entry(first, FatEntry::EndOfChain).unwrap();
+    assert_eq!(fs.stats().unwrap().free_clusters(), free_clusters - 2);
+    fs.set_fat_entry(first, FatEntry::Free).unwrap();
+    fs.set_fat_entry(first + 1, FatEntry::Free).unwrap();
+    assert_eq!(fs.stats().unwrap().free_clusters(), free_clusters);
+    assert_eq!(fs.fat_entry(first).unwrap(), FatEntry::Free);
+    assert_eq!(fs.fat_entry(first + 1).unwrap(), FatEntry::Free);
+
+    assert!(matches!(fs.fat_entry(1), Err(axfatfs::Error::InvalidInput)));
+    assert!(matches!(
+        fs.set_fat_entry(first, FatEntry::Next(0)),
+        Err(axfatfs::Error::InvalidInput)
+    ));
+    assert!(matches!(
+        fs.set_fat_entry(u32::MAX, FatEntry::Free),
+        Err(axfatfs::Error::InvalidInput)
+    ));
+    assert!(fs.check().unwrap().is_clean());
+}
+
+#[test]
+#[cfg(feature = "unsafe_raw")]
+fn test_fat_entry_fat12() {
+    call_with_fs(test_fat_entry, FAT12_IMG, 55)
+}
+
+#[test]
+#[cfg(feature = "unsafe_raw")]
+fn test_fat_entry_fat16() {
+    call_with_fs(test_fat_entry, FAT16_IMG, 55)
+}
+
+#[test]
+#[cfg(feature = "unsafe_raw")]
+fn test_fat_entry_fat32() {
+    call_with_fs(test_fat_entry, FAT32_IMG, 55)
+}
+
+#[test]
+fn test_fat_plus() {
+    use axfatfs::{FatType, FormatVolumeOptions, RamDisk, Read as _, Seek, SeekFrom, Write as _};
+
+    const CLUSTER_SIZE: u64 = 64 * 1024;
+    const GIB_4: u64 = 1 << 32;
+
+    let _ = env_logger::builder().is_test(true).try_init();
+    let mut disk = RamDisk::with_len(GIB_4 + GIB_4 / 8);
+    let format_options = FormatVolumeOptions::new()
+        .fat_type(FatType::Fat32)
+        .bytes_per_cluster(CLUSTER_SIZE as u32);
+    axfatfs::format_volume(&mut disk, format_options).unwrap();
+    let fs = axfatfs::FileSystem::new(disk, FsOptions::new().fat_plus(true)).unwrap();
+    let mut file = fs.root_dir().create_file("BIG.BIN").unwrap();
+    file.write_at(TEST_STR.as_bytes(), 0).unwrap();
+    drop(file);
+    let first_cluster = fs.metadata("BIG.BIN").unwrap().first_cluster().unwrap();
+    let bpb = fs.bpb();
+    let bytes_per_sector = u64::from(bpb.bytes_per_sector());
+    let fat_offset = u64::from(bpb.reserved_sectors()) * bytes_per_sector;
+    let fat_size = u64::from(bpb.sectors_per_fat()) * bytes_per_sector;
+    let root_dir_offset = u64::from(bpb.first_data_sector()) * bytes_per_sector;
+    let mut disk = fs.into_inner().unwrap();
+    let mut root_dir = vec![0_u8; CLUSTER_SIZE as usize];
+    disk.seek(SeekFrom::Start(root_dir_offset)).unwrap();
+    disk.read_exact(&mut root_dir).unwrap();
+    let entry_pos = root_dir.chunks(32).position(|e| &e[..11] == b"BIG     BIN").unwrap();
+    let entry_offset = root_dir_offset + entry_pos as u64 * 32;
+
+    // make the file a contiguous chain of 4 GiB + 1 cluster using the FAT+ size encoding
+    let clusters = (GIB_4 / CLUSTER_SIZE + 1) as u32;
+    let mut fat = Vec::new();
+    for cluster in first_cluster..first_cluster + clusters {
+        let next: u32 = if cluster + 1 == first_cluster + clusters {
+            0x0FFF_FFFF
+        } else {
+            cluster + 1
+        };
+        fat.extend_from_slice(&next.to_le_bytes());
+    }
+    for i in 0..2 {
+        disk.seek(SeekFrom::Start(
+            fat_offset + i * fat_size + u64::from(first_cluster) * 4,
+        ))
+        .unwrap();
+        disk.write_all(&fat).unwrap();
+    }
+    disk.seek(SeekFrom::Start(entry_offset + 12)).unwrap();
+    disk.write_all(&[1]).unwrap();
+    disk.seek(SeekFrom::Start(entry_offset + 28)).unwrap();
+    disk.write_all(&(CLUSTER_SIZE as u32).to_le_bytes()).unwrap();
+    disk.seek(SeekFrom::Start(0)).unwrap();
+
+    let fs = axfatfs::FileSystem::new(disk, FsOptions::new().fat_plus(true)).unwrap();
+    let mut file = fs.root_dir().open_file("BIG.BIN").unwrap();
+    assert_eq!(file.metadata().unwrap().len(), GIB_4 + CLUSTER_SIZE);
+    assert_eq!(Seek::seek(&mut file, SeekFrom::End(0)).unwrap(), GIB_4 + CLUSTER_SIZE);
+    let extents = file.extents().map(Result::unwrap).collect::<Vec<_>>();
+    assert_eq!(extents.len(), 2);
+    assert_eq!(
+        extents.iter().map(|e| u64::from(e.size)).sum::<u64>(),
+        GIB_4 + CLUSTER_SIZE
+    );
+    // write across the 4 GiB boundary and extend the file
+    let pos = GIB_4 - 4;
+    assert_eq!(file.write_at(TEST_STR2.as_bytes(), pos).unwrap(), TEST_STR2.len());
+    let end = GIB_4 + CLUSTER_SIZE;
+    assert_eq!(file.write_at(TEST_STR2.as_bytes(), end).unwrap(), TEST_STR2.len());
+    let mut buf = [0_u8; TEST_STR2.len()];
+    assert_eq!(file.read_at(&mut buf, pos).unwrap(), TEST_STR2.len());
+    assert_eq!(&buf, TEST_STR2.as_bytes());
+    assert_eq!(file.read_at(&mut buf, end).unwrap(), TEST_STR2.len());
+    assert_eq!(&buf, TEST_STR2.as_bytes());
+    let len = end + TEST_STR2.len() as u64;
+    assert_eq!(file.metadata().unwrap().len(), len);
+    drop(file);
+    assert_eq!(fs.metadata("BIG.BIN").unwrap().len(), len);
+    assert!(fs.check().unwrap().is_clean());
+    let disk = fs.into_inner().unwrap();
+
+    // without FAT+ only the lower 32 bits of the size are used
+    let fs = axfatfs::FileSystem::new(disk, FsOptions::new()).unwrap();
+    assert_eq!(fs.metadata("BIG.BIN").unwrap().len(), len - GIB_4);
+    let mut file = fs.root_dir().open_file("BIG.BIN").unwrap();
+    assert!(matches!(
+        Seek::seek(&mut file, SeekFrom::Start(GIB_4)),
+        Err(axfatfs::Error::InvalidInput)
+    ));
+}
+
+#[derive(Default)]
+struct CountingObserver {
+    bytes_read: AtomicU64,
+    bytes_written: AtomicU64,
+    flushes: AtomicU32,
+    allocated_clusters: AtomicU32,
+    freed_clusters: AtomicU32,
+    dir_operations: Mutex<Vec<(DirOperation, String)>>,
+}
+
+impl FsObserver for CountingObserver {
+    fn on_storage_read(&self, _offset: u64, len: usize) {
+        self.bytes_read.fetch_add(len as u64, Ordering::Relaxed);
+    }
+
+    fn on_storage_write(&self, _offset: u64, len: usize) {
+        self.bytes_written.fetch_add(len as u64, Ordering::Relaxed);
+    }
+
+    fn on_storage_flush(&self) {
+        self.flushes.fetch_add(1, Ordering::Relaxed);
+    }
+
+    fn on_clusters_allocated(&self, _first_cluster: u32, count: u32) {
+        self.allocated_clusters.fetch_add(count, Ordering::Relaxed);
+    }
+
+    fn on_clusters_freed(&self, count: u32) {
+        self.freed_clusters.fetch_add(count, Ordering::Relaxed);
+    }
+
+    fn on_dir_operation(&self, op: DirOperation, name: &str) {
+        if op != DirOperation::Lookup {
+            self.dir_operations.lock().unwrap().push((op, name.to_owned()));
+        }
+    }
+}
+
+fn test_observer(tmp_path: &str) {
+    // every test needs its own observer because tests run in parallel
+    let observer: &'static CountingObserver = Box::leak(Box::default());
+    let file = fs::OpenOptions::new().read(true).write(true).open(tmp_path).unwrap();
+    let fs = FileSystem::new(BufStream::new(file), FsOptions::new().observer(observer)).unwrap();
+    let cluster_size = fs.cluster_size();
+    let data = vec![0xAA_u8; cluster_size as usize * 3];
+    let mut file = fs.root_dir().create_file("observed.bin").unwrap();
+    file.write_all(&data).unwrap();
+    file.seek(io::SeekFrom::Start(0)).unwrap();
+    let mut buf = Vec::new();
+    file.read_to_end(&mut buf).unwrap();
+    drop(file);
+    assert!(observer.bytes_written.load(Ordering::Relaxed) >= data.len() as u64);
+    assert!(observer.bytes_read.load(Ordering::Relaxed) >= data.len() as u64);
+    assert_eq!(observer.allocated_clusters.load(Ordering::Relaxed), 3);
+    fs.root_dir().create_dir("observed").unwrap();
+    fs.root_dir()
+        .rename("observed.bin", &fs.root_dir(), "observed/moved.bin")
+        .unwrap();
+    fs.root_dir().remove("observed/moved.bin").unwrap();
+    assert_eq!(observer.allocated_clusters.load(Ordering::Relaxed), 4);
+    assert_eq!(observer.freed_clusters.load(Ordering::Relaxed), 3);
+    let ops = observer.dir_operations.lock().unwrap().clone();
+    let expected = [
+        (DirOperation::Create, "observed.bin"),
+        (DirOperation::Create, "observed"),
+        (DirOperation::Rename, "observed.bin"),
+        (DirOperation::Remove, "moved.bin"),
+    ];
+    assert_eq!(ops.len(), expected.len());
+    for ((op, name), (expected_op, expected_name)) in ops.iter().zip(expected) {
+        assert_eq!(*op, expected_op);
+        assert_eq!(name, expected_name);
+    }
+    let flushes = observer.flushes.load(Ordering::Relaxed);
+    fs.unmount().unwrap();
+    assert!(observer.flushes.load(Ordering::Relaxed) > flushes);
+}
+
+#[test]
+fn test_observer_fat12() {
+    call_with_tmp_img(test_observer, FAT12_IMG, 58)
+}
+
+#[test]
+fn test_observer_fat16() {
+    call_with_tmp_img(test_observer, FAT16_IMG, 58)
+}
+
+#[test]
+fn test_observer_fat32() {
+    call_with_tmp_img(test_observer, FAT32_IMG, 58)
+}
+
+fn test_watch(fs: FileSystem) {
+    let root_dir = fs.root_dir();
+    root_dir.create_dir("watched").unwrap();
+    let all_events = Arc::new(Mutex::new(Vec::new()));
+    let dir_events = Arc::new(Mutex::new(Vec::new()));
+    let events = Arc::clone(&all_events);
+    let all_id = fs.watch_all(move |e| events.lock().unwrap().push(format!("{:?}", e)));
+    let events = Arc::clone(&dir_events);
+    fs.watch("watched", move |e| events.lock().unwrap().push(format!("{:?}", e)))
+        .unwrap();
+    assert!(matches!(fs.watch("missing", |_| {}), Err(axfatfs::Error::NotFound)));
+
+    let mut file = root_dir.create_file("watched/a.txt").unwrap();
+    let created_id = root_dir
+        .open_dir("watched")
+        .unwrap()
+        .iter()
+        .find(|e| e.as_ref().unwrap().file_name() == "a.txt");
+    let created_id = created_id.unwrap().unwrap().id();
+    file.write_all(TEST_STR.as_bytes()).unwrap();
+    file.seek(io::SeekFrom::Start(0)).unwrap();
+    file.truncate().unwrap();
+    drop(file);
+    root_dir.rename("watched/a.txt", &root_dir, "b.txt").unwrap();
+    let moved_id = root_dir.iter().find(|e| e.as_ref().unwrap().file_name() == "b.txt");
+    let moved_id = moved_id.unwrap().unwrap().id();
+    root_dir.remove("b.txt").unwrap();
+    assert!(fs.unwatch(all_id));
+    assert!(!fs.unwatch(all_id));
+    root_dir.create_file("watched/c.txt").unwrap();
+
+    let all_events = all_events.lock().unwrap();
+    assert_eq!(
+        all_events[0],
+        format!("Created {{ id: {:?}, name: \"a.txt\", is_dir: false }}", created_id)
+    );
+    let modified = format!("Modified {{ id: {:?} }}", created_id);
+    assert!(all_events[1..all_events.len() - 2].iter().all(|e| *e == modified));
+    assert!(all_events.len() >= 5);
+    assert_eq!(
+        all_events[all_events.len() - 2],
+        format!(
+            "Renamed {{ old_id: {:?}, old_name: \"a.txt\", new_id: {:?}, new_name: \"b.txt\" }}",
+            created_id, moved_id
+        )
+    );
+    assert_eq!(
+        all_events[all_events.len() - 1],
+        format!("Removed {{ id: {:?}, name: \"b.txt\" }}", moved_id)
+    );
+    // the removal happened outside of the watched directory, the rename moved the file out of it
+    let dir_events = dir_events.lock().unwrap();
+    assert_eq!(dir_events[..dir_events.len() - 1], all_events[..all_events.len() - 1]);
+    assert!(dir_events[dir_events.len() - 1].contains("name: \"c.txt\""));
+}
+
+#[test]
+fn test_watch_fat12() {
+    call_with_fs(test_watch, FAT12_IMG, 59)
+}
+
+#[test]
+fn test_watch_fat16() {
+    call_with_fs(test_watch, FAT16_IMG, 59)
+}
+
+#[test]
+fn test_watch_fat32() {
+    call_with_fs(test_watch, FAT32_IMG, 59)
+}
+
+fn test_into_inner_releases_watchers(fs: FileSystem) {
+    let captured = Arc::new(());
+    let watcher_captured = Arc::clone(&captured);
+    fs.watch_all(move |_| {
+        let _ = &watcher_captured;
+    });
+    let dir_captured = Arc::clone(&captured);
+    fs.watch("/", move |_| {
+        let _ = &dir_captured;
+    })
+    .unwrap();
+    assert_eq!(Arc::strong_count(&captured), 3);
+    fs.into_inner().unwrap();
+    assert_eq!(Arc::strong_count(&captured), 1);
+}
+
+#[test]
+fn test_into_inner_releases_watchers_fat32() {
+    call_with_fs(test_into_inner_releases_watchers, FAT32_IMG, 67)
+}
+
+fn test_iter_fails_on_modification(fs: FileSystem) {
+    let root_dir = fs.root_dir();
+    let dir = root_dir.create_dir("dir").unwrap();
+    dir.create_file("a.txt").unwrap();
+    let mut iter = dir.iter();
+    assert_eq!(iter.next().unwrap().unwrap().file_name(), ".");
+    // an iterator of another directory and a clone made before the change are not affected
+    let mut root_iter = root_dir.iter();
+    let mut cloned_iter = iter.clone();
+    dir.create_file("b.txt").unwrap();
+    assert!(matches!(iter.next(), Some(Err(axfatfs::Error::DirectoryModified))));
+    assert!(iter.next().is_none());
+    assert!(matches!(
+        cloned_iter.next(),
+        Some(Err(axfatfs::Error::DirectoryModified))
+    ));
+    assert!(root_iter.next().unwrap().is_ok());
+    // a new iteration sees all entries
+    let names = dir.iter().map(|r| r.unwrap().file_name()).collect::<Vec<_>>();
+    assert_eq!(names, [".", "..", "a.txt", "b.txt"]);
+    let mut iter = dir.iter();
+    iter.next().unwrap().unwrap();
+    dir.remove("a.txt").unwrap();
+    assert!(matches!(iter.next(), Some(Err(axfatfs::Error::DirectoryModified))));
+    // modifying file contents does not change the list of entries
+    let iter = dir.iter();
+    dir.open_file("b.txt").unwrap().write_all(TEST_STR.as_bytes()).unwrap();
+    assert_eq!(iter.count(), 3);
+}
+
+#[test]
+fn test_iter_fails_on_modification_fat12() {
+    call_with_fs(test_iter_fails_on_modification, FAT12_IMG, 60)
+}
+
+#[test]
+fn test_iter_fails_on_modification_fat16() {
+    call_with_fs(test_iter_fails_on_modification, FAT16_IMG, 60)
+}
+
+#[test]
+fn test_iter_fails_on_modification_fat32() {
+    call_with_fs(test_iter_fails_on_modification, FAT32_IMG, 60)
+}
+
+fn test_open_or_create(fs: FileSystem) {
+    let root_dir = fs.root_dir();
+    let (mut file, created) = root_dir.open_or_create_file("new.txt").unwrap();
+    assert!(created);
+    file.write_all(TEST_STR.as_bytes()).unwrap();
+    drop(file);
+    let (mut file, created) = root_dir.open_or_create_file("NEW.TXT").unwrap();
+    assert!(!created);
+    let mut content = String::new();
+    file.read_to_string(&mut content).unwrap();
+    assert_eq!(content, TEST_STR);
+    assert!(matches!(
+        root_dir.open_or_create_file("very"),
+        Err(axfatfs::Error::InvalidInput)
+    ));
+
+    let (dir, created) = root_dir.open_or_create_dir("very/new-dir").unwrap();
+    assert!(created);
+    dir.create_file("a.txt").unwrap();
+    let (dir, created) = root_dir.open_or_create_dir("very/new-dir").unwrap();
+    assert!(!created);
+    assert!(dir.exists("a.txt"));
+    assert!(matches!(
+        root_dir.open_or_create_dir("new.txt"),
+        Err(axfatfs::Error::InvalidInput)
+    ));
+
+    // exactly one of concurrent callers creates the entry
+    let created_count = AtomicU32::new(0);
+    std::thread::scope(|s| {
+        for _ in 0..4 {
+            s.spawn(|| {
+                let (_, created) = root_dir.open_or_create_file("very/concurrent.txt").unwrap();
+                if created {
+                    created_count.fetch_add(1, Ordering::SeqCst);
+                }
+            });
+        }
+    });
+    assert_eq!(created_count.load(Ordering::SeqCst), 1);
+}
+
+#[test]
+fn test_open_or_create_fat12() {
+    call_with_fs(test_open_or_create, FAT12_IMG, 61)
+}
+
+#[test]
+fn test_open_or_create_fat16() {
+    call_with_fs(test_open_or_create, FAT16_IMG, 61)
+}
+
+#[test]
+fn test_open_or_create_fat32() {
+    call_with_fs(test_open_or_create, FAT32_IMG, 61)
+}
+
+fn test_read_write_whole_file(fs: FileSystem) {
+    assert_eq!(fs.read("very/long/path/test.txt").unwrap(), TEST_STR2.as_bytes());
+    assert_eq!(fs.read_to_string("short.txt").unwrap(), TEST_STR2);
+    fs.write("very/new.txt", TEST_STR).unwrap();
+    assert_eq!(fs.read_to_string("very/new.txt").unwrap(), TEST_STR);
+    // existing file is truncated
+    fs.write("very/new.txt", b"abc").unwrap();
+    assert_eq!(fs.read("very/new.txt").unwrap(), b"abc");
+    fs.write("very/new.txt", []).unwrap();
+    assert!(fs.read("very/new.txt").unwrap().is_empty());
+    fs.write("invalid.bin", [0xFF, 0xFE]).unwrap();
+    assert!(matches!(
+        fs.read_to_string("invalid.bin"),
+        Err(axfatfs::Error::InvalidInput)
+    ));
+    assert!(matches!(fs.read("missing.txt"), Err(axfatfs::Error::NotFound)));
+    assert!(matches!(fs.read("very"), Err(axfatfs::Error::InvalidInput)));
+    assert!(fs.check().unwrap().is_clean());
+}
+
+#[test]
+fn test_read_write_whole_file_fat12() {
+    call_with_fs(test_read_write_whole_file, FAT12_IMG, 62)
+}
+
+#[test]
+fn test_read_write_whole_file_fat16() {
+    call_with_fs(test_read_write_whole_file, FAT16_IMG, 62)
+}
+
+#[test]
+fn test_read_write_whole_file_fat32() {
+    call_with_fs(test_read_write_whole_file, FAT32_IMG, 62)
+}
+
+fn test_buf_read(fs: FileSystem) {
+    let cluster_size = fs.cluster_size() as usize;
+    let lines = (0..(cluster_size / 8))
+        .map(|i| format!("line {}", i))
+        .collect::<Vec<_>>();
+    fs.write("lines.txt", lines.join("\n")).unwrap();
+
+    let file = fs.open_file("lines.txt").unwrap();
+    let read_lines = file.lines().map(|r| r.unwrap()).collect::<Vec<_>>();
+    assert_eq!(read_lines, lines);
+
+    // reads and seeks between buffered reads use the position consumed from the buffer
+    let mut file = fs.open_file("lines.txt").unwrap();
+    let mut line = Vec::new();
+    assert_eq!(axfatfs::BufRead::read_until(&mut file, b'\n', &mut line).unwrap(), 7);
+    assert_eq!(line, b"line 0\n");
+    assert_eq!(file.stream_position().unwrap(), 7);
+    let mut buf = [0; 6];
+    file.read_exact(&mut buf).unwrap();
+    assert_eq!(&buf, b"line 1");
+    file.seek(io::SeekFrom::Start(cluster_size as u64 - 2)).unwrap();
+    let mut rest = String::new();
+    file.read_to_string(&mut rest).unwrap();
+    assert_eq!(file.fill_buf().unwrap(), b"");
+    let mut line = String::new();
+    file.seek(io::SeekFrom::Start(cluster_size as u64 - 2)).unwrap();
+    file.read_line(&mut line).unwrap();
+    assert!(rest.starts_with(&line));
+
+    // writes through the same file discard the buffer
+    file.rewind().unwrap();
+    assert_eq!(&file.fill_buf().unwrap()[..4], b"line");
+    file.write_all(b"LINE").unwrap();
+    file.rewind().unwrap();
+    assert_eq!(&file.fill_buf().unwrap()[..6], b"LINE 0");
+    file.consume(5);
+    file.truncate().unwrap();
+    file.rewind().unwrap();
+    assert_eq!(file.fill_buf().unwrap(), b"LINE ");
+}
+
+#[test]
+fn test_buf_read_fat12() {
+    call_with_fs(test_buf_read, FAT12_IMG, 63)
+}
+
+#[test]
+fn test_buf_read_fat16() {
+    call_with_fs(test_buf_read, FAT16_IMG, 63)
+}
+
+#[test]
+fn test_buf_read_fat32() {
+    call_with_fs(test_buf_read, FAT32_IMG, 63)
+}
+
+fn test_file_len(fs: FileSystem) {
+    let root_dir = fs.root_dir();
+    let mut file = root_dir.create_file("len.txt").unwrap();
+    assert!(file.is_empty());
+    assert_eq!(file.len(), 0);
+    file.write_all(TEST_STR.as_bytes()).unwrap();
+    assert!(!file.is_empty());
+    assert_eq!(file.len(), TEST_STR.len() as u64);
+    // the position does not change the size until data is written after a gap
+    file.seek(io::SeekFrom::Start(100)).unwrap();
+    assert_eq!(file.len(), TEST_STR.len() as u64);
+    file.write_all(b"x").unwrap();
+    assert_eq!(file.len(), 101);
+    // another handle sees the size stored in the entry
+    file.flush().unwrap();
+    assert_eq!(root_dir.open_file("len.txt").unwrap().len(), 101);
+    file.seek(io::SeekFrom::Start(10)).unwrap();
+    file.truncate().unwrap();
+    assert_eq!(file.len(), 10);
+    assert_eq!(root_dir.open_file("short.txt").unwrap().len(), TEST_STR2.len() as u64);
+}
+
+#[test]
+fn test_file_len_fat12() {
+    call_with_fs(test_file_len, FAT12_IMG, 64)
+}
+
+#[test]
+fn test_file_len_fat16() {
+    call_with_fs(test_file_len, FAT16_IMG, 64)
+}
+
+#[test]
+fn test_file_len_fat32() {
+    call_with_fs(test_file_len, FAT32_IMG, 64)
+}
+
+fn test_quota(fs: FileSystem) {
+    let cluster_size = u64::from(fs.cluster_size());
+    let root_dir = fs.root_dir();
+    let tenant_dir = root_dir.create_dir("tenant").unwrap();
+    let quota_dir = axfatfs::QuotaDir::new(tenant_dir, 4 * cluster_size).unwrap();
+    assert_eq!(quota_dir.limit(), 4 * cluster_size);
+    assert_eq!(quota_dir.used(), cluster_size);
+    // writes are shortened to the quota and fail when nothing can be written
+    let mut file = quota_dir.create_file("a.bin").unwrap();
+    assert_eq!(quota_dir.used(), cluster_size);
+    let data = vec![0x55_u8; 10 * cluster_size as usize];
+    let err = axfatfs::Write::write_all(&mut file, &data).unwrap_err();
+    assert!(matches!(err, axfatfs::Error::NotEnoughSpace));
+    assert_eq!(file.as_file().len(), 3 * cluster_size);
+    assert_eq!(quota_dir.used(), 4 * cluster_size);
+    assert_eq!(quota_dir.available(), 0);
+    file.flush().unwrap();
+    // new entries cannot be created, but existing ones can be opened
+    assert!(matches!(
+        quota_dir.create_file("b.bin"),
+        Err(axfatfs::Error::NotEnoughSpace)
+    ));
+    assert!(matches!(
+        quota_dir.create_dir("sub"),
+        Err(axfatfs::Error::NotEnoughSpace)
+    ));
+    assert_eq!(
+        quota_dir.create_file("a.bin").unwrap().as_file().len(),
+        3 * cluster_size
+    );
+    // the quota can be exceeded only through other handles
+    assert!(root_dir.create_file("outside.txt").is_ok());
+    // truncation and removal release space
+    file.seek(io::SeekFrom::Start(cluster_size)).unwrap();
+    file.truncate().unwrap();
+    assert_eq!(quota_dir.used(), 2 * cluster_size);
+    let sub_dir = quota_dir.create_dir("sub").unwrap();
+    assert_eq!(quota_dir.used(), 3 * cluster_size);
+    let mut sub_file = sub_dir.create_file("c.bin").unwrap();
+    sub_file.write_all(TEST_STR.as_bytes()).unwrap();
+    sub_file.flush().unwrap();
+    assert_eq!(quota_dir.used(), 4 * cluster_size);
+    // renaming requires space for an extension of the destination directory
+    assert!(matches!(
+        sub_dir.rename("c.bin", &quota_dir, "c.bin"),
+        Err(axfatfs::Error::NotEnoughSpace)
+    ));
+    quota_dir.remove("a.bin").unwrap();
+    sub_dir.rename("c.bin", &quota_dir, "c.bin").unwrap();
+    quota_dir.remove("sub").unwrap();
+    assert_eq!(quota_dir.used(), 2 * cluster_size);
+    assert_eq!(
+        quota_dir.used(),
+        quota_dir.as_dir().disk_usage().unwrap().allocated_size()
+    );
+    // the directory tree cannot be escaped
+    assert!(matches!(sub_dir.open_dir(".."), Err(axfatfs::Error::InvalidInput)));
+    assert!(matches!(
+        quota_dir.create_file("../escape.txt"),
+        Err(axfatfs::Error::InvalidInput)
+    ));
+    let other_quota_dir = axfatfs::QuotaDir::new(root_dir.create_dir("other").unwrap(), cluster_size).unwrap();
+    assert!(matches!(
+        quota_dir.rename("c.bin", &other_quota_dir, "c.bin"),
+        Err(axfatfs::Error::InvalidInput)
+    ));
+}
+
+#[test]
+fn test_quota_fat12() {
+    call_with_fs(test_quota, FAT12_IMG, 65)
+}
+
+#[test]
+fn test_quota_fat16() {
+    call_with_fs(test_quota, FAT16_IMG, 65)
+}
+
+#[test]
+fn test_quota_fat32() {
+    call_with_fs(test_quota, FAT32_IMG, 65)
+}
+
+fn test_verify_against(fs: FileSystem) {
+    let host_dir = format!("{}/verify-{:?}", TMP_DIR, fs.fat_type());
+    fs::create_dir_all(format!("{}/boot/grub", host_dir)).unwrap();
+    fs::write(format!("{}/boot/kernel.bin", host_dir), TEST_STR).unwrap();
+    fs::write(format!("{}/boot/grub/grub.cfg", host_dir), TEST_STR2).unwrap();
+    fs::write(format!("{}/short.txt", host_dir), TEST_STR2).unwrap();
+    fs.populate_from(&host_dir, PopulateOptions::new()).unwrap();
+
+    // entries of the original image are not present on the host
+    let removed = |path: &str| Difference::Removed {
+        path: path.to_string(),
+        is_dir: path.starts_with("very"),
+    };
+    let differences = fs.verify_against(&host_dir, CompareOptions::new()).unwrap();
+    assert_eq!(
+        differences,
+        [removed("long.txt"), removed("very"), removed("very-long-dir-name")]
+    );
+
+    fs::write(format!("{}/boot/kernel.bin", host_dir), TEST_STR.to_uppercase()).unwrap();
+    fs::write(format!("{}/new.cfg", host_dir), TEST_STR2).unwrap();
+    fs.root_dir().remove("boot/grub/grub.cfg").unwrap();
+    let options = CompareOptions::new().compare_modified(false);
+    let mut differences = fs.verify_against(&host_dir, options).unwrap();
+    fs::remove_dir_all(&host_dir).unwrap();
+    differences.sort_by(|d1, d2| d1.path().cmp(d2.path()));
+    let added = |path: &str| Difference::Added {
+        path: path.to_string(),
+        is_dir: false,
+    };
+    assert_eq!(
+        differences,
+        [
+            added("boot/grub/grub.cfg"),
+            Difference::Changed {
+                path: "boot/kernel.bin".to_string(),
+                reasons: ChangeReasons::CONTENT
+            },
+            removed("long.txt"),
+            added("new.cfg"),
+            removed("very"),
+            removed("very-long-dir-name"),
+        ]
+    );
+}
+
+#[test]
+fn test_verify_against_fat12() {
+    call_with_fs(test_verify_against, FAT12_IMG, 66)
+}
+
+#[test]
+fn test_verify_against_fat16() {
+    call_with_fs(test_verify_against, FAT16_IMG, 66)
+}
+
+#[test]
+fn test_verify_against_fat32() {
+    call_with_fs(test_verify_against, FAT32_IMG, 66)
+}